@@ -0,0 +1,134 @@
+use std::{error::Error, path::PathBuf, time::Duration};
+
+use chat_client::client::ClientOptions;
+
+const DEFAULT_CLIENTS: usize = 50;
+const DEFAULT_DURATION: u64 = 10;
+const DEFAULT_RATE: f64 = 1.0;
+const DEFAULT_DRAIN: u64 = 2;
+const DEFAULT_PREFIX: &str = "bench";
+const DEFAULT_PASSWORD: &str = "bench";
+
+const SMOKE_RAMP_UP: u64 = 2;
+const SMOKE_MAX_ERROR_RATE: f64 = 0.01;
+
+const STORM_CLIENTS: usize = 200;
+const STORM_MAX_HEARTBEAT_GAP: Duration = Duration::from_secs(2);
+
+const USAGE: &str = "Usage: chat_bench [options]
+
+Options:
+    --host <host>              Server host (default: SERVER_HOST or localhost)
+    --port <port>              Server port
+    --clients <n>              Number of simulated clients (default: 50)
+    --duration <secs>          Length of the measured send phase (default: 10)
+    --rate <msgs/sec>          Direct messages per second per client (default: 1.0)
+    --ramp-up <secs>           Spread client start-up over this many seconds (default: 0)
+    --drain <secs>             Time to wait for in-flight messages after sending stops (default: 2)
+    --prefix <name>            Username prefix for simulated clients (default: bench)
+    --password <password>      Password for simulated clients (default: bench)
+    --csv <path>               Append a summary row to a CSV file
+    --max-error-rate <ratio>   Exit with an error if the error rate is above this ratio
+    --max-heartbeat-gap <ms>   Exit with an error if the watching client went unanswered for longer
+    --smoke                    50 clients for 10 seconds with a 2 second ramp-up, failing on errors
+    --auth-storm               200 clients logging in at once, failing if heartbeats stall for 2 seconds
+    --help                     Print this message";
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub options: ClientOptions,
+    pub clients: usize,
+    pub duration: Duration,
+    pub rate: f64,
+    pub ramp_up: Duration,
+    pub drain: Duration,
+    pub prefix: String,
+    pub password: String,
+    pub csv: Option<PathBuf>,
+    pub max_error_rate: Option<f64>,
+    pub max_heartbeat_gap: Option<Duration>,
+}
+
+impl BenchConfig {
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::default();
+
+        if let Ok(env_host) = std::env::var("SERVER_HOST") {
+            config.options = config.options.with_host(env_host.trim());
+        }
+
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} expects a value", arg));
+
+            match arg.as_str() {
+                "--host" => config.options = config.options.with_host(&value()?),
+                "--port" => config.options = config.options.with_port(value()?.parse()?),
+                "--clients" => config.clients = value()?.parse()?,
+                "--duration" => config.duration = Duration::from_secs(value()?.parse()?),
+                "--rate" => config.rate = value()?.parse()?,
+                "--ramp-up" => config.ramp_up = Duration::from_secs(value()?.parse()?),
+                "--drain" => config.drain = Duration::from_secs(value()?.parse()?),
+                "--prefix" => config.prefix = value()?,
+                "--password" => config.password = value()?,
+                "--csv" => config.csv = Some(PathBuf::from(value()?)),
+                "--max-error-rate" => config.max_error_rate = Some(value()?.parse()?),
+                "--max-heartbeat-gap" => config.max_heartbeat_gap = Some(Duration::from_millis(value()?.parse()?)),
+                "--smoke" => {
+                    config.clients = DEFAULT_CLIENTS;
+                    config.duration = Duration::from_secs(DEFAULT_DURATION);
+                    config.ramp_up = Duration::from_secs(SMOKE_RAMP_UP);
+                    config.max_error_rate = Some(SMOKE_MAX_ERROR_RATE);
+                }
+                // the reconnect wave after a restart, every client creates its account in the same instant
+                "--auth-storm" => {
+                    config.clients = STORM_CLIENTS;
+                    config.duration = Duration::from_secs(DEFAULT_DURATION);
+                    config.ramp_up = Duration::ZERO;
+                    config.max_heartbeat_gap = Some(STORM_MAX_HEARTBEAT_GAP);
+                }
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument '{}'\n\n{}", arg, USAGE).into()),
+            }
+        }
+
+        if config.clients < 2 {
+            return Err("--clients must be at least 2 so clients have someone to message".into());
+        }
+        if !(config.rate > 0.0 && config.rate.is_finite()) {
+            return Err("--rate must be a positive number".into());
+        }
+
+        Ok(config)
+    }
+
+    pub fn send_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate)
+    }
+
+    pub fn start_delay(&self, index: usize) -> Duration {
+        self.ramp_up.mul_f64(index as f64 / self.clients as f64)
+    }
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            options: ClientOptions::new(),
+            clients: DEFAULT_CLIENTS,
+            duration: Duration::from_secs(DEFAULT_DURATION),
+            rate: DEFAULT_RATE,
+            ramp_up: Duration::ZERO,
+            drain: Duration::from_secs(DEFAULT_DRAIN),
+            prefix: DEFAULT_PREFIX.to_string(),
+            password: DEFAULT_PASSWORD.to_string(),
+            csv: None,
+            max_error_rate: None,
+            max_heartbeat_gap: None,
+        }
+    }
+}
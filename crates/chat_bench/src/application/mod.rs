@@ -0,0 +1,81 @@
+use std::error::Error;
+
+mod config;
+mod simulation;
+mod stats;
+
+use config::BenchConfig;
+use simulation::Simulation;
+
+const TRACING_LEVEL: tracing::Level = tracing::Level::INFO;
+
+#[derive(Debug)]
+pub struct Application {
+    config: BenchConfig,
+}
+
+impl Application {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let config = BenchConfig::from_args()?;
+
+        tracing_subscriber::fmt()
+            .with_max_level(TRACING_LEVEL)
+            .compact()
+            .with_writer(std::io::stderr)
+            .init();
+
+        Ok(Self { config })
+    }
+
+    pub async fn run(&self) -> Result<(), Box<dyn Error>> {
+        let config = &self.config;
+
+        tracing::info!(
+            "Simulating {} clients against {}:{} for {} seconds at {} msgs/sec each (ramp-up {} seconds)",
+            config.clients,
+            config.options.host(),
+            config.options.port(),
+            config.duration.as_secs(),
+            config.rate,
+            config.ramp_up.as_secs()
+        );
+
+        let stats = Simulation::new(config.clone()).run().await;
+
+        stats.print_summary(config.clients);
+
+        if let Some(path) = &config.csv {
+            stats.append_csv(path, config.clients, config.duration, config.rate)?;
+            tracing::info!("Appended summary to {}", path.display());
+        }
+
+        if let Some(max_error_rate) = config.max_error_rate {
+            let failed_clients = stats.connection_failures + stats.auth_failures;
+            let failure_rate = failed_clients as f64 / config.clients as f64;
+
+            if stats.error_rate() > max_error_rate || failure_rate > max_error_rate {
+                return Err(format!(
+                    "Error rate {:.2}% or client failure rate {:.2}% is above the allowed {:.2}%",
+                    stats.error_rate() * 100.0,
+                    failure_rate * 100.0,
+                    max_error_rate * 100.0
+                )
+                .into());
+            }
+        }
+
+        if let Some(max_gap) = config.max_heartbeat_gap {
+            let gap = stats.heartbeat_percentiles().max;
+            if gap > max_gap {
+                return Err(format!(
+                    "Heartbeats went unanswered for {}ms, more than the allowed {}ms",
+                    gap.as_millis(),
+                    max_gap.as_millis()
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
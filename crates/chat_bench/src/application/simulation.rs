@@ -0,0 +1,273 @@
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use chat_client::client::{ChatClient, ClientEvent, SendStatus};
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+use super::{config::BenchConfig, stats::Stats};
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const WATCH_KEEPALIVE: Duration = Duration::from_secs(1);
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+const BODY_PREFIX: &str = "bench";
+
+#[derive(Debug)]
+pub struct Simulation {
+    config: Arc<BenchConfig>,
+    stats: Arc<Mutex<Stats>>,
+    online: Arc<RwLock<Vec<String>>>,
+    epoch: Instant,
+    run_id: String,
+}
+
+// small xorshift so recipients are spread without pulling in a rng crate
+struct Xorshift(u64);
+
+impl Simulation {
+    pub fn new(config: BenchConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            stats: Arc::new(Mutex::new(Stats::default())),
+            online: Arc::new(RwLock::new(Vec::new())),
+            epoch: Instant::now(),
+            run_id: chrono::Utc::now().format("%H%M%S%3f").to_string(),
+        }
+    }
+
+    pub async fn run(self) -> Stats {
+        let simulation = Arc::new(self);
+        let send_until = simulation.send_until();
+
+        // logs in before the others so it measures the storm instead of taking part in it
+        let watcher_h = simulation
+            .connect_watcher()
+            .await
+            .map(|watcher| tokio::spawn(Arc::clone(&simulation).watch_heartbeats(watcher)));
+
+        let mut handles = Vec::with_capacity(simulation.config.clients);
+        for index in 0..simulation.config.clients {
+            handles.push(tokio::spawn(Arc::clone(&simulation).run_client(index)));
+        }
+
+        let progress_h = tokio::spawn(Arc::clone(&simulation).report_progress(send_until));
+
+        for handle in handles {
+            if let Err(e) = handle.await {
+                tracing::error!("Simulated client panicked: {}", e);
+            }
+        }
+        progress_h.abort();
+        if let Some(watcher_h) = watcher_h {
+            watcher_h.await.ok();
+        }
+
+        let mut stats = simulation.stats.lock().unwrap();
+        std::mem::take(&mut *stats)
+    }
+
+    fn send_until(&self) -> Instant {
+        self.epoch + self.config.ramp_up + self.config.duration
+    }
+
+    fn encode_body(&self) -> String {
+        format!("{} {}", BODY_PREFIX, self.epoch.elapsed().as_micros())
+    }
+
+    fn decode_latency(&self, body: &str) -> Option<Duration> {
+        let sent = body.strip_prefix(BODY_PREFIX)?.trim().parse::<u64>().ok()?;
+        let now = self.epoch.elapsed();
+        Some(now.saturating_sub(Duration::from_micros(sent)))
+    }
+
+    fn pick_recipient(&self, username: &str, rng: &mut Xorshift) -> Option<String> {
+        let online = self.online.read().unwrap();
+        let others = online.iter().filter(|name| *name != username).count();
+        if others == 0 {
+            return None;
+        }
+
+        let pick = (rng.next() % others as u64) as usize;
+        online.iter().filter(|name| *name != username).nth(pick).cloned()
+    }
+
+    async fn report_progress(self: Arc<Self>, send_until: Instant) {
+        let mut interval = time::interval(PROGRESS_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let stats = self.stats.lock().unwrap();
+            let phase = if Instant::now() < send_until {
+                "sending"
+            } else {
+                "draining"
+            };
+            tracing::info!(
+                "[{}] connected: {} | sent: {} | received: {} | errors: {}",
+                phase,
+                stats.connected,
+                stats.sent,
+                stats.received,
+                stats.errors()
+            );
+        }
+    }
+
+    async fn connect_watcher(&self) -> Option<ChatClient> {
+        let username = format!("{}_{}_watch", self.config.prefix, self.run_id);
+        let options = self.config.options.clone().with_keepalive_interval(WATCH_KEEPALIVE);
+        let (client, mut events) = match ChatClient::connect(options).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!("The watching client could not connect: {}", e);
+                return None;
+            }
+        };
+        client.create_account(&username, &self.config.password).await;
+        if !self.authenticate(&username, &mut events).await {
+            client.disconnect().await;
+            return None;
+        }
+        // nobody messages the watcher, its events are only drained
+        tokio::spawn(async move { while events.recv().await.is_some() {} });
+        Some(client)
+    }
+
+    // the watcher says nothing but heartbeats, so how long ago one was echoed shows how responsive the server is
+    async fn watch_heartbeats(self: Arc<Self>, watcher: ChatClient) {
+        let stop_at = self.send_until() + self.config.drain;
+        let mut ticker = time::interval(WATCH_INTERVAL);
+        while Instant::now() < stop_at {
+            ticker.tick().await;
+            let contact = watcher.contact().await;
+            let gap = contact.two_way.unwrap_or(contact.received);
+            self.stats.lock().unwrap().record_heartbeat_gap(gap);
+        }
+        watcher.disconnect().await;
+    }
+
+    // a busy server asks for patience, the client sends the login again by itself
+    async fn authenticate(&self, username: &str, events: &mut mpsc::UnboundedReceiver<ClientEvent>) -> bool {
+        time::timeout(AUTH_TIMEOUT, async {
+            while let Some(event) = events.recv().await {
+                match event {
+                    ClientEvent::Authenticated => return true,
+                    ClientEvent::AuthFailed(error) => {
+                        tracing::warn!("Client {} could not authenticate: {}", username, error);
+                        return false;
+                    }
+                    ClientEvent::ServerBusy { retry_after, .. } => {
+                        tracing::debug!("Client {} asked to retry in {} seconds", username, retry_after);
+                        self.stats.lock().unwrap().auth_busy += 1;
+                    }
+                    ClientEvent::Closed => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn run_client(self: Arc<Self>, index: usize) {
+        time::sleep(self.config.start_delay(index)).await;
+
+        let username = format!("{}_{}_{}", self.config.prefix, self.run_id, index);
+
+        let (client, mut events) = match ChatClient::connect(self.config.options.clone()).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!("Client {} could not connect: {}", username, e);
+                self.stats.lock().unwrap().connection_failures += 1;
+                return;
+            }
+        };
+        self.stats.lock().unwrap().connected += 1;
+
+        let auth_start = Instant::now();
+        client.create_account(&username, &self.config.password).await;
+
+        if !self.authenticate(&username, &mut events).await {
+            self.stats.lock().unwrap().auth_failures += 1;
+            client.disconnect().await;
+            return;
+        }
+
+        self.stats.lock().unwrap().record_auth(auth_start.elapsed());
+        self.online.write().unwrap().push(username.clone());
+
+        let send_until = self.send_until();
+        let stop_at = send_until + self.config.drain;
+        let mut rng = Xorshift::new(index as u64);
+
+        let interval = self.config.send_interval();
+        // spread the first send of each client over one interval to avoid bursts
+        let jitter = interval.mul_f64((rng.next() % 1000) as f64 / 1000.0);
+        let mut ticker = time::interval_at(Instant::now() + jitter, interval);
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        let stop = time::sleep_until(stop_at);
+        tokio::pin!(stop);
+
+        loop {
+            tokio::select! {
+                () = &mut stop => break,
+                _ = ticker.tick(), if Instant::now() < send_until => {
+                    let Some(recipient) = self.pick_recipient(&username, &mut rng) else {
+                        continue;
+                    };
+
+                    let status = client.send_direct_message(&recipient, &self.encode_body()).await;
+                    let mut stats = self.stats.lock().unwrap();
+                    match status {
+                        SendStatus::Sent | SendStatus::Pending => stats.sent += 1,
+                        SendStatus::OutboxFull => stats.outbox_full += 1,
+                    }
+                },
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+
+                    let mut stats = self.stats.lock().unwrap();
+                    match event {
+                        ClientEvent::DirectMessage { body, .. } => {
+                            stats.received += 1;
+                            if let Some(latency) = self.decode_latency(&body) {
+                                stats.record_delivery(latency);
+                            }
+                        }
+                        ClientEvent::Delivered { .. } => stats.delivered += 1,
+                        ClientEvent::DeliveryFailed { error, .. } => {
+                            tracing::debug!("Client {} delivery failed: {}", username, error);
+                            stats.delivery_errors += 1;
+                        }
+                        ClientEvent::Disconnected => stats.disconnects += 1,
+                        ClientEvent::Closed => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        client.disconnect().await;
+    }
+}
+
+impl Xorshift {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
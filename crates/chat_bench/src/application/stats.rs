@@ -0,0 +1,179 @@
+use std::{fmt, fs::OpenOptions, io::Write, path::Path, time::Duration};
+
+const CSV_HEADER: &str = "timestamp,clients,duration_secs,rate,connected,connection_failures,auth_failures,\
+disconnects,sent,delivered,received,errors,error_rate,auth_p50_ms,auth_p99_ms,latency_p50_ms,latency_p90_ms,\
+latency_p99_ms,latency_max_ms,auth_busy,heartbeat_gap_max_ms";
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub connected: u64,
+    pub connection_failures: u64,
+    pub auth_failures: u64,
+    // logins the server turned away for a while, the client tried again after the hinted delay
+    pub auth_busy: u64,
+    pub disconnects: u64,
+    pub sent: u64,
+    pub delivered: u64,
+    pub received: u64,
+    pub delivery_errors: u64,
+    pub outbox_full: u64,
+    auth_latencies: Vec<Duration>,
+    delivery_latencies: Vec<Duration>,
+    heartbeat_gaps: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    pub fn record_auth(&mut self, latency: Duration) {
+        self.auth_latencies.push(latency);
+    }
+
+    pub fn record_delivery(&mut self, latency: Duration) {
+        self.delivery_latencies.push(latency);
+    }
+
+    // how long the watching client had gone without an echo, sampled through the run
+    pub fn record_heartbeat_gap(&mut self, gap: Duration) {
+        self.heartbeat_gaps.push(gap);
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.delivery_errors + self.outbox_full
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let attempts = self.sent + self.outbox_full;
+        if attempts == 0 {
+            return 0.0;
+        }
+        self.errors() as f64 / attempts as f64
+    }
+
+    pub fn auth_percentiles(&self) -> Percentiles {
+        Percentiles::from_samples(&self.auth_latencies)
+    }
+
+    pub fn delivery_percentiles(&self) -> Percentiles {
+        Percentiles::from_samples(&self.delivery_latencies)
+    }
+
+    pub fn heartbeat_percentiles(&self) -> Percentiles {
+        Percentiles::from_samples(&self.heartbeat_gaps)
+    }
+
+    pub fn print_summary(&self, clients: usize) {
+        println!(
+            "Clients:             {} requested, {} connected",
+            clients, self.connected
+        );
+        println!("Connection failures: {}", self.connection_failures);
+        println!("Auth failures:       {}", self.auth_failures);
+        println!("Auth busy replies:   {}", self.auth_busy);
+        println!("Disconnects:         {}", self.disconnects);
+        println!("Messages sent:       {}", self.sent);
+        println!("Messages delivered:  {}", self.delivered);
+        println!("Messages received:   {}", self.received);
+        println!(
+            "Errors:              {} ({} delivery, {} outbox full)",
+            self.errors(),
+            self.delivery_errors,
+            self.outbox_full
+        );
+        println!("Error rate:          {:.2}%", self.error_rate() * 100.0);
+        println!("Auth latency:        {}", self.auth_percentiles());
+        println!("Delivery latency:    {}", self.delivery_percentiles());
+        println!("Heartbeat gap:       {}", self.heartbeat_percentiles());
+    }
+
+    pub fn append_csv(&self, path: &Path, clients: usize, duration: Duration, rate: f64) -> std::io::Result<()> {
+        let write_header = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if write_header {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+
+        let auth = self.auth_percentiles();
+        let delivery = self.delivery_percentiles();
+
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{:.4},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{:.3}",
+            chrono::Local::now().to_rfc3339(),
+            clients,
+            duration.as_secs(),
+            rate,
+            self.connected,
+            self.connection_failures,
+            self.auth_failures,
+            self.disconnects,
+            self.sent,
+            self.delivered,
+            self.received,
+            self.errors(),
+            self.error_rate(),
+            millis(auth.p50),
+            millis(auth.p99),
+            millis(delivery.p50),
+            millis(delivery.p90),
+            millis(delivery.p99),
+            millis(delivery.max),
+            self.auth_busy,
+            millis(self.heartbeat_percentiles().max),
+        )
+    }
+}
+
+impl Percentiles {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+
+        let at = |percentile: f64| sorted[((sorted.len() - 1) as f64 * percentile).round() as usize];
+
+        Self {
+            samples: sorted.len(),
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p50: at(0.50),
+            p90: at(0.90),
+            p99: at(0.99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.samples == 0 {
+            return write!(f, "no samples");
+        }
+
+        write!(
+            f,
+            "mean {:.2}ms | p50 {:.2}ms | p90 {:.2}ms | p99 {:.2}ms | max {:.2}ms ({} samples)",
+            millis(self.mean),
+            millis(self.p50),
+            millis(self.p90),
+            millis(self.p99),
+            millis(self.max),
+            self.samples
+        )
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
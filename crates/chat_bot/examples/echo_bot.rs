@@ -0,0 +1,33 @@
+use chat_bot::{Bot, BotError};
+use chat_client::client::ClientOptions;
+
+#[tokio::main]
+async fn main() -> Result<(), BotError> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .compact()
+        .init();
+
+    let mut options = ClientOptions::new();
+    if let Ok(env_host) = std::env::var("SERVER_HOST") {
+        options = options.with_host(env_host.trim());
+    }
+
+    let username = std::env::var("BOT_USERNAME").unwrap_or_else(|_| "echo".to_string());
+    let password = std::env::var("BOT_PASSWORD").unwrap_or_else(|_| "echo".to_string());
+
+    Bot::builder(&username, &password)
+        .with_options(options)
+        .with_register(true)
+        .on_direct_message(|ctx, message| async move {
+            ctx.reply(message.body()).await;
+            Ok(())
+        })
+        .command("ping", |ctx, _| async move {
+            ctx.reply("pong").await;
+            Ok(())
+        })
+        .build()
+        .run()
+        .await
+}
@@ -0,0 +1,235 @@
+use std::{collections::HashMap, error::Error, future::Future, pin::Pin, sync::Arc};
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions, SendStatus};
+use chat_core::transport::{Connector, TcpConnector};
+
+pub type BotError = Box<dyn Error + Send + Sync>;
+type BoxFuture = Pin<Box<dyn Future<Output = Result<(), BotError>> + Send>>;
+type MessageHandler = Arc<dyn Fn(Context, DirectMessage) -> BoxFuture + Send + Sync>;
+type CommandHandler = Arc<dyn Fn(Context, String) -> BoxFuture + Send + Sync>;
+
+const DEFAULT_COMMAND_PREFIX: char = '!';
+
+#[derive(Debug, Clone)]
+pub struct DirectMessage {
+    sender: String,
+    body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Context {
+    client: ChatClient,
+    username: String,
+    sender: String,
+}
+
+pub struct BotBuilder {
+    options: ClientOptions,
+    username: String,
+    password: String,
+    register: bool,
+    command_prefix: char,
+    direct_message_handlers: Vec<MessageHandler>,
+    mention_handlers: Vec<MessageHandler>,
+    commands: HashMap<String, CommandHandler>,
+}
+
+pub struct Bot {
+    options: ClientOptions,
+    username: String,
+    password: String,
+    register: bool,
+    command_prefix: char,
+    direct_message_handlers: Vec<MessageHandler>,
+    mention_handlers: Vec<MessageHandler>,
+    commands: HashMap<String, CommandHandler>,
+}
+
+impl DirectMessage {
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+impl Context {
+    pub fn client(&self) -> &ChatClient {
+        &self.client
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub async fn reply(&self, text: &str) -> SendStatus {
+        self.client.send_direct_message(&self.sender, text).await
+    }
+}
+
+impl BotBuilder {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            options: ClientOptions::default(),
+            username: username.to_string(),
+            password: password.to_string(),
+            register: false,
+            command_prefix: DEFAULT_COMMAND_PREFIX,
+            direct_message_handlers: Vec::new(),
+            mention_handlers: Vec::new(),
+            commands: HashMap::new(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_register(mut self, register: bool) -> Self {
+        self.register = register;
+        self
+    }
+
+    pub fn with_command_prefix(mut self, command_prefix: char) -> Self {
+        self.command_prefix = command_prefix;
+        self
+    }
+
+    pub fn on_direct_message<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Context, DirectMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BotError>> + Send + 'static,
+    {
+        self.direct_message_handlers
+            .push(Arc::new(move |ctx, message| Box::pin(handler(ctx, message))));
+        self
+    }
+
+    pub fn on_mention<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Context, DirectMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BotError>> + Send + 'static,
+    {
+        self.mention_handlers
+            .push(Arc::new(move |ctx, message| Box::pin(handler(ctx, message))));
+        self
+    }
+
+    pub fn command<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Context, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BotError>> + Send + 'static,
+    {
+        self.commands.insert(
+            name.to_string(),
+            Arc::new(move |ctx, args| Box::pin(handler(ctx, args))),
+        );
+        self
+    }
+
+    pub fn build(self) -> Bot {
+        Bot {
+            options: self.options,
+            username: self.username,
+            password: self.password,
+            register: self.register,
+            command_prefix: self.command_prefix,
+            direct_message_handlers: self.direct_message_handlers,
+            mention_handlers: self.mention_handlers,
+            commands: self.commands,
+        }
+    }
+}
+
+impl Bot {
+    pub fn builder(username: &str, password: &str) -> BotBuilder {
+        BotBuilder::new(username, password)
+    }
+
+    pub async fn run(self) -> Result<(), BotError> {
+        let connector = TcpConnector::new(self.options.host(), self.options.port());
+        self.run_with(connector).await
+    }
+
+    // for servers reached some other way than the host and port of the options, like one in the same process
+    pub async fn run_with<C: Connector>(self, connector: C) -> Result<(), BotError> {
+        let (client, mut events) = ChatClient::connect_with(connector, self.options.clone()).await?;
+        client.login(&self.username, &self.password).await;
+
+        let mut registered = false;
+
+        while let Some(event) = events.recv().await {
+            match event {
+                ClientEvent::Authenticated => tracing::info!("Bot {} authenticated", self.username),
+                ClientEvent::AuthFailed(error) => {
+                    if self.register && !registered {
+                        registered = true;
+                        client.create_account(&self.username, &self.password).await;
+                        continue;
+                    }
+                    client.disconnect().await;
+                    return Err(format!("Authentication failed: {}", error).into());
+                }
+                ClientEvent::DirectMessage { sender, body, .. } => {
+                    let ctx = Context {
+                        client: client.clone(),
+                        username: self.username.clone(),
+                        sender: sender.clone(),
+                    };
+                    self.dispatch(ctx, DirectMessage { sender, body });
+                }
+                ClientEvent::DeliveryFailed { recipient, error, .. } => {
+                    tracing::warn!("Bot could not deliver message to {:?}: {}", recipient, error);
+                }
+                ClientEvent::Closed => break,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&self, ctx: Context, message: DirectMessage) {
+        if let Some(command) = message.body.strip_prefix(self.command_prefix) {
+            let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+            if let Some(handler) = self.commands.get(name) {
+                Self::spawn_isolated(name, handler(ctx, args.trim().to_string()));
+                return;
+            }
+        }
+
+        if Self::mentions(&message.body, &self.username) {
+            for handler in &self.mention_handlers {
+                Self::spawn_isolated("on_mention", handler(ctx.clone(), message.clone()));
+            }
+        }
+
+        for handler in &self.direct_message_handlers {
+            Self::spawn_isolated("on_direct_message", handler(ctx.clone(), message.clone()));
+        }
+    }
+
+    fn mentions(body: &str, username: &str) -> bool {
+        body.split(|c: char| !(c.is_alphanumeric() || c == '@' || c == '_'))
+            .any(|word| word.strip_prefix('@') == Some(username))
+    }
+
+    fn spawn_isolated(name: &str, handler: BoxFuture) {
+        let name = name.to_string();
+        // a failing or panicking handler must never take the bot down
+        tokio::spawn(async move {
+            match tokio::spawn(handler).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!("Handler {} failed: {}", name, e),
+                Err(e) => tracing::error!("Handler {} panicked: {}", name, e),
+            }
+        });
+    }
+}
@@ -0,0 +1,48 @@
+use chat_bot::Bot;
+use chat_client::client::ClientEvent;
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+// the bot of the echo_bot example
+fn echo_bot() -> Bot {
+    Bot::builder("echo", "echo")
+        .with_register(true)
+        .on_direct_message(|ctx, message| async move {
+            ctx.reply(message.body()).await;
+            Ok(())
+        })
+        .command("ping", |ctx, _| async move {
+            ctx.reply("pong").await;
+            Ok(())
+        })
+        .build()
+}
+
+async fn reply(alice: &mut TestClient) -> (String, String) {
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    let ClientEvent::DirectMessage { sender, body, .. } = event else {
+        unreachable!();
+    };
+    (sender, body)
+}
+
+#[tokio::test]
+async fn the_echo_bot_answers_direct_messages() {
+    let server = TestServer::start();
+    let bot = tokio::spawn(echo_bot().run_with(server.connector()));
+    // the account does not exist yet, the bot registers it
+    eventually(|| async { server.is_logged_in("echo").await }).await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send_direct_message("echo", "hello there").await;
+    assert_eq!(reply(&mut alice).await, ("echo".to_string(), "hello there".to_string()));
+
+    // commands go to their handler instead
+    alice.client().send_direct_message("echo", "!ping").await;
+    assert_eq!(reply(&mut alice).await, ("echo".to_string(), "pong".to_string()));
+
+    assert!(!bot.is_finished());
+    bot.abort();
+}
@@ -0,0 +1,37 @@
+// the client without its front-end, builds with --no-default-features
+use std::error::Error;
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut options = ClientOptions::new();
+    if let Ok(host) = std::env::var("SERVER_HOST") {
+        options = options.with_host(host.trim());
+    }
+    let username = std::env::var("CHAT_USERNAME").unwrap_or_else(|_| "headless".to_string());
+    let password = std::env::var("CHAT_PASSWORD").unwrap_or_else(|_| "headless".to_string());
+
+    let (client, mut events) = ChatClient::connect(options).await?;
+    client.login(&username, &password).await;
+
+    // answers every direct message with how long it was, until the server goes away
+    while let Some(event) = events.recv().await {
+        match event {
+            ClientEvent::DirectMessage {
+                sender,
+                body,
+                self_note: false,
+                ..
+            } => {
+                let reply = format!("{} characters", body.chars().count());
+                client.send_direct_message(&sender, &reply).await;
+            }
+            ClientEvent::Closed => break,
+            _ => {}
+        }
+    }
+
+    client.disconnect().await;
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::{collections::BTreeMap, path::Path};
+
+// how many definitions a single line may go through before it has to be a real command
+pub const MAX_EXPANSIONS: usize = 16;
+pub const BUILTIN_ALIASES: &[(&str, &str)] = &[("m", "msg"), ("n", "note"), ("h", "history"), ("q", "dc")];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Definition {
+    // another command name, the arguments are passed along
+    Alias(String),
+    // a whole line, `$1` to `$9` and `$*` are replaced by the arguments
+    Macro(String),
+}
+
+// user defined shortcuts, kept in a file next to the input history
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Aliases {
+    entries: BTreeMap<String, Definition>,
+}
+
+impl Definition {
+    // a single word without parameters only renames a command
+    pub fn guess(expansion: &str) -> Self {
+        let expansion = expansion.trim();
+        if expansion.contains(char::is_whitespace) || expansion.contains('$') {
+            Self::Macro(expansion.to_string())
+        } else {
+            Self::Alias(expansion.to_string())
+        }
+    }
+
+    pub fn expansion(&self) -> &str {
+        match self {
+            Self::Alias(expansion) | Self::Macro(expansion) => expansion,
+        }
+    }
+
+    fn head(&self) -> &str {
+        self.expansion().split_whitespace().next().unwrap_or_default()
+    }
+
+    fn apply(&self, name: &str, args: &str) -> Result<String, String> {
+        let template = match self {
+            Self::Alias(command) if args.is_empty() => return Ok(command.clone()),
+            Self::Alias(command) => return Ok(format!("{} {}", command, args)),
+            Self::Macro(template) => template,
+        };
+
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let mut line = String::with_capacity(template.len() + args.len());
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek().copied()) {
+                ('$', Some('*')) => {
+                    chars.next();
+                    line.push_str(args);
+                }
+                ('$', Some(digit @ '1'..='9')) => {
+                    chars.next();
+                    let position = digit as usize - '0' as usize;
+                    let word = words
+                        .get(position - 1)
+                        .ok_or_else(|| format!("{} needs at least {} arguments", name, position))?;
+                    line.push_str(word);
+                }
+                _ => line.push(c),
+            }
+        }
+        Ok(line.trim().to_string())
+    }
+}
+
+impl Aliases {
+    // one definition per line, `alias m = "msg"` or `macro standup = "msg team Daily standup starting"`
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut aliases = Self::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let definition = Self::parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            aliases
+                .define(&definition.0, definition.1)
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+        Ok(aliases)
+    }
+
+    fn parse_line(line: &str) -> Result<(String, Definition), String> {
+        let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let (name, expansion) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("expected '{} <name> = \"...\"'", kind))?;
+        let expansion = expansion.trim();
+        let expansion = expansion
+            .strip_prefix('"')
+            .and_then(|expansion| expansion.strip_suffix('"'))
+            .ok_or("the expansion has to be quoted")?;
+
+        let definition = match kind {
+            "alias" => Definition::Alias(expansion.to_string()),
+            "macro" => Definition::Macro(expansion.to_string()),
+            _ => return Err(format!("expected 'alias' or 'macro', got '{}'", kind)),
+        };
+        Ok((name.trim().to_string(), definition))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_config())
+    }
+
+    pub fn to_config(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(name, definition)| match definition {
+                Definition::Alias(command) => format!("alias {} = \"{}\"\n", name, command),
+                Definition::Macro(template) => format!("macro {} = \"{}\"\n", name, template),
+            })
+            .collect()
+    }
+
+    // refused when following the definitions from the new name would come back to it
+    pub fn define(&mut self, name: &str, definition: Definition) -> Result<(), String> {
+        if name.is_empty() || name.contains(char::is_whitespace) || name.contains(['=', '"']) {
+            return Err(format!("Invalid alias name '{}'", name));
+        }
+        if definition.head().is_empty() {
+            return Err(format!("{} would expand to nothing", name));
+        }
+
+        let previous = self.entries.insert(name.to_string(), definition);
+        if let Err(e) = self.check_chain(name) {
+            match previous {
+                Some(previous) => self.entries.insert(name.to_string(), previous),
+                None => self.entries.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    // user definitions shadow the built-in ones
+    pub fn get(&self, name: &str) -> Option<Definition> {
+        self.entries.get(name).cloned().or_else(|| {
+            BUILTIN_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, command)| Definition::Alias(command.to_string()))
+        })
+    }
+
+    // built-in aliases first, then what the user defined
+    pub fn list(&self) -> Vec<(String, Definition)> {
+        BUILTIN_ALIASES
+            .iter()
+            .filter(|(alias, _)| !self.entries.contains_key(*alias))
+            .map(|(alias, command)| (alias.to_string(), Definition::Alias(command.to_string())))
+            .chain(
+                self.entries
+                    .iter()
+                    .map(|(name, definition)| (name.clone(), definition.clone())),
+            )
+            .collect()
+    }
+
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let mut line = line.trim().to_string();
+        let mut seen: Vec<String> = Vec::new();
+
+        loop {
+            let (name, args) = match line.split_once(char::is_whitespace) {
+                Some((name, args)) => (name.to_string(), args.trim().to_string()),
+                None => (line.clone(), String::new()),
+            };
+            let Some(definition) = self.get(&name) else {
+                return Ok(line);
+            };
+            if seen.contains(&name) {
+                seen.push(name);
+                return Err(format!("Alias cycle: {}", seen.join(" -> ")));
+            }
+            if seen.len() >= MAX_EXPANSIONS {
+                return Err(format!("{} nests more than {} aliases", seen[0], MAX_EXPANSIONS));
+            }
+
+            line = definition.apply(&name, &args)?;
+            seen.push(name);
+        }
+    }
+
+    fn check_chain(&self, name: &str) -> Result<(), String> {
+        let mut chain = vec![name.to_string()];
+        let mut current = name.to_string();
+        while let Some(definition) = self.get(&current) {
+            let next = definition.head().to_string();
+            if chain.contains(&next) {
+                chain.push(next);
+                return Err(format!("Alias cycle: {}", chain.join(" -> ")));
+            }
+            if chain.len() > MAX_EXPANSIONS {
+                return Err(format!("{} nests more than {} aliases", name, MAX_EXPANSIONS));
+            }
+            chain.push(next.clone());
+            current = next;
+        }
+        Ok(())
+    }
+}
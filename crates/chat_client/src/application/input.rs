@@ -0,0 +1,423 @@
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use chat_client::{
+    completion::{Completer, Completion},
+    drafts::{Drafts, PROMPT_DRAFT},
+    history::InputHistory,
+    text,
+    vault::Vault,
+};
+
+const HISTORY_FILE_NAME: &str = ".chat_rs_history";
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const CTRL_G: u8 = 0x07;
+const CTRL_R: u8 = 0x12;
+const TAB: u8 = b'\t';
+const ESCAPE: u8 = 0x1b;
+const BACKSPACE: u8 = 0x7f;
+const CTRL_H: u8 = 0x08;
+const CTRL_N: u8 = 0x0e;
+const CTRL_P: u8 = 0x10;
+
+// what the conversation keys stand for, typed input stays as the draft for the next prompt
+const NEXT_CONVERSATION: &str = "/switch +";
+const PREVIOUS_CONVERSATION: &str = "/switch -";
+
+#[derive(Debug)]
+pub struct LineEditor {
+    completer: Arc<Mutex<Completer>>,
+    // what is typed at the command prompt goes in here key by key, so a crash or a wrong key loses nothing
+    drafts: Arc<Mutex<Drafts>>,
+    history: InputHistory,
+}
+
+#[derive(Debug)]
+pub struct Input {
+    editor: Option<LineEditor>,
+}
+
+struct RawMode {
+    original: libc::termios,
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Search,
+    NextConversation,
+    PreviousConversation,
+    Cancel,
+    Interrupt,
+    Eof,
+    Ignored,
+}
+
+impl LineEditor {
+    pub fn new(completer: Arc<Mutex<Completer>>, drafts: Arc<Mutex<Drafts>>, vault: Option<Arc<Vault>>) -> Self {
+        let history_file = history_file();
+        // a file that does not open with the vault, or without one, is neither read nor written
+        let history = match history_file.as_deref().map(|path| InputHistory::load(path, vault)) {
+            Some(Ok(history)) => history,
+            Some(Err(e)) => {
+                tracing::warn!("Ignoring the history, {}", e);
+                InputHistory::default()
+            }
+            None => InputHistory::default(),
+        };
+
+        Self {
+            completer,
+            drafts,
+            history,
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str, completion: Completion) -> Option<String> {
+        let line = match RawMode::enable() {
+            Some(raw_mode) => {
+                let line = self.read_raw(prompt, completion);
+                drop(raw_mode);
+                println!();
+                line
+            }
+            None => {
+                print!("{}", prompt);
+                std::io::stdout().flush().unwrap();
+                let mut line = String::new();
+                match std::io::stdin().read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line),
+                }
+            }
+        }?;
+
+        let line = line.trim().to_string();
+        if completion == Completion::Command {
+            self.add_history(&line);
+        }
+        Some(line)
+    }
+
+    fn read_raw(&mut self, prompt: &str, completion: Completion) -> Option<String> {
+        let mut buffer: Vec<char> = match completion {
+            Completion::Command => self
+                .drafts
+                .lock()
+                .unwrap()
+                .get(PROMPT_DRAFT)
+                .unwrap_or("")
+                .chars()
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut cursor = buffer.len();
+        let mut history_index = self.history.lines().len();
+        let mut search: Option<(String, usize)> = None;
+
+        Self::redraw(prompt, &buffer, cursor);
+
+        loop {
+            let key = Self::read_key();
+
+            if let Some((query, index)) = search.as_mut() {
+                match key {
+                    Key::Char(c) => {
+                        query.push(c);
+                        *index = self.history.lines().len();
+                    }
+                    Key::Backspace => {
+                        query.pop();
+                        *index = self.history.lines().len();
+                    }
+                    Key::Search => {}
+                    Key::Cancel | Key::Interrupt | Key::Eof => {
+                        search = None;
+                        Self::redraw(prompt, &buffer, cursor);
+                        continue;
+                    }
+                    _ => {
+                        let found = self.history.lines().get(*index).cloned().unwrap_or_default();
+                        buffer = found.chars().collect();
+                        cursor = buffer.len();
+                        search = None;
+                        if matches!(key, Key::Enter) {
+                            Self::redraw(prompt, &buffer, cursor);
+                            self.keep_draft(completion, &[]);
+                            return Some(buffer.into_iter().collect());
+                        }
+                        Self::redraw(prompt, &buffer, cursor);
+                        continue;
+                    }
+                }
+
+                let start = if matches!(key, Key::Search) {
+                    *index
+                } else {
+                    self.history.lines().len()
+                };
+                if let Some(found) = self.history.lines()[..start]
+                    .iter()
+                    .rposition(|entry| entry.contains(query.as_str()))
+                {
+                    *index = found;
+                }
+                let found = self.history.lines().get(*index).map(String::as_str).unwrap_or("");
+                print!("\r\x1b[K(reverse-i-search)`{}': {}", query, found);
+                std::io::stdout().flush().unwrap();
+                continue;
+            }
+
+            match key {
+                Key::Char(c) => {
+                    buffer.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    self.keep_draft(completion, &[]);
+                    return Some(buffer.into_iter().collect());
+                }
+                Key::Tab => {
+                    let line: String = buffer.iter().collect();
+                    let (completed, candidates) = self.completer.lock().unwrap().complete(completion, &line);
+                    if completed == line && candidates.len() > 1 {
+                        print!("\r\n{}\r\n", candidates.join("  "));
+                    }
+                    buffer = completed.chars().collect();
+                    cursor = buffer.len();
+                }
+                Key::Backspace => {
+                    if cursor > 0 {
+                        cursor -= 1;
+                        buffer.remove(cursor);
+                    }
+                }
+                Key::Up => {
+                    if history_index > 0 {
+                        history_index -= 1;
+                        buffer = self.history.lines()[history_index].chars().collect();
+                        cursor = buffer.len();
+                    }
+                }
+                Key::Down => {
+                    if history_index < self.history.lines().len() {
+                        history_index += 1;
+                        buffer = self
+                            .history
+                            .lines()
+                            .get(history_index)
+                            .map(|entry| entry.chars().collect())
+                            .unwrap_or_default();
+                        cursor = buffer.len();
+                    }
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(buffer.len()),
+                Key::Search => {
+                    search = Some((String::new(), self.history.lines().len()));
+                    print!("\r\x1b[K(reverse-i-search)`': ");
+                    std::io::stdout().flush().unwrap();
+                    continue;
+                }
+                // only at the command prompt, a question like the recipient is answered first
+                Key::NextConversation if completion == Completion::Command => {
+                    return Some(NEXT_CONVERSATION.to_string())
+                }
+                Key::PreviousConversation if completion == Completion::Command => {
+                    return Some(PREVIOUS_CONVERSATION.to_string())
+                }
+                Key::Interrupt => return None,
+                Key::Eof => {
+                    if buffer.is_empty() {
+                        return None;
+                    }
+                }
+                Key::NextConversation | Key::PreviousConversation | Key::Cancel | Key::Ignored => {}
+            }
+
+            self.keep_draft(completion, &buffer);
+            Self::redraw(prompt, &buffer, cursor);
+        }
+    }
+
+    // only the command prompt has a draft, answers to questions like the password are never kept
+    fn keep_draft(&self, completion: Completion, buffer: &[char]) {
+        if completion == Completion::Command {
+            let text: String = buffer.iter().collect();
+            self.drafts.lock().unwrap().set(PROMPT_DRAFT, &text, Instant::now());
+        }
+    }
+
+    fn redraw(prompt: &str, buffer: &[char], cursor: usize) {
+        let line: String = buffer.iter().collect();
+        print!("\r\x1b[K{}{}", prompt, line);
+        // back over the columns after the cursor, wide characters take two
+        let behind: String = buffer[cursor..].iter().collect();
+        let columns = text::width(&behind);
+        if columns > 0 {
+            print!("\x1b[{}D", columns);
+        }
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn read_byte() -> Option<u8> {
+        let mut byte = [0u8; 1];
+        match std::io::stdin().read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn read_key() -> Key {
+        let Some(byte) = Self::read_byte() else {
+            return Key::Eof;
+        };
+
+        match byte {
+            b'\r' | b'\n' => Key::Enter,
+            TAB => Key::Tab,
+            BACKSPACE | CTRL_H => Key::Backspace,
+            CTRL_R => Key::Search,
+            CTRL_N => Key::NextConversation,
+            CTRL_P => Key::PreviousConversation,
+            CTRL_C => Key::Interrupt,
+            CTRL_G => Key::Cancel,
+            CTRL_D => Key::Eof,
+            ESCAPE => match (Self::read_byte(), Self::read_byte()) {
+                (Some(b'['), Some(b'A')) => Key::Up,
+                (Some(b'['), Some(b'B')) => Key::Down,
+                (Some(b'['), Some(b'C')) => Key::Right,
+                (Some(b'['), Some(b'D')) => Key::Left,
+                _ => Key::Ignored,
+            },
+            0x00..=0x1f => Key::Ignored,
+            _ => {
+                let width = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                let mut bytes = vec![byte];
+                for _ in 1..width {
+                    match Self::read_byte() {
+                        Some(byte) => bytes.push(byte),
+                        None => return Key::Eof,
+                    }
+                }
+                match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                    Some(c) => Key::Char(c),
+                    None => Key::Ignored,
+                }
+            }
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.is_empty() || line == NEXT_CONVERSATION || line == PREVIOUS_CONVERSATION {
+            return;
+        }
+        if let Err(e) = self.history.add(line) {
+            let path = self.history.path().unwrap_or(Path::new(""));
+            tracing::warn!("Could not write history file {}: {}", path.display(), e);
+        }
+    }
+}
+
+// for secrets asked before the session starts, piped input is read as it comes
+pub fn read_hidden(prompt: &str) -> Option<String> {
+    eprint!("{}", prompt);
+    let hidden = RawMode::hidden();
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if hidden.is_some() {
+        eprintln!();
+    }
+    match read {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+}
+
+pub fn history_file() -> Option<PathBuf> {
+    super::home_file("HISTORY_FILE", HISTORY_FILE_NAME)
+}
+
+impl Input {
+    pub fn new(completer: Arc<Mutex<Completer>>, drafts: Arc<Mutex<Drafts>>, vault: Option<Arc<Vault>>) -> Self {
+        Self {
+            editor: Some(LineEditor::new(completer, drafts, vault)),
+        }
+    }
+
+    pub async fn read_line(&mut self, prompt: &str, completion: Completion) -> Option<String> {
+        let mut editor = self.editor.take()?;
+        let prompt = prompt.to_string();
+
+        let (editor, line) = tokio::task::spawn_blocking(move || {
+            let line = editor.read_line(&prompt, completion);
+            (editor, line)
+        })
+        .await
+        .ok()?;
+
+        self.editor = Some(editor);
+        line
+    }
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        Self::clearing(libc::ICANON | libc::ECHO | libc::ISIG)
+    }
+
+    // lines as usual, only what is typed does not show
+    fn hidden() -> Option<Self> {
+        Self::clearing(libc::ECHO)
+    }
+
+    fn clearing(flags: libc::tcflag_t) -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        // SAFETY: termios is a plain C struct and only handed to tcgetattr/tcsetattr on stdin
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+
+            let mut raw = original;
+            raw.c_lflag &= !flags;
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+
+            Some(Self { original })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the attributes read in `enable`
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
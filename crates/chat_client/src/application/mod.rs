@@ -1,214 +1,1872 @@
-use std::{error::Error, io::Write};
+use std::{
+    error::Error,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use chat_client::{
+    aliases::{Aliases, Definition},
+    client::{
+        command_argument, parse_request, run_json, run_once, ChatClient, ClientEvent, ClientOptions, KnownKeys,
+        OnceError, OutboxState, QualityWeights, SendStatus, SigningIdentity, TranscriptExport, Verification,
+        DEFAULT_SEARCH_LIMIT, NOTES, ONCE_TIMEOUT,
+    },
+    completion::{Completer, Completion},
+    drafts::{Drafts, SAVE_DELAY},
+    profiles::{Profile, Profiles},
+    text::{self, Shortcodes, MAX_NAME_COLUMNS},
+    theme::{Class, ColorChoice, Theme},
+    title::{self, SessionStatus},
+    vault::{self, KeySource, Vault},
+};
 use chat_core::{
-    constants::{HOST, PORT},
-    protocol::{Message, MessageType},
+    integrity::Integrity,
+    normalize::{normalize_body, normalize_username},
+    protocol::{DisconnectReason, Message, PresenceChange, PresenceScope, PresenceStatus},
+    trace::FrameTracer,
 };
-use tokio::{net::TcpStream, sync::mpsc};
+use chrono::{DateTime, Local, Utc};
+use tokio::{io::BufReader, sync::mpsc};
+
+mod input;
+
+use input::Input;
 
 const TRACING_LEVEL: tracing::Level = tracing::Level::DEBUG;
+const ALIAS_FILE_NAME: &str = ".chat_rs_aliases";
+const PROFILE_FILE_NAME: &str = ".chat_rs_profiles";
+const DRAFT_FILE_NAME: &str = ".chat_rs_drafts";
+const EMOJI_FILE_NAME: &str = ".chat_rs_emoji";
+const VAULT_FILE_NAME: &str = ".chat_rs_vault";
+const SIGNING_KEY_FILE_NAME: &str = ".chat_rs_signing_key";
+const KNOWN_KEYS_FILE_NAME: &str = ".chat_rs_known_keys";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum OutputMode {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    output: OutputMode,
+    color: ColorChoice,
+    trace_file: Option<PathBuf>,
+    profile: Option<String>,
+    // shortcodes like :tada: are shown as they were typed
+    no_emoji: bool,
+    // leaves the terminal title alone
+    no_title: bool,
+    // the rest of the command line after `once`, run instead of the interactive session
+    once: Option<String>,
+    // stays a guest even with a profile that has credentials
+    listen_only: bool,
+}
 
 #[derive(Debug)]
-pub struct Application {}
+pub struct Application {
+    completer: Arc<Mutex<Completer>>,
+    output: OutputMode,
+    theme: Theme,
+    tracer: Option<FrameTracer>,
+    aliases: Aliases,
+    alias_file: Option<PathBuf>,
+    profile: Option<Profile>,
+    once: Option<String>,
+    drafts: Arc<Mutex<Drafts>>,
+    // set when the history and drafts are encrypted, they are never written in plaintext then
+    vault: Option<Arc<Vault>>,
+    shortcodes: Shortcodes,
+    status: Arc<Mutex<SessionStatus>>,
+    // whether the terminal title follows the status
+    title: bool,
+    listen_only: bool,
+}
 
 impl Application {
     pub fn new() -> Result<Self, Box<dyn Error>> {
         use tracing_subscriber::fmt::format::FmtSpan;
-        tracing_subscriber::fmt()
+
+        let args = Self::parse_args()?;
+        let output = args.output;
+
+        let subscriber = tracing_subscriber::fmt()
             .with_max_level(TRACING_LEVEL)
             .compact()
-            .with_span_events(FmtSpan::FULL)
-            .init();
+            .with_span_events(FmtSpan::FULL);
+
+        // stdout belongs to the conversation in text mode and to the event stream in json mode
+        subscriber.with_writer(std::io::stderr).init();
+
+        let tracer = match &args.trace_file {
+            Some(path) => Some(FrameTracer::create(path)?),
+            None => None,
+        };
+
+        // json is read by programs, escape sequences would only get in the way
+        let theme = match output {
+            OutputMode::Text => Theme::detect(args.color),
+            OutputMode::Json => Theme::new(false),
+        };
+
+        let alias_file = home_file("ALIAS_FILE", ALIAS_FILE_NAME);
+        // a broken file should not keep anyone from chatting, the built-in aliases still work
+        let aliases = match alias_file.as_deref().map(Aliases::load).transpose() {
+            Ok(aliases) => aliases.unwrap_or_default(),
+            Err(e) => {
+                theme.print(Class::Warning, &format!("Ignoring aliases, {}", e));
+                Aliases::default()
+            }
+        };
+
+        // nothing starts with a vault that does not unlock
+        let vault = open_vault()?;
 
-        Ok(Application::default())
+        // same as the aliases, an unreadable file is left alone and nothing typed now is kept
+        let drafts = match home_file("DRAFT_FILE", DRAFT_FILE_NAME)
+            .as_deref()
+            .map(|path| Drafts::load_with(path, vault.clone()))
+            .transpose()
+        {
+            Ok(drafts) => drafts.unwrap_or_default(),
+            Err(e) => {
+                theme.print(Class::Warning, &format!("Ignoring drafts, {}", e));
+                Drafts::default()
+            }
+        };
+
+        let extra_shortcodes = home_file("EMOJI_FILE", EMOJI_FILE_NAME);
+        let shortcodes = match extra_shortcodes.as_deref().map(|path| Shortcodes::default().load(path)) {
+            _ if args.no_emoji => Shortcodes::disabled(),
+            Some(Ok(shortcodes)) => shortcodes,
+            Some(Err(e)) => {
+                theme.print(Class::Warning, &format!("Ignoring extra shortcodes, {}", e));
+                Shortcodes::default()
+            }
+            None => Shortcodes::default(),
+        };
+
+        let profile = match &args.profile {
+            Some(name) => {
+                let path =
+                    home_file("PROFILE_FILE", PROFILE_FILE_NAME).ok_or("--profile needs PROFILE_FILE or HOME")?;
+                let profiles = Profiles::load(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let profile = profiles
+                    .get(name)
+                    .ok_or_else(|| format!("No profile '{}' in {}", name, path.display()))?;
+                Some(profile.clone())
+            }
+            None => None,
+        };
+
+        Ok(Application {
+            completer: Arc::default(),
+            output,
+            theme,
+            tracer,
+            aliases,
+            alias_file,
+            profile,
+            once: args.once,
+            drafts: Arc::new(Mutex::new(drafts)),
+            vault,
+            shortcodes,
+            status: Arc::default(),
+            // only a terminal shows a title, and json output is read by programs
+            title: !args.no_title && output == OutputMode::Text && std::io::stdout().is_terminal(),
+            listen_only: args.listen_only,
+        })
     }
 
-    fn get_user_data() -> (String, String) {
-        let mut username = String::new();
-        let mut password = String::new();
+    fn parse_args() -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args::default();
+        let mut args = std::env::args().skip(1);
 
-        print!("Enter username: ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut username).unwrap();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output" => {
+                    parsed.output = match args.next().as_deref() {
+                        Some("text") => OutputMode::Text,
+                        Some("json") => OutputMode::Json,
+                        _ => return Err("--output expects 'text' or 'json'".into()),
+                    }
+                }
+                "--color" => {
+                    let choice = args.next().unwrap_or_default();
+                    parsed.color = ColorChoice::parse(&choice).map_err(|e| format!("--color {}", e))?;
+                }
+                "--trace-file" => {
+                    let path = args.next().ok_or("--trace-file expects a path")?;
+                    parsed.trace_file = Some(PathBuf::from(path));
+                }
+                "--profile" => {
+                    parsed.profile = Some(args.next().ok_or("--profile expects a name")?);
+                }
+                "--no-emoji" => parsed.no_emoji = true,
+                "--no-title" => parsed.no_title = true,
+                "--listen-only" => parsed.listen_only = true,
+                // the shell already split the command, the interactive grammar wants it as one line
+                "once" => {
+                    let line = args.by_ref().collect::<Vec<_>>().join(" ");
+                    if line.trim().is_empty() {
+                        return Err("once expects a command, e.g. once msg alice hello".into());
+                    }
+                    parsed.once = Some(line);
+                }
+                _ => return Err(format!("Unknown argument '{}'", arg).into()),
+            }
+        }
 
-        print!("Enter password: ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut password).unwrap();
+        Ok(parsed)
+    }
 
-        (username, password)
+    // None when the prompt was abandoned or the name is unusable, which is said right away
+    async fn get_user_data(theme: Theme, input: &mut Input) -> Option<(String, String)> {
+        let username = input.read_line("Enter username: ", Completion::Nothing).await?;
+        let password = input.read_line("Enter password: ", Completion::Nothing).await?;
+
+        match normalize_username(&username) {
+            Ok(username) => Some((username, password)),
+            Err(e) => {
+                theme.print(Class::Warning, &e);
+                None
+            }
+        }
     }
 
-    fn get_message_data() -> (String, String) {
-        let mut recipient = String::new();
-        let mut message = String::new();
+    async fn get_password_change(input: &mut Input) -> Option<(String, String)> {
+        let old_password = input.read_line("Enter current password: ", Completion::Nothing).await?;
+        let new_password = input.read_line("Enter new password: ", Completion::Nothing).await?;
 
-        print!("Enter recipient: ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut recipient).unwrap();
+        Some((old_password, new_password))
+    }
 
-        print!("Enter message: ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut message).unwrap();
+    async fn handle_edit_command(theme: Theme, client: &ChatClient, command: &str, args: &str) {
+        let (id, body) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+        let Ok(id) = id.parse::<u64>() else {
+            match command {
+                "edit" => theme.print(Class::Warning, "Usage: edit <id> <text>"),
+                _ => theme.print(Class::Warning, "Usage: delete <id>"),
+            }
+            return;
+        };
 
-        (recipient, message)
+        let result = match command {
+            "edit" if body.trim().is_empty() => Err("Usage: edit <id> <text>".to_string()),
+            "edit" => match normalize_body(body) {
+                Ok(body) => client.edit_message(id, &body).await,
+                Err(e) => Err(e),
+            },
+            _ => client.delete_message(id).await,
+        };
+        if let Err(e) = result {
+            theme.print(Class::Warning, &e);
+        }
     }
 
-    pub async fn run(&self) -> Result<(), Box<dyn Error>> {
-        tracing::debug!("Starting application");
+    async fn send_to_active(theme: Theme, client: &ChatClient, body: &str) {
+        let body = match normalize_body(body) {
+            Ok(body) => body,
+            Err(e) => return theme.print(Class::Warning, &e),
+        };
+        match client.send_to_active(&body).await {
+            Ok(SendStatus::Sent) => {}
+            Ok(SendStatus::Pending) => {
+                theme.print(Class::Warning, "Message is pending until the connection is restored")
+            }
+            Ok(SendStatus::OutboxFull) => theme.print(Class::Error, "Outbox is full, message was dropped"),
+            Err(e) => theme.print(Class::Warning, &e),
+        }
+    }
+
+    // `switch` lists, `switch <name>` shows its scrollback and routes typed lines there, + and - go through them
+    async fn handle_conversation_command(&self, client: &ChatClient, command: &str, args: &str) {
+        let theme = self.theme;
+        let switched = match (command, args) {
+            ("switch", "") => {
+                let conversations = client.conversations().await;
+                if conversations.is_empty() {
+                    theme.print(Class::System, "No conversations");
+                }
+                let active = client.active_conversation().await;
+                for (name, unread) in conversations {
+                    let marker = if active.as_ref() == Some(&name) { "*" } else { " " };
+                    match unread {
+                        0 => theme.print(Class::System, &format!("{} {}", marker, name)),
+                        unread => theme.print(Class::System, &format!("{} {} ({} unread)", marker, name, unread)),
+                    }
+                }
+                return;
+            }
+            ("switch", "+" | "-") => client
+                .cycle_conversation(args == "+")
+                .await
+                .ok_or_else(|| "No conversations".to_string()),
+            ("switch", name) => client.switch_conversation(name).await,
+            (_, "") => Err("Usage: close <name>".to_string()),
+            (_, name) => {
+                match client.close_conversation(name).await {
+                    true => theme.print(Class::System, &format!("Closed {}", name)),
+                    false => theme.print(Class::Warning, &format!("No conversation with {}", name)),
+                }
+                return;
+            }
+        };
+
+        let name = match switched {
+            Ok(name) => name,
+            Err(e) => {
+                theme.print(Class::Warning, &e);
+                return;
+            }
+        };
+        self.completer.lock().unwrap().add_username(&name);
+        let username = client.username().await;
+        for line in client.scrollback(&name).await.unwrap_or_default() {
+            let time = line.sent_at.with_timezone(&Local).format("%H:%M:%S");
+            let id = line.id.map(|id| format!(" #{}", id)).unwrap_or_default();
+            let class = match username.as_ref() == Some(&line.sender) {
+                true => Class::Own,
+                false => Class::Incoming,
+            };
+            theme.print(
+                class,
+                &format!(
+                    "[{}]{} {}: {}",
+                    time,
+                    id,
+                    theme.name(&text::truncate(&line.sender, MAX_NAME_COLUMNS)),
+                    self.shortcodes.expand(&line.body)
+                ),
+            );
+        }
+        theme.print(Class::System, &format!("Now talking to {}", name));
+    }
+
+    async fn handle_ephemeral_command(theme: Theme, client: &ChatClient, args: &str) {
+        let mut parts = args.trim().splitn(3, ' ');
+        let (Some(recipient), Some(ttl), Some(body)) = (parts.next(), parts.next(), parts.next()) else {
+            theme.print(Class::Warning, "Usage: ephemeral <user> <seconds> <text>");
+            return;
+        };
+        let Ok(ttl) = ttl.parse::<u64>() else {
+            theme.print(
+                Class::Warning,
+                &format!(
+                    "Invalid time to live '{}', usage: ephemeral <user> <seconds> <text>",
+                    ttl
+                ),
+            );
+            return;
+        };
+        let (recipient, body) = match (normalize_username(recipient), normalize_body(body)) {
+            (Ok(recipient), Ok(body)) => (recipient, body),
+            (Err(e), _) | (_, Err(e)) => {
+                theme.print(Class::Warning, &e);
+                return;
+            }
+        };
 
-        let mut host = HOST.to_string();
+        match client
+            .send_ephemeral_message(&recipient, &body, Duration::from_secs(ttl))
+            .await
+        {
+            SendStatus::Sent => {}
+            SendStatus::Pending => theme.print(
+                Class::Warning,
+                &format!("Message to {} is pending until the connection is restored", recipient),
+            ),
+            SendStatus::OutboxFull => theme.print(
+                Class::Error,
+                &format!("Outbox is full, message to {} was dropped", recipient),
+            ),
+        }
+    }
+
+    async fn handle_file_command(theme: Theme, client: &ChatClient, command: &str, args: &str) {
+        let args = args.trim();
+        let result = match command {
+            "sendfile" => match args.split_once(' ') {
+                Some((recipient, path)) if !path.trim().is_empty() => {
+                    client.send_file(recipient, Path::new(path.trim())).await
+                }
+                _ => Err("Usage: sendfile <user> <path>".to_string()),
+            },
+            _ => {
+                let (id, reason) = args.split_once(' ').unwrap_or((args, ""));
+                let Ok(id) = id.parse::<u64>() else {
+                    match command {
+                        "accept" => theme.print(Class::Warning, "Usage: accept <id>"),
+                        _ => theme.print(Class::Warning, "Usage: reject <id> [reason]"),
+                    }
+                    return;
+                };
+                let reason = match reason.trim() {
+                    "" => "Rejected",
+                    reason => reason,
+                };
+                let handled = match command {
+                    "accept" => client.accept_file(id).await,
+                    _ => client.reject_file(id, reason).await,
+                };
+                if handled {
+                    Ok(())
+                } else {
+                    Err(format!("No open file transfer #{}", id))
+                }
+            }
+        };
+        if let Err(e) = result {
+            theme.print(Class::Warning, &e);
+        }
+    }
+
+    async fn get_message_data(input: &mut Input) -> Option<(String, String)> {
+        let recipient = input.read_line("Enter recipient: ", Completion::Username).await?;
+        let message = input.read_line("Enter message: ", Completion::Nothing).await?;
+
+        Some((recipient, message))
+    }
+
+    fn options(&self) -> ClientOptions {
+        let mut options = ClientOptions::new();
+
+        if let Some(tracer) = &self.tracer {
+            options = options.with_tracer(tracer.clone());
+        }
 
         if let Ok(env_host) = std::env::var("SERVER_HOST") {
-            host = env_host.trim().to_string();
+            options = options.with_host(env_host.trim());
         }
 
-        tracing::debug!("Connecting to server at {}:{}", host, PORT);
+        if let Some(capacity) = std::env::var("OUTBOX_CAPACITY")
+            .ok()
+            .and_then(|capacity| capacity.trim().parse().ok())
+        {
+            options = options.with_outbox_capacity(capacity);
+        }
 
-        let stream = TcpStream::connect((host, PORT)).await?;
-        let stream_addr = stream.peer_addr()?;
+        if let Ok(download_dir) = std::env::var("DOWNLOAD_DIR") {
+            options = options.with_download_dir(PathBuf::from(download_dir.trim()));
+        }
 
-        let (reader, writer) = stream.into_split();
-        let (tx, rx) = mpsc::unbounded_channel::<Message>();
+        if let Some(language) = std::env::var("LANG").ok().and_then(|locale| language_of(&locale)) {
+            options = options.with_language(&language);
+        }
 
-        let (hdc_tx, hdc_rx) = mpsc::channel::<bool>(1);
-        let (sdc_tx, sdc_rx) = mpsc::channel::<bool>(1);
+        // like the server's, but in order of preference
+        if let Some(integrity) = std::env::var("FRAME_INTEGRITY").ok().and_then(|integrity| {
+            integrity
+                .split(',')
+                .map(Integrity::parse)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+        }) {
+            options = options.with_integrity(integrity);
+        }
 
-        let send_h = tokio::spawn(Self::handle_send(writer, rx, sdc_tx, hdc_rx));
-        let recv_h = tokio::spawn(Self::handle_receive(reader, tx.clone(), hdc_tx, sdc_rx));
+        // `round_trip=2,reconnects=0.5`, what each measurement counts toward the connection quality
+        if let Ok(weights) = std::env::var("QUALITY_WEIGHTS") {
+            match QualityWeights::parse(&weights) {
+                Ok(weights) => options = options.with_quality_weights(weights),
+                Err(e) => tracing::warn!("Ignoring QUALITY_WEIGHTS: {}", e),
+            }
+        }
+
+        // the keys of others are checked whether or not this client signs its own messages
+        if let Some(path) = home_file("KNOWN_KEYS_FILE", KNOWN_KEYS_FILE_NAME) {
+            match KnownKeys::load(&path) {
+                Ok(known_keys) => options = options.with_known_keys(known_keys),
+                Err(e) => tracing::warn!("Ignoring the known signing keys: {}", e),
+            }
+        }
+
+        // the key file is made on the first start, copy it to the other devices of the account
+        if std::env::var("SIGN_MESSAGES").is_ok_and(|sign| sign.trim() == "on") {
+            match home_file("SIGNING_KEY_FILE", SIGNING_KEY_FILE_NAME)
+                .ok_or_else(|| "SIGN_MESSAGES needs SIGNING_KEY_FILE or HOME".to_string())
+                .and_then(|path| SigningIdentity::load_or_create(&path))
+            {
+                Ok(identity) => options = options.with_signing_identity(identity),
+                Err(e) => tracing::warn!("Sending unsigned messages: {}", e),
+            }
+        }
+
+        // named on the command line, so it wins over the environment
+        if let Some(profile) = &self.profile {
+            if let Some(host) = &profile.host {
+                options = options.with_host(host);
+            }
+            if let Some(port) = profile.port {
+                options = options.with_port(port);
+            }
+        }
+
+        options
+    }
+
+    pub async fn run(&self) -> Result<(), Box<dyn Error>> {
+        tracing::debug!("Starting application");
+        let theme = self.theme;
+
+        let (client, events) = ChatClient::connect(self.options()).await?;
+
+        if let Some(line) = &self.once {
+            return self.run_once(client, events, line).await;
+        }
+
+        // a listener stays a guest whatever the profile holds, 'auth' still logs in
+        if let Some((username, password)) = self.profile.as_ref().and_then(Profile::credentials) {
+            if !self.listen_only {
+                client.login(username, password).await;
+            }
+        }
+
+        if self.output == OutputMode::Json {
+            let input = BufReader::new(tokio::io::stdin());
+            return Ok(run_json(client, events, input, tokio::io::stdout()).await?);
+        }
+        // kept up for completion and 'who', servers without subscriptions are not asked
+        client.follow_presence(PresenceScope::All).await;
 
-        tracing::debug!("Connected to server {}", stream_addr);
+        let events_h = tokio::spawn(Self::handle_events(
+            self.theme,
+            events,
+            client.clone(),
+            Arc::clone(&self.completer),
+            self.shortcodes.clone(),
+            Arc::clone(&self.status),
+            self.title,
+        ));
+
+        if !self.drafts.lock().unwrap().is_empty() {
+            theme.print(Class::System, "Restored unsent input, 'draft list' shows it");
+        }
+        if self.listen_only {
+            theme.print(Class::System, "Listening without logging in, 'auth' logs in");
+        }
+        let drafts_h = tokio::spawn(Self::save_drafts(theme, Arc::clone(&self.drafts)));
+        let mut input = Input::new(
+            Arc::clone(&self.completer),
+            Arc::clone(&self.drafts),
+            self.vault.clone(),
+        );
+        let mut aliases = self.aliases.clone();
 
         loop {
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
+            Self::refresh_status(&client, &self.status).await;
+            let conversations = client.conversations().await.into_iter().map(|(name, _)| name);
+            self.completer.lock().unwrap().set_conversations(conversations);
+            let prompt = self.status.lock().unwrap().prompt();
+            let line = input.read_line(&prompt, Completion::Command).await;
+            self.status.lock().unwrap().acknowledge();
+            Self::show_title(&self.status, self.title);
+            // in a conversation a line is text for it, commands start with a slash there
+            let line = match line {
+                Some(line)
+                    if !line.is_empty() && !line.starts_with('/') && client.active_conversation().await.is_some() =>
+                {
+                    Self::send_to_active(theme, &client, &line).await;
+                    continue;
+                }
+                line => line,
+            };
+            let line = line.as_deref().map(|line| line.strip_prefix('/').unwrap_or(line));
+            let line = match aliases.expand(line.unwrap_or("dc")) {
+                Ok(line) => line,
+                Err(e) => {
+                    theme.print(Class::Warning, &e);
+                    continue;
+                }
+            };
+            let (command, args) = line.split_once(' ').unwrap_or((&line, ""));
+            if !command.is_empty() && command_argument(command).is_none() {
+                theme.print(Class::Warning, &format!("Unknown command '{}'", command));
+                continue;
+            }
+
+            // with arguments it exports a conversation of ours, which takes what reading its history takes
+            let checked = match (command, args.trim()) {
+                ("export", args) if !args.is_empty() => "history",
+                (command, _) => command,
+            };
+            if let Err(e) = client.check_command(checked).await {
+                theme.print(Class::Warning, &e.to_string());
+                continue;
+            }
 
-            let message = match input.trim() {
+            let message = match command {
                 "new" => {
-                    let (username, password) = Self::get_user_data();
-                    Message::auth_create(username.trim(), password.trim())
+                    if let Some((username, password)) = Self::get_user_data(theme, &mut input).await {
+                        if !client.create_account(&username, &password).await {
+                            theme.print(Class::Warning, "Not connected to the server, could not create account");
+                        }
+                    }
+                    continue;
                 }
                 "auth" => {
-                    let (username, password) = Self::get_user_data();
-                    Message::auth(username.trim(), password.trim())
+                    if let Some((username, password)) = Self::get_user_data(theme, &mut input).await {
+                        if !client.login(&username, &password).await {
+                            theme.print(Class::Warning, "Not connected to the server, could not authenticate");
+                        }
+                    }
+                    continue;
                 }
                 "msg" => {
-                    let (recipient, message) = Self::get_message_data();
-                    Message::direct_message_send(recipient.trim(), message.trim())
+                    // asks for whatever the line does not already say
+                    let data = match args.trim().split_once(' ') {
+                        Some((recipient, message)) => Some((recipient.to_string(), message.to_string())),
+                        None => Self::get_message_data(&mut input).await,
+                    };
+                    let data = data.map(|(recipient, message)| {
+                        Ok::<_, String>((normalize_username(&recipient)?, normalize_body(&message)?))
+                    });
+                    if let Some(Err(e)) = &data {
+                        theme.print(Class::Warning, e);
+                    }
+                    if let Some(Ok((recipient, message))) = data {
+                        self.completer.lock().unwrap().add_username(&recipient);
+                        self.status.lock().unwrap().set_active_peer(&recipient);
+                        match client.send_direct_message(&recipient, &message).await {
+                            SendStatus::Sent => {}
+                            SendStatus::Pending => theme.print(
+                                Class::Warning,
+                                &format!("Message to {} is pending until the connection is restored", recipient),
+                            ),
+                            SendStatus::OutboxFull => theme.print(
+                                Class::Error,
+                                &format!("Outbox is full, message to {} was dropped", recipient),
+                            ),
+                        }
+                    }
+                    continue;
+                }
+                "note" => {
+                    match args.trim() {
+                        "" => theme.print(Class::Warning, "Usage: note <text>"),
+                        body => match client.send_note(body).await {
+                            None => theme.print(Class::Warning, "Log in before writing notes"),
+                            Some(SendStatus::Sent) => {}
+                            Some(SendStatus::Pending) => {
+                                theme.print(Class::Warning, "Note is pending until the connection is restored")
+                            }
+                            Some(SendStatus::OutboxFull) => {
+                                theme.print(Class::Error, "Outbox is full, note was dropped")
+                            }
+                        },
+                    }
+                    continue;
+                }
+                "passwd" => {
+                    if let Some((old_password, new_password)) = Self::get_password_change(&mut input).await {
+                        if !client.change_password(&old_password, &new_password).await {
+                            theme.print(Class::Warning, "Not connected to the server, could not change password");
+                        }
+                    }
+                    continue;
+                }
+                "rename" => {
+                    match args.trim() {
+                        "" => theme.print(Class::Warning, "Usage: rename <new name>"),
+                        new_name => {
+                            let Some(password) = input.read_line("Enter password: ", Completion::Nothing).await else {
+                                continue;
+                            };
+                            if !client.rename_account(new_name, &password).await {
+                                theme.print(Class::Warning, "Not connected to the server, could not rename account");
+                            }
+                        }
+                    }
+                    continue;
                 }
-                "log" => Message::SERVER_DEBUG_LOG,
-                "dc" => Message::DISCONNECT,
-                "shutdown" => Message::server_shutdown(5),
-                _ => Message::heartbeat(),
+                "resetpw" => {
+                    if let Some((username, password)) = Self::get_user_data(theme, &mut input).await {
+                        if !client.reset_password(&username, &password).await {
+                            theme.print(Class::Warning, "Not connected to the server, could not reset password");
+                        }
+                    }
+                    continue;
+                }
+                "alias" | "unalias" => {
+                    self.handle_alias_command(&mut aliases, command, args.trim());
+                    continue;
+                }
+                "switch" | "close" => {
+                    self.handle_conversation_command(&client, command, args.trim()).await;
+                    continue;
+                }
+                "outbox" => {
+                    Self::handle_outbox_command(theme, &client, args.trim()).await;
+                    continue;
+                }
+                "draft" => {
+                    self.handle_draft_command(args.trim());
+                    continue;
+                }
+                "edit" | "delete" => {
+                    Self::handle_edit_command(theme, &client, command, args).await;
+                    continue;
+                }
+                "ephemeral" => {
+                    Self::handle_ephemeral_command(theme, &client, args).await;
+                    continue;
+                }
+                "search" => {
+                    match args.trim() {
+                        "" => theme.print(Class::Warning, "Usage: search <text>"),
+                        query => {
+                            if !client.search(query, None, DEFAULT_SEARCH_LIMIT).await {
+                                theme.print(Class::Warning, "Not connected to the server, could not search");
+                            }
+                        }
+                    }
+                    continue;
+                }
+                "read" => {
+                    match args.split_whitespace().next() {
+                        Some(peer) => {
+                            if !client.mark_read(peer).await {
+                                theme.print(
+                                    Class::Warning,
+                                    &format!("Not connected to the server, could not mark {} as read", peer),
+                                );
+                            }
+                        }
+                        None => theme.print(Class::Warning, "Usage: read <user>"),
+                    }
+                    continue;
+                }
+                // several pairs are set together, none of them if one is refused
+                "pref" => {
+                    let words: Vec<&str> = args.split_whitespace().collect();
+                    if words.is_empty() || words.len() % 2 != 0 {
+                        theme.print(Class::Warning, "Usage: pref <key> <value> [<key> <value>...]");
+                        continue;
+                    }
+                    let updates: Vec<(String, String)> = words
+                        .chunks(2)
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect();
+                    if !client.set_preferences(&updates).await {
+                        theme.print(
+                            Class::Warning,
+                            &format!("Not connected to the server, could not set {}", updates[0].0),
+                        );
+                    }
+                    continue;
+                }
+                "prefs" => {
+                    for (key, value) in client.preferences().await {
+                        theme.print(Class::System, &format!("{} = {}", key, value));
+                    }
+                    continue;
+                }
+                "mute" | "unmute" => {
+                    match args.split_whitespace().next() {
+                        Some(sender) => {
+                            let sent = match command {
+                                "mute" => client.mute(sender).await,
+                                _ => client.unmute(sender).await,
+                            };
+                            if !sent {
+                                theme.print(
+                                    Class::Warning,
+                                    &format!("Not connected to the server, could not {} {}", command, sender),
+                                );
+                            }
+                        }
+                        None => theme.print(Class::Warning, &format!("Usage: {} <user>", command)),
+                    }
+                    continue;
+                }
+                "who" => {
+                    Self::handle_who_command(theme, &client, args.trim()).await;
+                    continue;
+                }
+                "trust" => {
+                    match args.split_whitespace().next() {
+                        Some(username) => match client.trust_signing_key(username).await {
+                            Ok(fingerprint) => theme.print(
+                                Class::System,
+                                &format!("Trusting {} with key {}", username, fingerprint),
+                            ),
+                            Err(e) => theme.print(Class::Warning, &e),
+                        },
+                        None => theme.print(Class::Warning, "Usage: trust <user>"),
+                    }
+                    continue;
+                }
+                "fingerprint" => {
+                    let username = args.split_whitespace().next();
+                    match (client.signing_fingerprint(username).await, username) {
+                        (Some(fingerprint), Some(username)) => {
+                            theme.print(Class::System, &format!("Key of {}: {}", username, fingerprint))
+                        }
+                        (Some(fingerprint), None) => theme.print(Class::System, &format!("Your key: {}", fingerprint)),
+                        (None, Some(username)) => {
+                            theme.print(Class::Warning, &format!("No signing key of {} known", username))
+                        }
+                        (None, None) => {
+                            theme.print(Class::Warning, "Not signing messages, start with SIGN_MESSAGES=on")
+                        }
+                    }
+                    continue;
+                }
+                "mutes" => {
+                    let muted = client.muted_senders().await;
+                    match muted.is_empty() {
+                        true => theme.print(Class::System, "Nobody is muted"),
+                        false => theme.print(
+                            Class::System,
+                            &format!("Muted: {}", muted.into_iter().collect::<Vec<_>>().join(", ")),
+                        ),
+                    }
+                    continue;
+                }
+                "sendfile" | "accept" | "reject" => {
+                    Self::handle_file_command(theme, &client, command, args).await;
+                    continue;
+                }
+                "export" if !args.trim().is_empty() => {
+                    let exported = match TranscriptExport::parse(args) {
+                        Ok(export) => client.export_transcript(export).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = exported {
+                        theme.print(Class::Warning, &e);
+                    }
+                    continue;
+                }
+                "dc" => {
+                    client.disconnect().await;
+                    break;
+                }
+                _ => match parse_request(command, args) {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        theme.print(Class::Warning, &e);
+                        continue;
+                    }
+                    None => Message::heartbeat(),
+                },
             };
 
-            if tx.is_closed() {
-                break;
-            }
-
             let msg_type = message.message_type();
-            if let Err(e) = tx.send(message) {
-                tracing::error!("Error sending message: {}", e);
-                break;
-            }
-            if msg_type == MessageType::Disconnect {
-                break;
+            if !client.send(message).await {
+                theme.print(
+                    Class::Warning,
+                    &format!("Not connected to the server, could not send {:?}", msg_type),
+                );
             }
         }
 
-        send_h.await?;
-        recv_h.await?;
+        events_h.await?;
+
+        drafts_h.abort();
+        let mut drafts = self.drafts.lock().unwrap();
+        if drafts.is_dirty() {
+            if let Err(e) = drafts.save() {
+                theme.print(Class::Warning, &format!("Could not save drafts: {}", e));
+            }
+        }
+        drop(drafts);
 
         tracing::debug!("Closing connection");
 
         Ok(())
     }
 
-    async fn handle_send(
-        mut writer: tokio::net::tcp::OwnedWriteHalf,
-        mut rx: mpsc::UnboundedReceiver<Message>,
-        dc_tx: mpsc::Sender<bool>,
-        mut dc_rx: mpsc::Receiver<bool>,
-    ) {
-        loop {
-            if dc_rx.try_recv().is_ok() {
-                break;
+    // the answer is printed like it would be in a session, failures come back as OnceError for the exit code
+    async fn run_once(
+        &self,
+        client: ChatClient,
+        mut events: mpsc::UnboundedReceiver<ClientEvent>,
+        line: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let line = self.aliases.expand(line).map_err(OnceError::Usage)?;
+        let credentials = self
+            .profile
+            .as_ref()
+            .and_then(Profile::credentials)
+            .ok_or_else(|| OnceError::Usage("once needs a --profile with a username and a password".to_string()))?;
+
+        let answered = run_once(&client, &mut events, credentials, &line, ONCE_TIMEOUT).await?;
+        if self.output == OutputMode::Json {
+            for event in answered {
+                println!("{}", event.to_json());
             }
+            return Ok(());
+        }
 
-            if let Some(message) = rx.recv().await {
-                if message.is(MessageType::Break) {
-                    // dc_tx.try_send(true).unwrap();
-                    break;
+        let (tx, rx) = mpsc::unbounded_channel();
+        for event in answered {
+            tx.send(event).ok();
+        }
+        drop(tx);
+        Self::handle_events(
+            self.theme,
+            rx,
+            client,
+            Arc::clone(&self.completer),
+            self.shortcodes.clone(),
+            Arc::clone(&self.status),
+            false,
+        )
+        .await;
+        Ok(())
+    }
+
+    async fn refresh_status(client: &ChatClient, status: &Mutex<SessionStatus>) {
+        let state = client.connection_state().await;
+        let username = client.username().await;
+        let server = client.server_name().await;
+        let contact = client.contact().await;
+        let quality = client.connection_quality().await;
+        let conversation = client.active_conversation().await;
+
+        let mut status = status.lock().unwrap();
+        status.set_state(state);
+        status.set_username(username);
+        status.set_server(server);
+        status.set_contact(contact);
+        status.set_quality(quality);
+        status.set_conversation(conversation);
+    }
+
+    // written only when it changed, the sequence is invisible but not free
+    fn show_title(status: &Mutex<SessionStatus>, enabled: bool) {
+        if !enabled {
+            return;
+        }
+        if let Some(changed) = status.lock().unwrap().title_change() {
+            let mut stdout = std::io::stdout();
+            write!(stdout, "{}", title::title_sequence(&changed)).ok();
+            stdout.flush().ok();
+        }
+    }
+
+    // `alias` lists, `alias <name> <expansion>` defines and `unalias <name>` removes, changes are saved right away
+    fn handle_alias_command(&self, aliases: &mut Aliases, command: &str, args: &str) {
+        let theme = self.theme;
+        let result = match (command, args.split_once(' ')) {
+            ("alias", None) if args.is_empty() => {
+                for (name, definition) in aliases.list() {
+                    let kind = match definition {
+                        Definition::Alias(_) => "alias",
+                        Definition::Macro(_) => "macro",
+                    };
+                    theme.print(
+                        Class::System,
+                        &format!("{} {} = \"{}\"", kind, name, definition.expansion()),
+                    );
                 }
-                tracing::debug!("Sending message: {:?}", message.message_type());
-                message.send(&mut writer).await.unwrap();
-                if message.is(MessageType::Disconnect) {
-                    dc_tx.try_send(true).unwrap();
-                    break;
+                return;
+            }
+            ("alias", Some((name, _))) if command_argument(name).is_some() => {
+                Err(format!("{} is a built-in command", name))
+            }
+            ("alias", Some((name, expansion))) => aliases
+                .define(name, Definition::guess(expansion))
+                .map(|()| format!("{} now expands to {}", name, expansion.trim())),
+            ("unalias", None) if aliases.remove(args) => Ok(format!("Removed {}", args)),
+            ("unalias", None) if !args.is_empty() => Err(format!("{} is not a user defined alias", args)),
+            ("alias", _) => Err("Usage: alias [<name> <command or line>]".to_string()),
+            _ => Err("Usage: unalias <name>".to_string()),
+        };
+
+        match result {
+            Ok(done) => {
+                theme.print(Class::System, &done);
+                if let Some(path) = &self.alias_file {
+                    if let Err(e) = aliases.save(path) {
+                        theme.print(
+                            Class::Warning,
+                            &format!("Could not save aliases to {}: {}", path.display(), e),
+                        );
+                    }
                 }
             }
+            Err(e) => theme.print(Class::Warning, &e),
         }
     }
 
-    async fn handle_receive(
-        mut reader: tokio::net::tcp::OwnedReadHalf,
-        tx: mpsc::UnboundedSender<Message>,
-        dc_tx: mpsc::Sender<bool>,
-        mut dc_rx: mpsc::Receiver<bool>,
-    ) {
+    fn handle_draft_command(&self, args: &str) {
+        let theme = self.theme;
+        let mut drafts = self.drafts.lock().unwrap();
+        match args.split_once(' ').unwrap_or((args, "")) {
+            ("" | "list", _) => {
+                if drafts.is_empty() {
+                    theme.print(Class::System, "No drafts");
+                }
+                for (name, text) in drafts.entries() {
+                    theme.print(Class::System, &format!("{}: {}", name, text));
+                }
+            }
+            ("clear", name) => {
+                let name = Some(name.trim()).filter(|name| !name.is_empty());
+                let cleared = drafts.clear(name, Instant::now());
+                theme.print(Class::System, &format!("Cleared {} drafts", cleared));
+            }
+            _ => theme.print(Class::Warning, "Usage: draft [list | clear [<name>]]"),
+        }
+    }
+
+    // the editor only records changes, the file is written from here once typing pauses
+    async fn save_drafts(theme: Theme, drafts: Arc<Mutex<Drafts>>) {
+        let mut interval = tokio::time::interval(SAVE_DELAY / 2);
         loop {
-            if dc_rx.try_recv().is_ok() {
-                break;
+            interval.tick().await;
+            if let Err(e) = drafts.lock().unwrap().save_if_due(Instant::now()) {
+                theme.print(Class::Warning, &format!("Could not save drafts: {}", e));
             }
+        }
+    }
 
-            if !Message::has_header_start(&mut reader).await {
-                continue;
+    // everyone by default, a scope that is not followed yet is followed from now on
+    async fn handle_who_command(theme: Theme, client: &ChatClient, args: &str) {
+        let scope = match args {
+            "" => PresenceScope::All,
+            scope => match PresenceScope::parse(scope) {
+                Ok(scope) => scope,
+                Err(e) => return theme.print(Class::Warning, &e),
+            },
+        };
+        match client.roster(&scope).await {
+            Some(entries) if entries.is_empty() => theme.print(Class::System, "Nobody is online"),
+            Some(entries) => {
+                let names: Vec<String> = entries
+                    .iter()
+                    .map(|entry| match entry.status {
+                        PresenceStatus::Online => theme.name(&entry.user),
+                        PresenceStatus::DoNotDisturb => format!("{} (dnd)", theme.name(&entry.user)),
+                    })
+                    .collect();
+                theme.print(
+                    Class::System,
+                    &format!("Online ({}): {}", names.len(), names.join(", ")),
+                );
+            }
+            None => {
+                client.follow_presence(scope).await;
+                theme.print(Class::System, "Asking the server who is online, try again in a moment");
             }
+        }
+    }
 
-            let message = Message::receive(&mut reader).await;
-            match message {
-                Ok(message) => {
-                    tracing::debug!("Received message: {:?}", message.message_type());
-                    match message.message_type() {
-                        MessageType::Disconnect => {
-                            if let Err(e) = dc_tx.try_send(true) {
-                                tracing::warn!("Error sending disconnect message: {}", e);
-                            }
-                            tx.send(Message::BREAK).unwrap();
-                            break;
-                        }
-                        MessageType::Heartbeat => {
-                            tx.send(Message::heartbeat()).unwrap();
+    async fn handle_outbox_command(theme: Theme, client: &ChatClient, args: &str) {
+        match args.split_once(' ').unwrap_or((args, "")) {
+            ("", _) => {
+                let entries = client.outbox().await;
+                if entries.is_empty() {
+                    theme.print(Class::System, "Outbox is empty");
+                }
+                for (index, entry) in entries.iter().enumerate() {
+                    let status = match entry.state() {
+                        OutboxState::Pending => "pending",
+                        OutboxState::InFlight => "sending",
+                    };
+                    theme.print(
+                        Class::System,
+                        &format!(
+                            "[{}] {} to {} ({}): {}",
+                            index,
+                            status,
+                            entry.recipient(),
+                            entry.queued_at().format("%H:%M:%S"),
+                            entry.body()
+                        ),
+                    );
+                }
+            }
+            ("cancel", index) => match index.trim().parse::<usize>() {
+                Ok(index) => match client.cancel_outbox_entry(index).await {
+                    Some(entry) => theme.print(Class::System, &format!("Cancelled message to {}", entry.recipient())),
+                    None => theme.print(Class::Warning, &format!("No pending message with index {}", index)),
+                },
+                Err(_) => theme.print(Class::Warning, "Usage: outbox cancel <index>"),
+            },
+            _ => theme.print(Class::Warning, "Usage: outbox [cancel <index>]"),
+        }
+    }
+
+    // now is server time so a skewed local clock does not distort the age
+    fn describe_sent_at(sent_at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+        let local = sent_at.with_timezone(&Local).format("%H:%M:%S");
+        let age = now.signed_duration_since(sent_at);
+
+        // anything older than a minute was most likely queued while we were offline
+        match age.num_seconds() {
+            ..=59 => format!("at {}", local),
+            60..=3599 => format!("at {}, sent {}m ago", local, age.num_minutes()),
+            3600..=86399 => format!("at {}, sent {}h ago", local, age.num_hours()),
+            _ => format!("at {}, sent {}d ago", local, age.num_days()),
+        }
+    }
+
+    // the largest unit and the one below it, 3725 seconds are 1h02m
+    fn describe_age(seconds: u64) -> String {
+        match seconds {
+            ..=59 => format!("{}s", seconds),
+            60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+            3600..=86399 => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+            _ => format!("{}d{:02}h", seconds / 86400, seconds % 86400 / 3600),
+        }
+    }
+
+    fn describe_disconnect(reason: DisconnectReason) -> &'static str {
+        match reason {
+            DisconnectReason::ClientQuit => "The server closed the connection",
+            DisconnectReason::ServerShutdown => "The server is shutting down",
+            DisconnectReason::Kicked => "You were kicked",
+            DisconnectReason::Banned => "You were banned",
+            DisconnectReason::IdleTimeout => "You stopped answering the server",
+            DisconnectReason::ProtocolError => "The server could not understand the client",
+            DisconnectReason::AuthTimeout => "You did not log in in time",
+        }
+    }
+
+    async fn handle_events(
+        theme: Theme,
+        mut events: mpsc::UnboundedReceiver<ClientEvent>,
+        client: ChatClient,
+        completer: Arc<Mutex<Completer>>,
+        shortcodes: Shortcodes,
+        status: Arc<Mutex<SessionStatus>>,
+        title: bool,
+    ) {
+        // what others wrote is shown with its shortcodes expanded, their names cut to fit the columns
+        let name = |name: &str| theme.name(&text::truncate(name, MAX_NAME_COLUMNS));
+        // session rows are shown as one table once the list is complete
+        let mut sessions = Vec::new();
+        while let Some(event) = events.recv().await {
+            match &event {
+                // a muted sender's messages are shown, they just do not count as new
+                ClientEvent::DirectMessage {
+                    sender,
+                    self_note: false,
+                    muted: false,
+                    ..
+                } => status.lock().unwrap().record_incoming(sender),
+                ClientEvent::UnreadSummary(counts) => status
+                    .lock()
+                    .unwrap()
+                    .set_unread(counts.iter().map(|(_, count)| count).sum()),
+                _ => {}
+            }
+            Self::refresh_status(&client, &status).await;
+            Self::show_title(&status, title);
+
+            match event {
+                ClientEvent::Connected => tracing::debug!("Connected to server"),
+                // the prompt shows the state, the events around a change already explain it
+                ClientEvent::StateChanged { from, to } => tracing::debug!("Connection state {} -> {}", from, to),
+                ClientEvent::Disconnected => theme.print(Class::Warning, "Connection lost"),
+                ClientEvent::Reconnecting(interval) => {
+                    theme.print(Class::Warning, &format!("Reconnecting in {} seconds", interval))
+                }
+                ClientEvent::Authenticated => theme.print(Class::System, "Authenticated"),
+                ClientEvent::AuthFailed(error) => {
+                    theme.print(Class::Error, &format!("Authentication failed | Error: {}", error))
+                }
+                ClientEvent::DirectMessage {
+                    sender,
+                    body,
+                    sent_at,
+                    id,
+                    expires_at,
+                    self_note,
+                    muted,
+                    verification,
+                } => {
+                    {
+                        let mut completer = completer.lock().unwrap();
+                        completer.add_username(&sender);
+                        completer.add_conversation(if self_note { NOTES } else { &sender });
+                    }
+                    let body = shortcodes.expand(&body);
+                    let now = client.server_time_now().await;
+                    let id = id.map(|id| format!(" #{}", id)).unwrap_or_default();
+                    let expires = expires_at
+                        .map(|expires_at| format!(" (expires {})", expires_at.with_timezone(&Local).format("%H:%M:%S")))
+                        .unwrap_or_default();
+                    let muted = if muted { " (muted)" } else { "" };
+                    let signed = signature_note(verification);
+                    if self_note {
+                        theme.print(
+                            Class::Own,
+                            &format!(
+                                "Note{}{} {}: {}{}",
+                                id,
+                                signed,
+                                Self::describe_sent_at(sent_at, now),
+                                body,
+                                expires
+                            ),
+                        );
+                    } else {
+                        theme.print(
+                            Class::Incoming,
+                            &format!(
+                                "Message{} from {}{}{} {}: {}{}",
+                                id,
+                                name(&sender),
+                                muted,
+                                signed,
+                                Self::describe_sent_at(sent_at, now),
+                                body,
+                                expires
+                            ),
+                        );
+                    }
+                    if verification == Verification::Invalid {
+                        theme.print(
+                            Class::Warning,
+                            &format!("The message{} does not carry a valid signature of {}", id, sender),
+                        );
+                    }
+                }
+                ClientEvent::MessageVerified {
+                    id,
+                    sender,
+                    verification,
+                } => {
+                    let id = id.map(|id| format!(" #{}", id)).unwrap_or_default();
+                    match verification {
+                        Verification::Invalid => theme.print(
+                            Class::Warning,
+                            &format!("The message{} does not carry a valid signature of {}", id, sender),
+                        ),
+                        verification => theme.print(
+                            Class::System,
+                            &format!("Message{} from {} is {}", id, name(&sender), verification),
+                        ),
+                    }
+                }
+                ClientEvent::SigningKeyChanged {
+                    username,
+                    trusted,
+                    announced,
+                } => theme.print(
+                    Class::Warning,
+                    &format!(
+                        "The signing key of {} changed from {} to {}, compare it with them before `trust {}`",
+                        username, trusted, announced, username
+                    ),
+                ),
+                ClientEvent::Delivered { recipient, id, .. } => match id {
+                    Some(id) => theme.print(Class::Own, &format!("Message #{} to {} delivered", id, recipient)),
+                    None => theme.print(Class::Own, &format!("Message to {} delivered", recipient)),
+                },
+                ClientEvent::MessageEdited { id, sender, body, .. } => theme.print(
+                    Class::Incoming,
+                    &format!(
+                        "Message #{} from {} edited: {}",
+                        id,
+                        name(&sender),
+                        shortcodes.expand(&body)
+                    ),
+                ),
+                ClientEvent::MessageDeleted { id, sender } => theme.print(
+                    Class::Incoming,
+                    &format!("Message #{} from {} deleted", id, theme.name(&sender)),
+                ),
+                ClientEvent::MessageExpired { id, sender } => theme.print(
+                    Class::Incoming,
+                    &format!("Message #{} from {} expired", id, theme.name(&sender)),
+                ),
+                ClientEvent::HistoryEntry {
+                    id,
+                    sender,
+                    body,
+                    sent_at,
+                    edited,
+                    deleted,
+                    verification,
+                    ..
+                } => {
+                    let time = sent_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+                    let sender = name(&sender);
+                    let body = shortcodes.expand(&body);
+                    let signed = signature_note(verification);
+                    match (deleted, edited) {
+                        (true, _) => theme.print(Class::Incoming, &format!("[{}] #{} {}: (deleted)", time, id, sender)),
+                        (false, true) => theme.print(
+                            Class::Incoming,
+                            &format!("[{}] #{} {}: {} (edited)", time, id, sender, body),
+                        ),
+                        (false, false) => theme.print(
+                            Class::Incoming,
+                            &format!("[{}] #{} {}{}: {}", time, id, sender, signed, body),
+                        ),
+                    }
+                }
+                ClientEvent::HistoryEnd(count) => theme.print(Class::System, &format!("{} messages in history", count)),
+                ClientEvent::SearchResult {
+                    id,
+                    peer,
+                    sent_at,
+                    snippet,
+                    highlight: (start, end),
+                } => {
+                    let time = sent_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+                    theme.print(
+                        Class::Incoming,
+                        &format!(
+                            "[{}] #{} with {}: {}*{}*{}",
+                            time,
+                            id,
+                            theme.name(&peer),
+                            &snippet[..start],
+                            &snippet[start..end],
+                            &snippet[end..]
+                        ),
+                    );
+                }
+                ClientEvent::SearchEnd { error: Some(error), .. } => {
+                    theme.print(Class::Warning, &format!("Search failed: {}", error))
+                }
+                ClientEvent::SearchEnd { count, error: None } => {
+                    theme.print(Class::System, &format!("{} matching messages", count))
+                }
+                ClientEvent::Preferences(entries) => {
+                    let entries: Vec<String> = entries
+                        .iter()
+                        .map(|(key, value)| format!("{} = {}", key, value))
+                        .collect();
+                    tracing::debug!("Preferences: {}", entries.join(", "))
+                }
+                ClientEvent::PreferencesChanged(_) => theme.print(
+                    Class::System,
+                    "Preferences were changed in another session, 'prefs' shows them",
+                ),
+                ClientEvent::MutedSenders(senders) => tracing::debug!("Muted: {}", senders.join(", ")),
+                ClientEvent::ScheduledMessages(entries) => match entries.is_empty() {
+                    true => theme.print(Class::System, "No scheduled messages"),
+                    false => {
+                        for entry in entries {
+                            let due = entry.due.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+                            theme.print(
+                                Class::System,
+                                &format!(
+                                    "#{} at {} to {}: {}",
+                                    entry.id,
+                                    due,
+                                    theme.name(&entry.recipient),
+                                    entry.body
+                                ),
+                            );
                         }
-                        MessageType::ServerShutdownWarning => {
-                            let data = message.payload().get_data();
-                            let timeout = u64::from_be_bytes(data[0].clone().try_into().unwrap());
-                            tracing::warn!("Server shutting down in {} seconds", timeout);
+                    }
+                },
+                ClientEvent::ScheduledMessageSent {
+                    id,
+                    recipient,
+                    error: None,
+                } => theme.print(
+                    Class::System,
+                    &format!("Scheduled message #{} was sent to {}", id, theme.name(&recipient)),
+                ),
+                ClientEvent::ScheduledMessageSent {
+                    id,
+                    recipient,
+                    error: Some(error),
+                } => theme.print(
+                    Class::Warning,
+                    &format!("Scheduled message #{} to {} was not sent: {}", id, recipient, error),
+                ),
+                ClientEvent::ApiTokens(tokens) => match tokens.is_empty() {
+                    true => theme.print(Class::System, "No API tokens"),
+                    false => {
+                        for token in tokens {
+                            let created = token.created.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+                            theme.print(
+                                Class::System,
+                                &format!("#{} {} (made {})", token.id, token.label, created),
+                            );
                         }
-                        MessageType::MessageError => {
-                            let data = message.payload().get_data();
-                            let error = std::str::from_utf8(&data[0]).unwrap();
-                            tracing::error!("Could not send message | Error: {}", error);
+                    }
+                },
+                ClientEvent::ApiTokenCreated { info, token } => theme.print(
+                    Class::System,
+                    &format!(
+                        "API token #{} '{}': {} (it is not shown again)",
+                        info.id, info.label, token
+                    ),
+                ),
+                ClientEvent::UnreadSummary(counts) => match counts.split_first() {
+                    Some(((peer, count), rest)) => {
+                        let others: String = rest
+                            .iter()
+                            .map(|(peer, count)| format!(", {} from {}", count, peer))
+                            .collect();
+                        let noun = if *count == 1 { "message" } else { "messages" };
+                        theme.print(
+                            Class::System,
+                            &format!("You have {} unread {} from {}{}", count, noun, peer, others),
+                        )
+                    }
+                    None => theme.print(Class::System, "No unread messages"),
+                },
+                ClientEvent::SyncStarted { total, .. } => theme.print(
+                    Class::System,
+                    &format!("Fetching {} messages that arrived while you were away", total),
+                ),
+                ClientEvent::SyncProgress { delivered, remaining } => {
+                    tracing::debug!("Fetched {} queued messages, {} to go", delivered, remaining)
+                }
+                ClientEvent::SyncFinished { delivered } => tracing::debug!("Fetched all {} queued messages", delivered),
+                ClientEvent::FileOffered {
+                    id,
+                    recipient,
+                    filename,
+                } => theme.print(
+                    Class::System,
+                    &format!("Offered {} to {} as transfer #{}", filename, recipient, id),
+                ),
+                ClientEvent::FileOffer {
+                    id,
+                    sender,
+                    filename,
+                    size,
+                } => theme.print(
+                    Class::Incoming,
+                    &format!(
+                        "{} wants to send you {} ({} bytes), type 'accept {}' or 'reject {}'",
+                        theme.name(&sender),
+                        filename,
+                        size,
+                        id,
+                        id
+                    ),
+                ),
+                ClientEvent::FileAccepted(id) => {
+                    theme.print(Class::System, &format!("Transfer #{} accepted, sending", id))
+                }
+                ClientEvent::FileSent(id) => theme.print(Class::System, &format!("Transfer #{} sent", id)),
+                ClientEvent::FileReceived { id, sender, path } => theme.print(
+                    Class::Incoming,
+                    &format!(
+                        "Transfer #{} from {} saved to {}",
+                        id,
+                        theme.name(&sender),
+                        path.display()
+                    ),
+                ),
+                ClientEvent::FileRejected { id, reason } => {
+                    theme.print(Class::Warning, &format!("Transfer #{} cancelled: {}", id, reason))
+                }
+                ClientEvent::DeliveryExpired { id, recipient } => theme.print(
+                    Class::Warning,
+                    &format!("Message #{} to {} expired before it was delivered", id, recipient),
+                ),
+                ClientEvent::DeliveryFailed { recipient, error, .. } => match recipient {
+                    Some(recipient) => theme.print(
+                        Class::Error,
+                        &format!("Could not send message to {} | Error: {}", recipient, error),
+                    ),
+                    None => theme.print(Class::Error, &format!("Could not send message | Error: {}", error)),
+                },
+                ClientEvent::ServerCapabilities(capabilities) => {
+                    tracing::debug!("Server capabilities: {}", capabilities.join(", "))
+                }
+                ClientEvent::ServerIdentity { name, id } => tracing::debug!("Connected to {} ({})", name, id),
+                ClientEvent::Motd(text) if text.is_empty() => {}
+                ClientEvent::Motd(text) => theme.print(Class::System, &format!("Message of the day: {}", text)),
+                ClientEvent::ReauthRequired(reason) => {
+                    theme.print(Class::Warning, &format!("Re-authentication required: {}", reason))
+                }
+                ClientEvent::SecurityNotice { detail, .. } => {
+                    theme.print(Class::Warning, &format!("Security notice: {}", detail))
+                }
+                ClientEvent::ConnectionStale { two_way_ms, .. } => theme.print(
+                    Class::Warning,
+                    &format!(
+                        "The server has not heard from us for {}, reconnecting",
+                        Self::describe_age(two_way_ms / 1000)
+                    ),
+                ),
+                // the title and the prompt show it
+                ClientEvent::ConnectionQuality(level) => tracing::debug!("Connection quality {}", level),
+                ClientEvent::ProtocolFault { error, recovered: true } => theme.print(
+                    Class::Warning,
+                    &format!("Skipped a garbled message from the server: {}", error),
+                ),
+                ClientEvent::ProtocolFault {
+                    error,
+                    recovered: false,
+                } => theme.print(
+                    Class::Warning,
+                    &format!("Lost track of the server's messages, reconnecting: {}", error),
+                ),
+                ClientEvent::ServerDisconnected { reason, detail } => match detail.as_str() {
+                    "" => theme.print(Class::Warning, Self::describe_disconnect(reason)),
+                    detail => theme.print(
+                        Class::Warning,
+                        &format!("{}: {}", Self::describe_disconnect(reason), detail),
+                    ),
+                },
+                ClientEvent::ServerBusy { retry_after, reason } => theme.print(
+                    Class::Warning,
+                    &format!("{}, retrying in {} seconds", reason, retry_after),
+                ),
+                ClientEvent::AccessLevelChanged { username, level } => {
+                    theme.print(Class::System, &format!("Access level of {} is now {}", username, level))
+                }
+                ClientEvent::UserKicked { username, reason } => {
+                    theme.print(Class::System, &format!("Kicked {}: {}", username, reason))
+                }
+                ClientEvent::MissedNotice {
+                    detail, occurred_at, ..
+                } => {
+                    let now = client.server_time_now().await;
+                    theme.print(
+                        Class::System,
+                        &format!(
+                            "While you were away {}: {}",
+                            Self::describe_sent_at(occurred_at, now),
+                            detail
+                        ),
+                    );
+                }
+                ClientEvent::UserInfo {
+                    username,
+                    access_level,
+                    sessions,
+                    sent,
+                    delivered,
+                    queued,
+                    dropped,
+                    last_error,
+                    temporary_grants,
+                } => {
+                    let counters = [
+                        (sent, "sent"),
+                        (delivered, "delivered"),
+                        (queued, "queued"),
+                        (dropped, "dropped"),
+                    ]
+                    .map(|(count, what)| format!("{} {}", count, what));
+                    theme.print(
+                        Class::System,
+                        &format!(
+                            "{} ({}, {} sessions): {}",
+                            theme.name(&username),
+                            access_level,
+                            sessions,
+                            counters.join(", ")
+                        ),
+                    );
+                    if let Some(error) = last_error {
+                        theme.print(Class::System, &format!("Last delivery error: {}", error));
+                    }
+                    if !temporary_grants.is_empty() {
+                        let grants: Vec<String> = temporary_grants
+                            .iter()
+                            .map(|(permission, expires_at)| format!("{} until {}", permission, expires_at))
+                            .collect();
+                        theme.print(Class::System, &format!("Temporary grants: {}", grants.join(", ")));
+                    }
+                }
+                ClientEvent::SessionInfo(info) => sessions.push(info),
+                ClientEvent::SessionListEnd { count, kicked } => {
+                    let mut rows = vec![[
+                        "session",
+                        "user",
+                        "peer",
+                        "level",
+                        "connected",
+                        "idle",
+                        "heartbeat",
+                        "two-way",
+                        "in",
+                        "out",
+                        "integrity",
+                    ]
+                    .map(String::from)
+                    .to_vec()];
+                    for info in sessions.drain(..) {
+                        rows.push(vec![
+                            info.id.chars().take(8).collect(),
+                            text::truncate(&info.username, MAX_NAME_COLUMNS),
+                            info.peer,
+                            info.access_level,
+                            Self::describe_age(info.connected),
+                            Self::describe_age(info.idle),
+                            Self::describe_age(info.heartbeat_age),
+                            Self::describe_age(info.two_way_age),
+                            info.received.to_string(),
+                            info.sent.to_string(),
+                            // failures only once there are some, most sessions never have one
+                            match info.integrity_failures {
+                                0 => info.integrity,
+                                failures => format!("{} ({} failed)", info.integrity, failures),
+                            },
+                        ]);
+                    }
+                    if count > 0 {
+                        for line in text::table(&rows) {
+                            theme.print(Class::System, &line);
                         }
-                        MessageType::DirectMessageReceive => {
-                            let data = message.payload().get_data();
-                            let sender = std::str::from_utf8(&data[0]).unwrap();
-                            let message = std::str::from_utf8(&data[1]).unwrap();
-                            tracing::info!("Message from {}: {}", sender, message);
+                    }
+                    let summary = match (kicked, count) {
+                        (true, _) => format!("Kicked {} sessions", count),
+                        (false, 1) => "1 session".to_string(),
+                        (false, _) => format!("{} sessions", count),
+                    };
+                    theme.print(Class::System, &summary);
+                }
+                ClientEvent::IpBans(list) => {
+                    let mut rows = vec![["address", "remaining"].map(String::from).to_vec()];
+                    for (address, remaining) in &list.bans {
+                        rows.push(vec![address.clone(), Self::describe_age(*remaining)]);
+                    }
+                    if !list.bans.is_empty() {
+                        for line in text::table(&rows) {
+                            theme.print(Class::System, &line);
                         }
-                        _ => {}
                     }
+                    theme.print(
+                        Class::System,
+                        &format!(
+                            "{} banned addresses, {} bans issued, {} connections turned away",
+                            list.bans.len(),
+                            list.issued,
+                            list.turned_away
+                        ),
+                    );
                 }
-                Err(e) => {
-                    tracing::error!("Error receiving message: {}", e);
-                    break;
+                ClientEvent::ConsistencyReport(report) => {
+                    for violation in &report.violations {
+                        let note = if violation.repaired { " (repaired)" } else { "" };
+                        theme.print(
+                            Class::Warning,
+                            &format!("{}: {}{}", violation.invariant, violation.detail, note),
+                        );
+                    }
+                    let repaired = report.violations.iter().filter(|violation| violation.repaired).count();
+                    theme.print(
+                        Class::System,
+                        &format!(
+                            "Checked {} users and {} sessions: {} violations, {} repaired",
+                            report.users,
+                            report.sessions,
+                            report.violations.len(),
+                            repaired
+                        ),
+                    );
+                }
+                ClientEvent::ServerStats {
+                    mode,
+                    sessions,
+                    users,
+                    missed_heartbeats,
+                    stalled_writes,
+                    flood_warnings,
+                    flood_disconnects,
+                    queued_messages,
+                    expired_messages,
+                    closed_by_peer,
+                    broken_connections,
+                    presence_coalesced,
+                    presence_dropped,
+                    accept_errors,
+                    integrity_failures,
+                    webhooks_delivered,
+                    webhooks_failed,
+                    auth_queue_depth,
+                    auth_rejected,
+                    journal_dropped,
+                    journal_failing,
+                    server_name,
+                    server_id,
+                } => {
+                    let counters = [
+                        (sessions, "sessions"),
+                        (users, "logged in"),
+                        (missed_heartbeats, "missed heartbeats"),
+                        (stalled_writes, "stalled writes"),
+                        (flood_warnings, "flood warnings"),
+                        (flood_disconnects, "flood disconnects"),
+                        (queued_messages, "queued messages"),
+                        (expired_messages, "expired messages"),
+                        (closed_by_peer, "closed by peer"),
+                        (broken_connections, "broken connections"),
+                        (presence_coalesced, "coalesced presence changes"),
+                        (presence_dropped, "dropped presence changes"),
+                        (accept_errors, "accept errors"),
+                        (integrity_failures, "integrity failures"),
+                        (webhooks_delivered, "webhooks delivered"),
+                        (webhooks_failed, "webhooks failed"),
+                        (auth_queue_depth, "logins queued"),
+                        (auth_rejected, "logins turned away"),
+                        (journal_dropped, "journal entries dropped"),
+                    ]
+                    .map(|(count, what)| format!("{} {}", count, what));
+                    // older servers do not say who they are
+                    let server = match server_name.as_str() {
+                        "" => "Server".to_string(),
+                        name => format!("Server {} ({})", name, server_id),
+                    };
+                    theme.print(
+                        Class::System,
+                        &format!("{} is {} with {}", server, mode, counters.join(", ")),
+                    );
+                    if journal_failing {
+                        theme.print(Class::System, "The server cannot write its journal right now");
+                    }
+                }
+                ClientEvent::StateExported { path, users } => {
+                    theme.print(Class::System, &format!("Server exported {} users to {}", users, path))
+                }
+                ClientEvent::TranscriptExported { peer, path, messages } => theme.print(
+                    Class::System,
+                    &format!(
+                        "Exported {} messages with {} to {}",
+                        messages,
+                        theme.name(&peer),
+                        path.display()
+                    ),
+                ),
+                ClientEvent::TranscriptFailed { peer, error } => theme.print(
+                    Class::Error,
+                    &format!(
+                        "Could not export the conversation with {}: {}",
+                        theme.name(&peer),
+                        error
+                    ),
+                ),
+                ClientEvent::PasswordChanged(username) => {
+                    theme.print(Class::System, &format!("Password of {} changed", username))
+                }
+                ClientEvent::UserRenamed { old, new } => {
+                    completer.lock().unwrap().add_username(&new);
+                    theme.print(Class::System, &format!("{} is now known as {}", old, new))
+                }
+                ClientEvent::PresenceUpdate { online, offline } => {
+                    let mut completer = completer.lock().unwrap();
+                    for user in &online {
+                        completer.add_username(user);
+                    }
+                    drop(completer);
+                    if !online.is_empty() {
+                        theme.print(Class::System, &format!("Online: {}", online.join(", ")));
+                    }
+                    if !offline.is_empty() {
+                        theme.print(Class::System, &format!("Offline: {}", offline.join(", ")));
+                    }
+                }
+                ClientEvent::PresenceSnapshot(snapshot) => {
+                    let mut completer = completer.lock().unwrap();
+                    for entry in &snapshot.entries {
+                        completer.add_username(&entry.user);
+                    }
+                }
+                ClientEvent::PresenceDelta(delta) => {
+                    if delta.change == PresenceChange::Joined {
+                        completer.lock().unwrap().add_username(&delta.entry.user);
+                    }
+                }
+                ClientEvent::ServerShutdownWarning(timeout) => {
+                    theme.print(Class::Warning, &format!("Server shutting down in {} seconds", timeout))
                 }
+                ClientEvent::LogLevelChanged { previous, current } => theme.print(
+                    Class::System,
+                    &format!("Server log filter changed from '{}' to '{}'", previous, current),
+                ),
+                ClientEvent::TimeSynced {
+                    offset_ms,
+                    round_trip_ms,
+                } => tracing::debug!("Server clock offset {}ms, round trip {}ms", offset_ms, round_trip_ms),
+                ClientEvent::Rejected { command, reason } => match command {
+                    Some(command) => theme.print(
+                        Class::Warning,
+                        &format!("The server rejected '{}': {}", command, reason),
+                    ),
+                    None => theme.print(Class::Warning, &reason),
+                },
+                ClientEvent::Closed => break,
+            }
+        }
+    }
+}
+
+// VAULT=passphrase asks for it at startup, the first start sets it
+fn open_vault() -> Result<Option<Arc<Vault>>, Box<dyn Error>> {
+    let source = match std::env::var("VAULT").unwrap_or_default().trim() {
+        "" | "off" => return Ok(None),
+        "passphrase" => None,
+        #[cfg(feature = "keyring")]
+        "keyring" => Some(KeySource::Keyring),
+        #[cfg(not(feature = "keyring"))]
+        "keyring" => return Err("VAULT=keyring needs a client built with the keyring feature".into()),
+        other => return Err(format!("VAULT expects 'passphrase' or 'keyring', got '{}'", other).into()),
+    };
+    let path = home_file("VAULT_FILE", VAULT_FILE_NAME).ok_or("VAULT needs VAULT_FILE or HOME")?;
+
+    let vault = match source {
+        Some(source) => Vault::open_or_create(&path, &source)?,
+        None if path.exists() => {
+            let passphrase = input::read_hidden("Passphrase: ").ok_or("No passphrase given")?;
+            Vault::unlock(&path, &KeySource::Passphrase(passphrase))?
+        }
+        None => {
+            let passphrase = input::read_hidden("New passphrase for the local files: ").ok_or("No passphrase given")?;
+            if passphrase.is_empty() {
+                return Err("The passphrase cannot be empty".into());
+            }
+            if input::read_hidden("Repeat the passphrase: ").as_deref() != Some(passphrase.as_str()) {
+                return Err("The passphrases do not match".into());
             }
+            Vault::create(&path, &KeySource::Passphrase(passphrase))?
         }
+    };
+    Ok(Some(Arc::new(vault)))
+}
+
+// `client encrypt-files`, seals the history and drafts written before VAULT was set
+pub fn encrypt_files() -> Result<(), Box<dyn Error>> {
+    let vault = open_vault()?.ok_or("encrypt-files needs VAULT set to 'passphrase' or 'keyring'")?;
+    let files: Vec<PathBuf> = [input::history_file(), home_file("DRAFT_FILE", DRAFT_FILE_NAME)]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let encrypted = vault::encrypt_files(&vault, &files)?;
+    for path in &encrypted {
+        println!("Encrypted {}", path.display());
+    }
+    if encrypted.is_empty() {
+        println!("Nothing left to encrypt");
+    }
+    Ok(())
+}
+
+// the path from the environment, otherwise the file in the home directory
+fn home_file(variable: &str, name: &str) -> Option<PathBuf> {
+    std::env::var(variable)
+        .ok()
+        .map(|path| PathBuf::from(path.trim()))
+        .or_else(|| std::env::var("HOME").ok().map(|home| PathBuf::from(home).join(name)))
+}
+
+// `de_AT.UTF-8` asks for `de-AT`, the C and POSIX locales ask for nothing
+fn language_of(locale: &str) -> Option<String> {
+    let language = locale.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+    match language.as_str() {
+        "" | "C" | "POSIX" => None,
+        _ => Some(language),
     }
 }
 
-impl Default for Application {
-    fn default() -> Self {
-        Self {}
+// unsigned is how most messages arrive, only signed ones are marked
+fn signature_note(verification: Verification) -> &'static str {
+    match verification {
+        Verification::Unsigned => "",
+        Verification::Verified => " (verified)",
+        Verification::Unverified => " (unverified)",
+        Verification::Invalid => " (invalid signature)",
     }
 }
@@ -0,0 +1,29 @@
+// mirrors the server's access levels, declared from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessLevel {
+    Guest,
+    User,
+    Moderator,
+    Admin,
+}
+
+impl AccessLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "guest" => Ok(AccessLevel::Guest),
+            "user" => Ok(AccessLevel::User),
+            "moderator" => Ok(AccessLevel::Moderator),
+            "admin" => Ok(AccessLevel::Admin),
+            _ => Err(format!("Unknown access level '{}'", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessLevel::Guest => "guest",
+            AccessLevel::User => "user",
+            AccessLevel::Moderator => "moderator",
+            AccessLevel::Admin => "admin",
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use chat_core::{capability, protocol::MessageType};
+
+use super::{access::AccessLevel, json::JsonValue};
+
+pub const DEFAULT_HISTORY_LIMIT: u64 = 20;
+pub const DEFAULT_SEARCH_LIMIT: u64 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientCommand {
+    Login {
+        username: String,
+        password: String,
+    },
+    Register {
+        username: String,
+        password: String,
+    },
+    Send {
+        to: String,
+        body: String,
+        // seconds, makes the message ephemeral
+        ttl: Option<u64>,
+    },
+    Outbox,
+    Cancel {
+        index: usize,
+    },
+    Edit {
+        id: u64,
+        body: String,
+    },
+    Delete {
+        id: u64,
+    },
+    History {
+        with: String,
+        limit: u64,
+        before: Option<u64>,
+    },
+    Read {
+        with: String,
+    },
+    Preference {
+        key: String,
+        value: String,
+    },
+    Search {
+        query: String,
+        with: Option<String>,
+        limit: u64,
+    },
+    // answered with the connection state
+    State,
+    Disconnect,
+}
+
+impl ClientCommand {
+    pub fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let field = |key: &str| -> Result<String, String> {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(|field| field.to_string())
+                .ok_or_else(|| format!("Missing string field '{}'", key))
+        };
+
+        let number = |key: &str| -> Result<u64, String> {
+            value
+                .get(key)
+                .and_then(JsonValue::as_u64)
+                .ok_or_else(|| format!("Missing numeric field '{}'", key))
+        };
+
+        let command = value
+            .get("cmd")
+            .and_then(JsonValue::as_str)
+            .ok_or("Missing string field 'cmd'")?;
+
+        match command {
+            "login" => Ok(ClientCommand::Login {
+                username: field("username")?,
+                password: field("password")?,
+            }),
+            "register" => Ok(ClientCommand::Register {
+                username: field("username")?,
+                password: field("password")?,
+            }),
+            "send" => Ok(ClientCommand::Send {
+                to: field("to")?,
+                body: field("body")?,
+                ttl: value.get("ttl").and_then(JsonValue::as_u64),
+            }),
+            "outbox" => Ok(ClientCommand::Outbox),
+            "cancel" => Ok(ClientCommand::Cancel {
+                index: number("index")? as usize,
+            }),
+            "edit" => Ok(ClientCommand::Edit {
+                id: number("id")?,
+                body: field("body")?,
+            }),
+            "delete" => Ok(ClientCommand::Delete { id: number("id")? }),
+            "history" => Ok(ClientCommand::History {
+                with: field("with")?,
+                limit: value
+                    .get("limit")
+                    .and_then(JsonValue::as_u64)
+                    .unwrap_or(DEFAULT_HISTORY_LIMIT),
+                before: value.get("before").and_then(JsonValue::as_u64),
+            }),
+            "read" => Ok(ClientCommand::Read { with: field("with")? }),
+            "pref" => Ok(ClientCommand::Preference {
+                key: field("key")?,
+                value: field("value")?,
+            }),
+            "search" => Ok(ClientCommand::Search {
+                query: field("query")?,
+                with: field("with").ok(),
+                limit: value
+                    .get("limit")
+                    .and_then(JsonValue::as_u64)
+                    .unwrap_or(DEFAULT_SEARCH_LIMIT),
+            }),
+            "state" => Ok(ClientCommand::State),
+            "disconnect" => Ok(ClientCommand::Disconnect),
+            _ => Err(format!("Unknown command '{}'", command)),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        Self::from_json(&JsonValue::parse(line)?)
+    }
+}
+
+// what the first argument of a typed command names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandArgument {
+    Text,
+    Username,
+    Conversation,
+}
+
+// every command a typed line can start with, the prompt turns away anything else and completes from here
+pub const COMMANDS: &[(&str, CommandArgument)] = &[
+    ("accept", CommandArgument::Text),
+    ("alias", CommandArgument::Text),
+    ("auth", CommandArgument::Text),
+    ("bans", CommandArgument::Text),
+    ("close", CommandArgument::Conversation),
+    ("dc", CommandArgument::Text),
+    ("delete", CommandArgument::Text),
+    ("demote", CommandArgument::Username),
+    ("draft", CommandArgument::Text),
+    ("drain", CommandArgument::Text),
+    ("edit", CommandArgument::Text),
+    ("ephemeral", CommandArgument::Username),
+    ("export", CommandArgument::Username),
+    ("fingerprint", CommandArgument::Username),
+    ("fsck", CommandArgument::Text),
+    ("grant", CommandArgument::Username),
+    ("history", CommandArgument::Username),
+    ("kick", CommandArgument::Username),
+    ("kickwhere", CommandArgument::Text),
+    ("log", CommandArgument::Text),
+    ("loglevel", CommandArgument::Text),
+    ("motd", CommandArgument::Text),
+    ("msg", CommandArgument::Username),
+    ("mute", CommandArgument::Username),
+    ("mutes", CommandArgument::Text),
+    ("new", CommandArgument::Text),
+    ("note", CommandArgument::Text),
+    ("outbox", CommandArgument::Text),
+    ("passwd", CommandArgument::Text),
+    ("pref", CommandArgument::Text),
+    ("prefs", CommandArgument::Text),
+    ("promote", CommandArgument::Username),
+    ("read", CommandArgument::Username),
+    ("reject", CommandArgument::Text),
+    ("rename", CommandArgument::Text),
+    ("renameuser", CommandArgument::Username),
+    ("resetpw", CommandArgument::Username),
+    ("schedule", CommandArgument::Text),
+    ("schedules", CommandArgument::Text),
+    ("search", CommandArgument::Text),
+    ("sendfile", CommandArgument::Username),
+    ("sessions", CommandArgument::Text),
+    ("shutdown", CommandArgument::Text),
+    ("stats", CommandArgument::Text),
+    ("switch", CommandArgument::Conversation),
+    ("token", CommandArgument::Text),
+    ("tokens", CommandArgument::Text),
+    ("trust", CommandArgument::Username),
+    ("unalias", CommandArgument::Text),
+    ("unban", CommandArgument::Text),
+    ("undrain", CommandArgument::Text),
+    ("ungrant", CommandArgument::Username),
+    ("unmute", CommandArgument::Username),
+    ("unschedule", CommandArgument::Text),
+    ("untoken", CommandArgument::Text),
+    ("userinfo", CommandArgument::Username),
+    ("who", CommandArgument::Text),
+];
+
+// None for a word that is not a command
+pub fn command_argument(command: &str) -> Option<CommandArgument> {
+    COMMANDS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, argument)| *argument)
+}
+
+// commands that only work when the server advertised the matching capability
+pub fn required_capability(command: &str) -> Option<&'static str> {
+    match command {
+        "msg" | "send" | "note" => Some(capability::DIRECT_MESSAGES),
+        "loglevel" => Some(capability::ADMIN_LOG_LEVEL),
+        "drain" | "undrain" | "stats" | "userinfo" | "sessions" | "motd" | "fsck" => {
+            Some(capability::ADMIN_SERVER_MODE)
+        }
+        "kick" | "kickwhere" | "promote" | "demote" | "bans" | "unban" => Some(capability::MODERATION),
+        "edit" | "delete" => Some(capability::MESSAGE_EDIT),
+        "history" => Some(capability::HISTORY),
+        "search" => Some(capability::SEARCH),
+        "read" => Some(capability::UNREAD),
+        "ephemeral" => Some(capability::EPHEMERAL_MESSAGES),
+        "sendfile" | "accept" | "reject" => Some(capability::FILE_TRANSFER),
+        "join" | "leave" | "rooms" => Some(capability::ROOMS),
+        "mute" | "unmute" | "mutes" => Some(capability::MUTING),
+        "grant" | "ungrant" => Some(capability::TEMPORARY_GRANTS),
+        "schedule" | "schedules" | "unschedule" => Some(capability::SCHEDULED_MESSAGES),
+        "token" | "tokens" | "untoken" => Some(capability::API_TOKENS),
+        "who" => Some(capability::PRESENCE_SUBSCRIPTIONS),
+        "trust" => Some(capability::MESSAGE_SIGNING),
+        _ => None,
+    }
+}
+
+// the lowest level the server's default presets allow the command for, None works before logging in
+pub fn required_level(command: &str) -> Option<AccessLevel> {
+    match command {
+        "msg" | "send" | "note" | "ephemeral" | "passwd" | "rename" | "edit" | "delete" | "history" | "search"
+        | "read" | "pref" | "sendfile" | "accept" | "reject" | "mute" | "unmute" | "mutes" | "schedule"
+        | "schedules" | "unschedule" | "token" | "tokens" | "untoken" | "who" | "trust" => Some(AccessLevel::User),
+        "kick" | "kickwhere" | "stats" | "userinfo" | "sessions" => Some(AccessLevel::Moderator),
+        "log" | "shutdown" | "loglevel" | "drain" | "undrain" | "resetpw" | "export" | "renameuser" | "promote"
+        | "demote" | "motd" | "bans" | "unban" | "fsck" | "grant" | "ungrant" => Some(AccessLevel::Admin),
+        _ => None,
+    }
+}
+
+// the command that sends a message type, for explaining NACKs
+pub fn command_for(message_type: MessageType) -> Option<&'static str> {
+    match message_type {
+        MessageType::Auth | MessageType::AuthChallenge => Some("auth"),
+        MessageType::AuthCreate => Some("new"),
+        MessageType::PasswordChange => Some("passwd"),
+        MessageType::RenameAccount => Some("rename"),
+        MessageType::ServerDebugLog => Some("log"),
+        MessageType::ServerShutdown => Some("shutdown"),
+        MessageType::AdminSetLogLevel => Some("loglevel"),
+        MessageType::AdminResetPassword => Some("resetpw"),
+        MessageType::AdminSetServerMode => Some("drain"),
+        MessageType::AdminServerStats => Some("stats"),
+        MessageType::AdminUserInfo => Some("userinfo"),
+        MessageType::AdminKickUser => Some("kick"),
+        MessageType::AdminListSessions => Some("sessions"),
+        MessageType::AdminKickWhere => Some("kickwhere"),
+        MessageType::AdminListIpBans => Some("bans"),
+        MessageType::AdminClearIpBan => Some("unban"),
+        MessageType::AdminSetAccessLevel => Some("promote"),
+        MessageType::AdminExportState => Some("export"),
+        MessageType::AdminRenameUser => Some("renameuser"),
+        MessageType::AdminSetMotd => Some("motd"),
+        MessageType::AdminConsistencyCheck => Some("fsck"),
+        MessageType::AdminGrantTemporary => Some("grant"),
+        MessageType::AdminRevokeTemporary => Some("ungrant"),
+        MessageType::DirectMessageSend => Some("msg"),
+        MessageType::MessageEdit => Some("edit"),
+        MessageType::MessageDelete => Some("delete"),
+        MessageType::HistoryRequest => Some("history"),
+        MessageType::MarkConversationRead => Some("read"),
+        MessageType::SearchRequest => Some("search"),
+        MessageType::FileOffer => Some("sendfile"),
+        MessageType::FileAccept => Some("accept"),
+        MessageType::FileReject => Some("reject"),
+        MessageType::SetPreference | MessageType::GetPreferences => Some("pref"),
+        MessageType::MuteAdd => Some("mute"),
+        MessageType::MuteRemove => Some("unmute"),
+        MessageType::MuteList => Some("mutes"),
+        MessageType::ScheduleMessageSend => Some("schedule"),
+        MessageType::ScheduleList => Some("schedules"),
+        MessageType::ScheduleCancel => Some("unschedule"),
+        MessageType::ApiTokenCreate => Some("token"),
+        MessageType::ApiTokenList => Some("tokens"),
+        MessageType::ApiTokenRevoke => Some("untoken"),
+        MessageType::SubscribePresence | MessageType::UnsubscribePresence => Some("who"),
+        _ => None,
+    }
+}
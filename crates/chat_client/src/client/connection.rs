@@ -0,0 +1,144 @@
+use std::fmt;
+
+use super::command::required_level;
+
+// where the client stands with the server, only `next` moves it from one state to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    // the stream is open but nobody is logged in
+    Connected,
+    Authenticating,
+    Ready,
+    // the server asked for the login again, the connection stays open meanwhile
+    Reauthenticating,
+    // a Disconnect went out or came in, the connection is about to end
+    Draining,
+}
+
+// what happened on the network or what the user asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionInput {
+    Connect,
+    Opened,
+    LoginSent,
+    Authenticated,
+    AuthFailed,
+    ReauthRequired,
+    Drain,
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: ConnectionState,
+    pub input: ConnectionInput,
+}
+
+// why a command cannot be used right now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unsupported { command: String, capability: &'static str },
+    InvalidState { command: String, state: ConnectionState },
+    // judged by the access level the login reported
+    Forbidden { command: String, problem: String },
+}
+
+impl ConnectionState {
+    pub const ALL: [ConnectionState; 7] = [
+        ConnectionState::Disconnected,
+        ConnectionState::Connecting,
+        ConnectionState::Connected,
+        ConnectionState::Authenticating,
+        ConnectionState::Ready,
+        ConnectionState::Reauthenticating,
+        ConnectionState::Draining,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Disconnected => "disconnected",
+            Self::Connecting => "connecting",
+            Self::Connected => "connected",
+            Self::Authenticating => "authenticating",
+            Self::Ready => "ready",
+            Self::Reauthenticating => "reauthenticating",
+            Self::Draining => "draining",
+        }
+    }
+
+    // a connection that is open and not on its way out
+    pub fn is_open(self) -> bool {
+        matches!(
+            self,
+            Self::Connected | Self::Authenticating | Self::Ready | Self::Reauthenticating
+        )
+    }
+
+    pub fn next(self, input: ConnectionInput) -> Result<Self, InvalidTransition> {
+        use ConnectionInput as Input;
+
+        let next = match (self, input) {
+            (Self::Disconnected, Input::Connect) => Self::Connecting,
+            (Self::Connecting, Input::Opened) => Self::Connected,
+            // logging in again on a ready connection switches accounts
+            (Self::Connected | Self::Authenticating | Self::Ready, Input::LoginSent) => Self::Authenticating,
+            (Self::Reauthenticating, Input::LoginSent) => Self::Reauthenticating,
+            (Self::Authenticating | Self::Reauthenticating, Input::Authenticated) => Self::Ready,
+            (Self::Authenticating | Self::Reauthenticating, Input::AuthFailed) => Self::Connected,
+            (Self::Ready, Input::ReauthRequired) => Self::Reauthenticating,
+            (state, Input::Drain) if state.is_open() || state == Self::Draining => Self::Draining,
+            (state, Input::Lost) if state != Self::Disconnected => Self::Disconnected,
+            (from, input) => return Err(InvalidTransition { from, input }),
+        };
+        Ok(next)
+    }
+
+    // whether the command can go out now or wait in the client until the state allows it
+    pub fn accepts(self, command: &str, logging_in: bool) -> bool {
+        match command {
+            // remembered and sent on the next connection
+            "auth" => self != Self::Draining,
+            "new" => self.is_open(),
+            // these wait in the outbox for the login
+            "msg" | "send" | "note" | "ephemeral" => self == Self::Ready || (logging_in && self != Self::Draining),
+            _ if required_level(command).is_some() => self == Self::Ready,
+            // local commands work in every state
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No transition from {} on {:?}", self.from, self.input)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported { command, capability } => write!(
+                f,
+                "The server does not support '{}', '{}' is not available",
+                capability, command
+            ),
+            Self::InvalidState { command, state } => {
+                let problem = match state {
+                    ConnectionState::Disconnected | ConnectionState::Connecting => "not connected to the server",
+                    ConnectionState::Draining => "the connection is closing",
+                    _ => "you must log in first",
+                };
+                write!(f, "Cannot use '{}': {}", command, problem)
+            }
+            Self::Forbidden { command, problem } => write!(f, "Cannot use '{}': {}", command, problem),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+
+// the conversation of notes to yourself, a peer of that name is still reached with `msg`
+pub const NOTES: &str = "notes";
+// per conversation, the oldest lines go first
+pub const SCROLLBACK_LINES: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrollbackLine {
+    pub sender: String,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+    // older servers do not assign message ids, our own lines have none until the server confirms them
+    pub id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Conversation {
+    // arrived while another conversation was active
+    unread: u64,
+    scrollback: VecDeque<ScrollbackLine>,
+}
+
+// what the client has open locally, the server knows nothing of it
+#[derive(Debug, Clone, Default)]
+pub struct Conversations {
+    entries: BTreeMap<String, Conversation>,
+    active: Option<String>,
+}
+
+impl Conversations {
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    // opens it if needed, everything in it counts as read
+    pub fn switch(&mut self, name: &str) {
+        self.entries.entry(name.to_string()).or_default().unread = 0;
+        self.active = Some(name.to_string());
+    }
+
+    // the next or previous one by name, wrapping around, None if there is none to switch to
+    pub fn cycle(&mut self, forward: bool) -> Option<String> {
+        let names: Vec<&String> = self.entries.keys().collect();
+        let position = self
+            .active
+            .as_ref()
+            .and_then(|active| names.iter().position(|name| *name == active));
+        let next = match (position, forward) {
+            (None, true) => 0,
+            (None, false) => names.len().checked_sub(1)?,
+            (Some(position), true) => (position + 1) % names.len(),
+            (Some(position), false) => (position + names.len() - 1) % names.len(),
+        };
+        let name = names.get(next)?.to_string();
+        self.switch(&name);
+        Some(name)
+    }
+
+    // its scrollback goes with it, false if it was not open
+    pub fn close(&mut self, name: &str) -> bool {
+        if self.active.as_deref() == Some(name) {
+            self.active = None;
+        }
+        self.entries.remove(name).is_some()
+    }
+
+    // opens the conversation, only lines that count are unread while it is not active
+    pub fn record(&mut self, name: &str, line: ScrollbackLine, counts: bool) {
+        let active = self.active.as_deref() == Some(name);
+        let conversation = self.entries.entry(name.to_string()).or_default();
+        if counts && !active {
+            conversation.unread += 1;
+        }
+        conversation.scrollback.push_back(line);
+        if conversation.scrollback.len() > SCROLLBACK_LINES {
+            conversation.scrollback.pop_front();
+        }
+    }
+
+    // an account rename moves its conversation along, merged into one already open under the new name
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let Some(conversation) = self.entries.remove(old) else {
+            return;
+        };
+        let merged = self.entries.entry(new.to_string()).or_default();
+        merged.unread += conversation.unread;
+        merged.scrollback.extend(conversation.scrollback);
+        while merged.scrollback.len() > SCROLLBACK_LINES {
+            merged.scrollback.pop_front();
+        }
+        if self.active.as_deref() == Some(old) {
+            self.active = Some(new.to_string());
+        }
+    }
+
+    // sorted by name, with their unread counts
+    pub fn list(&self) -> Vec<(String, u64)> {
+        self.entries
+            .iter()
+            .map(|(name, conversation)| (name.clone(), conversation.unread))
+            .collect()
+    }
+
+    // oldest first, None if it is not open
+    pub fn scrollback(&self, name: &str) -> Option<Vec<ScrollbackLine>> {
+        self.entries
+            .get(name)
+            .map(|conversation| conversation.scrollback.iter().cloned().collect())
+    }
+}
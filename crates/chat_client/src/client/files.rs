@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt};
+
+#[derive(Debug)]
+struct OutgoingFile {
+    // None until the server confirmed the offer
+    id: Option<u64>,
+    recipient: String,
+    filename: String,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct IncomingFile {
+    sender: String,
+    filename: String,
+    size: u64,
+    sha256: String,
+    data: Vec<u8>,
+    next_chunk: u64,
+    accepted: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FileTransfers {
+    outgoing: Vec<OutgoingFile>,
+    incoming: HashMap<u64, IncomingFile>,
+}
+
+impl IncomingFile {
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    // the data is complete and matches what the sender offered
+    pub fn verify(&self) -> Result<(), String> {
+        if self.data.len() as u64 != self.size {
+            return Err(format!("Received {} of {} bytes", self.data.len(), self.size));
+        }
+        if sha256_hex(&self.data) != self.sha256 {
+            return Err("Checksum mismatch".into());
+        }
+        Ok(())
+    }
+
+    // never overwrites, a taken name gets a counter appended before the extension
+    pub async fn save(&self, directory: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(directory).await?;
+
+        let path = Path::new(&self.filename);
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("download");
+        let extension = path.extension().and_then(|extension| extension.to_str());
+
+        for attempt in 0.. {
+            let name = match (attempt, extension) {
+                (0, _) => self.filename.clone(),
+                (_, Some(extension)) => format!("{} ({}).{}", stem, attempt, extension),
+                (_, None) => format!("{} ({})", stem, attempt),
+            };
+            let candidate = directory.join(name);
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+                .await
+            {
+                Ok(mut file) => {
+                    file.write_all(&self.data).await?;
+                    // tokio hands the write to a background task, it may not have landed yet
+                    file.flush().await?;
+                    return Ok(candidate);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+}
+
+impl FileTransfers {
+    pub fn push_outgoing(&mut self, recipient: &str, filename: &str, data: Vec<u8>) {
+        self.outgoing.push(OutgoingFile {
+            id: None,
+            recipient: recipient.to_string(),
+            filename: filename.to_string(),
+            data,
+        });
+    }
+
+    // offers are confirmed in the order they were sent
+    pub fn confirm_outgoing(&mut self, id: u64, recipient: &str, filename: &str) -> bool {
+        match self
+            .outgoing
+            .iter_mut()
+            .find(|file| file.id.is_none() && file.recipient == recipient && file.filename == filename)
+        {
+            Some(file) => {
+                file.id = Some(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn take_outgoing(&mut self, id: u64) -> Option<Vec<u8>> {
+        let index = self.outgoing.iter().position(|file| file.id == Some(id))?;
+        Some(self.outgoing.remove(index).data)
+    }
+
+    pub fn push_incoming(&mut self, id: u64, sender: &str, filename: &str, size: u64, sha256: &str) {
+        self.incoming.insert(
+            id,
+            IncomingFile {
+                sender: sender.to_string(),
+                filename: filename.to_string(),
+                size,
+                sha256: sha256.to_string(),
+                data: Vec::new(),
+                next_chunk: 0,
+                accepted: false,
+            },
+        );
+    }
+
+    pub fn accept_incoming(&mut self, id: u64) -> bool {
+        match self.incoming.get_mut(&id) {
+            Some(file) if !file.accepted => {
+                file.accepted = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn add_chunk(&mut self, id: u64, index: u64, bytes: &[u8]) -> Result<(), String> {
+        let file = self
+            .incoming
+            .get_mut(&id)
+            .filter(|file| file.accepted)
+            .ok_or_else(|| format!("No accepted transfer {}", id))?;
+
+        if index != file.next_chunk {
+            return Err(format!("Expected chunk {}, got {}", file.next_chunk, index));
+        }
+        if file.data.len() as u64 + bytes.len() as u64 > file.size {
+            return Err("Chunks exceed the offered size".into());
+        }
+        file.data.extend_from_slice(bytes);
+        file.next_chunk += 1;
+        Ok(())
+    }
+
+    pub fn take_incoming(&mut self, id: u64) -> Option<IncomingFile> {
+        self.incoming.remove(&id)
+    }
+
+    // true if the transfer was still known on this side
+    pub fn remove(&mut self, id: u64) -> bool {
+        let outgoing = self.take_outgoing(id).is_some();
+        self.incoming.remove(&id).is_some() || outgoing
+    }
+
+    // the server cancels every transfer of a dropped connection, so nothing here survives it
+    pub fn clear(&mut self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.outgoing.iter().filter_map(|file| file.id).collect();
+        ids.extend(self.incoming.keys());
+        self.outgoing.clear();
+        self.incoming.clear();
+        ids
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// offers name the file, not where it goes, so anything that looks like a path is cut down to its last part
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next()?.trim();
+    if name.is_empty() || name.starts_with('.') {
+        return None;
+    }
+    Some(name.to_string())
+}
@@ -0,0 +1,168 @@
+use std::{io, time::Duration};
+
+use chrono::Local;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+use super::{json::JsonValue, ChatClient, ClientCommand, ClientEvent, SendStatus};
+
+// the --output json mode: a command per input line, every event and every answer a JSON object on its own line.
+// a line that is not a command is answered with an error object and the next one is read
+pub async fn run_json<R, W>(
+    client: ChatClient,
+    mut events: mpsc::UnboundedReceiver<ClientEvent>,
+    input: R,
+    mut output: W,
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    // events and answers share the output, one writer keeps their lines whole
+    let (lines_tx, mut lines_rx) = mpsc::unbounded_channel::<JsonValue>();
+    let writer_h = tokio::spawn(async move {
+        while let Some(value) = lines_rx.recv().await {
+            output.write_all(format!("{}\n", value).as_bytes()).await?;
+            output.flush().await?;
+        }
+        Ok::<(), io::Error>(())
+    });
+
+    let events_tx = lines_tx.clone();
+    let events_h = tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            events_tx.send(event.to_json()).ok();
+            if matches!(event, ClientEvent::Closed) {
+                break;
+            }
+        }
+    });
+
+    let error = |error: String| response("error").with("error", error);
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let command = match ClientCommand::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                lines_tx.send(error(e)).ok();
+                continue;
+            }
+        };
+
+        match command {
+            ClientCommand::Login { username, password } => {
+                client.login(&username, &password).await;
+            }
+            ClientCommand::Register { username, password } => {
+                client.create_account(&username, &password).await;
+            }
+            ClientCommand::Send { to, body, ttl } => {
+                let checked = match ttl {
+                    Some(_) => client.check_command("ephemeral").await,
+                    None => client.check_command("send").await,
+                };
+                if let Err(e) = checked {
+                    lines_tx.send(error(e.to_string())).ok();
+                    continue;
+                }
+                let sent = match ttl {
+                    Some(ttl) => {
+                        client
+                            .send_ephemeral_message(&to, &body, Duration::from_secs(ttl))
+                            .await
+                    }
+                    None => client.send_direct_message(&to, &body).await,
+                };
+                let status = match sent {
+                    SendStatus::Sent => "sent",
+                    SendStatus::Pending => "pending",
+                    SendStatus::OutboxFull => "outbox_full",
+                };
+                lines_tx
+                    .send(response("send_status").with("to", to).with("status", status))
+                    .ok();
+            }
+            ClientCommand::Outbox => {
+                let entries: Vec<JsonValue> = client.outbox().await.iter().map(|entry| entry.to_json()).collect();
+                lines_tx.send(response("outbox").with("entries", entries)).ok();
+            }
+            ClientCommand::Cancel { index } => {
+                let recipient = client
+                    .cancel_outbox_entry(index)
+                    .await
+                    .map(|entry| entry.recipient().to_string());
+                lines_tx
+                    .send(response("cancelled").with("index", index).with("recipient", recipient))
+                    .ok();
+            }
+            ClientCommand::Edit { id, body } => {
+                let result = match client.check_command("edit").await {
+                    Ok(()) => client.edit_message(id, &body).await,
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = result {
+                    lines_tx.send(error(e)).ok();
+                }
+            }
+            ClientCommand::Delete { id } => {
+                let result = match client.check_command("delete").await {
+                    Ok(()) => client.delete_message(id).await,
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = result {
+                    lines_tx.send(error(e)).ok();
+                }
+            }
+            ClientCommand::History { with, limit, before } => {
+                if let Err(e) = client.check_command("history").await {
+                    lines_tx.send(error(e.to_string())).ok();
+                    continue;
+                }
+                client.request_history(&with, limit, before).await;
+            }
+            ClientCommand::Read { with } => {
+                if let Err(e) = client.check_command("read").await {
+                    lines_tx.send(error(e.to_string())).ok();
+                    continue;
+                }
+                client.mark_read(&with).await;
+            }
+            ClientCommand::Preference { key, value } => {
+                if let Err(e) = client.check_command("pref").await {
+                    lines_tx.send(error(e.to_string())).ok();
+                    continue;
+                }
+                client.set_preference(&key, &value).await;
+            }
+            ClientCommand::Search { query, with, limit } => {
+                if let Err(e) = client.check_command("search").await {
+                    lines_tx.send(error(e.to_string())).ok();
+                    continue;
+                }
+                client.search(&query, with.as_deref(), limit).await;
+            }
+            ClientCommand::State => {
+                let state = client.connection_state().await;
+                lines_tx.send(response("state").with("state", state.as_str())).ok();
+            }
+            ClientCommand::Disconnect => break,
+        }
+    }
+
+    client.disconnect().await;
+    events_h.await.ok();
+    drop(lines_tx);
+    writer_h.await.map_err(io::Error::other)?
+}
+
+fn response(kind: &str) -> JsonValue {
+    JsonValue::object()
+        .with("type", kind)
+        .with("timestamp", Local::now().to_rfc3339())
+}
@@ -0,0 +1,3344 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chat_core::{
+    auth::{challenge_response, derive_key, PLAIN_LOGIN_REQUIRED},
+    capability::{self, Capabilities},
+    constants::{HOST, PORT},
+    integrity::Integrity,
+    protocol::{
+        ApiTokenInfo, ConsistencyReport, DisconnectReason, ErrorCode, HeartbeatEcho, IpBanList, Message, MessageId,
+        MessageType, PresenceDelta, PresenceEntry, PresenceScope, PresenceSnapshot, ScheduleTime, ScheduledEntry,
+        SessionInfo, HISTORY_DELETED, HISTORY_EDITED, HISTORY_SELF_NOTE, MAX_FIELD_SIZE, NOTICE_KICKED,
+        NOTICE_SESSION_TAKEOVER,
+    },
+    time_sync::{Clock, SystemClock, TimeSample},
+    trace::{Direction, FrameTracer},
+    transport::{Connector, Stream, TcpConnector},
+    version::{FrameError, VersionRange},
+};
+use chrono::{DateTime, Local, Utc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, RwLock},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+mod access;
+mod command;
+mod connection;
+mod conversations;
+mod files;
+mod machine;
+mod once;
+mod outbox;
+mod quality;
+mod request;
+mod roster;
+mod shutdown;
+mod signing;
+mod sync;
+mod transcript;
+
+pub use access::AccessLevel;
+pub use chat_core::json;
+pub use command::{
+    command_argument, command_for, required_capability, required_level, ClientCommand, CommandArgument, COMMANDS,
+    DEFAULT_HISTORY_LIMIT, DEFAULT_SEARCH_LIMIT,
+};
+pub use connection::{CommandError, ConnectionInput, ConnectionState, InvalidTransition};
+pub use conversations::{Conversations, ScrollbackLine, NOTES, SCROLLBACK_LINES};
+use files::FileTransfers;
+use json::JsonValue;
+pub use machine::run_json;
+pub use once::{run_once, OnceError, ONCE_TIMEOUT};
+use outbox::Outbox;
+pub use outbox::{OutboxEntry, OutboxState};
+pub use quality::{QualitySample, QualityScorer, QualityWeights, MAX_QUALITY, SEND_TIMEOUT};
+pub use request::{parse_request, parse_schedule_time};
+pub use roster::Roster;
+use shutdown::ShutdownToken;
+use signing::fold_username;
+pub use signing::{fingerprint, verify_signature, KnownKeys, PublicKey, SigningIdentity, Verification};
+use sync::OfflineSync;
+use transcript::TranscriptJob;
+pub use transcript::{
+    Transcript, TranscriptEntry, TranscriptExport, TranscriptFormat, TRANSCRIPT_MAX_MESSAGES, TRANSCRIPT_PAGE,
+};
+
+const DEFAULT_OUTBOX_CAPACITY: usize = 32;
+const DEFAULT_RECONNECT_INTERVAL: u64 = 5;
+const DEFAULT_SYNC_BATCH: u64 = 50;
+const DEFAULT_TIME_SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// longer than the server's heartbeat interval, so a healthy server always speaks first
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(45);
+const DEFAULT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const RECENT_SENT_IDS: usize = 100;
+// signed messages per sender kept until the server tells us their key, the rest are shown as they are
+const MAX_AWAITING_SIGNATURES: usize = 100;
+const FILE_CHUNK_SIZE: usize = 16 * 1024;
+const DEFAULT_DOWNLOAD_DIR: &str = "downloads";
+// how far past a bad frame the next header is looked for
+const MAX_RESYNC_BYTES: usize = 4 * MAX_FIELD_SIZE as usize;
+// bad frames in a row before the stream is given up for lost
+const MAX_PROTOCOL_FAULTS: u32 = 3;
+// how often per keepalive interval the silence counts toward the connection quality
+const QUALITY_SAMPLES: u32 = 4;
+type ArcRwLock<T> = Arc<RwLock<T>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Connected,
+    Disconnected,
+    Reconnecting(u64),
+    StateChanged {
+        from: ConnectionState,
+        to: ConnectionState,
+    },
+    Authenticated,
+    AuthFailed(String),
+    DirectMessage {
+        sender: String,
+        body: String,
+        sent_at: DateTime<Utc>,
+        // older servers do not assign message ids
+        id: Option<u64>,
+        // ephemeral messages are gone from the server after this
+        expires_at: Option<DateTime<Utc>>,
+        // written by this user on another of their sessions
+        self_note: bool,
+        // from a sender this user muted, it is shown but not notified
+        muted: bool,
+        verification: Verification,
+    },
+    // a signed message checked again once the server told us the sender's key
+    MessageVerified {
+        id: Option<u64>,
+        sender: String,
+        verification: Verification,
+    },
+    // the server has a key for the user that is not the one trusted, `trust` replaces the trusted one with it
+    SigningKeyChanged {
+        username: String,
+        trusted: String,
+        announced: String,
+    },
+    Delivered {
+        recipient: String,
+        id: Option<u64>,
+        // the id the client gave the message, tells it apart from others to the same recipient
+        client_id: Uuid,
+    },
+    MessageEdited {
+        id: u64,
+        sender: String,
+        body: String,
+        edited_at: DateTime<Utc>,
+    },
+    MessageDeleted {
+        id: u64,
+        sender: String,
+    },
+    MessageExpired {
+        id: u64,
+        sender: String,
+    },
+    HistoryEntry {
+        id: u64,
+        sender: String,
+        recipient: String,
+        body: String,
+        sent_at: DateTime<Utc>,
+        edited: bool,
+        deleted: bool,
+        self_note: bool,
+        // checked like a live message, edits and deletions leave an entry unsigned
+        verification: Verification,
+    },
+    HistoryEnd(u64),
+    // newest first, `highlight` is the byte range of the match within the snippet
+    SearchResult {
+        id: u64,
+        peer: String,
+        sent_at: DateTime<Utc>,
+        snippet: String,
+        highlight: (usize, usize),
+    },
+    SearchEnd {
+        count: u64,
+        // set when the server refused the search
+        error: Option<String>,
+    },
+    // peers with unread messages and how many, sorted by peer
+    UnreadSummary(Vec<(String, u64)>),
+    // messages were queued while we were away and are pulled in batches, direct messages counted by sender
+    SyncStarted {
+        total: u64,
+        peers: Vec<(String, u64)>,
+    },
+    // after each batch, delivered counts since the sync started, across reconnects
+    SyncProgress {
+        delivered: u64,
+        remaining: u64,
+    },
+    SyncFinished {
+        delivered: u64,
+    },
+    // every setting as the server stores it, sent after login and after each change
+    Preferences(Vec<(String, String)>),
+    // another session of this user changed them, this is the resulting set
+    PreferencesChanged(Vec<(String, String)>),
+    // everyone this user muted, sorted, after login and after each change
+    MutedSenders(Vec<String>),
+    // this user's messages waiting on the server, soonest first, after each change
+    ScheduledMessages(Vec<ScheduledEntry>),
+    // a scheduled message came due, `error` says why it did not go out
+    ScheduledMessageSent {
+        id: u64,
+        recipient: String,
+        error: Option<String>,
+    },
+    // this user's tokens for the http bridge, oldest first, after a list or a revocation
+    ApiTokens(Vec<ApiTokenInfo>),
+    // the only time the token is seen, the server keeps a hash
+    ApiTokenCreated {
+        info: ApiTokenInfo,
+        token: String,
+    },
+    FileOffered {
+        id: u64,
+        recipient: String,
+        filename: String,
+    },
+    FileOffer {
+        id: u64,
+        sender: String,
+        filename: String,
+        size: u64,
+    },
+    FileAccepted(u64),
+    FileSent(u64),
+    FileReceived {
+        id: u64,
+        sender: String,
+        path: PathBuf,
+    },
+    // declined by the other side, cancelled by either side or the server, or failed verification
+    FileRejected {
+        id: u64,
+        reason: String,
+    },
+    DeliveryFailed {
+        recipient: Option<String>,
+        error: String,
+        // tells an unknown recipient apart from one that is merely offline
+        code: ErrorCode,
+    },
+    // a message the server kept for an offline recipient ran out before they came back
+    DeliveryExpired {
+        id: u64,
+        recipient: String,
+    },
+    ServerCapabilities(Vec<String>),
+    // the name is chosen by the operator, the id stays the same across restarts of the server
+    ServerIdentity {
+        name: String,
+        id: String,
+    },
+    Motd(String),
+    ReauthRequired(String),
+    PasswordChanged(String),
+    UserRenamed {
+        old: String,
+        new: String,
+    },
+    // who logged in and who logged out since the last update, other users only
+    PresenceUpdate {
+        online: Vec<String>,
+        offline: Vec<String>,
+    },
+    // a followed scope started over, also after every login and after a gap in the deltas
+    PresenceSnapshot(PresenceSnapshot),
+    // only deltas that were applied to the roster of their scope
+    PresenceDelta(PresenceDelta),
+    ServerShutdownWarning(u64),
+    LogLevelChanged {
+        previous: String,
+        current: String,
+    },
+    TimeSynced {
+        offset_ms: i64,
+        round_trip_ms: i64,
+    },
+    SecurityNotice {
+        kind: String,
+        detail: String,
+    },
+    // the server kept talking without echoing our heartbeats, the connection is dropped and reopened
+    ConnectionStale {
+        received_ms: u64,
+        two_way_ms: u64,
+    },
+    // from 0 to MAX_QUALITY, whenever the level changes
+    ConnectionQuality(u8),
+    // a frame from the server could not be read, unless recovered the connection is dropped and reopened
+    ProtocolFault {
+        error: String,
+        recovered: bool,
+    },
+    // the server ended the connection and said why
+    ServerDisconnected {
+        reason: DisconnectReason,
+        detail: String,
+    },
+    ServerBusy {
+        retry_after: u64,
+        reason: String,
+    },
+    ServerStats {
+        mode: String,
+        sessions: u64,
+        users: u64,
+        missed_heartbeats: u64,
+        stalled_writes: u64,
+        flood_warnings: u64,
+        flood_disconnects: u64,
+        queued_messages: u64,
+        expired_messages: u64,
+        closed_by_peer: u64,
+        broken_connections: u64,
+        presence_coalesced: u64,
+        presence_dropped: u64,
+        accept_errors: u64,
+        integrity_failures: u64,
+        webhooks_delivered: u64,
+        webhooks_failed: u64,
+        auth_queue_depth: u64,
+        auth_rejected: u64,
+        journal_dropped: u64,
+        journal_failing: bool,
+        server_name: String,
+        server_id: String,
+    },
+    // relay counters of one user since the server started
+    UserInfo {
+        username: String,
+        access_level: String,
+        sessions: u64,
+        sent: u64,
+        delivered: u64,
+        queued: u64,
+        dropped: u64,
+        last_error: Option<String>,
+        // permission and when it lapses, rfc3339
+        temporary_grants: Vec<(String, String)>,
+    },
+    // one per session of a list or a kick, closed by SessionListEnd
+    SessionInfo(SessionInfo),
+    // kicked is false for a listing and a dry run
+    SessionListEnd {
+        count: u64,
+        kicked: bool,
+    },
+    // the bans in effect, also the answer to lifting one
+    IpBans(IpBanList),
+    // what a consistency check found, and repaired if asked to
+    ConsistencyReport(ConsistencyReport),
+    // the path is on the server's filesystem
+    StateExported {
+        path: String,
+        users: u64,
+    },
+    // the path is on our filesystem
+    TranscriptExported {
+        peer: String,
+        path: PathBuf,
+        messages: u64,
+    },
+    TranscriptFailed {
+        peer: String,
+        error: String,
+    },
+    AccessLevelChanged {
+        username: String,
+        level: String,
+    },
+    UserKicked {
+        username: String,
+        reason: String,
+    },
+    // replayed after login, it happened while no session was connected
+    MissedNotice {
+        kind: String,
+        detail: String,
+        occurred_at: DateTime<Utc>,
+    },
+    // `command` is None when the server did not say which request it refused
+    Rejected {
+        command: Option<String>,
+        reason: String,
+    },
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    Sent,
+    Pending,
+    OutboxFull,
+}
+
+// how long ago the server was heard from, and how long ago it proved that it hears us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contact {
+    pub received: Duration,
+    // None until the server echoed one of our heartbeats, older servers never do
+    pub two_way: Option<Duration>,
+    // the keepalive interval, two-way contact may lag behind by this much on a healthy connection
+    pub expected: Duration,
+}
+
+impl Contact {
+    // the server is heard but did not hear us for a while
+    pub fn is_one_way(&self) -> bool {
+        self.two_way
+            .is_some_and(|two_way| two_way.saturating_sub(self.received) > self.expected)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    host: String,
+    port: u16,
+    outbox_capacity: usize,
+    reconnect_interval: u64,
+    tracer: Option<FrameTracer>,
+    clock: Arc<dyn Clock>,
+    time_sync_interval: Duration,
+    keepalive_interval: Duration,
+    disconnect_timeout: Duration,
+    download_dir: PathBuf,
+    language: Option<String>,
+    integrity: Vec<Integrity>,
+    offline_sync: u64,
+    quality_weights: QualityWeights,
+    signing: Option<SigningIdentity>,
+    known_keys: KnownKeys,
+}
+
+// a signed message whose sender's key was asked for, checked again when it arrives
+#[derive(Debug)]
+struct AwaitingSignature {
+    id: Option<u64>,
+    sender: String,
+    recipient: String,
+    body: String,
+    signature: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct ClientState {
+    tx: Option<mpsc::UnboundedSender<Message>>,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    credentials: Option<(String, String)>,
+    // derived from the credentials next to it, a rename or password change derives a new one
+    login_key: Option<((String, String), String)>,
+    // the first frame of a connection decides how to log in, a login asked for earlier waits for it
+    greeted: bool,
+    login_pending: bool,
+    // from the server hello, None for servers that still expect the plain password
+    nonce: Option<String>,
+    // replaces the stored password once the server confirms the change
+    pending_password: Option<String>,
+    // the login or registration last sent on this connection, a busy server gets it again later
+    auth_request: Option<Message>,
+    connection: ConnectionState,
+    // guest until the login succeeds, None once logged in to a server that does not report it
+    access_level: Option<AccessLevel>,
+    // stays the same when the account is renamed, older servers do not send one
+    user_id: Option<String>,
+    closing: bool,
+    // our Disconnect went out on the current connection, the next Ack answers it
+    disconnect_sent: bool,
+    disconnect_timeout: Duration,
+    outbox: Outbox,
+    tracer: Option<FrameTracer>,
+    clock: Arc<dyn Clock>,
+    time_sync_interval: Duration,
+    keepalive_interval: Duration,
+    // when the current connection last delivered a frame
+    last_received: Instant,
+    // our last heartbeat sequence, the last one the server sent and the highest of ours it echoed
+    heartbeat_sequence: u64,
+    peer_sequence: u64,
+    acknowledged: u64,
+    // when the server last echoed one of our heartbeats, None until it did on this connection
+    last_two_way: Option<Instant>,
+    // the sequence of our last probe and when it went out, until the server answers it
+    probe: Option<(u64, Instant)>,
+    quality: QualityScorer,
+    time_sample: Option<TimeSample>,
+    // None until the server hello arrives, older servers never send one
+    capabilities: Option<Capabilities>,
+    // from the server hello, older servers do not introduce themselves
+    server_name: Option<String>,
+    // a busy server asks for a longer pause before the next reconnect
+    retry_after: Option<u64>,
+    // ids of our latest delivered messages, the only ones edit and delete accept
+    sent_ids: VecDeque<u64>,
+    unread: BTreeMap<String, u64>,
+    preferences: BTreeMap<String, String>,
+    // of the set above, zero for servers that do not count
+    preferences_version: u64,
+    muted: BTreeSet<String>,
+    // the presence scopes followed, None while a snapshot is awaited, followed again after every login
+    rosters: BTreeMap<PresenceScope, Option<Roster>>,
+    // kept across reconnects, they only live as long as the client
+    conversations: Conversations,
+    files: FileTransfers,
+    download_dir: PathBuf,
+    // at most one at a time, the history entries for it are not passed on as events
+    transcript: Option<TranscriptJob>,
+    // declared in every client hello, servers without catalogs answer in their own language
+    language: Option<String>,
+    // in order of preference, the first one the server offers is selected on every connection
+    integrity_preference: Vec<Integrity>,
+    // what the current connection agreed on, crc32 until the server confirms another
+    integrity: Integrity,
+    offline_sync: OfflineSync,
+    // None sends unsigned messages, signatures of others are checked either way
+    signing: Option<SigningIdentity>,
+    known_keys: KnownKeys,
+    // the key the server last told us for a user, by folded username
+    announced_keys: BTreeMap<String, PublicKey>,
+    // by folded username, an entry means the key was asked for and no answer came yet
+    awaiting_keys: BTreeMap<String, Vec<AwaitingSignature>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    state: ArcRwLock<ClientState>,
+}
+
+impl ClientEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientEvent::Connected => "connected",
+            ClientEvent::Disconnected => "disconnected",
+            ClientEvent::Reconnecting(_) => "reconnecting",
+            ClientEvent::StateChanged { .. } => "state_changed",
+            ClientEvent::Authenticated => "authenticated",
+            ClientEvent::AuthFailed(_) => "auth_failed",
+            ClientEvent::DirectMessage { .. } => "direct_message",
+            ClientEvent::MessageVerified { .. } => "message_verified",
+            ClientEvent::SigningKeyChanged { .. } => "signing_key_changed",
+            ClientEvent::Delivered { .. } => "delivered",
+            ClientEvent::DeliveryFailed { .. } => "delivery_failed",
+            ClientEvent::DeliveryExpired { .. } => "delivery_expired",
+            ClientEvent::MessageEdited { .. } => "message_edited",
+            ClientEvent::MessageDeleted { .. } => "message_deleted",
+            ClientEvent::MessageExpired { .. } => "message_expired",
+            ClientEvent::HistoryEntry { .. } => "history_entry",
+            ClientEvent::HistoryEnd(_) => "history_end",
+            ClientEvent::SearchResult { .. } => "search_result",
+            ClientEvent::SearchEnd { .. } => "search_end",
+            ClientEvent::UnreadSummary(_) => "unread_summary",
+            ClientEvent::SyncStarted { .. } => "sync_started",
+            ClientEvent::SyncProgress { .. } => "sync_progress",
+            ClientEvent::SyncFinished { .. } => "sync_finished",
+            ClientEvent::Preferences(_) => "preferences",
+            ClientEvent::PreferencesChanged(_) => "preferences_changed",
+            ClientEvent::MutedSenders(_) => "muted_senders",
+            ClientEvent::ScheduledMessages(_) => "scheduled_messages",
+            ClientEvent::ScheduledMessageSent { .. } => "scheduled_message_sent",
+            ClientEvent::ApiTokens(_) => "api_tokens",
+            ClientEvent::ApiTokenCreated { .. } => "api_token_created",
+            ClientEvent::FileOffered { .. } => "file_offered",
+            ClientEvent::FileOffer { .. } => "file_offer",
+            ClientEvent::FileAccepted(_) => "file_accepted",
+            ClientEvent::FileSent(_) => "file_sent",
+            ClientEvent::FileReceived { .. } => "file_received",
+            ClientEvent::FileRejected { .. } => "file_rejected",
+            ClientEvent::ServerCapabilities(_) => "server_capabilities",
+            ClientEvent::ServerIdentity { .. } => "server_identity",
+            ClientEvent::Motd(_) => "motd",
+            ClientEvent::ReauthRequired(_) => "reauth_required",
+            ClientEvent::PasswordChanged(_) => "password_changed",
+            ClientEvent::UserRenamed { .. } => "user_renamed",
+            ClientEvent::PresenceUpdate { .. } => "presence_update",
+            ClientEvent::PresenceSnapshot(_) => "presence_snapshot",
+            ClientEvent::PresenceDelta(_) => "presence_delta",
+            ClientEvent::ServerShutdownWarning(_) => "server_shutdown_warning",
+            ClientEvent::LogLevelChanged { .. } => "log_level_changed",
+            ClientEvent::TimeSynced { .. } => "time_synced",
+            ClientEvent::SecurityNotice { .. } => "security_notice",
+            ClientEvent::ConnectionStale { .. } => "connection_stale",
+            ClientEvent::ConnectionQuality(_) => "connection_quality",
+            ClientEvent::ProtocolFault { .. } => "protocol_fault",
+            ClientEvent::ServerDisconnected { .. } => "server_disconnected",
+            ClientEvent::ServerBusy { .. } => "server_busy",
+            ClientEvent::ServerStats { .. } => "server_stats",
+            ClientEvent::UserInfo { .. } => "user_info",
+            ClientEvent::SessionInfo(_) => "session_info",
+            ClientEvent::SessionListEnd { .. } => "session_list_end",
+            ClientEvent::IpBans(_) => "ip_bans",
+            ClientEvent::ConsistencyReport(_) => "consistency_report",
+            ClientEvent::StateExported { .. } => "state_exported",
+            ClientEvent::TranscriptExported { .. } => "transcript_exported",
+            ClientEvent::TranscriptFailed { .. } => "transcript_failed",
+            ClientEvent::AccessLevelChanged { .. } => "access_level_changed",
+            ClientEvent::UserKicked { .. } => "user_kicked",
+            ClientEvent::MissedNotice { .. } => "missed_notice",
+            ClientEvent::Rejected { .. } => "rejected",
+            ClientEvent::Closed => "closed",
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let value = JsonValue::object()
+            .with("type", self.kind())
+            .with("timestamp", Local::now().to_rfc3339());
+
+        match self {
+            ClientEvent::Reconnecting(interval) => value.with("interval", *interval),
+            ClientEvent::StateChanged { from, to } => value.with("from", from.as_str()).with("to", to.as_str()),
+            ClientEvent::AuthFailed(error) => value.with("error", error.as_str()),
+            ClientEvent::DirectMessage {
+                sender,
+                body,
+                sent_at,
+                id,
+                expires_at,
+                self_note,
+                muted,
+                verification,
+            } => value
+                .with("sender", sender.as_str())
+                .with("body", body.as_str())
+                .with("sent_at", sent_at.to_rfc3339())
+                .with("id", *id)
+                .with("expires_at", expires_at.map(|expires_at| expires_at.to_rfc3339()))
+                .with("self_note", *self_note)
+                .with("muted", *muted)
+                .with("verification", verification.as_str()),
+            ClientEvent::MessageVerified {
+                id,
+                sender,
+                verification,
+            } => value
+                .with("id", *id)
+                .with("sender", sender.as_str())
+                .with("verification", verification.as_str()),
+            ClientEvent::SigningKeyChanged {
+                username,
+                trusted,
+                announced,
+            } => value
+                .with("username", username.as_str())
+                .with("trusted", trusted.as_str())
+                .with("announced", announced.as_str()),
+            ClientEvent::Delivered {
+                recipient,
+                id,
+                client_id,
+            } => value
+                .with("recipient", recipient.as_str())
+                .with("id", *id)
+                .with("client_id", client_id.to_string()),
+            ClientEvent::MessageEdited {
+                id,
+                sender,
+                body,
+                edited_at,
+            } => value
+                .with("id", *id)
+                .with("sender", sender.as_str())
+                .with("body", body.as_str())
+                .with("edited_at", edited_at.to_rfc3339()),
+            ClientEvent::MessageDeleted { id, sender } | ClientEvent::MessageExpired { id, sender } => {
+                value.with("id", *id).with("sender", sender.as_str())
+            }
+            ClientEvent::HistoryEntry {
+                id,
+                sender,
+                recipient,
+                body,
+                sent_at,
+                edited,
+                deleted,
+                self_note,
+                verification,
+            } => value
+                .with("id", *id)
+                .with("sender", sender.as_str())
+                .with("recipient", recipient.as_str())
+                .with("body", body.as_str())
+                .with("sent_at", sent_at.to_rfc3339())
+                .with("edited", *edited)
+                .with("deleted", *deleted)
+                .with("self_note", *self_note)
+                .with("verification", verification.as_str()),
+            ClientEvent::HistoryEnd(count) => value.with("count", *count),
+            ClientEvent::SearchResult {
+                id,
+                peer,
+                sent_at,
+                snippet,
+                highlight,
+            } => value
+                .with("id", *id)
+                .with("peer", peer.as_str())
+                .with("sent_at", sent_at.to_rfc3339())
+                .with("snippet", snippet.as_str())
+                .with("highlight", vec![highlight.0, highlight.1]),
+            ClientEvent::SearchEnd { count, error } => value.with("count", *count).with("error", error.clone()),
+            ClientEvent::UnreadSummary(counts) => value.with(
+                "unread",
+                JsonValue::Object(
+                    counts
+                        .iter()
+                        .map(|(peer, count)| (peer.clone(), JsonValue::from(*count)))
+                        .collect(),
+                ),
+            ),
+            ClientEvent::SyncStarted { total, peers } => value.with("total", *total).with(
+                "peers",
+                JsonValue::Object(
+                    peers
+                        .iter()
+                        .map(|(peer, count)| (peer.clone(), JsonValue::from(*count)))
+                        .collect(),
+                ),
+            ),
+            ClientEvent::SyncProgress { delivered, remaining } => {
+                value.with("delivered", *delivered).with("remaining", *remaining)
+            }
+            ClientEvent::SyncFinished { delivered } => value.with("delivered", *delivered),
+            ClientEvent::MutedSenders(senders) => value.with("senders", senders.clone()),
+            ClientEvent::ScheduledMessages(entries) => value.with(
+                "scheduled",
+                entries
+                    .iter()
+                    .map(|entry| {
+                        JsonValue::object()
+                            .with("id", entry.id)
+                            .with("recipient", entry.recipient.as_str())
+                            .with("body", entry.body.as_str())
+                            .with("due", entry.due.to_rfc3339())
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            ClientEvent::ApiTokens(tokens) => value.with(
+                "tokens",
+                tokens
+                    .iter()
+                    .map(|token| {
+                        JsonValue::object()
+                            .with("id", token.id)
+                            .with("label", token.label.as_str())
+                            .with("created", token.created.to_rfc3339())
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            ClientEvent::ApiTokenCreated { info, token } => value
+                .with("id", info.id)
+                .with("label", info.label.as_str())
+                .with("created", info.created.to_rfc3339())
+                .with("token", token.as_str()),
+            ClientEvent::ScheduledMessageSent { id, recipient, error } => value
+                .with("id", *id)
+                .with("recipient", recipient.as_str())
+                .with("error", error.clone()),
+            ClientEvent::Preferences(entries) | ClientEvent::PreferencesChanged(entries) => value.with(
+                "preferences",
+                JsonValue::Object(
+                    entries
+                        .iter()
+                        .map(|(key, setting)| (key.clone(), JsonValue::from(setting.as_str())))
+                        .collect(),
+                ),
+            ),
+            ClientEvent::FileOffered {
+                id,
+                recipient,
+                filename,
+            } => value
+                .with("id", *id)
+                .with("recipient", recipient.as_str())
+                .with("filename", filename.as_str()),
+            ClientEvent::FileOffer {
+                id,
+                sender,
+                filename,
+                size,
+            } => value
+                .with("id", *id)
+                .with("sender", sender.as_str())
+                .with("filename", filename.as_str())
+                .with("size", *size),
+            ClientEvent::FileAccepted(id) | ClientEvent::FileSent(id) => value.with("id", *id),
+            ClientEvent::FileReceived { id, sender, path } => value
+                .with("id", *id)
+                .with("sender", sender.as_str())
+                .with("path", path.display().to_string()),
+            ClientEvent::FileRejected { id, reason } => value.with("id", *id).with("reason", reason.as_str()),
+            ClientEvent::DeliveryExpired { id, recipient } => {
+                value.with("id", *id).with("recipient", recipient.as_str())
+            }
+            ClientEvent::DeliveryFailed { recipient, error, code } => value
+                .with("recipient", recipient.clone())
+                .with("error", error.as_str())
+                .with("code", code.as_str()),
+            ClientEvent::ServerCapabilities(capabilities) => value.with("capabilities", capabilities.clone()),
+            ClientEvent::ServerIdentity { name, id } => value.with("name", name.as_str()).with("id", id.as_str()),
+            ClientEvent::Motd(text) => value.with("text", text.as_str()),
+            ClientEvent::ReauthRequired(reason) => value.with("reason", reason.as_str()),
+            ClientEvent::PasswordChanged(username) => value.with("username", username.as_str()),
+            ClientEvent::UserRenamed { old, new } => value.with("old", old.as_str()).with("new", new.as_str()),
+            ClientEvent::PresenceUpdate { online, offline } => {
+                value.with("online", online.clone()).with("offline", offline.clone())
+            }
+            ClientEvent::PresenceSnapshot(snapshot) => value
+                .with("scope", snapshot.scope.encode())
+                .with("version", snapshot.version)
+                .with(
+                    "users",
+                    snapshot
+                        .entries
+                        .iter()
+                        .map(|entry| {
+                            JsonValue::object()
+                                .with("user", entry.user.as_str())
+                                .with("status", entry.status.as_str())
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            ClientEvent::PresenceDelta(delta) => value
+                .with("scope", delta.scope.encode())
+                .with("version", delta.version)
+                .with("change", delta.change.as_str())
+                .with("user", delta.entry.user.as_str())
+                .with("status", delta.entry.status.as_str()),
+            ClientEvent::ServerShutdownWarning(timeout) => value.with("timeout", *timeout),
+            ClientEvent::LogLevelChanged { previous, current } => value
+                .with("previous", previous.as_str())
+                .with("current", current.as_str()),
+            ClientEvent::TimeSynced {
+                offset_ms,
+                round_trip_ms,
+            } => value
+                .with("offset_ms", *offset_ms)
+                .with("round_trip_ms", *round_trip_ms),
+            ClientEvent::SecurityNotice { kind, detail } => {
+                value.with("kind", kind.as_str()).with("detail", detail.as_str())
+            }
+            ClientEvent::ConnectionStale {
+                received_ms,
+                two_way_ms,
+            } => value.with("received_ms", *received_ms).with("two_way_ms", *two_way_ms),
+            ClientEvent::ConnectionQuality(level) => value.with("level", *level as u64),
+            ClientEvent::ProtocolFault { error, recovered } => {
+                value.with("error", error.as_str()).with("recovered", *recovered)
+            }
+            ClientEvent::ServerDisconnected { reason, detail } => {
+                value.with("reason", reason.as_str()).with("detail", detail.as_str())
+            }
+            ClientEvent::ServerBusy { retry_after, reason } => {
+                value.with("retry_after", *retry_after).with("reason", reason.as_str())
+            }
+            ClientEvent::ServerStats {
+                mode,
+                sessions,
+                users,
+                missed_heartbeats,
+                stalled_writes,
+                flood_warnings,
+                flood_disconnects,
+                queued_messages,
+                expired_messages,
+                closed_by_peer,
+                broken_connections,
+                presence_coalesced,
+                presence_dropped,
+                accept_errors,
+                integrity_failures,
+                webhooks_delivered,
+                webhooks_failed,
+                auth_queue_depth,
+                auth_rejected,
+                journal_dropped,
+                journal_failing,
+                server_name,
+                server_id,
+            } => value
+                .with("mode", mode.as_str())
+                .with("sessions", *sessions)
+                .with("users", *users)
+                .with("missed_heartbeats", *missed_heartbeats)
+                .with("stalled_writes", *stalled_writes)
+                .with("flood_warnings", *flood_warnings)
+                .with("flood_disconnects", *flood_disconnects)
+                .with("queued_messages", *queued_messages)
+                .with("expired_messages", *expired_messages)
+                .with("closed_by_peer", *closed_by_peer)
+                .with("broken_connections", *broken_connections)
+                .with("presence_coalesced", *presence_coalesced)
+                .with("presence_dropped", *presence_dropped)
+                .with("accept_errors", *accept_errors)
+                .with("integrity_failures", *integrity_failures)
+                .with("webhooks_delivered", *webhooks_delivered)
+                .with("webhooks_failed", *webhooks_failed)
+                .with("auth_queue_depth", *auth_queue_depth)
+                .with("auth_rejected", *auth_rejected)
+                .with("journal_dropped", *journal_dropped)
+                .with("journal_failing", *journal_failing)
+                .with("server_name", server_name.as_str())
+                .with("server_id", server_id.as_str()),
+            ClientEvent::UserInfo {
+                username,
+                access_level,
+                sessions,
+                sent,
+                delivered,
+                queued,
+                dropped,
+                last_error,
+                temporary_grants,
+            } => value
+                .with("username", username.as_str())
+                .with("access_level", access_level.as_str())
+                .with("sessions", *sessions)
+                .with("sent", *sent)
+                .with("delivered", *delivered)
+                .with("queued", *queued)
+                .with("dropped", *dropped)
+                .with("last_error", last_error.clone())
+                .with(
+                    "temporary_grants",
+                    temporary_grants
+                        .iter()
+                        .map(|(permission, expires_at)| {
+                            JsonValue::object()
+                                .with("permission", permission.as_str())
+                                .with("expires_at", expires_at.as_str())
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            ClientEvent::SessionInfo(info) => value
+                .with("id", info.id.as_str())
+                .with("username", info.username.as_str())
+                .with("peer", info.peer.as_str())
+                .with("access_level", info.access_level.as_str())
+                .with("connected", info.connected)
+                .with("idle", info.idle)
+                .with("heartbeat_age", info.heartbeat_age)
+                .with("received", info.received)
+                .with("sent", info.sent)
+                .with("received_age", info.received_age)
+                .with("two_way_age", info.two_way_age)
+                .with("integrity", info.integrity.as_str())
+                .with("integrity_failures", info.integrity_failures),
+            ClientEvent::SessionListEnd { count, kicked } => value.with("count", *count).with("kicked", *kicked),
+            ClientEvent::IpBans(list) => value
+                .with(
+                    "bans",
+                    JsonValue::Object(
+                        list.bans
+                            .iter()
+                            .map(|(address, remaining)| (address.clone(), JsonValue::from(*remaining)))
+                            .collect(),
+                    ),
+                )
+                .with("issued", list.issued)
+                .with("turned_away", list.turned_away),
+            ClientEvent::ConsistencyReport(report) => value
+                .with("users", report.users)
+                .with("sessions", report.sessions)
+                .with(
+                    "violations",
+                    report
+                        .violations
+                        .iter()
+                        .map(|violation| {
+                            JsonValue::object()
+                                .with("invariant", violation.invariant.as_str())
+                                .with("detail", violation.detail.as_str())
+                                .with("repaired", violation.repaired)
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            ClientEvent::StateExported { path, users } => value.with("path", path.as_str()).with("users", *users),
+            ClientEvent::TranscriptExported { peer, path, messages } => value
+                .with("peer", peer.as_str())
+                .with("path", path.display().to_string())
+                .with("messages", *messages),
+            ClientEvent::TranscriptFailed { peer, error } => {
+                value.with("peer", peer.as_str()).with("error", error.as_str())
+            }
+            ClientEvent::AccessLevelChanged { username, level } => {
+                value.with("username", username.as_str()).with("level", level.as_str())
+            }
+            ClientEvent::UserKicked { username, reason } => value
+                .with("username", username.as_str())
+                .with("reason", reason.as_str()),
+            ClientEvent::MissedNotice {
+                kind,
+                detail,
+                occurred_at,
+            } => value
+                .with("kind", kind.as_str())
+                .with("detail", detail.as_str())
+                .with("occurred_at", occurred_at.to_rfc3339()),
+            ClientEvent::Rejected { command, reason } => {
+                value.with("command", command.clone()).with("reason", reason.as_str())
+            }
+            _ => value,
+        }
+    }
+}
+
+impl ClientOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_outbox_capacity(mut self, outbox_capacity: usize) -> Self {
+        self.outbox_capacity = outbox_capacity;
+        self
+    }
+
+    pub fn with_reconnect_interval(mut self, reconnect_interval: u64) -> Self {
+        self.reconnect_interval = reconnect_interval;
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: FrameTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_time_sync_interval(mut self, time_sync_interval: Duration) -> Self {
+        self.time_sync_interval = time_sync_interval;
+        self
+    }
+
+    // a heartbeat goes out when nothing arrived from the server for this long
+    pub fn with_keepalive_interval(mut self, keepalive_interval: Duration) -> Self {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    // how long to wait for the server to acknowledge our Disconnect before closing anyway
+    pub fn with_disconnect_timeout(mut self, disconnect_timeout: Duration) -> Self {
+        self.disconnect_timeout = disconnect_timeout;
+        self
+    }
+
+    pub fn with_download_dir(mut self, download_dir: PathBuf) -> Self {
+        self.download_dir = download_dir;
+        self
+    }
+
+    // the language server texts like error details should arrive in, codes stay the same
+    pub fn with_language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    // in order of preference, crc32 needs no offer and ends the list implicitly
+    pub fn with_integrity(mut self, integrity: Vec<Integrity>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    // queued messages pulled at a time from servers that allow it, 0 has them all sent at login
+    pub fn with_offline_sync(mut self, batch: u64) -> Self {
+        self.offline_sync = batch;
+        self
+    }
+
+    pub fn with_quality_weights(mut self, quality_weights: QualityWeights) -> Self {
+        self.quality_weights = quality_weights;
+        self
+    }
+
+    // signs every direct message sent and registers the key with servers that check them
+    pub fn with_signing_identity(mut self, identity: SigningIdentity) -> Self {
+        self.signing = Some(identity);
+        self
+    }
+
+    pub fn with_known_keys(mut self, known_keys: KnownKeys) -> Self {
+        self.known_keys = known_keys;
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            host: HOST.to_string(),
+            port: PORT,
+            outbox_capacity: DEFAULT_OUTBOX_CAPACITY,
+            reconnect_interval: DEFAULT_RECONNECT_INTERVAL,
+            tracer: None,
+            clock: Arc::new(SystemClock),
+            time_sync_interval: DEFAULT_TIME_SYNC_INTERVAL,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            disconnect_timeout: DEFAULT_DISCONNECT_TIMEOUT,
+            download_dir: PathBuf::from(DEFAULT_DOWNLOAD_DIR),
+            language: None,
+            integrity: vec![Integrity::Crc32],
+            offline_sync: DEFAULT_SYNC_BATCH,
+            quality_weights: QualityWeights::default(),
+            signing: None,
+            known_keys: KnownKeys::default(),
+        }
+    }
+}
+
+impl ClientState {
+    fn send(&self, message: Message) -> bool {
+        match &self.tx {
+            Some(tx) => tx.send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    fn username(&self) -> Option<String> {
+        self.credentials.as_ref().map(|(username, _)| username.clone())
+    }
+
+    fn server_time_now(&self) -> DateTime<Utc> {
+        let now = self.clock.now();
+        self.time_sample.map_or(now, |sample| sample.server_time(now))
+    }
+
+    // messages to and from ourselves are notes
+    fn conversation_of(&self, peer: &str) -> String {
+        match self.username() {
+            Some(username) if username == peer => NOTES.to_string(),
+            _ => peer.to_string(),
+        }
+    }
+
+    // holds the login back until the first frame of the connection told us whether there is a nonce
+    fn send_login(&mut self) -> bool {
+        if self.tx.is_some() {
+            self.advance(ConnectionInput::LoginSent);
+        }
+        if !self.greeted {
+            self.login_pending = true;
+            return self.tx.is_some();
+        }
+        match self.login_message() {
+            Some(message) => self.send_auth(message),
+            None => false,
+        }
+    }
+
+    fn send_auth(&mut self, message: Message) -> bool {
+        self.auth_request = Some(message.clone());
+        self.send(message)
+    }
+
+    fn login_message(&mut self) -> Option<Message> {
+        let credentials = self.credentials.clone()?;
+        let (username, password) = &credentials;
+        let Some(nonce) = self.nonce.clone() else {
+            return Some(Message::auth(username, password));
+        };
+
+        let key = match &self.login_key {
+            Some((derived_for, key)) if *derived_for == credentials => key.clone(),
+            _ => match derive_key(username, password) {
+                Ok(key) => {
+                    self.login_key = Some((credentials.clone(), key.clone()));
+                    key
+                }
+                Err(e) => {
+                    tracing::warn!("Could not derive login key: {}", e);
+                    return Some(Message::auth(username, password));
+                }
+            },
+        };
+        match challenge_response(&key, &nonce) {
+            Ok(response) => Some(Message::auth_challenge(username, &response)),
+            Err(e) => {
+                tracing::warn!("Could not answer login challenge: {}", e);
+                Some(Message::auth(username, password))
+            }
+        }
+    }
+
+    // the sequences for the next heartbeat we send
+    fn next_heartbeat(&mut self, reply: bool) -> HeartbeatEcho {
+        self.heartbeat_sequence += 1;
+        HeartbeatEcho {
+            sequence: self.heartbeat_sequence,
+            echo: self.peer_sequence,
+            reply,
+        }
+    }
+
+    // the answer to a heartbeat from the server, servers without sequences get one like they always did
+    fn receive_heartbeat(&mut self, echo: Option<HeartbeatEcho>) -> Option<Message> {
+        let Some(echo) = echo else {
+            return Some(Message::heartbeat());
+        };
+        self.peer_sequence = self.peer_sequence.max(echo.sequence);
+        if echo.echo > self.acknowledged && echo.echo <= self.heartbeat_sequence {
+            self.acknowledged = echo.echo;
+            self.last_two_way = Some(Instant::now());
+        }
+        if let Some((_, sent)) = self.probe.filter(|(sequence, _)| echo.reply && echo.echo >= *sequence) {
+            self.probe = None;
+            self.sample_quality(QualitySample::RoundTrip(sent.elapsed()));
+        }
+        (!echo.reply).then(|| Message::heartbeat_with(self.next_heartbeat(true)))
+    }
+
+    // what the server last proved about the connection, anything it sent or only what it echoed
+    fn heard(&self) -> Instant {
+        self.last_two_way
+            .map_or(self.last_received, |last_two_way| last_two_way.min(self.last_received))
+    }
+
+    fn emit(&self, event: ClientEvent) {
+        // the front-end may have stopped listening, events are best effort
+        self.events.send(event).ok();
+    }
+
+    fn sample_quality(&mut self, sample: QualitySample) {
+        if let Some(level) = self.quality.record(sample) {
+            self.emit(ClientEvent::ConnectionQuality(level));
+        }
+    }
+
+    // inputs the current state has no transition for are dropped, they come from a connection that is already gone
+    fn advance(&mut self, input: ConnectionInput) {
+        match self.connection.next(input) {
+            Ok(to) if to != self.connection => {
+                let from = std::mem::replace(&mut self.connection, to);
+                self.emit(ClientEvent::StateChanged { from, to });
+            }
+            Ok(_) => {}
+            Err(e) => tracing::debug!("{}", e),
+        }
+    }
+
+    fn lost(&mut self) {
+        if self.connection != ConnectionState::Disconnected {
+            self.advance(ConnectionInput::Lost);
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.tx = None;
+        self.lost();
+        self.access_level = Some(AccessLevel::Guest);
+        self.capabilities = None;
+        self.outbox.requeue_in_flight();
+        self.offline_sync.disconnected();
+        for id in self.files.clear() {
+            self.emit(ClientEvent::FileRejected {
+                id,
+                reason: "Connection lost".to_string(),
+            });
+        }
+        if let Some(job) = self.transcript.take() {
+            self.emit(ClientEvent::TranscriptFailed {
+                peer: job.export.peer,
+                error: "Connection lost".to_string(),
+            });
+        }
+    }
+
+    // judged by the default presets, per-user overrides only show up as NACKs
+    fn access_problem(&self, command: &str) -> Option<String> {
+        let required = required_level(command)?;
+        if self.connection != ConnectionState::Ready {
+            // messages typed while reconnecting wait in the outbox for the login
+            if required == AccessLevel::User && self.credentials.is_some() {
+                return None;
+            }
+            return Some("you must log in first".to_string());
+        }
+        match self.access_level {
+            Some(level) if level < required => Some(format!(
+                "{} only, you are logged in as {}",
+                required.as_str(),
+                level.as_str()
+            )),
+            _ => None,
+        }
+    }
+
+    fn flush_outbox(&mut self) {
+        for message in self.outbox.take_pending() {
+            if !self.send(message) {
+                self.outbox.requeue_in_flight();
+                return;
+            }
+        }
+    }
+
+    fn server_supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(capability))
+    }
+
+    // and whether it stands, a signature neither key here vouches for waits for the sender's key from the server
+    fn check_signature(&self, sender: &str, recipient: &str, body: &str, signature: &[u8]) -> (Verification, bool) {
+        let verifies = |key: &PublicKey| verify_signature(key, sender, recipient, body, signature);
+        let trusted = self.known_keys.get(sender);
+        if trusted.is_some_and(&verifies) {
+            return (Verification::Verified, true);
+        }
+        let announced = fold_username(sender)
+            .ok()
+            .and_then(|sender| self.announced_keys.get(&sender));
+        match (trusted, announced) {
+            // theirs, only not the key trusted so far
+            (_, Some(key)) if verifies(key) => (Verification::Unverified, true),
+            (None, None) => (Verification::Unverified, false),
+            _ => (Verification::Invalid, false),
+        }
+    }
+
+    // what a message is shown with first, one that cannot be settled yet gets a MessageVerified once it is
+    fn verification_of(
+        &mut self,
+        id: Option<u64>,
+        sender: &str,
+        recipient: &str,
+        body: &str,
+        message: &Message,
+    ) -> Verification {
+        let Some(signature) = message.signature() else {
+            return Verification::Unsigned;
+        };
+        match self.check_signature(sender, recipient, body, signature) {
+            (verification, true) => verification,
+            // unverified until the server tells us their key, it may have changed
+            (_, false) => {
+                self.await_signing_key(AwaitingSignature {
+                    id,
+                    sender: sender.to_string(),
+                    recipient: recipient.to_string(),
+                    body: body.to_string(),
+                    signature: signature.to_vec(),
+                });
+                Verification::Unverified
+            }
+        }
+    }
+
+    // the sender's key is asked for once, however many of their messages wait for it
+    fn await_signing_key(&mut self, awaiting: AwaitingSignature) {
+        if !self.server_supports(capability::MESSAGE_SIGNING) {
+            return;
+        }
+        let Ok(sender) = fold_username(&awaiting.sender) else {
+            return;
+        };
+        if !self.awaiting_keys.contains_key(&sender) {
+            self.send(Message::signing_key_request(&awaiting.sender));
+        }
+        let waiting = self.awaiting_keys.entry(sender).or_default();
+        if waiting.len() < MAX_AWAITING_SIGNATURES {
+            waiting.push(awaiting);
+        }
+    }
+
+    // the first key seen for a user is trusted, a different one later only once the user says so
+    fn receive_signing_key(&mut self, username: &str, key: Option<PublicKey>) {
+        let Ok(folded) = fold_username(username) else {
+            return;
+        };
+        let previous = match key {
+            Some(key) => self.announced_keys.insert(folded.clone(), key),
+            None => self.announced_keys.remove(&folded),
+        };
+        match (self.known_keys.get(username).copied(), key) {
+            (None, Some(key)) => {
+                if let Err(e) = self.known_keys.trust(username, key) {
+                    tracing::warn!("Could not save the signing key of {}: {}", username, e);
+                }
+            }
+            (Some(trusted), Some(key)) if trusted != key && previous != Some(key) => {
+                self.emit(ClientEvent::SigningKeyChanged {
+                    username: username.to_string(),
+                    trusted: fingerprint(&trusted),
+                    announced: fingerprint(&key),
+                });
+            }
+            _ => {}
+        }
+        for awaiting in self.awaiting_keys.remove(&folded).unwrap_or_default() {
+            let (verification, _) = self.check_signature(
+                &awaiting.sender,
+                &awaiting.recipient,
+                &awaiting.body,
+                &awaiting.signature,
+            );
+            if verification != Verification::Unverified {
+                self.emit(ClientEvent::MessageVerified {
+                    id: awaiting.id,
+                    sender: awaiting.sender,
+                    verification,
+                });
+            }
+        }
+    }
+}
+
+impl ChatClient {
+    pub async fn connect(options: ClientOptions) -> io::Result<(Self, mpsc::UnboundedReceiver<ClientEvent>)> {
+        tracing::debug!("Connecting to server at {}:{}", options.host, options.port);
+        let connector = TcpConnector::new(&options.host, options.port);
+        Self::connect_with(connector, options).await
+    }
+
+    pub async fn connect_with<C: Connector>(
+        connector: C,
+        options: ClientOptions,
+    ) -> io::Result<(Self, mpsc::UnboundedReceiver<ClientEvent>)> {
+        // every connection attempt gets its own span, the first one is attempt 0
+        let span = tracing::info_span!("connection", attempt = 0u64);
+        let stream = connector.connect().instrument(span.clone()).await?;
+
+        let (events_tx, events_rx) = mpsc::unbounded_channel::<ClientEvent>();
+        let state = Arc::new(RwLock::new(ClientState {
+            tx: None,
+            events: events_tx,
+            credentials: None,
+            login_key: None,
+            greeted: false,
+            login_pending: false,
+            nonce: None,
+            pending_password: None,
+            auth_request: None,
+            // the first connection is already open, `open_connection` moves on from here
+            connection: ConnectionState::Connecting,
+            access_level: Some(AccessLevel::Guest),
+            user_id: None,
+            closing: false,
+            disconnect_sent: false,
+            disconnect_timeout: options.disconnect_timeout,
+            outbox: Outbox::new(options.outbox_capacity),
+            tracer: options.tracer.clone(),
+            clock: Arc::clone(&options.clock),
+            time_sync_interval: options.time_sync_interval,
+            keepalive_interval: options.keepalive_interval,
+            last_received: Instant::now(),
+            heartbeat_sequence: 0,
+            peer_sequence: 0,
+            acknowledged: 0,
+            last_two_way: None,
+            probe: None,
+            quality: QualityScorer::new(options.quality_weights),
+            time_sample: None,
+            capabilities: None,
+            server_name: None,
+            retry_after: None,
+            sent_ids: VecDeque::new(),
+            unread: BTreeMap::new(),
+            preferences: BTreeMap::new(),
+            preferences_version: 0,
+            muted: BTreeSet::new(),
+            rosters: BTreeMap::new(),
+            conversations: Conversations::default(),
+            files: FileTransfers::default(),
+            download_dir: options.download_dir.clone(),
+            transcript: None,
+            language: options.language.clone(),
+            integrity_preference: options.integrity.clone(),
+            integrity: Integrity::default(),
+            offline_sync: OfflineSync::new(options.offline_sync),
+            signing: options.signing.clone(),
+            known_keys: options.known_keys.clone(),
+            announced_keys: BTreeMap::new(),
+            awaiting_keys: BTreeMap::new(),
+        }));
+
+        let handles = Self::open_connection(stream, &state).instrument(span).await;
+        tokio::spawn(Self::handle_connection(connector, handles, options, Arc::clone(&state)));
+
+        Ok((Self { state }, events_rx))
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> bool {
+        let mut state = self.state.write().await;
+        state.credentials = Some((username.to_string(), password.to_string()));
+        state.send_login()
+    }
+
+    pub async fn create_account(&self, username: &str, password: &str) -> bool {
+        let mut state = self.state.write().await;
+        state.credentials = Some((username.to_string(), password.to_string()));
+        if state.tx.is_some() {
+            state.advance(ConnectionInput::LoginSent);
+        }
+        state.send_auth(Message::auth_create(username, password))
+    }
+
+    pub async fn send_direct_message(&self, recipient: &str, body: &str) -> SendStatus {
+        self.send_with_ttl(recipient, body, None).await.0
+    }
+
+    // the client id comes back in the Delivered event of this message, None when the outbox was full
+    pub async fn send_direct_message_with_id(&self, recipient: &str, body: &str) -> (SendStatus, Option<Uuid>) {
+        self.send_with_ttl(recipient, body, None).await
+    }
+
+    // a direct message to yourself, None before a login told us who that is
+    pub async fn send_note(&self, body: &str) -> Option<SendStatus> {
+        let username = self.username().await?;
+        Some(self.send_with_ttl(&username, body, None).await.0)
+    }
+
+    // the server drops the message once the ttl has passed, delivered or not
+    pub async fn send_ephemeral_message(&self, recipient: &str, body: &str, ttl: Duration) -> SendStatus {
+        self.send_with_ttl(recipient, body, Some(ttl.as_secs().max(1))).await.0
+    }
+
+    async fn send_with_ttl(&self, recipient: &str, body: &str, ttl: Option<u64>) -> (SendStatus, Option<Uuid>) {
+        let mut state = self.state.write().await;
+        if state.outbox.is_full() {
+            return (SendStatus::OutboxFull, None);
+        }
+
+        if let Some(username) = state.username() {
+            let line = ScrollbackLine {
+                sender: username,
+                body: body.to_string(),
+                sent_at: state.server_time_now(),
+                id: None,
+            };
+            let conversation = state.conversation_of(recipient);
+            state.conversations.record(&conversation, line, false);
+        }
+
+        let signature = state
+            .signing
+            .as_ref()
+            .zip(state.username())
+            .and_then(|(identity, username)| identity.sign(&username, recipient, body));
+        let entry = OutboxEntry::new(recipient, body, ttl).with_signature(signature);
+        let client_id = Some(entry.id());
+        if state.connection == ConnectionState::Ready && state.send(entry.message()) {
+            state.outbox.push_in_flight(entry);
+            (SendStatus::Sent, client_id)
+        } else {
+            state.outbox.push_pending(entry);
+            (SendStatus::Pending, client_id)
+        }
+    }
+
+    pub async fn edit_message(&self, id: u64, body: &str) -> Result<(), String> {
+        let state = self.state.read().await;
+        if !state.sent_ids.contains(&id) {
+            return Err(format!("Message {} is not one of your recent messages", id));
+        }
+        if !state.send(Message::message_edit(MessageId::new(id), body)) {
+            return Err("Not connected to the server".into());
+        }
+        Ok(())
+    }
+
+    pub async fn delete_message(&self, id: u64) -> Result<(), String> {
+        let state = self.state.read().await;
+        if !state.sent_ids.contains(&id) {
+            return Err(format!("Message {} is not one of your recent messages", id));
+        }
+        if !state.send(Message::message_delete(MessageId::new(id))) {
+            return Err("Not connected to the server".into());
+        }
+        Ok(())
+    }
+
+    // without a cursor the newest messages are returned
+    pub async fn request_history(&self, peer: &str, limit: u64, before: Option<u64>) -> bool {
+        let request = match before {
+            Some(before) => Message::history_request_before(peer, limit, MessageId::new(before)),
+            None => Message::history_request(peer, limit),
+        };
+        self.state.read().await.send(request)
+    }
+
+    // the server's history is collected page by page, the transcript is written once the server runs out
+    pub async fn export_transcript(&self, export: TranscriptExport) -> Result<(), String> {
+        let mut state = self.state.write().await;
+        if let Some(job) = &state.transcript {
+            return Err(format!("Still exporting the conversation with {}", job.export.peer));
+        }
+        if !state.send(Message::history_request(&export.peer, TRANSCRIPT_PAGE)) {
+            return Err("Not connected to the server".into());
+        }
+        state.transcript = Some(TranscriptJob::new(export));
+        Ok(())
+    }
+
+    // searches every conversation unless a peer is given
+    pub async fn search(&self, query: &str, peer: Option<&str>, limit: u64) -> bool {
+        self.state
+            .read()
+            .await
+            .send(Message::search_request(query, peer, limit))
+    }
+
+    pub async fn mark_read(&self, peer: &str) -> bool {
+        let mut state = self.state.write().await;
+        state.unread.remove(peer);
+        state.send(Message::mark_conversation_read(peer))
+    }
+
+    // as last reported by the server
+    pub async fn unread_counts(&self) -> BTreeMap<String, u64> {
+        self.state.read().await.unread.clone()
+    }
+
+    pub async fn set_preference(&self, key: &str, value: &str) -> bool {
+        self.state.read().await.send(Message::set_preference(key, value))
+    }
+
+    // all of them or none, older servers take them one at a time and may stop halfway
+    pub async fn set_preferences(&self, updates: &[(String, String)]) -> bool {
+        let state = self.state.read().await;
+        let batched = state
+            .capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(capability::PREFERENCE_BATCH));
+        match batched {
+            true => state.send(Message::set_preferences(updates)),
+            false => updates
+                .iter()
+                .all(|(key, value)| state.send(Message::set_preference(key, value))),
+        }
+    }
+
+    // as last reported by the server
+    pub async fn preferences(&self) -> BTreeMap<String, String> {
+        self.state.read().await.preferences.clone()
+    }
+
+    pub async fn preferences_version(&self) -> u64 {
+        self.state.read().await.preferences_version
+    }
+
+    pub async fn mute(&self, sender: &str) -> bool {
+        self.state.read().await.send(Message::mute_add(sender))
+    }
+
+    pub async fn unmute(&self, sender: &str) -> bool {
+        self.state.read().await.send(Message::mute_remove(sender))
+    }
+
+    // as last reported by the server
+    pub async fn muted_senders(&self) -> BTreeSet<String> {
+        self.state.read().await.muted.clone()
+    }
+
+    pub async fn schedule_message(&self, recipient: &str, body: &str, due: ScheduleTime) -> bool {
+        self.state
+            .read()
+            .await
+            .send(Message::schedule_message_send(recipient, body, due))
+    }
+
+    pub async fn list_scheduled(&self) -> bool {
+        self.state.read().await.send(Message::schedule_list())
+    }
+
+    pub async fn cancel_scheduled(&self, id: u64) -> bool {
+        self.state.read().await.send(Message::schedule_cancel(id))
+    }
+
+    pub async fn create_api_token(&self, label: &str) -> bool {
+        self.state.read().await.send(Message::api_token_create(label))
+    }
+
+    pub async fn list_api_tokens(&self) -> bool {
+        self.state.read().await.send(Message::api_token_list())
+    }
+
+    pub async fn revoke_api_token(&self, id: u64) -> bool {
+        self.state.read().await.send(Message::api_token_revoke(id))
+    }
+
+    // the roster is filled by the snapshot that answers and kept up from then on, before login it waits for it
+    pub async fn follow_presence(&self, scope: PresenceScope) -> bool {
+        let mut state = self.state.write().await;
+        let message = Message::subscribe_presence(&scope);
+        state.rosters.insert(scope, None);
+        state.connection != ConnectionState::Ready || state.send(message)
+    }
+
+    pub async fn unfollow_presence(&self, scope: &PresenceScope) -> bool {
+        let mut state = self.state.write().await;
+        state.rosters.remove(scope);
+        state.send(Message::unsubscribe_presence(scope))
+    }
+
+    // None unless the scope is followed and its snapshot arrived
+    pub async fn roster(&self, scope: &PresenceScope) -> Option<Vec<PresenceEntry>> {
+        self.state
+            .read()
+            .await
+            .rosters
+            .get(scope)
+            .and_then(Option::as_ref)
+            .map(Roster::entries)
+    }
+
+    pub async fn is_muted(&self, sender: &str) -> bool {
+        self.state.read().await.muted.contains(sender)
+    }
+
+    // your own name is the notes, everything in the conversation counts as read
+    pub async fn switch_conversation(&self, name: &str) -> Result<String, String> {
+        let mut state = self.state.write().await;
+        let name = match name.trim() {
+            "" => return Err("Usage: switch <name>".to_string()),
+            name => state.conversation_of(name),
+        };
+        state.conversations.switch(&name);
+        Ok(name)
+    }
+
+    // to the next or previous open conversation, None if none is open
+    pub async fn cycle_conversation(&self, forward: bool) -> Option<String> {
+        self.state.write().await.conversations.cycle(forward)
+    }
+
+    pub async fn close_conversation(&self, name: &str) -> bool {
+        let mut state = self.state.write().await;
+        let name = state.conversation_of(name.trim());
+        state.conversations.close(&name)
+    }
+
+    pub async fn active_conversation(&self) -> Option<String> {
+        self.state.read().await.conversations.active().map(str::to_string)
+    }
+
+    // sorted by name, with what arrived in them while another one was active
+    pub async fn conversations(&self) -> Vec<(String, u64)> {
+        self.state.read().await.conversations.list()
+    }
+
+    pub async fn scrollback(&self, name: &str) -> Option<Vec<ScrollbackLine>> {
+        let state = self.state.read().await;
+        state.conversations.scrollback(&state.conversation_of(name))
+    }
+
+    // a line typed without a command, sent to whoever the active conversation is with
+    pub async fn send_to_active(&self, body: &str) -> Result<SendStatus, String> {
+        let active = self.active_conversation().await;
+        match active.as_deref() {
+            None => Err("No active conversation, 'switch <name>' opens one".to_string()),
+            Some(NOTES) => self
+                .send_note(body)
+                .await
+                .ok_or_else(|| "Log in before writing notes".to_string()),
+            Some(peer) => Ok(self.send_direct_message(peer, body).await),
+        }
+    }
+
+    // newest last
+    pub async fn recent_sent_ids(&self) -> Vec<u64> {
+        self.state.read().await.sent_ids.iter().copied().collect()
+    }
+
+    // the whole file is read up front, transfers are meant for small files
+    pub async fn send_file(&self, recipient: &str, path: &Path) -> Result<(), String> {
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("'{}' is not a file", path.display()))?;
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        let mut state = self.state.write().await;
+        let offer = Message::file_offer(recipient, filename, data.len() as u64, &files::sha256_hex(&data));
+        if !state.send(offer) {
+            return Err("Not connected to the server".into());
+        }
+        state.files.push_outgoing(recipient, filename, data);
+        Ok(())
+    }
+
+    pub async fn accept_file(&self, id: u64) -> bool {
+        let mut state = self.state.write().await;
+        state.files.accept_incoming(id) && state.send(Message::file_accept(id))
+    }
+
+    // also cancels a transfer in either direction that is still running
+    pub async fn reject_file(&self, id: u64, reason: &str) -> bool {
+        let mut state = self.state.write().await;
+        state.files.remove(id) && state.send(Message::file_reject(id, reason))
+    }
+
+    pub async fn change_password(&self, old_password: &str, new_password: &str) -> bool {
+        let mut state = self.state.write().await;
+        state.pending_password = Some(new_password.to_string());
+        state.send(Message::password_change(old_password, new_password))
+    }
+
+    pub async fn rename_account(&self, new_name: &str, password: &str) -> bool {
+        self.state
+            .read()
+            .await
+            .send(Message::rename_account(new_name, password))
+    }
+
+    pub async fn reset_password(&self, username: &str, password: &str) -> bool {
+        self.state
+            .read()
+            .await
+            .send(Message::admin_reset_password(username, password))
+    }
+
+    pub async fn send(&self, message: Message) -> bool {
+        self.state.read().await.send(message)
+    }
+
+    pub async fn outbox(&self) -> Vec<OutboxEntry> {
+        self.state.read().await.outbox.entries().cloned().collect()
+    }
+
+    pub async fn cancel_outbox_entry(&self, index: usize) -> Option<OutboxEntry> {
+        self.state.write().await.outbox.cancel(index)
+    }
+
+    pub async fn is_authenticated(&self) -> bool {
+        self.state.read().await.connection == ConnectionState::Ready
+    }
+
+    pub async fn connection_state(&self) -> ConnectionState {
+        self.state.read().await.connection
+    }
+
+    pub async fn user_id(&self) -> Option<String> {
+        self.state.read().await.user_id.clone()
+    }
+
+    pub async fn username(&self) -> Option<String> {
+        self.state.read().await.username()
+    }
+
+    pub async fn server_name(&self) -> Option<String> {
+        self.state.read().await.server_name.clone()
+    }
+
+    pub async fn integrity(&self) -> Integrity {
+        self.state.read().await.integrity
+    }
+
+    pub async fn capabilities(&self) -> Option<Capabilities> {
+        self.state.read().await.capabilities.clone()
+    }
+
+    pub async fn server_supports(&self, capability: &str) -> bool {
+        self.state.read().await.server_supports(capability)
+    }
+
+    // of this client's own key without a username, of the key trusted for the user otherwise
+    pub async fn signing_fingerprint(&self, username: Option<&str>) -> Option<String> {
+        let state = self.state.read().await;
+        match username {
+            Some(username) => state.known_keys.get(username).map(fingerprint),
+            None => state.signing.as_ref().map(SigningIdentity::fingerprint),
+        }
+    }
+
+    // replaces the trusted key of the user with the one the server announced, answers its fingerprint
+    pub async fn trust_signing_key(&self, username: &str) -> Result<String, String> {
+        let mut state = self.state.write().await;
+        let folded = fold_username(username)?;
+        match state.announced_keys.get(&folded).copied() {
+            Some(key) if state.known_keys.get(username) != Some(&key) => {
+                state.known_keys.trust(username, key)?;
+                Ok(fingerprint(&key))
+            }
+            _ => Err(format!("No new signing key of {} to trust", username)),
+        }
+    }
+
+    pub async fn access_level(&self) -> Option<AccessLevel> {
+        self.state.read().await.access_level
+    }
+
+    // checked locally so unsupported or forbidden commands never reach the server as NACKs
+    // also refuses commands the connection state does not allow, rather than have them fail on a closed channel
+    pub async fn check_command(&self, command: &str) -> Result<(), CommandError> {
+        let state = self.state.read().await;
+        if let (Some(capability), Some(capabilities)) = (required_capability(command), &state.capabilities) {
+            if !capabilities.supports(capability) {
+                return Err(CommandError::Unsupported {
+                    command: command.to_string(),
+                    capability,
+                });
+            }
+        }
+
+        if !state.connection.accepts(command, state.credentials.is_some()) {
+            return Err(CommandError::InvalidState {
+                command: command.to_string(),
+                state: state.connection,
+            });
+        }
+        match state.access_problem(command) {
+            Some(problem) => Err(CommandError::Forbidden {
+                command: command.to_string(),
+                problem,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn contact(&self) -> Contact {
+        let state = self.state.read().await;
+        let now = Instant::now();
+        Contact {
+            received: now - state.last_received,
+            two_way: state.last_two_way.map(|since| now - since),
+            expected: state.keepalive_interval,
+        }
+    }
+
+    // from 0 to MAX_QUALITY
+    pub async fn connection_quality(&self) -> u8 {
+        self.state.read().await.quality.level()
+    }
+
+    pub async fn time_sample(&self) -> Option<TimeSample> {
+        self.state.read().await.time_sample
+    }
+
+    // falls back to the local clock until the first time sync completed
+    pub async fn server_time_now(&self) -> DateTime<Utc> {
+        self.state.read().await.server_time_now()
+    }
+
+    pub async fn disconnect(&self) {
+        let mut state = self.state.write().await;
+        state.closing = true;
+        if state.send(Message::disconnect(DisconnectReason::ClientQuit, "")) {
+            state.advance(ConnectionInput::Drain);
+        } else {
+            state.emit(ClientEvent::Closed);
+        }
+    }
+
+    async fn handle_connection<C: Connector>(
+        connector: C,
+        handles: (JoinHandle<()>, JoinHandle<()>),
+        options: ClientOptions,
+        state: ArcRwLock<ClientState>,
+    ) {
+        use tokio::time::{self, Duration};
+
+        let mut handles = Some(handles);
+        let mut attempt: u64 = 0;
+
+        loop {
+            if let Some((send_h, recv_h)) = handles.take() {
+                if let Err(e) = send_h.await {
+                    tracing::error!("Send task failed: {}", e);
+                }
+                if let Err(e) = recv_h.await {
+                    tracing::error!("Receive task failed: {}", e);
+                }
+            }
+
+            let mut write_state = state.write().await;
+            if write_state.closing {
+                write_state.lost();
+                write_state.emit(ClientEvent::Closed);
+                break;
+            }
+            if write_state.tx.is_some() {
+                write_state.emit(ClientEvent::Disconnected);
+            }
+            write_state.disconnected();
+            write_state.sample_quality(QualitySample::Reconnect);
+            let interval = write_state
+                .retry_after
+                .take()
+                .map_or(options.reconnect_interval, |retry_after| {
+                    retry_after.max(options.reconnect_interval)
+                });
+            write_state.emit(ClientEvent::Reconnecting(interval));
+            drop(write_state);
+
+            time::sleep(Duration::from_secs(interval)).await;
+
+            if state.read().await.closing {
+                state.read().await.emit(ClientEvent::Closed);
+                break;
+            }
+
+            attempt += 1;
+            state.write().await.advance(ConnectionInput::Connect);
+            let span = tracing::info_span!("connection", attempt);
+            handles = async {
+                match connector.connect().await {
+                    Ok(stream) => Some(Self::open_connection(stream, &state).await),
+                    Err(e) => {
+                        tracing::debug!("Could not reconnect to server: {}", e);
+                        state.write().await.lost();
+                        None
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+        }
+    }
+
+    async fn open_connection<S: Stream>(stream: S, state: &ArcRwLock<ClientState>) -> (JoinHandle<()>, JoinHandle<()>) {
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, rx) = mpsc::unbounded_channel::<Message>();
+
+        let (hdc_tx, hdc_rx) = mpsc::channel::<bool>(1);
+        let (sdc_tx, sdc_rx) = mpsc::channel::<bool>(1);
+        let shutdown = ShutdownToken::new();
+
+        let (tracer, clock, time_sync_interval) = {
+            let mut state = state.write().await;
+            state.last_received = Instant::now();
+            state.heartbeat_sequence = 0;
+            state.peer_sequence = 0;
+            state.acknowledged = 0;
+            state.last_two_way = None;
+            state.probe = None;
+            state.disconnect_sent = false;
+            state.greeted = false;
+            state.login_pending = false;
+            state.auth_request = None;
+            state.nonce = None;
+            state.integrity = Integrity::default();
+            (state.tracer.clone(), Arc::clone(&state.clock), state.time_sync_interval)
+        };
+
+        let send_h = tokio::spawn(
+            Self::handle_send(
+                writer,
+                rx,
+                tracer.clone(),
+                (sdc_tx, hdc_rx),
+                shutdown.clone(),
+                Arc::clone(state),
+            )
+            .in_current_span(),
+        );
+        let recv_h = tokio::spawn(
+            Self::handle_receive(
+                reader,
+                tx.clone(),
+                tracer,
+                (hdc_tx, sdc_rx),
+                shutdown.clone(),
+                Arc::clone(state),
+            )
+            .in_current_span(),
+        );
+
+        let mut write_state = state.write().await;
+        tokio::spawn(Self::sync_time(tx.clone(), clock, time_sync_interval).in_current_span());
+        tokio::spawn(Self::keep_alive(tx.clone(), shutdown, Arc::clone(state)).in_current_span());
+        write_state.tx = Some(tx);
+        write_state.emit(ClientEvent::Connected);
+        write_state.advance(ConnectionInput::Opened);
+        if write_state.credentials.is_some() {
+            write_state.send_login();
+        }
+
+        (send_h, recv_h)
+    }
+
+    async fn sync_time(tx: mpsc::UnboundedSender<Message>, clock: Arc<dyn Clock>, interval: Duration) {
+        // the first tick completes immediately, so every connection starts with a sync
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if tx.send(Message::time_sync(clock.now())).is_err() {
+                        break;
+                    }
+                }
+                _ = tx.closed() => break,
+            }
+        }
+    }
+
+    // the server normally leads, this only speaks up when it went quiet or talks without echoing us, and
+    // gives up on a connection the server stopped echoing on for two intervals
+    async fn keep_alive(tx: mpsc::UnboundedSender<Message>, shutdown: ShutdownToken, state: ArcRwLock<ClientState>) {
+        let mut last_sent = Instant::now();
+        // the silence is looked at a few times per interval, so a stalled link shows before the probe goes out
+        let period = state.read().await.keepalive_interval / QUALITY_SAMPLES;
+        let mut samples = tokio::time::interval(period.max(Duration::from_millis(1)));
+        samples.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let (heard, last_two_way, interval) = {
+                let state = state.read().await;
+                (state.heard(), state.last_two_way, state.keepalive_interval)
+            };
+            let mut deadline = heard.max(last_sent) + interval;
+            if let Some(last_two_way) = last_two_way {
+                deadline = deadline.min(last_two_way + interval * 2);
+            }
+
+            tokio::select! {
+                () = tokio::time::sleep_until(deadline) => {
+                    let mut state = state.write().await;
+                    let now = Instant::now();
+                    if let Some(last_two_way) = state.last_two_way.filter(|since| *since + interval * 2 <= now) {
+                        let received = now - state.last_received;
+                        let two_way = now - last_two_way;
+                        tracing::warn!(
+                            "No echo from the server for {:?} while it was last heard {:?} ago, reconnecting",
+                            two_way,
+                            received
+                        );
+                        state.emit(ClientEvent::ConnectionStale {
+                            received_ms: received.as_millis() as u64,
+                            two_way_ms: two_way.as_millis() as u64,
+                        });
+                        shutdown.cancel();
+                        break;
+                    }
+                    // something may have arrived while we slept
+                    if state.heard() + interval > now {
+                        continue;
+                    }
+                    let probe = state.next_heartbeat(false);
+                    if tx.send(Message::heartbeat_with(probe)).is_err() {
+                        break;
+                    }
+                    state.probe = Some((probe.sequence, now));
+                    last_sent = now;
+                }
+                _ = samples.tick() => {
+                    let mut state = state.write().await;
+                    let gap = state.last_received.elapsed();
+                    state.sample_quality(QualitySample::Heard { gap, expected: interval });
+                }
+                () = shutdown.cancelled() => break,
+                _ = tx.closed() => break,
+            }
+        }
+    }
+
+    async fn handle_send<W: AsyncWrite + Unpin>(
+        mut writer: W,
+        mut rx: mpsc::UnboundedReceiver<Message>,
+        tracer: Option<FrameTracer>,
+        (dc_tx, mut dc_rx): (mpsc::Sender<bool>, mpsc::Receiver<bool>),
+        shutdown: ShutdownToken,
+        state: ArcRwLock<ClientState>,
+    ) {
+        let mut integrity = Integrity::default();
+        loop {
+            if dc_rx.try_recv().is_ok() {
+                break;
+            }
+
+            let message = tokio::select! {
+                // the receiver gave up on the stream, nothing queued is worth sending anymore
+                () = shutdown.cancelled() => {
+                    writer.shutdown().await.ok();
+                    break;
+                }
+                message = rx.recv() => message,
+            };
+            if let Some(message) = message {
+                if message.is(MessageType::Break) {
+                    writer.shutdown().await.ok();
+                    break;
+                }
+                let disconnecting = message.is(MessageType::Disconnect);
+                let disconnect_timeout = if disconnecting {
+                    let mut state = state.write().await;
+                    state.disconnect_sent = true;
+                    state.disconnect_timeout
+                } else {
+                    Duration::ZERO
+                };
+                tracing::debug!("Sending message: {:?}", message.message_type());
+                let started = Instant::now();
+                if let Err(e) = message.send_using(&mut writer, integrity).await {
+                    tracing::error!("Error sending message: {}", e);
+                    shutdown.cancel();
+                    break;
+                }
+                if started.elapsed() >= SEND_TIMEOUT {
+                    tracing::debug!("Writing {:?} took {:?}", message.message_type(), started.elapsed());
+                    state.write().await.sample_quality(QualitySample::SendTimeout);
+                }
+                // the select is the last frame with the old trailer
+                if let Some(selected) = message.switches_integrity() {
+                    integrity = selected;
+                }
+                if let Some(tracer) = &tracer {
+                    tracer.record(Direction::Sent, &message);
+                }
+                if disconnecting {
+                    // the receiver reports the Ack or the server closing its end
+                    if tokio::time::timeout(disconnect_timeout, dc_rx.recv()).await.is_err() {
+                        tracing::debug!(
+                            "Server did not acknowledge the disconnect within {:?}",
+                            disconnect_timeout
+                        );
+                    }
+                    writer.shutdown().await.ok();
+                    dc_tx.try_send(true).ok();
+                    break;
+                }
+            }
+        }
+    }
+
+    // sends the login again unless the connection moved on while it waited
+    async fn retry_auth(request: Message, retry_after: u64, state: ArcRwLock<ClientState>) {
+        tokio::time::sleep(Duration::from_secs(retry_after)).await;
+        let state = state.read().await;
+        if state.connection == ConnectionState::Authenticating && state.auth_request.as_ref() == Some(&request) {
+            state.send(request);
+        }
+    }
+
+    // older servers do not stamp messages, local receive time is the best guess then
+    // tells the frontend to drop an ephemeral message once its time is up
+    async fn expire_message(id: u64, sender: String, expires_at: DateTime<Utc>, state: ArcRwLock<ClientState>) {
+        let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(remaining).await;
+        state.read().await.emit(ClientEvent::MessageExpired { id, sender });
+    }
+
+    fn server_timestamp(message: &Message, index: usize) -> DateTime<Utc> {
+        message
+            .payload()
+            .str_field(index)
+            .ok()
+            .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now)
+    }
+
+    fn user_info(message: &Message) -> Result<ClientEvent, String> {
+        let payload = message.payload();
+        let last_error = payload.str_field(7)?;
+        Ok(ClientEvent::UserInfo {
+            username: payload.str_field(0)?.to_string(),
+            access_level: payload.str_field(1)?.to_string(),
+            sessions: payload.u64_field(2)?,
+            sent: payload.u64_field(3)?,
+            delivered: payload.u64_field(4)?,
+            queued: payload.u64_field(5)?,
+            dropped: payload.u64_field(6)?,
+            last_error: (!last_error.is_empty()).then(|| last_error.to_string()),
+            temporary_grants: message.user_info_grants()?,
+        })
+    }
+
+    fn search_result(message: &Message) -> Result<ClientEvent, String> {
+        let payload = message.payload();
+        let snippet = payload.str_field(3)?;
+        let (start, end) = (payload.u64_field(4)? as usize, payload.u64_field(5)? as usize);
+        if start > end || !snippet.is_char_boundary(start) || !snippet.is_char_boundary(end) {
+            return Err(format!("Highlight {}..{} does not fit the snippet", start, end));
+        }
+
+        Ok(ClientEvent::SearchResult {
+            id: payload.u64_field(0)?,
+            peer: payload.str_field(1)?.to_string(),
+            sent_at: payload.timestamp_field(2)?,
+            snippet: snippet.to_string(),
+            highlight: (start, end),
+        })
+    }
+
+    async fn history_entry(message: &Message, state: &ArcRwLock<ClientState>) -> Result<ClientEvent, String> {
+        let payload = message.payload();
+        let flags = payload.u64_field(5)?;
+        let (id, sender, recipient, body) = (
+            payload.u64_field(0)?,
+            payload.str_field(1)?,
+            payload.str_field(2)?,
+            payload.str_field(3)?,
+        );
+        let verification = state
+            .write()
+            .await
+            .verification_of(Some(id), sender, recipient, body, message);
+
+        Ok(ClientEvent::HistoryEntry {
+            id,
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            body: body.to_string(),
+            sent_at: payload.timestamp_field(4)?,
+            edited: flags & HISTORY_EDITED != 0,
+            deleted: flags & HISTORY_DELETED != 0,
+            self_note: flags & HISTORY_SELF_NOTE != 0,
+            verification,
+        })
+    }
+
+    async fn handle_file_offer(message: &Message, state: &ArcRwLock<ClientState>) {
+        let payload = message.payload();
+        let (sender, filename, size, sha256, id) = match (
+            payload.str_field(0),
+            payload.str_field(1),
+            payload.u64_field(2),
+            payload.str_field(3),
+            payload.u64_field(4),
+        ) {
+            (Ok(sender), Ok(filename), Ok(size), Ok(sha256), Ok(id)) => (sender, filename, size, sha256, id),
+            (Err(e), _, _, _, _)
+            | (_, Err(e), _, _, _)
+            | (_, _, Err(e), _, _)
+            | (_, _, _, Err(e), _)
+            | (_, _, _, _, Err(e)) => {
+                tracing::warn!("Invalid file offer: {}", e);
+                return;
+            }
+        };
+
+        let state = &mut state.write().await;
+        let Some(filename) = files::sanitize_filename(filename) else {
+            state.send(Message::file_reject(id, "Invalid filename"));
+            return;
+        };
+        state.files.push_incoming(id, sender, &filename, size, sha256);
+        state.emit(ClientEvent::FileOffer {
+            id,
+            sender: sender.to_string(),
+            filename,
+            size,
+        });
+    }
+
+    // chunks are queued all at once, the server relays them as they arrive
+    async fn send_file_chunks(id: u64, state: &ArcRwLock<ClientState>) {
+        let mut state = state.write().await;
+        let Some(data) = state.files.take_outgoing(id) else {
+            return;
+        };
+        state.emit(ClientEvent::FileAccepted(id));
+
+        for (index, chunk) in data.chunks(FILE_CHUNK_SIZE).enumerate() {
+            if !state.send(Message::file_chunk(id, index as u64, chunk)) {
+                return;
+            }
+        }
+        if state.send(Message::file_complete(id)) {
+            state.emit(ClientEvent::FileSent(id));
+        }
+    }
+
+    async fn handle_history_entry(event: ClientEvent, state: &ArcRwLock<ClientState>) {
+        let mut state = state.write().await;
+        let (
+            Some(job),
+            ClientEvent::HistoryEntry {
+                id,
+                sender,
+                body,
+                sent_at,
+                edited,
+                deleted,
+                ..
+            },
+        ) = (&mut state.transcript, &event)
+        else {
+            state.emit(event);
+            return;
+        };
+        job.add(TranscriptEntry {
+            id: Some(*id),
+            sender: sender.clone(),
+            body: body.clone(),
+            sent_at: *sent_at,
+            edited: *edited,
+            deleted: *deleted,
+        });
+    }
+
+    // a full page is followed by the one before it, the last one finishes the transcript
+    async fn handle_history_end(count: u64, state: &ArcRwLock<ClientState>) {
+        let (export, transcript) = {
+            let mut state = state.write().await;
+            let Some(job) = &mut state.transcript else {
+                state.emit(ClientEvent::HistoryEnd(count));
+                return;
+            };
+            let next = job.next_page(count).map(|before| {
+                Message::history_request_before(&job.export.peer, TRANSCRIPT_PAGE, MessageId::new(before))
+            });
+            if next.is_some_and(|request| state.send(request)) {
+                return;
+            }
+            let Some(job) = state.transcript.take() else {
+                return;
+            };
+            let local = state
+                .conversations
+                .scrollback(&state.conversation_of(&job.export.peer))
+                .unwrap_or_default();
+            job.finish(local)
+        };
+
+        let text = transcript.render(export.format, &Local);
+        let event = match tokio::fs::write(&export.path, text).await {
+            Ok(()) => ClientEvent::TranscriptExported {
+                peer: export.peer,
+                path: export.path,
+                messages: transcript.entries().len() as u64,
+            },
+            Err(e) => {
+                tracing::warn!("Could not write {}: {}", export.path.display(), e);
+                ClientEvent::TranscriptFailed {
+                    peer: export.peer,
+                    error: e.to_string(),
+                }
+            }
+        };
+        state.read().await.emit(event);
+    }
+
+    async fn receive_file(id: u64, state: &ArcRwLock<ClientState>) {
+        let (file, download_dir) = {
+            let mut state = state.write().await;
+            let Some(file) = state.files.take_incoming(id) else {
+                return;
+            };
+            (file, state.download_dir.clone())
+        };
+
+        let result = match file.verify() {
+            Ok(()) => file.save(&download_dir).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let event = match result {
+            Ok(path) => ClientEvent::FileReceived {
+                id,
+                sender: file.sender().to_string(),
+                path,
+            },
+            Err(e) => {
+                tracing::warn!("Could not receive {}: {}", file.filename(), e);
+                ClientEvent::FileRejected { id, reason: e }
+            }
+        };
+        state.read().await.emit(event);
+    }
+
+    async fn handle_receive<R: AsyncRead + Unpin>(
+        mut reader: R,
+        tx: mpsc::UnboundedSender<Message>,
+        tracer: Option<FrameTracer>,
+        (dc_tx, mut dc_rx): (mpsc::Sender<bool>, mpsc::Receiver<bool>),
+        shutdown: ShutdownToken,
+        state: ArcRwLock<ClientState>,
+    ) {
+        let mut faults = 0;
+        // a resync already consumed the next header start
+        let mut synced = false;
+        // switched by the server's confirmation, every frame before it has the old trailer
+        let mut integrity = Integrity::default();
+
+        loop {
+            if !std::mem::take(&mut synced) {
+                let valid = tokio::select! {
+                    // the sender gave up waiting for the Ack to our Disconnect
+                    _ = dc_rx.recv() => break,
+                    () = shutdown.cancelled() => break,
+                    valid = Message::read_frame_start(&mut reader) => valid,
+                };
+                match valid {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        let error = "Expected the start of a frame".to_string();
+                        synced = Self::recover(&mut reader, error, &mut faults, &shutdown, &state).await;
+                        if !synced {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        match e {
+                            FrameError::PeerClosed => tracing::debug!("The server closed the connection"),
+                            e => tracing::error!("Connection lost: {}", e),
+                        }
+                        dc_tx.try_send(true).ok();
+                        tx.send(Message::BREAK).ok();
+                        break;
+                    }
+                }
+            }
+
+            let message = Message::receive_using(&mut reader, VersionRange::default(), integrity).await;
+            match message {
+                Ok(message) => {
+                    faults = 0;
+                    tracing::debug!("Received message: {:?}", message.message_type());
+                    if let Some(tracer) = &tracer {
+                        tracer.record(Direction::Received, &message);
+                    }
+                    {
+                        let mut state = state.write().await;
+                        state.last_received = Instant::now();
+                        if !state.greeted {
+                            state.greeted = true;
+                            // servers from before login challenges send no nonce, the oldest no hello at all
+                            if message.is(MessageType::ServerHello) {
+                                state.nonce = message.payload().str_field(1).ok().map(str::to_string);
+                                let capabilities = message
+                                    .payload()
+                                    .str_field(0)
+                                    .ok()
+                                    .and_then(|capabilities| Capabilities::parse(capabilities).ok())
+                                    .unwrap_or_default();
+                                // first of all, everything after it already goes out with the new trailer
+                                let selected = state.integrity_preference.iter().copied().find(|integrity| {
+                                    integrity
+                                        .capability()
+                                        .map_or(true, |capability| capabilities.supports(capability))
+                                });
+                                if let Some(integrity) = selected.filter(|integrity| *integrity != Integrity::Crc32) {
+                                    state.send(Message::integrity_select(integrity));
+                                }
+                                // ahead of the login, so its answer is already in that language
+                                let languages = capabilities.supports(capability::LANGUAGES);
+                                if let Some(language) = state.language.clone().filter(|_| languages) {
+                                    state.send(Message::client_hello(&language));
+                                }
+                                if state.offline_sync.enabled() && capabilities.supports(capability::OFFLINE_SYNC) {
+                                    state.send(Message::sync_select());
+                                }
+                            }
+                            if std::mem::take(&mut state.login_pending) {
+                                state.send_login();
+                            }
+                        }
+                    }
+                    match message.message_type() {
+                        MessageType::Disconnect => {
+                            let (reason, detail) = message.disconnect_reason().unwrap_or_else(|e| {
+                                tracing::warn!("Invalid disconnect: {}", e);
+                                (DisconnectReason::ProtocolError, e)
+                            });
+                            let mut state = state.write().await;
+                            // coming back would only get us thrown out again
+                            if matches!(reason, DisconnectReason::Kicked | DisconnectReason::Banned) {
+                                state.closing = true;
+                            }
+                            state.advance(ConnectionInput::Drain);
+                            state.emit(ClientEvent::ServerDisconnected { reason, detail });
+                            if state.disconnect_sent {
+                                // both sides asked at once, ours is answered by theirs
+                                dc_tx.try_send(true).ok();
+                            }
+                            // the sender writes the Ack and closes our end
+                            tx.send(Message::ACK).ok();
+                            tx.send(Message::BREAK).ok();
+                            break;
+                        }
+                        MessageType::Ack if state.read().await.disconnect_sent => {
+                            dc_tx.try_send(true).ok();
+                            break;
+                        }
+                        MessageType::Ack => {
+                            let mut state = state.write().await;
+                            // older servers do not name the message, their answers come in order
+                            let resolved = match message.payload().str_field(1).ok().map(Uuid::parse_str) {
+                                Some(Ok(client_id)) => state.outbox.resolve(client_id),
+                                _ => state.outbox.resolve_in_flight(),
+                            };
+                            if let Some(entry) = resolved {
+                                let id = message.payload().u64_field(0).ok();
+                                if let Some(id) = id {
+                                    if state.sent_ids.len() >= RECENT_SENT_IDS {
+                                        state.sent_ids.pop_front();
+                                    }
+                                    state.sent_ids.push_back(id);
+                                }
+                                state.emit(ClientEvent::Delivered {
+                                    recipient: entry.recipient().to_string(),
+                                    id,
+                                    client_id: entry.id(),
+                                });
+                            }
+                        }
+                        MessageType::Nack => {
+                            let state = state.read().await;
+                            let command = message.rejected_type().and_then(command_for);
+                            let reason = match (command, message.rejection_reason()) {
+                                (Some(_), Some(reason)) => reason.to_string(),
+                                (Some(command), None) => state
+                                    .access_problem(command)
+                                    .unwrap_or_else(|| "your account is not allowed to do that".to_string()),
+                                (None, _) => "Request rejected by the server".to_string(),
+                            };
+                            state.emit(ClientEvent::Rejected {
+                                command: command.map(str::to_string),
+                                reason,
+                            });
+                        }
+                        MessageType::Heartbeat => {
+                            let reply = state.write().await.receive_heartbeat(message.heartbeat_echo());
+                            if let Some(reply) = reply {
+                                tx.send(reply).ok();
+                            }
+                        }
+                        MessageType::AuthSuccess => {
+                            let mut state = state.write().await;
+                            state.advance(ConnectionInput::Authenticated);
+                            state.user_id = message.payload().str_field(0).ok().map(str::to_string);
+                            // older servers do not send the level, the client then leaves the checks to them
+                            state.access_level = message
+                                .payload()
+                                .str_field(1)
+                                .ok()
+                                .and_then(|level| AccessLevel::parse(level).ok());
+                            state.emit(ClientEvent::Authenticated);
+                            // before the queued messages, their recipients may ask for it as soon as they arrive
+                            if let Some(identity) = &state.signing {
+                                if state.server_supports(capability::MESSAGE_SIGNING) {
+                                    state.send(Message::signing_key_set(Some(&identity.public_key())));
+                                }
+                            }
+                            state.flush_outbox();
+                            // a restarted server counts from zero again
+                            state.preferences_version = 0;
+                            // another device may have changed them since the last session
+                            if state
+                                .capabilities
+                                .as_ref()
+                                .is_some_and(|capabilities| capabilities.supports(capability::PREFERENCES))
+                            {
+                                state.send(Message::get_preferences());
+                            }
+                            if state
+                                .capabilities
+                                .as_ref()
+                                .is_some_and(|capabilities| capabilities.supports(capability::MUTING))
+                            {
+                                state.send(Message::mute_list());
+                            }
+                            // subscriptions end with the session that made them
+                            if state
+                                .capabilities
+                                .as_ref()
+                                .is_some_and(|capabilities| capabilities.supports(capability::PRESENCE_SUBSCRIPTIONS))
+                            {
+                                let scopes: Vec<PresenceScope> = state.rosters.keys().cloned().collect();
+                                for scope in scopes {
+                                    state.rosters.insert(scope.clone(), None);
+                                    state.send(Message::subscribe_presence(&scope));
+                                }
+                            }
+                        }
+                        MessageType::AuthFailure => {
+                            let error = message.payload().str_field(0).unwrap_or("Unknown error");
+                            let handle = Arc::clone(&state);
+                            let mut state = state.write().await;
+                            // the account has no login key yet, one password login creates it
+                            if error == PLAIN_LOGIN_REQUIRED {
+                                if let Some((username, password)) = state.credentials.clone() {
+                                    state.send_auth(Message::auth(&username, &password));
+                                    continue;
+                                }
+                            }
+                            // nothing was checked, a login still in flight goes out again once the queue had time to drain
+                            if let Some(retry_after) = message.auth_retry_after() {
+                                if state.connection == ConnectionState::Authenticating {
+                                    if let Some(request) = state.auth_request.clone() {
+                                        tokio::spawn(Self::retry_auth(request, retry_after, handle).in_current_span());
+                                    }
+                                }
+                                state.emit(ClientEvent::ServerBusy {
+                                    retry_after,
+                                    reason: error.to_string(),
+                                });
+                                continue;
+                            }
+                            state.pending_password = None;
+                            state.advance(ConnectionInput::AuthFailed);
+                            state.emit(ClientEvent::AuthFailed(error.to_string()));
+                        }
+                        MessageType::IntegritySelected => match message.switches_integrity() {
+                            Some(selected) => {
+                                integrity = selected;
+                                state.write().await.integrity = selected;
+                                tracing::debug!("Frames are checked with {} from now on", selected);
+                            }
+                            None => tracing::warn!("Invalid integrity confirmation"),
+                        },
+                        MessageType::ServerHello => {
+                            match Capabilities::parse(message.payload().str_field(0).unwrap_or("")) {
+                                Ok(capabilities) => {
+                                    let mut state = state.write().await;
+                                    state.emit(ClientEvent::ServerCapabilities(
+                                        capabilities.iter().map(str::to_string).collect(),
+                                    ));
+                                    state.capabilities = Some(capabilities);
+                                    let payload = message.payload();
+                                    if let (Ok(name), Ok(id)) = (payload.str_field(2), payload.str_field(3)) {
+                                        state.server_name = Some(name.to_string());
+                                        state.emit(ClientEvent::ServerIdentity {
+                                            name: name.to_string(),
+                                            id: id.to_string(),
+                                        });
+                                    }
+                                }
+                                Err(e) => tracing::warn!("Invalid server hello: {}", e),
+                            }
+                        }
+                        MessageType::TimeSyncReply => {
+                            let payload = message.payload();
+                            let timestamps = (0..3)
+                                .map(|index| payload.timestamp_field(index))
+                                .collect::<Result<Vec<_>, _>>();
+                            match timestamps.as_deref() {
+                                Ok(&[client_sent, server_received, server_sent]) => {
+                                    let mut state = state.write().await;
+                                    let sample = TimeSample::from_exchange(
+                                        client_sent,
+                                        server_received,
+                                        server_sent,
+                                        state.clock.now(),
+                                    );
+                                    state.time_sample = Some(sample);
+                                    state.emit(ClientEvent::TimeSynced {
+                                        offset_ms: sample.offset().num_milliseconds(),
+                                        round_trip_ms: sample.round_trip().num_milliseconds(),
+                                    });
+                                    let round_trip = sample.round_trip().to_std().unwrap_or_default();
+                                    state.sample_quality(QualitySample::RoundTrip(round_trip));
+                                }
+                                Ok(_) => {}
+                                Err(e) => tracing::warn!("Invalid time sync reply: {}", e),
+                            }
+                        }
+                        MessageType::ReauthRequired => {
+                            let reason = message.payload().str_field(0).unwrap_or("Session expired");
+                            let mut state = state.write().await;
+                            state.advance(ConnectionInput::ReauthRequired);
+                            state.access_level = Some(AccessLevel::Guest);
+                            state.emit(ClientEvent::ReauthRequired(reason.to_string()));
+                            if state.credentials.is_some() {
+                                state.send_login();
+                            }
+                        }
+                        MessageType::SecurityNotice => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(kind), Ok(detail)) => {
+                                    let mut state = state.write().await;
+                                    // reconnecting would just take the session back from the new login
+                                    if kind == NOTICE_SESSION_TAKEOVER || kind == NOTICE_KICKED {
+                                        state.closing = true;
+                                    }
+                                    state.emit(ClientEvent::SecurityNotice {
+                                        kind: kind.to_string(),
+                                        detail: detail.to_string(),
+                                    });
+                                }
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid security notice: {}", e),
+                            }
+                        }
+                        MessageType::ServerBusy => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.str_field(1)) {
+                                (Ok(retry_after), Ok(reason)) => {
+                                    let mut state = state.write().await;
+                                    state.retry_after = Some(retry_after);
+                                    state.emit(ClientEvent::ServerBusy {
+                                        retry_after,
+                                        reason: reason.to_string(),
+                                    });
+                                }
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid server busy notice: {}", e),
+                            }
+                        }
+                        MessageType::StateExported => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.u64_field(1)) {
+                                (Ok(path), Ok(users)) => state.read().await.emit(ClientEvent::StateExported {
+                                    path: path.to_string(),
+                                    users,
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid export confirmation: {}", e),
+                            }
+                        }
+                        MessageType::ServerStats => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.u64_field(1), payload.u64_field(2)) {
+                                (Ok(mode), Ok(sessions), Ok(users)) => {
+                                    state.read().await.emit(ClientEvent::ServerStats {
+                                        mode: mode.to_string(),
+                                        sessions,
+                                        users,
+                                        // older servers do not count them
+                                        missed_heartbeats: payload.u64_field(3).unwrap_or(0),
+                                        stalled_writes: payload.u64_field(4).unwrap_or(0),
+                                        flood_warnings: payload.u64_field(5).unwrap_or(0),
+                                        flood_disconnects: payload.u64_field(6).unwrap_or(0),
+                                        queued_messages: payload.u64_field(7).unwrap_or(0),
+                                        expired_messages: payload.u64_field(8).unwrap_or(0),
+                                        server_name: payload.str_field(9).unwrap_or_default().to_string(),
+                                        server_id: payload.str_field(10).unwrap_or_default().to_string(),
+                                        closed_by_peer: payload.u64_field(11).unwrap_or(0),
+                                        broken_connections: payload.u64_field(12).unwrap_or(0),
+                                        presence_coalesced: payload.u64_field(13).unwrap_or(0),
+                                        presence_dropped: payload.u64_field(14).unwrap_or(0),
+                                        accept_errors: payload.u64_field(15).unwrap_or(0),
+                                        integrity_failures: payload.u64_field(16).unwrap_or(0),
+                                        webhooks_delivered: payload.u64_field(17).unwrap_or(0),
+                                        webhooks_failed: payload.u64_field(18).unwrap_or(0),
+                                        auth_queue_depth: payload.u64_field(19).unwrap_or(0),
+                                        auth_rejected: payload.u64_field(20).unwrap_or(0),
+                                        journal_dropped: payload.u64_field(21).unwrap_or(0),
+                                        journal_failing: payload.u64_field(22).unwrap_or(0) != 0,
+                                    })
+                                }
+                                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                                    tracing::warn!("Invalid server stats: {}", e)
+                                }
+                            }
+                        }
+                        MessageType::Motd => match message.payload().str_field(0) {
+                            Ok(text) => state.read().await.emit(ClientEvent::Motd(text.to_string())),
+                            Err(e) => tracing::warn!("Invalid message of the day: {}", e),
+                        },
+                        MessageType::SigningKey => match message.signing_key_of() {
+                            Ok((username, key)) => state.write().await.receive_signing_key(&username, key),
+                            Err(e) => tracing::warn!("Invalid signing key: {}", e),
+                        },
+                        MessageType::UserInfo => match Self::user_info(&message) {
+                            Ok(event) => state.read().await.emit(event),
+                            Err(e) => tracing::warn!("Invalid user info: {}", e),
+                        },
+                        MessageType::SessionInfo => match message.session_info_entry() {
+                            Ok(info) => state.read().await.emit(ClientEvent::SessionInfo(info)),
+                            Err(e) => tracing::warn!("Invalid session info: {}", e),
+                        },
+                        MessageType::SessionListEnd => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.u64_field(1)) {
+                                (Ok(count), Ok(kicked)) => state.read().await.emit(ClientEvent::SessionListEnd {
+                                    count,
+                                    kicked: kicked != 0,
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid end of session list: {}", e),
+                            }
+                        }
+                        MessageType::IpBanList => match message.ip_bans() {
+                            Ok(list) => state.read().await.emit(ClientEvent::IpBans(list)),
+                            Err(e) => tracing::warn!("Invalid ban list: {}", e),
+                        },
+                        MessageType::ConsistencyReport => match message.consistency_result() {
+                            Ok(report) => state.read().await.emit(ClientEvent::ConsistencyReport(report)),
+                            Err(e) => tracing::warn!("Invalid consistency report: {}", e),
+                        },
+                        MessageType::AccessLevelChanged => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(username), Ok(level)) => {
+                                    let mut state = state.write().await;
+                                    let own = state.credentials.as_ref().is_some_and(|(name, _)| name == username);
+                                    if own {
+                                        state.access_level = AccessLevel::parse(level).ok();
+                                    }
+                                    state.emit(ClientEvent::AccessLevelChanged {
+                                        username: username.to_string(),
+                                        level: level.to_string(),
+                                    })
+                                }
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid access level change: {}", e),
+                            }
+                        }
+                        MessageType::MissedNotice => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1), payload.timestamp_field(2)) {
+                                (Ok(kind), Ok(detail), Ok(occurred_at)) => {
+                                    state.read().await.emit(ClientEvent::MissedNotice {
+                                        kind: kind.to_string(),
+                                        detail: detail.to_string(),
+                                        occurred_at,
+                                    })
+                                }
+                                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                                    tracing::warn!("Invalid missed notice: {}", e)
+                                }
+                            }
+                        }
+                        MessageType::UserKicked => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(username), Ok(reason)) => state.read().await.emit(ClientEvent::UserKicked {
+                                    username: username.to_string(),
+                                    reason: reason.to_string(),
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid kick confirmation: {}", e),
+                            }
+                        }
+                        MessageType::PasswordChanged => match message.payload().str_field(0) {
+                            Ok(username) => {
+                                let mut state = state.write().await;
+                                let own = state.credentials.as_ref().is_some_and(|(name, _)| name == username);
+                                if own {
+                                    if let Some(password) = state.pending_password.take() {
+                                        state.credentials = Some((username.to_string(), password));
+                                    }
+                                }
+                                state.emit(ClientEvent::PasswordChanged(username.to_string()));
+                            }
+                            Err(e) => tracing::warn!("Invalid password change confirmation: {}", e),
+                        },
+                        MessageType::UserRenamed => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(old), Ok(new)) => {
+                                    let mut state = state.write().await;
+                                    // reconnects have to log in under the new name
+                                    if let Some((username, _)) =
+                                        state.credentials.as_mut().filter(|(username, _)| username == old)
+                                    {
+                                        *username = new.to_string();
+                                    }
+                                    if let Some(count) = state.unread.remove(old) {
+                                        *state.unread.entry(new.to_string()).or_default() += count;
+                                    }
+                                    state.conversations.rename(old, new);
+                                    state.emit(ClientEvent::UserRenamed {
+                                        old: old.to_string(),
+                                        new: new.to_string(),
+                                    });
+                                }
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid rename notice: {}", e),
+                            }
+                        }
+                        MessageType::PresenceUpdate => match message.presence_changes() {
+                            Ok((online, offline)) => {
+                                state.read().await.emit(ClientEvent::PresenceUpdate { online, offline })
+                            }
+                            Err(e) => tracing::warn!("Invalid presence update: {}", e),
+                        },
+                        MessageType::PresenceSnapshot => match message.roster_snapshot() {
+                            Ok(snapshot) => {
+                                let mut state = state.write().await;
+                                if let Some(roster) = state.rosters.get_mut(&snapshot.scope) {
+                                    *roster = Some(Roster::from_snapshot(&snapshot));
+                                    state.emit(ClientEvent::PresenceSnapshot(snapshot));
+                                }
+                            }
+                            Err(e) => tracing::warn!("Invalid presence snapshot: {}", e),
+                        },
+                        MessageType::PresenceDelta => match message.roster_delta() {
+                            Ok(delta) => {
+                                let mut state = state.write().await;
+                                let applied = match state.rosters.get_mut(&delta.scope) {
+                                    Some(Some(roster)) => Some(roster.apply(&delta)),
+                                    // not followed, or a new snapshot is on its way
+                                    _ => None,
+                                };
+                                match applied {
+                                    Some(true) => state.emit(ClientEvent::PresenceDelta(delta)),
+                                    Some(false) => {
+                                        tracing::debug!(
+                                            "Missed presence changes of {} before version {}, asking for a snapshot",
+                                            delta.scope.encode(),
+                                            delta.version
+                                        );
+                                        state.rosters.insert(delta.scope.clone(), None);
+                                        state.send(Message::subscribe_presence(&delta.scope));
+                                    }
+                                    None => {}
+                                }
+                            }
+                            Err(e) => tracing::warn!("Invalid presence delta: {}", e),
+                        },
+                        MessageType::ServerShutdownWarning => match message.payload().u64_field(0) {
+                            Ok(timeout) => state.read().await.emit(ClientEvent::ServerShutdownWarning(timeout)),
+                            Err(e) => tracing::warn!("Invalid shutdown warning: {}", e),
+                        },
+                        MessageType::AdminLogLevelChanged => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(previous), Ok(current)) => state.read().await.emit(ClientEvent::LogLevelChanged {
+                                    previous: previous.to_string(),
+                                    current: current.to_string(),
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid log level change: {}", e),
+                            }
+                        }
+                        MessageType::MessageError => {
+                            let error = message.payload().str_field(0).unwrap_or("Unknown error");
+                            let mut state = state.write().await;
+                            let recipient = state
+                                .outbox
+                                .resolve_in_flight()
+                                .map(|entry| entry.recipient().to_string());
+                            state.emit(ClientEvent::DeliveryFailed {
+                                recipient,
+                                error: error.to_string(),
+                                code: message.error_code(),
+                            });
+                        }
+                        MessageType::DirectMessageReceive => {
+                            let payload = message.payload();
+                            match (payload.str_field(0), payload.str_field(1)) {
+                                (Ok(sender), Ok(body)) => {
+                                    let id = payload.u64_field(3).ok();
+                                    let expires_at = payload.timestamp_field(4).ok();
+                                    if let (Some(id), Some(expires_at)) = (id, expires_at) {
+                                        tokio::spawn(
+                                            Self::expire_message(
+                                                id,
+                                                sender.to_string(),
+                                                expires_at,
+                                                Arc::clone(&state),
+                                            )
+                                            .in_current_span(),
+                                        );
+                                    }
+                                    let mut state = state.write().await;
+                                    let line = ScrollbackLine {
+                                        sender: sender.to_string(),
+                                        body: body.to_string(),
+                                        sent_at: Self::server_timestamp(&message, 2),
+                                        id,
+                                    };
+                                    let conversation = match message.is_self_note() {
+                                        true => NOTES.to_string(),
+                                        false => state.conversation_of(sender),
+                                    };
+                                    // like the title, a muted sender's messages are kept but not counted
+                                    state.conversations.record(&conversation, line, !message.is_muted());
+                                    // notes are the only messages not addressed to us
+                                    let recipient = match message.is_self_note() {
+                                        true => sender.to_string(),
+                                        false => state.username().unwrap_or_default(),
+                                    };
+                                    let verification = state.verification_of(id, sender, &recipient, body, &message);
+                                    state.emit(ClientEvent::DirectMessage {
+                                        sender: sender.to_string(),
+                                        body: body.to_string(),
+                                        sent_at: Self::server_timestamp(&message, 2),
+                                        id,
+                                        expires_at,
+                                        self_note: message.is_self_note(),
+                                        muted: message.is_muted(),
+                                        verification,
+                                    })
+                                }
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid direct message: {}", e),
+                            }
+                        }
+                        MessageType::MessageEdited => {
+                            let payload = message.payload();
+                            match (
+                                payload.u64_field(0),
+                                payload.str_field(1),
+                                payload.str_field(2),
+                                payload.timestamp_field(3),
+                            ) {
+                                (Ok(id), Ok(sender), Ok(body), Ok(edited_at)) => {
+                                    state.read().await.emit(ClientEvent::MessageEdited {
+                                        id,
+                                        sender: sender.to_string(),
+                                        body: body.to_string(),
+                                        edited_at,
+                                    })
+                                }
+                                (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+                                    tracing::warn!("Invalid message edit: {}", e)
+                                }
+                            }
+                        }
+                        MessageType::MessageDeleted => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.str_field(1)) {
+                                (Ok(id), Ok(sender)) => state.read().await.emit(ClientEvent::MessageDeleted {
+                                    id,
+                                    sender: sender.to_string(),
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid message deletion: {}", e),
+                            }
+                        }
+                        MessageType::HistoryEntry => match Self::history_entry(&message, &state).await {
+                            Ok(event) => Self::handle_history_entry(event, &state).await,
+                            Err(e) => tracing::warn!("Invalid history entry: {}", e),
+                        },
+                        MessageType::FileOffered => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.str_field(1), payload.str_field(2)) {
+                                (Ok(id), Ok(recipient), Ok(filename)) => {
+                                    let mut state = state.write().await;
+                                    if state.files.confirm_outgoing(id, recipient, filename) {
+                                        state.emit(ClientEvent::FileOffered {
+                                            id,
+                                            recipient: recipient.to_string(),
+                                            filename: filename.to_string(),
+                                        });
+                                    }
+                                }
+                                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                                    tracing::warn!("Invalid file offer confirmation: {}", e)
+                                }
+                            }
+                        }
+                        MessageType::FileOffer => Self::handle_file_offer(&message, &state).await,
+                        MessageType::FileAccept => match message.payload().u64_field(0) {
+                            Ok(id) => Self::send_file_chunks(id, &state).await,
+                            Err(e) => tracing::warn!("Invalid file accept: {}", e),
+                        },
+                        MessageType::FileChunk => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.u64_field(1), payload.field(2)) {
+                                (Ok(id), Ok(index), Ok(bytes)) => {
+                                    let mut state = state.write().await;
+                                    if let Err(e) = state.files.add_chunk(id, index, bytes) {
+                                        state.files.remove(id);
+                                        state.send(Message::file_reject(id, &e));
+                                        state.emit(ClientEvent::FileRejected { id, reason: e });
+                                    }
+                                }
+                                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                                    tracing::warn!("Invalid file chunk: {}", e)
+                                }
+                            }
+                        }
+                        MessageType::FileComplete => match message.payload().u64_field(0) {
+                            Ok(id) => Self::receive_file(id, &state).await,
+                            Err(e) => tracing::warn!("Invalid file completion: {}", e),
+                        },
+                        MessageType::FileReject => {
+                            let payload = message.payload();
+                            match payload.u64_field(0) {
+                                Ok(id) => {
+                                    let mut state = state.write().await;
+                                    if state.files.remove(id) {
+                                        state.emit(ClientEvent::FileRejected {
+                                            id,
+                                            reason: payload.str_field(1).unwrap_or("Rejected").to_string(),
+                                        });
+                                    }
+                                }
+                                Err(e) => tracing::warn!("Invalid file rejection: {}", e),
+                            }
+                        }
+                        MessageType::HistoryEnd => match message.payload().u64_field(0) {
+                            Ok(count) => Self::handle_history_end(count, &state).await,
+                            Err(e) => tracing::warn!("Invalid history end: {}", e),
+                        },
+                        MessageType::SearchResult => match Self::search_result(&message) {
+                            Ok(event) => state.read().await.emit(event),
+                            Err(e) => tracing::warn!("Invalid search result: {}", e),
+                        },
+                        MessageType::MessageExpired => {
+                            let payload = message.payload();
+                            match (payload.u64_field(0), payload.str_field(1)) {
+                                (Ok(id), Ok(recipient)) => state.read().await.emit(ClientEvent::DeliveryExpired {
+                                    id,
+                                    recipient: recipient.to_string(),
+                                }),
+                                (Err(e), _) | (_, Err(e)) => tracing::warn!("Invalid expiry notice: {}", e),
+                            }
+                        }
+                        MessageType::SearchEnd => match message.payload().u64_field(0) {
+                            Ok(count) => state.read().await.emit(ClientEvent::SearchEnd {
+                                count,
+                                error: message.payload().str_field(1).ok().map(str::to_string),
+                            }),
+                            Err(e) => tracing::warn!("Invalid search end: {}", e),
+                        },
+                        MessageType::UnreadSummary => match message.unread_counts() {
+                            Ok(counts) => {
+                                let mut state = state.write().await;
+                                state.unread = counts.iter().cloned().collect();
+                                state.emit(ClientEvent::UnreadSummary(counts));
+                            }
+                            Err(e) => tracing::warn!("Invalid unread summary: {}", e),
+                        },
+                        MessageType::Preferences | MessageType::PreferencesChanged => {
+                            match message.preference_entries() {
+                                Ok(entries) => {
+                                    let mut state = state.write().await;
+                                    let version = message.preferences_version().unwrap_or_default();
+                                    // an answer that was overtaken by the change of another session
+                                    if version < state.preferences_version {
+                                        continue;
+                                    }
+                                    state.preferences_version = version;
+                                    state.preferences = entries.iter().cloned().collect();
+                                    match message.message_type() {
+                                        MessageType::Preferences => state.emit(ClientEvent::Preferences(entries)),
+                                        _ => state.emit(ClientEvent::PreferencesChanged(entries)),
+                                    }
+                                }
+                                Err(e) => tracing::warn!("Invalid preferences: {}", e),
+                            }
+                        }
+                        MessageType::MutedSenders => match message.muted_sender_names() {
+                            Ok(senders) => {
+                                let mut state = state.write().await;
+                                state.muted = senders.iter().cloned().collect();
+                                state.emit(ClientEvent::MutedSenders(senders));
+                            }
+                            Err(e) => tracing::warn!("Invalid muted senders: {}", e),
+                        },
+                        MessageType::ScheduledMessages => match message.scheduled_entries() {
+                            Ok(entries) => state.read().await.emit(ClientEvent::ScheduledMessages(entries)),
+                            Err(e) => tracing::warn!("Invalid scheduled messages: {}", e),
+                        },
+                        MessageType::ScheduleFired => {
+                            match message.schedule_outcome() {
+                                Ok((id, recipient, error)) => state
+                                    .read()
+                                    .await
+                                    .emit(ClientEvent::ScheduledMessageSent { id, recipient, error }),
+                                Err(e) => tracing::warn!("Invalid scheduled message outcome: {}", e),
+                            }
+                        }
+                        MessageType::ApiTokens => match message.api_token_entries() {
+                            Ok(tokens) => state.read().await.emit(ClientEvent::ApiTokens(tokens)),
+                            Err(e) => tracing::warn!("Invalid API tokens: {}", e),
+                        },
+                        MessageType::ApiTokenCreated => match message.created_api_token() {
+                            Ok((info, token)) => state.read().await.emit(ClientEvent::ApiTokenCreated { info, token }),
+                            Err(e) => tracing::warn!("Invalid API token: {}", e),
+                        },
+                        MessageType::OfflineSummary => match message.offline_counts() {
+                            Ok(summary) => {
+                                let mut state = state.write().await;
+                                let user_id = state.user_id.clone();
+                                let request = state.offline_sync.start(user_id.as_deref(), &summary);
+                                state.emit(ClientEvent::SyncStarted {
+                                    total: summary.total,
+                                    peers: summary.peers,
+                                });
+                                state.send(request);
+                            }
+                            Err(e) => tracing::warn!("Invalid offline summary: {}", e),
+                        },
+                        MessageType::SyncBatch => match message.sync_sequences() {
+                            Ok(batch) => {
+                                let mut state = state.write().await;
+                                if let Some(delivered) = state.offline_sync.announce(batch) {
+                                    state.emit(ClientEvent::SyncFinished { delivered });
+                                }
+                            }
+                            Err(e) => tracing::warn!("Invalid sync batch: {}", e),
+                        },
+                        _ => {}
+                    }
+                    // counted once handled, so the progress comes after the message that completed a batch
+                    if matches!(
+                        message.message_type(),
+                        MessageType::DirectMessageReceive | MessageType::MessageEdited | MessageType::MessageDeleted
+                    ) {
+                        let mut state = state.write().await;
+                        if let Some(step) = state.offline_sync.received() {
+                            state.emit(ClientEvent::SyncProgress {
+                                delivered: step.delivered,
+                                remaining: step.remaining,
+                            });
+                            state.send(step.request);
+                        }
+                    }
+                }
+                // a server this far ahead or behind cannot be talked to, it is told why in a frame it can read
+                Err(FrameError::UnsupportedVersion { version, supported }) => {
+                    tracing::error!(
+                        "The server speaks protocol version {}, this client {}, update whichever is older",
+                        version,
+                        supported
+                    );
+                    tx.send(supported.unsupported_notice(version)).ok();
+                    tx.send(Message::BREAK).ok();
+                    break;
+                }
+                Err(FrameError::Closed(e)) => {
+                    tracing::error!("Connection lost partway through a frame: {}", e);
+                    dc_tx.try_send(true).ok();
+                    tx.send(Message::BREAK).ok();
+                    break;
+                }
+                Err(e) => {
+                    synced = Self::recover(&mut reader, e.to_string(), &mut faults, &shutdown, &state).await;
+                    if !synced {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // a bad frame is skipped up to the next header, too many in a row or none in reach and the connection
+    // is torn down for the reconnect logic to take over
+    async fn recover<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        error: String,
+        faults: &mut u32,
+        shutdown: &ShutdownToken,
+        state: &ArcRwLock<ClientState>,
+    ) -> bool {
+        *faults += 1;
+        let recovered = *faults < MAX_PROTOCOL_FAULTS
+            && tokio::select! {
+                () = shutdown.cancelled() => false,
+                found = Message::resync(reader, MAX_RESYNC_BYTES) => matches!(found, Ok(true)),
+            };
+
+        if recovered {
+            tracing::warn!("Skipped a frame the server sent: {}", error);
+        } else {
+            tracing::error!("Giving up on the connection: {}", error);
+            shutdown.cancel();
+        }
+        state.read().await.emit(ClientEvent::ProtocolFault { error, recovered });
+        recovered
+    }
+}
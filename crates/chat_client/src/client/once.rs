@@ -0,0 +1,291 @@
+use std::{fmt, time::Duration};
+
+use chat_core::{
+    normalize::{normalize_body, normalize_username},
+    protocol::Message,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{parse_request, ChatClient, ClientEvent, SendStatus, DEFAULT_SEARCH_LIMIT};
+
+// long enough for a login hash on a busy server
+pub const ONCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// why a single command did not go through, each has its own exit code so scripts can tell them apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnceError {
+    // the server refused the command or the message it carried
+    Failed(String),
+    Usage(String),
+    Auth(String),
+    Connection(String),
+    Timeout(Duration),
+}
+
+// what a command line turns into before anything is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request {
+    Direct { recipient: String, body: String },
+    Note(String),
+    Edit { id: u64, body: String },
+    Delete(u64),
+    Search(String),
+    Read(String),
+    Preference { key: String, value: String },
+    Mute { sender: String, muted: bool },
+    Server(Message),
+}
+
+impl OnceError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Failed(_) => 1,
+            Self::Usage(_) => 2,
+            Self::Auth(_) => 3,
+            Self::Connection(_) => 4,
+            Self::Timeout(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for OnceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed(e) | Self::Usage(e) => f.write_str(e),
+            Self::Auth(e) => write!(f, "Authentication failed: {}", e),
+            Self::Connection(e) => write!(f, "Connection failed: {}", e),
+            Self::Timeout(timeout) => write!(f, "No answer within {} seconds", timeout.as_secs()),
+        }
+    }
+}
+
+impl std::error::Error for OnceError {}
+
+// logs in, sends the command, waits for the answer that belongs to it and disconnects,
+// the events that answered the command are returned for printing
+pub async fn run_once(
+    client: &ChatClient,
+    events: &mut mpsc::UnboundedReceiver<ClientEvent>,
+    (username, password): (&str, &str),
+    line: &str,
+    timeout: Duration,
+) -> Result<Vec<ClientEvent>, OnceError> {
+    let line = line.trim();
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    // a typo fails before anything goes over the wire
+    let request = parse(command, args)?;
+    let expected = request.clone();
+
+    let outcome = tokio::time::timeout(timeout, async {
+        client.login(username, password).await;
+        authenticated(events).await?;
+        client
+            .check_command(command)
+            .await
+            .map_err(|e| OnceError::Failed(e.to_string()))?;
+        let sent = send(client, request).await?;
+        answer(command, &expected, sent, events).await
+    })
+    .await
+    .unwrap_or(Err(OnceError::Timeout(timeout)));
+
+    // the outcome is known either way, a slow goodbye does not change it
+    client.disconnect().await;
+    tokio::time::timeout(timeout, async {
+        while let Some(event) = events.recv().await {
+            if matches!(event, ClientEvent::Closed) {
+                break;
+            }
+        }
+    })
+    .await
+    .ok();
+
+    outcome
+}
+
+fn parse(command: &str, args: &str) -> Result<Request, OnceError> {
+    let usage = |usage: &str| OnceError::Usage(format!("Usage: {}", usage));
+    let args = args.trim();
+
+    let request = match command {
+        "msg" | "send" => match args.split_once(' ') {
+            Some((recipient, body)) if !body.trim().is_empty() => Request::Direct {
+                recipient: normalize_username(recipient).map_err(OnceError::Usage)?,
+                body: normalize_body(body).map_err(OnceError::Usage)?,
+            },
+            _ => return Err(usage("msg <user> <text>")),
+        },
+        "note" if args.is_empty() => return Err(usage("note <text>")),
+        "note" => Request::Note(normalize_body(args).map_err(OnceError::Usage)?),
+        "edit" => match args.split_once(' ').map(|(id, body)| (id.parse(), body.trim())) {
+            Some((Ok(id), body)) if !body.is_empty() => Request::Edit {
+                id,
+                body: normalize_body(body).map_err(OnceError::Usage)?,
+            },
+            _ => return Err(usage("edit <id> <text>")),
+        },
+        "delete" => match args.parse() {
+            Ok(id) => Request::Delete(id),
+            Err(_) => return Err(usage("delete <id>")),
+        },
+        "search" if args.is_empty() => return Err(usage("search <text>")),
+        "search" => Request::Search(args.to_string()),
+        "read" => match args.split_whitespace().next() {
+            Some(peer) => Request::Read(normalize_username(peer).map_err(OnceError::Usage)?),
+            None => return Err(usage("read <user>")),
+        },
+        "pref" => match args.split_once(' ') {
+            Some((key, value)) if !value.trim().is_empty() => Request::Preference {
+                key: key.to_string(),
+                value: value.trim().to_string(),
+            },
+            _ => return Err(usage("pref <key> <value>")),
+        },
+        "mute" | "unmute" => match args.split_whitespace().next() {
+            Some(sender) => Request::Mute {
+                sender: normalize_username(sender).map_err(OnceError::Usage)?,
+                muted: command == "mute",
+            },
+            None => return Err(usage(&format!("{} <user>", command))),
+        },
+        "mutes" => Request::Server(Message::mute_list()),
+        // the server answers the debug log request in its own log only
+        "log" => return Err(OnceError::Usage("'log' has no answer to wait for".to_string())),
+        _ => match parse_request(command, args) {
+            Some(Ok(message)) => Request::Server(message),
+            Some(Err(e)) => return Err(OnceError::Usage(e)),
+            None => return Err(OnceError::Usage(format!("'{}' cannot be run once", command))),
+        },
+    };
+    Ok(request)
+}
+
+async fn authenticated(events: &mut mpsc::UnboundedReceiver<ClientEvent>) -> Result<(), OnceError> {
+    while let Some(event) = events.recv().await {
+        match event {
+            ClientEvent::Authenticated => return Ok(()),
+            ClientEvent::AuthFailed(e) => return Err(OnceError::Auth(e)),
+            ClientEvent::ServerBusy { reason, .. } => return Err(OnceError::Connection(reason)),
+            ClientEvent::ServerDisconnected { detail, .. } => return Err(OnceError::Connection(detail)),
+            ClientEvent::Disconnected | ClientEvent::Closed => {
+                return Err(OnceError::Connection("the server closed the connection".to_string()))
+            }
+            _ => {}
+        }
+    }
+    Err(OnceError::Connection("the client stopped".to_string()))
+}
+
+// the client id of a direct message, its delivery is the answer
+async fn send(client: &ChatClient, request: Request) -> Result<Option<Uuid>, OnceError> {
+    let sent = match request {
+        Request::Direct { recipient, body } => return send_direct(client, &recipient, &body).await,
+        Request::Note(body) => match client.username().await {
+            Some(username) => return send_direct(client, &username, &body).await,
+            None => false,
+        },
+        Request::Edit { id, body } => {
+            return client
+                .edit_message(id, &body)
+                .await
+                .map(|_| None)
+                .map_err(OnceError::Failed)
+        }
+        Request::Delete(id) => return client.delete_message(id).await.map(|_| None).map_err(OnceError::Failed),
+        Request::Search(query) => client.search(&query, None, DEFAULT_SEARCH_LIMIT).await,
+        Request::Read(peer) => client.mark_read(&peer).await,
+        Request::Preference { key, value } => client.set_preference(&key, &value).await,
+        Request::Mute { sender, muted: true } => client.mute(&sender).await,
+        Request::Mute { sender, muted: false } => client.unmute(&sender).await,
+        Request::Server(message) => client.send(message).await,
+    };
+    if sent {
+        Ok(None)
+    } else {
+        Err(OnceError::Connection("the command could not be sent".to_string()))
+    }
+}
+
+async fn send_direct(client: &ChatClient, recipient: &str, body: &str) -> Result<Option<Uuid>, OnceError> {
+    match client.send_direct_message_with_id(recipient, body).await {
+        (SendStatus::Sent, client_id) => Ok(client_id),
+        _ => Err(OnceError::Connection("the command could not be sent".to_string())),
+    }
+}
+
+// the server does not number requests, the first event of the kind the command asks for is its answer
+async fn answer(
+    command: &str,
+    request: &Request,
+    sent: Option<Uuid>,
+    events: &mut mpsc::UnboundedReceiver<ClientEvent>,
+) -> Result<Vec<ClientEvent>, OnceError> {
+    let mut answered = Vec::new();
+    while let Some(event) = events.recv().await {
+        match (command, &event) {
+            (_, ClientEvent::Rejected { reason, .. }) => return Err(OnceError::Failed(reason.clone())),
+            (_, ClientEvent::DeliveryFailed { error, .. }) => return Err(OnceError::Failed(error.clone())),
+            (_, ClientEvent::ServerDisconnected { detail, .. }) => return Err(OnceError::Connection(detail.clone())),
+            (_, ClientEvent::Disconnected | ClientEvent::Closed) => {
+                return Err(OnceError::Connection("the server closed the connection".to_string()))
+            }
+            (_, ClientEvent::SearchEnd { error: Some(error), .. }) => return Err(OnceError::Failed(error.clone())),
+            // results come one by one ahead of the event that ends them
+            ("history", ClientEvent::HistoryEntry { .. })
+            | ("search", ClientEvent::SearchResult { .. })
+            | ("sessions" | "kickwhere", ClientEvent::SessionInfo(_)) => {
+                answered.push(event);
+                continue;
+            }
+            _ => {}
+        }
+
+        // the login brings its own unread counts and preferences, only the ones showing the change answer it
+        let done = match (request, &event) {
+            // an earlier message to the same recipient may be delivered first
+            (Request::Direct { .. } | Request::Note(_), ClientEvent::Delivered { client_id, .. }) => {
+                sent == Some(*client_id)
+            }
+            (Request::Read(peer), ClientEvent::UnreadSummary(unread)) => {
+                !unread.iter().any(|(from, _)| from.eq_ignore_ascii_case(peer))
+            }
+            (Request::Preference { key, value }, ClientEvent::Preferences(preferences)) => {
+                preferences.iter().any(|(k, v)| k == key && v == value)
+            }
+            (Request::Mute { sender, muted }, ClientEvent::MutedSenders(senders)) => {
+                senders.iter().any(|name| name.eq_ignore_ascii_case(sender)) == *muted
+            }
+            _ => answers(command, &event),
+        };
+        if done {
+            answered.push(event);
+            return Ok(answered);
+        }
+    }
+    Err(OnceError::Connection("the client stopped".to_string()))
+}
+
+fn answers(command: &str, event: &ClientEvent) -> bool {
+    matches!(
+        (command, event),
+        ("edit", ClientEvent::MessageEdited { .. })
+            | ("delete", ClientEvent::MessageDeleted { .. })
+            | ("history", ClientEvent::HistoryEnd(_))
+            | ("search", ClientEvent::SearchEnd { .. })
+            | ("shutdown", ClientEvent::ServerShutdownWarning(_))
+            | ("loglevel", ClientEvent::LogLevelChanged { .. })
+            | ("drain" | "undrain" | "stats", ClientEvent::ServerStats { .. })
+            | ("kick", ClientEvent::UserKicked { .. })
+            | ("promote" | "demote", ClientEvent::AccessLevelChanged { .. })
+            | ("motd", ClientEvent::Motd(_))
+            | ("userinfo" | "grant" | "ungrant", ClientEvent::UserInfo { .. })
+            | ("sessions" | "kickwhere", ClientEvent::SessionListEnd { .. })
+            | ("bans" | "unban", ClientEvent::IpBans(_))
+            | ("fsck", ClientEvent::ConsistencyReport(_))
+            | ("mutes", ClientEvent::MutedSenders(_))
+            | ("export", ClientEvent::StateExported { .. })
+            | ("renameuser", ClientEvent::UserRenamed { .. })
+    )
+}
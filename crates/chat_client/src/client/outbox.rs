@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+use chat_core::protocol::Message;
+use chrono::{DateTime, Local};
+use uuid::Uuid;
+
+use super::json::JsonValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxState {
+    Pending,
+    InFlight,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    // stays the same across retries so the server can tell a resend from a new message
+    id: Uuid,
+    recipient: String,
+    body: String,
+    // seconds, only set for ephemeral messages
+    ttl: Option<u64>,
+    // made once when queued, every retry carries the same one
+    signature: Option<Vec<u8>>,
+    queued_at: DateTime<Local>,
+    state: OutboxState,
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    entries: VecDeque<OutboxEntry>,
+    capacity: usize,
+}
+
+impl OutboxEntry {
+    pub(super) fn new(recipient: &str, body: &str, ttl: Option<u64>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            recipient: recipient.to_string(),
+            body: body.to_string(),
+            ttl,
+            signature: None,
+            queued_at: Local::now(),
+            state: OutboxState::Pending,
+        }
+    }
+
+    pub(super) fn with_signature(mut self, signature: Option<Vec<u8>>) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn ttl(&self) -> Option<u64> {
+        self.ttl
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    pub fn queued_at(&self) -> DateTime<Local> {
+        self.queued_at
+    }
+
+    pub fn state(&self) -> OutboxState {
+        self.state
+    }
+
+    pub fn message(&self) -> Message {
+        let ttl = self.ttl.unwrap_or(0);
+        let message = Message::direct_message_send_with_id(&self.recipient, &self.body, ttl, &self.id.to_string());
+        match &self.signature {
+            Some(signature) => message.with_signature(signature),
+            None => message,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let state = match self.state {
+            OutboxState::Pending => "pending",
+            OutboxState::InFlight => "sending",
+        };
+
+        JsonValue::object()
+            .with("id", self.id.to_string())
+            .with("recipient", self.recipient.as_str())
+            .with("body", self.body.as_str())
+            .with("ttl", self.ttl)
+            .with("queued_at", self.queued_at.to_rfc3339())
+            .with("state", state)
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter()
+    }
+
+    pub fn push_pending(&mut self, mut entry: OutboxEntry) -> bool {
+        entry.state = OutboxState::Pending;
+        self.push(entry)
+    }
+
+    pub fn push_in_flight(&mut self, mut entry: OutboxEntry) -> bool {
+        entry.state = OutboxState::InFlight;
+        self.push(entry)
+    }
+
+    pub fn take_pending(&mut self) -> Vec<Message> {
+        self.entries
+            .iter_mut()
+            .filter(|entry| entry.state == OutboxState::Pending)
+            .map(|entry| {
+                entry.state = OutboxState::InFlight;
+                entry.message()
+            })
+            .collect()
+    }
+
+    pub fn requeue_in_flight(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.state = OutboxState::Pending;
+        }
+    }
+
+    // the server answers direct messages in the order it received them
+    pub fn resolve_in_flight(&mut self) -> Option<OutboxEntry> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.state == OutboxState::InFlight)?;
+        self.entries.remove(index)
+    }
+
+    // an answer that names the message, anything still waiting before it stays in flight
+    pub fn resolve(&mut self, id: Uuid) -> Option<OutboxEntry> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.id == id && entry.state == OutboxState::InFlight)?;
+        self.entries.remove(index)
+    }
+
+    pub fn cancel(&mut self, index: usize) -> Option<OutboxEntry> {
+        if self.entries.get(index)?.state != OutboxState::Pending {
+            return None;
+        }
+        self.entries.remove(index)
+    }
+
+    fn push(&mut self, entry: OutboxEntry) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.entries.push_back(entry);
+        true
+    }
+}
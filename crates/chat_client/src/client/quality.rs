@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+// the best connection, the worst is 0
+pub const MAX_QUALITY: u8 = 4;
+// a frame that takes longer than this to write counts against the connection
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// round trips up to the first are perfect, from the second on unusable
+const GOOD_ROUND_TRIP: Duration = Duration::from_millis(100);
+const BAD_ROUND_TRIP: Duration = Duration::from_millis(1000);
+// a healthy server speaks well within the keepalive interval, silence for this share of it starts to count
+const QUIET_SHARE: f64 = 0.75;
+// silence from then on for this share of the interval is unusable
+const SILENT_SHARE: f64 = 0.5;
+// how far past the middle between two levels the score has to go before the level follows it
+const HYSTERESIS: f64 = 0.2;
+
+// how much each measurement counts, 1 for all of them by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityWeights {
+    pub round_trip: f64,
+    pub regularity: f64,
+    pub send_timeouts: f64,
+    pub reconnects: f64,
+    // the share a new sample has in its average, from 0 to 1
+    pub smoothing: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualitySample {
+    // a time sync or a heartbeat probe came back after this long
+    RoundTrip(Duration),
+    // the server was heard from again or is still quiet, after this long, against the keepalive interval
+    Heard { gap: Duration, expected: Duration },
+    SendTimeout,
+    Reconnect,
+}
+
+// penalties go from 0 for perfect to 1 for unusable, averaged over the samples, the level follows with hysteresis
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityScorer {
+    weights: QualityWeights,
+    round_trip: f64,
+    regularity: f64,
+    send_timeouts: f64,
+    reconnects: f64,
+    level: u8,
+}
+
+impl Default for QualityWeights {
+    fn default() -> Self {
+        Self {
+            round_trip: 1.0,
+            regularity: 1.0,
+            send_timeouts: 1.0,
+            reconnects: 1.0,
+            smoothing: 0.3,
+        }
+    }
+}
+
+impl QualityWeights {
+    // `round_trip=2,reconnects=0.5`, what is left out keeps its default
+    pub fn parse(weights: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for pair in weights.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected '<name>=<weight>', got '{}'", pair))?;
+            let value: f64 = match value.trim().parse() {
+                Ok(value) if value >= 0.0 => value,
+                _ => return Err(format!("Invalid weight '{}' for {}", value.trim(), key.trim())),
+            };
+            match key.trim() {
+                "round_trip" => parsed.round_trip = value,
+                "regularity" => parsed.regularity = value,
+                "send_timeouts" => parsed.send_timeouts = value,
+                "reconnects" => parsed.reconnects = value,
+                "smoothing" if value <= 1.0 => parsed.smoothing = value,
+                "smoothing" => return Err(format!("Smoothing has to be between 0 and 1, got {}", value)),
+                key => return Err(format!("Unknown weight '{}'", key)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl QualityScorer {
+    // a new connection starts out at its best
+    pub fn new(weights: QualityWeights) -> Self {
+        Self {
+            weights,
+            round_trip: 0.0,
+            regularity: 0.0,
+            send_timeouts: 0.0,
+            reconnects: 0.0,
+            level: MAX_QUALITY,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    // the level if the sample changed it
+    pub fn record(&mut self, sample: QualitySample) -> Option<u8> {
+        let smoothing = self.weights.smoothing;
+        let average = |average: f64, penalty: f64| average + smoothing * (penalty - average);
+        match sample {
+            QualitySample::RoundTrip(round_trip) => {
+                let penalty = share(
+                    round_trip.saturating_sub(GOOD_ROUND_TRIP),
+                    BAD_ROUND_TRIP - GOOD_ROUND_TRIP,
+                );
+                self.round_trip = average(self.round_trip, penalty);
+                self.send_timeouts = average(self.send_timeouts, 0.0);
+                self.reconnects = average(self.reconnects, 0.0);
+            }
+            QualitySample::Heard { gap, expected } => {
+                let ratio = gap.as_secs_f64() / expected.as_secs_f64().max(f64::EPSILON);
+                let penalty = ((ratio - QUIET_SHARE) / SILENT_SHARE).clamp(0.0, 1.0);
+                // a stall counts right away, only the recovery is averaged
+                self.regularity = penalty.max(average(self.regularity, penalty));
+                self.send_timeouts = average(self.send_timeouts, 0.0);
+                self.reconnects = average(self.reconnects, 0.0);
+            }
+            QualitySample::SendTimeout => self.send_timeouts = average(self.send_timeouts, 1.0),
+            QualitySample::Reconnect => {
+                self.reconnects = average(self.reconnects, 1.0);
+                // the new connection has not been quiet yet
+                self.regularity = 0.0;
+            }
+        }
+
+        let score = self.score();
+        if (score - self.level as f64).abs() < 0.5 + HYSTERESIS {
+            return None;
+        }
+        let level = score.round() as u8;
+        (level != self.level).then(|| {
+            self.level = level;
+            level
+        })
+    }
+
+    // from 0 to MAX_QUALITY, each weighted penalty takes its share of what the others left
+    pub fn score(&self) -> f64 {
+        let weighted = [
+            (self.weights.round_trip, self.round_trip),
+            (self.weights.regularity, self.regularity),
+            (self.weights.send_timeouts, self.send_timeouts),
+            (self.weights.reconnects, self.reconnects),
+        ];
+        let left: f64 = weighted
+            .iter()
+            .map(|(weight, penalty)| 1.0 - (weight * penalty).min(1.0))
+            .product();
+        MAX_QUALITY as f64 * left
+    }
+}
+
+fn share(part: Duration, whole: Duration) -> f64 {
+    (part.as_secs_f64() / whole.as_secs_f64()).clamp(0.0, 1.0)
+}
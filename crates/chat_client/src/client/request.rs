@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use chat_core::{
+    normalize::{normalize_body, normalize_username},
+    protocol::{Message, MessageId, ScheduleTime, SessionFilter},
+};
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+
+use super::command::DEFAULT_HISTORY_LIMIT;
+
+// the server request a typed command line stands for, None for commands that are not a single request
+pub fn parse_request(command: &str, args: &str) -> Option<Result<Message, String>> {
+    let request = match command {
+        "log" => Ok(Message::SERVER_DEBUG_LOG),
+        "shutdown" => Ok(Message::server_shutdown(5)),
+        "loglevel" => parse_log_level(args),
+        "drain" => parse_drain(args),
+        "undrain" => Ok(Message::admin_set_server_mode("normal", None)),
+        "kick" => parse_kick(args),
+        "sessions" => parse_sessions(args),
+        "kickwhere" => parse_kick_where(args),
+        "bans" => Ok(Message::admin_list_ip_bans()),
+        "unban" => match args.trim() {
+            "" => Err("Usage: unban <address>".to_string()),
+            address => Ok(Message::admin_clear_ip_ban(address)),
+        },
+        "promote" | "demote" => parse_access_level(command, args),
+        "stats" => Ok(Message::admin_server_stats()),
+        "fsck" => match args.trim() {
+            "" => Ok(Message::admin_consistency_check(false)),
+            "--repair" => Ok(Message::admin_consistency_check(true)),
+            _ => Err("Usage: fsck [--repair]".to_string()),
+        },
+        "motd" => parse_motd(args),
+        "userinfo" => match args.trim() {
+            "" => Err("Usage: userinfo <username>".to_string()),
+            username => normalize_username(username).map(|username| Message::admin_user_info(&username)),
+        },
+        "export" if args.trim().is_empty() => Ok(Message::admin_export_state()),
+        "renameuser" => parse_rename_user(args),
+        "grant" => parse_grant(args),
+        "ungrant" => match args.split_whitespace().collect::<Vec<_>>()[..] {
+            [username, permission] => {
+                normalize_username(username).map(|username| Message::admin_revoke_temporary(&username, permission))
+            }
+            _ => Err("Usage: ungrant <username> <permission>".into()),
+        },
+        "history" => parse_history(args),
+        "schedule" => parse_schedule(args),
+        "schedules" => Ok(Message::schedule_list()),
+        "unschedule" => match args.trim().parse() {
+            Ok(id) => Ok(Message::schedule_cancel(id)),
+            Err(_) => Err("Usage: unschedule <id>".to_string()),
+        },
+        "token" => match args.trim() {
+            "" => Err("Usage: token <label>".to_string()),
+            label => Ok(Message::api_token_create(label)),
+        },
+        "tokens" => Ok(Message::api_token_list()),
+        "untoken" => match args.trim().parse() {
+            Ok(id) => Ok(Message::api_token_revoke(id)),
+            Err(_) => Err("Usage: untoken <id>".to_string()),
+        },
+        _ => return None,
+    };
+    Some(request)
+}
+
+fn parse_log_level(args: &str) -> Result<Message, String> {
+    let mut args = args.split_whitespace();
+    let filter = args.next().ok_or("Usage: loglevel <filter> [revert_secs]")?;
+    let revert_after = args
+        .next()
+        .map(|seconds| {
+            seconds
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid revert timeout '{}'", seconds))
+        })
+        .transpose()?;
+
+    Ok(Message::admin_set_log_level(filter, revert_after))
+}
+
+fn parse_rename_user(args: &str) -> Result<Message, String> {
+    match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [old_name, new_name] => Ok(Message::admin_rename_user(
+            &normalize_username(old_name)?,
+            &normalize_username(new_name)?,
+        )),
+        _ => Err("Usage: renameuser <username> <new name>".into()),
+    }
+}
+
+fn parse_grant(args: &str) -> Result<Message, String> {
+    let usage = || "Usage: grant <username> <permission> <seconds>".to_string();
+    match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [username, permission, seconds] => {
+            let seconds = seconds.parse().map_err(|_| usage())?;
+            Ok(Message::admin_grant_temporary(
+                &normalize_username(username)?,
+                permission,
+                seconds,
+            ))
+        }
+        _ => Err(usage()),
+    }
+}
+
+const SESSIONS_USAGE: &str = "sessions [--guests] [--idle <minutes>] [--ip <prefix>]";
+const KICK_WHERE_USAGE: &str = "kickwhere [--guests] [--idle <minutes>] [--ip <prefix>] [--dry-run] [reason]";
+
+// the filter options in front, returns the words after them starting with the first one that is not a filter option
+fn parse_session_filter<'a>(args: &'a str, usage: &str) -> Result<(SessionFilter, Vec<&'a str>), String> {
+    let mut filter = SessionFilter::default();
+    let mut args = args.split_whitespace().peekable();
+    while let Some(option) = args.next_if(|arg| matches!(*arg, "--guests" | "--idle" | "--ip")) {
+        if option == "--guests" {
+            filter.guests_only = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("Usage: {}", usage))?;
+        if option == "--idle" {
+            filter.idle_minutes = value
+                .parse()
+                .map_err(|_| format!("Invalid idle minutes '{}', usage: {}", value, usage))?;
+        } else {
+            filter.ip_prefix = value.to_string();
+        }
+    }
+    Ok((filter, args.collect()))
+}
+
+fn parse_sessions(args: &str) -> Result<Message, String> {
+    match parse_session_filter(args, SESSIONS_USAGE)? {
+        (filter, rest) if rest.is_empty() => Ok(Message::admin_list_sessions(&filter)),
+        _ => Err(format!("Usage: {}", SESSIONS_USAGE)),
+    }
+}
+
+// without any condition every session below the sender's rank would go, so one is required
+fn parse_kick_where(args: &str) -> Result<Message, String> {
+    let (filter, rest) = parse_session_filter(args, KICK_WHERE_USAGE)?;
+    if filter == SessionFilter::default() {
+        return Err(format!("Usage: {}", KICK_WHERE_USAGE));
+    }
+    let (dry_run, reason) = match rest.split_first() {
+        Some((&"--dry-run", reason)) => (true, reason),
+        _ => (false, &rest[..]),
+    };
+
+    Ok(Message::admin_kick_where(&filter, &reason.join(" "), dry_run))
+}
+
+fn parse_kick(args: &str) -> Result<Message, String> {
+    let (username, reason) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    if username.is_empty() {
+        return Err("Usage: kick <username> [reason]".into());
+    }
+
+    Ok(Message::admin_kick_user(&normalize_username(username)?, reason.trim()))
+}
+
+// demote without a level drops the user back to a regular account
+fn parse_access_level(command: &str, args: &str) -> Result<Message, String> {
+    let mut args = args.split_whitespace();
+    let username = args
+        .next()
+        .ok_or_else(|| format!("Usage: {} <username> <guest|user|moderator|admin>", command))?;
+    let level = match (command, args.next()) {
+        (_, Some(level)) => level,
+        ("demote", None) => "user",
+        (_, None) => return Err(format!("Usage: {} <username> <guest|user|moderator|admin>", command)),
+    };
+
+    Ok(Message::admin_set_access_level(&normalize_username(username)?, level))
+}
+
+fn parse_history(args: &str) -> Result<Message, String> {
+    let mut args = args.split_whitespace();
+    let peer = normalize_username(args.next().ok_or("Usage: history <username> [limit] [before_id]")?)?;
+    let limit = match args.next() {
+        Some(limit) => limit.parse::<u64>().map_err(|_| {
+            format!(
+                "Invalid limit '{}', usage: history <username> [limit] [before_id]",
+                limit
+            )
+        })?,
+        None => DEFAULT_HISTORY_LIMIT,
+    };
+
+    match args.next() {
+        Some(before) => {
+            let before = before.parse::<u64>().map_err(|_| {
+                format!(
+                    "Invalid message id '{}', usage: history <username> [limit] [before_id]",
+                    before
+                )
+            })?;
+            Ok(Message::history_request_before(&peer, limit, MessageId::new(before)))
+        }
+        None => Ok(Message::history_request(&peer, limit)),
+    }
+}
+
+fn parse_drain(args: &str) -> Result<Message, String> {
+    let stop_after = match args.split_whitespace().next() {
+        Some(seconds) => Some(
+            seconds
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid stop timeout '{}', usage: drain [stop_secs]", seconds))?,
+        ),
+        None => None,
+    };
+
+    Ok(Message::admin_set_server_mode("draining", stop_after))
+}
+
+// `motd [--broadcast] <text>` or `motd [--broadcast] --clear`
+fn parse_motd(args: &str) -> Result<Message, String> {
+    let args = args.trim();
+    let (broadcast, text) = match args.strip_prefix("--broadcast") {
+        Some(text) => (true, text.trim()),
+        None => (false, args),
+    };
+    match text {
+        "" => Err("Usage: motd [--broadcast] <text> | --clear".to_string()),
+        "--clear" => Ok(Message::admin_set_motd("", broadcast)),
+        text => Ok(Message::admin_set_motd(text, broadcast)),
+    }
+}
+
+const SCHEDULE_USAGE: &str = "Usage: schedule <HH:MM | 30s | 10m | 2h | 1d | RFC3339> <user> <text>";
+
+// `schedule <time> <user> <text>`, the text may be quoted
+fn parse_schedule(args: &str) -> Result<Message, String> {
+    let mut parts = args.trim().splitn(3, ' ');
+    let (Some(time), Some(recipient), Some(body)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(SCHEDULE_USAGE.to_string());
+    };
+    let due = parse_schedule_time(time, Local::now())?;
+    let body = body.trim();
+    let body = body
+        .strip_prefix('"')
+        .and_then(|body| body.strip_suffix('"'))
+        .unwrap_or(body);
+    Ok(Message::schedule_message_send(
+        &normalize_username(recipient)?,
+        &normalize_body(body)?,
+        due,
+    ))
+}
+
+// a clock time is its next occurrence in the time zone of `now`, relative times are counted by the server
+pub fn parse_schedule_time<Tz: TimeZone>(value: &str, now: DateTime<Tz>) -> Result<ScheduleTime, String> {
+    let invalid = || format!("Invalid time '{}', expected HH:MM, 30s, 10m, 2h, 1d or RFC3339", value);
+    if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M") {
+        let timezone = now.timezone();
+        let mut date = now.date_naive();
+        // a clock time that does not exist on a day, skipped by daylight saving, is looked up on the next
+        for _ in 0..3 {
+            if let Some(due) = timezone.from_local_datetime(&date.and_time(time)).earliest() {
+                if due > now {
+                    return Ok(ScheduleTime::At(due.with_timezone(&Utc)));
+                }
+            }
+            date = date.succ_opt().ok_or_else(invalid)?;
+        }
+        return Err(invalid());
+    }
+    if let Ok(due) = DateTime::parse_from_rfc3339(value) {
+        return Ok(ScheduleTime::At(due.with_timezone(&Utc)));
+    }
+
+    let split = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (count, unit) = value.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let seconds = count.checked_mul(unit).ok_or_else(invalid)?;
+    Ok(ScheduleTime::In(Duration::from_secs(seconds)))
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use chat_core::protocol::{PresenceChange, PresenceDelta, PresenceEntry, PresenceSnapshot, PresenceStatus};
+
+// who is online in one scope, built from a snapshot and kept up by the deltas that follow it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+    version: u64,
+    users: BTreeMap<String, PresenceStatus>,
+}
+
+impl Roster {
+    pub fn from_snapshot(snapshot: &PresenceSnapshot) -> Self {
+        Self {
+            version: snapshot.version,
+            users: snapshot
+                .entries
+                .iter()
+                .map(|entry| (entry.user.clone(), entry.status))
+                .collect(),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // false when the delta does not follow the last version, the roster is stale then and needs a new snapshot
+    pub fn apply(&mut self, delta: &PresenceDelta) -> bool {
+        if delta.version <= self.version {
+            // from before the snapshot, already in it
+            return true;
+        }
+        if delta.version != self.version + 1 {
+            return false;
+        }
+        self.version = delta.version;
+        match delta.change {
+            PresenceChange::Joined | PresenceChange::StatusChanged => {
+                self.users.insert(delta.entry.user.clone(), delta.entry.status);
+            }
+            PresenceChange::Left => {
+                self.users.remove(&delta.entry.user);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    // sorted by name
+    pub fn entries(&self) -> Vec<PresenceEntry> {
+        self.users
+            .iter()
+            .map(|(user, status)| PresenceEntry {
+                user: user.clone(),
+                status: *status,
+            })
+            .collect()
+    }
+}
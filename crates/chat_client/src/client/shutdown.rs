@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+// stops every task of one connection, whichever of them runs into the problem first
+#[derive(Debug, Clone)]
+pub(super) struct ShutdownToken {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownToken {
+    pub fn new() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub async fn cancelled(&self) {
+        // the token holds the sender, so the channel cannot close underneath
+        self.tx.subscribe().wait_for(|cancelled| *cancelled).await.ok();
+    }
+}
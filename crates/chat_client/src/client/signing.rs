@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chat_core::{
+    json::JsonValue,
+    normalize::{normalize_body, normalize_username},
+    protocol::SIGNING_KEY_LENGTH,
+    trace::{from_hex, to_hex},
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+// in front of everything signed, a signature made for something else never passes for a direct message
+const SIGNED_CONTEXT: &[u8] = b"chat_rs direct message 1";
+// of the key's sha256, enough to compare two of them by eye
+const FINGERPRINT_BYTES: usize = 8;
+
+pub type PublicKey = [u8; SIGNING_KEY_LENGTH];
+
+// what a received direct message's signature says about its sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Unsigned,
+    // signed, but there is no trusted key of the sender's to check it with, or it is one they changed to since
+    Unverified,
+    Verified,
+    // does not match the sender's trusted key, it was altered on the way or is not theirs
+    Invalid,
+}
+
+// this user's key pair, the secret half never leaves the client. the server keeps one key per account,
+// a second device signs with the same key file or its messages stop verifying for everyone who trusted the first
+#[derive(Clone)]
+pub struct SigningIdentity {
+    key: SigningKey,
+}
+
+// the keys trusted on first use, by username as the server compares them
+#[derive(Debug, Clone, Default)]
+pub struct KnownKeys {
+    // None keeps them for this run only
+    path: Option<PathBuf>,
+    keys: BTreeMap<String, PublicKey>,
+}
+
+impl Verification {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verification::Unsigned => "unsigned",
+            Verification::Unverified => "unverified",
+            Verification::Verified => "verified",
+            Verification::Invalid => "invalid",
+        }
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for SigningIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningIdentity({})", self.fingerprint())
+    }
+}
+
+impl SigningIdentity {
+    pub fn generate() -> Result<Self, String> {
+        let mut seed = [0; SIGNING_KEY_LENGTH];
+        getrandom::getrandom(&mut seed).map_err(|e| format!("No randomness for a signing key: {}", e))?;
+        Ok(Self::from_seed(seed))
+    }
+
+    pub fn from_seed(seed: [u8; SIGNING_KEY_LENGTH]) -> Self {
+        Self {
+            key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    // the secret as hex on one line, a missing file is made with a fresh key only its owner may read
+    pub fn load_or_create(path: &Path) -> Result<Self, String> {
+        let problem = |e: &dyn fmt::Display| format!("{}: {}", path.display(), e);
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                let seed = from_hex(content.trim()).map_err(|e| problem(&e))?;
+                let seed = seed.try_into().map_err(|_| problem(&"not a signing key"))?;
+                Ok(Self::from_seed(seed))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let identity = Self::generate()?;
+                let mut options = fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path).map_err(|e| problem(&e))?;
+                writeln!(file, "{}", to_hex(identity.key.as_bytes())).map_err(|e| problem(&e))?;
+                Ok(identity)
+            }
+            Err(e) => Err(problem(&e)),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.key.verifying_key().to_bytes()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.public_key())
+    }
+
+    // None for names or a body the server would refuse anyway
+    pub fn sign(&self, sender: &str, recipient: &str, body: &str) -> Option<Vec<u8>> {
+        let content = signed_content(sender, recipient, body).ok()?;
+        Some(self.key.sign(&content).to_bytes().to_vec())
+    }
+}
+
+// false for signatures and keys that are not even well formed
+pub fn verify_signature(key: &PublicKey, sender: &str, recipient: &str, body: &str, signature: &[u8]) -> bool {
+    let (Ok(key), Ok(signature), Ok(content)) = (
+        VerifyingKey::from_bytes(key),
+        Signature::from_slice(signature),
+        signed_content(sender, recipient, body),
+    ) else {
+        return false;
+    };
+    key.verify_strict(&content, &signature).is_ok()
+}
+
+// `1a2b:3c4d:5e6f:7a8b`, what two users read to each other to be sure of a key
+pub fn fingerprint(key: &PublicKey) -> String {
+    let digest = Sha256::digest(key);
+    digest[..FINGERPRINT_BYTES]
+        .chunks(2)
+        .map(to_hex)
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// the names as the server compares them and the body as it relays it, each with its length in front
+fn signed_content(sender: &str, recipient: &str, body: &str) -> Result<Vec<u8>, String> {
+    let mut content = SIGNED_CONTEXT.to_vec();
+    for part in [fold_username(sender)?, fold_username(recipient)?, normalize_body(body)?] {
+        content.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        content.extend_from_slice(part.as_bytes());
+    }
+    Ok(content)
+}
+
+// like the server's, "Alice" and "alice" are the same account
+pub(super) fn fold_username(name: &str) -> Result<String, String> {
+    normalize_username(name).map(|name| name.to_lowercase())
+}
+
+impl KnownKeys {
+    // a missing file is no keys yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut known = Self {
+            path: Some(path.to_path_buf()),
+            keys: BTreeMap::new(),
+        };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(known),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let problem = |e: &str| format!("{}: {}", path.display(), e);
+        let JsonValue::Object(entries) = JsonValue::parse(&content).map_err(|e| problem(&e))? else {
+            return Err(problem("not a key file"));
+        };
+        for (username, key) in entries {
+            let key = key
+                .as_str()
+                .and_then(|key| from_hex(key).ok())
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| problem(&format!("invalid key for {}", username)))?;
+            known.keys.insert(username, key);
+        }
+        Ok(known)
+    }
+
+    pub fn get(&self, username: &str) -> Option<&PublicKey> {
+        self.keys.get(&fold_username(username).ok()?)
+    }
+
+    // replaces whatever was trusted for the user before, written through to the file if there is one
+    pub fn trust(&mut self, username: &str, key: PublicKey) -> Result<(), String> {
+        self.keys.insert(fold_username(username)?, key);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let entries = self
+            .keys
+            .iter()
+            .map(|(username, key)| (username.clone(), JsonValue::from(to_hex(key))))
+            .collect();
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, format!("{}\n", JsonValue::Object(entries)))
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+use chat_core::protocol::{Message, OfflineSummary, SyncBatch};
+
+// how far the pull of the offline queue got, the cursor outlives the connection so an interrupted sync
+// picks up right after the last message that arrived
+#[derive(Debug, Default)]
+pub struct OfflineSync {
+    // messages asked for at a time, 0 leaves the queue to be sent whole at login
+    batch: u64,
+    // the account the cursor belongs to, another account's queue counts in sequences of its own
+    user_id: Option<String>,
+    cursor: u64,
+    // of the announced batch, what did not arrive yet
+    pending: VecDeque<u64>,
+    remaining: u64,
+    delivered: u64,
+    active: bool,
+}
+
+// what the client reports and asks for once the last message of a batch arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStep {
+    pub delivered: u64,
+    pub remaining: u64,
+    pub request: Message,
+}
+
+impl OfflineSync {
+    pub fn new(batch: u64) -> Self {
+        Self {
+            batch,
+            ..Self::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.batch > 0
+    }
+
+    // a resumed sync keeps counting, the server's cursor only knows about acknowledged messages and ours may be ahead
+    pub fn start(&mut self, user_id: Option<&str>, summary: &OfflineSummary) -> Message {
+        if self.user_id.as_deref() != user_id {
+            self.user_id = user_id.map(str::to_string);
+            self.cursor = 0;
+            self.active = false;
+        }
+        if !self.active {
+            self.delivered = 0;
+        }
+        self.active = true;
+        self.cursor = self.cursor.max(summary.cursor);
+        self.pending.clear();
+        Message::sync_request(self.cursor, self.batch)
+    }
+
+    // an empty batch with nothing remaining answers the final acknowledgement, the count is returned then
+    pub fn announce(&mut self, batch: SyncBatch) -> Option<u64> {
+        if !self.active {
+            return None;
+        }
+        if batch.sequences.is_empty() && batch.remaining == 0 {
+            self.active = false;
+            return Some(self.delivered);
+        }
+        self.pending = batch.sequences.into();
+        self.remaining = batch.remaining;
+        None
+    }
+
+    // a message the server queued arrived, the last one of a batch asks for the next or acknowledges them all
+    pub fn received(&mut self) -> Option<SyncStep> {
+        self.cursor = self.pending.pop_front()?;
+        self.delivered += 1;
+        if !self.pending.is_empty() {
+            return None;
+        }
+        let limit = match self.remaining {
+            0 => 0,
+            _ => self.batch,
+        };
+        Some(SyncStep {
+            delivered: self.delivered,
+            remaining: self.remaining,
+            request: Message::sync_request(self.cursor, limit),
+        })
+    }
+
+    // whatever of the batch did not arrive is still queued, the server announces it again after the login
+    pub fn disconnected(&mut self) {
+        self.pending.clear();
+    }
+}
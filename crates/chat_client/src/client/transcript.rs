@@ -0,0 +1,300 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use chat_core::normalize::normalize_username;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+
+use super::{conversations::ScrollbackLine, json::JsonValue};
+
+// the most the server sends for one history request, a full page means there may be more before it
+pub const TRANSCRIPT_PAGE: u64 = 100;
+// a conversation longer than this is cut off at its oldest messages
+pub const TRANSCRIPT_MAX_MESSAGES: usize = 10_000;
+// our own lines carry no id, one stands for the server's entry with the same sender and body sent this close to it
+const LOCAL_MATCH_WINDOW_SECS: i64 = 60;
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const EXPORT_USAGE: &str =
+    "Usage: export <user> <file> [--format txt|md|json] [--from <YYYY-MM-DD>] [--to <YYYY-MM-DD>]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    // None for lines only the local scrollback knows
+    pub id: Option<u64>,
+    pub sender: String,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+    pub edited: bool,
+    pub deleted: bool,
+}
+
+// what `export <user> <file>` asks for, the dates are local and both included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptExport {
+    pub peer: String,
+    pub path: PathBuf,
+    pub format: TranscriptFormat,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// one conversation, oldest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    peer: String,
+    entries: Vec<TranscriptEntry>,
+}
+
+// the server's history of a conversation as it comes in page by page, newest page first
+#[derive(Debug)]
+pub(super) struct TranscriptJob {
+    pub(super) export: TranscriptExport,
+    entries: Vec<TranscriptEntry>,
+    // the id the last page was asked for before, None for the first page
+    before: Option<u64>,
+}
+
+impl TranscriptFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "txt" => Ok(TranscriptFormat::Text),
+            "md" => Ok(TranscriptFormat::Markdown),
+            "json" => Ok(TranscriptFormat::Json),
+            _ => Err(format!("Unknown format '{}', expected txt, md or json", format)),
+        }
+    }
+
+    // by the file's extension, plain text for anything else
+    fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("md") => TranscriptFormat::Markdown,
+            Some("json") => TranscriptFormat::Json,
+            _ => TranscriptFormat::Text,
+        }
+    }
+}
+
+impl From<ScrollbackLine> for TranscriptEntry {
+    fn from(line: ScrollbackLine) -> Self {
+        Self {
+            id: line.id,
+            sender: line.sender,
+            body: line.body,
+            sent_at: line.sent_at,
+            edited: false,
+            deleted: false,
+        }
+    }
+}
+
+impl TranscriptExport {
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let date = |date: Option<&str>| match date.map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d")) {
+            Some(Ok(date)) => Ok(Some(date)),
+            _ => Err(EXPORT_USAGE.to_string()),
+        };
+
+        let (mut positional, mut format, mut from, mut to) = (Vec::new(), None, None, None);
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--format" => format = Some(TranscriptFormat::parse(args.next().ok_or(EXPORT_USAGE)?)?),
+                "--from" => from = date(args.next())?,
+                "--to" => to = date(args.next())?,
+                arg => positional.push(arg),
+            }
+        }
+        let [peer, path] = positional[..] else {
+            return Err(EXPORT_USAGE.to_string());
+        };
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(format!("The range starts on {} after it ends on {}", from, to));
+            }
+        }
+
+        let path = PathBuf::from(path);
+        Ok(Self {
+            peer: normalize_username(peer)?,
+            format: format.unwrap_or_else(|| TranscriptFormat::of_path(&path)),
+            path,
+            from,
+            to,
+        })
+    }
+}
+
+impl Transcript {
+    // the server's copy of a message wins, only it knows whether it was edited or deleted since
+    pub fn merge(peer: &str, local: Vec<TranscriptEntry>, server: Vec<TranscriptEntry>) -> Self {
+        let mut unmatched = Vec::new();
+        let mut by_id = BTreeMap::new();
+        for entry in server {
+            match entry.id {
+                Some(id) => {
+                    by_id.insert(id, entry);
+                }
+                None => unmatched.push(entry),
+            }
+        }
+
+        let mut claimed = BTreeSet::new();
+        for entry in local {
+            match entry.id {
+                Some(id) => {
+                    by_id.entry(id).or_insert(entry);
+                }
+                None => {
+                    let matching = by_id.iter().find(|(id, known)| {
+                        !claimed.contains(*id)
+                            && known.sender == entry.sender
+                            && known.body == entry.body
+                            && (known.sent_at - entry.sent_at).abs()
+                                <= chrono::Duration::seconds(LOCAL_MATCH_WINDOW_SECS)
+                    });
+                    match matching {
+                        Some((id, _)) => {
+                            claimed.insert(*id);
+                        }
+                        None => unmatched.push(entry),
+                    }
+                }
+            }
+        }
+
+        let mut entries: Vec<TranscriptEntry> = by_id.into_values().chain(unmatched).collect();
+        entries.sort_by_key(|entry| (entry.sent_at, entry.id));
+        Self {
+            peer: peer.to_string(),
+            entries,
+        }
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    // the entries sent on the days from one date to the other, both included, as the time zone counts days
+    pub fn between<Tz: TimeZone>(mut self, from: Option<NaiveDate>, to: Option<NaiveDate>, timezone: &Tz) -> Self {
+        self.entries.retain(|entry| {
+            let day = entry.sent_at.with_timezone(timezone).date_naive();
+            from.map_or(true, |from| day >= from) && to.map_or(true, |to| day <= to)
+        });
+        self
+    }
+
+    // timestamps are shown in the time zone, the json keeps its offset with them
+    pub fn render<Tz: TimeZone>(&self, format: TranscriptFormat, timezone: &Tz) -> String
+    where
+        Tz::Offset: Display,
+    {
+        let time = |entry: &TranscriptEntry| entry.sent_at.with_timezone(timezone);
+        let mut text = match format {
+            TranscriptFormat::Text => format!("Conversation with {}\n\n", self.peer),
+            TranscriptFormat::Markdown => format!("# Conversation with {}\n\n", self.peer),
+            TranscriptFormat::Json => {
+                let messages: Vec<JsonValue> = self
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        JsonValue::object()
+                            .with("id", entry.id)
+                            .with("sender", entry.sender.as_str())
+                            .with("body", entry.body.as_str())
+                            .with("sent_at", time(entry).to_rfc3339())
+                            .with("edited", entry.edited)
+                            .with("deleted", entry.deleted)
+                    })
+                    .collect();
+                let transcript = JsonValue::object()
+                    .with("peer", self.peer.as_str())
+                    .with("messages", messages);
+                return format!("{}\n", transcript);
+            }
+        };
+
+        for entry in &self.entries {
+            let timestamp = time(entry).format(TIME_FORMAT);
+            let line = match (format, entry.deleted, entry.edited) {
+                (TranscriptFormat::Markdown, true, _) => {
+                    format!("- **{}** {}: *(deleted)*", timestamp, entry.sender)
+                }
+                (TranscriptFormat::Markdown, false, edited) => format!(
+                    "- **{}** {}: {}{}",
+                    timestamp,
+                    entry.sender,
+                    // continuation lines stay in the list item
+                    entry.body.replace('\n', "\n  "),
+                    if edited { " *(edited)*" } else { "" }
+                ),
+                (_, true, _) => format!("[{}] {}: (deleted)", timestamp, entry.sender),
+                (_, false, edited) => format!(
+                    "[{}] {}: {}{}",
+                    timestamp,
+                    entry.sender,
+                    entry.body,
+                    if edited { " (edited)" } else { "" }
+                ),
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl TranscriptJob {
+    pub(super) fn new(export: TranscriptExport) -> Self {
+        Self {
+            export,
+            entries: Vec::new(),
+            before: None,
+        }
+    }
+
+    pub(super) fn add(&mut self, entry: TranscriptEntry) {
+        self.entries.push(entry);
+    }
+
+    // the id to ask for the next page before, None once the server ran out or the pages reach past the first day
+    pub(super) fn next_page(&mut self, count: u64) -> Option<u64> {
+        if count < TRANSCRIPT_PAGE || self.entries.len() >= TRANSCRIPT_MAX_MESSAGES {
+            return None;
+        }
+        let oldest = self
+            .entries
+            .iter()
+            .filter(|entry| entry.id.is_some())
+            .min_by_key(|entry| entry.id)?;
+        if let Some(from) = self.export.from {
+            if oldest.sent_at.with_timezone(&Local).date_naive() < from {
+                return None;
+            }
+        }
+        // a server that pages no further back would be asked forever
+        let before = oldest.id.filter(|id| self.before.map_or(true, |before| *id < before))?;
+        self.before = Some(before);
+        Some(before)
+    }
+
+    // merged with what the scrollback holds, cut to the dates asked for
+    pub(super) fn finish(self, local: Vec<ScrollbackLine>) -> (TranscriptExport, Transcript) {
+        let local = local.into_iter().map(TranscriptEntry::from).collect();
+        let transcript =
+            Transcript::merge(&self.export.peer, local, self.entries).between(self.export.from, self.export.to, &Local);
+        (self.export, transcript)
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+
+use crate::client::{command_argument, CommandArgument, COMMANDS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Command,
+    Username,
+    Nothing,
+}
+
+// what tab offers at the prompt, the names are kept up to date from the events as they come in
+#[derive(Debug, Default)]
+pub struct Completer {
+    usernames: BTreeSet<String>,
+    conversations: BTreeSet<String>,
+    // set when a tab only listed the candidates, the next ones step through them while the line is left alone
+    cycle: Option<Cycle>,
+}
+
+#[derive(Debug)]
+struct Cycle {
+    head: String,
+    candidates: Vec<String>,
+    next: usize,
+    // the line as the last tab left it
+    line: String,
+}
+
+#[derive(Clone, Copy)]
+enum Source {
+    Commands,
+    Usernames,
+    Conversations,
+}
+
+impl Completer {
+    pub fn add_username(&mut self, username: &str) {
+        if !username.is_empty() {
+            self.usernames.insert(username.to_string());
+        }
+    }
+
+    pub fn add_conversation(&mut self, name: &str) {
+        if !name.is_empty() {
+            self.conversations.insert(name.to_string());
+        }
+    }
+
+    // the client's list replaces ours, closed conversations are not offered anymore
+    pub fn set_conversations(&mut self, names: impl IntoIterator<Item = String>) {
+        self.conversations = names.into_iter().collect();
+    }
+
+    // for the word the line ends with
+    pub fn candidates(&self, completion: Completion, line: &str) -> Vec<String> {
+        match split(completion, line) {
+            Some((_, word, source)) => self.matching(source, word),
+            None => Vec::new(),
+        }
+    }
+
+    // the completed line, and the candidates to list when there was nothing to add
+    pub fn complete(&mut self, completion: Completion, line: &str) -> (String, Vec<String>) {
+        if let Some(cycle) = self.cycle.as_mut().filter(|cycle| cycle.line == line) {
+            cycle.line = format!("{}{}", cycle.head, cycle.candidates[cycle.next]);
+            cycle.next = (cycle.next + 1) % cycle.candidates.len();
+            return (cycle.line.clone(), Vec::new());
+        }
+        self.cycle = None;
+
+        let Some((head, word, source)) = split(completion, line) else {
+            return (line.to_string(), Vec::new());
+        };
+        let candidates = self.matching(source, word);
+        match candidates.as_slice() {
+            [] => (line.to_string(), candidates),
+            [single] => (format!("{}{} ", head, single), Vec::new()),
+            [first, rest @ ..] => {
+                let prefix = rest.iter().fold(first.clone(), |prefix, candidate| {
+                    prefix
+                        .chars()
+                        .zip(candidate.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a)
+                        .collect()
+                });
+                if prefix == word {
+                    self.cycle = Some(Cycle {
+                        head: head.to_string(),
+                        candidates: candidates.clone(),
+                        next: 0,
+                        line: line.to_string(),
+                    });
+                }
+                (format!("{}{}", head, prefix), candidates)
+            }
+        }
+    }
+
+    fn matching(&self, source: Source, word: &str) -> Vec<String> {
+        let names: Box<dyn Iterator<Item = &str>> = match source {
+            Source::Commands => Box::new(COMMANDS.iter().map(|(command, _)| *command)),
+            Source::Usernames => Box::new(self.usernames.iter().map(String::as_str)),
+            Source::Conversations => Box::new(self.conversations.iter().map(String::as_str)),
+        };
+        names
+            .filter(|name| name.starts_with(word))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+// what stays in front of the completed word, the word, and where its candidates come from. commands are the first
+// word of a line, a slash in front included, and the first argument is completed by what the command takes
+fn split(completion: Completion, line: &str) -> Option<(&str, &str, Source)> {
+    match completion {
+        Completion::Nothing => None,
+        Completion::Username => Some(("", line, Source::Usernames)),
+        Completion::Command => {
+            let rest = line.strip_prefix('/').unwrap_or(line);
+            let Some((command, argument)) = rest.split_once(' ') else {
+                return Some((&line[..line.len() - rest.len()], rest, Source::Commands));
+            };
+            if argument.contains(' ') {
+                return None;
+            }
+            let source = match command_argument(command)? {
+                CommandArgument::Username => Source::Usernames,
+                CommandArgument::Conversation => Source::Conversations,
+                CommandArgument::Text => return None,
+            };
+            Some((&line[..line.len() - argument.len()], argument, source))
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chat_core::json::JsonValue;
+
+use crate::vault::{self, Vault};
+
+// longer than any message the server takes, anything past it is cut off
+pub const MAX_DRAFT_CHARS: usize = 4096;
+pub const MAX_DRAFTS: usize = 50;
+// the file is written once typing paused for this long, not on every key
+pub const SAVE_DELAY: Duration = Duration::from_millis(500);
+// the draft of the command line, there are no conversations of their own yet
+pub const PROMPT_DRAFT: &str = "prompt";
+
+// half-typed input, only ever kept in a local file and never sent anywhere
+#[derive(Debug, Clone, Default)]
+pub struct Drafts {
+    entries: BTreeMap<String, String>,
+    path: Option<PathBuf>,
+    // sealed on every save when the client files are encrypted
+    vault: Option<Arc<Vault>>,
+    // the first change since the last save, None when the file is up to date
+    changed_at: Option<Instant>,
+    last_change: Option<Instant>,
+}
+
+impl Drafts {
+    // a JSON object from draft name to text
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let JsonValue::Object(fields) = JsonValue::parse(content)? else {
+            return Err("expected an object of drafts".to_string());
+        };
+
+        let mut drafts = Self::default();
+        for (key, value) in fields {
+            let text = value
+                .as_str()
+                .ok_or_else(|| format!("draft '{}' is not a string", key))?;
+            drafts.insert(&key, text);
+        }
+        Ok(drafts)
+    }
+
+    // a missing file holds no drafts, it is created on the first save
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::load_with(path, None)
+    }
+
+    // a file the vault cannot open is an error like any broken one, nothing gets written over it
+    pub fn load_with(path: &Path, vault: Option<Arc<Vault>>) -> Result<Self, String> {
+        let mut drafts = match vault::read_file(path, vault.as_deref()) {
+            Ok(content) => Self::parse(&content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.to_string()),
+        };
+        drafts.path = Some(path.to_path_buf());
+        drafts.vault = vault;
+        Ok(drafts)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    // sorted by name
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, text)| (key.as_str(), text.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // blank text drops the draft, false if there was no room for a new one
+    pub fn set(&mut self, key: &str, text: &str, now: Instant) -> bool {
+        if self.get(key) == Some(text) || (text.trim().is_empty() && self.get(key).is_none()) {
+            return true;
+        }
+        let stored = if text.trim().is_empty() {
+            self.entries.remove(key);
+            true
+        } else {
+            self.insert(key, text)
+        };
+        self.touch(now);
+        stored
+    }
+
+    // all of them without a key, returns how many went
+    pub fn clear(&mut self, key: Option<&str>, now: Instant) -> usize {
+        let cleared = match key {
+            Some(key) => usize::from(self.entries.remove(key).is_some()),
+            None => std::mem::take(&mut self.entries).len(),
+        };
+        if cleared > 0 {
+            self.touch(now);
+        }
+        cleared
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.changed_at.is_some()
+    }
+
+    // true if the file was written, changes keep coming in while someone types so a steady
+    // stream is still saved every few delays
+    pub fn save_if_due(&mut self, now: Instant) -> io::Result<bool> {
+        let (Some(changed_at), Some(last_change)) = (self.changed_at, self.last_change) else {
+            return Ok(false);
+        };
+        let paused = now.saturating_duration_since(last_change) >= SAVE_DELAY;
+        let overdue = now.saturating_duration_since(changed_at) >= SAVE_DELAY * 4;
+        if !paused && !overdue {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // through a temporary file, a crash while saving keeps the previous drafts,
+    // a failed save is only tried again after the next change
+    pub fn save(&mut self) -> io::Result<()> {
+        self.changed_at = None;
+        self.last_change = None;
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        vault::write_file(path, &format!("{}\n", self.to_json()), self.vault.as_deref())
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::Object(
+            self.entries
+                .iter()
+                .map(|(key, text)| (key.clone(), JsonValue::from(text.as_str())))
+                .collect(),
+        )
+    }
+
+    fn insert(&mut self, key: &str, text: &str) -> bool {
+        if !self.entries.contains_key(key) && self.entries.len() >= MAX_DRAFTS {
+            return false;
+        }
+        self.entries
+            .insert(key.to_string(), text.chars().take(MAX_DRAFT_CHARS).collect());
+        true
+    }
+
+    fn touch(&mut self, now: Instant) {
+        self.changed_at.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+}
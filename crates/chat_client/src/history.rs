@@ -0,0 +1,72 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::vault::{self, Vault};
+
+pub const MAX_HISTORY: usize = 500;
+
+// the lines entered at the prompt, oldest first
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    lines: Vec<String>,
+    path: Option<PathBuf>,
+    vault: Option<Arc<Vault>>,
+}
+
+impl InputHistory {
+    // a missing file holds no history, it is created with the first line
+    pub fn load(path: &Path, vault: Option<Arc<Vault>>) -> io::Result<Self> {
+        let lines = match vault::read_file(path, vault.as_deref()) {
+            Ok(content) => content.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut history = Self {
+            lines,
+            path: Some(path.to_path_buf()),
+            vault,
+        };
+        history.truncate();
+        Ok(history)
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    // the same line twice in a row is kept once
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        if self.lines.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+        self.lines.push(line.to_string());
+        self.truncate();
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        match &self.vault {
+            // a sealed file cannot be appended to, it is written whole and only the kept lines go in
+            Some(vault) => vault::write_file(path, &format!("{}\n", self.lines.join("\n")), Some(vault)),
+            None => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line)),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn truncate(&mut self) {
+        if self.lines.len() > MAX_HISTORY {
+            self.lines.drain(..self.lines.len() - MAX_HISTORY);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+#[cfg(feature = "cli")]
+pub mod aliases;
+pub mod client;
+#[cfg(feature = "cli")]
+pub mod completion;
+#[cfg(feature = "cli")]
+pub mod drafts;
+#[cfg(feature = "cli")]
+pub mod history;
+#[cfg(feature = "cli")]
+pub mod profiles;
+#[cfg(feature = "cli")]
+pub mod text;
+#[cfg(feature = "cli")]
+pub mod theme;
+#[cfg(feature = "cli")]
+pub mod title;
+#[cfg(feature = "cli")]
+pub mod vault;
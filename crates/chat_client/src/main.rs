@@ -1,12 +1,23 @@
 use std::error::Error;
 
+use chat_client::client::OnceError;
+
 mod application;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    if std::env::args().nth(1).as_deref() == Some("encrypt-files") {
+        return application::encrypt_files();
+    }
+
     let app = application::Application::new()?;
 
     if let Err(e) = app.run().await {
+        // scripts tell failures apart by the exit code
+        if let Some(once) = e.downcast_ref::<OnceError>() {
+            eprintln!("{}", once);
+            std::process::exit(once.exit_code());
+        }
         tracing::error!("Error running application: {}", e);
         return Err(e);
     }
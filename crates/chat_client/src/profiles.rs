@@ -0,0 +1,88 @@
+use std::{collections::BTreeMap, path::Path};
+
+// where to connect and who to log in as, picked by name with `--profile`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+// a `[name]` line starts a profile, `key = "value"` lines below it fill it in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profiles {
+    entries: BTreeMap<String, Profile>,
+}
+
+impl Profile {
+    // both or nothing, a profile with only a name cannot log in
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        Some((self.username.as_deref()?, self.password.as_deref()?))
+    }
+}
+
+impl Profiles {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut profiles = Self::default();
+        let mut current: Option<String> = None;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let problem = |e: String| format!("line {}: {}", number + 1, e);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let name = name.trim();
+                if name.is_empty() || profiles.entries.contains_key(name) {
+                    return Err(problem(format!("invalid or repeated profile name '{}'", name)));
+                }
+                profiles.entries.insert(name.to_string(), Profile::default());
+                current = Some(name.to_string());
+                continue;
+            }
+
+            let Some(profile) = current.as_ref().and_then(|name| profiles.entries.get_mut(name)) else {
+                return Err(problem("settings have to follow a [profile] line".to_string()));
+            };
+            Self::parse_setting(profile, line).map_err(problem)?;
+        }
+        Ok(profiles)
+    }
+
+    fn parse_setting(profile: &mut Profile, line: &str) -> Result<(), String> {
+        let (key, value) = line.split_once('=').ok_or("expected '<key> = \"...\"'")?;
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .ok_or("the value has to be quoted")?
+            .to_string();
+
+        match key.trim() {
+            "host" => profile.host = Some(value),
+            "port" => {
+                let port = value.parse().map_err(|_| format!("expected a port, got '{}'", value))?;
+                profile.port = Some(port);
+            }
+            "username" => profile.username = Some(value),
+            "password" => profile.password = Some(value),
+            key => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    // a missing file holds no profiles
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.entries.get(name)
+    }
+}
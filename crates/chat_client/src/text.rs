@@ -0,0 +1,323 @@
+use std::{collections::BTreeMap, path::Path};
+
+use unicode_width::UnicodeWidthChar;
+
+// usernames longer than this are cut when they head a line
+pub const MAX_NAME_COLUMNS: usize = 16;
+const ELLIPSIS: char = '…';
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+const BUILTIN_SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("beer", "🍺"),
+    ("bug", "🐛"),
+    ("cake", "🍰"),
+    ("check", "✅"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("muscle", "💪"),
+    ("ok_hand", "👌"),
+    ("party", "🥳"),
+    ("pizza", "🍕"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("smile", "😄"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsup", "👍"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("wink", "😉"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+];
+
+// `:name:` to emoji, the built-in table plus whatever the shortcode file adds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortcodes {
+    table: BTreeMap<String, String>,
+    enabled: bool,
+}
+
+impl Default for Shortcodes {
+    fn default() -> Self {
+        Self {
+            table: BUILTIN_SHORTCODES
+                .iter()
+                .map(|(name, emoji)| (name.to_string(), emoji.to_string()))
+                .collect(),
+            enabled: true,
+        }
+    }
+}
+
+impl Shortcodes {
+    // text passes through untouched
+    pub fn disabled() -> Self {
+        Self {
+            table: BTreeMap::new(),
+            enabled: false,
+        }
+    }
+
+    // `name = "emoji"` lines, a name that is built in is replaced
+    pub fn with_extras(mut self, content: &str) -> Result<Self, String> {
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let problem = |e: &str| format!("line {}: {}", number + 1, e);
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| problem("expected '<name> = \"...\"'"))?;
+            let name = name.trim().trim_matches(':');
+            if name.is_empty() || !name.chars().all(is_shortcode_char) {
+                return Err(problem(&format!("invalid shortcode name '{}'", name)));
+            }
+            let value = value
+                .trim()
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| problem("the replacement has to be quoted"))?;
+            self.table.insert(name.to_string(), value.to_string());
+        }
+        Ok(self)
+    }
+
+    // a missing file adds nothing
+    pub fn load(self, path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => self.with_extras(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(self),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.table.get(name).map(String::as_str)
+    }
+
+    // unknown names stay as they were, their closing colon may still open the next shortcode
+    pub fn expand(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(':') {
+            expanded.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let name_length = after.find(|c| !is_shortcode_char(c)).unwrap_or(after.len());
+            let emoji = Some(&after[..name_length])
+                .filter(|_| after[name_length..].starts_with(':'))
+                .and_then(|name| self.get(name));
+            match emoji {
+                Some(emoji) => {
+                    expanded.push_str(emoji);
+                    rest = &after[name_length + 1..];
+                }
+                None => {
+                    expanded.push(':');
+                    rest = after;
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '+' | '-')
+}
+
+// what the terminal draws for one user-perceived character, good enough for emoji, CJK and accents
+pub fn graphemes(text: &str) -> Vec<&str> {
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<char> = None;
+    let mut regional_indicators = 0;
+
+    for (index, c) in text.char_indices() {
+        let joins = match previous {
+            None => false,
+            Some(ZERO_WIDTH_JOINER) => true,
+            // two regional indicators make a flag, a third starts the next one
+            Some(_) if is_regional_indicator(c) => regional_indicators % 2 == 1,
+            Some(_) => extends(c),
+        };
+        if !joins && index > 0 {
+            clusters.push(&text[start..index]);
+            start = index;
+        }
+        regional_indicators = if is_regional_indicator(c) {
+            regional_indicators + 1
+        } else {
+            0
+        };
+        previous = Some(c);
+    }
+    if start < text.len() {
+        clusters.push(&text[start..]);
+    }
+    clusters
+}
+
+// columns one grapheme takes, emoji sequences are as wide as the emoji they start with
+pub fn cluster_width(cluster: &str) -> usize {
+    let mut chars = cluster.chars();
+    let Some(first) = chars.next() else {
+        return 0;
+    };
+    if is_regional_indicator(first) {
+        return 2;
+    }
+    let width = char_width(first);
+    if width == 1 && cluster.contains(EMOJI_PRESENTATION) {
+        return 2;
+    }
+    width
+}
+
+pub fn width(text: &str) -> usize {
+    graphemes(text).into_iter().map(cluster_width).sum()
+}
+
+// at most `columns` wide, with an ellipsis where something was cut, never in the middle of a grapheme
+pub fn truncate(text: &str, columns: usize) -> String {
+    if width(text) <= columns {
+        return text.to_string();
+    }
+    if columns == 0 {
+        return String::new();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for cluster in graphemes(text) {
+        let cluster_width = cluster_width(cluster);
+        if used + cluster_width > columns - 1 {
+            break;
+        }
+        truncated.push_str(cluster);
+        used += cluster_width;
+    }
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+// lines of at most `columns`, broken at spaces where possible and inside words that do not fit a line
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(2);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut used = 0;
+
+    for word in text.split(' ') {
+        let word_width = width(word);
+        let space = usize::from(!line.is_empty());
+        if used + space + word_width <= columns {
+            if space == 1 {
+                line.push(' ');
+            }
+            line.push_str(word);
+            used += space + word_width;
+            continue;
+        }
+
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+            used = 0;
+        }
+        for cluster in graphemes(word) {
+            let cluster_width = cluster_width(cluster);
+            if used + cluster_width > columns {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            }
+            line.push_str(cluster);
+            used += cluster_width;
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// every column as wide as its widest cell, two spaces apart, the last one is not padded
+pub fn table(rows: &[Vec<String>]) -> Vec<String> {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (column, cell) in row.iter().enumerate() {
+            match widths.get_mut(column) {
+                Some(widest) => *widest = (*widest).max(width(cell)),
+                None => widths.push(width(cell)),
+            }
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (column, cell) in row.iter().enumerate() {
+                line.push_str(cell);
+                if column + 1 < row.len() {
+                    line.push_str(&" ".repeat(widths[column] - width(cell) + 2));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+// marks, selectors and modifiers that belong to the character before them, and whatever else draws no column
+fn extends(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036f}'
+        | '\u{1ab0}'..='\u{1aff}'
+        | '\u{1dc0}'..='\u{1dff}'
+        | '\u{20d0}'..='\u{20ff}'
+        | '\u{fe00}'..='\u{fe0f}'
+        | '\u{fe20}'..='\u{fe2f}'
+        | ZERO_WIDTH_JOINER
+        | '\u{1f3fb}'..='\u{1f3ff}'
+        | '\u{e0020}'..='\u{e007f}'
+        | '\u{e0100}'..='\u{e01ef}'
+    ) || (!c.is_control() && UnicodeWidthChar::width(c) == Some(0))
+}
+
+// wide and fullwidth characters take two columns, the unicode-width tables come from EastAsianWidth.txt
+fn char_width(c: char) -> usize {
+    if c.is_control() || extends(c) || matches!(c, '\u{200b}'..='\u{200f}') {
+        return 0;
+    }
+    UnicodeWidthChar::width(c).unwrap_or(0)
+}
@@ -0,0 +1,99 @@
+use std::io::IsTerminal;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+// red and yellow are left out, a name should never look like an error or a warning
+const NAME_COLORS: &[&str] = &[
+    "\x1b[32m", "\x1b[34m", "\x1b[35m", "\x1b[36m", "\x1b[92m", "\x1b[94m", "\x1b[95m", "\x1b[96m",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    Always,
+    #[default]
+    Auto,
+    Never,
+}
+
+// what a line on the terminal is about, each class has its own look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    // messages from other users, only the sender names are colored
+    Incoming,
+    // what the user sent themselves
+    Own,
+    System,
+    Warning,
+    Error,
+}
+
+// everything the line-mode client shows the user goes through here, logs stay on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    color: bool,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "always" => Ok(Self::Always),
+            "auto" => Ok(Self::Auto),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("expected 'always', 'auto' or 'never', got '{}'", value)),
+        }
+    }
+
+    // auto colors a terminal unless NO_COLOR is set to anything but an empty string
+    pub fn resolve(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Auto => is_terminal && !no_color,
+            Self::Never => false,
+        }
+    }
+}
+
+impl Theme {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    pub fn detect(choice: ColorChoice) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self::new(choice.resolve(std::io::stdout().is_terminal(), no_color))
+    }
+
+    pub fn paint(&self, class: Class, text: &str) -> String {
+        let style = match class {
+            Class::Incoming => return text.to_string(),
+            Class::Own => BOLD,
+            Class::System => DIM,
+            Class::Warning => YELLOW,
+            Class::Error => RED,
+        };
+        self.styled(style, text)
+    }
+
+    // the same name always gets the same color, in every session and on every machine
+    pub fn name(&self, name: &str) -> String {
+        let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        });
+        self.styled(NAME_COLORS[hash as usize % NAME_COLORS.len()], name)
+    }
+
+    pub fn print(&self, class: Class, text: &str) {
+        println!("{}", self.paint(class, text));
+    }
+
+    fn styled(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
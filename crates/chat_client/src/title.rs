@@ -0,0 +1,200 @@
+use crate::client::{ConnectionState, Contact, MAX_QUALITY};
+
+pub const APP_NAME: &str = "chat_rs";
+
+// what the terminal title and the prompt say about the session, fed by the client's events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionStatus {
+    state: ConnectionState,
+    username: Option<String>,
+    server: Option<String>,
+    // messages that arrived since the user last looked
+    unread: u64,
+    // the conversation the user is in, what arrives there is read as it comes in
+    active_peer: Option<String>,
+    // where a line typed without a command goes, shown last in the prompt
+    conversation: Option<String>,
+    // only shown while the server is heard but does not echo us
+    contact: Option<Contact>,
+    // from 0 to MAX_QUALITY, shown while ready
+    quality: Option<u8>,
+    // what the title was set to last, None until it was set once
+    shown: Option<String>,
+}
+
+impl SessionStatus {
+    pub fn new() -> Self {
+        Self {
+            state: ConnectionState::Disconnected,
+            username: None,
+            server: None,
+            unread: 0,
+            active_peer: None,
+            conversation: None,
+            contact: None,
+            quality: None,
+            shown: None,
+        }
+    }
+
+    pub fn set_state(&mut self, state: ConnectionState) {
+        self.state = state;
+    }
+
+    pub fn set_username(&mut self, username: Option<String>) {
+        self.username = username;
+    }
+
+    pub fn set_server(&mut self, server: Option<String>) {
+        self.server = server;
+    }
+
+    pub fn set_active_peer(&mut self, peer: &str) {
+        self.active_peer = Some(peer.to_string());
+    }
+
+    pub fn set_conversation(&mut self, conversation: Option<String>) {
+        if let Some(peer) = &conversation {
+            self.set_active_peer(peer);
+        }
+        self.conversation = conversation;
+    }
+
+    pub fn set_contact(&mut self, contact: Contact) {
+        self.contact = Some(contact);
+    }
+
+    pub fn set_quality(&mut self, quality: u8) {
+        self.quality = Some(quality.min(MAX_QUALITY));
+    }
+
+    pub fn record_incoming(&mut self, sender: &str) {
+        if self.active_peer.as_deref() != Some(sender) {
+            self.unread += 1;
+        }
+    }
+
+    // the server's count of what waited while the user was away
+    pub fn set_unread(&mut self, unread: u64) {
+        self.unread = unread;
+    }
+
+    // everything on the screen counts as seen once the user enters a line
+    pub fn acknowledge(&mut self) {
+        self.unread = 0;
+    }
+
+    pub fn unread(&self) -> u64 {
+        self.unread
+    }
+
+    // chat_rs — bob@server ▂▄▆· (2 unread), the state is only named while it is not ready
+    pub fn title(&self) -> String {
+        let who = match (&self.username, &self.server) {
+            (Some(username), Some(server)) if self.state == ConnectionState::Ready => {
+                format!("{}@{}", username, server)
+            }
+            (Some(username), None) if self.state == ConnectionState::Ready => username.clone(),
+            (_, Some(server)) => server.clone(),
+            (_, None) => String::new(),
+        };
+        let mut title = match who.as_str() {
+            "" => APP_NAME.to_string(),
+            who => format!("{} — {}", APP_NAME, who),
+        };
+        if self.state != ConnectionState::Ready {
+            title.push_str(&format!(" [{}]", self.state));
+        }
+        if let Some(bars) = self.quality().map(quality_bars) {
+            title.push_str(&format!(" {}", bars));
+        }
+        if let Some(one_way) = self.one_way() {
+            title.push_str(&format!(" [{}]", one_way));
+        }
+        if self.unread > 0 {
+            title.push_str(&format!(" ({} unread)", self.unread));
+        }
+        title
+    }
+
+    // the server's name and how good the connection is once logged in, until then also what the client is
+    // waiting for, then the active conversation
+    pub fn prompt(&self) -> String {
+        let prompt = match (&self.server, self.state, self.quality().map(quality_word)) {
+            (Some(name), ConnectionState::Ready, Some(word)) => format!("[{}, {}] ", name, word),
+            (Some(name), ConnectionState::Ready, None) => format!("[{}] ", name),
+            (Some(name), state, _) => format!("[{}: {}] ", name, state),
+            (None, ConnectionState::Ready, Some(word)) => format!("[{}] ", word),
+            (None, ConnectionState::Ready, None) => String::new(),
+            (None, state, _) => format!("[{}] ", state),
+        };
+        let prompt = match self.one_way() {
+            Some(one_way) => format!("{}({}) ", prompt, one_way),
+            None => prompt,
+        };
+        let prompt = match self.unread {
+            0 => prompt,
+            unread => format!("{}({} unread) ", prompt, unread),
+        };
+        match &self.conversation {
+            Some(conversation) => format!("{}{}> ", prompt, conversation),
+            None => prompt,
+        }
+    }
+
+    // a lost connection has none
+    fn quality(&self) -> Option<u8> {
+        self.quality.filter(|_| self.state == ConnectionState::Ready)
+    }
+
+    // heard 2s ago, no echo for 95s
+    fn one_way(&self) -> Option<String> {
+        let contact = self.contact.filter(Contact::is_one_way)?;
+        Some(format!(
+            "heard {}s ago, no echo for {}s",
+            contact.received.as_secs(),
+            contact.two_way?.as_secs()
+        ))
+    }
+
+    // the title if it is not the one shown already
+    pub fn title_change(&mut self) -> Option<String> {
+        let title = self.title();
+        if self.shown.as_ref() == Some(&title) {
+            return None;
+        }
+        self.shown = Some(title.clone());
+        Some(title)
+    }
+}
+
+impl Default for SessionStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// signal bars, the missing ones as dots
+pub fn quality_bars(quality: u8) -> String {
+    "▂▄▆█"
+        .chars()
+        .enumerate()
+        .map(|(index, bar)| if index < quality as usize { bar } else { '·' })
+        .collect()
+}
+
+pub fn quality_word(quality: u8) -> &'static str {
+    match quality {
+        0 => "bad",
+        1 => "poor",
+        2 => "fair",
+        3 => "good",
+        _ => "great",
+    }
+}
+
+// the xterm sequence that sets the window title, control characters in names would end it early
+pub fn title_sequence(title: &str) -> String {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    format!("\x1b]0;{}\x07", title)
+}
@@ -0,0 +1,238 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use chat_core::{
+    json::JsonValue,
+    trace::{from_hex, to_hex},
+};
+
+// the start of every encrypted file, anything else on disk is plaintext
+pub const SEALED_HEADER: &[u8] = b"chat_rs sealed 1\n";
+const NONCE_LENGTH: usize = 12;
+const SALT_LENGTH: usize = 16;
+const KEY_LENGTH: u32 = 32;
+// sealed into the vault file, opening it proves the key before any other file is touched
+const CHECK: &[u8] = b"chat_rs vault";
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "chat_rs";
+
+// where the key of the encrypted client files comes from
+#[derive(Clone)]
+pub enum KeySource {
+    Passphrase(String),
+    // a random key kept by the OS, nothing to type at startup
+    #[cfg(feature = "keyring")]
+    Keyring,
+}
+
+// the key for history and drafts, it only exists once the vault file agreed with it
+pub struct Vault {
+    cipher: ChaCha20Poly1305,
+}
+
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Passphrase(_) => f.write_str("Passphrase(..)"),
+            #[cfg(feature = "keyring")]
+            KeySource::Keyring => f.write_str("Keyring"),
+        }
+    }
+}
+
+impl fmt::Debug for Vault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Vault(..)")
+    }
+}
+
+impl KeySource {
+    fn name(&self) -> &'static str {
+        match self {
+            KeySource::Passphrase(_) => "passphrase",
+            #[cfg(feature = "keyring")]
+            KeySource::Keyring => "keyring",
+        }
+    }
+}
+
+impl Vault {
+    // the vault file holds the salt and a sealed check value, never the key
+    pub fn create(path: &Path, source: &KeySource) -> Result<Self, String> {
+        if path.exists() {
+            return Err(format!("{} already exists", path.display()));
+        }
+        let mut salt = [0; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        let vault = Self::with_key(&new_key(source, path, &salt)?);
+
+        let content = JsonValue::object()
+            .with("version", 1u64)
+            .with("source", source.name())
+            .with("salt", to_hex(&salt).as_str())
+            .with("check", to_hex(&vault.seal(CHECK)).as_str());
+        write_atomically(path, format!("{}\n", content).as_bytes()).map_err(|e| e.to_string())?;
+        Ok(vault)
+    }
+
+    // a wrong passphrase ends here, before a single file was read or written
+    pub fn unlock(path: &Path, source: &KeySource) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let problem = |e: &str| format!("{}: {}", path.display(), e);
+        let content = JsonValue::parse(&content).map_err(|e| problem(&e))?;
+        let field = |key: &str| {
+            content
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| problem("not a vault file"))
+        };
+
+        if field("source")? != source.name() {
+            return Err(problem(&format!("the key comes from the {}", field("source")?)));
+        }
+        let salt = from_hex(field("salt")?).map_err(|e: String| problem(&e))?;
+        let check = from_hex(field("check")?).map_err(|e: String| problem(&e))?;
+
+        let vault = Self::with_key(&stored_key(source, path, &salt)?);
+        match vault.open(&check) {
+            Ok(check) if check == CHECK => Ok(vault),
+            _ => Err(problem("wrong passphrase or key")),
+        }
+    }
+
+    // a first start sets the vault up, every later one has to unlock it
+    pub fn open_or_create(path: &Path, source: &KeySource) -> Result<Self, String> {
+        match path.exists() {
+            true => Self::unlock(path, source),
+            false => Self::create(path, source),
+        }
+    }
+
+    // a fresh nonce for every write, the same content never looks the same twice
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("Encrypting into memory does not fail");
+        [SEALED_HEADER, nonce.as_slice(), &ciphertext].concat()
+    }
+
+    // the tag covers every byte, a changed or truncated file is refused as a whole
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let body = sealed.strip_prefix(SEALED_HEADER).ok_or("not encrypted")?;
+        if body.len() < NONCE_LENGTH {
+            return Err("damaged, too short".to_string());
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "could not be decrypted, it is damaged or sealed with another key".to_string())
+    }
+
+    fn with_key(key: &[u8]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+}
+
+pub fn is_sealed(content: &[u8]) -> bool {
+    content.starts_with(SEALED_HEADER)
+}
+
+// with a vault only sealed files are read, without one only plaintext, so nothing is ever mistaken for the other
+pub fn read_file(path: &Path, vault: Option<&Vault>) -> io::Result<String> {
+    let content = std::fs::read(path)?;
+    let content = match (vault, is_sealed(&content)) {
+        (Some(vault), true) => vault.open(&content).map_err(invalid_data)?,
+        (Some(_), false) => return Err(invalid_data("not encrypted, run `client encrypt-files` first")),
+        (None, true) => return Err(invalid_data("encrypted, set VAULT to read it")),
+        (None, false) => content,
+    };
+    String::from_utf8(content).map_err(|_| invalid_data("not UTF-8"))
+}
+
+// through a temporary file, a crash while writing keeps the previous content
+pub fn write_file(path: &Path, content: &str, vault: Option<&Vault>) -> io::Result<()> {
+    match vault {
+        Some(vault) => write_atomically(path, &vault.seal(content.as_bytes())),
+        None => write_atomically(path, content.as_bytes()),
+    }
+}
+
+// seals the plaintext files among these in place, returns the ones that were
+pub fn encrypt_files(vault: &Vault, paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut encrypted = Vec::new();
+    for path in paths {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if is_sealed(&content) {
+            continue;
+        }
+        write_atomically(path, &vault.seal(&content))?;
+        encrypted.push(path.clone());
+    }
+    Ok(encrypted)
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, content)?;
+    std::fs::rename(&temporary, path)
+}
+
+fn invalid_data(e: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.into())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, String> {
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        hash_length: KEY_LENGTH,
+        ..argon2::Config::default()
+    };
+    argon2::hash_raw(passphrase.as_bytes(), salt, &config).map_err(|e| e.to_string())
+}
+
+// only a keyring key is new, a passphrase derives the same one every time
+fn new_key(source: &KeySource, _path: &Path, salt: &[u8]) -> Result<Vec<u8>, String> {
+    match source {
+        KeySource::Passphrase(passphrase) => derive_key(passphrase, salt),
+        #[cfg(feature = "keyring")]
+        KeySource::Keyring => {
+            let mut key = vec![0; KEY_LENGTH as usize];
+            OsRng.fill_bytes(&mut key);
+            keyring_entry(_path)?
+                .set_secret(&key)
+                .map_err(|e| format!("Could not store the key in the keyring: {}", e))?;
+            Ok(key)
+        }
+    }
+}
+
+fn stored_key(source: &KeySource, _path: &Path, salt: &[u8]) -> Result<Vec<u8>, String> {
+    match source {
+        KeySource::Passphrase(passphrase) => derive_key(passphrase, salt),
+        #[cfg(feature = "keyring")]
+        KeySource::Keyring => keyring_entry(_path)?
+            .get_secret()
+            .map_err(|e| format!("Could not read the key from the keyring: {}", e)),
+    }
+}
+
+// one key per vault file, two client setups on the same account do not share one
+#[cfg(feature = "keyring")]
+fn keyring_entry(path: &Path) -> Result<keyring::Entry, String> {
+    let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+    keyring::Entry::new(KEYRING_SERVICE, &path.display().to_string()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "cli")]
+
+use chat_client::aliases::{Aliases, Definition, MAX_EXPANSIONS};
+
+const CONFIG: &str = r#"
+# shortcuts
+alias w = "msg"
+macro standup = "msg team Daily standup starting"
+macro ping = "msg $1 ping from $2"
+macro shout = "note $*"
+"#;
+
+#[test]
+fn builtin_and_configured_aliases_expand() {
+    let aliases = Aliases::parse(CONFIG).unwrap();
+
+    assert_eq!(aliases.expand("m bob hi there"), Ok("msg bob hi there".to_string()));
+    assert_eq!(aliases.expand("w bob hi"), Ok("msg bob hi".to_string()));
+    assert_eq!(
+        aliases.expand("standup"),
+        Ok("msg team Daily standup starting".to_string())
+    );
+    // real commands and unknown words pass through untouched
+    assert_eq!(aliases.expand("history bob 5"), Ok("history bob 5".to_string()));
+    assert_eq!(aliases.expand("frobnicate"), Ok("frobnicate".to_string()));
+}
+
+#[test]
+fn macros_take_parameters() {
+    let aliases = Aliases::parse(CONFIG).unwrap();
+
+    assert_eq!(
+        aliases.expand("ping bob alice"),
+        Ok("msg bob ping from alice".to_string())
+    );
+    assert_eq!(
+        aliases.expand("shout buy  more milk"),
+        Ok("note buy  more milk".to_string())
+    );
+    assert_eq!(
+        aliases.expand("ping bob"),
+        Err("ping needs at least 2 arguments".to_string())
+    );
+}
+
+#[test]
+fn cycles_are_refused_when_defined() {
+    let mut aliases = Aliases::default();
+    aliases.define("a", Definition::Alias("b".into())).unwrap();
+    aliases.define("b", Definition::Macro("c $*".into())).unwrap();
+
+    assert_eq!(
+        aliases.define("c", Definition::Alias("a".into())),
+        Err("Alias cycle: c -> a -> b -> c".to_string())
+    );
+    assert_eq!(
+        aliases.define("me", Definition::Alias("me".into())),
+        Err("Alias cycle: me -> me".to_string())
+    );
+    // nothing was kept from the refused definitions
+    assert_eq!(aliases.expand("a x"), Ok("c x".to_string()));
+    assert!(Aliases::parse("alias x = \"y\"\nalias y = \"x\"\n").is_err());
+}
+
+#[test]
+fn nesting_is_limited() {
+    let mut aliases = Aliases::default();
+    // defined front to back, nothing is defined yet where a new alias points
+    for level in 0..MAX_EXPANSIONS + 4 {
+        aliases
+            .define(&format!("a{}", level), Definition::Alias(format!("a{}", level + 1)))
+            .unwrap();
+    }
+
+    assert_eq!(aliases.expand("a10"), Ok(format!("a{}", MAX_EXPANSIONS + 4)));
+    assert_eq!(
+        aliases.expand("a0"),
+        Err(format!("a0 nests more than {} aliases", MAX_EXPANSIONS))
+    );
+}
+
+#[test]
+fn definitions_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("chat_rs_aliases_{}", std::process::id()));
+    let mut aliases = Aliases::parse(CONFIG).unwrap();
+    aliases
+        .define("later", Definition::guess("msg $1 see you later"))
+        .unwrap();
+    aliases.define("m", Definition::guess("note")).unwrap();
+    assert!(aliases.remove("w"));
+    aliases.save(&path).unwrap();
+
+    let loaded = Aliases::load(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(loaded, aliases);
+    assert_eq!(loaded.expand("later bob"), Ok("msg bob see you later".to_string()));
+    // the user definition shadows the built-in one
+    assert_eq!(loaded.expand("m hi"), Ok("note hi".to_string()));
+    assert_eq!(loaded.expand("w bob"), Ok("w bob".to_string()));
+}
+
+#[test]
+fn a_missing_file_is_an_empty_list() {
+    let path = std::env::temp_dir().join(format!("chat_rs_no_aliases_{}", std::process::id()));
+    assert_eq!(Aliases::load(&path), Ok(Aliases::default()));
+    assert_eq!(
+        Aliases::parse("alias broken = msg"),
+        Err("line 1: the expansion has to be quoted".to_string())
+    );
+}
@@ -0,0 +1,116 @@
+#![cfg(feature = "cli")]
+
+use chat_client::{
+    client::{command_argument, COMMANDS},
+    completion::{Completer, Completion},
+};
+
+fn seeded() -> Completer {
+    let mut completer = Completer::default();
+    for username in ["alice", "albert", "bob"] {
+        completer.add_username(username);
+    }
+    completer.set_conversations(["alice".to_string(), "bob".to_string(), "bobby".to_string()]);
+    completer
+}
+
+fn complete(completer: &mut Completer, completion: Completion, line: &str) -> String {
+    completer.complete(completion, line).0
+}
+
+#[test]
+fn commands_come_from_the_command_table() {
+    let mut completer = seeded();
+    assert_eq!(complete(&mut completer, Completion::Command, "fing"), "fingerprint ");
+    // a slash in front stays, the way commands are typed in a conversation
+    assert_eq!(complete(&mut completer, Completion::Command, "/fing"), "/fingerprint ");
+
+    let (line, candidates) = completer.complete(Completion::Command, "un");
+    assert_eq!(line, "un");
+    assert_eq!(
+        candidates,
+        [
+            "unalias",
+            "unban",
+            "undrain",
+            "ungrant",
+            "unmute",
+            "unschedule",
+            "untoken"
+        ]
+    );
+    assert_eq!(completer.candidates(Completion::Command, "").len(), COMMANDS.len());
+    assert!(completer
+        .candidates(Completion::Command, "")
+        .iter()
+        .all(|command| command_argument(command).is_some()));
+    assert!(completer.candidates(Completion::Command, "nosuch").is_empty());
+}
+
+#[test]
+fn the_first_argument_completes_what_the_command_takes() {
+    let mut completer = seeded();
+    assert_eq!(complete(&mut completer, Completion::Command, "msg b"), "msg bob ");
+    assert_eq!(
+        complete(&mut completer, Completion::Command, "/trust ali"),
+        "/trust alice "
+    );
+    // common prefixes are filled in before anything is listed
+    let (line, candidates) = completer.complete(Completion::Command, "msg a");
+    assert_eq!(line, "msg al");
+    assert_eq!(candidates, ["albert", "alice"]);
+
+    // text arguments and later words are left alone
+    assert_eq!(complete(&mut completer, Completion::Command, "note b"), "note b");
+    assert_eq!(complete(&mut completer, Completion::Command, "msg bob b"), "msg bob b");
+
+    // a recipient asked for on its own is a username
+    assert_eq!(complete(&mut completer, Completion::Username, "b"), "bob ");
+    assert_eq!(complete(&mut completer, Completion::Nothing, "b"), "b");
+}
+
+#[test]
+fn conversations_complete_from_the_open_ones() {
+    let mut completer = seeded();
+    assert_eq!(
+        complete(&mut completer, Completion::Command, "switch a"),
+        "switch alice "
+    );
+    let (line, candidates) = completer.complete(Completion::Command, "close bo");
+    assert_eq!(line, "close bob");
+    assert_eq!(candidates, ["bob", "bobby"]);
+
+    // a closed one is not offered anymore, one that just opened is
+    completer.set_conversations(["bobby".to_string()]);
+    completer.add_conversation("carol");
+    assert_eq!(
+        complete(&mut completer, Completion::Command, "switch bo"),
+        "switch bobby "
+    );
+    assert_eq!(
+        complete(&mut completer, Completion::Command, "switch c"),
+        "switch carol "
+    );
+    assert_eq!(complete(&mut completer, Completion::Command, "switch a"), "switch a");
+}
+
+#[test]
+fn repeated_tabs_cycle_through_the_candidates() {
+    let mut completer = seeded();
+    // the first tab lists them, every further one puts the next in place
+    let (line, candidates) = completer.complete(Completion::Command, "msg al");
+    assert_eq!((line.as_str(), candidates.len()), ("msg al", 2));
+    let line = complete(&mut completer, Completion::Command, &line);
+    assert_eq!(line, "msg albert");
+    let line = complete(&mut completer, Completion::Command, &line);
+    assert_eq!(line, "msg alice");
+    let line = complete(&mut completer, Completion::Command, &line);
+    assert_eq!(line, "msg albert");
+
+    // typing ends the cycle, the line is completed anew
+    assert_eq!(complete(&mut completer, Completion::Command, "msg alb"), "msg albert ");
+    let (line, _) = completer.complete(Completion::Command, "mute");
+    assert_eq!(line, "mute");
+    assert_eq!(complete(&mut completer, Completion::Command, &line), "mute");
+    assert_eq!(complete(&mut completer, Completion::Command, "mute"), "mutes");
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+
+use chat_client::client::{CommandError, ConnectionInput, ConnectionState, InvalidTransition};
+
+const INPUTS: [ConnectionInput; 8] = [
+    ConnectionInput::Connect,
+    ConnectionInput::Opened,
+    ConnectionInput::LoginSent,
+    ConnectionInput::Authenticated,
+    ConnectionInput::AuthFailed,
+    ConnectionInput::ReauthRequired,
+    ConnectionInput::Drain,
+    ConnectionInput::Lost,
+];
+
+// every change of state the client may ever make
+fn allowed() -> HashSet<(ConnectionState, ConnectionState)> {
+    use ConnectionState::*;
+
+    HashSet::from([
+        (Disconnected, Connecting),
+        (Connecting, Connected),
+        (Connecting, Disconnected),
+        (Connected, Authenticating),
+        (Connected, Draining),
+        (Connected, Disconnected),
+        (Authenticating, Ready),
+        (Authenticating, Connected),
+        (Authenticating, Draining),
+        (Authenticating, Disconnected),
+        (Ready, Authenticating),
+        (Ready, Reauthenticating),
+        (Ready, Draining),
+        (Ready, Disconnected),
+        (Reauthenticating, Ready),
+        (Reauthenticating, Connected),
+        (Reauthenticating, Draining),
+        (Reauthenticating, Disconnected),
+        (Draining, Disconnected),
+    ])
+}
+
+// xorshift, the sequences only have to be varied and repeatable
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+#[test]
+fn random_inputs_never_leave_the_allowed_transitions() {
+    let allowed = allowed();
+    let mut reached = HashSet::new();
+
+    for seed in 1..=500u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let mut state = ConnectionState::Disconnected;
+        for _ in 0..200 {
+            let input = INPUTS[rng.next() % INPUTS.len()];
+            match state.next(input) {
+                Ok(next) if next == state => {}
+                Ok(next) => {
+                    assert!(
+                        allowed.contains(&(state, next)),
+                        "seed {}: {} -> {} on {:?}",
+                        seed,
+                        state,
+                        next,
+                        input
+                    );
+                    reached.insert((state, next));
+                    state = next;
+                }
+                Err(e) => assert_eq!(e, InvalidTransition { from: state, input }),
+            }
+        }
+    }
+
+    // the random walks went everywhere the table allows
+    assert_eq!(reached, allowed);
+}
+
+#[test]
+fn ready_is_only_reached_through_a_login() {
+    for state in ConnectionState::ALL {
+        for input in INPUTS {
+            if state.next(input) == Ok(ConnectionState::Ready) && state != ConnectionState::Ready {
+                assert_eq!(input, ConnectionInput::Authenticated);
+                assert!(matches!(
+                    state,
+                    ConnectionState::Authenticating | ConnectionState::Reauthenticating
+                ));
+            }
+        }
+    }
+    assert!(ConnectionState::Disconnected.next(ConnectionInput::Lost).is_err());
+    assert_eq!(
+        ConnectionState::Draining.next(ConnectionInput::LoginSent),
+        Err(InvalidTransition {
+            from: ConnectionState::Draining,
+            input: ConnectionInput::LoginSent,
+        })
+    );
+}
+
+#[test]
+fn commands_are_judged_by_the_state() {
+    use ConnectionState::*;
+
+    for state in ConnectionState::ALL {
+        // local commands never depend on the connection
+        assert!(state.accepts("outbox", false));
+        assert!(state.accepts("help", false));
+        assert_eq!(state.accepts("history", true), state == Ready);
+        assert_eq!(state.accepts("auth", false), state != Draining);
+    }
+
+    // messages wait in the outbox while a login is on its way
+    assert!(Disconnected.accepts("msg", true));
+    assert!(Reauthenticating.accepts("msg", true));
+    assert!(!Connected.accepts("msg", false));
+    assert!(!Draining.accepts("msg", true));
+    assert!(!Connecting.accepts("new", false));
+    assert!(Connected.accepts("new", false));
+}
+
+#[test]
+fn state_errors_explain_themselves() {
+    let error = |state| CommandError::InvalidState {
+        command: "history".to_string(),
+        state,
+    };
+
+    assert_eq!(
+        error(ConnectionState::Connecting).to_string(),
+        "Cannot use 'history': not connected to the server"
+    );
+    assert_eq!(
+        error(ConnectionState::Authenticating).to_string(),
+        "Cannot use 'history': you must log in first"
+    );
+    assert_eq!(
+        error(ConnectionState::Draining).to_string(),
+        "Cannot use 'history': the connection is closing"
+    );
+}
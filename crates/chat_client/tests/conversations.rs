@@ -0,0 +1,74 @@
+use chat_client::client::{Conversations, ScrollbackLine, SCROLLBACK_LINES};
+use chrono::Utc;
+
+fn line(sender: &str, body: &str) -> ScrollbackLine {
+    ScrollbackLine {
+        sender: sender.to_string(),
+        body: body.to_string(),
+        sent_at: Utc::now(),
+        id: None,
+    }
+}
+
+#[test]
+fn only_other_conversations_count_as_unread() {
+    let mut conversations = Conversations::default();
+    conversations.switch("bob");
+    conversations.record("bob", line("bob", "hi"), true);
+    conversations.record("carol", line("carol", "hi"), true);
+    // a muted sender's lines are kept but not counted
+    conversations.record("dave", line("dave", "buy now"), false);
+    assert_eq!(
+        conversations.list(),
+        vec![
+            ("bob".to_string(), 0),
+            ("carol".to_string(), 1),
+            ("dave".to_string(), 0)
+        ]
+    );
+
+    conversations.switch("carol");
+    assert_eq!(conversations.list()[1], ("carol".to_string(), 0));
+    assert_eq!(conversations.active(), Some("carol"));
+}
+
+#[test]
+fn scrollback_keeps_the_newest_lines() {
+    let mut conversations = Conversations::default();
+    for index in 0..SCROLLBACK_LINES + 5 {
+        conversations.record("bob", line("bob", &index.to_string()), true);
+    }
+    let scrollback = conversations.scrollback("bob").unwrap();
+    assert_eq!(scrollback.len(), SCROLLBACK_LINES);
+    assert_eq!(scrollback[0].body, "5");
+    assert_eq!(conversations.scrollback("carol"), None);
+}
+
+#[test]
+fn cycling_wraps_around_and_needs_a_conversation() {
+    let mut conversations = Conversations::default();
+    assert_eq!(conversations.cycle(true), None);
+
+    conversations.record("bob", line("bob", "hi"), true);
+    conversations.record("alice", line("alice", "hi"), true);
+    assert_eq!(conversations.cycle(false).as_deref(), Some("bob"));
+    assert_eq!(conversations.cycle(true).as_deref(), Some("alice"));
+    assert_eq!(conversations.cycle(false).as_deref(), Some("bob"));
+    assert_eq!(
+        conversations.list(),
+        vec![("alice".to_string(), 0), ("bob".to_string(), 0)]
+    );
+}
+
+#[test]
+fn a_rename_merges_into_an_open_conversation() {
+    let mut conversations = Conversations::default();
+    conversations.record("bob", line("bob", "old name"), true);
+    conversations.record("robert", line("robert", "new name"), true);
+    conversations.switch("bob");
+
+    conversations.rename("bob", "robert");
+    assert_eq!(conversations.active(), Some("robert"));
+    assert_eq!(conversations.list(), vec![("robert".to_string(), 1)]);
+    assert_eq!(conversations.scrollback("robert").unwrap().len(), 2);
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "cli")]
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use chat_client::drafts::{Drafts, MAX_DRAFTS, MAX_DRAFT_CHARS, PROMPT_DRAFT, SAVE_DELAY};
+
+fn draft_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat_rs_drafts_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("drafts.json")
+}
+
+// one change per key, the way the line editor records them
+fn type_text(drafts: &mut Drafts, text: &str, start: Instant) -> Instant {
+    let mut now = start;
+    for end in 1..=text.chars().count() {
+        let typed: String = text.chars().take(end).collect();
+        drafts.set(PROMPT_DRAFT, &typed, now);
+        now += Duration::from_millis(50);
+    }
+    now
+}
+
+#[test]
+fn typed_input_survives_a_restart() {
+    let path = draft_file("restart");
+    let mut drafts = Drafts::load(&path).unwrap();
+    assert!(drafts.is_empty());
+
+    let start = Instant::now();
+    let now = type_text(&mut drafts, "msg bob half a thou", start);
+    assert!(!drafts.save_if_due(now).unwrap(), "saved while still typing");
+    assert!(drafts.save_if_due(now + SAVE_DELAY).unwrap());
+
+    // nothing is flushed on the way out, the process is simply gone
+    drop(drafts);
+    let restored = Drafts::load(&path).unwrap();
+    assert_eq!(restored.get(PROMPT_DRAFT), Some("msg bob half a thou"));
+}
+
+#[test]
+fn only_the_last_changes_are_lost_in_a_crash() {
+    let path = draft_file("crash");
+    let mut drafts = Drafts::load(&path).unwrap();
+    let start = Instant::now();
+    let now = type_text(&mut drafts, "hello", start);
+    drafts.save_if_due(now + SAVE_DELAY).unwrap();
+
+    drafts.set(PROMPT_DRAFT, "hello there", now + SAVE_DELAY * 2);
+    assert!(drafts.is_dirty());
+    drop(drafts);
+
+    assert_eq!(Drafts::load(&path).unwrap().get(PROMPT_DRAFT), Some("hello"));
+}
+
+#[test]
+fn steady_typing_is_still_saved_now_and_then() {
+    let path = draft_file("steady");
+    let mut drafts = Drafts::load(&path).unwrap();
+    let start = Instant::now();
+    let text = "a".repeat(100);
+    let mut saved = false;
+    let mut now = start;
+    for end in 1..=text.len() {
+        drafts.set(PROMPT_DRAFT, &text[..end], now);
+        saved |= drafts.save_if_due(now).unwrap();
+        now += SAVE_DELAY / 5;
+    }
+    assert!(saved);
+}
+
+#[test]
+fn a_sent_line_leaves_no_draft() {
+    let path = draft_file("sent");
+    let mut drafts = Drafts::load(&path).unwrap();
+    let now = type_text(&mut drafts, "note milk", Instant::now());
+    drafts.set(PROMPT_DRAFT, "", now);
+    drafts.save().unwrap();
+
+    assert_eq!(Drafts::load(&path).unwrap().get(PROMPT_DRAFT), None);
+}
+
+#[test]
+fn drafts_are_listed_and_cleared() {
+    let now = Instant::now();
+    let mut drafts = Drafts::default();
+    drafts.set("bob", "see you", now);
+    drafts.set(PROMPT_DRAFT, "search lunch", now);
+
+    let listed: Vec<(&str, &str)> = drafts.entries().collect();
+    assert_eq!(listed, vec![("bob", "see you"), (PROMPT_DRAFT, "search lunch")]);
+
+    assert_eq!(drafts.clear(Some("bob"), now), 1);
+    assert_eq!(drafts.clear(Some("bob"), now), 0);
+    assert_eq!(drafts.clear(None, now), 1);
+    assert!(drafts.is_empty());
+}
+
+#[test]
+fn drafts_are_capped() {
+    let now = Instant::now();
+    let mut drafts = Drafts::default();
+    drafts.set(PROMPT_DRAFT, &"x".repeat(MAX_DRAFT_CHARS + 10), now);
+    assert_eq!(drafts.get(PROMPT_DRAFT).unwrap().chars().count(), MAX_DRAFT_CHARS);
+
+    for index in 1..MAX_DRAFTS {
+        assert!(drafts.set(&format!("peer{}", index), "hi", now));
+    }
+    assert!(!drafts.set("one too many", "hi", now));
+    // the ones that exist can still change
+    assert!(drafts.set("peer1", "hello", now));
+}
+
+#[test]
+fn a_broken_file_is_reported() {
+    let path = draft_file("broken");
+    fs::write(&path, "[1, 2]").unwrap();
+    assert!(Drafts::load(&path).is_err());
+    fs::write(&path, r#"{"prompt": 3}"#).unwrap();
+    assert!(Drafts::load(&path).unwrap_err().contains("prompt"));
+}
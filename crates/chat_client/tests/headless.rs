@@ -0,0 +1,23 @@
+use std::process::Command;
+
+// what CI runs, the example only touches the programmatic client
+#[test]
+fn the_client_builds_without_its_front_end() {
+    // a target dir of its own, the one cargo test runs from is locked
+    let target = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("headless");
+    let output = Command::new(env!("CARGO"))
+        .args([
+            "check",
+            "--package",
+            "chat_client",
+            "--no-default-features",
+            "--example",
+            "headless",
+        ])
+        .env("CARGO_TARGET_DIR", &target)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
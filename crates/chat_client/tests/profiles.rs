@@ -0,0 +1,53 @@
+#![cfg(feature = "cli")]
+
+use chat_client::profiles::{Profile, Profiles};
+
+const CONFIG: &str = r#"
+# nightly jobs
+[ci]
+host = "chat.example.com"
+port = "9000"
+username = "ci-bot"
+password = "secret"
+
+[local]
+host = "127.0.0.1"
+"#;
+
+#[test]
+fn profiles_are_found_by_name() {
+    let profiles = Profiles::parse(CONFIG).unwrap();
+
+    let ci = profiles.get("ci").unwrap();
+    assert_eq!(ci.host.as_deref(), Some("chat.example.com"));
+    assert_eq!(ci.port, Some(9000));
+    assert_eq!(ci.credentials(), Some(("ci-bot", "secret")));
+
+    // a profile without both credentials cannot log in on its own
+    assert_eq!(profiles.get("local").unwrap().credentials(), None);
+    assert_eq!(profiles.get("staging"), None);
+}
+
+#[test]
+fn mistakes_name_the_line() {
+    assert_eq!(
+        Profiles::parse("host = \"a\""),
+        Err("line 1: settings have to follow a [profile] line".to_string())
+    );
+    assert_eq!(
+        Profiles::parse("[ci]\nport = \"many\""),
+        Err("line 2: expected a port, got 'many'".to_string())
+    );
+    assert_eq!(
+        Profiles::parse("[ci]\ncolor = \"red\""),
+        Err("line 2: unknown setting 'color'".to_string())
+    );
+    assert!(Profiles::parse("[ci]\n[ci]").is_err());
+}
+
+#[test]
+fn a_missing_file_has_no_profiles() {
+    let path = std::env::temp_dir().join(format!("chat_rs_no_profiles_{}", std::process::id()));
+    assert_eq!(Profiles::load(&path), Ok(Profiles::default()));
+    assert_eq!(Profile::default().credentials(), None);
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use chat_client::client::{QualitySample, QualityScorer, QualityWeights, MAX_QUALITY};
+
+const KEEPALIVE: Duration = Duration::from_secs(45);
+
+// the level after each sample
+fn trajectory(scorer: &mut QualityScorer, samples: impl IntoIterator<Item = QualitySample>) -> Vec<u8> {
+    samples
+        .into_iter()
+        .map(|sample| {
+            let changed = scorer.record(sample);
+            assert!(changed.is_none() || changed == Some(scorer.level()));
+            scorer.level()
+        })
+        .collect()
+}
+
+// quiet for this many quarters of the keepalive interval
+fn quiet(quarters: u32) -> QualitySample {
+    QualitySample::Heard {
+        gap: KEEPALIVE / 4 * quarters,
+        expected: KEEPALIVE,
+    }
+}
+
+fn round_trip(millis: u64) -> QualitySample {
+    QualitySample::RoundTrip(Duration::from_millis(millis))
+}
+
+#[test]
+fn a_stalled_link_drops_within_one_interval_and_recovers_gradually() {
+    let mut scorer = QualityScorer::new(QualityWeights::default());
+    assert_eq!(scorer.level(), MAX_QUALITY);
+
+    // a healthy server speaks every two thirds of the interval, the rest is silence
+    assert_eq!(trajectory(&mut scorer, (0..6).map(quiet)), [4, 4, 4, 4, 2, 0]);
+    assert_eq!(
+        trajectory(&mut scorer, (0..8).map(|_| quiet(0))),
+        [1, 2, 2, 3, 3, 3, 3, 4]
+    );
+}
+
+#[test]
+fn the_level_does_not_flap_around_a_boundary() {
+    // every sample replaces the last, the round trips alone decide
+    let mut scorer = QualityScorer::new(QualityWeights::parse("smoothing=1").unwrap());
+    // 235ms scores 3.4 and 190ms 3.6, either side of the middle between 3 and 4
+    let samples = [235, 190, 235, 190, 325, 190, 235, 190, 145].map(round_trip);
+    assert_eq!(trajectory(&mut scorer, samples), [4, 4, 4, 4, 3, 3, 3, 3, 4]);
+}
+
+#[test]
+fn reconnects_and_slow_writes_wear_off_with_good_samples() {
+    let mut scorer = QualityScorer::new(QualityWeights::default());
+    let samples = [QualitySample::Reconnect, QualitySample::Reconnect]
+        .into_iter()
+        .chain((0..6).map(|_| round_trip(50)));
+    assert_eq!(trajectory(&mut scorer, samples), [3, 2, 2, 3, 3, 3, 3, 4]);
+
+    let mut scorer = QualityScorer::new(QualityWeights::default());
+    // the third one falls short of the hysteresis, the fourth does not
+    let samples = (0..4).map(|_| QualitySample::SendTimeout);
+    assert_eq!(trajectory(&mut scorer, samples), [3, 2, 2, 1]);
+}
+
+#[test]
+fn weights_tune_what_counts() {
+    let weights = QualityWeights::parse("reconnects=0, round_trip=2").unwrap();
+    assert_eq!(weights.reconnects, 0.0);
+    assert_eq!(weights.round_trip, 2.0);
+    assert_eq!(weights.regularity, QualityWeights::default().regularity);
+
+    let mut scorer = QualityScorer::new(weights);
+    assert_eq!(
+        trajectory(&mut scorer, [QualitySample::Reconnect, QualitySample::Reconnect]),
+        [4, 4]
+    );
+    // twice the weight, a round trip that alone would cost a level costs two
+    assert_eq!(trajectory(&mut scorer, [round_trip(460)]), [3]);
+
+    for weights in ["round_trip", "round_trip=-1", "latency=1", "smoothing=2"] {
+        assert!(QualityWeights::parse(weights).is_err(), "{}", weights);
+    }
+}
@@ -0,0 +1,67 @@
+use chat_client::client::Roster;
+use chat_core::protocol::{
+    PresenceChange, PresenceDelta, PresenceEntry, PresenceScope, PresenceSnapshot, PresenceStatus,
+};
+
+fn entry(user: &str, status: PresenceStatus) -> PresenceEntry {
+    PresenceEntry {
+        user: user.to_string(),
+        status,
+    }
+}
+
+fn delta(version: u64, change: PresenceChange, user: &str, status: PresenceStatus) -> PresenceDelta {
+    PresenceDelta {
+        scope: PresenceScope::All,
+        version,
+        change,
+        entry: entry(user, status),
+    }
+}
+
+fn snapshot(version: u64) -> PresenceSnapshot {
+    PresenceSnapshot {
+        scope: PresenceScope::All,
+        version,
+        entries: vec![entry("alice", PresenceStatus::Online)],
+    }
+}
+
+#[test]
+fn deltas_are_applied_in_order() {
+    let mut roster = Roster::from_snapshot(&snapshot(3));
+
+    assert!(roster.apply(&delta(4, PresenceChange::Joined, "bob", PresenceStatus::Online)));
+    assert!(roster.apply(&delta(
+        5,
+        PresenceChange::StatusChanged,
+        "alice",
+        PresenceStatus::DoNotDisturb
+    )));
+    assert!(roster.apply(&delta(6, PresenceChange::Left, "bob", PresenceStatus::Online)));
+
+    assert_eq!(roster.version(), 6);
+    assert_eq!(roster.entries(), [entry("alice", PresenceStatus::DoNotDisturb)]);
+}
+
+#[test]
+fn a_gap_leaves_the_roster_as_it_was() {
+    let mut roster = Roster::from_snapshot(&snapshot(3));
+
+    assert!(!roster.apply(&delta(5, PresenceChange::Joined, "bob", PresenceStatus::Online)));
+    assert_eq!(roster.version(), 3);
+    assert!(!roster.contains("bob"));
+
+    // what the snapshot already holds is no gap
+    assert!(roster.apply(&delta(2, PresenceChange::Left, "alice", PresenceStatus::Online)));
+    assert!(roster.contains("alice"));
+}
+
+#[test]
+fn scopes_travel_as_text() {
+    for scope in [PresenceScope::All, PresenceScope::Contacts] {
+        assert_eq!(PresenceScope::parse(&scope.encode()), Ok(scope));
+    }
+    assert!(PresenceScope::parse("room:general").is_err());
+    assert!(PresenceScope::parse("everyone").is_err());
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use chat_client::client::parse_schedule_time;
+use chat_core::protocol::ScheduleTime;
+use chrono::{DateTime, FixedOffset, Utc};
+
+fn at(value: &str) -> ScheduleTime {
+    ScheduleTime::At(DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc))
+}
+
+#[test]
+fn clock_times_are_the_next_occurrence_in_the_local_zone() {
+    let now = DateTime::<FixedOffset>::parse_from_rfc3339("2024-03-10T14:30:00+02:00").unwrap();
+
+    assert_eq!(parse_schedule_time("18:00", now), Ok(at("2024-03-10T16:00:00Z")));
+    // already past today, so tomorrow
+    assert_eq!(parse_schedule_time("09:15", now), Ok(at("2024-03-11T07:15:00Z")));
+    assert_eq!(parse_schedule_time("14:30", now), Ok(at("2024-03-11T12:30:00Z")));
+}
+
+#[test]
+fn relative_times_are_left_to_the_server() {
+    let now = Utc::now();
+
+    assert_eq!(
+        parse_schedule_time("30s", now),
+        Ok(ScheduleTime::In(Duration::from_secs(30)))
+    );
+    assert_eq!(
+        parse_schedule_time("10m", now),
+        Ok(ScheduleTime::In(Duration::from_secs(600)))
+    );
+    assert_eq!(
+        parse_schedule_time("2h", now),
+        Ok(ScheduleTime::In(Duration::from_secs(7200)))
+    );
+    assert_eq!(
+        parse_schedule_time("1d", now),
+        Ok(ScheduleTime::In(Duration::from_secs(86400)))
+    );
+    assert_eq!(
+        parse_schedule_time("2024-03-10T16:00:00+01:00", now),
+        Ok(at("2024-03-10T15:00:00Z"))
+    );
+}
+
+#[test]
+fn anything_else_is_refused() {
+    let now = Utc::now();
+
+    for value in ["", "soon", "10", "10w", "m", "25:00", "-5m"] {
+        assert!(parse_schedule_time(value, now).is_err(), "{} was accepted", value);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fs, path::PathBuf};
+
+use chat_client::client::{fingerprint, verify_signature, KnownKeys, SigningIdentity};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat_rs_signing_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_signature_covers_the_sender_the_recipient_and_the_body() {
+    let alice = SigningIdentity::from_seed([7; 32]);
+    let key = alice.public_key();
+    let signature = alice.sign("alice", "bob", "see you at 8").unwrap();
+
+    assert!(verify_signature(&key, "alice", "bob", "see you at 8", &signature));
+    // the server folds names and trims bodies, neither breaks the signature
+    assert!(verify_signature(&key, "Alice", "BOB", "see you at 8 ", &signature));
+
+    assert!(!verify_signature(&key, "alice", "bob", "see you at 9", &signature));
+    assert!(!verify_signature(&key, "alice", "carol", "see you at 8", &signature));
+    assert!(!verify_signature(&key, "mallory", "bob", "see you at 8", &signature));
+    assert!(!verify_signature(&key, "alice", "bob", "see you at 8", &signature[1..]));
+
+    let mallory = SigningIdentity::from_seed([9; 32]);
+    assert!(!verify_signature(
+        &mallory.public_key(),
+        "alice",
+        "bob",
+        "see you at 8",
+        &signature
+    ));
+}
+
+#[test]
+fn fingerprints_are_short_and_stable() {
+    let identity = SigningIdentity::from_seed([7; 32]);
+    let printed = identity.fingerprint();
+    assert_eq!(printed, fingerprint(&identity.public_key()));
+    assert_eq!(printed.len(), 19, "{}", printed);
+    assert_eq!(printed.split(':').count(), 4);
+    assert_ne!(printed, SigningIdentity::from_seed([8; 32]).fingerprint());
+    // the secret stays out of logs
+    assert_eq!(format!("{:?}", identity), format!("SigningIdentity({})", printed));
+}
+
+#[test]
+fn the_key_file_is_made_once_and_read_back() {
+    let dir = scratch_dir("identity");
+    let path = dir.join("key");
+    let created = SigningIdentity::load_or_create(&path).unwrap();
+    let loaded = SigningIdentity::load_or_create(&path).unwrap();
+    assert_eq!(created.public_key(), loaded.public_key());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    fs::write(&path, "not hex").unwrap();
+    assert!(SigningIdentity::load_or_create(&path).is_err());
+}
+
+#[test]
+fn trusted_keys_persist_by_folded_username() {
+    let dir = scratch_dir("known");
+    let path = dir.join("known_keys");
+    let mut known = KnownKeys::load(&path).unwrap();
+    assert!(known.get("bob").is_none());
+
+    let bob = SigningIdentity::from_seed([1; 32]).public_key();
+    known.trust("Bob", bob).unwrap();
+    assert_eq!(known.get("bob"), Some(&bob));
+
+    let reloaded = KnownKeys::load(&path).unwrap();
+    assert_eq!(reloaded.get("BOB"), Some(&bob));
+
+    // a replaced key replaces the file's too
+    let rotated = SigningIdentity::from_seed([2; 32]).public_key();
+    known.trust("bob", rotated).unwrap();
+    assert_eq!(KnownKeys::load(&path).unwrap().get("bob"), Some(&rotated));
+
+    fs::write(&path, r#"{"bob": "abcd"}"#).unwrap();
+    assert!(KnownKeys::load(&path).is_err());
+}
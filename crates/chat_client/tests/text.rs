@@ -0,0 +1,126 @@
+#![cfg(feature = "cli")]
+
+use chat_client::text::{graphemes, table, truncate, width, wrap, Shortcodes};
+
+#[test]
+fn shortcodes_expand_next_to_punctuation() {
+    let shortcodes = Shortcodes::default();
+
+    assert_eq!(shortcodes.expand(":tada:"), "🎉");
+    assert_eq!(shortcodes.expand("shipped:tada:!"), "shipped🎉!");
+    assert_eq!(shortcodes.expand("(:fire:), :+1:."), "(🔥), 👍.");
+    assert_eq!(shortcodes.expand(":tada::tada:"), "🎉🎉");
+}
+
+#[test]
+fn unknown_shortcodes_pass_through() {
+    let shortcodes = Shortcodes::default();
+
+    assert_eq!(shortcodes.expand(":nope: :tada"), ":nope: :tada");
+    assert_eq!(shortcodes.expand("meet at 12:30:45"), "meet at 12:30:45");
+    // the colon that closes an unknown name can open a known one
+    assert_eq!(shortcodes.expand(":nope:tada:"), ":nope🎉");
+    assert_eq!(shortcodes.expand(":Tada: ::"), ":Tada: ::");
+}
+
+#[test]
+fn expansion_can_be_switched_off() {
+    assert_eq!(Shortcodes::disabled().expand("yay :tada:"), "yay :tada:");
+}
+
+#[test]
+fn extra_shortcodes_come_from_config() {
+    let shortcodes = Shortcodes::default()
+        .with_extras("# team\nshipit = \"🐿️\"\n:tada: = \"🥳\"\n")
+        .unwrap();
+    assert_eq!(shortcodes.expand(":shipit: :tada:"), "🐿️ 🥳");
+
+    assert!(Shortcodes::default().with_extras("Ship It = \"x\"").is_err());
+    assert!(Shortcodes::default().with_extras("shipit = x").is_err());
+}
+
+#[test]
+fn clusters_are_never_split() {
+    assert_eq!(graphemes("e\u{301}a"), vec!["e\u{301}", "a"]);
+    assert_eq!(graphemes("👍🏽!"), vec!["👍🏽", "!"]);
+    assert_eq!(graphemes("👩‍💻x"), vec!["👩‍💻", "x"]);
+    assert_eq!(graphemes("🇩🇪🇫🇷"), vec!["🇩🇪", "🇫🇷"]);
+}
+
+#[test]
+fn wide_characters_take_two_columns() {
+    assert_eq!(width("abc"), 3);
+    assert_eq!(width("日本語"), 6);
+    assert_eq!(width("🎉"), 2);
+    assert_eq!(width("❤️"), 2);
+    assert_eq!(width("👩‍💻"), 2);
+    assert_eq!(width("🇩🇪"), 2);
+    assert_eq!(width("e\u{301}"), 1);
+}
+
+#[test]
+fn truncation_fits_the_columns_exactly() {
+    assert_eq!(truncate("alice", 5), "alice");
+    assert_eq!(truncate("alice_the_great", 6), "alice…");
+
+    // a wide character that would end in the last column makes room for the ellipsis instead
+    assert_eq!(truncate("日本語のテキスト", 7), "日本語…");
+    assert_eq!(truncate("日本語のテキスト", 6), "日本…");
+    assert_eq!(width(&truncate("日本語のテキスト", 6)), 5);
+    assert_eq!(truncate("🎉🎉🎉", 6), "🎉🎉🎉");
+    assert_eq!(truncate("🎉🎉🎉", 5), "🎉🎉…");
+    assert_eq!(truncate("👩‍💻👩‍💻", 3), "👩‍💻…");
+    assert_eq!(truncate("abc", 0), "");
+}
+
+#[test]
+fn wrapping_breaks_at_spaces_and_inside_long_words() {
+    assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+    assert_eq!(wrap("日本語のテキスト", 6), vec!["日本語", "のテキ", "スト"]);
+    assert_eq!(wrap("go 🎉🎉🎉", 4), vec!["go", "🎉🎉", "🎉"]);
+    assert_eq!(wrap("", 10), vec![""]);
+    for line in wrap("a 日本語のテキスト and 🎉 more words", 5) {
+        assert!(width(&line) <= 5, "{:?}", line);
+    }
+}
+
+#[test]
+fn table_columns_line_up_by_display_width() {
+    let rows = [
+        vec!["user".to_string(), "peer".to_string(), "in".to_string()],
+        vec!["日本".to_string(), "10.1.0.5:4000".to_string(), "12".to_string()],
+        vec!["guest".to_string(), "[::1]:80".to_string(), "3".to_string()],
+    ];
+    let lines = table(&rows);
+    assert_eq!(lines[0], "user   peer           in");
+    assert_eq!(lines[1], "日本   10.1.0.5:4000  12");
+    assert_eq!(lines[2], "guest  [::1]:80       3");
+    assert!(table(&[]).is_empty());
+}
+
+#[test]
+fn widths_follow_the_east_asian_width_tables() {
+    assert_eq!(width("漢字かなカナ한글"), 16);
+    // blocks past the common ideographs: hangul jamo extended-a, tangut, kana supplement
+    assert_eq!(width("\u{a960}\u{17000}\u{1b000}"), 6);
+    assert_eq!(width("ｆｕｌｌ"), 8);
+    assert_eq!(width("ﾊﾝｶｸ"), 4);
+}
+
+#[test]
+fn emoji_are_wide_only_with_emoji_presentation() {
+    assert_eq!(width("🛖🪿🫠🧋"), 8);
+    // text presentation by default, a selector asks for the emoji
+    assert_eq!(width("🌡🛠"), 2);
+    assert_eq!(width("🌡️🛠️"), 4);
+    assert_eq!(truncate("🪿🪿🪿", 5), "🪿🪿…");
+}
+
+#[test]
+fn combining_marks_stay_with_their_letter() {
+    assert_eq!(width("שָׁלוֹם"), 4);
+    assert_eq!(graphemes("שָׁל"), vec!["שָׁ", "ל"]);
+    assert_eq!(width("กิน"), 2);
+    assert_eq!(width("n\u{303}o\u{308}\u{20dd}"), 2);
+    assert_eq!(truncate("שָׁלוֹם עולם", 4), "שָׁלוֹ…");
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "cli")]
+
+use chat_client::theme::{Class, ColorChoice, Theme};
+
+#[test]
+fn each_class_has_its_own_look() {
+    let theme = Theme::new(true);
+
+    assert_eq!(theme.paint(Class::Incoming, "hi"), "hi");
+    assert_eq!(theme.paint(Class::Own, "sent"), "\x1b[1msent\x1b[0m");
+    assert_eq!(theme.paint(Class::System, "joined"), "\x1b[2mjoined\x1b[0m");
+    assert_eq!(theme.paint(Class::Warning, "slow"), "\x1b[33mslow\x1b[0m");
+    assert_eq!(theme.paint(Class::Error, "failed"), "\x1b[31mfailed\x1b[0m");
+}
+
+#[test]
+fn names_keep_their_color() {
+    let theme = Theme::new(true);
+
+    assert_eq!(theme.name("alice"), "\x1b[96malice\x1b[0m");
+    assert_eq!(theme.name("bob"), "\x1b[92mbob\x1b[0m");
+    assert_eq!(theme.name("alice"), Theme::new(true).name("alice"));
+    for name in ["alice", "bob", "carol", "dave", "erin", "frank"] {
+        let colored = theme.name(name);
+        assert!(
+            !colored.starts_with("\x1b[31m") && !colored.starts_with("\x1b[33m"),
+            "{:?}",
+            colored
+        );
+    }
+}
+
+#[test]
+fn without_color_text_passes_through() {
+    let theme = Theme::new(false);
+
+    for class in [Class::Incoming, Class::Own, Class::System, Class::Warning, Class::Error] {
+        assert_eq!(theme.paint(class, "plain"), "plain");
+    }
+    assert_eq!(theme.name("alice"), "alice");
+}
+
+#[test]
+fn auto_colors_only_a_terminal_without_no_color() {
+    assert!(ColorChoice::Auto.resolve(true, false));
+    assert!(!ColorChoice::Auto.resolve(false, false));
+    assert!(!ColorChoice::Auto.resolve(true, true));
+    assert!(ColorChoice::Always.resolve(false, true));
+    assert!(!ColorChoice::Never.resolve(true, false));
+}
+
+#[test]
+fn parses_the_color_flag() {
+    assert_eq!(ColorChoice::parse("always"), Ok(ColorChoice::Always));
+    assert_eq!(ColorChoice::parse("auto"), Ok(ColorChoice::Auto));
+    assert_eq!(ColorChoice::parse("never"), Ok(ColorChoice::Never));
+    assert!(ColorChoice::parse("sometimes").is_err());
+}
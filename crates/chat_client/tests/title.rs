@@ -0,0 +1,162 @@
+#![cfg(feature = "cli")]
+
+use std::time::Duration;
+
+use chat_client::{
+    client::{ConnectionState, Contact},
+    title::{title_sequence, SessionStatus},
+};
+
+fn logged_in(username: &str, server: &str) -> SessionStatus {
+    let mut status = SessionStatus::new();
+    status.set_state(ConnectionState::Ready);
+    status.set_username(Some(username.to_string()));
+    status.set_server(Some(server.to_string()));
+    status
+}
+
+#[test]
+fn title_names_the_user_and_the_server() {
+    let mut status = logged_in("bob", "server");
+    assert_eq!(status.title(), "chat_rs — bob@server");
+
+    status.set_server(None);
+    assert_eq!(status.title(), "chat_rs — bob");
+}
+
+#[test]
+fn title_shows_the_state_until_ready() {
+    let mut status = SessionStatus::new();
+    assert_eq!(status.title(), "chat_rs [disconnected]");
+
+    status.set_state(ConnectionState::Connected);
+    status.set_server(Some("server".to_string()));
+    assert_eq!(status.title(), "chat_rs — server [connected]");
+
+    // the name stays known while the connection is lost, it is only shown once it is back
+    let mut status = logged_in("bob", "server");
+    status.set_state(ConnectionState::Connecting);
+    assert_eq!(status.title(), "chat_rs — server [connecting]");
+}
+
+#[test]
+fn incoming_messages_count_until_acknowledged() {
+    let mut status = logged_in("bob", "server");
+    status.record_incoming("alice");
+    status.record_incoming("carol");
+    assert_eq!(status.unread(), 2);
+    assert_eq!(status.title(), "chat_rs — bob@server (2 unread)");
+    assert_eq!(status.prompt(), "[server] (2 unread) ");
+
+    status.acknowledge();
+    assert_eq!(status.unread(), 0);
+    assert_eq!(status.title(), "chat_rs — bob@server");
+    assert_eq!(status.prompt(), "[server] ");
+}
+
+#[test]
+fn the_active_conversation_is_read_as_it_arrives() {
+    let mut status = logged_in("bob", "server");
+    status.set_active_peer("alice");
+
+    status.record_incoming("alice");
+    assert_eq!(status.unread(), 0);
+    status.record_incoming("carol");
+    assert_eq!(status.unread(), 1);
+}
+
+#[test]
+fn the_unread_summary_replaces_the_count() {
+    let mut status = logged_in("bob", "server");
+    status.record_incoming("alice");
+    status.set_unread(5);
+    assert_eq!(status.title(), "chat_rs — bob@server (5 unread)");
+}
+
+#[test]
+fn prompt_shows_what_the_client_waits_for() {
+    let mut status = SessionStatus::new();
+    assert_eq!(status.prompt(), "[disconnected] ");
+
+    status.set_state(ConnectionState::Ready);
+    assert_eq!(status.prompt(), "");
+    status.record_incoming("alice");
+    assert_eq!(status.prompt(), "(1 unread) ");
+
+    status.set_server(Some("server".to_string()));
+    status.set_state(ConnectionState::Authenticating);
+    assert_eq!(status.prompt(), "[server: authenticating] (1 unread) ");
+}
+
+#[test]
+fn prompt_ends_with_the_active_conversation() {
+    let mut status = logged_in("bob", "server");
+    status.set_conversation(Some("alice".to_string()));
+    assert_eq!(status.prompt(), "[server] alice> ");
+
+    // what arrives in it is seen already, the rest still counts
+    status.record_incoming("alice");
+    status.record_incoming("carol");
+    assert_eq!(status.prompt(), "[server] (1 unread) alice> ");
+
+    status.set_conversation(None);
+    status.acknowledge();
+    assert_eq!(status.prompt(), "[server] ");
+}
+
+#[test]
+fn a_server_that_stopped_echoing_shows_in_the_prompt() {
+    let mut status = logged_in("bob", "server");
+    let contact = |received: u64, two_way: Option<u64>| Contact {
+        received: Duration::from_secs(received),
+        two_way: two_way.map(Duration::from_secs),
+        expected: Duration::from_secs(45),
+    };
+
+    // quiet in both directions, or a server that never echoes, is nothing to point out
+    status.set_contact(contact(40, Some(60)));
+    assert_eq!(status.prompt(), "[server] ");
+    status.set_contact(contact(2, None));
+    assert_eq!(status.prompt(), "[server] ");
+
+    status.set_contact(contact(2, Some(95)));
+    assert_eq!(status.prompt(), "[server] (heard 2s ago, no echo for 95s) ");
+    assert_eq!(status.title(), "chat_rs — bob@server [heard 2s ago, no echo for 95s]");
+}
+
+#[test]
+fn connection_quality_shows_once_ready() {
+    let mut status = logged_in("bob", "server");
+    status.set_quality(3);
+    assert_eq!(status.title(), "chat_rs — bob@server ▂▄▆·");
+    assert_eq!(status.prompt(), "[server, good] ");
+    status.set_quality(0);
+    assert_eq!(status.title(), "chat_rs — bob@server ····");
+    assert_eq!(status.prompt(), "[server, bad] ");
+
+    // a connection that is not up has no quality to speak of
+    status.set_state(ConnectionState::Connecting);
+    assert!(!status.title().contains('·'));
+    assert!(!status.prompt().contains("bad"));
+}
+
+#[test]
+fn title_is_only_reported_when_it_changes() {
+    let mut status = logged_in("bob", "server");
+    assert_eq!(status.title_change().as_deref(), Some("chat_rs — bob@server"));
+    assert_eq!(status.title_change(), None);
+
+    status.record_incoming("alice");
+    assert_eq!(
+        status.title_change().as_deref(),
+        Some("chat_rs — bob@server (1 unread)")
+    );
+    status.set_state(ConnectionState::Ready);
+    assert_eq!(status.title_change(), None);
+}
+
+#[test]
+fn title_sequence_drops_control_characters() {
+    assert_eq!(title_sequence("chat_rs — bob"), "\x1b]0;chat_rs — bob\x07");
+    assert_eq!(title_sequence("evil\x07\x1b]0;name"), "\x1b]0;evil]0;name\x07");
+}
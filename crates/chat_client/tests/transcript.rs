@@ -0,0 +1,191 @@
+use chat_client::client::{ScrollbackLine, Transcript, TranscriptEntry, TranscriptExport, TranscriptFormat};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+
+fn at(minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 3, 1, 22, 0, 0).unwrap() + Duration::minutes(minute as i64)
+}
+
+fn server(id: u64, sender: &str, body: &str, sent_at: DateTime<Utc>) -> TranscriptEntry {
+    TranscriptEntry {
+        id: Some(id),
+        sender: sender.to_string(),
+        body: body.to_string(),
+        sent_at,
+        edited: false,
+        deleted: false,
+    }
+}
+
+fn local(id: Option<u64>, sender: &str, body: &str, sent_at: DateTime<Utc>) -> TranscriptEntry {
+    TranscriptEntry::from(ScrollbackLine {
+        sender: sender.to_string(),
+        body: body.to_string(),
+        sent_at,
+        id,
+    })
+}
+
+// two hours ahead of utc, so the evening of the first is past midnight
+fn timezone() -> FixedOffset {
+    FixedOffset::east_opt(2 * 3600).unwrap()
+}
+
+// alice's view of her conversation with bob, each side knows messages the other does not
+fn transcript() -> Transcript {
+    let local = vec![
+        local(Some(1), "bob", "hi", at(0)),
+        local(Some(2), "bob", "how r u", at(1)),
+        // our own lines never got their ids, the clock was a few seconds off the server's
+        local(None, "alice", "fine", at(2) + Duration::seconds(4)),
+        local(Some(4), "bob", "oops", at(3)),
+        local(None, "alice", "still there?", at(10)),
+    ];
+    let server = vec![
+        server(0, "bob", "earlier", at(0) - Duration::minutes(10)),
+        server(1, "bob", "hi", at(0)),
+        TranscriptEntry {
+            edited: true,
+            ..server(2, "bob", "how are you", at(1))
+        },
+        server(3, "alice", "fine", at(2)),
+        TranscriptEntry {
+            deleted: true,
+            ..server(4, "bob", "", at(3))
+        },
+    ];
+    Transcript::merge("bob", local, server)
+}
+
+#[test]
+fn overlapping_histories_merge_once_per_message_in_order() {
+    let transcript = transcript();
+    let entries: Vec<(Option<u64>, &str)> = transcript
+        .entries()
+        .iter()
+        .map(|entry| (entry.id, entry.body.as_str()))
+        .collect();
+    // the server's edit and deletion win over the scrollback, our id-less line is its entry
+    assert_eq!(
+        entries,
+        [
+            (Some(0), "earlier"),
+            (Some(1), "hi"),
+            (Some(2), "how are you"),
+            (Some(3), "fine"),
+            (Some(4), ""),
+            (None, "still there?"),
+        ]
+    );
+    assert!(transcript.entries()[2].edited);
+    assert!(transcript.entries()[4].deleted);
+}
+
+#[test]
+fn a_line_of_ours_too_far_from_the_server_entry_is_kept_apart() {
+    let local = vec![local(None, "alice", "ok", at(0))];
+    let server = vec![server(7, "alice", "ok", at(5))];
+    let transcript = Transcript::merge("bob", local, server);
+    assert_eq!(transcript.entries().len(), 2);
+}
+
+#[test]
+fn the_range_counts_days_in_the_time_zone() {
+    let day = NaiveDate::from_ymd_opt(2026, 3, 2).unwrap();
+    let transcript = transcript().between(Some(day), Some(day), &timezone());
+    assert_eq!(transcript.entries().len(), 5);
+    assert_eq!(transcript.entries()[0].id, Some(1));
+
+    let before = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+    let transcript = transcript.between(None, Some(before), &timezone());
+    assert!(transcript.entries().is_empty());
+}
+
+#[test]
+fn text_transcript() {
+    assert_eq!(
+        transcript().render(TranscriptFormat::Text, &timezone()),
+        "Conversation with bob
+
+[2026-03-01 23:50:00] bob: earlier
+[2026-03-02 00:00:00] bob: hi
+[2026-03-02 00:01:00] bob: how are you (edited)
+[2026-03-02 00:02:00] alice: fine
+[2026-03-02 00:03:00] bob: (deleted)
+[2026-03-02 00:10:00] alice: still there?
+"
+    );
+}
+
+#[test]
+fn markdown_transcript() {
+    assert_eq!(
+        transcript().render(TranscriptFormat::Markdown, &timezone()),
+        "# Conversation with bob
+
+- **2026-03-01 23:50:00** bob: earlier
+- **2026-03-02 00:00:00** bob: hi
+- **2026-03-02 00:01:00** bob: how are you *(edited)*
+- **2026-03-02 00:02:00** alice: fine
+- **2026-03-02 00:03:00** bob: *(deleted)*
+- **2026-03-02 00:10:00** alice: still there?
+"
+    );
+}
+
+#[test]
+fn json_transcript() {
+    let message = |id: &str, sender: &str, body: &str, sent_at: &str, edited: bool, deleted: bool| {
+        format!(
+            r#"{{"id":{},"sender":"{}","body":"{}","sent_at":"{}","edited":{},"deleted":{}}}"#,
+            id, sender, body, sent_at, edited, deleted
+        )
+    };
+    let messages = [
+        message("0", "bob", "earlier", "2026-03-01T23:50:00+02:00", false, false),
+        message("1", "bob", "hi", "2026-03-02T00:00:00+02:00", false, false),
+        message("2", "bob", "how are you", "2026-03-02T00:01:00+02:00", true, false),
+        message("3", "alice", "fine", "2026-03-02T00:02:00+02:00", false, false),
+        message("4", "bob", "", "2026-03-02T00:03:00+02:00", false, true),
+        message(
+            "null",
+            "alice",
+            "still there?",
+            "2026-03-02T00:10:00+02:00",
+            false,
+            false,
+        ),
+    ];
+    assert_eq!(
+        transcript().render(TranscriptFormat::Json, &timezone()),
+        format!(r#"{{"peer":"bob","messages":[{}]}}"#, messages.join(",")) + "\n"
+    );
+}
+
+#[test]
+fn export_arguments() {
+    let export = TranscriptExport::parse("bob bob.md --from 2026-03-01 --to 2026-03-02").unwrap();
+    assert_eq!(export.peer, "bob");
+    assert_eq!(export.path.to_str(), Some("bob.md"));
+    // the extension picks the format unless one is asked for
+    assert_eq!(export.format, TranscriptFormat::Markdown);
+    assert_eq!(export.from, NaiveDate::from_ymd_opt(2026, 3, 1));
+    assert_eq!(export.to, NaiveDate::from_ymd_opt(2026, 3, 2));
+    assert_eq!(
+        TranscriptExport::parse("--format json bob bob.md").unwrap().format,
+        TranscriptFormat::Json
+    );
+    assert_eq!(
+        TranscriptExport::parse("bob transcript").unwrap().format,
+        TranscriptFormat::Text
+    );
+
+    for args in [
+        "bob",
+        "bob a.txt b.txt",
+        "bob a.txt --from yesterday",
+        "bob a.txt --format pdf",
+        "bob a.txt --from 2026-03-02 --to 2026-03-01",
+    ] {
+        assert!(TranscriptExport::parse(args).is_err(), "{}", args);
+    }
+}
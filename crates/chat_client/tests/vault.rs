@@ -0,0 +1,102 @@
+#![cfg(feature = "cli")]
+
+use std::{fs, path::PathBuf, sync::Arc, time::Instant};
+
+use chat_client::{
+    drafts::{Drafts, PROMPT_DRAFT},
+    history::InputHistory,
+    vault::{self, KeySource, Vault},
+};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat_rs_vault_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn passphrase(text: &str) -> KeySource {
+    KeySource::Passphrase(text.to_string())
+}
+
+#[test]
+fn history_round_trips_through_the_vault() {
+    let dir = scratch_dir("round_trip");
+    let vault = Arc::new(Vault::create(&dir.join("vault"), &passphrase("correct horse")).unwrap());
+    let path = dir.join("history");
+
+    let mut history = InputHistory::load(&path, Some(Arc::clone(&vault))).unwrap();
+    history.add("msg bob the launch code is 1234").unwrap();
+    history.add("note buy milk").unwrap();
+    history.add("note buy milk").unwrap();
+
+    let content = fs::read(&path).unwrap();
+    assert!(vault::is_sealed(&content));
+    let text = String::from_utf8_lossy(&content);
+    assert!(!text.contains("launch code") && !text.contains("milk"), "{}", text);
+
+    // a restart unlocks with the passphrase alone
+    let unlocked = Vault::unlock(&dir.join("vault"), &passphrase("correct horse")).unwrap();
+    let history = InputHistory::load(&path, Some(Arc::new(unlocked))).unwrap();
+    assert_eq!(history.lines(), ["msg bob the launch code is 1234", "note buy milk"]);
+
+    let mut drafts = Drafts::load_with(&dir.join("drafts"), Some(vault)).unwrap();
+    drafts.set(PROMPT_DRAFT, "msg alice half a secret", Instant::now());
+    drafts.save().unwrap();
+    assert!(vault::is_sealed(&fs::read(dir.join("drafts")).unwrap()));
+}
+
+#[test]
+fn a_wrong_passphrase_neither_reads_nor_writes() {
+    let dir = scratch_dir("wrong");
+    let vault = Vault::create(&dir.join("vault"), &passphrase("right")).unwrap();
+    let path = dir.join("history");
+    vault::write_file(&path, "msg bob secret\n", Some(&vault)).unwrap();
+    let sealed = fs::read(&path).unwrap();
+
+    let error = Vault::unlock(&dir.join("vault"), &passphrase("wrong")).unwrap_err();
+    assert!(error.ends_with("wrong passphrase or key"), "{}", error);
+
+    // a key from another vault gets as far as the file and no further
+    let other = Arc::new(Vault::create(&dir.join("other"), &passphrase("wrong")).unwrap());
+    let error = InputHistory::load(&path, Some(Arc::clone(&other))).unwrap_err();
+    assert!(error.to_string().contains("could not be decrypted"), "{}", error);
+    assert!(Drafts::load_with(&path, Some(other)).is_err());
+    assert_eq!(fs::read(&path).unwrap(), sealed);
+
+    // and neither is a file someone changed
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    fs::write(&path, &tampered).unwrap();
+    assert!(vault::read_file(&path, Some(&vault)).is_err());
+    assert!(Vault::create(&dir.join("vault"), &passphrase("right")).is_err());
+}
+
+#[test]
+fn plaintext_files_are_migrated_once_and_never_mixed_up() {
+    let dir = scratch_dir("migrate");
+    let (history, drafts) = (dir.join("history"), dir.join("drafts"));
+    fs::write(&history, "msg bob hello\nnote later\n").unwrap();
+    fs::write(&drafts, "{\"prompt\":\"msg al\"}\n").unwrap();
+    let vault = Arc::new(Vault::create(&dir.join("vault"), &passphrase("secret")).unwrap());
+
+    // before the migration a vault refuses them, so they are not overwritten in plaintext either
+    assert!(InputHistory::load(&history, Some(Arc::clone(&vault))).is_err());
+    assert!(Drafts::load_with(&drafts, Some(Arc::clone(&vault))).is_err());
+
+    let files = [history.clone(), drafts.clone(), dir.join("missing")];
+    assert_eq!(
+        vault::encrypt_files(&vault, &files).unwrap(),
+        [history.clone(), drafts.clone()]
+    );
+    assert!(vault::encrypt_files(&vault, &files).unwrap().is_empty());
+
+    let loaded = InputHistory::load(&history, Some(Arc::clone(&vault))).unwrap();
+    assert_eq!(loaded.lines(), ["msg bob hello", "note later"]);
+    let loaded = Drafts::load_with(&drafts, Some(vault)).unwrap();
+    assert_eq!(loaded.get(PROMPT_DRAFT), Some("msg al"));
+
+    // without the vault the sealed files are refused rather than shown as garbage
+    assert!(InputHistory::load(&history, None).is_err());
+    assert!(Drafts::load(&drafts).is_err());
+}
@@ -0,0 +1,58 @@
+use argon2::Config;
+use blake2b_simd::{Hash, Params};
+
+use crate::trace::{from_hex, to_hex};
+
+// the server's answer to a challenge for an account without a login key, the client retries with Auth
+pub const PLAIN_LOGIN_REQUIRED: &str = "Password login required";
+
+const KEY_LENGTH: u32 = 32;
+const TOKEN_HASH_LENGTH: usize = 32;
+const RESPONSE_LENGTH: usize = 32;
+// argon2 wants at least 8 bytes of salt, usernames can be shorter
+const SALT_PREFIX: &str = "chat_rs-login-key:";
+
+// derived on both sides, the server keeps it next to the password hash and never needs the password to check a login
+pub fn derive_key(username: &str, password: &str) -> Result<String, String> {
+    let config = Config {
+        hash_length: KEY_LENGTH,
+        ..Config::default()
+    };
+    let salt = format!("{}{}", SALT_PREFIX, username);
+    argon2::hash_raw(password.as_bytes(), salt.as_bytes(), &config)
+        .map(|key| to_hex(&key))
+        .map_err(|e| e.to_string())
+}
+
+// only answers this one nonce, a captured response is useless on any other connection
+pub fn challenge_response(key: &str, nonce: &str) -> Result<Vec<u8>, String> {
+    Ok(mac(key, nonce)?.as_bytes().to_vec())
+}
+
+pub fn verify_response(key: &str, nonce: &str, response: &[u8]) -> bool {
+    // comparing hashes takes the same time no matter where they differ
+    mac(key, nonce).is_ok_and(|expected| expected.eq(response))
+}
+
+// api tokens are random and long, unlike passwords they need no slow hash to be safe at rest
+pub fn hash_api_token(token: &str) -> String {
+    to_hex(token_hash(token).as_bytes())
+}
+
+// for secrets that are kept as they are, like the control token
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    // the hashes have one length whatever was sent, and comparing them takes the same time no matter where they differ
+    token_hash(expected) == token_hash(given)
+}
+
+fn token_hash(token: &str) -> Hash {
+    Params::new().hash_length(TOKEN_HASH_LENGTH).hash(token.as_bytes())
+}
+
+fn mac(key: &str, nonce: &str) -> Result<Hash, String> {
+    let key = from_hex(key)?;
+    Ok(Params::new()
+        .hash_length(RESPONSE_LENGTH)
+        .key(&key)
+        .hash(nonce.as_bytes()))
+}
@@ -0,0 +1,55 @@
+use std::{
+    error::Error,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use chat_core::{
+    decode::{decode_stream, json_report, text_report, InputFormat},
+    integrity::Integrity,
+};
+
+const USAGE: &str =
+    "Usage: chat_decode [<capture-file>] [--format raw|hex|trace] [--integrity crc32|xxh3|none] [--json]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut format = InputFormat::default();
+    let mut integrity = Integrity::default();
+    let mut json = false;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = InputFormat::parse(&args.next().ok_or(USAGE)?)?,
+            "--integrity" => integrity = Integrity::parse(&args.next().ok_or(USAGE)?)?,
+            "--json" => json = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            // stdin, same as leaving the file out
+            "-" if path.is_none() => {}
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unknown argument '{}'\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let input = match path {
+        Some(path) => std::fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            input
+        }
+    };
+
+    let entries = decode_stream(&format.to_stream(&input)?, integrity);
+    if json {
+        print!("{}", json_report(&entries));
+    } else {
+        print!("{}", text_report(&entries));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use chat_core::trace::{Direction, TraceRecord};
+
+const USAGE: &str = "Usage: chat_trace <trace-file> [--session <id>] [--redact-bodies <output-file>]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut session = None;
+    let mut redacted_path = None;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--session" => session = Some(args.next().ok_or(USAGE)?),
+            "--redact-bodies" => redacted_path = Some(PathBuf::from(args.next().ok_or(USAGE)?)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unknown argument '{}'\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let path = path.ok_or(USAGE)?;
+    let records = TraceRecord::read_all(&path)?;
+
+    // a capture to check in or attach to a bug report, what was said does not leave the machine
+    if let Some(redacted_path) = redacted_path {
+        let redacted: String = records
+            .iter()
+            .filter(|record| session.is_none() || record.session() == session.as_deref())
+            .map(|record| format!("{}\n", record.redact_bodies().to_json()))
+            .collect();
+        fs::write(&redacted_path, &redacted)?;
+        println!(
+            "{} frames written to {}",
+            redacted.lines().count(),
+            redacted_path.display()
+        );
+        return Ok(());
+    }
+
+    let (mut sent, mut received) = (0, 0);
+    for record in records
+        .iter()
+        .filter(|record| session.is_none() || record.session() == session.as_deref())
+    {
+        match record.direction() {
+            Direction::Sent => sent += 1,
+            Direction::Received => received += 1,
+        }
+        println!("{}", record);
+    }
+
+    println!("{} frames ({} sent, {} received)", sent + received, sent, received);
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::{collections::BTreeSet, fmt};
+
+// stable identifiers, never rename one that has shipped
+pub const DIRECT_MESSAGES: &str = "direct_messages";
+pub const ADMIN_LOG_LEVEL: &str = "admin_log_level";
+pub const ADMIN_SERVER_MODE: &str = "admin_server_mode";
+pub const MODERATION: &str = "moderation";
+pub const MESSAGE_EDIT: &str = "message_edit";
+pub const HISTORY: &str = "history";
+pub const FILE_TRANSFER: &str = "file_transfer";
+pub const UNREAD: &str = "unread";
+pub const PREFERENCES: &str = "preferences";
+pub const EPHEMERAL_MESSAGES: &str = "ephemeral_messages";
+pub const SEARCH: &str = "search";
+pub const ROOMS: &str = "rooms";
+pub const PRESENCE: &str = "presence";
+pub const LANGUAGES: &str = "languages";
+pub const MUTING: &str = "muting";
+// several preferences in one SetPreference, applied together, and the set answered with its version
+pub const PREFERENCE_BATCH: &str = "preference_batch";
+pub const INTEGRITY_XXH3: &str = "integrity_xxh3";
+pub const INTEGRITY_NONE: &str = "integrity_none";
+// guests may stay connected without logging in and are sent broadcasts
+pub const LISTEN_ONLY: &str = "listen_only";
+// admins may lend a user one permission for a while, and take it back early
+pub const TEMPORARY_GRANTS: &str = "temporary_grants";
+// a client may leave its offline queue on the server and pull it in batches, acknowledging as it goes
+pub const OFFLINE_SYNC: &str = "offline_sync";
+// messages may be handed to the server to be sent at a later time
+pub const SCHEDULED_MESSAGES: &str = "scheduled_messages";
+// users may make tokens that send messages through the server's http bridge
+pub const API_TOKENS: &str = "api_tokens";
+// clients may follow a snapshot of who is online and the changes to it instead of every broadcast
+pub const PRESENCE_SUBSCRIPTIONS: &str = "presence_subscriptions";
+// users may register a key that others verify their direct messages with, the server relays signatures untouched
+pub const MESSAGE_SIGNING: &str = "message_signing";
+
+const SEPARATOR: char = ',';
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    names: BTreeSet<String>,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, capability: &str) -> Self {
+        self.names.insert(capability.to_string());
+        self
+    }
+
+    pub fn without(mut self, capability: &str) -> Self {
+        self.names.remove(capability);
+        self
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.names.contains(capability)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn encode(&self) -> String {
+        self.iter().collect::<Vec<_>>().join(&SEPARATOR.to_string())
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut capabilities = Self::new();
+
+        for name in value.split(SEPARATOR).filter(|name| !name.is_empty()) {
+            if !name
+                .bytes()
+                .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+            {
+                return Err(format!("Invalid capability '{}'", name));
+            }
+            capabilities.names.insert(name.to_string());
+        }
+
+        Ok(capabilities)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.names.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", self.iter().collect::<Vec<_>>().join(", "))
+    }
+}
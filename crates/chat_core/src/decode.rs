@@ -0,0 +1,307 @@
+use std::fmt;
+
+use crate::{
+    integrity::Integrity,
+    json::JsonValue,
+    protocol::{MessageType, HEADER_START, MAX_FIELD_COUNT, MAX_FIELD_SIZE},
+    trace::{from_hex, to_hex},
+    version::{FrameError, VersionRange},
+};
+
+// how the bytes handed to the decoder were captured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    // straight off the wire
+    #[default]
+    Raw,
+    // hex digits, whitespace between them is ignored
+    Hex,
+    // the json lines chat_trace reads, one frame per record
+    Trace,
+}
+
+// a stretch of the stream, together the entries cover every byte of it in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEntry {
+    Frame(DecodedFrame),
+    // bytes before the first header start, or between two frames
+    Skipped { offset: usize, len: usize },
+    // a header start that does not begin a frame, decoding picks up at the next header start
+    Malformed { offset: usize, len: usize, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFrame {
+    pub offset: usize,
+    pub len: usize,
+    pub version: u8,
+    pub type_byte: u8,
+    pub fields: Vec<Vec<u8>>,
+    pub checksum_valid: bool,
+}
+
+impl InputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Hex => "hex",
+            Self::Trace => "trace",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        [Self::Raw, Self::Hex, Self::Trace]
+            .into_iter()
+            .find(|format| format.as_str() == value)
+            .ok_or_else(|| format!("Unknown input format '{}', expected raw, hex or trace", value))
+    }
+
+    // the records of a trace are joined in the order they were written
+    pub fn to_stream(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Raw => Ok(input.to_vec()),
+            Self::Hex => {
+                let digits: String = String::from_utf8_lossy(input)
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect();
+                from_hex(&digits)
+            }
+            Self::Trace => {
+                let mut stream = Vec::new();
+                for (index, line) in String::from_utf8_lossy(input).lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let bytes = JsonValue::parse(line).and_then(|record| {
+                        record
+                            .get("bytes")
+                            .and_then(JsonValue::as_str)
+                            .ok_or_else(|| "Missing 'bytes'".to_string())
+                            .and_then(from_hex)
+                    });
+                    stream.extend(bytes.map_err(|e| format!("Line {}: {}", index + 1, e))?);
+                }
+                Ok(stream)
+            }
+        }
+    }
+}
+
+// never fails, whatever does not decode is reported where it is and skipped
+pub fn decode_stream(bytes: &[u8], integrity: Integrity) -> Vec<StreamEntry> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let start = next_header_start(bytes, offset);
+        if start > offset {
+            entries.push(StreamEntry::Skipped {
+                offset,
+                len: start - offset,
+            });
+        }
+        if start == bytes.len() {
+            break;
+        }
+
+        match decode_frame(bytes, start, integrity) {
+            Ok(frame) => {
+                offset = start + frame.len;
+                entries.push(StreamEntry::Frame(frame));
+            }
+            Err(reason) => {
+                offset = next_header_start(bytes, start + 2);
+                entries.push(StreamEntry::Malformed {
+                    offset: start,
+                    len: offset - start,
+                    reason,
+                });
+            }
+        }
+    }
+
+    entries
+}
+
+// one line per frame, skipped stretch and failure, plus one per field
+pub fn text_report(entries: &[StreamEntry]) -> String {
+    let mut report = String::new();
+    for entry in entries {
+        report.push_str(&format!("{}\n", entry));
+    }
+
+    let frames = entries.iter().filter(|entry| matches!(entry, StreamEntry::Frame(_)));
+    let bad = frames
+        .clone()
+        .filter(|entry| matches!(entry, StreamEntry::Frame(frame) if !frame.checksum_valid))
+        .count();
+    let skipped: usize = entries
+        .iter()
+        .map(|entry| match entry {
+            StreamEntry::Skipped { len, .. } => *len,
+            _ => 0,
+        })
+        .sum();
+    let malformed = entries
+        .iter()
+        .filter(|entry| matches!(entry, StreamEntry::Malformed { .. }))
+        .count();
+    report.push_str(&format!(
+        "{} frames ({} with a bad checksum), {} bytes skipped, {} malformed\n",
+        frames.count(),
+        bad,
+        skipped,
+        malformed
+    ));
+    report
+}
+
+// one json object per line, for tools
+pub fn json_report(entries: &[StreamEntry]) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry.to_json())).collect()
+}
+
+impl StreamEntry {
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            Self::Frame(frame) => {
+                let fields: Vec<JsonValue> = frame
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        JsonValue::object()
+                            .with("hex", to_hex(field))
+                            .with("text", String::from_utf8_lossy(field).into_owned())
+                    })
+                    .collect();
+                JsonValue::object()
+                    .with("kind", "frame")
+                    .with("offset", frame.offset)
+                    .with("len", frame.len)
+                    .with("version", frame.version as u64)
+                    .with(
+                        "type",
+                        frame.message_type().map(|message_type| format!("{:?}", message_type)),
+                    )
+                    .with("type_byte", frame.type_byte as u64)
+                    .with("checksum_valid", frame.checksum_valid)
+                    .with("fields", fields)
+            }
+            Self::Skipped { offset, len } => JsonValue::object()
+                .with("kind", "skipped")
+                .with("offset", *offset)
+                .with("len", *len),
+            Self::Malformed { offset, len, reason } => JsonValue::object()
+                .with("kind", "malformed")
+                .with("offset", *offset)
+                .with("len", *len)
+                .with("reason", reason.as_str()),
+        }
+    }
+}
+
+impl DecodedFrame {
+    // None for a type this build does not know
+    pub fn message_type(&self) -> Option<MessageType> {
+        MessageType::try_from(self.type_byte).ok()
+    }
+}
+
+impl fmt::Display for StreamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Frame(frame) => {
+                write!(f, "{}..{} ", frame.offset, frame.offset + frame.len)?;
+                match frame.message_type() {
+                    Some(message_type) => write!(f, "{:?}", message_type)?,
+                    None => write!(f, "unknown type 0x{:02x}", frame.type_byte)?,
+                }
+                let checksum = if frame.checksum_valid { "ok" } else { "INVALID" };
+                write!(
+                    f,
+                    " v{}, {} fields, checksum {}",
+                    frame.version,
+                    frame.fields.len(),
+                    checksum
+                )?;
+                for (index, field) in frame.fields.iter().enumerate() {
+                    write!(
+                        f,
+                        "\n  {}: {} {:?}",
+                        index,
+                        to_hex(field),
+                        String::from_utf8_lossy(field)
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Skipped { offset, len } => {
+                write!(
+                    f,
+                    "{}..{} skipped {} bytes without a header start",
+                    offset,
+                    offset + len,
+                    len
+                )
+            }
+            Self::Malformed { offset, len, reason } => {
+                write!(f, "{}..{} malformed: {}", offset, offset + len, reason)
+            }
+        }
+    }
+}
+
+fn next_header_start(bytes: &[u8], from: usize) -> usize {
+    let header = HEADER_START.to_be_bytes();
+    bytes
+        .get(from..)
+        .and_then(|rest| rest.windows(2).position(|window| window == header))
+        .map_or(bytes.len(), |position| from + position)
+}
+
+// the layout checks of Message::from_bytes_using, without giving up on a bad trailer or an unknown type
+fn decode_frame(bytes: &[u8], offset: usize, integrity: Integrity) -> Result<DecodedFrame, String> {
+    let mut position = offset + 2;
+    let mut take = |len: usize| -> Result<&[u8], String> {
+        let taken = bytes
+            .get(position..position + len)
+            .ok_or_else(|| format!("Stream ends {} bytes into the frame", bytes.len() - offset))?;
+        position += len;
+        Ok(taken)
+    };
+
+    let version = take(1)?[0];
+    let versions = VersionRange::default();
+    if !versions.supports(version) {
+        return Err(FrameError::UnsupportedVersion {
+            version,
+            supported: versions,
+        }
+        .to_string());
+    }
+    let type_byte = take(1)?[0];
+    let count = u32::from_be_bytes(take(4)?.try_into().unwrap());
+    if count > MAX_FIELD_COUNT {
+        return Err(format!("Too many payload fields ({} > {})", count, MAX_FIELD_COUNT));
+    }
+
+    let mut fields = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        if len > MAX_FIELD_SIZE {
+            return Err(format!("Payload field too large ({} > {} bytes)", len, MAX_FIELD_SIZE));
+        }
+        fields.push(take(len as usize)?.to_vec());
+    }
+    let trailer = take(integrity.trailer_len())?.to_vec();
+
+    Ok(DecodedFrame {
+        offset,
+        len: position - offset,
+        version,
+        type_byte,
+        checksum_valid: integrity.verify(fields.iter().map(Vec::as_slice), &trailer),
+        fields,
+    })
+}
@@ -0,0 +1,803 @@
+use std::net::{AddrParseError, IpAddr};
+
+use chrono::{DateTime, Utc};
+
+use crate::protocol::{
+    ApiTokenInfo, ConsistencyReport, ErrorCode, HeartbeatEcho, IpBanList, Message, MessageId, MessageType,
+    OfflineSummary, Payload, PresenceScope, PresenceSnapshot, ScheduleTime, ScheduledEntry, ServerStats, SessionFilter,
+    SessionInfo, SyncBatch, UserInfo, SIGNING_KEY_LENGTH,
+};
+
+// what a client asks the server for, decoded once from the frame so nothing past this point knows the field layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Heartbeat(Heartbeat),
+    TimeSync(TimeSync),
+    ClientHello(ClientHello),
+    Auth(Auth),
+    AuthChallenge(AuthChallenge),
+    AuthCreate(AuthCreate),
+    PasswordChange(PasswordChange),
+    RenameAccount(RenameAccount),
+    ServerDebugLog,
+    ServerShutdown(ServerShutdown),
+    AdminSetLogLevel(AdminSetLogLevel),
+    AdminResetPassword(AdminResetPassword),
+    AdminSetServerMode(AdminSetServerMode),
+    AdminServerStats,
+    AdminKickUser(AdminKickUser),
+    AdminSetAccessLevel(AdminSetAccessLevel),
+    AdminExportState,
+    AdminRenameUser(AdminRenameUser),
+    AdminUserInfo(AdminUserInfo),
+    AdminSetMotd(AdminSetMotd),
+    AdminListSessions(SessionFilter),
+    AdminKickWhere(AdminKickWhere),
+    AdminListIpBans,
+    AdminClearIpBan(AdminClearIpBan),
+    AdminConsistencyCheck(AdminConsistencyCheck),
+    AdminGrantTemporary(AdminGrantTemporary),
+    AdminRevokeTemporary(AdminRevokeTemporary),
+    DirectMessageSend(DirectMessageSend),
+    MessageEdit(MessageEdit),
+    MessageDelete(MessageDelete),
+    HistoryRequest(HistoryRequest),
+    MarkConversationRead(MarkConversationRead),
+    SearchRequest(SearchRequest),
+    FileOffer(FileOffer),
+    FileAccept(FileAccept),
+    FileReject(FileReject),
+    FileChunk(FileChunk),
+    FileComplete(FileComplete),
+    SetPreference(SetPreference),
+    GetPreferences,
+    MuteAdd(Mute),
+    MuteRemove(Mute),
+    MuteList,
+    SubscribePresence(PresenceScope),
+    UnsubscribePresence(PresenceScope),
+    SyncSelect,
+    SyncRequest(SyncRequest),
+    ScheduleMessageSend(ScheduleMessageSend),
+    ScheduleList,
+    ScheduleCancel(ScheduleCancel),
+    ApiTokenCreate(ApiTokenCreate),
+    ApiTokenRevoke(ApiTokenRevoke),
+    ApiTokenList,
+    SigningKeySet(SigningKeySet),
+    SigningKeyRequest(SigningKeyRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub sent_at: DateTime<Utc>,
+    // None for peers that send only the timestamp
+    pub echo: Option<HeartbeatEcho>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSync {
+    pub client_sent: DateTime<Utc>,
+}
+
+// the tag is not checked here, what counts as a language is up to the server's catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    pub language: String,
+}
+
+// passwords stay raw bytes where the server only ever hashes them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub username: String,
+    pub password: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthChallenge {
+    pub username: String,
+    pub response: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthCreate {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordChange {
+    pub old_password: Vec<u8>,
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameAccount {
+    pub new_name: String,
+    pub password: Vec<u8>,
+}
+
+// in seconds, as the admin asked for it, the server decides how long it waits at most
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerShutdown {
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminSetLogLevel {
+    pub directives: String,
+    pub revert_after: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminResetPassword {
+    pub username: String,
+    pub password: String,
+}
+
+// the mode is the name it travels as, the server knows which ones there are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminSetServerMode {
+    pub mode: String,
+    pub stop_after: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminKickUser {
+    pub username: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminSetAccessLevel {
+    pub username: String,
+    pub level: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminRenameUser {
+    pub old_name: String,
+    pub new_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminUserInfo {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminSetMotd {
+    pub text: String,
+    pub broadcast: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminKickWhere {
+    pub filter: SessionFilter,
+    pub reason: String,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdminClearIpBan {
+    pub address: IpAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdminConsistencyCheck {
+    pub repair: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminGrantTemporary {
+    pub username: String,
+    pub permission: String,
+    pub seconds: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminRevokeTemporary {
+    pub username: String,
+    pub permission: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectMessageSend {
+    pub recipient: String,
+    pub body: String,
+    // in seconds, zero keeps the message like no time to live at all
+    pub ttl: Option<u64>,
+    pub client_id: Option<String>,
+    // the sender's, over what it wrote, the server has no use for it but to pass it on
+    pub signature: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageEdit {
+    pub id: MessageId,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageDelete {
+    pub id: MessageId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRequest {
+    pub peer: String,
+    pub limit: u64,
+    // None starts at the newest message
+    pub before: Option<MessageId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkConversationRead {
+    pub peer: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchRequest {
+    pub query: String,
+    // None searches every conversation of the requesting user
+    pub peer: Option<String>,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOffer {
+    pub recipient: String,
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAccept {
+    pub transfer_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReject {
+    pub transfer_id: u64,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChunk {
+    pub transfer_id: u64,
+    pub index: u64,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileComplete {
+    pub transfer_id: u64,
+}
+
+// applied all together or not at all
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetPreference {
+    pub updates: Vec<(String, String)>,
+}
+
+// both MuteAdd and MuteRemove, the request says which way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mute {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncRequest {
+    pub cursor: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleMessageSend {
+    pub recipient: String,
+    pub body: String,
+    pub due: ScheduleTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleCancel {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiTokenCreate {
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiTokenRevoke {
+    pub id: u64,
+}
+
+// None takes the key off the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigningKeySet {
+    pub key: Option<[u8; SIGNING_KEY_LENGTH]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningKeyRequest {
+    pub username: String,
+}
+
+// what the server answers the session that asked, encoded only on the way out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ack,
+    Nack,
+    // a NACK that says what was wrong with the request
+    Rejected {
+        request: MessageType,
+        reason: String,
+    },
+    Heartbeat(HeartbeatEcho),
+    TimeSyncReply {
+        client_sent: DateTime<Utc>,
+        server_received: DateTime<Utc>,
+        server_sent: DateTime<Utc>,
+    },
+    AuthSuccess {
+        user_id: String,
+        access_level: String,
+    },
+    // failures without a code predate them, mostly malformed requests
+    AuthFailure {
+        code: Option<ErrorCode>,
+        error: String,
+    },
+    AuthBusy {
+        retry_after: u64,
+        error: String,
+    },
+    PasswordChanged {
+        username: String,
+    },
+    UserRenamed {
+        old_name: String,
+        new_name: String,
+    },
+    ServerStats(ServerStats),
+    UserInfo(UserInfo),
+    SessionInfo(SessionInfo),
+    SessionListEnd {
+        count: u64,
+        kicked: bool,
+    },
+    IpBanList(IpBanList),
+    ConsistencyReport(ConsistencyReport),
+    StateExported {
+        path: String,
+        users: u64,
+    },
+    AdminLogLevelChanged {
+        previous: String,
+        current: String,
+    },
+    AccessLevelChanged {
+        username: String,
+        level: String,
+    },
+    UserKicked {
+        username: String,
+        reason: String,
+    },
+    Motd {
+        text: String,
+    },
+    MessageError {
+        code: Option<ErrorCode>,
+        error: String,
+    },
+    MessageAck {
+        id: MessageId,
+        client_id: Option<String>,
+    },
+    MessageEdited {
+        id: MessageId,
+        sender: String,
+        body: String,
+        edited_at: DateTime<Utc>,
+    },
+    MessageDeleted {
+        id: MessageId,
+        sender: String,
+    },
+    HistoryEnd {
+        count: u64,
+    },
+    UnreadSummary(Vec<(String, u64)>),
+    SearchRefused {
+        reason: String,
+    },
+    SearchEnd {
+        count: u64,
+    },
+    OfflineSummary(OfflineSummary),
+    SyncBatch(SyncBatch),
+    FileOffered {
+        transfer_id: u64,
+        recipient: String,
+        filename: String,
+    },
+    FileReject {
+        transfer_id: u64,
+        reason: String,
+    },
+    Preferences {
+        entries: Vec<(String, String)>,
+        version: u64,
+    },
+    MutedSenders(Vec<String>),
+    ScheduledMessages(Vec<ScheduledEntry>),
+    ApiTokens(Vec<ApiTokenInfo>),
+    ApiTokenCreated {
+        info: ApiTokenInfo,
+        token: String,
+    },
+    SigningKey {
+        username: String,
+        key: Option<[u8; SIGNING_KEY_LENGTH]>,
+    },
+    PresenceSnapshot(PresenceSnapshot),
+    // a frame that was encoded before it was due, like queued messages, history rows and remembered acks
+    Frame(Message),
+}
+
+impl Request {
+    // None for frames no client sends the server, like replies and notices; the error names the field that was wrong
+    pub fn decode(message: &Message) -> Option<Result<Self, String>> {
+        Self::decode_fields(message).transpose()
+    }
+
+    fn decode_fields(message: &Message) -> Result<Option<Self>, String> {
+        let payload = message.payload();
+        let request = match message.message_type() {
+            MessageType::Heartbeat => Self::Heartbeat(heartbeat(message)?),
+            MessageType::TimeSync => Self::TimeSync(TimeSync {
+                client_sent: payload.timestamp_field(0)?,
+            }),
+            MessageType::ClientHello => Self::ClientHello(ClientHello {
+                language: payload.str_field(0)?.to_string(),
+            }),
+            MessageType::Auth => Self::Auth(Auth {
+                username: payload.username_field(0)?,
+                password: payload.field(1)?.to_vec(),
+            }),
+            MessageType::AuthChallenge => Self::AuthChallenge(AuthChallenge {
+                username: payload.username_field(0)?,
+                response: payload.field(1)?.to_vec(),
+            }),
+            MessageType::AuthCreate => Self::AuthCreate(AuthCreate {
+                username: payload.username_field(0)?,
+                password: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::PasswordChange => Self::PasswordChange(PasswordChange {
+                old_password: payload.field(0)?.to_vec(),
+                new_password: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::RenameAccount => Self::RenameAccount(RenameAccount {
+                new_name: payload.username_field(0)?,
+                password: payload.field(1)?.to_vec(),
+            }),
+            MessageType::ServerDebugLog => Self::ServerDebugLog,
+            MessageType::ServerShutdown => Self::ServerShutdown(ServerShutdown {
+                timeout: payload.u64_field(0)?,
+            }),
+            MessageType::AdminSetLogLevel => Self::AdminSetLogLevel(AdminSetLogLevel {
+                directives: payload.str_field(0)?.to_string(),
+                revert_after: optional_u64_field(payload, 1)?,
+            }),
+            MessageType::AdminResetPassword => Self::AdminResetPassword(AdminResetPassword {
+                username: payload.username_field(0)?,
+                password: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::AdminSetServerMode => Self::AdminSetServerMode(AdminSetServerMode {
+                mode: payload.str_field(0)?.to_string(),
+                stop_after: optional_u64_field(payload, 1)?,
+            }),
+            MessageType::AdminServerStats => Self::AdminServerStats,
+            MessageType::AdminKickUser => Self::AdminKickUser(AdminKickUser {
+                username: payload.username_field(0)?,
+                reason: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::AdminSetAccessLevel => Self::AdminSetAccessLevel(AdminSetAccessLevel {
+                username: payload.username_field(0)?,
+                level: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::AdminExportState => Self::AdminExportState,
+            MessageType::AdminRenameUser => Self::AdminRenameUser(AdminRenameUser {
+                old_name: payload.username_field(0)?,
+                new_name: payload.username_field(1)?,
+            }),
+            MessageType::AdminUserInfo => Self::AdminUserInfo(AdminUserInfo {
+                username: payload.username_field(0)?,
+            }),
+            MessageType::AdminSetMotd => Self::AdminSetMotd(AdminSetMotd {
+                text: payload.str_field(0)?.to_string(),
+                broadcast: matches!(payload.field(1), Ok([1])),
+            }),
+            MessageType::AdminListSessions => Self::AdminListSessions(message.session_filter()?),
+            MessageType::AdminKickWhere => Self::AdminKickWhere(AdminKickWhere {
+                filter: message.session_filter()?,
+                reason: payload.str_field(3)?.to_string(),
+                dry_run: payload.u64_field(4)? != 0,
+            }),
+            MessageType::AdminListIpBans => Self::AdminListIpBans,
+            MessageType::AdminClearIpBan => Self::AdminClearIpBan(AdminClearIpBan {
+                address: payload
+                    .str_field(0)?
+                    .trim()
+                    .parse()
+                    .map_err(|e: AddrParseError| e.to_string())?,
+            }),
+            MessageType::AdminConsistencyCheck => Self::AdminConsistencyCheck(AdminConsistencyCheck {
+                repair: payload.u64_field(0)? != 0,
+            }),
+            MessageType::AdminGrantTemporary => Self::AdminGrantTemporary(AdminGrantTemporary {
+                username: payload.username_field(0)?,
+                permission: payload.str_field(1)?.to_string(),
+                seconds: payload.u64_field(2)?,
+            }),
+            MessageType::AdminRevokeTemporary => Self::AdminRevokeTemporary(AdminRevokeTemporary {
+                username: payload.username_field(0)?,
+                permission: payload.str_field(1)?.to_string(),
+            }),
+            MessageType::DirectMessageSend => Self::DirectMessageSend(DirectMessageSend {
+                recipient: payload.username_field(0)?,
+                body: payload.body_field(1)?,
+                ttl: optional_u64_field(payload, 2)?,
+                client_id: match payload.field_count() {
+                    0..=3 => None,
+                    _ => Some(payload.str_field(3)?.to_string()).filter(|id| !id.is_empty()),
+                },
+                signature: message.signature().map(<[u8]>::to_vec),
+            }),
+            MessageType::MessageEdit => Self::MessageEdit(MessageEdit {
+                id: payload.message_id_field(0)?,
+                body: payload.body_field(1)?,
+            }),
+            MessageType::MessageDelete => Self::MessageDelete(MessageDelete {
+                id: payload.message_id_field(0)?,
+            }),
+            MessageType::HistoryRequest => Self::HistoryRequest(HistoryRequest {
+                peer: payload.username_field(0)?,
+                limit: payload.u64_field(1)?,
+                before: match payload.field_count() {
+                    0..=2 => None,
+                    _ => Some(payload.message_id_field(2)?),
+                },
+            }),
+            MessageType::MarkConversationRead => Self::MarkConversationRead(MarkConversationRead {
+                peer: payload.username_field(0)?,
+            }),
+            MessageType::SearchRequest => Self::SearchRequest(SearchRequest {
+                query: payload.str_field(0)?.trim().to_string(),
+                peer: Some(payload.str_field(1)?)
+                    .filter(|peer| !peer.is_empty())
+                    .map(str::to_string),
+                limit: payload.u64_field(2)?,
+            }),
+            MessageType::FileOffer => Self::FileOffer(FileOffer {
+                recipient: payload.str_field(0)?.to_string(),
+                filename: payload.str_field(1)?.to_string(),
+                size: payload.u64_field(2)?,
+                sha256: payload.str_field(3)?.to_string(),
+            }),
+            MessageType::FileAccept => Self::FileAccept(FileAccept {
+                transfer_id: payload.u64_field(0)?,
+            }),
+            MessageType::FileReject => Self::FileReject(FileReject {
+                transfer_id: payload.u64_field(0)?,
+                reason: payload.str_field(1).ok().map(str::to_string),
+            }),
+            MessageType::FileChunk => Self::FileChunk(FileChunk {
+                transfer_id: payload.u64_field(0)?,
+                index: payload.u64_field(1)?,
+                bytes: payload.field(2)?.to_vec(),
+            }),
+            MessageType::FileComplete => Self::FileComplete(FileComplete {
+                transfer_id: payload.u64_field(0)?,
+            }),
+            MessageType::SetPreference => Self::SetPreference(SetPreference {
+                updates: message.preference_updates()?,
+            }),
+            MessageType::GetPreferences => Self::GetPreferences,
+            MessageType::MuteAdd => Self::MuteAdd(Mute {
+                username: payload.username_field(0)?,
+            }),
+            MessageType::MuteRemove => Self::MuteRemove(Mute {
+                username: payload.username_field(0)?,
+            }),
+            MessageType::MuteList => Self::MuteList,
+            MessageType::SubscribePresence => Self::SubscribePresence(message.presence_scope()?),
+            MessageType::UnsubscribePresence => Self::UnsubscribePresence(message.presence_scope()?),
+            MessageType::SyncSelect => Self::SyncSelect,
+            MessageType::SyncRequest => Self::SyncRequest(SyncRequest {
+                cursor: payload.u64_field(0)?,
+                limit: payload.u64_field(1)?,
+            }),
+            MessageType::ScheduleMessageSend => Self::ScheduleMessageSend(ScheduleMessageSend {
+                recipient: payload.username_field(0)?,
+                body: payload.body_field(1)?,
+                due: ScheduleTime::parse(payload.str_field(2)?)?,
+            }),
+            MessageType::ScheduleList => Self::ScheduleList,
+            MessageType::ScheduleCancel => Self::ScheduleCancel(ScheduleCancel {
+                id: payload.u64_field(0)?,
+            }),
+            MessageType::ApiTokenCreate => Self::ApiTokenCreate(ApiTokenCreate {
+                label: payload.str_field(0)?.to_string(),
+            }),
+            MessageType::ApiTokenRevoke => Self::ApiTokenRevoke(ApiTokenRevoke {
+                id: payload.u64_field(0)?,
+            }),
+            MessageType::ApiTokenList => Self::ApiTokenList,
+            MessageType::SigningKeySet => Self::SigningKeySet(SigningKeySet {
+                key: payload.signing_key_field(0)?,
+            }),
+            MessageType::SigningKeyRequest => Self::SigningKeyRequest(SigningKeyRequest {
+                username: payload.username_field(0)?,
+            }),
+            _ => return Ok(None),
+        };
+        Ok(Some(request))
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Self::Heartbeat(_) => MessageType::Heartbeat,
+            Self::TimeSync(_) => MessageType::TimeSync,
+            Self::ClientHello(_) => MessageType::ClientHello,
+            Self::Auth(_) => MessageType::Auth,
+            Self::AuthChallenge(_) => MessageType::AuthChallenge,
+            Self::AuthCreate(_) => MessageType::AuthCreate,
+            Self::PasswordChange(_) => MessageType::PasswordChange,
+            Self::RenameAccount(_) => MessageType::RenameAccount,
+            Self::ServerDebugLog => MessageType::ServerDebugLog,
+            Self::ServerShutdown(_) => MessageType::ServerShutdown,
+            Self::AdminSetLogLevel(_) => MessageType::AdminSetLogLevel,
+            Self::AdminResetPassword(_) => MessageType::AdminResetPassword,
+            Self::AdminSetServerMode(_) => MessageType::AdminSetServerMode,
+            Self::AdminServerStats => MessageType::AdminServerStats,
+            Self::AdminKickUser(_) => MessageType::AdminKickUser,
+            Self::AdminSetAccessLevel(_) => MessageType::AdminSetAccessLevel,
+            Self::AdminExportState => MessageType::AdminExportState,
+            Self::AdminRenameUser(_) => MessageType::AdminRenameUser,
+            Self::AdminUserInfo(_) => MessageType::AdminUserInfo,
+            Self::AdminSetMotd(_) => MessageType::AdminSetMotd,
+            Self::AdminListSessions(_) => MessageType::AdminListSessions,
+            Self::AdminKickWhere(_) => MessageType::AdminKickWhere,
+            Self::AdminListIpBans => MessageType::AdminListIpBans,
+            Self::AdminClearIpBan(_) => MessageType::AdminClearIpBan,
+            Self::AdminConsistencyCheck(_) => MessageType::AdminConsistencyCheck,
+            Self::AdminGrantTemporary(_) => MessageType::AdminGrantTemporary,
+            Self::AdminRevokeTemporary(_) => MessageType::AdminRevokeTemporary,
+            Self::DirectMessageSend(_) => MessageType::DirectMessageSend,
+            Self::MessageEdit(_) => MessageType::MessageEdit,
+            Self::MessageDelete(_) => MessageType::MessageDelete,
+            Self::HistoryRequest(_) => MessageType::HistoryRequest,
+            Self::MarkConversationRead(_) => MessageType::MarkConversationRead,
+            Self::SearchRequest(_) => MessageType::SearchRequest,
+            Self::FileOffer(_) => MessageType::FileOffer,
+            Self::FileAccept(_) => MessageType::FileAccept,
+            Self::FileReject(_) => MessageType::FileReject,
+            Self::FileChunk(_) => MessageType::FileChunk,
+            Self::FileComplete(_) => MessageType::FileComplete,
+            Self::SetPreference(_) => MessageType::SetPreference,
+            Self::GetPreferences => MessageType::GetPreferences,
+            Self::MuteAdd(_) => MessageType::MuteAdd,
+            Self::MuteRemove(_) => MessageType::MuteRemove,
+            Self::MuteList => MessageType::MuteList,
+            Self::SubscribePresence(_) => MessageType::SubscribePresence,
+            Self::UnsubscribePresence(_) => MessageType::UnsubscribePresence,
+            Self::SyncSelect => MessageType::SyncSelect,
+            Self::SyncRequest(_) => MessageType::SyncRequest,
+            Self::ScheduleMessageSend(_) => MessageType::ScheduleMessageSend,
+            Self::ScheduleList => MessageType::ScheduleList,
+            Self::ScheduleCancel(_) => MessageType::ScheduleCancel,
+            Self::ApiTokenCreate(_) => MessageType::ApiTokenCreate,
+            Self::ApiTokenRevoke(_) => MessageType::ApiTokenRevoke,
+            Self::ApiTokenList => MessageType::ApiTokenList,
+            Self::SigningKeySet(_) => MessageType::SigningKeySet,
+            Self::SigningKeyRequest(_) => MessageType::SigningKeyRequest,
+        }
+    }
+}
+
+impl From<Response> for Message {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::Ack => Message::ACK,
+            Response::Nack => Message::NACK,
+            Response::Rejected { request, reason } => Message::rejected(request, &reason),
+            Response::Heartbeat(echo) => Message::heartbeat_with(echo),
+            Response::TimeSyncReply {
+                client_sent,
+                server_received,
+                server_sent,
+            } => Message::time_sync_reply(client_sent, server_received, server_sent),
+            Response::AuthSuccess { user_id, access_level } => Message::auth_success_with_id(&user_id, &access_level),
+            Response::AuthFailure { code: None, error } => Message::auth_fail(&error),
+            Response::AuthFailure {
+                code: Some(code),
+                error,
+            } => Message::auth_fail_with_code(code, &error),
+            Response::AuthBusy { retry_after, error } => Message::auth_busy(retry_after, &error),
+            Response::PasswordChanged { username } => Message::password_changed(&username),
+            Response::UserRenamed { old_name, new_name } => Message::user_renamed(&old_name, &new_name),
+            Response::ServerStats(stats) => Message::server_stats(&stats),
+            Response::UserInfo(info) => Message::user_info(&info),
+            Response::SessionInfo(info) => Message::session_info(&info),
+            Response::SessionListEnd { count, kicked } => Message::session_list_end(count, kicked),
+            Response::IpBanList(list) => Message::ip_ban_list(&list),
+            Response::ConsistencyReport(report) => Message::consistency_report(&report),
+            Response::StateExported { path, users } => Message::state_exported(&path, users),
+            Response::AdminLogLevelChanged { previous, current } => {
+                Message::admin_log_level_changed(&previous, &current)
+            }
+            Response::AccessLevelChanged { username, level } => Message::access_level_changed(&username, &level),
+            Response::UserKicked { username, reason } => Message::user_kicked(&username, &reason),
+            Response::Motd { text } => Message::motd(&text),
+            Response::MessageError { code: None, error } => Message::message_error(&error),
+            Response::MessageError {
+                code: Some(code),
+                error,
+            } => Message::message_error_with_code(code, &error),
+            Response::MessageAck { id, client_id: None } => Message::message_ack(id),
+            Response::MessageAck {
+                id,
+                client_id: Some(client_id),
+            } => Message::message_ack_for(id, &client_id),
+            Response::MessageEdited {
+                id,
+                sender,
+                body,
+                edited_at,
+            } => Message::message_edited(id, &sender, &body, edited_at),
+            Response::MessageDeleted { id, sender } => Message::message_deleted(id, &sender),
+            Response::HistoryEnd { count } => Message::history_end(count),
+            Response::UnreadSummary(counts) => Message::unread_summary(&counts),
+            Response::SearchRefused { reason } => Message::search_refused(&reason),
+            Response::SearchEnd { count } => Message::search_end(count),
+            Response::OfflineSummary(summary) => Message::offline_summary(&summary),
+            Response::SyncBatch(batch) => Message::sync_batch(&batch),
+            Response::FileOffered {
+                transfer_id,
+                recipient,
+                filename,
+            } => Message::file_offered(transfer_id, &recipient, &filename),
+            Response::FileReject { transfer_id, reason } => Message::file_reject(transfer_id, &reason),
+            Response::Preferences { entries, version } => Message::preferences(&entries, version),
+            Response::MutedSenders(usernames) => Message::muted_senders(&usernames),
+            Response::ScheduledMessages(entries) => Message::scheduled_messages(&entries),
+            Response::ApiTokens(tokens) => Message::api_tokens(&tokens),
+            Response::ApiTokenCreated { info, token } => Message::api_token_created(&info, &token),
+            Response::SigningKey { username, key } => Message::signing_key(&username, key.as_ref()),
+            Response::PresenceSnapshot(snapshot) => Message::presence_snapshot(&snapshot),
+            Response::Frame(message) => message,
+        }
+    }
+}
+
+// the first field is an rfc3339 timestamp, the sequence numbers follow it when the peer sends them
+fn heartbeat(message: &Message) -> Result<Heartbeat, String> {
+    let sent_at = DateTime::parse_from_rfc3339(message.payload().str_field(0)?).map_err(|e| e.to_string())?;
+    Ok(Heartbeat {
+        sent_at: sent_at.with_timezone(&Utc),
+        echo: message.heartbeat_echo(),
+    })
+}
+
+// a trailing field that older peers leave out, or leave empty to get to a field after it
+fn optional_u64_field(payload: &Payload, index: usize) -> Result<Option<u64>, String> {
+    match payload.field(index) {
+        Ok([]) | Err(_) => Ok(None),
+        Ok(_) => payload.u64_field(index).map(Some),
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::{capability, xxh3};
+
+// what the trailer of a frame is, computed over the field data only
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Integrity {
+    // every peer speaks it, it is what a connection starts with
+    #[default]
+    Crc32,
+    Xxh3,
+    // no trailer at all, only for a transport that already checks its bytes, like TLS
+    None,
+}
+
+impl Integrity {
+    pub const ALL: &[Integrity] = &[Integrity::Crc32, Integrity::Xxh3, Integrity::None];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Crc32 => "crc32",
+            Self::Xxh3 => "xxh3",
+            Self::None => "none",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|integrity| integrity.as_str().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| format!("Unknown frame integrity '{}'", value))
+    }
+
+    // what a server advertises to be asked for it, crc32 needs nothing
+    pub fn capability(&self) -> Option<&'static str> {
+        match self {
+            Self::Crc32 => None,
+            Self::Xxh3 => Some(capability::INTEGRITY_XXH3),
+            Self::None => Some(capability::INTEGRITY_NONE),
+        }
+    }
+
+    pub fn trailer_len(&self) -> usize {
+        match self {
+            Self::Crc32 => 4,
+            Self::Xxh3 => 8,
+            Self::None => 0,
+        }
+    }
+
+    pub fn trailer<'a>(&self, fields: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+        match self {
+            Self::Crc32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                for field in fields {
+                    hasher.update(field);
+                }
+                hasher.finalize().to_be_bytes().to_vec()
+            }
+            Self::Xxh3 => xxh3::hash64(&fields.into_iter().collect::<Vec<_>>().concat())
+                .to_be_bytes()
+                .to_vec(),
+            Self::None => Vec::new(),
+        }
+    }
+
+    pub fn verify<'a>(&self, fields: impl IntoIterator<Item = &'a [u8]>, trailer: &[u8]) -> bool {
+        self.trailer(fields) == trailer
+    }
+}
+
+impl fmt::Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
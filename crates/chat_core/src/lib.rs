@@ -1,2 +1,14 @@
+pub mod auth;
+pub mod capability;
 pub mod constants;
+pub mod decode;
+pub mod dto;
+pub mod integrity;
+pub mod json;
+pub mod normalize;
 pub mod protocol;
+pub mod time_sync;
+pub mod trace;
+pub mod transport;
+pub mod version;
+mod xxh3;
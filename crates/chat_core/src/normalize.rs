@@ -0,0 +1,40 @@
+use unicode_normalization::UnicodeNormalization;
+
+// the form a name is looked up and stored in, whatever terminal or line ending typed it
+//
+// Composed to NFC, so an accent typed as a separate combining mark finds the same user. Carriage
+// returns are dropped, runs of other control characters become one space and the ends are trimmed.
+pub fn normalize_username(input: &str) -> Result<String, String> {
+    let name = normalize(input, false);
+    if name.is_empty() {
+        return Err("Username must not be empty".to_string());
+    }
+    Ok(name)
+}
+
+// like a name, except that line breaks and tabs inside the body are kept
+pub fn normalize_body(input: &str) -> Result<String, String> {
+    let body = normalize(input, true);
+    if body.is_empty() {
+        return Err("Message must not be empty".to_string());
+    }
+    Ok(body)
+}
+
+fn normalize(input: &str, multiline: bool) -> String {
+    let mut normalized = String::with_capacity(input.len());
+    let mut in_control = false;
+    for c in input.nfc().filter(|c| *c != '\r') {
+        let kept = multiline && (c == '\n' || c == '\t');
+        if c.is_control() && !kept {
+            if !in_control {
+                normalized.push(' ');
+            }
+            in_control = true;
+            continue;
+        }
+        in_control = false;
+        normalized.push(c);
+    }
+    normalized.trim().to_string()
+}
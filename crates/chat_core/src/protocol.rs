@@ -1,21 +1,62 @@
 // use std::error::Error;
 
 use chrono::prelude::*;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    capability::Capabilities,
+    integrity::Integrity,
+    normalize::{normalize_body, normalize_username},
+    version::{FrameError, RawFrame, VersionRange, VERSION},
 };
 
 macro_rules! error_string {
     ($e:expr) => {
         if let Err(e) = $e {
-            return Err(e.to_string());
+            return Err(e.to_string().into());
+        }
+    };
+}
+
+// a failed read leaves nothing behind to sync up on
+macro_rules! read_or_closed {
+    ($e:expr) => {
+        if let Err(e) = $e {
+            return Err(FrameError::Closed(e.to_string()));
         }
     };
 }
 
-const HEADER_START: u16 = 0x5918;
-const VERSION: u8 = 0x01;
+pub(crate) const HEADER_START: u16 = 0x5918;
+
+// the server stats outgrew 16 fields, peers from before still reject a frame with more
+pub const MAX_FIELD_COUNT: u32 = 32;
+pub const MAX_FIELD_SIZE: u32 = 64 * 1024;
+
+pub const NOTICE_SESSION_TAKEOVER: &str = "session_takeover";
+pub const NOTICE_KICKED: &str = "kicked";
+pub const NOTICE_PASSWORD_RESET: &str = "password_reset";
+pub const NOTICE_ACCESS_LEVEL_CHANGED: &str = "access_level_changed";
+pub const NOTICE_TRUNCATED: &str = "truncated";
+pub const NOTICE_MESSAGE_EXPIRED: &str = "message_expired";
+
+// bits of the flags field on history entries
+pub const HISTORY_EDITED: u64 = 1 << 0;
+pub const HISTORY_DELETED: u64 = 1 << 1;
+pub const HISTORY_SELF_NOTE: u64 = 1 << 2;
+
+// bits of the flags field on received direct messages
+pub const DIRECT_MESSAGE_SELF_NOTE: u64 = 1 << 0;
+// the recipient muted the sender, it is not unread and asks for no notification
+pub const DIRECT_MESSAGE_MUTED: u64 = 1 << 1;
+
+// an ed25519 public key and a signature made with its secret half
+pub const SIGNING_KEY_LENGTH: usize = 32;
+pub const SIGNATURE_LENGTH: usize = 64;
+// the field a direct message carries its sender's signature in, after the ones it may leave out otherwise
+const SEND_SIGNATURE_FIELD: usize = 4;
+const RECEIVE_SIGNATURE_FIELD: usize = 6;
+const HISTORY_SIGNATURE_FIELD: usize = 6;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -25,54 +66,419 @@ pub enum MessageType {
     Nack = 0x02,
     Disconnect = 0x03,
     Heartbeat = 0x04,
+    TimeSync = 0x05,
+    TimeSyncReply = 0x06,
+    ClientHello = 0x07,
+    IntegritySelect = 0x08,
+    IntegritySelected = 0x09,
 
     // Authentification
     Auth = 0x10,
     AuthCreate = 0x11,
     AuthSuccess = 0x12,
     AuthFailure = 0x13,
+    PasswordChange = 0x14,
+    PasswordChanged = 0x15,
+    RenameAccount = 0x16,
+    AuthChallenge = 0x17,
 
     // Server administration
     ServerDebugLog = 0x20,
     ServerShutdown = 0x21,
+    AdminSetLogLevel = 0x22,
+    AdminResetPassword = 0x23,
+    AdminSetServerMode = 0x24,
+    AdminServerStats = 0x25,
+    AdminKickUser = 0x26,
+    AdminSetAccessLevel = 0x27,
+    AdminExportState = 0x28,
+    AdminRenameUser = 0x29,
+    AdminUserInfo = 0x2a,
+    AdminSetMotd = 0x2b,
+    AdminListSessions = 0x2c,
+    AdminKickWhere = 0x2d,
+    AdminListIpBans = 0x2e,
+    AdminClearIpBan = 0x2f,
 
     // Server Messages
     ServerShutdownWarning = 0x30,
+    AdminLogLevelChanged = 0x31,
+    ServerHello = 0x32,
+    ReauthRequired = 0x33,
+    SecurityNotice = 0x34,
+    ServerBusy = 0x35,
+    ServerStats = 0x36,
+    AccessLevelChanged = 0x37,
+    UserKicked = 0x38,
+    MissedNotice = 0x39,
+    StateExported = 0x3a,
+    UserRenamed = 0x3b,
+    UserInfo = 0x3c,
+    Motd = 0x3d,
+    SessionInfo = 0x3e,
+    SessionListEnd = 0x3f,
 
     // Messages
     MessageError = 0x40,
     DirectMessageSend = 0x41,
     DirectMessageReceive = 0x42,
+    MessageEdit = 0x43,
+    MessageDelete = 0x44,
+    MessageEdited = 0x45,
+    MessageDeleted = 0x46,
+    HistoryRequest = 0x47,
+    HistoryEntry = 0x48,
+    HistoryEnd = 0x49,
+    MarkConversationRead = 0x4a,
+    UnreadSummary = 0x4b,
+    SearchRequest = 0x4c,
+    SearchResult = 0x4d,
+    SearchEnd = 0x4e,
+    MessageExpired = 0x4f,
+
+    // File transfer
+    FileOffer = 0x50,
+    FileOffered = 0x51,
+    FileAccept = 0x52,
+    FileReject = 0x53,
+    FileChunk = 0x54,
+    FileComplete = 0x55,
+
+    // Preferences
+    SetPreference = 0x60,
+    GetPreferences = 0x61,
+    Preferences = 0x62,
+    PreferencesChanged = 0x63,
+
+    // Presence
+    PresenceUpdate = 0x70,
+    SubscribePresence = 0x71,
+    UnsubscribePresence = 0x72,
+    PresenceSnapshot = 0x73,
+    PresenceDelta = 0x74,
+
+    // Address bans
+    IpBanList = 0x80,
+
+    // State checks
+    AdminConsistencyCheck = 0x90,
+    ConsistencyReport = 0x91,
+
+    // Muting
+    MuteAdd = 0xa0,
+    MuteRemove = 0xa1,
+    MuteList = 0xa2,
+    MutedSenders = 0xa3,
+
+    // Temporary grants
+    AdminGrantTemporary = 0xb0,
+    AdminRevokeTemporary = 0xb1,
+
+    // Offline sync
+    SyncSelect = 0xc0,
+    OfflineSummary = 0xc1,
+    SyncRequest = 0xc2,
+    SyncBatch = 0xc3,
+
+    // Scheduled messages
+    ScheduleMessageSend = 0xd0,
+    ScheduleList = 0xd1,
+    ScheduleCancel = 0xd2,
+    ScheduledMessages = 0xd3,
+    ScheduleFired = 0xd4,
+
+    // API tokens
+    ApiTokenCreate = 0xe0,
+    ApiTokenRevoke = 0xe1,
+    ApiTokenList = 0xe2,
+    ApiTokens = 0xe3,
+    ApiTokenCreated = 0xe4,
+
+    // Message signing
+    SigningKeySet = 0xf0,
+    SigningKeyRequest = 0xf1,
+    SigningKey = 0xf2,
 
     // Break
     Break = 0xff,
 }
 
-#[derive(Debug, Clone)]
+// why a Disconnect was sent, travels as the first field of the frame
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+    ClientQuit = 0x00,
+    ServerShutdown = 0x01,
+    Kicked = 0x02,
+    Banned = 0x03,
+    IdleTimeout = 0x04,
+    ProtocolError = 0x05,
+    AuthTimeout = 0x06,
+}
+
+// what went wrong with a direct message, travels as the second field of a MessageError
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    // errors from older servers and anything without a code of its own
+    #[default]
+    Other = 0x00,
+    UserNotFound = 0x01,
+    RecipientOffline = 0x02,
+    OfflineQueueFull = 0x03,
+    InvalidCredentials = 0x04,
+    PasswordLoginDisabled = 0x05,
+    UserExists = 0x06,
+    // too many logins at once, an AuthFailure with it says when to try again
+    ServerBusy = 0x07,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Header {
     version: u8,
     message_type: MessageType,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct PayloadField {
     field_length: u32,
     field_data: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Payload {
     count: u32,
     fields: Vec<PayloadField>,
 }
 
-#[derive(Debug, Clone)]
+// what an admin sees in the ServerStats reply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub mode: String,
+    pub sessions: u64,
+    pub users: u64,
+    // summed over the sessions that are still connected, the other counters count since startup
+    pub missed_heartbeats: u64,
+    pub stalled_writes: u64,
+    pub flood_warnings: u64,
+    pub flood_disconnects: u64,
+    // messages waiting in offline queues right now
+    pub queued_messages: u64,
+    pub expired_messages: u64,
+    // connections that ended between frames and ones that broke inside a frame
+    pub closed_by_peer: u64,
+    pub broken_connections: u64,
+    // presence changes folded into another one and ones skipped for recipients that fell behind
+    pub presence_coalesced: u64,
+    pub presence_dropped: u64,
+    // failed accepts the server kept listening through
+    pub accept_errors: u64,
+    // frames whose trailer did not match the algorithm of their connection
+    pub integrity_failures: u64,
+    // offline notices posted to webhooks and ones given up on after the last retry
+    pub webhooks_delivered: u64,
+    pub webhooks_failed: u64,
+    // logins waiting for an argon2 slot right now and ones turned away busy since startup
+    pub auth_queue_depth: u64,
+    pub auth_rejected: u64,
+    // audit entries and metric snapshots the full journal queue gave up, and whether writing it fails right now
+    pub journal_dropped: u64,
+    pub journal_failing: bool,
+    // which deployment answered, the id stays the same across restarts
+    pub server_name: String,
+    pub server_id: String,
+}
+
+// what an admin sees in the UserInfo reply, the relay counters count since startup
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserInfo {
+    pub username: String,
+    pub access_level: String,
+    pub sessions: u64,
+    pub sent: u64,
+    pub delivered: u64,
+    pub queued: u64,
+    pub dropped: u64,
+    // empty until a message of the user could not be delivered
+    pub last_error: String,
+    // the permission and when it lapses as rfc3339, one entry per live grant
+    pub temporary_grants: Vec<(String, String)>,
+}
+
+// what the server assigns a direct message once, when it stores it, every relay, queue entry, history row,
+// ack and edit carries the same one, they go up in the order the messages were sent. a counter and not a UUID,
+// with a data directory it goes on where the last start left it, so a client never sees one id for two messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageId(u64);
+
+// which sessions an AdminListSessions or AdminKickWhere is about, every condition that is set has to match
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionFilter {
+    pub guests_only: bool,
+    // 0 matches any session
+    pub idle_minutes: u64,
+    // compared with the start of the peer address as text, empty matches any session
+    pub ip_prefix: String,
+}
+
+// one row of the session list, ages are in seconds and the counters count frames
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: String,
+    // "guest" until the session logged in
+    pub username: String,
+    pub peer: String,
+    pub access_level: String,
+    pub connected: u64,
+    // since the last frame that was not a heartbeat
+    pub idle: u64,
+    pub heartbeat_age: u64,
+    pub received: u64,
+    pub sent: u64,
+    // since the last frame of any kind, 0 from servers that do not send it
+    pub received_age: u64,
+    // since the client last echoed one of our heartbeats, proof that both directions work
+    pub two_way_age: u64,
+    // the algorithm the client selected and its frames that failed it, crc32 and 0 from older servers
+    pub integrity: String,
+    pub integrity_failures: u64,
+}
+
+// when a scheduled message is due, a relative time is counted from when the server got the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleTime {
+    At(DateTime<Utc>),
+    In(std::time::Duration),
+}
+
+// a message waiting on the server for its due time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledEntry {
+    pub id: u64,
+    pub recipient: String,
+    pub body: String,
+    pub due: DateTime<Utc>,
+}
+
+// a token a user made for the http bridge, the token itself is only ever sent once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiTokenInfo {
+    pub id: u64,
+    pub label: String,
+    pub created: DateTime<Utc>,
+}
+
+// whose presence a subscription follows, contacts are the users one has a conversation with
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PresenceScope {
+    All,
+    Contacts,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    DoNotDisturb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PresenceChange {
+    Joined,
+    Left,
+    StatusChanged,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceEntry {
+    pub user: String,
+    pub status: PresenceStatus,
+}
+
+// who is online in the scope as of `version`, the deltas that follow count on from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceSnapshot {
+    pub scope: PresenceScope,
+    pub version: u64,
+    pub entries: Vec<PresenceEntry>,
+}
+
+// one change, a version that does not follow the last one means something was missed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceDelta {
+    pub scope: PresenceScope,
+    pub version: u64,
+    pub change: PresenceChange,
+    pub entry: PresenceEntry,
+}
+
+// addresses turned away at accept time after repeated protocol violations, the counters count since startup
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpBanList {
+    // each address with the seconds left on its ban
+    pub bans: Vec<(String, u64)>,
+    pub issued: u64,
+    pub turned_away: u64,
+}
+
+// what a consistency check walked and the cross references it found broken
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    pub users: u64,
+    pub sessions: u64,
+    pub violations: Vec<ConsistencyViolation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyViolation {
+    // which invariant is broken, like `user_session`
+    pub invariant: String,
+    pub detail: String,
+    // only with a repair requested, and only for what could be dropped without guessing
+    pub repaired: bool,
+}
+
+// what waited for a client that pulls its offline queue, sent once after the login
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OfflineSummary {
+    // everything up to here was acknowledged before, the first SyncRequest starts from it
+    pub cursor: u64,
+    pub total: u64,
+    // direct messages by sender, notices about a message only count towards the total
+    pub peers: Vec<(String, u64)>,
+}
+
+// announces a batch of queued messages, they follow it in this order and nothing comes between them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncBatch {
+    // one per message, sent back as the cursor it acknowledges that message and everything before it
+    pub sequences: Vec<u64>,
+    // what is still queued after this batch
+    pub remaining: u64,
+}
+
+// the sequence numbers a heartbeat carries, peers that send only the timestamp have none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeartbeatEcho {
+    // counts from 1 per connection and side
+    pub sequence: u64,
+    // the last sequence received from the peer, 0 before the first one
+    pub echo: u64,
+    // replies are never answered, or two peers would keep each other busy
+    pub reply: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Message {
     header: Header,
     payload: Payload,
     checksum: u32,
 }
 
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
 #[derive(Debug)]
 pub struct MessageBuilder {
     header: Header,
@@ -80,31 +486,407 @@ pub struct MessageBuilder {
 }
 
 impl MessageType {
+    pub const ALL: &[MessageType] = &[
+        MessageType::Empty,
+        MessageType::Ack,
+        MessageType::Nack,
+        MessageType::Disconnect,
+        MessageType::Heartbeat,
+        MessageType::TimeSync,
+        MessageType::TimeSyncReply,
+        MessageType::ClientHello,
+        MessageType::IntegritySelect,
+        MessageType::IntegritySelected,
+        MessageType::Auth,
+        MessageType::AuthCreate,
+        MessageType::AuthSuccess,
+        MessageType::AuthFailure,
+        MessageType::PasswordChange,
+        MessageType::PasswordChanged,
+        MessageType::RenameAccount,
+        MessageType::AuthChallenge,
+        MessageType::ServerDebugLog,
+        MessageType::ServerShutdown,
+        MessageType::AdminSetLogLevel,
+        MessageType::AdminResetPassword,
+        MessageType::AdminSetServerMode,
+        MessageType::AdminServerStats,
+        MessageType::AdminKickUser,
+        MessageType::AdminSetAccessLevel,
+        MessageType::AdminExportState,
+        MessageType::AdminRenameUser,
+        MessageType::AdminUserInfo,
+        MessageType::AdminSetMotd,
+        MessageType::AdminListSessions,
+        MessageType::AdminKickWhere,
+        MessageType::AdminListIpBans,
+        MessageType::AdminClearIpBan,
+        MessageType::ServerShutdownWarning,
+        MessageType::AdminLogLevelChanged,
+        MessageType::ServerHello,
+        MessageType::ReauthRequired,
+        MessageType::SecurityNotice,
+        MessageType::ServerBusy,
+        MessageType::ServerStats,
+        MessageType::AccessLevelChanged,
+        MessageType::UserKicked,
+        MessageType::MissedNotice,
+        MessageType::StateExported,
+        MessageType::UserRenamed,
+        MessageType::UserInfo,
+        MessageType::Motd,
+        MessageType::SessionInfo,
+        MessageType::SessionListEnd,
+        MessageType::MessageError,
+        MessageType::DirectMessageSend,
+        MessageType::DirectMessageReceive,
+        MessageType::MessageEdit,
+        MessageType::MessageDelete,
+        MessageType::MessageEdited,
+        MessageType::MessageDeleted,
+        MessageType::HistoryRequest,
+        MessageType::HistoryEntry,
+        MessageType::HistoryEnd,
+        MessageType::MarkConversationRead,
+        MessageType::UnreadSummary,
+        MessageType::SearchRequest,
+        MessageType::SearchResult,
+        MessageType::SearchEnd,
+        MessageType::MessageExpired,
+        MessageType::FileOffer,
+        MessageType::FileOffered,
+        MessageType::FileAccept,
+        MessageType::FileReject,
+        MessageType::FileChunk,
+        MessageType::FileComplete,
+        MessageType::SetPreference,
+        MessageType::GetPreferences,
+        MessageType::Preferences,
+        MessageType::PreferencesChanged,
+        MessageType::PresenceUpdate,
+        MessageType::SubscribePresence,
+        MessageType::UnsubscribePresence,
+        MessageType::PresenceSnapshot,
+        MessageType::PresenceDelta,
+        MessageType::IpBanList,
+        MessageType::AdminConsistencyCheck,
+        MessageType::ConsistencyReport,
+        MessageType::MuteAdd,
+        MessageType::MuteRemove,
+        MessageType::MuteList,
+        MessageType::MutedSenders,
+        MessageType::AdminGrantTemporary,
+        MessageType::AdminRevokeTemporary,
+        MessageType::SyncSelect,
+        MessageType::OfflineSummary,
+        MessageType::SyncRequest,
+        MessageType::SyncBatch,
+        MessageType::ScheduleMessageSend,
+        MessageType::ScheduleList,
+        MessageType::ScheduleCancel,
+        MessageType::ScheduledMessages,
+        MessageType::ScheduleFired,
+        MessageType::ApiTokenCreate,
+        MessageType::ApiTokenRevoke,
+        MessageType::ApiTokenList,
+        MessageType::ApiTokens,
+        MessageType::ApiTokenCreated,
+        MessageType::SigningKeySet,
+        MessageType::SigningKeyRequest,
+        MessageType::SigningKey,
+        MessageType::Break,
+    ];
+
     pub fn from(value: u8) -> Self {
-        match value {
+        Self::try_from(value).unwrap_or(MessageType::Empty)
+    }
+
+    // the connection bookkeeping frames, everything from 0x10 up is application traffic
+    pub fn is_control(self) -> bool {
+        (self as u8) < 0x10
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let message_type = match value {
             0x00 => MessageType::Empty,
             0x01 => MessageType::Ack,
             0x02 => MessageType::Nack,
             0x03 => MessageType::Disconnect,
             0x04 => MessageType::Heartbeat,
+            0x05 => MessageType::TimeSync,
+            0x06 => MessageType::TimeSyncReply,
+            0x07 => MessageType::ClientHello,
+            0x08 => MessageType::IntegritySelect,
+            0x09 => MessageType::IntegritySelected,
 
             0x10 => MessageType::Auth,
             0x11 => MessageType::AuthCreate,
             0x12 => MessageType::AuthSuccess,
             0x13 => MessageType::AuthFailure,
+            0x14 => MessageType::PasswordChange,
+            0x15 => MessageType::PasswordChanged,
+            0x16 => MessageType::RenameAccount,
+            0x17 => MessageType::AuthChallenge,
 
             0x20 => MessageType::ServerDebugLog,
             0x21 => MessageType::ServerShutdown,
+            0x22 => MessageType::AdminSetLogLevel,
+            0x23 => MessageType::AdminResetPassword,
+            0x24 => MessageType::AdminSetServerMode,
+            0x25 => MessageType::AdminServerStats,
+            0x26 => MessageType::AdminKickUser,
+            0x27 => MessageType::AdminSetAccessLevel,
+            0x28 => MessageType::AdminExportState,
+            0x29 => MessageType::AdminRenameUser,
+            0x2a => MessageType::AdminUserInfo,
+            0x2b => MessageType::AdminSetMotd,
+            0x2c => MessageType::AdminListSessions,
+            0x2d => MessageType::AdminKickWhere,
+            0x2e => MessageType::AdminListIpBans,
+            0x2f => MessageType::AdminClearIpBan,
 
             0x30 => MessageType::ServerShutdownWarning,
+            0x31 => MessageType::AdminLogLevelChanged,
+            0x32 => MessageType::ServerHello,
+            0x33 => MessageType::ReauthRequired,
+            0x34 => MessageType::SecurityNotice,
+            0x35 => MessageType::ServerBusy,
+            0x36 => MessageType::ServerStats,
+            0x37 => MessageType::AccessLevelChanged,
+            0x38 => MessageType::UserKicked,
+            0x39 => MessageType::MissedNotice,
+            0x3a => MessageType::StateExported,
+            0x3b => MessageType::UserRenamed,
+            0x3c => MessageType::UserInfo,
+            0x3d => MessageType::Motd,
+            0x3e => MessageType::SessionInfo,
+            0x3f => MessageType::SessionListEnd,
 
             0x40 => MessageType::MessageError,
             0x41 => MessageType::DirectMessageSend,
             0x42 => MessageType::DirectMessageReceive,
+            0x43 => MessageType::MessageEdit,
+            0x44 => MessageType::MessageDelete,
+            0x45 => MessageType::MessageEdited,
+            0x46 => MessageType::MessageDeleted,
+            0x47 => MessageType::HistoryRequest,
+            0x48 => MessageType::HistoryEntry,
+            0x49 => MessageType::HistoryEnd,
+            0x4a => MessageType::MarkConversationRead,
+            0x4b => MessageType::UnreadSummary,
+            0x4c => MessageType::SearchRequest,
+            0x4d => MessageType::SearchResult,
+            0x4e => MessageType::SearchEnd,
+            0x4f => MessageType::MessageExpired,
+
+            0x50 => MessageType::FileOffer,
+            0x51 => MessageType::FileOffered,
+            0x52 => MessageType::FileAccept,
+            0x53 => MessageType::FileReject,
+            0x54 => MessageType::FileChunk,
+            0x55 => MessageType::FileComplete,
+            0x60 => MessageType::SetPreference,
+            0x61 => MessageType::GetPreferences,
+            0x62 => MessageType::Preferences,
+            0x63 => MessageType::PreferencesChanged,
+
+            0x70 => MessageType::PresenceUpdate,
+            0x71 => MessageType::SubscribePresence,
+            0x72 => MessageType::UnsubscribePresence,
+            0x73 => MessageType::PresenceSnapshot,
+            0x74 => MessageType::PresenceDelta,
+
+            0x80 => MessageType::IpBanList,
+
+            0x90 => MessageType::AdminConsistencyCheck,
+            0x91 => MessageType::ConsistencyReport,
+
+            0xa0 => MessageType::MuteAdd,
+            0xa1 => MessageType::MuteRemove,
+            0xa2 => MessageType::MuteList,
+            0xa3 => MessageType::MutedSenders,
+
+            0xb0 => MessageType::AdminGrantTemporary,
+            0xb1 => MessageType::AdminRevokeTemporary,
+
+            0xc0 => MessageType::SyncSelect,
+            0xc1 => MessageType::OfflineSummary,
+            0xc2 => MessageType::SyncRequest,
+            0xc3 => MessageType::SyncBatch,
+
+            0xd0 => MessageType::ScheduleMessageSend,
+            0xd1 => MessageType::ScheduleList,
+            0xd2 => MessageType::ScheduleCancel,
+            0xd3 => MessageType::ScheduledMessages,
+            0xd4 => MessageType::ScheduleFired,
+            0xe0 => MessageType::ApiTokenCreate,
+            0xe1 => MessageType::ApiTokenRevoke,
+            0xe2 => MessageType::ApiTokenList,
+            0xe3 => MessageType::ApiTokens,
+            0xe4 => MessageType::ApiTokenCreated,
+            0xf0 => MessageType::SigningKeySet,
+            0xf1 => MessageType::SigningKeyRequest,
+            0xf2 => MessageType::SigningKey,
 
             0xff => MessageType::Break,
 
-            _ => MessageType::Empty,
+            _ => return Err(format!("Unknown message type 0x{:02x}", value)),
+        };
+        Ok(message_type)
+    }
+}
+
+impl DisconnectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisconnectReason::ClientQuit => "client_quit",
+            DisconnectReason::ServerShutdown => "server_shutdown",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::Banned => "banned",
+            DisconnectReason::IdleTimeout => "idle_timeout",
+            DisconnectReason::ProtocolError => "protocol_error",
+            DisconnectReason::AuthTimeout => "auth_timeout",
+        }
+    }
+}
+
+impl TryFrom<u8> for DisconnectReason {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(DisconnectReason::ClientQuit),
+            0x01 => Ok(DisconnectReason::ServerShutdown),
+            0x02 => Ok(DisconnectReason::Kicked),
+            0x03 => Ok(DisconnectReason::Banned),
+            0x04 => Ok(DisconnectReason::IdleTimeout),
+            0x05 => Ok(DisconnectReason::ProtocolError),
+            0x06 => Ok(DisconnectReason::AuthTimeout),
+            _ => Err(format!("Unknown disconnect reason 0x{:02x}", value)),
+        }
+    }
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Other => "other",
+            ErrorCode::UserNotFound => "user_not_found",
+            ErrorCode::RecipientOffline => "recipient_offline",
+            ErrorCode::OfflineQueueFull => "offline_queue_full",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::PasswordLoginDisabled => "password_login_disabled",
+            ErrorCode::UserExists => "user_exists",
+            ErrorCode::ServerBusy => "server_busy",
+        }
+    }
+}
+
+impl TryFrom<u8> for ErrorCode {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(ErrorCode::Other),
+            0x01 => Ok(ErrorCode::UserNotFound),
+            0x02 => Ok(ErrorCode::RecipientOffline),
+            0x03 => Ok(ErrorCode::OfflineQueueFull),
+            0x04 => Ok(ErrorCode::InvalidCredentials),
+            0x05 => Ok(ErrorCode::PasswordLoginDisabled),
+            0x06 => Ok(ErrorCode::UserExists),
+            0x07 => Ok(ErrorCode::ServerBusy),
+            _ => Err(format!("Unknown error code 0x{:02x}", value)),
+        }
+    }
+}
+
+impl PresenceScope {
+    // `all` or `contacts`
+    pub fn encode(&self) -> String {
+        match self {
+            PresenceScope::All => "all".to_string(),
+            PresenceScope::Contacts => "contacts".to_string(),
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "all" => Ok(PresenceScope::All),
+            "contacts" => Ok(PresenceScope::Contacts),
+            _ => Err(format!("Unknown presence scope '{}', expected all or contacts", value)),
+        }
+    }
+}
+
+impl PresenceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::DoNotDisturb => "dnd",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "online" => Ok(PresenceStatus::Online),
+            "dnd" => Ok(PresenceStatus::DoNotDisturb),
+            _ => Err(format!("Unknown presence status '{}'", value)),
+        }
+    }
+}
+
+impl PresenceChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceChange::Joined => "joined",
+            PresenceChange::Left => "left",
+            PresenceChange::StatusChanged => "status",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "joined" => Ok(PresenceChange::Joined),
+            "left" => Ok(PresenceChange::Left),
+            "status" => Ok(PresenceChange::StatusChanged),
+            _ => Err(format!("Unknown presence change '{}'", value)),
+        }
+    }
+}
+
+impl ScheduleTime {
+    // `+<seconds>` for a relative time, RFC3339 for an absolute one
+    pub fn encode(&self) -> String {
+        match self {
+            ScheduleTime::At(due) => due.to_rfc3339(),
+            ScheduleTime::In(delay) => format!("+{}", delay.as_secs()),
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.strip_prefix('+') {
+            Some(seconds) => seconds
+                .parse()
+                .map(|seconds| ScheduleTime::In(std::time::Duration::from_secs(seconds)))
+                .map_err(|_| format!("Invalid delay '{}'", value)),
+            None => DateTime::parse_from_rfc3339(value)
+                .map(|due| ScheduleTime::At(due.with_timezone(&Utc)))
+                .map_err(|_| format!("Invalid time '{}', expected RFC3339 or +<seconds>", value)),
+        }
+    }
+
+    // the due time of a request the server got at `now`
+    pub fn due(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            ScheduleTime::At(due) => *due,
+            ScheduleTime::In(delay) => chrono::Duration::from_std(*delay)
+                .ok()
+                .and_then(|delay| now.checked_add_signed(delay))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
         }
     }
 }
@@ -127,9 +909,9 @@ impl PayloadField {
     }
 }
 
-impl Into<String> for PayloadField {
-    fn into(self) -> String {
-        String::from_utf8_lossy(&self.field_data).to_string()
+impl From<PayloadField> for String {
+    fn from(field: PayloadField) -> Self {
+        String::from_utf8_lossy(&field.field_data).to_string()
     }
 }
 
@@ -161,15 +943,67 @@ impl Payload {
     pub fn get_data(&self) -> Vec<Vec<u8>> {
         self.fields.iter().map(|field| field.field_data.clone()).collect()
     }
-}
 
-impl Default for Payload {
-    fn default() -> Self {
-        Payload {
-            count: 0,
-            fields: Vec::new(),
+    pub fn field_count(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn field(&self, index: usize) -> Result<&[u8], String> {
+        self.fields
+            .get(index)
+            .map(|field| field.field_data.as_slice())
+            .ok_or_else(|| format!("Missing payload field {}", index))
+    }
+
+    pub fn str_field(&self, index: usize) -> Result<&str, String> {
+        std::str::from_utf8(self.field(index)?).map_err(|_| format!("Payload field {} is not valid UTF-8", index))
+    }
+
+    // a name to look up or store, in the form normalize_username leaves it
+    pub fn username_field(&self, index: usize) -> Result<String, String> {
+        normalize_username(self.str_field(index)?)
+    }
+
+    // a message body, in the form normalize_body leaves it
+    pub fn body_field(&self, index: usize) -> Result<String, String> {
+        normalize_body(self.str_field(index)?)
+    }
+
+    // empty for no key at all
+    pub fn signing_key_field(&self, index: usize) -> Result<Option<[u8; SIGNING_KEY_LENGTH]>, String> {
+        match self.field(index)? {
+            [] => Ok(None),
+            bytes => bytes.try_into().map(Some).map_err(|_| {
+                format!(
+                    "Payload field {} is {} bytes, expected {}",
+                    index,
+                    bytes.len(),
+                    SIGNING_KEY_LENGTH
+                )
+            }),
         }
     }
+
+    pub fn u64_field(&self, index: usize) -> Result<u64, String> {
+        let bytes = self.field(index)?;
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| format!("Payload field {} is {} bytes, expected 8", index, bytes.len()))?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn message_id_field(&self, index: usize) -> Result<MessageId, String> {
+        self.u64_field(index).map(MessageId)
+    }
+
+    // timestamps travel as microseconds since the unix epoch
+    pub fn timestamp_field(&self, index: usize) -> Result<DateTime<Utc>, String> {
+        let micros = self.u64_field(index)?;
+        i64::try_from(micros)
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(|| format!("Payload field {} is not a valid timestamp", index))
+    }
 }
 
 impl Message {
@@ -216,6 +1050,73 @@ impl Message {
         }
     }
 
+    pub fn heartbeat_with(echo: HeartbeatEcho) -> Self {
+        MessageBuilder::new(MessageType::Heartbeat)
+            .with_field(Local::now().to_rfc3339().into_bytes())
+            .with_field(echo.sequence.to_be_bytes().to_vec())
+            .with_field(echo.echo.to_be_bytes().to_vec())
+            .with_field(u64::from(echo.reply).to_be_bytes().to_vec())
+            .build()
+    }
+
+    // None for heartbeats with only the timestamp
+    pub fn heartbeat_echo(&self) -> Option<HeartbeatEcho> {
+        let payload = self.payload();
+        Some(HeartbeatEcho {
+            sequence: payload.u64_field(1).ok()?,
+            echo: payload.u64_field(2).ok()?,
+            reply: payload.u64_field(3).ok()? != 0,
+        })
+    }
+
+    pub fn time_sync(client_sent: DateTime<Utc>) -> Self {
+        MessageBuilder::new(MessageType::TimeSync)
+            .with_field(timestamp_bytes(client_sent))
+            .build()
+    }
+
+    // sent right after the server hello, before the login, to servers that announce the languages capability
+    pub fn client_hello(language: &str) -> Self {
+        MessageBuilder::new(MessageType::ClientHello)
+            .with_field(language.as_bytes().to_vec())
+            .build()
+    }
+
+    // the algorithm the client wants its frames checked with, sent to servers that advertise it
+    pub fn integrity_select(integrity: Integrity) -> Self {
+        MessageBuilder::new(MessageType::IntegritySelect)
+            .with_field(integrity.as_str().as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn integrity_selected(integrity: Integrity) -> Self {
+        MessageBuilder::new(MessageType::IntegritySelected)
+            .with_field(integrity.as_str().as_bytes().to_vec())
+            .build()
+    }
+
+    // both frames still travel with the old trailer, the direction they went switches right after them
+    pub fn switches_integrity(&self) -> Option<Integrity> {
+        match self.message_type() {
+            MessageType::IntegritySelect | MessageType::IntegritySelected => {
+                Integrity::parse(self.payload.str_field(0).ok()?).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn time_sync_reply(
+        client_sent: DateTime<Utc>,
+        server_received: DateTime<Utc>,
+        server_sent: DateTime<Utc>,
+    ) -> Self {
+        MessageBuilder::new(MessageType::TimeSyncReply)
+            .with_field(timestamp_bytes(client_sent))
+            .with_field(timestamp_bytes(server_received))
+            .with_field(timestamp_bytes(server_sent))
+            .build()
+    }
+
     pub fn auth(username: &str, password: &str) -> Self {
         let mut payload = Payload::default();
         payload.add_field(username.as_bytes().to_vec());
@@ -229,6 +1130,14 @@ impl Message {
         }
     }
 
+    // the response proves the password for the nonce of this connection, see auth::challenge_response
+    pub fn auth_challenge(username: &str, response: &[u8]) -> Self {
+        MessageBuilder::new(MessageType::AuthChallenge)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(response.to_vec())
+            .build()
+    }
+
     pub fn auth_create(username: &str, password: &str) -> Self {
         let mut payload = Payload::default();
         payload.add_field(username.as_bytes().to_vec());
@@ -250,6 +1159,15 @@ impl Message {
         }
     }
 
+    // the id stays the same when the account is renamed, for clients that track users across renames,
+    // the access level lets clients refuse commands the server would reject anyway
+    pub fn auth_success_with_id(user_id: &str, access_level: &str) -> Self {
+        MessageBuilder::new(MessageType::AuthSuccess)
+            .with_field(user_id.as_bytes().to_vec())
+            .with_field(access_level.as_bytes().to_vec())
+            .build()
+    }
+
     pub fn auth_fail(error: &str) -> Self {
         let mut payload = Payload::default();
         payload.add_field(error.as_bytes().to_vec());
@@ -262,6 +1180,132 @@ impl Message {
         }
     }
 
+    // the code comes second, like in MessageError, older clients only read the text
+    pub fn auth_fail_with_code(code: ErrorCode, error: &str) -> Self {
+        MessageBuilder::new(MessageType::AuthFailure)
+            .with_field(error.as_bytes().to_vec())
+            .with_field(vec![code as u8])
+            .build()
+    }
+
+    // the login was not even checked, the retry-after in seconds follows the code
+    pub fn auth_busy(retry_after: u64, error: &str) -> Self {
+        MessageBuilder::new(MessageType::AuthFailure)
+            .with_field(error.as_bytes().to_vec())
+            .with_field(vec![ErrorCode::ServerBusy as u8])
+            .with_field(retry_after.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // None for every other failure, the credentials were wrong or the server could not tell
+    pub fn auth_retry_after(&self) -> Option<u64> {
+        let payload = self.payload();
+        match payload.field(1) {
+            Ok([code]) if *code == ErrorCode::ServerBusy as u8 => payload.u64_field(2).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn password_change(old_password: &str, new_password: &str) -> Self {
+        MessageBuilder::new(MessageType::PasswordChange)
+            .with_field(old_password.as_bytes().to_vec())
+            .with_field(new_password.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn password_changed(username: &str) -> Self {
+        MessageBuilder::new(MessageType::PasswordChanged)
+            .with_field(username.as_bytes().to_vec())
+            .build()
+    }
+
+    // the password confirms it is really the account owner asking
+    pub fn rename_account(new_name: &str, password: &str) -> Self {
+        MessageBuilder::new(MessageType::RenameAccount)
+            .with_field(new_name.as_bytes().to_vec())
+            .with_field(password.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_rename_user(old_name: &str, new_name: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminRenameUser)
+            .with_field(old_name.as_bytes().to_vec())
+            .with_field(new_name.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn user_renamed(old_name: &str, new_name: &str) -> Self {
+        MessageBuilder::new(MessageType::UserRenamed)
+            .with_field(old_name.as_bytes().to_vec())
+            .with_field(new_name.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn reauth_required(reason: &str) -> Self {
+        MessageBuilder::new(MessageType::ReauthRequired)
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn security_notice(kind: &str, detail: &str) -> Self {
+        MessageBuilder::new(MessageType::SecurityNotice)
+            .with_field(kind.as_bytes().to_vec())
+            .with_field(detail.as_bytes().to_vec())
+            .build()
+    }
+
+    // the peer answers with an Ack before it closes its end
+    pub fn disconnect(reason: DisconnectReason, detail: &str) -> Self {
+        MessageBuilder::new(MessageType::Disconnect)
+            .with_field(vec![reason as u8])
+            .with_field(detail.as_bytes().to_vec())
+            .build()
+    }
+
+    // a bare Disconnect predates the reason codes and means the client quit
+    pub fn disconnect_reason(&self) -> Result<(DisconnectReason, String), String> {
+        let payload = self.payload();
+        if payload.field_count() == 0 {
+            return Ok((DisconnectReason::ClientQuit, String::new()));
+        }
+        let code = match payload.field(0)? {
+            [code] => *code,
+            _ => return Err("Disconnect reason is not a single byte".to_string()),
+        };
+        let detail = match payload.field_count() {
+            1 => "",
+            _ => payload.str_field(1)?,
+        };
+        Ok((DisconnectReason::try_from(code)?, detail.to_string()))
+    }
+
+    // a NACK naming the message type the session was not allowed to send
+    pub fn not_authorized(rejected: MessageType) -> Self {
+        MessageBuilder::new(MessageType::Nack)
+            .with_field(vec![rejected as u8])
+            .build()
+    }
+
+    // a NACK that says what was wrong with the request, clients from before only read the type
+    pub fn rejected(rejected: MessageType, reason: &str) -> Self {
+        MessageBuilder::new(MessageType::Nack)
+            .with_field(vec![rejected as u8])
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    // None for a bare NACK, the server also sends those for malformed requests
+    pub fn rejected_type(&self) -> Option<MessageType> {
+        match self.payload().field(0) {
+            Ok([rejected]) => MessageType::try_from(*rejected).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn rejection_reason(&self) -> Option<&str> {
+        self.payload().str_field(1).ok()
+    }
+
     pub fn server_shutdown(timeout: u64) -> Self {
         let mut payload = Payload::default();
         payload.add_field(timeout.to_be_bytes().to_vec());
@@ -274,6 +1318,279 @@ impl Message {
         }
     }
 
+    pub fn admin_set_log_level(filter: &str, revert_after: Option<u64>) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::AdminSetLogLevel).with_field(filter.as_bytes().to_vec());
+        if let Some(revert_after) = revert_after {
+            builder = builder.with_field(revert_after.to_be_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn admin_reset_password(username: &str, password: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminResetPassword)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(password.as_bytes().to_vec())
+            .build()
+    }
+
+    // stop_after drains until no sessions are left or the timeout expires, then stops the server
+    pub fn admin_set_server_mode(mode: &str, stop_after: Option<u64>) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::AdminSetServerMode).with_field(mode.as_bytes().to_vec());
+        if let Some(stop_after) = stop_after {
+            builder = builder.with_field(stop_after.to_be_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn admin_server_stats() -> Self {
+        MessageBuilder::new(MessageType::AdminServerStats).build()
+    }
+
+    pub fn admin_kick_user(username: &str, reason: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminKickUser)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_set_access_level(username: &str, level: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminSetAccessLevel)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(level.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_export_state() -> Self {
+        MessageBuilder::new(MessageType::AdminExportState).build()
+    }
+
+    // the path is where the server wrote the export, on its own filesystem
+    pub fn state_exported(path: &str, users: u64) -> Self {
+        MessageBuilder::new(MessageType::StateExported)
+            .with_field(path.as_bytes().to_vec())
+            .with_field(users.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn access_level_changed(username: &str, level: &str) -> Self {
+        MessageBuilder::new(MessageType::AccessLevelChanged)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(level.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn user_kicked(username: &str, reason: &str) -> Self {
+        MessageBuilder::new(MessageType::UserKicked)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    // a notice that happened while the user was offline, replayed after the next login
+    pub fn missed_notice(kind: &str, detail: &str, occurred_at: DateTime<Utc>) -> Self {
+        MessageBuilder::new(MessageType::MissedNotice)
+            .with_field(kind.as_bytes().to_vec())
+            .with_field(detail.as_bytes().to_vec())
+            .with_field(timestamp_bytes(occurred_at))
+            .build()
+    }
+
+    pub fn server_busy(retry_after: u64, reason: &str) -> Self {
+        MessageBuilder::new(MessageType::ServerBusy)
+            .with_field(retry_after.to_be_bytes().to_vec())
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn server_stats(stats: &ServerStats) -> Self {
+        MessageBuilder::new(MessageType::ServerStats)
+            .with_field(stats.mode.as_bytes().to_vec())
+            .with_field(stats.sessions.to_be_bytes().to_vec())
+            .with_field(stats.users.to_be_bytes().to_vec())
+            .with_field(stats.missed_heartbeats.to_be_bytes().to_vec())
+            .with_field(stats.stalled_writes.to_be_bytes().to_vec())
+            .with_field(stats.flood_warnings.to_be_bytes().to_vec())
+            .with_field(stats.flood_disconnects.to_be_bytes().to_vec())
+            .with_field(stats.queued_messages.to_be_bytes().to_vec())
+            .with_field(stats.expired_messages.to_be_bytes().to_vec())
+            .with_field(stats.server_name.as_bytes().to_vec())
+            .with_field(stats.server_id.as_bytes().to_vec())
+            .with_field(stats.closed_by_peer.to_be_bytes().to_vec())
+            .with_field(stats.broken_connections.to_be_bytes().to_vec())
+            .with_field(stats.presence_coalesced.to_be_bytes().to_vec())
+            .with_field(stats.presence_dropped.to_be_bytes().to_vec())
+            .with_field(stats.accept_errors.to_be_bytes().to_vec())
+            .with_field(stats.integrity_failures.to_be_bytes().to_vec())
+            .with_field(stats.webhooks_delivered.to_be_bytes().to_vec())
+            .with_field(stats.webhooks_failed.to_be_bytes().to_vec())
+            .with_field(stats.auth_queue_depth.to_be_bytes().to_vec())
+            .with_field(stats.auth_rejected.to_be_bytes().to_vec())
+            .with_field(stats.journal_dropped.to_be_bytes().to_vec())
+            .with_field(u64::from(stats.journal_failing).to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_user_info(username: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminUserInfo)
+            .with_field(username.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn user_info(info: &UserInfo) -> Self {
+        MessageBuilder::new(MessageType::UserInfo)
+            .with_field(info.username.as_bytes().to_vec())
+            .with_field(info.access_level.as_bytes().to_vec())
+            .with_field(info.sessions.to_be_bytes().to_vec())
+            .with_field(info.sent.to_be_bytes().to_vec())
+            .with_field(info.delivered.to_be_bytes().to_vec())
+            .with_field(info.queued.to_be_bytes().to_vec())
+            .with_field(info.dropped.to_be_bytes().to_vec())
+            .with_field(info.last_error.as_bytes().to_vec())
+            .with_field(
+                info.temporary_grants
+                    .iter()
+                    .map(|(permission, expires_at)| format!("{} {}", permission, expires_at))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .into_bytes(),
+            )
+            .build()
+    }
+
+    // older servers leave the grants out, that reads as none
+    pub fn user_info_grants(&self) -> Result<Vec<(String, String)>, String> {
+        let Ok(grants) = self.payload().str_field(8) else {
+            return Ok(Vec::new());
+        };
+        grants
+            .split('\n')
+            .filter(|grant| !grant.is_empty())
+            .map(|grant| {
+                grant
+                    .split_once(' ')
+                    .map(|(permission, expires_at)| (permission.to_string(), expires_at.to_string()))
+                    .ok_or_else(|| format!("Invalid temporary grant '{}'", grant))
+            })
+            .collect()
+    }
+
+    // lends the user one permission on top of their access level until the duration is up
+    pub fn admin_grant_temporary(username: &str, permission: &str, duration_secs: u64) -> Self {
+        MessageBuilder::new(MessageType::AdminGrantTemporary)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(permission.as_bytes().to_vec())
+            .with_field(duration_secs.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_revoke_temporary(username: &str, permission: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminRevokeTemporary)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(permission.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_list_sessions(filter: &SessionFilter) -> Self {
+        filter
+            .fields(MessageBuilder::new(MessageType::AdminListSessions))
+            .build()
+    }
+
+    // a dry run answers with the sessions that would go and leaves them connected
+    pub fn admin_kick_where(filter: &SessionFilter, reason: &str, dry_run: bool) -> Self {
+        filter
+            .fields(MessageBuilder::new(MessageType::AdminKickWhere))
+            .with_field(reason.as_bytes().to_vec())
+            .with_field(u64::from(dry_run).to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the first three fields of both session requests
+    pub fn session_filter(&self) -> Result<SessionFilter, String> {
+        let payload = self.payload();
+        Ok(SessionFilter {
+            guests_only: payload.u64_field(0)? != 0,
+            idle_minutes: payload.u64_field(1)?,
+            ip_prefix: payload.str_field(2)?.to_string(),
+        })
+    }
+
+    pub fn session_info(info: &SessionInfo) -> Self {
+        MessageBuilder::new(MessageType::SessionInfo)
+            .with_field(info.id.as_bytes().to_vec())
+            .with_field(info.username.as_bytes().to_vec())
+            .with_field(info.peer.as_bytes().to_vec())
+            .with_field(info.access_level.as_bytes().to_vec())
+            .with_field(info.connected.to_be_bytes().to_vec())
+            .with_field(info.idle.to_be_bytes().to_vec())
+            .with_field(info.heartbeat_age.to_be_bytes().to_vec())
+            .with_field(info.received.to_be_bytes().to_vec())
+            .with_field(info.sent.to_be_bytes().to_vec())
+            .with_field(info.received_age.to_be_bytes().to_vec())
+            .with_field(info.two_way_age.to_be_bytes().to_vec())
+            .with_field(info.integrity.as_bytes().to_vec())
+            .with_field(info.integrity_failures.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn session_info_entry(&self) -> Result<SessionInfo, String> {
+        let payload = self.payload();
+        Ok(SessionInfo {
+            id: payload.str_field(0)?.to_string(),
+            username: payload.str_field(1)?.to_string(),
+            peer: payload.str_field(2)?.to_string(),
+            access_level: payload.str_field(3)?.to_string(),
+            connected: payload.u64_field(4)?,
+            idle: payload.u64_field(5)?,
+            heartbeat_age: payload.u64_field(6)?,
+            received: payload.u64_field(7)?,
+            sent: payload.u64_field(8)?,
+            received_age: payload.u64_field(9).unwrap_or_default(),
+            two_way_age: payload.u64_field(10).unwrap_or_default(),
+            integrity: payload.str_field(11).unwrap_or(Integrity::Crc32.as_str()).to_string(),
+            integrity_failures: payload.u64_field(12).unwrap_or_default(),
+        })
+    }
+
+    // closes the SessionInfo entries of a list, kicked is false for listings and dry runs
+    pub fn session_list_end(count: u64, kicked: bool) -> Self {
+        MessageBuilder::new(MessageType::SessionListEnd)
+            .with_field(count.to_be_bytes().to_vec())
+            .with_field(u64::from(kicked).to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn admin_log_level_changed(previous: &str, current: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminLogLevelChanged)
+            .with_field(previous.as_bytes().to_vec())
+            .with_field(current.as_bytes().to_vec())
+            .build()
+    }
+
+    // the nonce is fresh for every connection, clients answer it with an AuthChallenge instead of sending the password
+    pub fn server_hello(capabilities: &Capabilities, nonce: &str, server_name: &str, server_id: &str) -> Self {
+        MessageBuilder::new(MessageType::ServerHello)
+            .with_field(capabilities.encode().into_bytes())
+            .with_field(nonce.as_bytes().to_vec())
+            .with_field(server_name.as_bytes().to_vec())
+            .with_field(server_id.as_bytes().to_vec())
+            .build()
+    }
+
+    // an empty text clears the message of the day
+    pub fn admin_set_motd(text: &str, broadcast: bool) -> Self {
+        MessageBuilder::new(MessageType::AdminSetMotd)
+            .with_field(text.as_bytes().to_vec())
+            .with_field(vec![broadcast as u8])
+            .build()
+    }
+
+    pub fn motd(text: &str) -> Self {
+        MessageBuilder::new(MessageType::Motd)
+            .with_field(text.as_bytes().to_vec())
+            .build()
+    }
+
     pub fn server_shutdown_warning(timeout: u64) -> Self {
         let mut payload = Payload::default();
         payload.add_field(timeout.to_be_bytes().to_vec());
@@ -298,6 +1615,22 @@ impl Message {
         }
     }
 
+    // clients that predate codes only read the text
+    pub fn message_error_with_code(code: ErrorCode, error: &str) -> Self {
+        MessageBuilder::new(MessageType::MessageError)
+            .with_field(error.as_bytes().to_vec())
+            .with_field(vec![code as u8])
+            .build()
+    }
+
+    // unknown codes from newer servers are treated like errors without one
+    pub fn error_code(&self) -> ErrorCode {
+        match self.payload().field(1) {
+            Ok([code]) => ErrorCode::try_from(*code).unwrap_or_default(),
+            _ => ErrorCode::Other,
+        }
+    }
+
     pub fn direct_message_send(receiver: &str, message: &str) -> Self {
         let mut payload = Payload::default();
         payload.add_field(receiver.as_bytes().to_vec());
@@ -311,10 +1644,42 @@ impl Message {
         }
     }
 
-    pub fn direct_message_receive(sender: &str, message: &str) -> Self {
+    // the message disappears `ttl` seconds after the server received it, 0 keeps it for good
+    pub fn direct_message_send_with_ttl(receiver: &str, message: &str, ttl: u64) -> Self {
+        MessageBuilder::new(MessageType::DirectMessageSend)
+            .with_field(receiver.as_bytes().to_vec())
+            .with_field(message.as_bytes().to_vec())
+            .with_field(ttl.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the client id lets the server answer a retry without relaying it twice, a ttl of 0 never expires
+    pub fn direct_message_send_with_id(receiver: &str, message: &str, ttl: u64, client_id: &str) -> Self {
+        MessageBuilder::new(MessageType::DirectMessageSend)
+            .with_field(receiver.as_bytes().to_vec())
+            .with_field(message.as_bytes().to_vec())
+            .with_field(ttl.to_be_bytes().to_vec())
+            .with_field(client_id.as_bytes().to_vec())
+            .build()
+    }
+
+    // sent_at is when the server received the message, not when it is delivered,
+    // ephemeral messages also carry the time they expire
+    pub fn direct_message_receive(
+        sender: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        id: MessageId,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
         let mut payload = Payload::default();
         payload.add_field(sender.as_bytes().to_vec());
         payload.add_field(message.as_bytes().to_vec());
+        payload.add_field(sent_at.to_rfc3339().into_bytes());
+        payload.add_field(id.to_bytes());
+        if let Some(expires_at) = expires_at {
+            payload.add_field(timestamp_bytes(expires_at));
+        }
         let checksum = payload.checksum();
 
         Message {
@@ -324,6 +1689,847 @@ impl Message {
         }
     }
 
+    // a message the user sent to themselves, relayed to their other sessions, the expiry field
+    // stays empty for notes that do not expire so the flags are always the sixth field
+    pub fn self_note(
+        owner: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        id: MessageId,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self::flagged_direct_message(owner, message, sent_at, id, expires_at, DIRECT_MESSAGE_SELF_NOTE)
+    }
+
+    // a relayed message from a sender the recipient muted
+    pub fn muted_direct_message(
+        sender: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        id: MessageId,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self::flagged_direct_message(sender, message, sent_at, id, expires_at, DIRECT_MESSAGE_MUTED)
+    }
+
+    fn flagged_direct_message(
+        sender: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        id: MessageId,
+        expires_at: Option<DateTime<Utc>>,
+        flags: u64,
+    ) -> Self {
+        MessageBuilder::new(MessageType::DirectMessageReceive)
+            .with_field(sender.as_bytes().to_vec())
+            .with_field(message.as_bytes().to_vec())
+            .with_field(sent_at.to_rfc3339().into_bytes())
+            .with_field(id.to_bytes())
+            .with_field(expires_at.map(timestamp_bytes).unwrap_or_default())
+            .with_field(flags.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the stored message a frame is about, None for frames that are not about one
+    pub fn message_id(&self) -> Option<MessageId> {
+        let index = match self.message_type() {
+            MessageType::DirectMessageReceive => 3,
+            MessageType::MessageEdit
+            | MessageType::MessageDelete
+            | MessageType::MessageEdited
+            | MessageType::MessageDeleted
+            | MessageType::HistoryEntry
+            | MessageType::SearchResult
+            | MessageType::MessageExpired => 0,
+            MessageType::Ack if self.payload().field_count() == 1 => 0,
+            _ => return None,
+        };
+        self.payload().message_id_field(index).ok()
+    }
+
+    // the sender's signature, relayed as it came, the fields before it are filled in where the message left them out
+    pub fn with_signature(self, signature: &[u8]) -> Self {
+        let Some(index) = self.signature_field() else {
+            return self;
+        };
+        let payload = self.payload();
+        let mut builder = MessageBuilder::new(self.message_type());
+        for field in 0..index {
+            let bytes = payload.field(field).map(<[u8]>::to_vec).unwrap_or_default();
+            builder = builder.with_field(bytes);
+        }
+        builder.with_field(signature.to_vec()).build()
+    }
+
+    // None for unsigned direct messages, their history entries and every other frame
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.payload()
+            .field(self.signature_field()?)
+            .ok()
+            .filter(|signature| !signature.is_empty())
+    }
+
+    fn signature_field(&self) -> Option<usize> {
+        match self.message_type() {
+            MessageType::DirectMessageSend => Some(SEND_SIGNATURE_FIELD),
+            MessageType::DirectMessageReceive => Some(RECEIVE_SIGNATURE_FIELD),
+            MessageType::HistoryEntry => Some(HISTORY_SIGNATURE_FIELD),
+            _ => None,
+        }
+    }
+
+    pub fn is_self_note(&self) -> bool {
+        self.has_direct_message_flag(DIRECT_MESSAGE_SELF_NOTE)
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.has_direct_message_flag(DIRECT_MESSAGE_MUTED)
+    }
+
+    fn has_direct_message_flag(&self, flag: u64) -> bool {
+        self.is(MessageType::DirectMessageReceive) && self.payload().u64_field(5).is_ok_and(|flags| flags & flag != 0)
+    }
+
+    // an ACK that tells the sender which id the server assigned to its direct message
+    pub fn message_ack(id: MessageId) -> Self {
+        MessageBuilder::new(MessageType::Ack).with_field(id.to_bytes()).build()
+    }
+
+    // names the client's own id as well, a sender that lost an ack in between still resolves the right message
+    pub fn message_ack_for(id: MessageId, client_id: &str) -> Self {
+        MessageBuilder::new(MessageType::Ack)
+            .with_field(id.to_bytes())
+            .with_field(client_id.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn message_edit(id: MessageId, body: &str) -> Self {
+        MessageBuilder::new(MessageType::MessageEdit)
+            .with_field(id.to_bytes())
+            .with_field(body.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn message_delete(id: MessageId) -> Self {
+        MessageBuilder::new(MessageType::MessageDelete)
+            .with_field(id.to_bytes())
+            .build()
+    }
+
+    pub fn message_edited(id: MessageId, sender: &str, body: &str, edited_at: DateTime<Utc>) -> Self {
+        MessageBuilder::new(MessageType::MessageEdited)
+            .with_field(id.to_bytes())
+            .with_field(sender.as_bytes().to_vec())
+            .with_field(body.as_bytes().to_vec())
+            .with_field(timestamp_bytes(edited_at))
+            .build()
+    }
+
+    pub fn message_deleted(id: MessageId, sender: &str) -> Self {
+        MessageBuilder::new(MessageType::MessageDeleted)
+            .with_field(id.to_bytes())
+            .with_field(sender.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn history_request(peer: &str, limit: u64) -> Self {
+        MessageBuilder::new(MessageType::HistoryRequest)
+            .with_field(peer.as_bytes().to_vec())
+            .with_field(limit.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the page of messages older than `before`, pass the oldest id of the previous page to walk back
+    pub fn history_request_before(peer: &str, limit: u64, before: MessageId) -> Self {
+        MessageBuilder::new(MessageType::HistoryRequest)
+            .with_field(peer.as_bytes().to_vec())
+            .with_field(limit.to_be_bytes().to_vec())
+            .with_field(before.to_bytes())
+            .build()
+    }
+
+    // flags is a combination of the HISTORY_* bits, deleted entries carry an empty body
+    pub fn history_entry(
+        id: MessageId,
+        sender: &str,
+        recipient: &str,
+        body: &str,
+        sent_at: DateTime<Utc>,
+        flags: u64,
+    ) -> Self {
+        MessageBuilder::new(MessageType::HistoryEntry)
+            .with_field(id.to_bytes())
+            .with_field(sender.as_bytes().to_vec())
+            .with_field(recipient.as_bytes().to_vec())
+            .with_field(body.as_bytes().to_vec())
+            .with_field(timestamp_bytes(sent_at))
+            .with_field(flags.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn history_end(count: u64) -> Self {
+        MessageBuilder::new(MessageType::HistoryEnd)
+            .with_field(count.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn mark_conversation_read(peer: &str) -> Self {
+        MessageBuilder::new(MessageType::MarkConversationRead)
+            .with_field(peer.as_bytes().to_vec())
+            .build()
+    }
+
+    // one peer and count pair of fields per conversation with unread messages
+    pub fn unread_summary(counts: &[(String, u64)]) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::UnreadSummary);
+        for (peer, count) in counts {
+            builder = builder
+                .with_field(peer.as_bytes().to_vec())
+                .with_field(count.to_be_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn unread_counts(&self) -> Result<Vec<(String, u64)>, String> {
+        let payload = self.payload();
+        (0..payload.field_count())
+            .step_by(2)
+            .map(|index| Ok((payload.str_field(index)?.to_string(), payload.u64_field(index + 1)?)))
+            .collect()
+    }
+
+    // an empty peer searches every conversation of the requesting user
+    pub fn search_request(query: &str, peer: Option<&str>, limit: u64) -> Self {
+        MessageBuilder::new(MessageType::SearchRequest)
+            .with_field(query.as_bytes().to_vec())
+            .with_field(peer.unwrap_or_default().as_bytes().to_vec())
+            .with_field(limit.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the match is the byte range from start to end within the snippet
+    pub fn search_result(
+        id: MessageId,
+        peer: &str,
+        sent_at: DateTime<Utc>,
+        snippet: &str,
+        match_start: u64,
+        match_end: u64,
+    ) -> Self {
+        MessageBuilder::new(MessageType::SearchResult)
+            .with_field(id.to_bytes())
+            .with_field(peer.as_bytes().to_vec())
+            .with_field(timestamp_bytes(sent_at))
+            .with_field(snippet.as_bytes().to_vec())
+            .with_field(match_start.to_be_bytes().to_vec())
+            .with_field(match_end.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // a queued message nobody picked up before it ran out, sent to its author
+    pub fn message_expired(id: MessageId, recipient: &str) -> Self {
+        MessageBuilder::new(MessageType::MessageExpired)
+            .with_field(id.to_bytes())
+            .with_field(recipient.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn search_end(count: u64) -> Self {
+        MessageBuilder::new(MessageType::SearchEnd)
+            .with_field(count.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // a refused search still ends, the reason follows the empty count
+    pub fn search_refused(reason: &str) -> Self {
+        MessageBuilder::new(MessageType::SearchEnd)
+            .with_field(0u64.to_be_bytes().to_vec())
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    // sha256 is the hex digest of the whole file, the recipient checks it once the last chunk arrived
+    pub fn file_offer(recipient: &str, filename: &str, size: u64, sha256: &str) -> Self {
+        MessageBuilder::new(MessageType::FileOffer)
+            .with_field(recipient.as_bytes().to_vec())
+            .with_field(filename.as_bytes().to_vec())
+            .with_field(size.to_be_bytes().to_vec())
+            .with_field(sha256.as_bytes().to_vec())
+            .build()
+    }
+
+    // the offer as the recipient sees it, with the sender in place of the recipient
+    pub fn file_offer_relay(sender: &str, filename: &str, size: u64, sha256: &str, transfer_id: u64) -> Self {
+        MessageBuilder::new(MessageType::FileOffer)
+            .with_field(sender.as_bytes().to_vec())
+            .with_field(filename.as_bytes().to_vec())
+            .with_field(size.to_be_bytes().to_vec())
+            .with_field(sha256.as_bytes().to_vec())
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn file_offered(transfer_id: u64, recipient: &str, filename: &str) -> Self {
+        MessageBuilder::new(MessageType::FileOffered)
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .with_field(recipient.as_bytes().to_vec())
+            .with_field(filename.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn file_accept(transfer_id: u64) -> Self {
+        MessageBuilder::new(MessageType::FileAccept)
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // also cancels a transfer that is already running
+    pub fn file_reject(transfer_id: u64, reason: &str) -> Self {
+        MessageBuilder::new(MessageType::FileReject)
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .with_field(reason.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn file_chunk(transfer_id: u64, index: u64, bytes: &[u8]) -> Self {
+        MessageBuilder::new(MessageType::FileChunk)
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .with_field(index.to_be_bytes().to_vec())
+            .with_field(bytes.to_vec())
+            .build()
+    }
+
+    pub fn file_complete(transfer_id: u64) -> Self {
+        MessageBuilder::new(MessageType::FileComplete)
+            .with_field(transfer_id.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn set_preference(key: &str, value: &str) -> Self {
+        Self::set_preferences(&[(key.to_string(), value.to_string())])
+    }
+
+    // one key and value pair of fields per setting, the server applies all of them or none
+    pub fn set_preferences(updates: &[(String, String)]) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::SetPreference);
+        for (key, value) in updates {
+            builder = builder
+                .with_field(key.as_bytes().to_vec())
+                .with_field(value.as_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn preference_updates(&self) -> Result<Vec<(String, String)>, String> {
+        let payload = self.payload();
+        if payload.field_count() == 0 || payload.field_count() % 2 != 0 {
+            return Err("Preference updates are not key and value pairs".to_string());
+        }
+        self.preference_pairs(payload.field_count())
+    }
+
+    pub fn get_preferences() -> Self {
+        MessageBuilder::new(MessageType::GetPreferences).build()
+    }
+
+    // one key and value pair of fields per setting, then the version the set has on the server
+    pub fn preferences(entries: &[(String, String)], version: u64) -> Self {
+        Self::preference_set(MessageType::Preferences, entries, version)
+    }
+
+    // sent to the other sessions of the user, so every device ends up with the same set
+    pub fn preferences_changed(entries: &[(String, String)], version: u64) -> Self {
+        Self::preference_set(MessageType::PreferencesChanged, entries, version)
+    }
+
+    fn preference_set(message_type: MessageType, entries: &[(String, String)], version: u64) -> Self {
+        let mut builder = MessageBuilder::new(message_type);
+        for (key, value) in entries {
+            builder = builder
+                .with_field(key.as_bytes().to_vec())
+                .with_field(value.as_bytes().to_vec());
+        }
+        builder.with_field(version.to_be_bytes().to_vec()).build()
+    }
+
+    // the version field is left out, servers from before do not send one
+    pub fn preference_entries(&self) -> Result<Vec<(String, String)>, String> {
+        let count = self.payload().field_count();
+        self.preference_pairs(count - count % 2)
+    }
+
+    pub fn preferences_version(&self) -> Option<u64> {
+        let payload = self.payload();
+        match payload.field_count() % 2 {
+            1 => payload.u64_field(payload.field_count() - 1).ok(),
+            _ => None,
+        }
+    }
+
+    fn preference_pairs(&self, count: usize) -> Result<Vec<(String, String)>, String> {
+        let payload = self.payload();
+        (0..count)
+            .step_by(2)
+            .map(|index| {
+                Ok((
+                    payload.str_field(index)?.to_string(),
+                    payload.str_field(index + 1)?.to_string(),
+                ))
+            })
+            .collect()
+    }
+
+    // who came online and who went offline since the last update, each list joined by newlines
+    pub fn presence_update(online: &[String], offline: &[String]) -> Self {
+        MessageBuilder::new(MessageType::PresenceUpdate)
+            .with_field(online.join("\n").into_bytes())
+            .with_field(offline.join("\n").into_bytes())
+            .build()
+    }
+
+    // a subscription to a scope that is already followed asks for a new snapshot
+    pub fn subscribe_presence(scope: &PresenceScope) -> Self {
+        MessageBuilder::new(MessageType::SubscribePresence)
+            .with_field(scope.encode().into_bytes())
+            .build()
+    }
+
+    pub fn unsubscribe_presence(scope: &PresenceScope) -> Self {
+        MessageBuilder::new(MessageType::UnsubscribePresence)
+            .with_field(scope.encode().into_bytes())
+            .build()
+    }
+
+    pub fn presence_scope(&self) -> Result<PresenceScope, String> {
+        PresenceScope::parse(self.payload().str_field(0)?)
+    }
+
+    // one `name status` line per user, the lines spill into another field before one gets too large
+    pub fn presence_snapshot(snapshot: &PresenceSnapshot) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::PresenceSnapshot)
+            .with_field(snapshot.scope.encode().into_bytes())
+            .with_field(snapshot.version.to_be_bytes().to_vec());
+        let mut field = String::new();
+        for entry in &snapshot.entries {
+            let line = format!("{} {}", entry.user, entry.status.as_str());
+            if !field.is_empty() && field.len() + 1 + line.len() > MAX_FIELD_SIZE as usize {
+                builder = builder.with_field(std::mem::take(&mut field).into_bytes());
+            }
+            if !field.is_empty() {
+                field.push('\n');
+            }
+            field.push_str(&line);
+        }
+        if !field.is_empty() {
+            builder = builder.with_field(field.into_bytes());
+        }
+        builder.build()
+    }
+
+    pub fn roster_snapshot(&self) -> Result<PresenceSnapshot, String> {
+        let payload = self.payload();
+        let mut entries = Vec::new();
+        for index in 2..payload.field_count() {
+            for line in payload.str_field(index)?.split('\n').filter(|line| !line.is_empty()) {
+                let (user, status) = line
+                    .split_once(' ')
+                    .ok_or_else(|| format!("Invalid presence entry '{}'", line))?;
+                entries.push(PresenceEntry {
+                    user: user.to_string(),
+                    status: PresenceStatus::parse(status)?,
+                });
+            }
+        }
+        Ok(PresenceSnapshot {
+            scope: PresenceScope::parse(payload.str_field(0)?)?,
+            version: payload.u64_field(1)?,
+            entries,
+        })
+    }
+
+    pub fn presence_delta(delta: &PresenceDelta) -> Self {
+        MessageBuilder::new(MessageType::PresenceDelta)
+            .with_field(delta.scope.encode().into_bytes())
+            .with_field(delta.version.to_be_bytes().to_vec())
+            .with_field(delta.change.as_str().as_bytes().to_vec())
+            .with_field(delta.entry.user.as_bytes().to_vec())
+            .with_field(delta.entry.status.as_str().as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn roster_delta(&self) -> Result<PresenceDelta, String> {
+        let payload = self.payload();
+        Ok(PresenceDelta {
+            scope: PresenceScope::parse(payload.str_field(0)?)?,
+            version: payload.u64_field(1)?,
+            change: PresenceChange::parse(payload.str_field(2)?)?,
+            entry: PresenceEntry {
+                user: payload.str_field(3)?.to_string(),
+                status: PresenceStatus::parse(payload.str_field(4)?)?,
+            },
+        })
+    }
+
+    pub fn admin_list_ip_bans() -> Self {
+        MessageBuilder::new(MessageType::AdminListIpBans).build()
+    }
+
+    pub fn admin_clear_ip_ban(address: &str) -> Self {
+        MessageBuilder::new(MessageType::AdminClearIpBan)
+            .with_field(address.as_bytes().to_vec())
+            .build()
+    }
+
+    // one `address seconds` line per ban
+    pub fn ip_ban_list(list: &IpBanList) -> Self {
+        let bans: Vec<String> = list
+            .bans
+            .iter()
+            .map(|(address, remaining)| format!("{} {}", address, remaining))
+            .collect();
+        MessageBuilder::new(MessageType::IpBanList)
+            .with_field(list.issued.to_be_bytes().to_vec())
+            .with_field(list.turned_away.to_be_bytes().to_vec())
+            .with_field(bans.join("\n").into_bytes())
+            .build()
+    }
+
+    pub fn ip_bans(&self) -> Result<IpBanList, String> {
+        let payload = self.payload();
+        let bans = payload
+            .str_field(2)?
+            .split('\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (address, remaining) = line
+                    .split_once(' ')
+                    .ok_or_else(|| format!("Invalid ban entry '{}'", line))?;
+                let remaining = remaining.parse().map_err(|_| format!("Invalid ban entry '{}'", line))?;
+                Ok((address.to_string(), remaining))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(IpBanList {
+            bans,
+            issued: payload.u64_field(0)?,
+            turned_away: payload.u64_field(1)?,
+        })
+    }
+
+    pub fn admin_consistency_check(repair: bool) -> Self {
+        MessageBuilder::new(MessageType::AdminConsistencyCheck)
+            .with_field(u64::from(repair).to_be_bytes().to_vec())
+            .build()
+    }
+
+    // one `invariant<TAB>repaired<TAB>detail` line per violation
+    pub fn consistency_report(report: &ConsistencyReport) -> Self {
+        let violations: Vec<String> = report
+            .violations
+            .iter()
+            .map(|violation| {
+                format!(
+                    "{}\t{}\t{}",
+                    violation.invariant,
+                    u8::from(violation.repaired),
+                    violation.detail.replace(['\t', '\n'], " ")
+                )
+            })
+            .collect();
+        MessageBuilder::new(MessageType::ConsistencyReport)
+            .with_field(report.users.to_be_bytes().to_vec())
+            .with_field(report.sessions.to_be_bytes().to_vec())
+            .with_field(violations.join("\n").into_bytes())
+            .build()
+    }
+
+    pub fn consistency_result(&self) -> Result<ConsistencyReport, String> {
+        let payload = self.payload();
+        let violations = payload
+            .str_field(2)?
+            .split('\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut parts = line.splitn(3, '\t');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(invariant), Some(repaired @ ("0" | "1")), Some(detail)) => Ok(ConsistencyViolation {
+                        invariant: invariant.to_string(),
+                        detail: detail.to_string(),
+                        repaired: repaired == "1",
+                    }),
+                    _ => Err(format!("Invalid violation entry '{}'", line)),
+                }
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(ConsistencyReport {
+            users: payload.u64_field(0)?,
+            sessions: payload.u64_field(1)?,
+            violations,
+        })
+    }
+
+    // sent ahead of the login to servers with the offline_sync capability, the queue then waits to be pulled
+    pub fn sync_select() -> Self {
+        MessageBuilder::new(MessageType::SyncSelect).build()
+    }
+
+    // the cursor and total, then a sender and count pair per sender
+    pub fn offline_summary(summary: &OfflineSummary) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::OfflineSummary)
+            .with_field(summary.cursor.to_be_bytes().to_vec())
+            .with_field(summary.total.to_be_bytes().to_vec());
+        for (peer, count) in &summary.peers {
+            builder = builder
+                .with_field(peer.as_bytes().to_vec())
+                .with_field(count.to_be_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn offline_counts(&self) -> Result<OfflineSummary, String> {
+        let payload = self.payload();
+        let peers = (2..payload.field_count())
+            .step_by(2)
+            .map(|index| Ok((payload.str_field(index)?.to_string(), payload.u64_field(index + 1)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(OfflineSummary {
+            cursor: payload.u64_field(0)?,
+            total: payload.u64_field(1)?,
+            peers,
+        })
+    }
+
+    // acknowledges everything up to the cursor and asks for at most `limit` of what follows, 0 only acknowledges
+    pub fn sync_request(cursor: u64, limit: u64) -> Self {
+        MessageBuilder::new(MessageType::SyncRequest)
+            .with_field(cursor.to_be_bytes().to_vec())
+            .with_field(limit.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // the remaining count, then a sequence per message, an empty batch with nothing remaining ends the sync
+    pub fn sync_batch(batch: &SyncBatch) -> Self {
+        let mut builder =
+            MessageBuilder::new(MessageType::SyncBatch).with_field(batch.remaining.to_be_bytes().to_vec());
+        for sequence in &batch.sequences {
+            builder = builder.with_field(sequence.to_be_bytes().to_vec());
+        }
+        builder.build()
+    }
+
+    pub fn sync_sequences(&self) -> Result<SyncBatch, String> {
+        let payload = self.payload();
+        Ok(SyncBatch {
+            sequences: (1..payload.field_count())
+                .map(|index| payload.u64_field(index))
+                .collect::<Result<_, _>>()?,
+            remaining: payload.u64_field(0)?,
+        })
+    }
+
+    pub fn mute_add(username: &str) -> Self {
+        MessageBuilder::new(MessageType::MuteAdd)
+            .with_field(username.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn mute_remove(username: &str) -> Self {
+        MessageBuilder::new(MessageType::MuteRemove)
+            .with_field(username.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn mute_list() -> Self {
+        MessageBuilder::new(MessageType::MuteList).build()
+    }
+
+    // newline separated, the answer to every mute request
+    pub fn muted_senders(usernames: &[String]) -> Self {
+        MessageBuilder::new(MessageType::MutedSenders)
+            .with_field(usernames.join("\n").into_bytes())
+            .build()
+    }
+
+    pub fn muted_sender_names(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .payload()
+            .str_field(0)?
+            .split('\n')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn schedule_message_send(recipient: &str, body: &str, due: ScheduleTime) -> Self {
+        MessageBuilder::new(MessageType::ScheduleMessageSend)
+            .with_field(recipient.as_bytes().to_vec())
+            .with_field(body.as_bytes().to_vec())
+            .with_field(due.encode().into_bytes())
+            .build()
+    }
+
+    pub fn schedule_list() -> Self {
+        MessageBuilder::new(MessageType::ScheduleList).build()
+    }
+
+    pub fn schedule_cancel(id: u64) -> Self {
+        MessageBuilder::new(MessageType::ScheduleCancel)
+            .with_field(id.to_be_bytes().to_vec())
+            .build()
+    }
+
+    // four fields per entry, bodies may hold any separator; the answer to every schedule request
+    pub fn scheduled_messages(entries: &[ScheduledEntry]) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::ScheduledMessages);
+        for entry in entries {
+            builder = builder
+                .with_field(entry.id.to_be_bytes().to_vec())
+                .with_field(entry.recipient.as_bytes().to_vec())
+                .with_field(entry.body.as_bytes().to_vec())
+                .with_field(timestamp_bytes(entry.due));
+        }
+        builder.build()
+    }
+
+    pub fn scheduled_entries(&self) -> Result<Vec<ScheduledEntry>, String> {
+        let payload = self.payload();
+        if payload.field_count() % 4 != 0 {
+            return Err(format!("{} fields do not make whole entries", payload.field_count()));
+        }
+        (0..payload.field_count() / 4)
+            .map(|entry| {
+                let index = entry * 4;
+                Ok(ScheduledEntry {
+                    id: payload.u64_field(index)?,
+                    recipient: payload.str_field(index + 1)?.to_string(),
+                    body: payload.str_field(index + 2)?.to_string(),
+                    due: payload.timestamp_field(index + 3)?,
+                })
+            })
+            .collect()
+    }
+
+    // sent to the sender's sessions when the message went out, with the reason when it could not
+    pub fn schedule_fired(id: u64, recipient: &str, error: Option<&str>) -> Self {
+        MessageBuilder::new(MessageType::ScheduleFired)
+            .with_field(id.to_be_bytes().to_vec())
+            .with_field(recipient.as_bytes().to_vec())
+            .with_field(error.unwrap_or_default().as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn schedule_outcome(&self) -> Result<(u64, String, Option<String>), String> {
+        let payload = self.payload();
+        let error = match payload.str_field(2)? {
+            "" => None,
+            error => Some(error.to_string()),
+        };
+        Ok((payload.u64_field(0)?, payload.str_field(1)?.to_string(), error))
+    }
+
+    pub fn api_token_create(label: &str) -> Self {
+        MessageBuilder::new(MessageType::ApiTokenCreate)
+            .with_field(label.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn api_token_revoke(id: u64) -> Self {
+        MessageBuilder::new(MessageType::ApiTokenRevoke)
+            .with_field(id.to_be_bytes().to_vec())
+            .build()
+    }
+
+    pub fn api_token_list() -> Self {
+        MessageBuilder::new(MessageType::ApiTokenList).build()
+    }
+
+    // three fields per token, the answer to a list or a revocation
+    pub fn api_tokens(tokens: &[ApiTokenInfo]) -> Self {
+        let mut builder = MessageBuilder::new(MessageType::ApiTokens);
+        for token in tokens {
+            builder = builder
+                .with_field(token.id.to_be_bytes().to_vec())
+                .with_field(token.label.as_bytes().to_vec())
+                .with_field(timestamp_bytes(token.created));
+        }
+        builder.build()
+    }
+
+    pub fn api_token_entries(&self) -> Result<Vec<ApiTokenInfo>, String> {
+        let payload = self.payload();
+        if payload.field_count() % 3 != 0 {
+            return Err(format!("{} fields do not make whole entries", payload.field_count()));
+        }
+        (0..payload.field_count() / 3)
+            .map(|entry| {
+                let index = entry * 3;
+                Ok(ApiTokenInfo {
+                    id: payload.u64_field(index)?,
+                    label: payload.str_field(index + 1)?.to_string(),
+                    created: payload.timestamp_field(index + 2)?,
+                })
+            })
+            .collect()
+    }
+
+    // the only time the server hands out the token, it keeps no more than a hash
+    pub fn api_token_created(info: &ApiTokenInfo, token: &str) -> Self {
+        MessageBuilder::new(MessageType::ApiTokenCreated)
+            .with_field(info.id.to_be_bytes().to_vec())
+            .with_field(info.label.as_bytes().to_vec())
+            .with_field(timestamp_bytes(info.created))
+            .with_field(token.as_bytes().to_vec())
+            .build()
+    }
+
+    pub fn created_api_token(&self) -> Result<(ApiTokenInfo, String), String> {
+        let payload = self.payload();
+        let info = ApiTokenInfo {
+            id: payload.u64_field(0)?,
+            label: payload.str_field(1)?.to_string(),
+            created: payload.timestamp_field(2)?,
+        };
+        Ok((info, payload.str_field(3)?.to_string()))
+    }
+
+    // None takes the key off the account, messages from then on cannot be verified
+    pub fn signing_key_set(key: Option<&[u8; SIGNING_KEY_LENGTH]>) -> Self {
+        MessageBuilder::new(MessageType::SigningKeySet)
+            .with_field(key.map(|key| key.to_vec()).unwrap_or_default())
+            .build()
+    }
+
+    pub fn signing_key_request(username: &str) -> Self {
+        MessageBuilder::new(MessageType::SigningKeyRequest)
+            .with_field(username.as_bytes().to_vec())
+            .build()
+    }
+
+    // the key a user registered, the field is empty for users without one and for users that do not exist
+    pub fn signing_key(username: &str, key: Option<&[u8; SIGNING_KEY_LENGTH]>) -> Self {
+        MessageBuilder::new(MessageType::SigningKey)
+            .with_field(username.as_bytes().to_vec())
+            .with_field(key.map(|key| key.to_vec()).unwrap_or_default())
+            .build()
+    }
+
+    pub fn signing_key_of(&self) -> Result<(String, Option<[u8; SIGNING_KEY_LENGTH]>), String> {
+        let payload = self.payload();
+        Ok((payload.str_field(0)?.to_string(), payload.signing_key_field(1)?))
+    }
+
+    pub fn presence_changes(&self) -> Result<(Vec<String>, Vec<String>), String> {
+        let payload = self.payload();
+        let names = |index| -> Result<Vec<String>, String> {
+            Ok(payload
+                .str_field(index)?
+                .split('\n')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect())
+        };
+        Ok((names(0)?, names(1)?))
+    }
+
     pub fn is(&self, message_type: MessageType) -> bool {
         self.header.message_type == message_type
     }
@@ -336,11 +2542,40 @@ impl Message {
         &self.payload
     }
 
-    pub async fn send(&self, stream: &mut OwnedWriteHalf) -> Result<(), String> {
-        let mut buf: Vec<u8> = Vec::new();
+    pub fn version(&self) -> u8 {
+        self.header.version
+    }
+
+    pub(crate) fn with_version(mut self, version: u8) -> Self {
+        self.header.version = version;
+        self
+    }
+
+    // the same frame with one field swapped, for rewriting messages that are already queued
+    pub fn with_field_replaced(&self, index: usize, field_data: Vec<u8>) -> Self {
+        let mut message = self.clone();
+        if let Some(field) = message.payload.fields.get_mut(index) {
+            *field = PayloadField::new(field_data);
+        }
+        message.checksum = message.payload.checksum();
+        message
+    }
+
+    pub fn wire_size(&self) -> usize {
+        let fields: usize = self.payload.fields.iter().map(|field| 4 + field.field_data.len()).sum();
+        // header start, version, type, field count, fields, checksum
+        2 + 1 + 1 + 4 + fields + 4
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_using(Integrity::Crc32)
+    }
+
+    pub fn to_bytes_using(&self, integrity: Integrity) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::with_capacity(self.wire_size() - 4 + integrity.trailer_len());
         buf.extend_from_slice(&HEADER_START.to_be_bytes());
         buf.push(self.header.version);
-        buf.push(self.header.message_type.clone() as u8);
+        buf.push(self.header.message_type as u8);
         buf.extend_from_slice(&self.payload.count.to_be_bytes());
 
         for field in &self.payload.fields {
@@ -348,59 +2583,237 @@ impl Message {
             buf.extend_from_slice(&field.field_data);
         }
 
-        buf.extend_from_slice(&self.checksum.to_be_bytes());
+        match integrity {
+            // cached, most frames are built once and written once
+            Integrity::Crc32 => buf.extend_from_slice(&self.checksum.to_be_bytes()),
+            _ => buf.extend(integrity.trailer(self.payload.fields.iter().map(|field| field.field_data.as_slice()))),
+        }
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FrameError> {
+        Self::from_bytes_with(bytes, VersionRange::default())
+    }
+
+    pub fn from_bytes_with(bytes: &[u8], versions: VersionRange) -> Result<Self, FrameError> {
+        Self::from_bytes_using(bytes, versions, Integrity::Crc32)
+    }
+
+    pub fn from_bytes_using(bytes: &[u8], versions: VersionRange, integrity: Integrity) -> Result<Self, FrameError> {
+        let mut decoder = Decoder { bytes };
+
+        if u16::from_be_bytes(decoder.take_array()?) != HEADER_START {
+            return Err("Invalid header start".to_string().into());
+        }
+        let version = decoder.take_array::<1>()?[0];
+        if !versions.supports(version) {
+            return Err(FrameError::UnsupportedVersion {
+                version,
+                supported: versions,
+            });
+        }
+
+        let message_type = decoder.take_array::<1>()?[0];
+        let payload_count = u32::from_be_bytes(decoder.take_array()?);
+        Self::check_field_count(payload_count)?;
+
+        let mut fields = Vec::with_capacity(payload_count as usize);
+        for _ in 0..payload_count {
+            let field_length = u32::from_be_bytes(decoder.take_array()?);
+            Self::check_field_size(field_length)?;
+            fields.push(decoder.take(field_length as usize)?.to_vec());
+        }
 
-        error_string!(stream.write_all(&buf).await);
+        let trailer = decoder.take(integrity.trailer_len())?.to_vec();
+        if !decoder.bytes.is_empty() {
+            return Err("Trailing bytes after message".to_string().into());
+        }
+
+        versions.decode(RawFrame {
+            version,
+            message_type,
+            fields,
+            integrity,
+            trailer,
+        })
+    }
 
+    fn check_field_count(count: u32) -> Result<(), String> {
+        if count > MAX_FIELD_COUNT {
+            return Err(format!("Too many payload fields ({} > {})", count, MAX_FIELD_COUNT));
+        }
+        Ok(())
+    }
+
+    fn check_field_size(size: u32) -> Result<(), String> {
+        if size > MAX_FIELD_SIZE {
+            return Err(format!("Payload field too large ({} > {} bytes)", size, MAX_FIELD_SIZE));
+        }
         Ok(())
     }
 
-    pub async fn receive(stream: &mut OwnedReadHalf) -> Result<Self, String> {
+    pub async fn send<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> Result<(), String> {
+        self.send_using(stream, Integrity::Crc32).await
+    }
+
+    pub async fn send_using<W: AsyncWrite + Unpin>(&self, stream: &mut W, integrity: Integrity) -> Result<(), String> {
+        error_string!(stream.write_all(&self.to_bytes_using(integrity)).await);
+
+        Ok(())
+    }
+
+    pub async fn receive<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Self, FrameError> {
+        Self::receive_with(stream, VersionRange::default()).await
+    }
+
+    // an unsupported version is refused before the rest of the frame is read, its layout is unknown
+    pub async fn receive_with<R: AsyncRead + Unpin>(
+        stream: &mut R,
+        versions: VersionRange,
+    ) -> Result<Self, FrameError> {
+        Self::receive_using(stream, versions, Integrity::Crc32).await
+    }
+
+    pub async fn receive_using<R: AsyncRead + Unpin>(
+        stream: &mut R,
+        versions: VersionRange,
+        integrity: Integrity,
+    ) -> Result<Self, FrameError> {
         let mut buf = [0u8; 1];
-        error_string!(stream.read_exact(&mut buf).await);
+        read_or_closed!(stream.read_exact(&mut buf).await);
         let version = buf[0];
-        if version != VERSION {
-            return Err("Invalid version".into());
+        if !versions.supports(version) {
+            return Err(FrameError::UnsupportedVersion {
+                version,
+                supported: versions,
+            });
         }
 
         let mut buf = [0u8; 1];
-        error_string!(stream.read_exact(&mut buf).await);
-        let message_type = MessageType::from(buf[0]);
-
-        let mut builder = MessageBuilder::new(message_type);
+        read_or_closed!(stream.read_exact(&mut buf).await);
+        let message_type = buf[0];
 
         let mut buf = [0u8; 4];
-        error_string!(stream.read_exact(&mut buf).await);
+        read_or_closed!(stream.read_exact(&mut buf).await);
         let payload_count = u32::from_be_bytes(buf);
+        Self::check_field_count(payload_count)?;
 
+        let mut fields = Vec::with_capacity(payload_count as usize);
         for _ in 0..payload_count {
             let mut buf = [0u8; 4];
-            error_string!(stream.read_exact(&mut buf).await);
+            read_or_closed!(stream.read_exact(&mut buf).await);
             let field_length = u32::from_be_bytes(buf);
+            Self::check_field_size(field_length)?;
 
             let mut field_data = vec![0u8; field_length as usize];
-            error_string!(stream.read_exact(&mut field_data).await);
+            read_or_closed!(stream.read_exact(&mut field_data).await);
 
-            builder = builder.with_field(field_data);
+            fields.push(field_data);
         }
 
-        let mut buf = [0u8; 4];
-        error_string!(stream.read_exact(&mut buf).await);
-        let checksum = u32::from_be_bytes(buf);
+        let mut trailer = vec![0u8; integrity.trailer_len()];
+        read_or_closed!(stream.read_exact(&mut trailer).await);
 
-        if checksum != builder.payload.checksum() {
-            return Err("Invalid checksum".into());
-        }
+        versions.decode(RawFrame {
+            version,
+            message_type,
+            fields,
+            integrity,
+            trailer,
+        })
+    }
 
-        Ok(builder.build())
+    pub async fn has_header_start<R: AsyncRead + Unpin>(stream: &mut R) -> bool {
+        Self::read_header_start(stream).await.unwrap_or(false)
     }
 
-    pub async fn has_header_start(stream: &mut OwnedReadHalf) -> bool {
+    pub async fn read_header_start<R: AsyncRead + Unpin>(stream: &mut R) -> std::io::Result<bool> {
         let mut buffer = [0u8; 2];
-        match stream.read_exact(&mut buffer).await {
-            Ok(_) => u16::from_be_bytes(buffer) == HEADER_START,
-            Err(_) => false,
+        stream.read_exact(&mut buffer).await?;
+        Ok(u16::from_be_bytes(buffer) == HEADER_START)
+    }
+
+    // like read_header_start, but a stream that ends before the first byte is a peer that left,
+    // one that ends after it broke inside a frame
+    pub async fn read_frame_start<R: AsyncRead + Unpin>(stream: &mut R) -> Result<bool, FrameError> {
+        let mut buffer = [0u8; 2];
+        match stream.read(&mut buffer[..1]).await {
+            Ok(0) => return Err(FrameError::PeerClosed),
+            Ok(_) => {}
+            Err(e) => return Err(FrameError::Closed(e.to_string())),
+        }
+        read_or_closed!(stream.read_exact(&mut buffer[1..]).await);
+        Ok(u16::from_be_bytes(buffer) == HEADER_START)
+    }
+
+    // reads byte by byte up to the next header start and consumes it, false once the limit is used up
+    pub async fn resync<R: AsyncRead + Unpin>(stream: &mut R, limit: usize) -> std::io::Result<bool> {
+        let [first, second] = HEADER_START.to_be_bytes();
+        let mut previous = None;
+        for _ in 0..limit {
+            let byte = stream.read_u8().await?;
+            if previous == Some(first) && byte == second {
+                return Ok(true);
+            }
+            previous = Some(byte);
         }
+        Ok(false)
+    }
+}
+
+impl MessageId {
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+}
+
+impl std::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl SessionFilter {
+    pub fn matches(&self, guest: bool, idle_secs: u64, peer: &str) -> bool {
+        (!self.guests_only || guest)
+            && idle_secs >= self.idle_minutes.saturating_mul(60)
+            && peer.starts_with(&self.ip_prefix)
+    }
+
+    fn fields(&self, builder: MessageBuilder) -> MessageBuilder {
+        builder
+            .with_field(u64::from(self.guests_only).to_be_bytes().to_vec())
+            .with_field(self.idle_minutes.to_be_bytes().to_vec())
+            .with_field(self.ip_prefix.as_bytes().to_vec())
+    }
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        if self.bytes.len() < length {
+            return Err("Unexpected end of message".into());
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
     }
 }
 
@@ -435,3 +2848,7 @@ impl MessageBuilder {
         }
     }
 }
+
+fn timestamp_bytes(timestamp: DateTime<Utc>) -> Vec<u8> {
+    (timestamp.timestamp_micros().max(0) as u64).to_be_bytes().to_vec()
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+#[cfg(feature = "test-util")]
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::time::{Instant, Interval, Sleep};
+
+// timers go through tokio, so `tokio::time::pause` and `advance` drive them in tests
+pub trait Clock: fmt::Debug + Send + Sync {
+    // the wall clock, for timestamps and anything compared against them
+    fn now(&self) -> DateTime<Utc>;
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: std::time::Duration) -> Sleep {
+        tokio::time::sleep(duration)
+    }
+
+    fn interval(&self, period: std::time::Duration) -> Interval {
+        tokio::time::interval(period)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+// the wall clock moves with tokio's clock, a paused runtime stops both, and it can be set to any time
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+pub struct ManualClock {
+    // the wall clock time at the instant next to it
+    anchor: Mutex<(DateTime<Utc>, Instant)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSample {
+    offset: Duration,
+    round_trip: Duration,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            anchor: Mutex::new((now, Instant::now())),
+        }
+    }
+
+    // jumps the wall clock, timers keep their deadlines
+    pub fn set_now(&self, now: DateTime<Utc>) {
+        *self.anchor.lock().unwrap() = (now, Instant::now());
+    }
+
+    // only works on a paused runtime, timers due in between fire as they would have
+    pub async fn advance(&self, duration: std::time::Duration) {
+        tokio::time::advance(duration).await;
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        let (wall, instant) = *self.anchor.lock().unwrap();
+        Duration::from_std(Instant::now() - instant)
+            .ok()
+            .and_then(|elapsed| wall.checked_add_signed(elapsed))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl TimeSample {
+    // NTP style: the offset assumes both directions of the round trip take equally long
+    pub fn from_exchange(
+        client_sent: DateTime<Utc>,
+        server_received: DateTime<Utc>,
+        server_sent: DateTime<Utc>,
+        client_received: DateTime<Utc>,
+    ) -> Self {
+        let offset = ((server_received - client_sent) + (server_sent - client_received)) / 2;
+        let round_trip = (client_received - client_sent) - (server_sent - server_received);
+
+        Self {
+            offset,
+            round_trip: round_trip.max(Duration::zero()),
+        }
+    }
+
+    // how far the server clock is ahead of the local one
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    pub fn round_trip(&self) -> Duration {
+        self.round_trip
+    }
+
+    pub fn server_time(&self, local: DateTime<Utc>) -> DateTime<Utc> {
+        local + self.offset
+    }
+}
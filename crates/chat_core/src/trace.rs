@@ -0,0 +1,288 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    json::JsonValue,
+    protocol::{Message, MessageBuilder, MessageType},
+};
+
+// what a secret reads as in a trace, a replay logs in with it as the password
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameTracer {
+    writer: Arc<Mutex<BufWriter<File>>>,
+    session: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    timestamp: String,
+    direction: Direction,
+    session: Option<String>,
+    redacted: bool,
+    message: Message,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "sent" => Ok(Direction::Sent),
+            "received" => Ok(Direction::Received),
+            _ => Err(format!("Unknown direction '{}'", value)),
+        }
+    }
+}
+
+impl FrameTracer {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+            session: None,
+        })
+    }
+
+    pub fn for_session(&self, session: &str) -> Self {
+        Self {
+            writer: Arc::clone(&self.writer),
+            session: Some(session.to_string()),
+        }
+    }
+
+    pub fn record(&self, direction: Direction, message: &Message) {
+        let record = TraceRecord::new(direction, self.session.clone(), message);
+        let mut writer = self.writer.lock().unwrap();
+
+        // tracing must never break the connection it is observing
+        if let Err(e) = writeln!(writer, "{}", record.to_json()).and_then(|_| writer.flush()) {
+            tracing::warn!("Could not write frame trace: {}", e);
+        }
+    }
+}
+
+impl TraceRecord {
+    pub fn new(direction: Direction, session: Option<String>, message: &Message) -> Self {
+        let (message, redacted) = Self::redact(message);
+
+        Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            direction,
+            session,
+            redacted,
+            message,
+        }
+    }
+
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    pub fn is_redacted(&self) -> bool {
+        self.redacted
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let fields: Vec<JsonValue> = self
+            .message
+            .payload()
+            .get_data()
+            .iter()
+            .map(|field| JsonValue::from(summarize_field(field)))
+            .collect();
+
+        JsonValue::object()
+            .with("timestamp", self.timestamp.as_str())
+            .with("direction", self.direction.as_str())
+            .with("session", self.session.clone())
+            .with("type", format!("{:?}", self.message.message_type()))
+            .with("size", self.message.wire_size())
+            .with("redacted", self.redacted)
+            .with("fields", fields)
+            .with("bytes", to_hex(&self.message.to_bytes()))
+    }
+
+    pub fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| format!("Missing '{}'", key))
+        };
+
+        let bytes = from_hex(string("bytes")?)?;
+
+        Ok(Self {
+            timestamp: string("timestamp")?.to_string(),
+            direction: Direction::parse(string("direction")?)?,
+            session: value.get("session").and_then(JsonValue::as_str).map(str::to_string),
+            redacted: value.get("redacted") == Some(&JsonValue::Bool(true)),
+            message: Message::from_bytes(&bytes)?,
+        })
+    }
+
+    pub fn read_all(path: &Path) -> Result<Vec<Self>, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut records = Vec::new();
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = JsonValue::parse(&line).and_then(|value| Self::from_json(&value));
+            records.push(record.map_err(|e| format!("Line {}: {}", index + 1, e))?);
+        }
+
+        Ok(records)
+    }
+
+    // what was said replaced by text of the same shape, a capture stays replayable without it.
+    // the same body always reads the same, the relayed copy of a message still matches the one sent
+    pub fn redact_bodies(&self) -> Self {
+        let bodies: &[usize] = match self.message.message_type() {
+            MessageType::DirectMessageSend
+            | MessageType::DirectMessageReceive
+            | MessageType::MessageEdit
+            | MessageType::ScheduleMessageSend => &[1],
+            MessageType::MessageEdited => &[2],
+            MessageType::HistoryEntry | MessageType::SearchResult => &[3],
+            _ => return self.clone(),
+        };
+
+        let mut fields = self.message.payload().get_data();
+        for &index in bodies {
+            if let Some(body) = fields.get_mut(index) {
+                if let Ok(text) = std::str::from_utf8(body) {
+                    *body = redact_text(text).into_bytes();
+                }
+            }
+        }
+
+        Self {
+            redacted: true,
+            message: MessageBuilder::new(self.message.message_type())
+                .with_fields(fields)
+                .build(),
+            ..self.clone()
+        }
+    }
+
+    fn redact(message: &Message) -> (Message, bool) {
+        let secrets: &[usize] = match message.message_type() {
+            MessageType::Auth | MessageType::AuthCreate | MessageType::AdminResetPassword => &[1],
+            MessageType::PasswordChange => &[0, 1],
+            _ => return (message.clone(), false),
+        };
+
+        let mut fields = message.payload().get_data();
+        for &index in secrets {
+            if let Some(secret) = fields.get_mut(index) {
+                *secret = REDACTED.as_bytes().to_vec();
+            }
+        }
+
+        (
+            MessageBuilder::new(message.message_type()).with_fields(fields).build(),
+            true,
+        )
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+
+        write!(f, "{} {} ", self.timestamp, arrow)?;
+        if let Some(session) = &self.session {
+            write!(f, "[{}] ", session)?;
+        }
+        write!(
+            f,
+            "{:?} ({} bytes)",
+            self.message.message_type(),
+            self.message.wire_size()
+        )?;
+
+        for field in self.message.payload().get_data() {
+            write!(f, " {:?}", summarize_field(&field))?;
+        }
+        if self.redacted {
+            write!(f, " (redacted)")?;
+        }
+
+        Ok(())
+    }
+}
+
+// spaces, punctuation and the number of characters stay, so trimming and matching work alike
+fn redact_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            c if c.is_alphabetic() => 'x',
+            c if c.is_numeric() => '0',
+            c => c,
+        })
+        .collect()
+}
+
+// text as it is, anything else as hex
+pub fn summarize_field(field: &[u8]) -> String {
+    match std::str::from_utf8(field) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("0x{}", to_hex(field)),
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err("Odd number of hex digits".into());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            hex.get(index..index + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("Invalid hex at offset {}", index))
+        })
+        .collect()
+}
@@ -0,0 +1,453 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    sync::watch,
+};
+
+use super::Connector;
+use crate::{
+    integrity::Integrity,
+    protocol::{HEADER_START, MAX_FIELD_COUNT, MAX_FIELD_SIZE},
+};
+
+const LINK_BUFFER_SIZE: usize = 64 * 1024;
+// a held frame goes out on its own when nothing follows it this quickly
+const REORDER_WINDOW: Duration = Duration::from_millis(20);
+const PARTIAL_WRITE_PAUSE: Duration = Duration::from_millis(2);
+// spreads the seeds of consecutive connections over the whole range
+const SEED_STEP: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// what one direction of a link does to the frames passing through it, probabilities are per frame
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    // every frame waits a uniformly drawn time in this range
+    latency: Option<(Duration, Duration)>,
+    disconnect: f64,
+    corruption: f64,
+    partial_writes: f64,
+    stalls: Option<(f64, Duration)>,
+    reordering: f64,
+}
+
+// the faults of every connection made through a ChaosConnector, the same seed makes the same decisions
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    seed: u64,
+    to_server: Faults,
+    to_client: Faults,
+}
+
+// switches the chaos off and counts what it did, shared by every connection of a connector
+#[derive(Debug, Clone, Default)]
+pub struct ChaosControl {
+    calm: Arc<AtomicBool>,
+    links: Arc<Mutex<Vec<Arc<watch::Sender<bool>>>>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub connections: u64,
+    pub frames: u64,
+    pub disconnects: u64,
+    pub corrupted: u64,
+    pub partial_writes: u64,
+    pub stalls: u64,
+    pub reordered: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    connections: AtomicU64,
+    frames: AtomicU64,
+    disconnects: AtomicU64,
+    corrupted: AtomicU64,
+    partial_writes: AtomicU64,
+    stalls: AtomicU64,
+    reordered: AtomicU64,
+}
+
+// relays every connection through a task per direction, reconnects go through the same chaos
+#[derive(Debug, Clone)]
+pub struct ChaosConnector<C> {
+    inner: C,
+    chaos: Chaos,
+    control: ChaosControl,
+}
+
+// splitmix64, small and the same on every platform
+#[derive(Debug, Clone)]
+struct ChaosRng(u64);
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = Some((min, max.max(min)));
+        self
+    }
+
+    // both directions of the connection end, the peers see it closed
+    pub fn with_disconnects(mut self, probability: f64) -> Self {
+        self.disconnect = probability;
+        self
+    }
+
+    // flips one bit of the frame
+    pub fn with_corruption(mut self, probability: f64) -> Self {
+        self.corruption = probability;
+        self
+    }
+
+    // the frame arrives in two pieces with a pause between them
+    pub fn with_partial_writes(mut self, probability: f64) -> Self {
+        self.partial_writes = probability;
+        self
+    }
+
+    // nothing more passes in this direction for the duration
+    pub fn with_stalls(mut self, probability: f64, duration: Duration) -> Self {
+        self.stalls = Some((probability, duration));
+        self
+    }
+
+    // the frame swaps places with the one after it
+    pub fn with_reordering(mut self, probability: f64) -> Self {
+        self.reordering = probability;
+        self
+    }
+}
+
+impl Chaos {
+    // no faults until some are added
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            to_server: Faults::default(),
+            to_client: Faults::default(),
+        }
+    }
+
+    pub fn with_to_server(mut self, faults: Faults) -> Self {
+        self.to_server = faults;
+        self
+    }
+
+    pub fn with_to_client(mut self, faults: Faults) -> Self {
+        self.to_client = faults;
+        self
+    }
+
+    // the same faults in both directions
+    pub fn with_faults(self, faults: Faults) -> Self {
+        self.with_to_server(faults.clone()).with_to_client(faults)
+    }
+
+    // jittery, with the odd dropout and a connection lost now and then
+    pub fn flaky_wifi(seed: u64) -> Self {
+        Self::new(seed).with_faults(
+            Faults::new()
+                .with_latency(Duration::ZERO, Duration::from_millis(30))
+                .with_partial_writes(0.3)
+                .with_stalls(0.05, Duration::from_millis(200))
+                .with_disconnects(0.02),
+        )
+    }
+
+    // the answers take their time and come in pieces, what the client sends is unaffected
+    pub fn slow_server(seed: u64) -> Self {
+        Self::new(seed).with_to_client(
+            Faults::new()
+                .with_latency(Duration::from_millis(20), Duration::from_millis(120))
+                .with_partial_writes(0.5)
+                .with_stalls(0.1, Duration::from_millis(300)),
+        )
+    }
+
+    // damages and shuffles frames without ever closing the connection
+    pub fn corrupting_middlebox(seed: u64) -> Self {
+        Self::new(seed).with_faults(Faults::new().with_corruption(0.05).with_reordering(0.05))
+    }
+}
+
+impl ChaosControl {
+    // frames pass untouched from now on, one held back for reordering still goes out
+    pub fn calm(&self) {
+        self.calm.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_calm(&self) -> bool {
+        self.calm.load(Ordering::SeqCst)
+    }
+
+    // ends every connection open right now, like a disconnect drawn for each of them
+    pub fn cut(&self) {
+        for link in self.links.lock().unwrap().drain(..) {
+            link.send_replace(true);
+        }
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        let counters = &self.counters;
+        ChaosStats {
+            connections: counters.connections.load(Ordering::SeqCst),
+            frames: counters.frames.load(Ordering::SeqCst),
+            disconnects: counters.disconnects.load(Ordering::SeqCst),
+            corrupted: counters.corrupted.load(Ordering::SeqCst),
+            partial_writes: counters.partial_writes.load(Ordering::SeqCst),
+            stalls: counters.stalls.load(Ordering::SeqCst),
+            reordered: counters.reordered.load(Ordering::SeqCst),
+        }
+    }
+
+    fn count(&self, counter: impl Fn(&Counters) -> &AtomicU64) {
+        counter(&self.counters).fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl<C> ChaosConnector<C> {
+    pub fn new(inner: C, chaos: Chaos) -> Self {
+        Self {
+            inner,
+            chaos,
+            control: ChaosControl::default(),
+        }
+    }
+
+    pub fn control(&self) -> ChaosControl {
+        self.control.clone()
+    }
+}
+
+impl<C: Connector> Connector for ChaosConnector<C> {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> io::Result<Self::Stream> {
+        let (server_reader, server_writer) = tokio::io::split(self.inner.connect().await?);
+        let (client, relay) = tokio::io::duplex(LINK_BUFFER_SIZE);
+        let (client_reader, client_writer) = tokio::io::split(relay);
+
+        // the n-th connection draws the same faults on every run
+        let index = self.control.counters.connections.fetch_add(1, Ordering::SeqCst);
+        let seed = self.chaos.seed ^ index.wrapping_add(1).wrapping_mul(SEED_STEP);
+        let cut = Arc::new(watch::channel(false).0);
+        self.control.links.lock().unwrap().push(Arc::clone(&cut));
+
+        let to_server = Link {
+            faults: self.chaos.to_server.clone(),
+            rng: ChaosRng(seed),
+            control: self.control.clone(),
+            cut: Arc::clone(&cut),
+        };
+        let to_client = Link {
+            faults: self.chaos.to_client.clone(),
+            rng: ChaosRng(!seed),
+            control: self.control.clone(),
+            cut,
+        };
+        tokio::spawn(to_server.relay(client_reader, server_writer));
+        tokio::spawn(to_client.relay(server_reader, client_writer));
+        Ok(client)
+    }
+}
+
+// one direction of one connection
+struct Link {
+    faults: Faults,
+    rng: ChaosRng,
+    control: ChaosControl,
+    cut: Arc<watch::Sender<bool>>,
+}
+
+enum Next {
+    Frame(Vec<u8>),
+    // nothing came within the reordering window
+    Quiet,
+    Closed,
+}
+
+impl Link {
+    async fn relay<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(mut self, from: R, mut to: W) {
+        let mut frames = Frames::new(from);
+        let mut cut = self.cut.subscribe();
+        let mut held: Option<Vec<u8>> = None;
+
+        loop {
+            let next = tokio::select! {
+                _ = cut.wait_for(|cut| *cut) => break,
+                next = frames.next_within(held.is_some().then_some(REORDER_WINDOW)) => next,
+            };
+            let result = match next {
+                Next::Closed => break,
+                Next::Quiet => self.write(&mut to, held.take().unwrap_or_default()).await,
+                Next::Frame(frame) if self.control.is_calm() => {
+                    let result = self.write(&mut to, frame).await;
+                    match held.take() {
+                        Some(held) if result.is_ok() => self.write(&mut to, held).await,
+                        _ => result,
+                    }
+                }
+                Next::Frame(frame) => match self.disturb(frame).await {
+                    None => {
+                        self.control.count(|counters| &counters.disconnects);
+                        self.cut.send_replace(true);
+                        break;
+                    }
+                    Some(frame) if held.is_none() && self.rng.chance(self.faults.reordering) => {
+                        self.control.count(|counters| &counters.reordered);
+                        held = Some(frame);
+                        Ok(())
+                    }
+                    Some(frame) => {
+                        let result = self.write(&mut to, frame).await;
+                        match held.take() {
+                            Some(held) if result.is_ok() => self.write(&mut to, held).await,
+                            _ => result,
+                        }
+                    }
+                },
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        if let Some(held) = held {
+            to.write_all(&held).await.ok();
+        }
+        to.shutdown().await.ok();
+    }
+
+    // None when the connection is to end here
+    async fn disturb(&mut self, mut frame: Vec<u8>) -> Option<Vec<u8>> {
+        self.control.count(|counters| &counters.frames);
+        if self.rng.chance(self.faults.disconnect) {
+            return None;
+        }
+        if let Some((min, max)) = self.faults.latency {
+            tokio::time::sleep(self.rng.between(min, max)).await;
+        }
+        if let Some((probability, duration)) = self.faults.stalls {
+            if self.rng.chance(probability) {
+                self.control.count(|counters| &counters.stalls);
+                tokio::time::sleep(duration).await;
+            }
+        }
+        if !frame.is_empty() && self.rng.chance(self.faults.corruption) {
+            self.control.count(|counters| &counters.corrupted);
+            let bit = self.rng.below(frame.len() as u64 * 8);
+            frame[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+        Some(frame)
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(&mut self, to: &mut W, frame: Vec<u8>) -> io::Result<()> {
+        if frame.len() < 2 || self.control.is_calm() || !self.rng.chance(self.faults.partial_writes) {
+            return to.write_all(&frame).await;
+        }
+        self.control.count(|counters| &counters.partial_writes);
+        let split = 1 + self.rng.below(frame.len() as u64 - 1) as usize;
+        to.write_all(&frame[..split]).await?;
+        to.flush().await?;
+        tokio::time::sleep(PARTIAL_WRITE_PAUSE).await;
+        to.write_all(&frame[split..]).await
+    }
+}
+
+// cuts the stream at frame boundaries, whatever does not parse as a frame passes as one piece
+struct Frames<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Frames<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    async fn next_within(&mut self, window: Option<Duration>) -> Next {
+        match window {
+            Some(window) => tokio::time::timeout(window, self.next()).await.unwrap_or(Next::Quiet),
+            None => self.next().await,
+        }
+    }
+
+    // cancel safe, what was read stays in the buffer
+    async fn next(&mut self) -> Next {
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(length) = frame_len(&self.buffer) {
+                return Next::Frame(self.buffer.drain(..length).collect());
+            }
+            match self.reader.read(&mut chunk).await {
+                Ok(0) | Err(_) if self.buffer.is_empty() => return Next::Closed,
+                Ok(0) | Err(_) => return Next::Frame(std::mem::take(&mut self.buffer)),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+// the length of the frame at the start of the buffer, None until all of it is there.
+// frames are taken to end in the default trailer, after switching integrity the cuts may be off
+fn frame_len(buffer: &[u8]) -> Option<usize> {
+    let header = HEADER_START.to_be_bytes();
+    if buffer.len() < 2 {
+        return None;
+    }
+    if buffer[..2] != header {
+        let next = buffer.windows(2).skip(1).position(|window| window == header);
+        return Some(next.map_or(buffer.len(), |position| position + 1));
+    }
+
+    let count = u32::from_be_bytes(buffer.get(4..8)?.try_into().unwrap());
+    if count > MAX_FIELD_COUNT {
+        return Some(buffer.len());
+    }
+    let mut offset = 8;
+    for _ in 0..count {
+        let length = u32::from_be_bytes(buffer.get(offset..offset + 4)?.try_into().unwrap());
+        if length > MAX_FIELD_SIZE {
+            return Some(buffer.len());
+        }
+        offset += 4 + length as usize;
+    }
+    offset += Integrity::default().trailer_len();
+    (buffer.len() >= offset).then_some(offset)
+}
+
+impl ChaosRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(SEED_STEP);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        let draw = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        draw < probability
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound.max(1)
+    }
+
+    fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        let spread = (max - min).as_micros() as u64;
+        min + Duration::from_micros(self.below(spread + 1))
+    }
+}
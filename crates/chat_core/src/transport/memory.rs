@@ -0,0 +1,77 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{io::DuplexStream, sync::mpsc};
+
+use super::{Connector, Listener};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct MemoryListener {
+    rx: mpsc::UnboundedReceiver<(DuplexStream, SocketAddr)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryConnector {
+    tx: mpsc::UnboundedSender<(DuplexStream, SocketAddr)>,
+    next_port: Arc<AtomicU16>,
+    address: IpAddr,
+}
+
+// every connect hands the other end of a fresh duplex pipe to the listener
+pub fn network() -> (MemoryListener, MemoryConnector) {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    (
+        MemoryListener { rx },
+        MemoryConnector {
+            tx,
+            next_port: Arc::new(AtomicU16::new(1)),
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        },
+    )
+}
+
+impl Listener for MemoryListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, SocketAddr)> {
+        self.rx
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "memory network closed"))
+    }
+}
+
+impl MemoryConnector {
+    pub fn with_address(mut self, address: IpAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn connect_now(&self) -> io::Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+        let addr = SocketAddr::new(self.address, self.next_port.fetch_add(1, Ordering::Relaxed));
+
+        self.tx
+            .send((server, addr))
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "memory listener dropped"))?;
+
+        Ok(client)
+    }
+}
+
+impl Connector for MemoryConnector {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> io::Result<Self::Stream> {
+        self.connect_now()
+    }
+}
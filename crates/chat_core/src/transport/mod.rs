@@ -0,0 +1,58 @@
+use std::{future::Future, io, net::SocketAddr};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(feature = "test-util")]
+pub mod chaos;
+#[cfg(feature = "test-util")]
+pub mod memory;
+
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+pub trait Listener: Send + 'static {
+    type Stream: Stream;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr)>> + Send;
+}
+
+pub trait Connector: Send + Sync + 'static {
+    type Stream: Stream;
+
+    fn connect(&self) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    host: String,
+    port: u16,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Stream for T {}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, SocketAddr)> {
+        TcpListener::accept(self).await
+    }
+}
+
+impl TcpConnector {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+        }
+    }
+}
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    async fn connect(&self) -> io::Result<Self::Stream> {
+        TcpStream::connect((self.host.as_str(), self.port)).await
+    }
+}
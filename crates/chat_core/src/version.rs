@@ -0,0 +1,156 @@
+use std::fmt;
+
+use crate::{
+    integrity::Integrity,
+    protocol::{DisconnectReason, Message, MessageBuilder, MessageType},
+};
+
+// the version every frame is written in
+pub const VERSION: u8 = 0x01;
+// the oldest version this build still reads, older peers are told so in this version
+pub const OLDEST_VERSION: u8 = 0x01;
+
+const INVALID_CHECKSUM: &str = "Invalid checksum";
+
+// turns a frame of one wire version into the current in-memory message
+pub type Adapter = fn(RawFrame) -> Result<Message, String>;
+
+// one entry per version that ever shipped, a version without an adapter cannot be read
+const ADAPTERS: &[(u8, Adapter)] = &[(0x01, adapt_v1)];
+
+// the versions a decoder accepts, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VersionRange {
+    oldest: u8,
+    current: u8,
+}
+
+// a frame as it came off the wire, before its adapter made a message of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFrame {
+    pub version: u8,
+    pub message_type: u8,
+    pub fields: Vec<Vec<u8>>,
+    // the algorithm the connection agreed on, the trailer is checked against it
+    pub integrity: Integrity,
+    pub trailer: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    // the rest of the stream cannot be trusted, the peer has to be told and dropped
+    UnsupportedVersion { version: u8, supported: VersionRange },
+    // the peer closed the stream between two frames, the normal way to leave
+    PeerClosed,
+    // the stream ended or failed partway through a frame, there is nothing left to read
+    Closed(String),
+    Invalid(String),
+}
+
+impl VersionRange {
+    pub const fn new(oldest: u8, current: u8) -> Self {
+        Self { oldest, current }
+    }
+
+    pub fn oldest(&self) -> u8 {
+        self.oldest
+    }
+
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    pub fn supports(&self, version: u8) -> bool {
+        (self.oldest..=self.current).contains(&version) && adapter(version).is_some()
+    }
+
+    // the message is labelled with the current version whatever version it arrived in
+    pub fn decode(&self, frame: RawFrame) -> Result<Message, FrameError> {
+        let version = frame.version;
+        match adapter(version) {
+            Some(adapt) if self.supports(version) => Ok(adapt(frame)?.with_version(self.current)),
+            _ => Err(FrameError::UnsupportedVersion {
+                version,
+                supported: *self,
+            }),
+        }
+    }
+
+    // a Disconnect in the oldest version this build can write, even when the range no longer accepts it,
+    // so a peer that is far behind can still read why it was dropped
+    pub fn unsupported_notice(&self, version: u8) -> Message {
+        let detail = format!("Unsupported protocol version {}, this side speaks {}", version, self);
+        Message::disconnect(DisconnectReason::ProtocolError, &detail).with_version(OLDEST_VERSION)
+    }
+}
+
+impl Default for VersionRange {
+    fn default() -> Self {
+        Self::new(OLDEST_VERSION, VERSION)
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.oldest == self.current {
+            write!(f, "version {}", self.current)
+        } else {
+            write!(f, "versions {} to {}", self.oldest, self.current)
+        }
+    }
+}
+
+impl FrameError {
+    // only a frame that was read but made no sense leaves a stream that may be synced up again
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Self::Invalid(_))
+    }
+
+    // a whole frame whose trailer did not match, with a different algorithm on each side every frame is one
+    pub fn is_integrity_failure(&self) -> bool {
+        matches!(self, Self::Invalid(e) if e == INVALID_CHECKSUM)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion { version, supported } => {
+                write!(f, "Unsupported protocol version {}, expected {}", version, supported)
+            }
+            Self::PeerClosed => f.write_str("Connection closed by peer"),
+            Self::Closed(e) | Self::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+impl From<String> for FrameError {
+    fn from(e: String) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+impl From<FrameError> for String {
+    fn from(e: FrameError) -> Self {
+        e.to_string()
+    }
+}
+
+fn adapter(version: u8) -> Option<Adapter> {
+    ADAPTERS
+        .iter()
+        .find(|(known, _)| *known == version)
+        .map(|(_, adapter)| *adapter)
+}
+
+// the first format, a trailer over the field data, crc32 unless the connection agreed on another
+fn adapt_v1(frame: RawFrame) -> Result<Message, String> {
+    let message_type = MessageType::try_from(frame.message_type)?;
+    if !frame
+        .integrity
+        .verify(frame.fields.iter().map(Vec::as_slice), &frame.trailer)
+    {
+        return Err(INVALID_CHECKSUM.into());
+    }
+    Ok(MessageBuilder::new(message_type).with_fields(frame.fields).build())
+}
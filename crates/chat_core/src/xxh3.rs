@@ -0,0 +1,187 @@
+// XXH3 64 bit with the default secret and seed 0, the one-shot variant only, frames are hashed whole
+
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c, //
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f, //
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21, //
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c, //
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3, //
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8, //
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d, //
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64, //
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb, //
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e, //
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce, //
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e, //
+];
+
+const PRIME32_1: u64 = 0x9e37_79b1;
+const PRIME32_2: u64 = 0x85eb_ca77;
+const PRIME32_3: u64 = 0xc2b2_ae3d;
+const PRIME64_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME64_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME64_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME64_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME64_5: u64 = 0x27d4_eb2f_1656_67c5;
+const PRIME_MX1: u64 = 0x1656_6791_9e37_79f9;
+const PRIME_MX2: u64 = 0x9fb2_1c65_1e98_df25;
+
+const STRIPE_LEN: usize = 64;
+const SECRET_CONSUME_RATE: usize = 8;
+const STRIPES_PER_BLOCK: usize = (SECRET.len() - STRIPE_LEN) / SECRET_CONSUME_RATE;
+const BLOCK_LEN: usize = STRIPE_LEN * STRIPES_PER_BLOCK;
+const MIDSIZE_MAX: usize = 240;
+const MIDSIZE_START_OFFSET: usize = 3;
+const MIDSIZE_LAST_OFFSET: usize = 17;
+const SECRET_SIZE_MIN: usize = 136;
+const SECRET_LAST_ACC_START: usize = 7;
+const SECRET_MERGE_ACCS_START: usize = 11;
+
+pub fn hash64(input: &[u8]) -> u64 {
+    let len = input.len();
+    match len {
+        0 => avalanche64(read64(&SECRET, 56) ^ read64(&SECRET, 64)),
+        1..=3 => {
+            let combined = (u32::from(input[0]) << 16)
+                | (u32::from(input[len >> 1]) << 24)
+                | u32::from(input[len - 1])
+                | ((len as u32) << 8);
+            let bitflip = u64::from(read32(&SECRET, 0) ^ read32(&SECRET, 4));
+            avalanche64(u64::from(combined) ^ bitflip)
+        }
+        4..=8 => {
+            let low = u64::from(read32(input, 0));
+            let high = u64::from(read32(input, len - 4));
+            let bitflip = read64(&SECRET, 8) ^ read64(&SECRET, 16);
+            rrmxmx((high + (low << 32)) ^ bitflip, len as u64)
+        }
+        9..=16 => {
+            let low = read64(input, 0) ^ (read64(&SECRET, 24) ^ read64(&SECRET, 32));
+            let high = read64(input, len - 8) ^ (read64(&SECRET, 40) ^ read64(&SECRET, 48));
+            let acc = (len as u64)
+                .wrapping_add(low.swap_bytes())
+                .wrapping_add(high)
+                .wrapping_add(fold64(low, high));
+            avalanche(acc)
+        }
+        17..=128 => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            let rounds = (len - 1) / 32;
+            for round in (0..=rounds).rev() {
+                acc = acc
+                    .wrapping_add(mix16(input, 16 * round, 32 * round))
+                    .wrapping_add(mix16(input, len - 16 * (round + 1), 32 * round + 16));
+            }
+            avalanche(acc)
+        }
+        129..=MIDSIZE_MAX => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            for round in 0..8 {
+                acc = acc.wrapping_add(mix16(input, 16 * round, 16 * round));
+            }
+            acc = avalanche(acc);
+            for round in 8..len / 16 {
+                acc = acc.wrapping_add(mix16(input, 16 * round, 16 * (round - 8) + MIDSIZE_START_OFFSET));
+            }
+            acc = acc.wrapping_add(mix16(input, len - 16, SECRET_SIZE_MIN - MIDSIZE_LAST_OFFSET));
+            avalanche(acc)
+        }
+        _ => hash_long(input),
+    }
+}
+
+fn hash_long(input: &[u8]) -> u64 {
+    let len = input.len();
+    let mut acc = [
+        PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1,
+    ];
+
+    let blocks = (len - 1) / BLOCK_LEN;
+    for block in 0..blocks {
+        accumulate(&mut acc, &input[block * BLOCK_LEN..], STRIPES_PER_BLOCK);
+        scramble(&mut acc);
+    }
+    // the last stripe always ends at the end of the input, it may overlap the one before
+    let stripes = ((len - 1) - BLOCK_LEN * blocks) / STRIPE_LEN;
+    accumulate(&mut acc, &input[blocks * BLOCK_LEN..], stripes);
+    accumulate512(
+        &mut acc,
+        &input[len - STRIPE_LEN..],
+        SECRET.len() - STRIPE_LEN - SECRET_LAST_ACC_START,
+    );
+
+    let mut result = (len as u64).wrapping_mul(PRIME64_1);
+    for pair in 0..4 {
+        let secret = SECRET_MERGE_ACCS_START + 16 * pair;
+        result = result.wrapping_add(fold64(
+            acc[2 * pair] ^ read64(&SECRET, secret),
+            acc[2 * pair + 1] ^ read64(&SECRET, secret + 8),
+        ));
+    }
+    avalanche(result)
+}
+
+fn accumulate(acc: &mut [u64; 8], input: &[u8], stripes: usize) {
+    for stripe in 0..stripes {
+        accumulate512(acc, &input[stripe * STRIPE_LEN..], stripe * SECRET_CONSUME_RATE);
+    }
+}
+
+fn accumulate512(acc: &mut [u64; 8], input: &[u8], secret: usize) {
+    for lane in 0..8 {
+        let value = read64(input, 8 * lane);
+        let key = value ^ read64(&SECRET, secret + 8 * lane);
+        acc[lane ^ 1] = acc[lane ^ 1].wrapping_add(value);
+        acc[lane] = acc[lane].wrapping_add((key & 0xffff_ffff).wrapping_mul(key >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8]) {
+    for (lane, value) in acc.iter_mut().enumerate() {
+        let key = read64(&SECRET, SECRET.len() - STRIPE_LEN + 8 * lane);
+        *value = ((*value ^ (*value >> 47)) ^ key).wrapping_mul(PRIME32_1);
+    }
+}
+
+fn mix16(input: &[u8], offset: usize, secret: usize) -> u64 {
+    fold64(
+        read64(input, offset) ^ read64(&SECRET, secret),
+        read64(input, offset + 8) ^ read64(&SECRET, secret + 8),
+    )
+}
+
+// the 128 bit product, its halves xored
+fn fold64(a: u64, b: u64) -> u64 {
+    let product = u128::from(a) * u128::from(b);
+    (product as u64) ^ ((product >> 64) as u64)
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+fn avalanche64(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^ (h >> 28)
+}
+
+fn read32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,143 @@
+use std::process::Command;
+
+use chat_core::{
+    decode::{decode_stream, json_report, text_report, InputFormat, StreamEntry},
+    integrity::Integrity,
+    protocol::Message,
+    trace::{Direction, TraceRecord},
+};
+
+// garbage, a good frame, one with a flipped checksum bit, a header start with nonsense behind it,
+// a frame of a type this build does not know, and one cut off by the end of the capture
+fn capture() -> Vec<u8> {
+    let mut corrupted = Message::direct_message_send("bob", "hi").to_bytes();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0x01;
+    // no fields, so the crc32 trailer is zero
+    let unknown = [0x59, 0x18, 0x01, 0xee, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    let mut stream = b"junk".to_vec();
+    stream.extend(Message::direct_message_send("bob", "hello").to_bytes());
+    stream.extend(corrupted);
+    stream.extend([0x59, 0x18, 0x01, 0x04, 0x00, 0x00, 0x00, 0x63, b'x', b'x']);
+    stream.extend(unknown);
+    stream.extend(&unknown[..6]);
+    stream
+}
+
+#[test]
+fn every_byte_is_accounted_for_in_order() {
+    let bytes = capture();
+    let entries = decode_stream(&bytes, Integrity::Crc32);
+
+    let mut covered = 0;
+    for entry in &entries {
+        let (offset, len) = match entry {
+            StreamEntry::Frame(frame) => (frame.offset, frame.len),
+            StreamEntry::Skipped { offset, len } | StreamEntry::Malformed { offset, len, .. } => (*offset, *len),
+        };
+        assert_eq!(offset, covered, "{:?}", entry);
+        covered += len;
+    }
+    assert_eq!(covered, bytes.len());
+}
+
+#[test]
+fn the_text_report_lists_frames_gaps_and_failures() {
+    let report = text_report(&decode_stream(&capture(), Integrity::Crc32));
+    assert_eq!(
+        report,
+        [
+            "0..4 skipped 4 bytes without a header start",
+            "4..32 DirectMessageSend v1, 2 fields, checksum ok",
+            r#"  0: 626f62 "bob""#,
+            r#"  1: 68656c6c6f "hello""#,
+            "32..57 DirectMessageSend v1, 2 fields, checksum INVALID",
+            r#"  0: 626f62 "bob""#,
+            r#"  1: 6869 "hi""#,
+            "57..67 malformed: Too many payload fields (99 > 32)",
+            "67..79 unknown type 0xee v1, 0 fields, checksum ok",
+            "79..85 malformed: Stream ends 6 bytes into the frame",
+            "3 frames (1 with a bad checksum), 4 bytes skipped, 2 malformed",
+            "",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn the_json_report_has_one_object_per_entry() {
+    let report = json_report(&decode_stream(&capture(), Integrity::Crc32));
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], r#"{"kind":"skipped","offset":0,"len":4}"#);
+    assert_eq!(
+        lines[2],
+        concat!(
+            r#"{"kind":"frame","offset":32,"len":25,"version":1,"type":"DirectMessageSend","type_byte":65,"#,
+            r#""checksum_valid":false,"fields":[{"hex":"626f62","text":"bob"},{"hex":"6869","text":"hi"}]}"#
+        )
+    );
+    assert_eq!(
+        lines[3],
+        r#"{"kind":"malformed","offset":57,"len":10,"reason":"Too many payload fields (99 > 32)"}"#
+    );
+    assert!(lines[4].contains(r#""type":null,"type_byte":238,"#), "{}", lines[4]);
+}
+
+#[test]
+fn the_trailer_is_checked_with_the_chosen_algorithm() {
+    let bytes = Message::motd("hello").to_bytes_using(Integrity::Xxh3);
+    match decode_stream(&bytes, Integrity::Xxh3).as_slice() {
+        [StreamEntry::Frame(frame)] => assert!(frame.checksum_valid),
+        entries => panic!("Unexpected {:?}", entries),
+    }
+    // read as crc32 the frame ends four bytes early, and those are left over
+    match decode_stream(&bytes, Integrity::Crc32).as_slice() {
+        [StreamEntry::Frame(frame), StreamEntry::Skipped { len: 4, .. }] => assert!(!frame.checksum_valid),
+        entries => panic!("Unexpected {:?}", entries),
+    }
+}
+
+#[test]
+fn hex_and_trace_captures_become_the_same_stream() {
+    let first = Message::motd("hello");
+    let second = Message::heartbeat();
+    let mut raw = first.to_bytes();
+    raw.extend(second.to_bytes());
+
+    let hex: String = raw.iter().map(|byte| format!("{:02x} ", byte)).collect();
+    assert_eq!(InputFormat::Hex.to_stream(hex.as_bytes()), Ok(raw.clone()));
+    assert!(InputFormat::Hex.to_stream(b"59 1").is_err());
+
+    let trace = format!(
+        "{}\n\n{}\n",
+        TraceRecord::new(Direction::Sent, None, &first).to_json(),
+        TraceRecord::new(Direction::Received, Some("s1".into()), &second).to_json()
+    );
+    assert_eq!(InputFormat::Trace.to_stream(trace.as_bytes()), Ok(raw));
+    assert_eq!(
+        InputFormat::Trace.to_stream(b"{\"timestamp\":\"now\"}"),
+        Err("Line 1: Missing 'bytes'".to_string())
+    );
+    assert_eq!(InputFormat::parse("trace"), Ok(InputFormat::Trace));
+    assert!(InputFormat::parse("pcap").is_err());
+}
+
+#[test]
+fn the_binary_decodes_a_hex_capture_file() {
+    let path = std::env::temp_dir().join(format!("chat_rs_decode_{}.hex", std::process::id()));
+    let hex: String = capture().iter().map(|byte| format!("{:02x}", byte)).collect();
+    std::fs::write(&path, hex).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chat_decode"))
+        .arg(&path)
+        .args(["--format", "hex", "--json"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, json_report(&decode_stream(&capture(), Integrity::Crc32)));
+}
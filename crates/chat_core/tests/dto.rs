@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use chat_core::{
+    dto::{
+        AdminClearIpBan, DirectMessageSend, Mute, Request, Response, ScheduleMessageSend, SearchRequest, SigningKeySet,
+        SyncRequest,
+    },
+    protocol::{Message, MessageBuilder, MessageId, MessageType, PresenceScope, ScheduleTime},
+};
+
+fn decode(message: &Message) -> Request {
+    Request::decode(message).expect("a request").expect("valid fields")
+}
+
+#[test]
+fn requests_decode_from_the_frames_clients_send() {
+    assert_eq!(
+        decode(&Message::direct_message_send("bob", "hi")),
+        Request::DirectMessageSend(DirectMessageSend {
+            recipient: "bob".to_string(),
+            body: "hi".to_string(),
+            ttl: None,
+            client_id: None,
+            signature: None,
+        })
+    );
+    assert_eq!(
+        decode(&Message::direct_message_send_with_id("bob", "hi", 30, "c1")),
+        Request::DirectMessageSend(DirectMessageSend {
+            recipient: "bob".to_string(),
+            body: "hi".to_string(),
+            ttl: Some(30),
+            client_id: Some("c1".to_string()),
+            signature: None,
+        })
+    );
+    // the fields a signed message left out stay out
+    assert_eq!(
+        decode(&Message::direct_message_send("bob", "hi").with_signature(&[7; 64])),
+        Request::DirectMessageSend(DirectMessageSend {
+            recipient: "bob".to_string(),
+            body: "hi".to_string(),
+            ttl: None,
+            client_id: None,
+            signature: Some(vec![7; 64]),
+        })
+    );
+    assert_eq!(
+        decode(&Message::search_request("  hello ", None, 10)),
+        Request::SearchRequest(SearchRequest {
+            query: "hello".to_string(),
+            peer: None,
+            limit: 10,
+        })
+    );
+    assert_eq!(
+        decode(&Message::schedule_message_send(
+            "bob",
+            "later",
+            ScheduleTime::In(Duration::from_secs(60))
+        )),
+        Request::ScheduleMessageSend(ScheduleMessageSend {
+            recipient: "bob".to_string(),
+            body: "later".to_string(),
+            due: ScheduleTime::In(Duration::from_secs(60)),
+        })
+    );
+    assert_eq!(
+        decode(&Message::admin_clear_ip_ban(" 10.0.0.1 ")),
+        Request::AdminClearIpBan(AdminClearIpBan {
+            address: "10.0.0.1".parse().unwrap(),
+        })
+    );
+    assert_eq!(
+        decode(&Message::sync_request(7, 50)),
+        Request::SyncRequest(SyncRequest { cursor: 7, limit: 50 })
+    );
+
+    // both mute requests carry the same fields, the variant says which way
+    let mute = Mute {
+        username: "carol".to_string(),
+    };
+    assert_eq!(decode(&Message::mute_add("carol")), Request::MuteAdd(mute.clone()));
+    assert_eq!(decode(&Message::mute_remove("carol")), Request::MuteRemove(mute));
+    assert_eq!(
+        decode(&Message::subscribe_presence(&PresenceScope::Contacts)),
+        Request::SubscribePresence(PresenceScope::Contacts)
+    );
+    assert_eq!(decode(&Message::admin_server_stats()), Request::AdminServerStats);
+    assert_eq!(
+        decode(&Message::signing_key_set(Some(&[1; 32]))),
+        Request::SigningKeySet(SigningKeySet { key: Some([1; 32]) })
+    );
+    assert_eq!(
+        decode(&Message::signing_key_set(None)),
+        Request::SigningKeySet(SigningKeySet { key: None })
+    );
+}
+
+#[test]
+fn the_request_keeps_the_type_it_came_as() {
+    for message in [
+        Message::direct_message_send("bob", "hi"),
+        Message::mute_remove("carol"),
+        Message::message_edit(MessageId::new(3), "changed"),
+        Message::admin_server_stats(),
+    ] {
+        assert_eq!(decode(&message).message_type(), message.message_type());
+    }
+}
+
+#[test]
+fn bad_fields_are_an_error_and_replies_no_request() {
+    let error = Request::decode(&Message::admin_clear_ip_ban("not an address")).unwrap();
+    assert!(error.is_err());
+    let error = Request::decode(&MessageBuilder::new(MessageType::SyncRequest).build()).unwrap();
+    assert!(error.is_err());
+    let short_key = MessageBuilder::new(MessageType::SigningKeySet)
+        .with_field(vec![1; 31])
+        .build();
+    assert!(Request::decode(&short_key).unwrap().is_err());
+
+    assert!(Request::decode(&Message::ACK).is_none());
+    assert!(Request::decode(&Message::search_end(3)).is_none());
+}
+
+#[test]
+fn responses_encode_like_the_message_constructors() {
+    let id = MessageId::new(42);
+    assert_eq!(
+        Message::from(Response::MessageAck {
+            id,
+            client_id: Some("c1".to_string()),
+        }),
+        Message::message_ack_for(id, "c1")
+    );
+    assert_eq!(
+        Message::from(Response::MessageAck { id, client_id: None }),
+        Message::message_ack(id)
+    );
+    assert_eq!(Message::from(Response::SearchEnd { count: 3 }), Message::search_end(3));
+    assert_eq!(
+        Message::from(Response::Rejected {
+            request: MessageType::ScheduleCancel,
+            reason: "Unknown".to_string(),
+        }),
+        Message::rejected(MessageType::ScheduleCancel, "Unknown")
+    );
+    assert_eq!(Message::from(Response::Frame(Message::NACK)), Message::NACK);
+}
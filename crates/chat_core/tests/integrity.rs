@@ -0,0 +1,132 @@
+use chat_core::{
+    capability,
+    integrity::Integrity,
+    protocol::{Message, MessageType},
+    version::{FrameError, VersionRange},
+};
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Could not build runtime")
+        .block_on(future)
+}
+
+fn receive(mut bytes: &[u8], integrity: Integrity) -> Result<Message, FrameError> {
+    block_on(async {
+        assert!(Message::read_header_start(&mut bytes).await.unwrap());
+        Message::receive_using(&mut bytes, VersionRange::default(), integrity).await
+    })
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+#[test]
+fn xxh3_matches_the_reference_implementation() {
+    // from libxxhash, XXH3_64bits with the default secret, one per size class
+    let expected: [(usize, u64); 8] = [
+        (0, 0x2d06800538d394c2),
+        (3, 0x15f7093b173d005c),
+        (8, 0xdec6a9a43575982e),
+        (16, 0x7e484c18d74895d0),
+        (100, 0x8c97158042fbf926),
+        (200, 0x12fdb864685f344d),
+        (1000, 0x989765d0ea7a5ecd),
+        (2048, 0x19f6f9c987331373),
+    ];
+    for (len, hash) in expected {
+        let input = pattern(len);
+        assert_eq!(
+            Integrity::Xxh3.trailer([input.as_slice()]),
+            hash.to_be_bytes().to_vec(),
+            "length {}",
+            len
+        );
+    }
+}
+
+#[test]
+fn the_trailer_covers_the_field_data_only() {
+    let input = pattern(300);
+    let (first, second) = input.split_at(120);
+    for integrity in Integrity::ALL {
+        assert_eq!(
+            integrity.trailer([first, second]),
+            integrity.trailer([input.as_slice()])
+        );
+        assert_eq!(integrity.trailer([first, second]).len(), integrity.trailer_len());
+    }
+}
+
+#[test]
+fn every_algorithm_round_trips() {
+    let message = Message::motd(&"hello ".repeat(100));
+    for &integrity in Integrity::ALL {
+        let bytes = message.to_bytes_using(integrity);
+        assert_eq!(bytes.len(), message.wire_size() - 4 + integrity.trailer_len());
+        assert_eq!(
+            Message::from_bytes_using(&bytes, VersionRange::default(), integrity),
+            Ok(message.clone())
+        );
+        assert_eq!(receive(&bytes, integrity), Ok(message.clone()));
+
+        let mut sent = Vec::new();
+        block_on(message.send_using(&mut sent, integrity)).unwrap();
+        assert_eq!(sent, bytes);
+    }
+    assert_eq!(message.to_bytes_using(Integrity::Crc32), message.to_bytes());
+}
+
+#[test]
+fn a_frame_checked_with_another_algorithm_is_rejected() {
+    let message = Message::motd("hello");
+    for &sent in Integrity::ALL {
+        let bytes = message.to_bytes_using(sent);
+        for &expected in Integrity::ALL.iter().filter(|&&expected| expected != sent) {
+            let error = Message::from_bytes_using(&bytes, VersionRange::default(), expected).unwrap_err();
+            // a trailer of another length leaves the frame short or with bytes over
+            assert!(
+                matches!(error, FrameError::Invalid(_)),
+                "{} read as {}: {:?}",
+                sent,
+                expected,
+                error
+            );
+        }
+    }
+
+    // the stream reader takes the trailer length of the algorithm it expects, so it always gets to compare
+    let bytes = message.to_bytes_using(Integrity::Xxh3);
+    let error = receive(&bytes, Integrity::Crc32).unwrap_err();
+    assert!(error.is_integrity_failure(), "{:?}", error);
+    assert!(error.is_recoverable());
+}
+
+#[test]
+fn a_corrupted_xxh3_frame_is_an_integrity_failure() {
+    let mut bytes = Message::motd("hello").to_bytes_using(Integrity::Xxh3);
+    bytes[12] ^= 0x01;
+    let error = receive(&bytes, Integrity::Xxh3).unwrap_err();
+    assert_eq!(error, FrameError::Invalid("Invalid checksum".to_string()));
+    assert!(error.is_integrity_failure());
+}
+
+#[test]
+fn the_switch_frames_name_the_algorithm() {
+    for &integrity in Integrity::ALL {
+        assert_eq!(Integrity::parse(integrity.as_str()), Ok(integrity));
+        let select = Message::integrity_select(integrity);
+        assert!(select.is(MessageType::IntegritySelect));
+        assert_eq!(select.switches_integrity(), Some(integrity));
+        assert_eq!(
+            Message::integrity_selected(integrity).switches_integrity(),
+            Some(integrity)
+        );
+    }
+    assert!(Integrity::parse("md5").is_err());
+    assert_eq!(Message::motd("xxh3").switches_integrity(), None);
+    assert_eq!(Integrity::Crc32.capability(), None);
+    assert_eq!(Integrity::Xxh3.capability(), Some(capability::INTEGRITY_XXH3));
+}
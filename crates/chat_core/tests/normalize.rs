@@ -0,0 +1,53 @@
+use chat_core::{
+    normalize::{normalize_body, normalize_username},
+    protocol::Message,
+};
+
+#[test]
+fn windows_line_endings_are_dropped() {
+    assert_eq!(normalize_username("alice\r\n"), Ok("alice".to_string()));
+    assert_eq!(normalize_username(" alice \r"), Ok("alice".to_string()));
+    assert_eq!(
+        normalize_body("first line\r\nsecond line\r\n"),
+        Ok("first line\nsecond line".to_string())
+    );
+}
+
+#[test]
+fn composed_and_decomposed_names_are_the_same_key() {
+    let composed = normalize_username("\u{e9}mile").unwrap();
+    let decomposed = normalize_username("e\u{301}mile").unwrap();
+    assert_eq!(composed, decomposed);
+    assert_eq!(composed, "\u{e9}mile");
+    assert_eq!(normalize_body("cafe\u{301}"), Ok("caf\u{e9}".to_string()));
+}
+
+#[test]
+fn runs_of_control_characters_become_one_space() {
+    assert_eq!(normalize_username("al\u{0}\u{1b}ice"), Ok("al ice".to_string()));
+    assert_eq!(normalize_username("alice\tbob"), Ok("alice bob".to_string()));
+    // bodies keep their line breaks and tabs
+    assert_eq!(normalize_body("a\tb\nc\u{7}\u{8}d"), Ok("a\tb\nc d".to_string()));
+}
+
+#[test]
+fn nothing_left_after_normalizing_is_an_error() {
+    assert_eq!(normalize_username(""), Err("Username must not be empty".to_string()));
+    assert_eq!(
+        normalize_username(" \r\n\t"),
+        Err("Username must not be empty".to_string())
+    );
+    assert_eq!(
+        normalize_body("\r\n \u{0}"),
+        Err("Message must not be empty".to_string())
+    );
+}
+
+#[test]
+fn payload_fields_come_out_normalized() {
+    let message = Message::direct_message_send("bob \r\n", "hi\r\nthere ");
+    let payload = message.payload();
+    assert_eq!(payload.username_field(0), Ok("bob".to_string()));
+    assert_eq!(payload.body_field(1), Ok("hi\nthere".to_string()));
+    assert!(payload.username_field(2).is_err());
+}
@@ -0,0 +1,66 @@
+use chat_core::{
+    protocol::{Message, MessageType},
+    time_sync::TimeSample,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+fn at(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(1_700_000_000_000 + millis).unwrap()
+}
+
+#[test]
+fn symmetric_exchange_without_skew() {
+    // 20ms each way, the server takes 5ms to answer
+    let sample = TimeSample::from_exchange(at(0), at(20), at(25), at(45));
+
+    assert_eq!(sample.offset(), Duration::zero());
+    assert_eq!(sample.round_trip(), Duration::milliseconds(40));
+}
+
+#[test]
+fn server_ahead_of_client() {
+    // the server clock runs 10 seconds ahead
+    let sample = TimeSample::from_exchange(at(0), at(10_020), at(10_025), at(45));
+
+    assert_eq!(sample.offset(), Duration::seconds(10));
+    assert_eq!(sample.round_trip(), Duration::milliseconds(40));
+    assert_eq!(sample.server_time(at(100)), at(10_100));
+}
+
+#[test]
+fn server_behind_client() {
+    let sample = TimeSample::from_exchange(at(0), at(-2_990), at(-2_980), at(30));
+
+    assert_eq!(sample.offset(), Duration::seconds(-3));
+    assert_eq!(sample.round_trip(), Duration::milliseconds(20));
+}
+
+#[test]
+fn asymmetric_paths_split_the_difference() {
+    // 30ms out and 10ms back cannot be told apart from a 10ms offset
+    let sample = TimeSample::from_exchange(at(0), at(30), at(30), at(40));
+
+    assert_eq!(sample.offset(), Duration::milliseconds(10));
+    assert_eq!(sample.round_trip(), Duration::milliseconds(40));
+}
+
+#[test]
+fn round_trip_is_never_negative() {
+    // a server reporting more processing time than the whole exchange took
+    let sample = TimeSample::from_exchange(at(0), at(0), at(100), at(10));
+
+    assert_eq!(sample.round_trip(), Duration::zero());
+}
+
+#[test]
+fn reply_timestamps_round_trip_through_the_wire() {
+    let reply = Message::time_sync_reply(at(0), at(20), at(25));
+    let decoded = Message::from_bytes(&reply.to_bytes()).unwrap();
+
+    assert!(decoded.is(MessageType::TimeSyncReply));
+    let payload = decoded.payload();
+    assert_eq!(payload.timestamp_field(0), Ok(at(0)));
+    assert_eq!(payload.timestamp_field(1), Ok(at(20)));
+    assert_eq!(payload.timestamp_field(2), Ok(at(25)));
+    assert!(payload.timestamp_field(3).is_err());
+}
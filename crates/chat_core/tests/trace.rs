@@ -0,0 +1,76 @@
+use std::process::Command;
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageId, MessageType},
+    trace::{Direction, TraceRecord},
+};
+
+#[test]
+fn bodies_are_redacted_in_the_same_shape() {
+    let sent = TraceRecord::new(
+        Direction::Received,
+        Some("s1".into()),
+        &Message::direct_message_send("bob", " Lunch at 12? Café "),
+    );
+    let redacted = sent.redact_bodies();
+    assert!(redacted.is_redacted());
+    assert_eq!(redacted.session(), Some("s1"));
+    assert_eq!(redacted.timestamp(), sent.timestamp());
+    let payload = redacted.message().payload();
+    assert_eq!(payload.str_field(0), Ok("bob"));
+    assert_eq!(payload.str_field(1), Ok(" xxxxx xx 00? xxxx "));
+
+    // the relayed copy still matches the message it relays
+    let relayed = Message::direct_message_receive(
+        "alice",
+        " Lunch at 12? Café ",
+        chrono::Utc::now(),
+        MessageId::new(1),
+        None,
+    );
+    let relayed = TraceRecord::new(Direction::Sent, None, &relayed).redact_bodies();
+    assert_eq!(relayed.message().payload().str_field(1), payload.str_field(1));
+
+    // frames without a body stay as they were
+    let ack = TraceRecord::new(Direction::Sent, None, &Message::ACK);
+    assert_eq!(ack.redact_bodies(), ack);
+}
+
+#[test]
+fn the_binary_writes_a_redacted_capture() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("chat_rs_trace_{}.jsonl", std::process::id()));
+    let redacted_path = dir.join(format!("chat_rs_trace_redacted_{}.jsonl", std::process::id()));
+    let records = [
+        TraceRecord::new(
+            Direction::Received,
+            Some("s1".into()),
+            &Message::direct_message_send("bob", "the launch code is 1234"),
+        ),
+        TraceRecord::new(Direction::Sent, Some("s2".into()), &Message::ACK),
+    ];
+    let trace: String = records.iter().map(|record| format!("{}\n", record.to_json())).collect();
+    std::fs::write(&path, trace).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chat_trace"))
+        .arg(&path)
+        .args(["--session", "s1", "--redact-bodies"])
+        .arg(&redacted_path)
+        .output()
+        .unwrap();
+    let redacted = std::fs::read_to_string(&redacted_path);
+    std::fs::remove_file(&path).ok();
+    std::fs::remove_file(&redacted_path).ok();
+
+    assert!(output.status.success(), "{:?}", output);
+    let redacted = redacted.unwrap();
+    assert!(
+        !redacted.contains("launch") && !redacted.contains("1234"),
+        "{}",
+        redacted
+    );
+    let record = TraceRecord::from_json(&JsonValue::parse(redacted.trim()).unwrap()).unwrap();
+    assert!(record.message().is(MessageType::DirectMessageSend));
+    assert_eq!(record.message().payload().str_field(1), Ok("xxx xxxxxx xxxx xx 0000"));
+}
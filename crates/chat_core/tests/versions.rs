@@ -0,0 +1,90 @@
+use chat_core::{
+    protocol::{DisconnectReason, Message, MessageType},
+    version::{FrameError, VersionRange, OLDEST_VERSION},
+};
+
+// a DirectMessageSend to bob saying hi, as a v1 build wrote it
+const V1_FRAME: &[u8] = &[
+    0x59, 0x18, // header start
+    0x01, // version
+    0x41, // type
+    0x00, 0x00, 0x00, 0x02, // field count
+    0x00, 0x00, 0x00, 0x03, b'b', b'o', b'b', // receiver
+    0x00, 0x00, 0x00, 0x02, b'h', b'i', // body
+    0x94, 0x8c, 0x13, 0x78, // crc32 of the field data
+];
+
+// what a build that writes v2 and still reads v1 accepts
+const V2_BUILD: VersionRange = VersionRange::new(1, 2);
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Could not build runtime")
+        .block_on(future)
+}
+
+fn receive_with(mut bytes: &[u8], versions: VersionRange) -> Result<Message, FrameError> {
+    block_on(async {
+        assert!(Message::read_header_start(&mut bytes).await.unwrap());
+        Message::receive_with(&mut bytes, versions).await
+    })
+}
+
+#[test]
+fn a_v1_frame_decodes_in_a_v2_build() {
+    for message in [
+        Message::from_bytes_with(V1_FRAME, V2_BUILD).unwrap(),
+        receive_with(V1_FRAME, V2_BUILD).unwrap(),
+    ] {
+        assert!(message.is(MessageType::DirectMessageSend));
+        assert_eq!(message.payload().str_field(0), Ok("bob"));
+        assert_eq!(message.payload().str_field(1), Ok("hi"));
+        // normalized to what the build speaks
+        assert_eq!(message.version(), 2);
+    }
+}
+
+#[test]
+fn the_current_build_reads_its_own_frames() {
+    let message = Message::from_bytes(V1_FRAME).unwrap();
+    assert_eq!(message, Message::direct_message_send("bob", "hi"));
+    assert_eq!(message.to_bytes(), V1_FRAME);
+}
+
+#[test]
+fn versions_outside_the_range_are_refused() {
+    let v2_only = VersionRange::new(2, 2);
+    let refused = Err(FrameError::UnsupportedVersion {
+        version: 1,
+        supported: v2_only,
+    });
+    assert_eq!(Message::from_bytes_with(V1_FRAME, v2_only), refused);
+    assert_eq!(receive_with(V1_FRAME, v2_only), refused);
+
+    // a version nobody wrote an adapter for is refused even inside the range
+    let mut v2_frame = V1_FRAME.to_vec();
+    v2_frame[2] = 0x02;
+    assert_eq!(
+        Message::from_bytes_with(&v2_frame, V2_BUILD),
+        Err(FrameError::UnsupportedVersion {
+            version: 2,
+            supported: V2_BUILD,
+        })
+    );
+}
+
+#[test]
+fn the_notice_is_readable_by_the_oldest_peer() {
+    let notice = V2_BUILD.unsupported_notice(7);
+    assert_eq!(notice.version(), OLDEST_VERSION);
+
+    let decoded = Message::from_bytes_with(&notice.to_bytes(), VersionRange::new(1, 1)).unwrap();
+    assert_eq!(
+        decoded.disconnect_reason(),
+        Ok((
+            DisconnectReason::ProtocolError,
+            "Unsupported protocol version 7, this side speaks versions 1 to 2".to_string()
+        ))
+    );
+}
@@ -0,0 +1,193 @@
+use chat_core::{
+    protocol::{Message, MessageBuilder, MessageType, MAX_FIELD_COUNT, MAX_FIELD_SIZE},
+    version::FrameError,
+};
+use proptest::{collection::vec, prelude::*};
+
+// header start, version, type and field count precede the fields
+const FIELDS_OFFSET: usize = 8;
+
+fn message_type() -> impl Strategy<Value = MessageType> {
+    prop::sample::select(MessageType::ALL)
+}
+
+fn field() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => vec(any::<u8>(), 0..64),
+        2 => "\\PC{0,32}".prop_map(String::into_bytes),
+        1 => Just(Vec::new()),
+        1 => Just(vec![0xff; MAX_FIELD_SIZE as usize]),
+    ]
+}
+
+fn fields() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    vec(field(), 0..=MAX_FIELD_COUNT as usize)
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (message_type(), fields())
+        .prop_map(|(message_type, fields)| MessageBuilder::new(message_type).with_fields(fields).build())
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Could not build runtime")
+        .block_on(future)
+}
+
+fn send(message: &Message) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    block_on(message.send(&mut bytes)).expect("sending into a Vec cannot fail");
+    bytes
+}
+
+fn receive(mut bytes: &[u8]) -> Result<Message, String> {
+    block_on(async {
+        match Message::read_header_start(&mut bytes).await {
+            Ok(true) => Message::receive(&mut bytes).await.map_err(String::from),
+            Ok(false) => Err("Invalid header start".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn codec_and_send_agree(message in message()) {
+        prop_assert_eq!(send(&message), message.to_bytes());
+    }
+
+    #[test]
+    fn encode_decode_is_identity(message in message()) {
+        let bytes = message.to_bytes();
+
+        let decoded = Message::from_bytes(&bytes).expect("valid frame");
+        prop_assert_eq!(&decoded, &message);
+        prop_assert_eq!(decoded.payload().get_data(), message.payload().get_data());
+
+        let received = receive(&bytes).expect("valid frame");
+        prop_assert_eq!(&received, &message);
+    }
+
+    #[test]
+    fn wire_size_matches_encoding(message in message()) {
+        prop_assert_eq!(message.wire_size(), message.to_bytes().len());
+        prop_assert_eq!(message.wire_size(), send(&message).len());
+    }
+
+    #[test]
+    fn truncated_encoding_errors(message in message(), cut in any::<prop::sample::Index>()) {
+        let bytes = message.to_bytes();
+        let truncated = &bytes[..cut.index(bytes.len())];
+
+        prop_assert!(Message::from_bytes(truncated).is_err());
+        prop_assert!(receive(truncated).is_err());
+    }
+
+    #[test]
+    fn checksum_detects_payload_mutation(
+        message_type in message_type(),
+        fields in vec(vec(any::<u8>(), 1..64), 1..=MAX_FIELD_COUNT as usize),
+        position in any::<prop::sample::Index>(),
+        flip in 1..=u8::MAX,
+    ) {
+        let message = MessageBuilder::new(message_type).with_fields(fields.clone()).build();
+        let mut bytes = message.to_bytes();
+
+        // only touch field data, length prefixes are covered by the truncation property
+        let mut data_positions = Vec::new();
+        let mut offset = FIELDS_OFFSET;
+        for field in &fields {
+            offset += 4;
+            data_positions.extend(offset..offset + field.len());
+            offset += field.len();
+        }
+        let position = data_positions[position.index(data_positions.len())];
+        bytes[position] ^= flip;
+
+        prop_assert!(Message::from_bytes(&bytes).is_err());
+        prop_assert!(receive(&bytes).is_err());
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in vec(any::<u8>(), 0..256)) {
+        let decoded = Message::from_bytes(&bytes);
+        let received = receive(&bytes);
+
+        if let Ok(message) = decoded {
+            prop_assert_eq!(received, Ok(message));
+        }
+    }
+}
+
+#[test]
+fn oversized_fields_are_rejected() {
+    let message = MessageBuilder::new(MessageType::DirectMessageSend)
+        .with_field(vec![0; MAX_FIELD_SIZE as usize + 1])
+        .build();
+    let bytes = message.to_bytes();
+
+    assert!(Message::from_bytes(&bytes).is_err());
+    assert!(receive(&bytes).is_err());
+}
+
+#[test]
+fn too_many_fields_are_rejected() {
+    let message = MessageBuilder::new(MessageType::DirectMessageSend)
+        .with_fields(vec![Vec::new(); MAX_FIELD_COUNT as usize + 1])
+        .build();
+    let bytes = message.to_bytes();
+
+    assert!(Message::from_bytes(&bytes).is_err());
+    assert!(receive(&bytes).is_err());
+}
+
+#[test]
+fn a_frame_cut_short_reads_as_closed() {
+    let bytes = Message::motd("hello").to_bytes();
+    let mut cut = &bytes[2..bytes.len() - 1];
+    let error = block_on(Message::receive(&mut cut)).unwrap_err();
+    assert!(matches!(error, FrameError::Closed(_)), "{:?}", error);
+    assert!(!error.is_recoverable());
+
+    let mut corrupted = Message::motd("hello").to_bytes();
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    let error = block_on(Message::receive(&mut &corrupted[2..])).unwrap_err();
+    assert_eq!(error, FrameError::Invalid("Invalid checksum".to_string()));
+    assert!(error.is_recoverable());
+}
+
+#[test]
+fn resync_finds_the_next_header() {
+    let frame = Message::motd("hello").to_bytes();
+    let bytes = [b"garbage".as_slice(), &frame].concat();
+
+    let mut stream = bytes.as_slice();
+    assert!(block_on(Message::resync(&mut stream, 64)).unwrap());
+    assert!(block_on(Message::receive(&mut stream)).unwrap().is(MessageType::Motd));
+
+    // the limit counts every byte looked at, the header start included
+    let mut stream = bytes.as_slice();
+    assert!(!block_on(Message::resync(&mut stream, 8)).unwrap());
+    assert!(block_on(Message::resync(&mut b"no header".as_slice(), 64)).is_err());
+}
+
+#[test]
+fn the_end_of_the_stream_between_frames_is_a_peer_that_left() {
+    let bytes = Message::motd("hello").to_bytes();
+    let mut stream = bytes.as_slice();
+    assert_eq!(block_on(Message::read_frame_start(&mut stream)), Ok(true));
+    assert!(block_on(Message::receive(&mut stream)).is_ok());
+    assert_eq!(
+        block_on(Message::read_frame_start(&mut stream)),
+        Err(FrameError::PeerClosed)
+    );
+
+    // half a header start is a frame that was cut off
+    let error = block_on(Message::read_frame_start(&mut &bytes[..1])).unwrap_err();
+    assert!(matches!(error, FrameError::Closed(_)), "{:?}", error);
+    assert!(!FrameError::PeerClosed.is_recoverable());
+}
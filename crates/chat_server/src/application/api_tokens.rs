@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use chat_core::{auth::hash_api_token, json::JsonValue, protocol::ApiTokenInfo};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::export;
+
+pub const API_TOKENS_FILE: &str = "api_tokens.json";
+pub const MAX_API_TOKENS: usize = 10;
+pub const MAX_TOKEN_LABEL: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ApiToken {
+    id: u64,
+    user: String,
+    label: String,
+    // the token is never stored, a lost one is revoked and made anew
+    hash: String,
+    created: DateTime<Utc>,
+}
+
+// tokens of every user, written through to the data directory on every change
+#[derive(Debug)]
+pub struct ApiTokens {
+    tokens: BTreeMap<u64, ApiToken>,
+    next_id: u64,
+    // nothing is persisted without a data directory
+    file: Option<PathBuf>,
+}
+
+impl ApiToken {
+    fn info(&self) -> ApiTokenInfo {
+        ApiTokenInfo {
+            id: self.id,
+            label: self.label.clone(),
+            created: self.created,
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("id", self.id)
+            .with("user", self.user.as_str())
+            .with("label", self.label.as_str())
+            .with("hash", self.hash.as_str())
+            .with("created", self.created.to_rfc3339())
+    }
+
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let field = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| format!("API token without '{}'", key))
+        };
+        let created = field("created")?;
+        Ok(Self {
+            id: value
+                .get("id")
+                .and_then(JsonValue::as_u64)
+                .ok_or("API token without 'id'")?,
+            user: field("user")?.to_string(),
+            label: field("label")?.to_string(),
+            hash: field("hash")?.to_string(),
+            created: DateTime::parse_from_rfc3339(created)
+                .map_err(|_| format!("Invalid creation time '{}'", created))?
+                .with_timezone(&Utc),
+        })
+    }
+}
+
+impl Default for ApiTokens {
+    fn default() -> Self {
+        Self {
+            tokens: BTreeMap::new(),
+            next_id: 1,
+            file: None,
+        }
+    }
+}
+
+impl ApiTokens {
+    // what a previous run left behind, a missing file means no tokens
+    pub fn load(&mut self, path: PathBuf) -> Result<usize, String> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        if let Some(content) = content {
+            let problem = |e: String| format!("{}: {}", path.display(), e);
+            let document = JsonValue::parse(&content).map_err(problem)?;
+            for value in document.get("tokens").and_then(JsonValue::as_array).unwrap_or_default() {
+                let token = ApiToken::from_json(value).map_err(problem)?;
+                self.next_id = self.next_id.max(token.id + 1);
+                self.tokens.insert(token.id, token);
+            }
+        }
+        self.file = Some(path);
+        Ok(self.tokens.len())
+    }
+
+    // the token goes back to the user once and is forgotten here
+    pub fn create(&mut self, user: &str, label: &str, now: DateTime<Utc>) -> Result<(ApiTokenInfo, String), String> {
+        let label = label.trim();
+        if label.is_empty() || label.chars().count() > MAX_TOKEN_LABEL {
+            return Err(format!("A token label has 1 to {} characters", MAX_TOKEN_LABEL));
+        }
+        if self.list_for(user).len() >= MAX_API_TOKENS {
+            return Err(format!("At most {} tokens per user", MAX_API_TOKENS));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let api_token = ApiToken {
+            id,
+            user: user.to_string(),
+            label: label.to_string(),
+            hash: hash_api_token(&secret),
+            created: now,
+        };
+        let info = api_token.info();
+        self.tokens.insert(id, api_token);
+        if let Err(e) = self.save() {
+            self.tokens.remove(&id);
+            return Err(e);
+        }
+        Ok((info, secret))
+    }
+
+    // only the owner may revoke, someone else's id looks like one that does not exist
+    pub fn revoke(&mut self, user: &str, id: u64) -> Result<(), String> {
+        match self.tokens.get(&id) {
+            Some(token) if token.user == user => {}
+            _ => return Err(format!("No API token #{}", id)),
+        }
+        let revoked = self.tokens.remove(&id).expect("Checked above");
+        if let Err(e) = self.save() {
+            self.tokens.insert(id, revoked);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // oldest first
+    pub fn list_for(&self, user: &str) -> Vec<ApiTokenInfo> {
+        self.tokens
+            .values()
+            .filter(|token| token.user == user)
+            .map(ApiToken::info)
+            .collect()
+    }
+
+    // the owner and the id of the token, the hashes are compared so the token never has to be kept
+    #[cfg_attr(not(feature = "http-bridge"), allow(dead_code))]
+    pub fn authenticate(&self, secret: &str) -> Option<(String, u64)> {
+        let hash = hash_api_token(secret);
+        self.tokens
+            .values()
+            .find(|token| token.hash == hash)
+            .map(|token| (token.user.clone(), token.id))
+    }
+
+    #[cfg_attr(not(feature = "http-bridge"), allow(dead_code))]
+    pub fn contains(&self, id: u64) -> bool {
+        self.tokens.contains_key(&id)
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let mut changed = false;
+        for token in self.tokens.values_mut().filter(|token| token.user == old) {
+            token.user = new.to_string();
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = self.save() {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let document = JsonValue::object().with(
+            "tokens",
+            JsonValue::Array(self.tokens.values().map(ApiToken::to_json).collect()),
+        );
+        export::write_document(path, &document).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
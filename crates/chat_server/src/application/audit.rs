@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    timestamp: DateTime<Utc>,
+    actor: String,
+    action: String,
+    detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+}
+
+impl AuditEntry {
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+}
+
+impl AuditLog {
+    pub fn record(&mut self, actor: &str, action: &str, detail: String) -> &AuditEntry {
+        tracing::info!(target: "audit", actor, action, "{}", detail);
+
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(AuditEntry {
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            detail,
+        });
+        self.entries.back().expect("just pushed")
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn entries(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.entries.iter()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::Semaphore;
+
+pub const AUTH_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how many argon2 runs go at once and how long a login waits for its turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthLimits {
+    pub concurrency: usize,
+    pub queue_timeout: Duration,
+}
+
+// argon2 runs on the blocking pool and at most `concurrency` at a time, so a reconnect storm after a restart
+// queues up here instead of starving the runtime that keeps the heartbeats going
+#[derive(Debug)]
+pub struct AuthLimiter {
+    limits: AuthLimits,
+    permits: Arc<Semaphore>,
+    waiting: AtomicU64,
+    rejected: AtomicU64,
+}
+
+// the queue did not move in time, the client is told when to try again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthBusy {
+    pub retry_after: u64,
+}
+
+impl Default for AuthLimits {
+    fn default() -> Self {
+        Self {
+            concurrency: default_concurrency(),
+            queue_timeout: AUTH_QUEUE_TIMEOUT,
+        }
+    }
+}
+
+// one argon2 run per core
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+impl Default for AuthLimiter {
+    fn default() -> Self {
+        Self::new(AuthLimits::default())
+    }
+}
+
+impl AuthLimiter {
+    pub fn new(limits: AuthLimits) -> Self {
+        Self {
+            limits,
+            permits: Arc::new(Semaphore::new(limits.concurrency)),
+            waiting: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn run<T, F>(&self, work: F) -> Result<T, AuthBusy>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = tokio::time::timeout(self.limits.queue_timeout, Arc::clone(&self.permits).acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        let Ok(Ok(permit)) = permit else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(AuthBusy {
+                retry_after: self.limits.queue_timeout.as_secs().max(1),
+            });
+        };
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            work()
+        })
+        .await;
+        // a blocking task cannot be cancelled, so the only error is a panic in the work itself
+        Ok(result.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())))
+    }
+
+    pub fn limits(&self) -> AuthLimits {
+        self.limits
+    }
+
+    // logins waiting for their turn right now
+    pub fn queue_depth(&self) -> u64 {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    // logins turned away since startup because their turn did not come in time
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    // takes every slot until the permit is dropped, so tests can fill the queue at will
+    #[cfg(feature = "test-util")]
+    pub async fn occupy(&self) -> tokio::sync::OwnedSemaphorePermit {
+        Arc::clone(&self.permits)
+            .acquire_many_owned(self.limits.concurrency as u32)
+            .await
+            .expect("The auth limiter is never closed")
+    }
+}
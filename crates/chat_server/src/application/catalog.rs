@@ -0,0 +1,213 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+// one `<language>.toml` file per language, inside the data directory
+pub const LANGUAGE_DIR: &str = "lang";
+// what the built-in texts are written in, it needs no file
+pub const BUILT_IN_LANGUAGE: &str = "en";
+const MAX_LANGUAGE_LENGTH: usize = 35;
+
+// keyed by error code or notice kind, `{name}` is replaced by the parameter of that name
+const BUILT_IN: &[(&str, &str)] = &[
+    ("user_not_found", "User {user} does not exist"),
+    ("recipient_offline", "{user} is not online"),
+    ("offline_queue_full", "Offline queue of {user} is full"),
+    ("invalid_credentials", "Invalid username or password"),
+    (
+        "password_login_disabled",
+        "Password login is disabled, use a client that answers the login challenge",
+    ),
+    ("user_exists", "User already exists"),
+    ("server_busy", "The server is busy, try again in {seconds} seconds"),
+    ("session_takeover", "Your session was taken over by a new login"),
+    (
+        "session_takeover_from",
+        "Your session was taken over by a new login from {address}",
+    ),
+];
+
+// the texts clients are sent, per language, a key a catalog does not translate falls back to the default
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    default_language: Option<String>,
+    languages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    // a missing directory leaves the built-in texts
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let mut catalog = Self::default();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(catalog),
+            Err(e) => return Err(format!("Could not read {}: {}", dir.display(), e)),
+        };
+
+        for entry in entries {
+            let path = entry
+                .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?
+                .path();
+            if path.extension().map_or(true, |extension| extension != "toml") {
+                continue;
+            }
+            let language = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .filter(|stem| is_language_tag(stem))
+                .ok_or_else(|| format!("{} is not named after a language", path.display()))?;
+            let document =
+                fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            let texts = parse(&document).map_err(|e| format!("{}: {}", path.display(), e))?;
+            catalog = catalog.with_language(language, texts);
+        }
+        Ok(catalog)
+    }
+
+    pub fn with_language(mut self, language: &str, texts: HashMap<String, String>) -> Self {
+        self.languages.insert(language.to_ascii_lowercase(), texts);
+        self
+    }
+
+    // None, or a language without a catalog, answers with the built-in texts
+    pub fn set_default_language(&mut self, language: Option<String>) {
+        self.default_language = language.map(|language| language.to_ascii_lowercase());
+    }
+
+    pub fn has_language(&self, language: &str) -> bool {
+        let language = language.to_ascii_lowercase();
+        language == BUILT_IN_LANGUAGE || self.languages.contains_key(&language)
+    }
+
+    // the requested language, then the server default, then the built-in text
+    pub fn text(&self, language: Option<&str>, key: &str, params: &[(&str, &str)]) -> String {
+        let text = match language.map(|language| (language, self.lookup(language, key))) {
+            Some((_, Some(text))) => Some(text),
+            // asking for the built-in language skips the default
+            Some((language, None)) if primary(language).eq_ignore_ascii_case(BUILT_IN_LANGUAGE) => None,
+            _ => self
+                .default_language
+                .as_deref()
+                .and_then(|language| self.lookup(language, key)),
+        };
+        match text {
+            Some(text) => interpolate(text, params),
+            None => built_in(key, params),
+        }
+    }
+
+    // `de-AT` falls back to `de` before anything else is tried
+    fn lookup(&self, language: &str, key: &str) -> Option<&str> {
+        let language = language.to_ascii_lowercase();
+        let text = [language.as_str(), primary(&language)]
+            .into_iter()
+            .find_map(|language| self.languages.get(language)?.get(key));
+        text.map(String::as_str)
+    }
+}
+
+// what the server says without a catalog, also what it logs and counts
+pub fn built_in(key: &str, params: &[(&str, &str)]) -> String {
+    match BUILT_IN.iter().find(|(built_in, _)| *built_in == key) {
+        Some((_, text)) => interpolate(text, params),
+        None => key.to_string(),
+    }
+}
+
+// BCP 47 shaped, letters, digits and dashes
+pub fn is_language_tag(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_LANGUAGE_LENGTH
+        && value
+            .split('-')
+            .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_alphanumeric()))
+}
+
+fn primary(language: &str) -> &str {
+    language.split('-').next().unwrap_or(language)
+}
+
+// placeholders without a parameter are left as they are, so a typo in a catalog shows up in the text
+fn interpolate(text: &str, params: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let param = tail.find('}').and_then(|end| {
+            params
+                .iter()
+                .find(|(name, _)| *name == &tail[1..end])
+                .map(|(_, value)| (*value, end))
+        });
+        match param {
+            Some((value, end)) => {
+                result.push_str(value);
+                rest = &tail[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &tail[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// the part of TOML a flat table of strings needs: `key = "text"` lines, comments and blank lines
+pub fn parse(document: &str) -> Result<HashMap<String, String>, String> {
+    let mut texts = HashMap::new();
+
+    for (index, line) in document.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(format!("Line {}: tables are not supported", index + 1));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Line {}: expected 'key = \"text\"'", index + 1))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+        {
+            return Err(format!("Line {}: invalid key '{}'", index + 1, key));
+        }
+        let text = parse_string(value.trim()).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+        if texts.insert(key.to_string(), text).is_some() {
+            return Err(format!("Line {}: '{}' is defined twice", index + 1, key));
+        }
+    }
+
+    Ok(texts)
+}
+
+// a basic string, with a comment allowed after it
+fn parse_string(value: &str) -> Result<String, String> {
+    let mut chars = value.strip_prefix('"').ok_or("expected a quoted text")?.chars();
+    let mut text = String::new();
+    loop {
+        match chars.next().ok_or("unterminated text")? {
+            '"' => break,
+            '\\' => match chars.next().ok_or("unterminated text")? {
+                '"' => text.push('"'),
+                '\\' => text.push('\\'),
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                other => return Err(format!("unknown escape '\\{}'", other)),
+            },
+            other => text.push(other),
+        }
+    }
+
+    let rest = chars.as_str().trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected '{}' after the text", rest));
+    }
+    Ok(text)
+}
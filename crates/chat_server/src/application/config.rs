@@ -0,0 +1,926 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chat_core::{
+    constants::PORT,
+    integrity::Integrity,
+    protocol::MAX_FIELD_SIZE,
+    version::{VersionRange, OLDEST_VERSION, VERSION},
+};
+
+use super::{
+    auth_limit::{self, AuthLimits, AUTH_QUEUE_TIMEOUT},
+    catalog::{self, Catalog, BUILT_IN_LANGUAGE, LANGUAGE_DIR},
+    data_dir,
+    dedup::{DEDUP_CAPACITY, DEDUP_TTL},
+    export,
+    flood::{FloodLimits, FLOOD_COOLDOWN, MAX_BYTES_PER_SECOND, MAX_FRAMES_PER_SECOND},
+    ip_guard::{ViolationLimits, IP_BAN_DURATION, VIOLATION_DECAY, VIOLATION_THRESHOLD},
+    offline::{OfflineWebhooks, OFFLINE_TTL},
+    permissions::AccessPresets,
+    presence::{PresenceTiming, PRESENCE_INTERVAL, PRESENCE_WINDOW},
+    schedule::{ScheduleLimits, MAX_PENDING_SCHEDULED, MAX_SCHEDULE_HORIZON},
+    server::{
+        Server, ACCEPT_BACKOFF, DISCONNECT_TIMEOUT, HANDSHAKE_TIMEOUT, HEARTBEAT_GRACE, HEARTBEAT_INTERVAL,
+        MAX_INTEGRITY_FAILURES, MAX_MISSED_HEARTBEATS, MAX_SESSION_AGE, OFFERED_INTEGRITY, WRITE_TIMEOUT,
+    },
+    session::TakeoverPolicy,
+    store::{DeletedHistory, EDIT_WINDOW, MAX_MESSAGE_TTL},
+    transfers::{MAX_CHUNK_SIZE, MAX_FILE_SIZE},
+    DATA_DIR, SERVER_NAME,
+};
+#[cfg(feature = "webhook")]
+use super::{webhook::WebhookPlugin, WebhookUrl};
+
+// values of keys containing one of these never show up in the logs
+const SECRET_MARKERS: [&str; 3] = ["PASSWORD", "SECRET", "TOKEN"];
+// TENANT_<NAME>_<KEY> sets KEY for one tenant only
+const TENANT_PREFIX: &str = "TENANT_";
+
+// everything the server reads from the environment, parsed but not yet checked
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    users_file: Option<PathBuf>,
+    access_presets_file: Option<PathBuf>,
+    trace_file: Option<PathBuf>,
+    // the socket `server ctl` talks to, on unless switched off
+    control_socket: Option<bool>,
+    // host:port of the http bridge, needs a server built with the http-bridge feature
+    http_bridge: Option<SocketAddr>,
+    // 0 keeps authenticated sessions alive for as long as they are connected
+    session_max_age: Option<u64>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_grace: Option<Duration>,
+    heartbeat_max_misses: Option<u32>,
+    handshake_timeout: Option<Duration>,
+    disconnect_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    accept_backoff: Option<Duration>,
+    takeover_policy: Option<TakeoverPolicy>,
+    edit_window: Option<Duration>,
+    deleted_history: Option<DeletedHistory>,
+    max_message_ttl: Option<Duration>,
+    rename_grace: Option<Duration>,
+    dedup_capacity: Option<usize>,
+    dedup_ttl: Option<Duration>,
+    schedule_max_pending: Option<usize>,
+    schedule_max_horizon: Option<Duration>,
+    max_file_size: Option<u64>,
+    max_chunk_size: Option<u64>,
+    plain_auth: Option<bool>,
+    offline_queue: Option<bool>,
+    offline_ttl: Option<Duration>,
+    conceal_users: Option<bool>,
+    // guests may stay to listen, for announcement servers
+    listen_only: Option<bool>,
+    flood_max_frames: Option<u64>,
+    flood_max_bytes: Option<u64>,
+    flood_cooldown: Option<Duration>,
+    ip_ban_threshold: Option<u32>,
+    ip_ban_decay: Option<Duration>,
+    ip_ban_duration: Option<Duration>,
+    // argon2 runs at once, one per core unless set
+    auth_concurrency: Option<usize>,
+    auth_queue_timeout: Option<Duration>,
+    server_name: Option<String>,
+    // for clients that do not ask for a language, needs a catalog in the data directory unless it is the built-in one
+    default_language: Option<String>,
+    presence: Option<bool>,
+    presence_interval: Option<Duration>,
+    presence_window: Option<Duration>,
+    // raising it turns clients away that speak an older protocol version
+    oldest_protocol_version: Option<u8>,
+    // what clients may select besides crc32, none belongs only behind a transport that checks the bytes, like TLS
+    frame_integrity: Option<Vec<Integrity>>,
+    // 0 keeps sessions connected however many of their frames fail the check
+    max_integrity_failures: Option<u32>,
+    // each with users, sessions and data of its own, the settings above are what they share unless overridden
+    tenants: Vec<(String, ServerConfig)>,
+    // direct messages are posted here, needs a server built with the webhook feature
+    webhook_url: Option<String>,
+    // users may point a webhook at these hosts and their subdomains, needs the webhook feature too
+    webhook_domains: Option<Vec<String>>,
+    // whether the post carries the whole message or only the snippet
+    webhook_bodies: Option<bool>,
+    // values that did not parse, reported together with everything validate finds
+    problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+
+    // unknown keys are ignored, the environment holds much more than the server's settings
+    pub fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let vars: Vec<(String, String)> = vars
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.as_ref().trim().to_string()))
+            .collect();
+        let mut config = Self::default();
+        for (key, value) in &vars {
+            config.set(key, value);
+        }
+        if let Some((_, names)) = vars.iter().find(|(key, _)| key == "TENANTS") {
+            config.set_tenants(names, &vars);
+        }
+        config
+    }
+
+    // a tenant starts from everything set for the process and lives in its own directory below DATA_DIR
+    fn set_tenants(&mut self, names: &str, vars: &[(String, String)]) {
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if let Err(e) = validate_tenant_name(name) {
+                self.problems.push(format!("TENANTS: {}", e));
+                continue;
+            }
+            if self.tenants.iter().any(|(known, _)| known == name) {
+                self.problems.push(format!("TENANTS: '{}' is listed twice", name));
+                continue;
+            }
+
+            let prefix = format!("{}{}_", TENANT_PREFIX, name.to_ascii_uppercase().replace('-', "_"));
+            let mut tenant = Self {
+                data_dir: Some(self.data_dir().join(name)),
+                ..Self::default()
+            };
+            for (key, value) in vars.iter().filter(|(key, _)| key != "DATA_DIR") {
+                tenant.set(key, value);
+            }
+            for (key, value) in vars
+                .iter()
+                .filter_map(|(key, value)| Some((key.strip_prefix(&prefix)?, value)))
+            {
+                tenant.set(key, value);
+            }
+            self.tenants.push((name.to_string(), tenant));
+        }
+        if self.tenants.is_empty() && self.problems.is_empty() {
+            self.problems
+                .push(format!("TENANTS: expected a list of tenant names, got '{}'", names));
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let path = || Ok(PathBuf::from(value));
+        let seconds = || {
+            value
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("expected seconds, got '{}'", value))
+        };
+        let millis = || {
+            value
+                .parse()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("expected milliseconds, got '{}'", value))
+        };
+
+        let result = match key {
+            "PORT" => value
+                .parse()
+                .map(|port| self.port = Some(port))
+                .map_err(|_| format!("expected a port between 1 and 65535, got '{}'", value)),
+            "DATA_DIR" => path().map(|path| self.data_dir = Some(path)),
+            "USERS_FILE" => path().map(|path| self.users_file = Some(path)),
+            "ACCESS_PRESETS_FILE" => path().map(|path| self.access_presets_file = Some(path)),
+            "TRACE_FILE" => path().map(|path| self.trace_file = Some(path)),
+            "CONTROL_SOCKET" => parse_switch(value).map(|on| self.control_socket = Some(on)),
+            "HTTP_BRIDGE" => value
+                .parse()
+                .map(|addr| self.http_bridge = Some(addr))
+                .map_err(|_| format!("expected an address like 127.0.0.1:8080, got '{}'", value)),
+            "SESSION_MAX_AGE" => seconds().map(|age| self.session_max_age = Some(age.as_secs())),
+            "HEARTBEAT_INTERVAL" => seconds().map(|interval| self.heartbeat_interval = Some(interval)),
+            "HEARTBEAT_GRACE" => seconds().map(|grace| self.heartbeat_grace = Some(grace)),
+            "HEARTBEAT_MAX_MISSES" => parse(value, "a number").map(|misses| self.heartbeat_max_misses = Some(misses)),
+            "HANDSHAKE_TIMEOUT" => seconds().map(|timeout| self.handshake_timeout = Some(timeout)),
+            "DISCONNECT_TIMEOUT" => seconds().map(|timeout| self.disconnect_timeout = Some(timeout)),
+            "WRITE_TIMEOUT" => seconds().map(|timeout| self.write_timeout = Some(timeout)),
+            "ACCEPT_BACKOFF_MS" => millis().map(|backoff| self.accept_backoff = Some(backoff)),
+            "SESSION_TAKEOVER" => TakeoverPolicy::parse(value).map(|policy| self.takeover_policy = Some(policy)),
+            "EDIT_WINDOW" => seconds().map(|window| self.edit_window = Some(window)),
+            "HISTORY_DELETIONS" => DeletedHistory::parse(value).map(|history| self.deleted_history = Some(history)),
+            "MAX_MESSAGE_TTL" => seconds().map(|ttl| self.max_message_ttl = Some(ttl)),
+            "RENAME_GRACE" => seconds().map(|grace| self.rename_grace = Some(grace)),
+            "DEDUP_CAPACITY" => parse(value, "a number").map(|capacity| self.dedup_capacity = Some(capacity)),
+            "DEDUP_TTL" => seconds().map(|ttl| self.dedup_ttl = Some(ttl)),
+            "SCHEDULE_MAX_PENDING" => parse(value, "a number").map(|pending| self.schedule_max_pending = Some(pending)),
+            "SCHEDULE_MAX_HORIZON" => seconds().map(|horizon| self.schedule_max_horizon = Some(horizon)),
+            "MAX_FILE_SIZE" => parse(value, "bytes").map(|size| self.max_file_size = Some(size)),
+            "MAX_CHUNK_SIZE" => parse(value, "bytes").map(|size| self.max_chunk_size = Some(size)),
+            "PLAIN_AUTH" => parse_switch(value).map(|on| self.plain_auth = Some(on)),
+            "OFFLINE_QUEUE" => parse_switch(value).map(|on| self.offline_queue = Some(on)),
+            "OFFLINE_TTL" => seconds().map(|ttl| self.offline_ttl = Some(ttl)),
+            "CONCEAL_USERS" => parse_switch(value).map(|on| self.conceal_users = Some(on)),
+            "LISTEN_ONLY" => parse_switch(value).map(|on| self.listen_only = Some(on)),
+            "FLOOD_MAX_FRAMES" => parse(value, "frames per second").map(|frames| self.flood_max_frames = Some(frames)),
+            "FLOOD_MAX_BYTES" => parse(value, "bytes per second").map(|bytes| self.flood_max_bytes = Some(bytes)),
+            "FLOOD_COOLDOWN" => seconds().map(|cooldown| self.flood_cooldown = Some(cooldown)),
+            "IP_BAN_THRESHOLD" => parse(value, "a number").map(|threshold| self.ip_ban_threshold = Some(threshold)),
+            "IP_BAN_DECAY" => seconds().map(|decay| self.ip_ban_decay = Some(decay)),
+            "IP_BAN_DURATION" => seconds().map(|ban| self.ip_ban_duration = Some(ban)),
+            "AUTH_CONCURRENCY" => parse(value, "a number").map(|concurrency| self.auth_concurrency = Some(concurrency)),
+            "AUTH_QUEUE_TIMEOUT" => seconds().map(|timeout| self.auth_queue_timeout = Some(timeout)),
+            "PRESENCE" => parse_switch(value).map(|on| self.presence = Some(on)),
+            "PRESENCE_INTERVAL_MS" => millis().map(|interval| self.presence_interval = Some(interval)),
+            "PRESENCE_WINDOW_MS" => millis().map(|window| self.presence_window = Some(window)),
+            "SERVER_NAME" => {
+                self.server_name = Some(value.to_string());
+                Ok(())
+            }
+            "DEFAULT_LANGUAGE" => {
+                if catalog::is_language_tag(value) {
+                    self.default_language = Some(value.to_string());
+                    Ok(())
+                } else {
+                    Err(format!("expected a language like 'en' or 'pt-BR', got '{}'", value))
+                }
+            }
+            "WEBHOOK_URL" => {
+                self.webhook_url = Some(value.to_string());
+                Ok(())
+            }
+            "WEBHOOK_DOMAINS" => {
+                let domains: Vec<String> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|domain| !domain.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if domains.is_empty() {
+                    Err(format!("expected a list of domains, got '{}'", value))
+                } else {
+                    self.webhook_domains = Some(domains);
+                    Ok(())
+                }
+            }
+            "WEBHOOK_BODIES" => parse_switch(value).map(|on| self.webhook_bodies = Some(on)),
+            "OLDEST_PROTOCOL_VERSION" => {
+                parse(value, "a protocol version").map(|version| self.oldest_protocol_version = Some(version))
+            }
+            "FRAME_INTEGRITY" => value
+                .split(',')
+                .map(Integrity::parse)
+                .collect::<Result<Vec<_>, _>>()
+                .map(|integrity| self.frame_integrity = Some(integrity))
+                .map_err(|_| format!("expected a list of crc32, xxh3 and none, got '{}'", value)),
+            "MAX_INTEGRITY_FAILURES" => {
+                parse(value, "a number").map(|failures| self.max_integrity_failures = Some(failures))
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(problem) = result {
+            self.problems.push(format!("{}: {}", key, problem));
+        }
+    }
+
+    // runs before anything is bound or written, every problem is reported at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = self.problems.clone();
+        let mut problem = |key: &str, message: String| problems.push(format!("{}: {}", key, message));
+
+        if self.port == Some(0) {
+            problem("PORT", "expected a port between 1 and 65535, got '0'".into());
+        }
+
+        let interval = self.heartbeat_interval();
+        if interval.is_zero() {
+            problem("HEARTBEAT_INTERVAL", "must be at least 1 second".into());
+        } else if self.heartbeat_grace() >= interval {
+            problem(
+                "HEARTBEAT_GRACE",
+                format!(
+                    "must be shorter than HEARTBEAT_INTERVAL ({}s), got {}s",
+                    interval.as_secs(),
+                    self.heartbeat_grace().as_secs()
+                ),
+            );
+        }
+
+        for (key, timeout) in [
+            ("HANDSHAKE_TIMEOUT", self.handshake_timeout),
+            ("DISCONNECT_TIMEOUT", self.disconnect_timeout),
+            ("WRITE_TIMEOUT", self.write_timeout),
+            ("MAX_MESSAGE_TTL", self.max_message_ttl),
+            ("OFFLINE_TTL", self.offline_ttl),
+            ("FLOOD_COOLDOWN", self.flood_cooldown),
+            ("IP_BAN_DECAY", self.ip_ban_decay),
+            ("IP_BAN_DURATION", self.ip_ban_duration),
+            ("AUTH_QUEUE_TIMEOUT", self.auth_queue_timeout),
+            ("SCHEDULE_MAX_HORIZON", self.schedule_max_horizon),
+        ] {
+            if timeout.is_some_and(|timeout| timeout.is_zero()) {
+                problem(key, "must be at least 1 second".into());
+            }
+        }
+
+        // a queued message is acknowledged, which tells the sender the recipient exists
+        if self.conceal_users == Some(true) && self.offline_queue != Some(false) {
+            problem("CONCEAL_USERS", "requires OFFLINE_QUEUE=off".into());
+        }
+
+        if self.presence_interval.is_some_and(|interval| interval.is_zero()) {
+            problem("PRESENCE_INTERVAL_MS", "must be at least 1 millisecond".into());
+        }
+
+        if self.auth_concurrency == Some(0) {
+            problem("AUTH_CONCURRENCY", "must be at least 1".into());
+        }
+
+        if self.flood_max_frames == Some(0) {
+            problem("FLOOD_MAX_FRAMES", "must be at least 1 frame per second".into());
+        }
+        // a single frame of the largest size has to get through
+        if let Some(bytes) = self.flood_max_bytes.filter(|bytes| *bytes < MAX_FIELD_SIZE as u64) {
+            problem(
+                "FLOOD_MAX_BYTES",
+                format!("must be at least {} bytes per second, got {}", MAX_FIELD_SIZE, bytes),
+            );
+        }
+
+        if let Some(version) = self
+            .oldest_protocol_version
+            .filter(|version| !(OLDEST_VERSION..=VERSION).contains(version))
+        {
+            problem(
+                "OLDEST_PROTOCOL_VERSION",
+                format!("must be between {} and {}, got {}", OLDEST_VERSION, VERSION, version),
+            );
+        }
+
+        if self.server_name.as_deref() == Some("") {
+            problem("SERVER_NAME", "must not be empty".into());
+        }
+
+        if let Some(url) = &self.webhook_url {
+            #[cfg(feature = "webhook")]
+            if let Err(e) = WebhookUrl::parse(url) {
+                problem("WEBHOOK_URL", e);
+            }
+            #[cfg(not(feature = "webhook"))]
+            problem(
+                "WEBHOOK_URL",
+                format!("'{}' needs a server built with the webhook feature", url),
+            );
+        }
+
+        #[cfg(not(feature = "http-bridge"))]
+        if let Some(addr) = self.http_bridge {
+            problem(
+                "HTTP_BRIDGE",
+                format!("'{}' needs a server built with the http-bridge feature", addr),
+            );
+        }
+
+        #[cfg(not(feature = "webhook"))]
+        if self.webhook_domains.is_some() {
+            problem(
+                "WEBHOOK_DOMAINS",
+                "needs a server built with the webhook feature".into(),
+            );
+        }
+        if self.webhook_bodies.is_some() && self.webhook_domains.is_none() {
+            problem("WEBHOOK_BODIES", "requires WEBHOOK_DOMAINS".into());
+        }
+
+        if self.max_file_size == Some(0) {
+            problem("MAX_FILE_SIZE", "must be at least 1 byte".into());
+        }
+        match self.max_chunk_size {
+            Some(0) => problem("MAX_CHUNK_SIZE", "must be at least 1 byte".into()),
+            Some(size) if size > MAX_FIELD_SIZE as u64 => problem(
+                "MAX_CHUNK_SIZE",
+                format!("must be at most {} bytes, got {}", MAX_FIELD_SIZE, size),
+            ),
+            _ => {}
+        }
+
+        if let Some(path) = &self.access_presets_file {
+            match fs::read_to_string(path) {
+                Ok(config) => {
+                    if let Err(e) = AccessPresets::parse(&config) {
+                        problem("ACCESS_PRESETS_FILE", format!("{}: {}", path.display(), e));
+                    }
+                }
+                Err(e) => problem(
+                    "ACCESS_PRESETS_FILE",
+                    format!("could not read {}: {}", path.display(), e),
+                ),
+            }
+        }
+
+        // a missing users file is fine, the server starts with the built-in users
+        if let Some(path) = self.users_file().filter(|path| path.exists()) {
+            match fs::read_to_string(&path) {
+                Ok(document) => {
+                    if let Err(e) = export::parse_snapshot(&document) {
+                        problem("USERS_FILE", format!("{}: {}", path.display(), e));
+                    }
+                }
+                Err(e) => problem("USERS_FILE", format!("could not read {}: {}", path.display(), e)),
+            }
+        }
+
+        // a data directory that cannot be used is reported below
+        let languages = self.data_dir().join(LANGUAGE_DIR);
+        let catalog = match self.data_dir().is_file() {
+            true => Ok(Catalog::default()),
+            false => Catalog::load(&languages),
+        };
+        match catalog {
+            Ok(catalog) => {
+                if let Some(language) = self
+                    .default_language
+                    .as_deref()
+                    .filter(|language| !catalog.has_language(language))
+                {
+                    problem(
+                        "DEFAULT_LANGUAGE",
+                        format!("no catalog for '{}' in {}", language, languages.display()),
+                    );
+                }
+            }
+            Err(e) => problem("DATA_DIR", e),
+        }
+
+        if let Err(e) = check_writable(&self.data_dir()) {
+            problem(
+                "DATA_DIR",
+                format!("{} is not writable: {}", self.data_dir().display(), e),
+            );
+        }
+
+        // what the tenant inherited is already reported once for the process
+        for (name, tenant) in &self.tenants {
+            let inherited = problems.clone();
+            if let Err(e) = tenant.validate() {
+                problems.extend(
+                    e.problems
+                        .into_iter()
+                        .filter(|problem| !inherited.contains(problem))
+                        .map(|problem| format!("tenant {}: {}", name, problem)),
+                );
+            }
+        }
+        for (index, (name, tenant)) in self.tenants.iter().enumerate() {
+            for (other, other_tenant) in &self.tenants[..index] {
+                if tenant.port() == other_tenant.port() {
+                    problems.push(format!(
+                        "TENANTS: {} and {} both listen on port {}",
+                        other,
+                        name,
+                        tenant.port()
+                    ));
+                }
+                if tenant.data_dir() == other_tenant.data_dir() {
+                    problems.push(format!(
+                        "TENANTS: {} and {} share the data directory {}",
+                        other,
+                        name,
+                        tenant.data_dir().display()
+                    ));
+                }
+                if tenant.users_file().is_some() && tenant.users_file() == other_tenant.users_file() {
+                    problems.push(format!("TENANTS: {} and {} share a USERS_FILE", other, name));
+                }
+                if let Some(addr) = tenant
+                    .http_bridge
+                    .filter(|addr| Some(*addr) == other_tenant.http_bridge)
+                {
+                    problems.push(format!("TENANTS: {} and {} both bridge http on {}", other, name, addr));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    // empty when the process serves a single instance with the settings above
+    pub fn tenants(&self) -> &[(String, ServerConfig)] {
+        &self.tenants
+    }
+
+    pub fn tenant(&self, name: &str) -> Option<&ServerConfig> {
+        self.tenants
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, tenant)| tenant)
+    }
+
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(PORT)
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir.clone().unwrap_or_else(|| PathBuf::from(DATA_DIR))
+    }
+
+    // a relative USERS_FILE lives inside the data directory
+    pub fn users_file(&self) -> Option<PathBuf> {
+        self.users_file
+            .as_deref()
+            .map(|path| data_dir::resolve(&self.data_dir(), path))
+    }
+
+    pub fn access_presets_file(&self) -> Option<&Path> {
+        self.access_presets_file.as_deref()
+    }
+
+    pub fn trace_file(&self) -> Option<&Path> {
+        self.trace_file.as_deref()
+    }
+
+    fn server_name(&self) -> String {
+        self.server_name.clone().unwrap_or_else(|| SERVER_NAME.to_string())
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+            .unwrap_or(Duration::from_secs(HEARTBEAT_INTERVAL))
+    }
+
+    fn heartbeat_grace(&self) -> Duration {
+        self.heartbeat_grace.unwrap_or(HEARTBEAT_GRACE)
+    }
+
+    fn flood_limits(&self) -> FloodLimits {
+        FloodLimits {
+            frames_per_second: self.flood_max_frames.unwrap_or(MAX_FRAMES_PER_SECOND),
+            bytes_per_second: self.flood_max_bytes.unwrap_or(MAX_BYTES_PER_SECOND),
+            cooldown: self.flood_cooldown.unwrap_or(FLOOD_COOLDOWN),
+        }
+    }
+
+    fn schedule_limits(&self) -> ScheduleLimits {
+        ScheduleLimits {
+            max_pending: self.schedule_max_pending.unwrap_or(MAX_PENDING_SCHEDULED),
+            max_horizon: self.schedule_max_horizon.unwrap_or(MAX_SCHEDULE_HORIZON),
+        }
+    }
+
+    fn violation_limits(&self) -> ViolationLimits {
+        ViolationLimits {
+            threshold: self.ip_ban_threshold.unwrap_or(VIOLATION_THRESHOLD),
+            decay: self.ip_ban_decay.unwrap_or(VIOLATION_DECAY),
+            ban: self.ip_ban_duration.unwrap_or(IP_BAN_DURATION),
+        }
+    }
+
+    fn auth_limits(&self) -> AuthLimits {
+        AuthLimits {
+            concurrency: self.auth_concurrency.unwrap_or_else(auth_limit::default_concurrency),
+            queue_timeout: self.auth_queue_timeout.unwrap_or(AUTH_QUEUE_TIMEOUT),
+        }
+    }
+
+    fn presence(&self) -> Option<PresenceTiming> {
+        self.presence.unwrap_or(false).then(|| PresenceTiming {
+            interval: self.presence_interval.unwrap_or(PRESENCE_INTERVAL),
+            window: self.presence_window.unwrap_or(PRESENCE_WINDOW),
+        })
+    }
+
+    fn control_socket(&self) -> bool {
+        self.control_socket.unwrap_or(true)
+    }
+
+    fn frame_integrity(&self) -> Vec<Integrity> {
+        self.frame_integrity
+            .clone()
+            .unwrap_or_else(|| OFFERED_INTEGRITY.to_vec())
+    }
+
+    fn protocol_versions(&self) -> VersionRange {
+        VersionRange::new(self.oldest_protocol_version.unwrap_or(OLDEST_VERSION), VERSION)
+    }
+
+    pub(super) fn apply(&self, mut server: Server) -> Server {
+        server = server
+            .with_port(self.port())
+            .with_data_dir(self.data_dir())
+            .with_control_socket(self.control_socket())
+            .with_http_bridge(self.http_bridge)
+            .with_server_name(self.server_name())
+            .with_default_language(self.default_language.clone())
+            .with_heartbeat_interval(self.heartbeat_interval())
+            .with_heartbeat_grace(self.heartbeat_grace());
+
+        if let Some(seconds) = self.session_max_age {
+            server = server.with_max_session_age((seconds > 0).then(|| Duration::from_secs(seconds)));
+        }
+        if let Some(misses) = self.heartbeat_max_misses {
+            server = server.with_max_missed_heartbeats(misses);
+        }
+        if let Some(timeout) = self.handshake_timeout {
+            server = server.with_handshake_timeout(timeout);
+        }
+        if let Some(timeout) = self.disconnect_timeout {
+            server = server.with_disconnect_timeout(timeout);
+        }
+        if let Some(timeout) = self.write_timeout {
+            server = server.with_write_timeout(timeout);
+        }
+        if let Some(backoff) = self.accept_backoff {
+            server = server.with_accept_backoff(backoff);
+        }
+        if let Some(policy) = self.takeover_policy {
+            server = server.with_takeover_policy(policy);
+        }
+        if let Some(window) = self.edit_window {
+            server = server.with_edit_window(window);
+        }
+        if let Some(history) = self.deleted_history {
+            server = server.with_deleted_history(history);
+        }
+        if let Some(ttl) = self.max_message_ttl {
+            server = server.with_max_message_ttl(ttl);
+        }
+        if let Some(grace) = self.rename_grace {
+            server = server.with_rename_grace(grace);
+        }
+        if let Some(capacity) = self.dedup_capacity {
+            server = server.with_dedup_capacity(capacity);
+        }
+        if let Some(ttl) = self.dedup_ttl {
+            server = server.with_dedup_ttl(ttl);
+        }
+        if let Some(size) = self.max_file_size {
+            server = server.with_max_file_size(size);
+        }
+        if let Some(size) = self.max_chunk_size {
+            server = server.with_max_chunk_size(size);
+        }
+        if let Some(plain_auth) = self.plain_auth {
+            server = server.with_plain_auth(plain_auth);
+        }
+        if let Some(offline_queue) = self.offline_queue {
+            server = server.with_offline_queue(offline_queue);
+        }
+        if let Some(ttl) = self.offline_ttl {
+            server = server.with_offline_ttl(ttl);
+        }
+        if let Some(conceal_users) = self.conceal_users {
+            server = server.with_conceal_users(conceal_users);
+        }
+        if let Some(listen_only) = self.listen_only {
+            server = server.with_listen_only(listen_only);
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(url) = self.webhook_url.as_deref().and_then(|url| WebhookUrl::parse(url).ok()) {
+            server = server.with_plugin(std::sync::Arc::new(WebhookPlugin::new(url)));
+        }
+        if let Some(domains) = &self.webhook_domains {
+            server = server.with_offline_webhooks(OfflineWebhooks::new(
+                domains.clone(),
+                self.webhook_bodies.unwrap_or(false),
+            ));
+        }
+        server
+            .with_flood_limits(self.flood_limits())
+            .with_schedule_limits(self.schedule_limits())
+            .with_violation_limits(self.violation_limits())
+            .with_auth_limits(self.auth_limits())
+            .with_protocol_versions(self.protocol_versions())
+            .with_integrity(self.frame_integrity())
+            .with_max_integrity_failures(self.max_integrity_failures.unwrap_or(MAX_INTEGRITY_FAILURES))
+            .with_presence(self.presence())
+    }
+
+    // the values the server runs with, defaults included
+    pub fn effective(&self) -> Vec<(&'static str, String)> {
+        let seconds = |duration: Duration| format!("{}s", duration.as_secs());
+        let millis = |duration: Duration| format!("{}ms", duration.as_millis());
+        let switch = |on: bool| if on { "on" } else { "off" }.to_string();
+        let path = |path: Option<PathBuf>| path.map_or("none".to_string(), |path| path.display().to_string());
+
+        vec![
+            ("PORT", self.port().to_string()),
+            ("DATA_DIR", self.data_dir().display().to_string()),
+            ("SERVER_NAME", self.server_name()),
+            (
+                "DEFAULT_LANGUAGE",
+                self.default_language
+                    .clone()
+                    .unwrap_or_else(|| BUILT_IN_LANGUAGE.to_string()),
+            ),
+            ("USERS_FILE", path(self.users_file())),
+            ("ACCESS_PRESETS_FILE", path(self.access_presets_file.clone())),
+            ("TRACE_FILE", path(self.trace_file.clone())),
+            ("CONTROL_SOCKET", switch(self.control_socket())),
+            (
+                "HTTP_BRIDGE",
+                self.http_bridge.map_or("none".to_string(), |addr| addr.to_string()),
+            ),
+            (
+                "TENANTS",
+                match self.tenants.is_empty() {
+                    true => "none".to_string(),
+                    false => self
+                        .tenants
+                        .iter()
+                        .map(|(name, _)| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                },
+            ),
+            (
+                "SESSION_MAX_AGE",
+                match self.session_max_age.unwrap_or(MAX_SESSION_AGE) {
+                    0 => "never".to_string(),
+                    age => format!("{}s", age),
+                },
+            ),
+            ("HEARTBEAT_INTERVAL", seconds(self.heartbeat_interval())),
+            ("HEARTBEAT_GRACE", seconds(self.heartbeat_grace())),
+            (
+                "HEARTBEAT_MAX_MISSES",
+                self.heartbeat_max_misses.unwrap_or(MAX_MISSED_HEARTBEATS).to_string(),
+            ),
+            (
+                "HANDSHAKE_TIMEOUT",
+                seconds(self.handshake_timeout.unwrap_or(Duration::from_secs(HANDSHAKE_TIMEOUT))),
+            ),
+            (
+                "DISCONNECT_TIMEOUT",
+                seconds(self.disconnect_timeout.unwrap_or(DISCONNECT_TIMEOUT)),
+            ),
+            ("WRITE_TIMEOUT", seconds(self.write_timeout.unwrap_or(WRITE_TIMEOUT))),
+            (
+                "ACCEPT_BACKOFF_MS",
+                millis(self.accept_backoff.unwrap_or(ACCEPT_BACKOFF)),
+            ),
+            (
+                "SESSION_TAKEOVER",
+                self.takeover_policy.unwrap_or_default().as_str().to_string(),
+            ),
+            ("EDIT_WINDOW", seconds(self.edit_window.unwrap_or(EDIT_WINDOW))),
+            (
+                "HISTORY_DELETIONS",
+                self.deleted_history.unwrap_or_default().as_str().to_string(),
+            ),
+            (
+                "MAX_MESSAGE_TTL",
+                seconds(self.max_message_ttl.unwrap_or(MAX_MESSAGE_TTL)),
+            ),
+            ("RENAME_GRACE", seconds(self.rename_grace.unwrap_or_default())),
+            (
+                "DEDUP_CAPACITY",
+                self.dedup_capacity.unwrap_or(DEDUP_CAPACITY).to_string(),
+            ),
+            ("DEDUP_TTL", seconds(self.dedup_ttl.unwrap_or(DEDUP_TTL))),
+            ("SCHEDULE_MAX_PENDING", self.schedule_limits().max_pending.to_string()),
+            ("SCHEDULE_MAX_HORIZON", seconds(self.schedule_limits().max_horizon)),
+            ("MAX_FILE_SIZE", self.max_file_size.unwrap_or(MAX_FILE_SIZE).to_string()),
+            (
+                "MAX_CHUNK_SIZE",
+                self.max_chunk_size.unwrap_or(MAX_CHUNK_SIZE).to_string(),
+            ),
+            ("PLAIN_AUTH", switch(self.plain_auth.unwrap_or(true))),
+            ("OFFLINE_QUEUE", switch(self.offline_queue.unwrap_or(true))),
+            ("OFFLINE_TTL", seconds(self.offline_ttl.unwrap_or(OFFLINE_TTL))),
+            ("CONCEAL_USERS", switch(self.conceal_users.unwrap_or(false))),
+            ("LISTEN_ONLY", switch(self.listen_only.unwrap_or(false))),
+            ("FLOOD_MAX_FRAMES", self.flood_limits().frames_per_second.to_string()),
+            ("FLOOD_MAX_BYTES", self.flood_limits().bytes_per_second.to_string()),
+            ("FLOOD_COOLDOWN", seconds(self.flood_limits().cooldown)),
+            ("IP_BAN_THRESHOLD", self.violation_limits().threshold.to_string()),
+            ("IP_BAN_DECAY", seconds(self.violation_limits().decay)),
+            ("IP_BAN_DURATION", seconds(self.violation_limits().ban)),
+            ("AUTH_CONCURRENCY", self.auth_limits().concurrency.to_string()),
+            ("AUTH_QUEUE_TIMEOUT", seconds(self.auth_limits().queue_timeout)),
+            ("OLDEST_PROTOCOL_VERSION", self.protocol_versions().oldest().to_string()),
+            (
+                "FRAME_INTEGRITY",
+                self.frame_integrity()
+                    .iter()
+                    .map(Integrity::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            (
+                "MAX_INTEGRITY_FAILURES",
+                self.max_integrity_failures
+                    .unwrap_or(MAX_INTEGRITY_FAILURES)
+                    .to_string(),
+            ),
+            ("PRESENCE", switch(self.presence.unwrap_or(false))),
+            (
+                "PRESENCE_INTERVAL_MS",
+                millis(self.presence_interval.unwrap_or(PRESENCE_INTERVAL)),
+            ),
+            (
+                "PRESENCE_WINDOW_MS",
+                millis(self.presence_window.unwrap_or(PRESENCE_WINDOW)),
+            ),
+            (
+                "WEBHOOK_DOMAINS",
+                self.webhook_domains
+                    .as_ref()
+                    .map_or("none".to_string(), |domains| domains.join(",")),
+            ),
+            ("WEBHOOK_BODIES", switch(self.webhook_bodies.unwrap_or(false))),
+            // a token in the query stays out of the logs
+            (
+                "WEBHOOK_URL",
+                self.webhook_url.as_deref().map_or("none".to_string(), |url| {
+                    url.split('?').next().unwrap_or(url).to_string()
+                }),
+            ),
+        ]
+    }
+
+    // one line for the startup log
+    pub fn summary(&self) -> String {
+        self.effective()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, redact(key, value)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl ConfigError {
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+fn parse<T: FromStr>(value: &str, expected: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("expected {}, got '{}'", expected, value))
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got '{}'", value)),
+    }
+}
+
+// part of the data directory and of the TENANT_<NAME>_ keys, so nothing that needs quoting
+fn validate_tenant_name(name: &str) -> Result<(), String> {
+    let valid = name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' is not a tenant name, expected up to 32 lowercase letters, digits and dashes",
+            name
+        )),
+    }
+}
+
+fn redact(key: &str, value: String) -> String {
+    if SECRET_MARKERS.iter().any(|marker| key.contains(marker)) {
+        "<redacted>".to_string()
+    } else {
+        value
+    }
+}
+
+// creates the directory if needed and writes a probe file, the lock file comes later
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".write-check-{}", std::process::id()));
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)
+}
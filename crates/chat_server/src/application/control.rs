@@ -0,0 +1,260 @@
+use std::{
+    fs::{self, OpenOptions, Permissions},
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+};
+
+use chat_core::{
+    auth::tokens_match,
+    json::JsonValue,
+    normalize::normalize_username,
+    protocol::{Message, MessageType, SessionFilter},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+use super::{
+    permissions::Access,
+    server::{Server, ACCEPT_BACKOFF},
+    ArcRwLock, SharedState,
+};
+
+pub const CONTROL_SOCKET: &str = "control.sock";
+pub const CONTROL_TOKEN: &str = "control.token";
+
+const COMMANDS: &str = "stats, sessions, userinfo, kick, promote, demote, grant, ungrant, renameuser, resetpw, \
+                        motd, drain, undrain, loglevel, bans, unban, fsck, export";
+
+// one command per line and one answer per line, the token is written anew on every start and only the
+// owner of the data directory can read it
+pub(super) fn open(data_dir: &Path) -> Result<(UnixListener, String), String> {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let token_path = data_dir.join(CONTROL_TOKEN);
+    remove_if_present(&token_path).map_err(|e| format!("Could not remove {}: {}", token_path.display(), e))?;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&token_path)
+        .and_then(|mut file| writeln!(file, "{}", token))
+        .map_err(|e| format!("Could not write {}: {}", token_path.display(), e))?;
+
+    // left behind by a server that died, the lock on the data directory says it is nobody's anymore
+    let socket_path = data_dir.join(CONTROL_SOCKET);
+    remove_if_present(&socket_path).map_err(|e| format!("Could not remove {}: {}", socket_path.display(), e))?;
+    let listener =
+        UnixListener::bind(&socket_path).map_err(|e| format!("Could not bind {}: {}", socket_path.display(), e))?;
+    fs::set_permissions(&socket_path, Permissions::from_mode(0o600))
+        .map_err(|e| format!("Could not restrict {}: {}", socket_path.display(), e))?;
+
+    Ok((listener, token))
+}
+
+// a ctl that finds neither knows the server is not running
+pub(super) fn close(data_dir: &Path) {
+    for file in [CONTROL_SOCKET, CONTROL_TOKEN] {
+        let path = data_dir.join(file);
+        if let Err(e) = remove_if_present(&path) {
+            tracing::warn!("Could not remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub(super) async fn serve(listener: UnixListener, token: String, shared_state: ArcRwLock<SharedState>) {
+    let token = Arc::new(token);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, Arc::clone(&token), Arc::clone(&shared_state)));
+            }
+            Err(e) => {
+                tracing::warn!("Could not accept a control connection: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+// what `server ctl` does: reads the token, sends one command as `user` and returns the answer,
+// without a user the command runs as the server itself, which needs no account
+pub async fn send_command(data_dir: &Path, user: Option<&str>, command: &[String]) -> Result<JsonValue, String> {
+    let token_path = data_dir.join(CONTROL_TOKEN);
+    let token =
+        fs::read_to_string(&token_path).map_err(|e| format!("Could not read {}: {}", token_path.display(), e))?;
+    let mut request = JsonValue::object()
+        .with("token", token.trim())
+        .with("command", command.to_vec());
+    if let Some(user) = user {
+        request = request.with("user", user);
+    }
+
+    let socket_path = data_dir.join(CONTROL_SOCKET);
+    let stream = UnixStream::connect(&socket_path)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {}", socket_path.display(), e))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await
+        .map_err(|e| format!("Could not send the command: {}", e))?;
+    let response = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .map_err(|e| format!("Could not read the answer: {}", e))?
+        .ok_or("The server closed the connection without an answer")?;
+    JsonValue::parse(&response)
+}
+
+async fn handle_connection(stream: UnixStream, token: Arc<String>, shared_state: ArcRwLock<SharedState>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match execute(&line, &token, &shared_state).await {
+            Ok(replies) => JsonValue::object()
+                .with("ok", !replies.iter().any(|reply| reply.is(MessageType::Nack)))
+                .with("replies", replies.iter().map(reply_to_json).collect::<Vec<_>>()),
+            Err(e) => JsonValue::object().with("ok", false).with("error", e),
+        };
+        if writer.write_all(format!("{}\n", response).as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+// the message goes through the same permission check and handler as one from a client logged in as `user`,
+// or as an admin without an account when no user is named
+async fn execute(line: &str, token: &str, shared_state: &ArcRwLock<SharedState>) -> Result<Vec<Message>, String> {
+    let request = JsonValue::parse(line)?;
+    if !request
+        .get("token")
+        .and_then(JsonValue::as_str)
+        .is_some_and(|given| tokens_match(token, given))
+    {
+        return Err("Invalid control token".to_string());
+    }
+    let user = request
+        .get("user")
+        .and_then(JsonValue::as_str)
+        .map(normalize_username)
+        .transpose()?;
+    let args = request
+        .get("command")
+        .and_then(JsonValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .map(|arg| arg.as_str().ok_or("Command arguments must be strings"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let message = command(&args)?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let Some(session_id) = shared_state
+        .write()
+        .await
+        .open_control_session(user.as_deref(), tx.clone())
+        .await
+    else {
+        return Err(format!("Unknown user {}", user.unwrap_or_default()));
+    };
+    let actor = shared_state.read().await.actor_of(session_id).await;
+    tracing::info!("Control command {} as {}", args[0], actor);
+
+    let message_type = message.message_type();
+    let access = shared_state.read().await.access_of(session_id, message_type).await;
+    let result = match access {
+        Access::Denied => Err(format!("{} may not send {:?}", actor, message_type)),
+        Access::Allowed | Access::Temporary => {
+            if access == Access::Temporary {
+                shared_state
+                    .write()
+                    .await
+                    .record_temporary_use(session_id, message_type)
+                    .await;
+            }
+            let plugins = shared_state.read().await.plugins();
+            Server::dispatch(&message, tx, &plugins, Arc::clone(shared_state), session_id).await;
+            Ok(())
+        }
+    };
+    shared_state.write().await.close_control_session(session_id);
+    result?;
+
+    let mut replies = Vec::new();
+    while let Ok(reply) = rx.try_recv() {
+        replies.push(reply);
+    }
+    Ok(replies)
+}
+
+// the same words the client's admin commands use
+fn command(args: &[&str]) -> Result<Message, String> {
+    let seconds = |value: &str| {
+        value
+            .parse::<u64>()
+            .map_err(|_| format!("Expected seconds, got '{}'", value))
+    };
+    let message = match args {
+        ["stats"] => Message::admin_server_stats(),
+        ["sessions"] => Message::admin_list_sessions(&SessionFilter::default()),
+        ["userinfo", username] => Message::admin_user_info(username),
+        ["kick", username, reason @ ..] => Message::admin_kick_user(username, &reason.join(" ")),
+        ["promote", username, level] => Message::admin_set_access_level(username, level),
+        ["demote", username] => Message::admin_set_access_level(username, "user"),
+        ["demote", username, level] => Message::admin_set_access_level(username, level),
+        ["grant", username, permission, duration] => {
+            Message::admin_grant_temporary(username, permission, seconds(duration)?)
+        }
+        ["ungrant", username, permission] => Message::admin_revoke_temporary(username, permission),
+        ["renameuser", old_name, new_name] => Message::admin_rename_user(old_name, new_name),
+        ["resetpw", username, password] => Message::admin_reset_password(username, password),
+        ["motd", "--clear"] => Message::admin_set_motd("", false),
+        ["motd", text @ ..] if !text.is_empty() => Message::admin_set_motd(&text.join(" "), false),
+        ["drain"] => Message::admin_set_server_mode("draining", None),
+        ["drain", stop_after] => Message::admin_set_server_mode("draining", Some(seconds(stop_after)?)),
+        ["undrain"] => Message::admin_set_server_mode("normal", None),
+        ["loglevel", filter] => Message::admin_set_log_level(filter, None),
+        ["loglevel", filter, revert_after] => Message::admin_set_log_level(filter, Some(seconds(revert_after)?)),
+        ["bans"] => Message::admin_list_ip_bans(),
+        ["unban", address] => Message::admin_clear_ip_ban(address),
+        ["fsck"] => Message::admin_consistency_check(false),
+        ["fsck", "--repair"] => Message::admin_consistency_check(true),
+        ["export"] => Message::admin_export_state(),
+        [] => return Err(format!("Missing command, expected one of {}", COMMANDS)),
+        [name, ..] => {
+            return Err(format!(
+                "Unknown command or arguments for {}, expected one of {}",
+                name, COMMANDS
+            ))
+        }
+    };
+    Ok(message)
+}
+
+// readable text stays text, eight other bytes are a number and anything else is hex
+fn reply_to_json(reply: &Message) -> JsonValue {
+    let payload = reply.payload();
+    let fields = (0..payload.field_count())
+        .filter_map(|index| payload.field(index).ok())
+        .map(|field| match std::str::from_utf8(field) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n' && c != '\t') => JsonValue::from(text),
+            _ if field.len() == 8 => JsonValue::from(u64::from_be_bytes(field.try_into().unwrap())),
+            _ => JsonValue::from(field.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+        })
+        .collect::<Vec<_>>();
+    JsonValue::object()
+        .with("type", format!("{:?}", reply.message_type()))
+        .with("fields", fields)
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
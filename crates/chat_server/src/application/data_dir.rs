@@ -0,0 +1,116 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+pub const LOCK_FILE: &str = "server.lock";
+pub const SERVER_ID_FILE: &str = "server_id";
+pub const MOTD_FILE: &str = "motd";
+
+// owns the data directory for as long as it lives, a second owner fails to open it
+#[derive(Debug)]
+pub struct DataDir {
+    path: PathBuf,
+    // the advisory lock goes away with the descriptor, also when the process dies
+    _lock: File,
+}
+
+impl DataDir {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        fs::create_dir_all(path).map_err(|e| format!("Could not create data directory {}: {}", path.display(), e))?;
+
+        let lock_path = path.join(LOCK_FILE);
+        let mut lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|e| format!("Could not open {}: {}", lock_path.display(), e))?;
+
+        let locked = try_lock(&lock).map_err(|e| format!("Could not lock {}: {}", lock_path.display(), e))?;
+        if !locked {
+            let mut owner = String::new();
+            lock.read_to_string(&mut owner).ok();
+            let owner = match owner.trim() {
+                "" => "unknown".to_string(),
+                pid => pid.to_string(),
+            };
+            return Err(format!(
+                "Data directory {} is already in use by process {}",
+                path.display(),
+                owner
+            ));
+        }
+
+        // only the owner rewrites the pid, so a refused instance never clobbers it
+        lock.set_len(0)
+            .and_then(|_| lock.rewind())
+            .and_then(|_| writeln!(lock, "{}", std::process::id()))
+            .map_err(|e| format!("Could not write {}: {}", lock_path.display(), e))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            _lock: lock,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // absolute paths are kept as they are, relative ones live inside the data directory
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        resolve(&self.path, path)
+    }
+}
+
+pub fn resolve(data_dir: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        data_dir.join(path)
+    }
+}
+
+// made up on the first start, the same server keeps its id for as long as the directory lives
+pub fn server_id(data_dir: &Path) -> Result<Uuid, String> {
+    let path = data_dir.join(SERVER_ID_FILE);
+    match fs::read_to_string(&path) {
+        Ok(id) => Uuid::parse_str(id.trim()).map_err(|e| format!("Invalid server id in {}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let id = Uuid::new_v4();
+            fs::write(&path, format!("{}\n", id)).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            Ok(id)
+        }
+        Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+// a missing file is an empty message of the day
+pub fn load_motd(path: &Path) -> Result<String, String> {
+    match fs::read_to_string(path) {
+        Ok(motd) => Ok(motd.trim_end().to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+fn try_lock(file: &File) -> io::Result<bool> {
+    // SAFETY: the descriptor belongs to `file`, which outlives the call
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result == 0 {
+        return Ok(true);
+    }
+
+    let error = io::Error::last_os_error();
+    if error.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chat_core::protocol::Message;
+use uuid::Uuid;
+
+pub const DEDUP_CAPACITY: usize = 256;
+pub const DEDUP_TTL: Duration = Duration::from_secs(5 * 60);
+
+// the reply to every client message id a user sent recently, keyed by user so it outlives the session
+#[derive(Debug)]
+pub struct DedupCache {
+    // per user, 0 turns deduplication off
+    capacity: usize,
+    ttl: Duration,
+    seen: HashMap<Uuid, VecDeque<(String, Instant, Message)>>,
+}
+
+impl DedupCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, capacity: usize, ttl: Duration) {
+        self.capacity = capacity;
+        self.ttl = ttl;
+        self.seen.clear();
+    }
+
+    pub fn get(&mut self, user: Uuid, client_id: &str, now: Instant) -> Option<Message> {
+        let seen = self.seen.get_mut(&user)?;
+        while seen
+            .front()
+            .is_some_and(|(_, at, _)| now.duration_since(*at) >= self.ttl)
+        {
+            seen.pop_front();
+        }
+        if seen.is_empty() {
+            self.seen.remove(&user);
+            return None;
+        }
+        seen.iter()
+            .find(|(id, _, _)| id == client_id)
+            .map(|(_, _, reply)| reply.clone())
+    }
+
+    // the oldest entry makes room once the user is at capacity
+    pub fn insert(&mut self, user: Uuid, client_id: &str, reply: Message, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let seen = self.seen.entry(user).or_default();
+        while seen.len() >= self.capacity {
+            seen.pop_front();
+        }
+        seen.push_back((client_id.to_string(), now, reply));
+    }
+}
+
+impl Default for DedupCache {
+    fn default() -> Self {
+        Self::new(DEDUP_CAPACITY, DEDUP_TTL)
+    }
+}
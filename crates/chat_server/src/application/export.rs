@@ -0,0 +1,207 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use chat_core::{
+    json::JsonValue,
+    trace::{from_hex, to_hex},
+};
+use uuid::Uuid;
+
+use super::{permissions::Permissions, session::AccessLevel, user::User};
+
+// bump whenever a field changes meaning, older versions are migrated on import
+pub const EXPORT_VERSION: u64 = 2;
+// version 1 had no user ids, its users get fresh ones
+const FIRST_VERSION_WITH_IDS: u64 = 2;
+// live session state, never exported, files written by builds that did still load without it
+const TRANSIENT_FIELDS: &[&str] = &["session_id", "online", "last_seen"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    // existing users stay, a username that exists on both sides is a conflict
+    #[default]
+    Merge,
+    // the import becomes the whole user database
+    Replace,
+}
+
+// the users of a document, and how much of it only made sense while the server that wrote it ran
+#[derive(Debug)]
+pub struct Snapshot {
+    pub users: Vec<User>,
+    pub transient_fields: usize,
+}
+
+impl ImportMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "merge" => Ok(ImportMode::Merge),
+            "replace" => Ok(ImportMode::Replace),
+            _ => Err(format!("Unknown import mode '{}', expected merge or replace", value)),
+        }
+    }
+}
+
+// sorted by name so the same state always exports to the same document
+pub fn export_users<'a>(users: impl IntoIterator<Item = &'a User>) -> JsonValue {
+    let mut users: Vec<&User> = users.into_iter().collect();
+    users.sort_by(|a, b| a.name().cmp(b.name()));
+
+    JsonValue::object()
+        .with("version", EXPORT_VERSION)
+        .with("users", users.into_iter().map(user_to_json).collect::<Vec<_>>())
+}
+
+pub fn parse_snapshot(document: &str) -> Result<Snapshot, String> {
+    let document = JsonValue::parse(document)?;
+    let version = match document.get("version").and_then(JsonValue::as_u64) {
+        Some(version @ 1..=EXPORT_VERSION) => version,
+        Some(version) => {
+            return Err(format!(
+                "Unsupported export version {}, expected {} or older",
+                version, EXPORT_VERSION
+            ))
+        }
+        None => return Err("Missing export version".into()),
+    };
+
+    let users = document
+        .get("users")
+        .and_then(JsonValue::as_array)
+        .ok_or("Missing user list")?;
+    let transient_fields = users
+        .iter()
+        .map(|user| TRANSIENT_FIELDS.iter().filter(|key| user.get(key).is_some()).count())
+        .sum();
+    let users = users
+        .iter()
+        .enumerate()
+        .map(|(index, user)| user_from_json(user, version).map_err(|e| format!("User {}: {}", index + 1, e)))
+        .collect::<Result<_, _>>()?;
+    Ok(Snapshot {
+        users,
+        transient_fields,
+    })
+}
+
+// everything is checked before the first user is added, a conflict leaves the store untouched
+pub fn import_users(store: &mut HashMap<Uuid, User>, users: Vec<User>, mode: ImportMode) -> Result<usize, String> {
+    let existing: BTreeSet<&str> = match mode {
+        ImportMode::Merge => store.values().map(User::name).collect(),
+        ImportMode::Replace => BTreeSet::new(),
+    };
+    let mut seen = BTreeSet::new();
+    let mut seen_ids = BTreeSet::new();
+    let mut conflicts = BTreeSet::new();
+    for user in &users {
+        let exists = existing.contains(user.name()) || (mode == ImportMode::Merge && store.contains_key(&user.id()));
+        if !seen.insert(user.name()) || !seen_ids.insert(user.id()) || exists {
+            conflicts.insert(user.name());
+        }
+    }
+    if !conflicts.is_empty() {
+        let conflicts: Vec<&str> = conflicts.into_iter().collect();
+        return Err(format!("Conflicting usernames: {}", conflicts.join(", ")));
+    }
+
+    if mode == ImportMode::Replace {
+        store.clear();
+    }
+    let count = users.len();
+    for user in users {
+        store.insert(user.id(), user);
+    }
+    Ok(count)
+}
+
+// through a temporary file, so a crash never leaves half a document behind
+pub fn write_document(path: &Path, document: &JsonValue) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, format!("{}\n", document))?;
+    fs::rename(&temporary, path)
+}
+
+// only what outlives a restart, the session a user is logged in with stays out
+fn user_to_json(user: &User) -> JsonValue {
+    let preferences = user
+        .preferences()
+        .entries()
+        .into_iter()
+        .map(|(key, value)| (key, JsonValue::from(value)))
+        .collect();
+    let muted: Vec<String> = user.preferences().muted().iter().map(Uuid::to_string).collect();
+
+    JsonValue::object()
+        .with("id", user.id().to_string())
+        .with("name", user.name())
+        .with("pw_hash", user.pw_hash())
+        .with("auth_key", user.auth_key())
+        .with("access_level", user.access_level().as_str())
+        .with("granted", permission_list(user.granted()))
+        .with("revoked", permission_list(user.revoked()))
+        .with("preferences", JsonValue::Object(preferences))
+        .with("muted", muted)
+        .with("signing_key", user.signing_key().map(|key| to_hex(key.as_slice())))
+}
+
+fn user_from_json(value: &JsonValue, version: u64) -> Result<User, String> {
+    let field = |key: &str| -> Result<&str, String> {
+        value
+            .get(key)
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| format!("Missing string field '{}'", key))
+    };
+
+    let name = field("name")?;
+    if name.is_empty() {
+        return Err("Empty username".into());
+    }
+    let mut user = User::new(name, field("pw_hash")?.to_string());
+    // missing for accounts exported before login challenges
+    user.set_auth_key(value.get("auth_key").and_then(JsonValue::as_str).map(str::to_string));
+    if version >= FIRST_VERSION_WITH_IDS {
+        let id = field("id")?;
+        user = user.with_id(Uuid::parse_str(id).map_err(|_| format!("Invalid user id '{}'", id))?);
+    }
+    user.set_access_level(AccessLevel::parse(field("access_level")?)?);
+    user.set_permission_overrides(
+        Permissions::parse(field("granted")?)?,
+        Permissions::parse(field("revoked")?)?,
+    );
+
+    if let Some(JsonValue::Object(preferences)) = value.get("preferences") {
+        // room notify levels could be set before they were dropped, there is nothing left to apply them to
+        for (key, value) in preferences.iter().filter(|(key, _)| !key.starts_with("room.")) {
+            let value = value
+                .as_str()
+                .ok_or_else(|| format!("Preference '{}' is not a string", key))?;
+            user.preferences_mut().set(key, value)?;
+        }
+    }
+    // missing for accounts exported before muting
+    for id in value.get("muted").and_then(JsonValue::as_array).unwrap_or_default() {
+        let id = id.as_str().ok_or("Muted sender is not a string")?;
+        let id = Uuid::parse_str(id).map_err(|_| format!("Invalid muted sender '{}'", id))?;
+        user.preferences_mut().mute(id)?;
+    }
+    // missing for accounts exported before message signing
+    if let Some(key) = value.get("signing_key").and_then(JsonValue::as_str) {
+        let key = from_hex(key)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| format!("Invalid signing key '{}'", key))?;
+        user.set_signing_key(Some(key));
+    }
+    Ok(user)
+}
+
+// empty instead of "none", so the list parses back
+fn permission_list(permissions: Permissions) -> String {
+    if permissions == Permissions::NONE {
+        return String::new();
+    }
+    permissions.to_string()
+}
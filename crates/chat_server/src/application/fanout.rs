@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use chat_core::protocol::Message;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use super::{session::Session, ArcRwLock};
+
+// sessions served at the same time, a wedged session only ever holds up one of these slots
+pub const FANOUT_CONCURRENCY: usize = 64;
+// how long one session may keep its lock before it counts as failed
+pub const FANOUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// who got the message and who did not, failures are sessions that were closed or wedged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub delivered: usize,
+    pub failed: Vec<Uuid>,
+}
+
+impl DeliveryReport {
+    pub fn attempted(&self) -> usize {
+        self.delivered + self.failed.len()
+    }
+}
+
+// the targets are a snapshot taken under the state lock, which must not be held while this runs
+pub async fn fan_out(targets: Vec<(Uuid, ArcRwLock<Session>)>, message: &Message) -> DeliveryReport {
+    let mut report = DeliveryReport::default();
+    let mut sends = JoinSet::new();
+    let mut targets = targets.into_iter();
+
+    loop {
+        // refilled as sends finish, never more than the limit in flight
+        while sends.len() < FANOUT_CONCURRENCY {
+            let Some((id, session)) = targets.next() else {
+                break;
+            };
+            let message = message.clone();
+            sends.spawn(async move {
+                let sent =
+                    tokio::time::timeout(FANOUT_TIMEOUT, async { session.read().await.send(message).is_ok() }).await;
+                (id, sent == Ok(true))
+            });
+        }
+
+        match sends.join_next().await {
+            Some(Ok((_, true))) => report.delivered += 1,
+            Some(Ok((id, false))) => report.failed.push(id),
+            Some(Err(e)) => tracing::warn!("Fan-out send failed: {}", e),
+            None => break,
+        }
+    }
+
+    report
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+pub const MAX_FRAMES_PER_SECOND: u64 = 200;
+pub const MAX_BYTES_PER_SECOND: u64 = 4 * 1024 * 1024;
+pub const FLOOD_COOLDOWN: Duration = Duration::from_secs(60);
+const WINDOW: Duration = Duration::from_secs(1);
+
+// hard protocol limits per session, far above anything a well-behaved client sends
+#[derive(Debug, Clone, Copy)]
+pub struct FloodLimits {
+    pub frames_per_second: u64,
+    pub bytes_per_second: u64,
+    // how long the address of a disconnected flooder is turned away
+    pub cooldown: Duration,
+}
+
+impl Default for FloodLimits {
+    fn default() -> Self {
+        Self {
+            frames_per_second: MAX_FRAMES_PER_SECOND,
+            bytes_per_second: MAX_BYTES_PER_SECOND,
+            cooldown: FLOOD_COOLDOWN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodVerdict {
+    Allow,
+    // over the limit, the frame is dropped
+    Drop,
+    // the first breach, the frame is dropped and the peer told to slow down
+    Warn,
+    // still over the limit in a later window despite the warning
+    Disconnect,
+}
+
+// counts what a session sent in fixed one second windows
+#[derive(Debug)]
+pub struct FloodMeter {
+    limits: FloodLimits,
+    window_start: Option<Instant>,
+    frames: u64,
+    bytes: u64,
+    breached: bool,
+    // cleared by a window that stays under the limits
+    warned: bool,
+}
+
+impl FloodMeter {
+    pub fn new(limits: FloodLimits) -> Self {
+        Self {
+            limits,
+            window_start: None,
+            frames: 0,
+            bytes: 0,
+            breached: false,
+            warned: false,
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.limits.cooldown
+    }
+
+    pub fn record(&mut self, bytes: u64, now: Instant) -> FloodVerdict {
+        if self
+            .window_start
+            .map_or(true, |start| now.duration_since(start) >= WINDOW)
+        {
+            if !self.breached {
+                self.warned = false;
+            }
+            self.window_start = Some(now);
+            self.frames = 0;
+            self.bytes = 0;
+            self.breached = false;
+        }
+
+        self.frames += 1;
+        self.bytes += bytes;
+        if self.frames <= self.limits.frames_per_second && self.bytes <= self.limits.bytes_per_second {
+            return FloodVerdict::Allow;
+        }
+
+        let first_in_window = !self.breached;
+        self.breached = true;
+        match (first_in_window, self.warned) {
+            (true, false) => {
+                self.warned = true;
+                FloodVerdict::Warn
+            }
+            (true, true) => FloodVerdict::Disconnect,
+            (false, _) => FloodVerdict::Drop,
+        }
+    }
+}
+
+// addresses of disconnected flooders and until when they are turned away at accept time
+#[derive(Debug, Default)]
+pub struct FloodCooldowns {
+    until: HashMap<IpAddr, Instant>,
+}
+
+impl FloodCooldowns {
+    // expired entries are forgotten whenever a new one comes in
+    pub fn insert(&mut self, ip: IpAddr, until: Instant, now: Instant) {
+        self.until.retain(|_, until| *until > now);
+        self.until.insert(ip, until);
+    }
+
+    pub fn remaining(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        self.until
+            .get(&ip)
+            .filter(|until| **until > now)
+            .map(|until| *until - now)
+    }
+}
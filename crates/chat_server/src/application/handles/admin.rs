@@ -1,28 +1,562 @@
-use chat_core::protocol::Message;
+use std::{sync::Arc, time::Duration};
 
-use crate::application::{ArcRwLock, SharedState};
+use chat_core::{
+    dto::{
+        AdminConsistencyCheck, AdminGrantTemporary, AdminRenameUser, AdminResetPassword, AdminRevokeTemporary,
+        AdminSetAccessLevel, AdminSetLogLevel, AdminSetMotd, AdminSetServerMode, AdminUserInfo, Response,
+        ServerShutdown,
+    },
+    protocol::{
+        DisconnectReason, Message, MessageType, SessionFilter, UserInfo, NOTICE_ACCESS_LEVEL_CHANGED,
+        NOTICE_PASSWORD_RESET,
+    },
+};
+use chrono::Utc;
+use uuid::Uuid;
 
-pub async fn handle_server_shutdown(message: &Message, shared_state: ArcRwLock<SharedState>) {
-    let data = message.payload().get_data();
-    let timeout = u64::from_be_bytes(data[0].clone().try_into().unwrap());
+use super::{auth::hash_credentials_queued, Replies};
+use crate::application::{
+    export::write_document,
+    fanout::fan_out,
+    mode::ServerMode,
+    permissions::{Permissions, MAX_TEMPORARY_GRANT},
+    session::AccessLevel,
+    ArcRwLock, SharedState, ShutdownReason, SERVER_ACTOR,
+};
 
-    let read_shared_state = shared_state.read().await;
-    let sessions = read_shared_state.sessions();
+const MAX_SHUTDOWN_TIMEOUT: u64 = 60 * 60;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-    for (id, session) in sessions.iter() {
-        if let Err(e) = session.read().await.send(Message::server_shutdown_warning(timeout)) {
-            tracing::warn!("Error sending server shutdown warning to session {}: {}", id, e);
+pub async fn handle_server_shutdown(request: ServerShutdown, shared_state: ArcRwLock<SharedState>) {
+    let timeout = request.timeout.min(MAX_SHUTDOWN_TIMEOUT);
+
+    let state = shared_state.read().await;
+    let clock = state.clock();
+    let targets = state.session_handles();
+    drop(state);
+
+    let report = fan_out(targets, &Message::server_shutdown_warning(timeout)).await;
+    if !report.failed.is_empty() {
+        tracing::warn!(
+            "Server shutdown warning reached {} of {} sessions",
+            report.delivered,
+            report.attempted()
+        );
+    }
+
+    clock.sleep(Duration::from_secs(timeout)).await;
+
+    stop_server(shared_state, ShutdownReason::AdminRequested).await;
+}
+
+// every session is told why it goes before the listener stops
+pub async fn stop_server(shared_state: ArcRwLock<SharedState>, reason: ShutdownReason) {
+    let targets = shared_state.read().await.session_handles();
+    let disconnect = Message::disconnect(DisconnectReason::ServerShutdown, "Server is shutting down");
+    let report = fan_out(targets, &disconnect).await;
+    if !report.failed.is_empty() {
+        tracing::warn!(
+            "Server shutdown reached {} of {} sessions",
+            report.delivered,
+            report.attempted()
+        );
+    }
+
+    shared_state.write().await.shutdown(reason).await;
+}
+
+pub async fn handle_set_server_mode(
+    request: AdminSetServerMode,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let stop_after = request.stop_after.map(|timeout| timeout.min(MAX_SHUTDOWN_TIMEOUT));
+    let mode = match (ServerMode::parse(&request.mode), stop_after) {
+        (Ok(ServerMode::Normal), Some(_)) => {
+            tracing::warn!("Invalid server mode request: only draining can stop the server");
+            replies.send(Response::Nack);
+            return;
+        }
+        (Ok(mode), _) => mode,
+        (Err(e), _) => {
+            tracing::warn!("Invalid server mode request: {}", e);
+            replies.send(Response::Nack);
+            return;
+        }
+    };
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+    let previous = state.mode();
+    state.set_mode(mode);
+
+    let stop_note = stop_after
+        .map(|seconds| format!(" (stops within {} seconds)", seconds))
+        .unwrap_or_default();
+    tracing::info!("Server mode changed from {} to {}{}", previous, mode, stop_note);
+    state.audit(
+        &actor,
+        "set_server_mode",
+        format!("{} -> {}{}", previous, mode, stop_note),
+    );
+    drop(state);
+
+    handle_server_stats(replies, Arc::clone(&shared_state)).await;
+
+    if let Some(seconds) = stop_after {
+        tokio::spawn(drain_then_stop(shared_state, session_id, Duration::from_secs(seconds)));
+    }
+}
+
+// the session that asked for the drain does not keep the server alive
+async fn drain_then_stop(shared_state: ArcRwLock<SharedState>, requester: Uuid, timeout: Duration) {
+    let clock = shared_state.read().await.clock();
+    let deadline = clock.instant() + timeout;
+    let mut interval = clock.interval(DRAIN_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let state = shared_state.read().await;
+        if state.mode() != ServerMode::Draining {
+            tracing::info!("Drain was cancelled, the server keeps running");
+            return;
+        }
+        let remaining = state.sessions().keys().filter(|id| **id != requester).count();
+        drop(state);
+
+        if remaining == 0 {
+            tracing::info!("All sessions drained, stopping the server");
+            break;
+        }
+        if clock.instant() >= deadline {
+            tracing::warn!("Drain timed out with {} sessions left, stopping the server", remaining);
+            break;
+        }
+    }
+
+    stop_server(shared_state, ShutdownReason::Drained).await;
+}
+
+// written to the data directory, `import-users` on another instance picks it up from there
+pub async fn handle_export_state(replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let state = shared_state.read().await;
+    let document = state.export_users();
+    let users = state.user_count() as u64;
+    let data_dir = state.data_dir().to_path_buf();
+    drop(state);
+
+    let path = data_dir.join(format!("users-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3f")));
+    let written = std::fs::create_dir_all(&data_dir).and_then(|_| write_document(&path, &document));
+    if let Err(e) = written {
+        tracing::error!("Could not export state to {}: {}", path.display(), e);
+        replies.send(Response::Nack);
+        return;
+    }
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+    state.audit(&actor, "export_state", path.display().to_string());
+    drop(state);
+
+    tracing::info!("Exported {} users to {}", users, path.display());
+    replies.send(Response::StateExported {
+        path: path.to_string_lossy().to_string(),
+        users,
+    });
+}
+
+pub async fn handle_server_stats(replies: &Replies, shared_state: ArcRwLock<SharedState>) {
+    let stats = shared_state.read().await.server_stats().await;
+    replies.send(Response::ServerStats(stats));
+}
+
+// without the broadcast flag only the admin sees the new message, everyone else gets it on the next connection
+pub async fn handle_set_motd(
+    request: AdminSetMotd,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (motd, broadcast) = (request.text.trim(), request.broadcast);
+
+    let mut state = shared_state.write().await;
+    if let Err(e) = state.set_motd(motd) {
+        tracing::warn!("Could not set the message of the day: {}", e);
+        replies.send(Response::Nack);
+        return;
+    }
+    let actor = state.actor_of(session_id).await;
+    let note = if broadcast { " (broadcast)" } else { "" };
+    tracing::info!("Message of the day changed by {}{}", actor, note);
+    state.audit(&actor, "set_motd", format!("'{}'{}", motd, note));
+    drop(state);
+
+    if !broadcast {
+        replies.send(Response::Motd { text: motd.to_string() });
+        return;
+    }
+    let targets = shared_state.read().await.session_handles();
+    let report = fan_out(targets, &Message::motd(motd)).await;
+    if !report.failed.is_empty() {
+        tracing::warn!(
+            "Message of the day reached {} of {} sessions",
+            report.delivered,
+            report.attempted()
+        );
+    }
+}
+
+// relay counters for troubleshooting messages that do not arrive
+pub async fn handle_user_info(request: AdminUserInfo, replies: &Replies, shared_state: ArcRwLock<SharedState>) {
+    let state = shared_state.read().await;
+    let Some(info) = user_info(&state, &request.username).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    drop(state);
+
+    replies.send(Response::UserInfo(info));
+}
+
+// None for a user that does not exist
+async fn user_info(state: &SharedState, username: &str) -> Option<UserInfo> {
+    let (user, counters) = (state.get_user(username)?, state.relay_counters(username)?);
+    let now = state.clock().now();
+    Some(UserInfo {
+        username: user.name().to_string(),
+        access_level: user.access_level().as_str().to_string(),
+        sessions: state.sessions_of_user(username).await.len() as u64,
+        sent: counters.sent,
+        delivered: counters.delivered,
+        queued: counters.queued,
+        dropped: counters.dropped,
+        last_error: counters.last_error.unwrap_or_default(),
+        temporary_grants: user
+            .temporary_grants()
+            .iter()
+            .filter(|grant| grant.expires_at > now)
+            .map(|grant| (grant.permission.to_string(), grant.expires_at.to_rfc3339()))
+            .collect(),
+    })
+}
+
+pub async fn handle_list_sessions(filter: SessionFilter, replies: &Replies, shared_state: ArcRwLock<SharedState>) {
+    let sessions = shared_state.read().await.list_sessions(&filter).await;
+    let count = sessions.len() as u64;
+    for (_, _, info) in sessions {
+        replies.send(Response::SessionInfo(info));
+    }
+    replies.send(Response::SessionListEnd { count, kicked: false });
+}
+
+pub async fn handle_set_log_level(
+    request: AdminSetLogLevel,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (directives, revert_after) = (request.directives.as_str(), request.revert_after);
+
+    let actor = shared_state.read().await.actor_of(session_id).await;
+
+    let mut state = shared_state.write().await;
+    let Some(log_control) = state.log_control_mut() else {
+        tracing::warn!("Log level control is not available");
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let previous = match log_control.set_filter(directives) {
+        Ok(previous) => previous,
+        Err(e) => {
+            tracing::warn!("{}", e);
+            replies.send(Response::Nack);
+            return;
         }
+    };
+    let current = log_control.filter();
+    let generation = log_control.generation();
+
+    let revert_note = revert_after
+        .map(|seconds| format!(" (reverts in {} seconds)", seconds))
+        .unwrap_or_default();
+    state.audit(
+        &actor,
+        "set_log_level",
+        format!("{} -> {}{}", previous, current, revert_note),
+    );
+    drop(state);
+
+    replies.send(Response::AdminLogLevelChanged {
+        previous: previous.clone(),
+        current,
+    });
+
+    if let Some(seconds) = revert_after {
+        tokio::spawn(revert_log_level(
+            shared_state,
+            previous,
+            generation,
+            Duration::from_secs(seconds),
+        ));
+    }
+}
+
+async fn revert_log_level(shared_state: ArcRwLock<SharedState>, previous: String, generation: u64, delay: Duration) {
+    tokio::time::sleep(delay).await;
+
+    let mut state = shared_state.write().await;
+    let Some(log_control) = state.log_control_mut() else {
+        return;
+    };
+
+    // a later change supersedes the pending revert
+    if log_control.generation() != generation {
+        return;
     }
 
-    tokio::time::sleep(tokio::time::Duration::from_secs(timeout)).await;
+    match log_control.set_filter(&previous) {
+        Ok(reverted) => state.audit(
+            SERVER_ACTOR,
+            "revert_log_level",
+            format!("{} -> {}", reverted, previous),
+        ),
+        Err(e) => tracing::warn!("Could not revert log level: {}", e),
+    }
+}
 
-    for (id, session) in sessions.iter() {
-        if let Err(e) = session.read().await.send(Message::DISCONNECT) {
-            tracing::warn!("Error sending server shutdown to session {}: {}", id, e);
+pub async fn handle_set_access_level(
+    request: AdminSetAccessLevel,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let username = request.username;
+    let level = match AccessLevel::parse(&request.level) {
+        Ok(level) => level,
+        Err(e) => {
+            tracing::warn!("Invalid access level request: {}", e);
+            replies.send(Response::Nack);
+            return;
         }
+    };
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+
+    // demoting yourself could leave the server without an admin
+    if actor == username {
+        tracing::warn!("{} tried to change their own access level", actor);
+        replies.send(Response::Nack);
+        return;
+    }
+
+    let Some(previous) = state.set_access_level(&username, level.clone()).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    state.audit(
+        &actor,
+        "set_access_level",
+        format!("{}: {} -> {}", username, previous.as_str(), level.as_str()),
+    );
+
+    let changed = Message::access_level_changed(&username, level.as_str());
+    let detail = format!("Your access level was changed to {}", level.as_str());
+    state
+        .notify_user(&username, changed, NOTICE_ACCESS_LEVEL_CHANGED, &detail)
+        .await;
+    drop(state);
+
+    replies.send(Response::AccessLevelChanged {
+        username,
+        level: level.as_str().to_string(),
+    });
+}
+
+// only a permission the admin holds themselves, answered with the user's info so the grant shows
+pub async fn handle_grant_temporary(
+    request: AdminGrantTemporary,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (username, name, seconds) = (request.username, request.permission.as_str(), request.seconds);
+    let reject = |reason: String| {
+        replies.send(Response::Rejected {
+            request: MessageType::AdminGrantTemporary,
+            reason,
+        });
+    };
+    let Some(permission) = Permissions::named(name) else {
+        return reject(format!("Unknown permission '{}'", name));
+    };
+    let duration = Duration::from_secs(seconds);
+    if duration.is_zero() || duration > MAX_TEMPORARY_GRANT {
+        return reject(format!(
+            "A grant lasts between 1 second and {} hours",
+            MAX_TEMPORARY_GRANT.as_secs() / 3600
+        ));
     }
 
-    drop(read_shared_state);
-    shared_state.write().await.shutdown().await;
+    let mut state = shared_state.write().await;
+    if !state.permissions_of(session_id).await.contains(permission) {
+        return reject(format!("You do not hold {} yourself", name));
+    }
+    let actor = state.actor_of(session_id).await;
+    if state.grant_temporary(&username, permission, duration, &actor).is_none() {
+        return reject(format!("Unknown user '{}'", username));
+    }
+    tracing::info!("{} granted {} to {} for {} seconds", actor, name, username, seconds);
+    state.audit(
+        &actor,
+        "grant_temporary",
+        format!("{}: {} for {} seconds", username, name, seconds),
+    );
+    let info = user_info(&state, &username).await;
+    drop(state);
+
+    if let Some(info) = info {
+        replies.send(Response::UserInfo(info));
+    }
+}
+
+// ends a grant before it lapses, answered like the grant
+pub async fn handle_revoke_temporary(
+    request: AdminRevokeTemporary,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (username, name) = (request.username, request.permission.as_str());
+    let reject = |reason: String| {
+        replies.send(Response::Rejected {
+            request: MessageType::AdminRevokeTemporary,
+            reason,
+        });
+    };
+    let Some(permission) = Permissions::named(name) else {
+        return reject(format!("Unknown permission '{}'", name));
+    };
+
+    let mut state = shared_state.write().await;
+    let Some(grant) = state.revoke_temporary(&username, permission) else {
+        return reject(format!("{} has no temporary grant of {}", username, name));
+    };
+    let actor = state.actor_of(session_id).await;
+    state.audit(
+        &actor,
+        "revoke_temporary",
+        format!("{}: {}, granted by {}", username, name, grant.granted_by),
+    );
+    let info = user_info(&state, &username).await;
+    drop(state);
+
+    if let Some(info) = info {
+        replies.send(Response::UserInfo(info));
+    }
+}
+
+pub async fn handle_reset_password(
+    request: AdminResetPassword,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let username = request.username;
+    if request.password.is_empty() {
+        tracing::warn!("Invalid password reset request: empty password");
+        replies.send(Response::Nack);
+        return;
+    }
+
+    let (hash, auth_key) = match hash_credentials_queued(&shared_state, &username, &request.password).await {
+        Ok(Ok(credentials)) => credentials,
+        Ok(Err(e)) => {
+            tracing::error!("Could not hash password: {}", e);
+            replies.send(Response::Nack);
+            return;
+        }
+        Err(busy) => {
+            replies.send(Response::Rejected {
+                request: MessageType::AdminResetPassword,
+                reason: format!("The server is busy, try again in {} seconds", busy.retry_after),
+            });
+            return;
+        }
+    };
+
+    let mut state = shared_state.write().await;
+    if !state.set_password(&username, hash, auth_key) {
+        replies.send(Response::Nack);
+        return;
+    }
+
+    let actor = state.actor_of(session_id).await;
+    let sessions = state.sessions_of_user(&username).await;
+    for &id in &sessions {
+        if id != session_id {
+            state.expire_session(id, "Password was reset by an administrator").await;
+        }
+    }
+    if sessions.is_empty() {
+        state.queue_missed_notice(
+            &username,
+            NOTICE_PASSWORD_RESET,
+            "Your password was reset by an administrator",
+        );
+    }
+    state.audit(&actor, "reset_password", username.to_string());
+    drop(state);
+
+    replies.send(Response::PasswordChanged { username });
+}
+
+pub async fn handle_rename_user(
+    request: AdminRenameUser,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let AdminRenameUser { old_name, new_name } = request;
+
+    let mut state = shared_state.write().await;
+    if let Err(e) = state.rename_user(&old_name, &new_name) {
+        tracing::debug!("Could not rename {} to {}: {}", old_name, new_name, e);
+        replies.send(Response::Nack);
+        return;
+    }
+
+    // the requester may itself have been renamed
+    let actor = state.actor_of(session_id).await;
+    state.audit(&actor, "rename_user", format!("{} -> {}", old_name, new_name));
+    if !state.announce_rename(&old_name, &new_name).await.contains(&actor) {
+        replies.send(Response::UserRenamed { old_name, new_name });
+    }
+}
+
+// the report lists what was found, with the repair flag the dangling references in it are gone already
+pub async fn handle_consistency_check(
+    request: AdminConsistencyCheck,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let repair = request.repair;
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+    let report = state.check_consistency(&actor, repair).await;
+    drop(state);
+
+    let repaired = report.violations.iter().filter(|violation| violation.repaired).count();
+    tracing::info!(
+        "{} ran a consistency check: {} violations, {} repaired",
+        actor,
+        report.violations.len(),
+        repaired
+    );
+    replies.send(Response::ConsistencyReport(report));
 }
@@ -0,0 +1,69 @@
+use chat_core::{
+    dto::{ApiTokenCreate, ApiTokenRevoke, Response},
+    protocol::MessageType,
+};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+pub async fn handle_api_token_create(
+    request: ApiTokenCreate,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let now = shared_state.clock().now();
+    match shared_state.api_tokens_mut().create(&user, &request.label, now) {
+        Ok((info, token)) => {
+            shared_state.audit(&user, "create_api_token", format!("#{} '{}'", info.id, info.label));
+            replies.send(Response::ApiTokenCreated { info, token });
+        }
+        Err(e) => {
+            tracing::debug!("{} cannot create an API token: {}", user, e);
+            replies.send(Response::Rejected {
+                request: MessageType::ApiTokenCreate,
+                reason: e,
+            });
+        }
+    }
+}
+
+pub async fn handle_api_token_revoke(
+    request: ApiTokenRevoke,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let id = request.id;
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    match shared_state.api_tokens_mut().revoke(&user, id) {
+        Ok(()) => {
+            shared_state.audit(&user, "revoke_api_token", format!("#{}", id));
+            replies.send(Response::ApiTokens(shared_state.api_tokens().list_for(&user)));
+        }
+        Err(e) => replies.send(Response::Rejected {
+            request: MessageType::ApiTokenRevoke,
+            reason: e,
+        }),
+    }
+}
+
+pub async fn handle_api_token_list(replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let shared_state = shared_state.read().await;
+    match shared_state.get_user_by_session(&session_id).await {
+        Some(user) => replies.send(Response::ApiTokens(shared_state.api_tokens().list_for(&user))),
+        None => replies.send(Response::Nack),
+    }
+}
@@ -1,74 +1,423 @@
+use std::sync::OnceLock;
+
 use argon2::Config;
-use chat_core::protocol::Message;
-use tokio::sync::mpsc;
+use chat_core::{
+    auth::{derive_key, hash_api_token, verify_response, PLAIN_LOGIN_REQUIRED},
+    dto::{Auth, AuthChallenge, AuthCreate, PasswordChange, RenameAccount, Response},
+    protocol::ErrorCode,
+};
 use uuid::Uuid;
 
-use crate::application::{user::User, ArcRwLock, SharedState};
+use super::Replies;
+use crate::application::{
+    auth_limit::AuthBusy,
+    session::TakeoverPolicy,
+    user::{validate_username, User},
+    ArcRwLock, SharedState,
+};
+
+pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+    let config = Config::default();
+    // TODO: create a random salt for each user
+    argon2::hash_encoded(password.as_bytes(), b"randomsalt", &config)
+}
+
+// the password hash and the login key always change together
+pub fn hash_credentials(username: &str, password: &str) -> Result<(String, String), String> {
+    let hash = hash_password(password).map_err(|e| e.to_string())?;
+    Ok((hash, derive_key(username, password)?))
+}
+
+// hash_credentials once the auth limiter gives it a turn, on the blocking pool
+pub async fn hash_credentials_queued(
+    shared_state: &ArcRwLock<SharedState>,
+    username: &str,
+    password: &str,
+) -> Result<Result<(String, String), String>, AuthBusy> {
+    let limiter = shared_state.read().await.auth_limiter();
+    let (username, password) = (username.to_string(), password.to_string());
+    limiter.run(move || hash_credentials(&username, &password)).await
+}
+
+// unknown users are checked against this, so a failed login costs the same argon2 run whether the name exists or not
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("").expect("Could not hash the dummy password"))
+}
+
+// challenges for unknown users are checked against this, made from a secret that never leaves the server
+// so nobody can answer for it, and the same name always gets the same key
+fn fake_key(username: &str) -> String {
+    static SECRET: OnceLock<String> = OnceLock::new();
+    let secret = SECRET.get_or_init(|| format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()));
+    hash_api_token(&format!("{}:{}", secret, username))
+}
+
+// once the auth limiter gives it a turn, on the blocking pool, without a hash the dummy is checked and nothing matches
+async fn verify_password(
+    shared_state: &ArcRwLock<SharedState>,
+    hash: Option<&str>,
+    password: &[u8],
+) -> Result<bool, AuthBusy> {
+    let limiter = shared_state.read().await.auth_limiter();
+    let known = hash.is_some();
+    let (hash, password) = (hash.map(str::to_string), password.to_vec());
+    let verified = limiter
+        .run(move || argon2::verify_encoded(hash.as_deref().unwrap_or(dummy_hash()), &password).unwrap_or(false))
+        .await?;
+    Ok(verified && known)
+}
+
+// Auth and RenameAccount carry the password as raw bytes, derived once the auth limiter gives it a turn, on the blocking pool
+async fn derive_key_queued(
+    shared_state: &ArcRwLock<SharedState>,
+    username: &str,
+    password: &[u8],
+) -> Result<Option<String>, AuthBusy> {
+    let limiter = shared_state.read().await.auth_limiter();
+    let (username, password) = (username.to_string(), password.to_vec());
+    limiter
+        .run(move || {
+            std::str::from_utf8(&password)
+                .ok()
+                .and_then(|password| derive_key(&username, password).ok())
+        })
+        .await
+}
+
+pub async fn handle_auth(request: Auth, replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    if shared_state.read().await.is_authenticated(session_id).await {
+        replies.send(Response::Nack);
+        return;
+    }
+    if !shared_state.read().await.plain_auth() {
+        auth_fail(replies, &shared_state, session_id, ErrorCode::PasswordLoginDisabled).await;
+        return;
+    }
+    let password = request.password.as_slice();
+
+    let user = shared_state.read().await.get_user(&request.username).cloned();
+    let verified = match verify_password(&shared_state, user.as_ref().map(User::pw_hash), password).await {
+        Ok(verified) => verified,
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    };
+    shared_state.write().await.record_password_check();
+    let Some(user) = user.filter(|_| verified) else {
+        auth_fail(replies, &shared_state, session_id, ErrorCode::InvalidCredentials).await;
+        return;
+    };
+
+    // accounts from before login challenges get their key with the first password login,
+    // with the queue full the login still goes through and the key waits for the next one
+    if user.auth_key().is_none() {
+        if let Ok(Some(key)) = derive_key_queued(&shared_state, user.name(), password).await {
+            shared_state.write().await.set_auth_key(user.name(), key);
+        }
+    }
+    log_in(&user, replies, &shared_state, session_id).await;
+}
 
-pub async fn handle_auth(
-    message: &Message,
-    tx: mpsc::UnboundedSender<Message>,
+pub async fn handle_auth_challenge(
+    request: AuthChallenge,
+    replies: &Replies,
     shared_state: ArcRwLock<SharedState>,
     session_id: Uuid,
 ) {
     if shared_state.read().await.is_authenticated(session_id).await {
-        tx.send(Message::NACK).unwrap();
+        replies.send(Response::Nack);
         return;
     }
-    let data = message.payload().get_data();
-    let username = std::str::from_utf8(&data[0]).unwrap();
 
-    let user = shared_state.read().await.get_user(username).cloned();
-    if let Some(user) = user {
-        if user.session_id().is_some() {
-            tx.send(Message::auth_fail("User already logged in")).unwrap();
+    let state = shared_state.read().await;
+    let user = state.get_user(&request.username).cloned();
+    let nonce = state.nonce_of(session_id).await.unwrap_or_default();
+    let plain_auth = state.plain_auth();
+    drop(state);
+
+    // only accounts that exist and have no key yet are sent to the password login,
+    // unknown users are answered like a wrong response, so nobody can probe which names exist
+    let key = user.as_ref().and_then(User::auth_key);
+    if user.is_some() && key.is_none() && plain_auth {
+        replies.send(Response::AuthFailure {
+            code: None,
+            error: PLAIN_LOGIN_REQUIRED.to_string(),
+        });
+        return;
+    }
+    let fake = fake_key(&request.username);
+    let verified = verify_response(key.unwrap_or(&fake), &nonce, &request.response);
+    match user {
+        Some(user) if verified && key.is_some() => log_in(&user, replies, &shared_state, session_id).await,
+        _ => auth_fail(replies, &shared_state, session_id, ErrorCode::InvalidCredentials).await,
+    }
+}
+
+// the credentials were checked, whichever way they arrived
+async fn log_in(user: &User, replies: &Replies, shared_state: &ArcRwLock<SharedState>, session_id: Uuid) {
+    let existing = user.session_id().filter(|id| *id != session_id);
+    if let Some(existing) = existing {
+        if let Err(e) = check_takeover(shared_state, existing, session_id).await {
+            replies.send(Response::AuthFailure {
+                code: None,
+                error: e.to_string(),
+            });
             return;
         }
-        if argon2::verify_encoded(&user.pw_hash(), &data[1]).unwrap() {
-            shared_state
-                .write()
-                .await
-                .authenticate(session_id, user.name().to_string())
-                .await;
-
-            tx.send(Message::auth_success()).unwrap();
-            return;
+    }
+
+    let mut state = shared_state.write().await;
+    if let Some(existing) = existing.filter(|_| state.takeover_policy() != TakeoverPolicy::Allow) {
+        state.take_over_session(user.name(), existing, session_id).await;
+    }
+    state.authenticate(session_id, user.name()).await;
+    drop(state);
+
+    replies.send(Response::AuthSuccess {
+        user_id: user.id().to_string(),
+        access_level: user.access_level().as_str().to_string(),
+    });
+    let mut state = shared_state.write().await;
+    for notice in state.take_missed_notices(user.name()) {
+        replies.send(Response::Frame(notice));
+    }
+    // taken first so expired messages are already out of the count, a syncing client pulls the queue itself
+    let (offline_messages, offline_summary) = match state.offline_sync(session_id).await {
+        true => (Vec::new(), Some(state.offline_summary(user.name()).await)),
+        false => (state.take_offline_messages(user.name()).await, None),
+    };
+    // the summary goes ahead of the queued messages, so the client knows what is coming
+    let unread = state.unread().summary(user.name());
+    if !unread.is_empty() {
+        replies.send(Response::UnreadSummary(unread));
+    }
+    for message in offline_messages {
+        replies.send(Response::Frame(message));
+    }
+    if let Some(summary) = offline_summary.filter(|summary| summary.total > 0) {
+        replies.send(Response::OfflineSummary(summary));
+    }
+}
+
+// in the language the client asked for, the code is there for clients that translate on their own
+async fn auth_fail(replies: &Replies, shared_state: &ArcRwLock<SharedState>, session_id: Uuid, code: ErrorCode) {
+    let error = shared_state.read().await.error_text(session_id, code, &[]).await;
+    replies.send(Response::AuthFailure {
+        code: Some(code),
+        error,
+    });
+}
+
+// nothing was checked, the client logs in again once the queue had time to drain
+async fn auth_busy(replies: &Replies, shared_state: &ArcRwLock<SharedState>, session_id: Uuid, busy: AuthBusy) {
+    let seconds = busy.retry_after.to_string();
+    let error = shared_state
+        .read()
+        .await
+        .error_text(session_id, ErrorCode::ServerBusy, &[("seconds", &seconds)])
+        .await;
+    tracing::debug!("Session {} turned away from the auth queue", session_id);
+    replies.send(Response::AuthBusy {
+        retry_after: busy.retry_after,
+        error,
+    });
+}
+
+async fn check_takeover(
+    shared_state: &ArcRwLock<SharedState>,
+    existing: Uuid,
+    session_id: Uuid,
+) -> Result<(), &'static str> {
+    let state = shared_state.read().await;
+
+    match state.takeover_policy() {
+        TakeoverPolicy::Reject => Err("User already logged in"),
+        TakeoverPolicy::Replace { same_peer: false } | TakeoverPolicy::Allow => Ok(()),
+        TakeoverPolicy::Replace { same_peer: true } => {
+            let old_ip = state.peer_of(existing).await.map(|peer| peer.ip());
+            let new_ip = state.peer_of(session_id).await.map(|peer| peer.ip());
+
+            // a session that is already gone cannot be hijacked
+            if old_ip.is_none() || old_ip == new_ip {
+                Ok(())
+            } else {
+                Err("User already logged in from another address")
+            }
         }
     }
-    tx.send(Message::auth_fail("Invalid username or password")).unwrap();
 }
 
 pub async fn handle_auth_create(
-    message: &Message,
-    tx: mpsc::UnboundedSender<Message>,
+    request: AuthCreate,
+    replies: &Replies,
     shared_state: ArcRwLock<SharedState>,
     session_id: Uuid,
 ) {
     if shared_state.read().await.is_authenticated(session_id).await {
-        tx.send(Message::NACK).unwrap();
+        replies.send(Response::Nack);
+        return;
+    }
+    let username = request.username;
+
+    if let Err(error) = validate_username(&username) {
+        replies.send(Response::AuthFailure { code: None, error });
+        return;
+    }
+
+    // hashed before the name is checked, a taken name must not answer faster than a free one
+    let (hash, auth_key) = match hash_credentials_queued(&shared_state, &username, &request.password).await {
+        Ok(Ok(credentials)) => credentials,
+        Ok(Err(e)) => {
+            tracing::error!("Could not hash password: {}", e);
+            replies.send(Response::AuthFailure {
+                code: None,
+                error: "Could not create account".to_string(),
+            });
+            return;
+        }
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    };
+    shared_state.write().await.record_password_check();
+
+    if shared_state.read().await.is_name_available(&username) {
+        let mut user = User::new(&username, hash);
+        user.set_auth_key(Some(auth_key));
+        let user_id = user.id().to_string();
+        let access_level = user.access_level().as_str().to_string();
+
+        shared_state.write().await.add_user(user);
+        shared_state.write().await.authenticate(session_id, &username).await;
+        replies.send(Response::AuthSuccess { user_id, access_level });
+        return;
+    }
+
+    auth_fail(replies, &shared_state, session_id, ErrorCode::UserExists).await;
+}
+
+pub async fn handle_rename_account(
+    request: RenameAccount,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (new_name, password) = (request.new_name, request.password.as_slice());
+
+    let state = shared_state.read().await;
+    let Some(username) = state.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    let hash = state.get_user(&username).map(|user| user.pw_hash().to_string());
+    drop(state);
+
+    match verify_password(&shared_state, hash.as_deref(), password).await {
+        Ok(true) => {}
+        Ok(false) => {
+            replies.send(Response::AuthFailure {
+                code: None,
+                error: "Invalid password".to_string(),
+            });
+            return;
+        }
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    }
+
+    // the password is at hand, so the key for the new name does not have to wait for a password login,
+    // it is derived before the state is locked
+    let key = match derive_key_queued(&shared_state, &new_name, password).await {
+        Ok(key) => key,
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    };
+
+    let mut state = shared_state.write().await;
+    if let Err(error) = state.rename_user(&username, &new_name) {
+        replies.send(Response::AuthFailure { code: None, error });
         return;
     }
-    let data = message.payload().get_data();
-    let username = std::str::from_utf8(&data[0]).unwrap();
-
-    if shared_state.read().await.get_user(username).is_none() {
-        let password = std::str::from_utf8(&data[1]).unwrap();
-        let config = Config::default();
-        // TODO: create a random salt for each user
-        let hash = argon2::hash_encoded(password.as_bytes(), b"randomsalt", &config).unwrap();
-
-        let username_string = username.to_string();
-        let user = User::new(username, hash);
-
-        shared_state.write().await.add_user(username.to_string(), user);
-        shared_state
-            .write()
-            .await
-            .authenticate(session_id, username_string)
-            .await;
-        tx.send(Message::auth_success()).unwrap();
+    if let Some(key) = key {
+        state.set_auth_key(&new_name, key);
+    }
+    state.audit(&new_name, "rename_account", username.clone());
+    state.announce_rename(&username, &new_name).await;
+}
+
+pub async fn handle_password_change(
+    request: PasswordChange,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (old_password, new_password) = (request.old_password.as_slice(), request.new_password.as_str());
+
+    let Some(username) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
         return;
+    };
+
+    let hash = shared_state
+        .read()
+        .await
+        .get_user(&username)
+        .map(|user| user.pw_hash().to_string());
+    match verify_password(&shared_state, hash.as_deref(), old_password).await {
+        Ok(true) => {}
+        Ok(false) => {
+            replies.send(Response::AuthFailure {
+                code: None,
+                error: "Invalid password".to_string(),
+            });
+            return;
+        }
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    }
+
+    if new_password.is_empty() {
+        replies.send(Response::AuthFailure {
+            code: None,
+            error: "Password must not be empty".to_string(),
+        });
+        return;
+    }
+
+    let (hash, auth_key) = match hash_credentials_queued(&shared_state, &username, new_password).await {
+        Ok(Ok(credentials)) => credentials,
+        Ok(Err(e)) => {
+            tracing::error!("Could not hash password: {}", e);
+            replies.send(Response::AuthFailure {
+                code: None,
+                error: "Could not change password".to_string(),
+            });
+            return;
+        }
+        Err(busy) => {
+            auth_busy(replies, &shared_state, session_id, busy).await;
+            return;
+        }
+    };
+
+    let mut state = shared_state.write().await;
+    state.set_password(&username, hash, auth_key);
+    for id in state.sessions_of_user(&username).await {
+        if id != session_id {
+            state.expire_session(id, "Password changed").await;
+        }
     }
+    state.audit(&username, "change_password", String::new());
+    drop(state);
 
-    tx.send(Message::auth_fail("User already exists")).unwrap();
+    replies.send(Response::PasswordChanged { username });
 }
@@ -0,0 +1,181 @@
+use chat_core::{
+    dto::{FileAccept, FileChunk, FileComplete, FileOffer, FileReject, Response},
+    protocol::Message,
+};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+// the id is confirmed before anything is checked, so a rejection can always name the transfer
+pub async fn handle_file_offer(
+    request: FileOffer,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let FileOffer {
+        recipient,
+        filename,
+        size,
+        sha256,
+    } = request;
+
+    let mut shared_state = shared_state.write().await;
+    let Some(sender) = shared_state.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let id = shared_state.file_transfers_mut().next_id();
+    replies.send(Response::FileOffered {
+        transfer_id: id,
+        recipient: recipient.clone(),
+        filename: filename.clone(),
+    });
+    let reject = |reason: String| {
+        replies.send(Response::FileReject {
+            transfer_id: id,
+            reason,
+        })
+    };
+
+    let recipient_session = match shared_state.get_session_by_user(&recipient).await {
+        Some(session) => session.read().await.id(),
+        None => return reject(format!("User {} is not online", recipient)),
+    };
+    if recipient_session == session_id {
+        return reject("Cannot send a file to yourself".to_string());
+    }
+    if filename.is_empty() {
+        return reject("Missing filename".to_string());
+    }
+    if let Err(e) = shared_state
+        .file_transfers_mut()
+        .offer(id, session_id, recipient_session, size)
+    {
+        return reject(e);
+    }
+
+    let relayed = Message::file_offer_relay(&sender, &filename, size, &sha256, id);
+    if !shared_state.send_to_session(recipient_session, relayed).await {
+        shared_state.file_transfers_mut().remove(id);
+        reject("Recipient went offline".to_string());
+    }
+}
+
+pub async fn handle_file_accept(
+    request: FileAccept,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let id = request.transfer_id;
+
+    let mut shared_state = shared_state.write().await;
+    match shared_state.file_transfers_mut().accept(id, session_id) {
+        Ok(transfer) => {
+            let sender = transfer.sender();
+            if !shared_state.send_to_session(sender, Message::file_accept(id)).await {
+                cancel(&mut shared_state, id, "Sender went offline").await;
+            }
+        }
+        Err(e) => {
+            tracing::debug!("Session {} cannot accept transfer {}: {}", session_id, id, e);
+            replies.send(Response::Nack);
+        }
+    }
+}
+
+// either side may reject, before the transfer started or in the middle of it
+pub async fn handle_file_reject(
+    request: FileReject,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let id = request.transfer_id;
+    let reason = request.reason.as_deref().unwrap_or("Rejected");
+
+    let mut shared_state = shared_state.write().await;
+    let peer = shared_state
+        .file_transfers()
+        .get(id)
+        .and_then(|transfer| transfer.peer_of(session_id));
+    match peer {
+        Some(peer) => {
+            shared_state.file_transfers_mut().remove(id);
+            shared_state
+                .send_to_session(peer, Message::file_reject(id, reason))
+                .await;
+        }
+        None => replies.send(Response::Nack),
+    }
+}
+
+pub async fn handle_file_chunk(request: FileChunk, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let FileChunk {
+        transfer_id: id,
+        index,
+        bytes,
+    } = request;
+
+    let mut shared_state = shared_state.write().await;
+    if !is_part_of(&shared_state, id, session_id) {
+        // chunks that were already in flight when the transfer was cancelled
+        tracing::debug!("Dropping chunk {} of unknown transfer {}", index, id);
+        return;
+    }
+
+    let chunk = shared_state
+        .file_transfers_mut()
+        .chunk(id, session_id, index, bytes.len() as u64)
+        .map(|transfer| transfer.recipient());
+    match chunk {
+        Ok(recipient) => {
+            let relayed = Message::file_chunk(id, index, &bytes);
+            if !shared_state.send_to_session(recipient, relayed).await {
+                cancel(&mut shared_state, id, "Recipient went offline").await;
+            }
+        }
+        Err(e) => cancel(&mut shared_state, id, &e).await,
+    }
+}
+
+pub async fn handle_file_complete(request: FileComplete, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let id = request.transfer_id;
+
+    let mut shared_state = shared_state.write().await;
+    if !is_part_of(&shared_state, id, session_id) {
+        tracing::debug!("Dropping completion of unknown transfer {}", id);
+        return;
+    }
+
+    match shared_state.file_transfers_mut().complete(id, session_id) {
+        Ok(transfer) => {
+            shared_state
+                .send_to_session(transfer.recipient(), Message::file_complete(id))
+                .await;
+        }
+        Err(e) => cancel(&mut shared_state, id, &e).await,
+    }
+}
+
+fn is_part_of(shared_state: &SharedState, id: u64, session_id: Uuid) -> bool {
+    shared_state
+        .file_transfers()
+        .get(id)
+        .is_some_and(|transfer| transfer.peer_of(session_id).is_some())
+}
+
+// a broken transfer is told to both ends so neither keeps waiting for it
+async fn cancel(shared_state: &mut SharedState, id: u64, reason: &str) {
+    if let Some(transfer) = shared_state.file_transfers_mut().remove(id) {
+        tracing::debug!("Cancelled transfer {}: {}", id, reason);
+        for session in [transfer.sender(), transfer.recipient()] {
+            shared_state
+                .send_to_session(session, Message::file_reject(id, reason))
+                .await;
+        }
+    }
+}
@@ -1,33 +1,365 @@
-use chat_core::protocol::Message;
-use tokio::sync::mpsc;
+use std::time::Duration;
+
+use chat_core::{
+    dto::{DirectMessageSend, HistoryRequest, MarkConversationRead, MessageDelete, MessageEdit, Response, SyncRequest},
+    protocol::{ErrorCode, Message, MessageId, SyncBatch},
+};
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::application::{ArcRwLock, SharedState};
+use super::Replies;
+use crate::application::{catalog, store::StoredMessage, ArcRwLock, Delivery, RelayCounters, SharedState};
+
+const MAX_HISTORY_ENTRIES: u64 = 100;
+const MAX_SYNC_BATCH: u64 = 100;
+
+// what a sender hands the server to relay, a signature is theirs and passed on untouched
+#[derive(Debug, Clone, Copy)]
+pub struct Outgoing<'a> {
+    pub recipient: &'a str,
+    pub body: &'a str,
+    pub signature: Option<&'a [u8]>,
+}
 
 pub async fn handle_direct_message_send(
-    message: &Message,
-    tx: mpsc::UnboundedSender<Message>,
+    request: DirectMessageSend,
+    replies: &Replies,
     shared_state: ArcRwLock<SharedState>,
     session_id: Uuid,
 ) {
-    let payload = message.payload();
-    let recipient = std::str::from_utf8(&payload.get_data()[0]).unwrap().to_string();
-    let message = std::str::from_utf8(&payload.get_data()[1]).unwrap().to_string();
-    let sender = shared_state
-        .read()
-        .await
-        .get_user_by_session(&session_id)
-        .await
-        .unwrap();
+    let client_id = request.client_id.as_deref();
+    let outgoing = Outgoing {
+        recipient: &request.recipient,
+        body: &request.body,
+        signature: request.signature.as_deref(),
+    };
+    let Some(sender) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let clock = shared_state.clock();
+    let sent_at = clock.now();
+    // a retry whose ack got lost gets the same answer again, the recipient already has it
+    let dedup_key = client_id.zip(shared_state.user_id(&sender));
+    if let Some((client_id, user)) = dedup_key {
+        if let Some(reply) = shared_state
+            .dedup_mut()
+            .get(user, client_id, clock.instant().into_std())
+        {
+            tracing::debug!("{} sent message {} again, not relaying it", sender, client_id);
+            replies.send(Response::Frame(reply));
+            return;
+        }
+    }
+    let expires_at = match expiry(request.ttl, sent_at, shared_state.max_message_ttl()) {
+        Ok(expires_at) => expires_at,
+        Err(error) => {
+            replies.send(Response::MessageError { code: None, error });
+            return;
+        }
+    };
+    let response =
+        match relay_direct_message(&mut shared_state, &sender, outgoing, sent_at, expires_at, session_id).await {
+            Ok(id) => Message::from(Response::MessageAck {
+                id,
+                client_id: client_id.map(str::to_string),
+            }),
+            // nothing was stored, a retry is a fresh attempt
+            Err((code, error)) => {
+                replies.send(Response::MessageError {
+                    code: Some(code),
+                    error,
+                });
+                return;
+            }
+        };
+    if let Some((client_id, user)) = dedup_key {
+        shared_state
+            .dedup_mut()
+            .insert(user, client_id, response.clone(), clock.instant().into_std());
+    }
+    replies.send(Response::Frame(response));
+}
+
+// everything after the request was checked, also the way a scheduled message goes out once it is due
+pub async fn relay_direct_message(
+    shared_state: &mut SharedState,
+    sender: &str,
+    outgoing: Outgoing<'_>,
+    sent_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    session_id: Uuid,
+) -> Result<MessageId, (ErrorCode, String)> {
+    let Outgoing {
+        recipient,
+        body: message,
+        signature,
+    } = outgoing;
+    let signed = |relayed: Message| match signature {
+        Some(signature) => relayed.with_signature(signature),
+        None => relayed,
+    };
+    count_relay(shared_state, sender, |counters| counters.sent += 1);
+    // the recipient is addressed by the name it registered with from here on
+    let Some(recipient) = shared_state.resolve_username(recipient).map(str::to_string) else {
+        let params = [("user", recipient)];
+        let error = catalog::built_in(ErrorCode::UserNotFound.as_str(), &params);
+        count_relay(shared_state, sender, |counters| counters.drop_message(&error));
+        let error = shared_state
+            .error_text(session_id, ErrorCode::UserNotFound, &params)
+            .await;
+        return Err((ErrorCode::UserNotFound, error));
+    };
+    let recipient = recipient.as_str();
+    let id = shared_state
+        .message_store_mut()
+        .record(sender, recipient, message, sent_at, expires_at, signature);
+    tracing::Span::current().record("message_id", id.get());
+
+    if recipient == sender {
+        let note = signed(Message::self_note(sender, message, sent_at, id, expires_at));
+        deliver_self_note(shared_state, sender, session_id, note).await;
+        count_relay(shared_state, sender, |counters| counters.delivered += 1);
+        return Ok(id);
+    }
+
+    // queued messages keep their original stamp and are delivered on the next login,
+    // a muted sender's messages arrive all the same, only without asking for attention
+    let muted = shared_state.is_muted(recipient, sender);
+    let relayed = signed(match muted {
+        true => Message::muted_direct_message(sender, message, sent_at, id, expires_at),
+        false => Message::direct_message_receive(sender, message, sent_at, id, expires_at),
+    });
+    let dnd = shared_state
+        .get_user(recipient)
+        .is_some_and(|user| user.preferences().dnd());
+    let relayed = shared_state.deliver_to_user(recipient, relayed).await;
+    count_relay(shared_state, sender, |counters| match &relayed {
+        Ok(Delivery::Delivered) => counters.delivered += 1,
+        Ok(Delivery::Queued) => counters.queued += 1,
+        Err((_, error)) => counters.drop_message(error),
+    });
+    match relayed {
+        Ok(Delivery::Delivered) if !dnd => Ok(id),
+        Ok(_) if muted => Ok(id),
+        // a recipient in do not disturb mode catches up on it like on a queued message
+        Ok(_) => {
+            shared_state.unread_mut().increment(recipient, sender);
+            Ok(id)
+        }
+        Err((code, _)) => {
+            shared_state.message_store_mut().forget(id);
+            let code = match code {
+                ErrorCode::RecipientOffline if shared_state.conceal_users() => ErrorCode::UserNotFound,
+                code => code,
+            };
+            let error = shared_state.error_text(session_id, code, &[("user", recipient)]).await;
+            Err((code, error))
+        }
+    }
+}
+
+// edits and deletes answer with the notice itself, an ACK would resolve the client's in-flight message
+pub async fn handle_message_edit(
+    request: MessageEdit,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let MessageEdit { id, body } = request;
+    tracing::Span::current().record("message_id", id.get());
+    let Some(requester) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let edited_at = shared_state.clock().now();
+    let recipient = match editable_message(&mut shared_state, id, &requester) {
+        Ok(stored) => {
+            stored.edit(&body, edited_at);
+            stored.recipient().to_string()
+        }
+        Err(e) => {
+            tracing::debug!("{} cannot edit message {}: {}", requester, id, e);
+            replies.send(Response::Nack);
+            return;
+        }
+    };
+
+    let notice = Response::MessageEdited {
+        id,
+        sender: requester,
+        body,
+        edited_at,
+    };
+    if let Err((_, e)) = shared_state.deliver_to_user(&recipient, notice.clone().into()).await {
+        tracing::warn!("Could not relay edit of message {} to {}: {}", id, recipient, e);
+    }
+    replies.send(notice);
+}
+
+pub async fn handle_message_delete(
+    request: MessageDelete,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let id = request.id;
+    tracing::Span::current().record("message_id", id.get());
+    let Some(requester) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let recipient = match editable_message(&mut shared_state, id, &requester) {
+        Ok(stored) => {
+            stored.delete();
+            stored.recipient().to_string()
+        }
+        Err(e) => {
+            tracing::debug!("{} cannot delete message {}: {}", requester, id, e);
+            replies.send(Response::Nack);
+            return;
+        }
+    };
+
+    let notice = Response::MessageDeleted { id, sender: requester };
+    if let Err((_, e)) = shared_state.deliver_to_user(&recipient, notice.clone().into()).await {
+        tracing::warn!("Could not relay deletion of message {} to {}: {}", id, recipient, e);
+    }
+    replies.send(notice);
+}
+
+pub async fn handle_history_request(
+    request: HistoryRequest,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let limit = request.limit.min(MAX_HISTORY_ENTRIES);
 
     let shared_state = shared_state.read().await;
+    let Some(requester) = shared_state.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let entries = shared_state.message_store().conversation(
+        &requester,
+        &request.peer,
+        request.before,
+        limit as usize,
+        shared_state.deleted_history(),
+        shared_state.clock().now(),
+    );
+    for entry in &entries {
+        replies.send(Response::Frame(entry.to_history_entry()));
+    }
+    replies.send(Response::HistoryEnd {
+        count: entries.len() as u64,
+    });
+}
+
+// the batch is announced with a sequence per message, which the client acknowledges in its next request
+pub async fn handle_sync_request(
+    request: SyncRequest,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let limit = request.limit.min(MAX_SYNC_BATCH);
+
+    // sent under the lock, so nothing delivered to this user meanwhile ends up between the batch and its header
+    let mut state = shared_state.write().await;
+    let Some(user) = state.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    let (batch, remaining) = state.offline_batch(&user, request.cursor, limit as usize).await;
+    let (sequences, messages): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    replies.send(Response::SyncBatch(SyncBatch { sequences, remaining }));
+    for message in messages {
+        replies.send(Response::Frame(message));
+    }
+}
+
+// answers with what is still unread, so the client can refresh its counters
+pub async fn handle_mark_conversation_read(
+    request: MarkConversationRead,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    shared_state.unread_mut().clear(&user, &request.peer);
+    replies.send(Response::UnreadSummary(shared_state.unread().summary(&user)));
+}
+
+// a time to live in seconds, zero or none keeps the message
+fn expiry(ttl: Option<u64>, sent_at: DateTime<Utc>, max_ttl: Duration) -> Result<Option<DateTime<Utc>>, String> {
+    let ttl = ttl.unwrap_or_default();
+    if ttl == 0 {
+        return Ok(None);
+    }
+    if ttl > max_ttl.as_secs() {
+        return Err(format!(
+            "Time to live of {} seconds exceeds the maximum of {} seconds",
+            ttl,
+            max_ttl.as_secs()
+        ));
+    }
+    Ok(Some(sent_at + chrono::Duration::seconds(ttl as i64)))
+}
+
+// only the original sender may change a message, and only until the edit window closes
+fn editable_message<'a>(
+    shared_state: &'a mut SharedState,
+    id: MessageId,
+    requester: &str,
+) -> Result<&'a mut StoredMessage, String> {
+    let edit_window = shared_state.edit_window();
+    let now = shared_state.clock().now();
+    let stored = shared_state
+        .message_store_mut()
+        .get_mut(id)
+        .ok_or_else(|| format!("Message {} does not exist", id))?;
+
+    if stored.sender() != requester {
+        return Err("Not the sender".into());
+    }
+    if stored.is_deleted() {
+        return Err("Message was deleted".into());
+    }
+    if stored.is_expired(now) {
+        return Err("Message has expired".into());
+    }
+    let age = now.signed_duration_since(stored.sent_at());
+    if age.to_std().unwrap_or_default() > edit_window {
+        return Err("Edit window has closed".into());
+    }
+    Ok(stored)
+}
+
+// notes only go to the author's other sessions that are online now, nothing is queued or counted as unread
+async fn deliver_self_note(shared_state: &SharedState, owner: &str, from: Uuid, note: Message) {
+    for session_id in shared_state.sessions_of_user(owner).await {
+        if session_id != from {
+            shared_state.send_to_session(session_id, note.clone()).await;
+        }
+    }
+}
 
-    if let Some(session) = shared_state.get_session_by_user(&recipient).await {
-        let other_session = session.read().await;
-        let message = Message::direct_message_receive(&sender, &message);
-        other_session.send(message).unwrap();
-    } else {
-        tx.send(Message::message_error(&format!("User {} is not connected", recipient)))
-            .unwrap();
+fn count_relay(shared_state: &mut SharedState, sender: &str, count: impl FnOnce(&mut RelayCounters)) {
+    if let Some(counters) = shared_state.relay_counters_mut(sender) {
+        count(counters);
     }
 }
@@ -1,24 +1,92 @@
-use chat_core::protocol::Message;
-use chrono::{DateTime, Utc};
+use chat_core::{
+    dto::{ClientHello, Heartbeat, Response, TimeSync},
+    protocol::Message,
+};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use super::{ArcRwLock, SharedState};
+use super::{catalog, ArcRwLock, SharedState};
 
 pub mod admin;
+pub mod api_token;
 pub mod auth;
+pub mod file;
 pub mod message;
+pub mod moderation;
+pub mod preferences;
+pub mod presence;
+pub mod schedule;
+pub mod search;
+pub mod signing;
 
-pub async fn handle_heartbeat(message: &Message, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+// where a handler's answers to the session go, encoded on the way out; sent right away rather than returned,
+// so an answer keeps its place among what the state sends the session under the same lock
+#[derive(Debug, Clone)]
+pub struct Replies {
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl Replies {
+    pub fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self { tx }
+    }
+
+    pub fn send(&self, response: Response) {
+        self.tx.send(response.into()).ok();
+    }
+
+    // for what keeps sending to the session after the handler returned
+    pub fn sender(&self) -> mpsc::UnboundedSender<Message> {
+        self.tx.clone()
+    }
+}
+
+pub async fn handle_heartbeat(
+    request: Heartbeat,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let reply = shared_state
+        .read()
+        .await
+        .receive_heartbeat(session_id, Some(request.sent_at), request.echo)
+        .await;
+    if let Some(reply) = reply {
+        replies.send(Response::Heartbeat(reply));
+    }
+}
+
+pub async fn handle_time_sync(request: TimeSync, replies: &Replies, shared_state: ArcRwLock<SharedState>) {
+    let clock = shared_state.read().await.clock();
+    let server_received = clock.now();
+    replies.send(Response::TimeSyncReply {
+        client_sent: request.client_sent,
+        server_received,
+        server_sent: clock.now(),
+    });
+}
+
+// may come again later, the newest language counts for everything sent after it
+pub async fn handle_client_hello(
+    request: ClientHello,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    if !catalog::is_language_tag(&request.language) {
+        tracing::warn!("Invalid language '{}' from session {}", request.language, session_id);
+        replies.send(Response::Nack);
+        return;
+    }
     shared_state
         .read()
         .await
-        .update_heartbeat(
-            session_id,
-            Some(
-                DateTime::parse_from_rfc3339(std::str::from_utf8(&message.payload().get_data()[0]).unwrap())
-                    .unwrap()
-                    .with_timezone(&Utc),
-            ),
-        )
+        .set_language(session_id, &request.language)
         .await;
 }
+
+// only changes what happens at login, a client selects it before it authenticates
+pub async fn handle_sync_select(shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    shared_state.read().await.select_offline_sync(session_id).await;
+}
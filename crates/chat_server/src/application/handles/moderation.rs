@@ -0,0 +1,119 @@
+use chat_core::dto::{AdminClearIpBan, AdminKickUser, AdminKickWhere, Response};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+pub async fn handle_kick_user(
+    request: AdminKickUser,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let AdminKickUser { username, reason } = request;
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+    let actor_level = state.access_level_of(session_id).await;
+    let target_level = state.get_user(&username).map(|user| user.access_level().clone());
+
+    // moderators can remove users but never someone of their own rank or above
+    let (Some(actor_level), Some(target_level)) = (actor_level, target_level) else {
+        replies.send(Response::Nack);
+        return;
+    };
+    if target_level >= actor_level {
+        tracing::warn!("{} may not kick {}", actor, username);
+        replies.send(Response::Nack);
+        return;
+    }
+
+    let sessions = state.sessions_of_user(&username).await;
+    if sessions.is_empty() {
+        replies.send(Response::Nack);
+        return;
+    }
+    for id in sessions {
+        state.kick_session(id, &reason).await;
+    }
+    state.audit(&actor, "kick", format!("{} ({})", username, reason));
+    drop(state);
+
+    tracing::info!("{} kicked {}: {}", actor, username, reason);
+    replies.send(Response::UserKicked { username, reason });
+}
+
+// the same filter as the session list, the actor's own session and anyone of their rank or above are left alone
+pub async fn handle_kick_where(
+    request: AdminKickWhere,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let AdminKickWhere {
+        filter,
+        reason,
+        dry_run,
+    } = request;
+
+    let mut state = shared_state.write().await;
+    let actor = state.actor_of(session_id).await;
+    let Some(actor_level) = state.access_level_of(session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    let targets: Vec<_> = state
+        .list_sessions(&filter)
+        .await
+        .into_iter()
+        .filter(|(id, level, _)| *id != session_id && *level < actor_level)
+        .collect();
+
+    if !dry_run {
+        for (id, _, _) in &targets {
+            state.kick_session(*id, &reason).await;
+        }
+        state.audit(&actor, "kick", format!("{} sessions ({})", targets.len(), reason));
+    }
+    drop(state);
+
+    if !dry_run {
+        tracing::info!("{} kicked {} sessions: {}", actor, targets.len(), reason);
+    }
+    let count = targets.len() as u64;
+    for (_, _, info) in targets {
+        replies.send(Response::SessionInfo(info));
+    }
+    replies.send(Response::SessionListEnd {
+        count,
+        kicked: !dry_run,
+    });
+}
+
+pub async fn handle_list_ip_bans(replies: &Replies, shared_state: ArcRwLock<SharedState>) {
+    let list = shared_state.read().await.ip_ban_list();
+    replies.send(Response::IpBanList(list));
+}
+
+// answered with the bans that are left, an address that was not banned is a NACK
+pub async fn handle_clear_ip_ban(
+    request: AdminClearIpBan,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let ip = request.address;
+
+    let mut state = shared_state.write().await;
+    if !state.clear_ip_ban(ip) {
+        replies.send(Response::Nack);
+        return;
+    }
+    let actor = state.actor_of(session_id).await;
+    state.audit(&actor, "clear_ip_ban", ip.to_string());
+    let list = state.ip_ban_list();
+    drop(state);
+
+    tracing::info!("{} lifted the ban on {}", actor, ip);
+    replies.send(Response::IpBanList(list));
+}
@@ -0,0 +1,98 @@
+use chat_core::{
+    dto::{Mute, Response, SetPreference},
+    protocol::{Message, MessageType},
+};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+// answers with the full set, so every change leaves the client with the server's view
+pub async fn handle_set_preference(
+    request: SetPreference,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let updates = request.updates;
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    if let Err(e) = shared_state.set_preferences(&user, &updates) {
+        tracing::debug!("{} cannot set {} preferences: {}", user, updates.len(), e);
+        replies.send(Response::Rejected {
+            request: MessageType::SetPreference,
+            reason: e,
+        });
+        return;
+    }
+    let Some(preferences) = shared_state.get_user(&user).map(|user| user.preferences()) else {
+        return;
+    };
+    let (entries, version) = (preferences.entries(), preferences.version());
+    // sent while the lock is held, so no later change can overtake it
+    let changed = Message::preferences_changed(&entries, version);
+    replies.send(Response::Preferences { entries, version });
+
+    for id in shared_state.sessions_of_user(&user).await {
+        if id != session_id {
+            shared_state.send_to_session(id, changed.clone()).await;
+        }
+    }
+}
+
+pub async fn handle_get_preferences(replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let shared_state = shared_state.read().await;
+    match shared_state.get_user_by_session(&session_id).await {
+        Some(user) => send_preferences(replies, &shared_state, &user),
+        None => replies.send(Response::Nack),
+    }
+}
+
+fn send_preferences(replies: &Replies, shared_state: &SharedState, user: &str) {
+    if let Some(user) = shared_state.get_user(user) {
+        let preferences = user.preferences();
+        replies.send(Response::Preferences {
+            entries: preferences.entries(),
+            version: preferences.version(),
+        });
+    }
+}
+
+// adding and removing both answer with the whole list, like preferences
+pub async fn handle_mute(
+    request: Mute,
+    mute: bool,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let sender = request.username;
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let changed = match mute {
+        true => shared_state.mute(&user, &sender),
+        false => shared_state.unmute(&user, &sender),
+    };
+    if let Err(e) = changed {
+        tracing::debug!("{} cannot change the mute of {}: {}", user, sender, e);
+        replies.send(Response::Nack);
+        return;
+    }
+    replies.send(Response::MutedSenders(shared_state.muted_senders(&user)));
+}
+
+pub async fn handle_mute_list(replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let shared_state = shared_state.read().await;
+    match shared_state.get_user_by_session(&session_id).await {
+        Some(user) => replies.send(Response::MutedSenders(shared_state.muted_senders(&user))),
+        None => replies.send(Response::Nack),
+    }
+}
@@ -0,0 +1,51 @@
+use chat_core::{
+    dto::Response,
+    protocol::{MessageType, PresenceScope},
+};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+pub async fn handle_subscribe_presence(
+    scope: PresenceScope,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    // the write lock keeps deltas from going out before the snapshot they count on from
+    let mut shared_state = shared_state.write().await;
+    match shared_state
+        .subscribe_presence(session_id, scope, replies.sender())
+        .await
+    {
+        Ok(snapshot) => {
+            tracing::debug!(
+                "Session {} follows presence of {} at version {}",
+                session_id,
+                snapshot.scope.encode(),
+                snapshot.version
+            );
+            replies.send(Response::PresenceSnapshot(snapshot));
+        }
+        Err(e) => replies.send(Response::Rejected {
+            request: MessageType::SubscribePresence,
+            reason: e,
+        }),
+    }
+}
+
+pub async fn handle_unsubscribe_presence(
+    scope: PresenceScope,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    match shared_state.write().await.unsubscribe_presence(session_id, &scope) {
+        true => replies.send(Response::Ack),
+        false => replies.send(Response::Rejected {
+            request: MessageType::UnsubscribePresence,
+            reason: format!("Not following the presence of {}", scope.encode()),
+        }),
+    }
+}
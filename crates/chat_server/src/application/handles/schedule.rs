@@ -0,0 +1,127 @@
+use chat_core::{
+    dto::{Response, ScheduleCancel, ScheduleMessageSend},
+    protocol::{Message, MessageType},
+};
+use uuid::Uuid;
+
+use super::{
+    message::{relay_direct_message, Outgoing},
+    Replies,
+};
+use crate::application::{ArcRwLock, SharedState};
+
+// only the time is checked here, whether the message may go out is decided when it is due
+pub async fn handle_schedule_message_send(
+    request: ScheduleMessageSend,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let ScheduleMessageSend { recipient, body, due } = request;
+    let Some(sender) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    let now = shared_state.clock().now();
+    let scheduled = shared_state
+        .schedule_mut()
+        .add(&sender, &recipient, &body, due.due(now), now);
+    match scheduled {
+        Ok(id) => {
+            tracing::debug!("{} scheduled message {} to {}", sender, id, recipient);
+            send_pending(replies, &shared_state, &sender);
+        }
+        Err(e) => {
+            tracing::debug!("{} cannot schedule a message to {}: {}", sender, recipient, e);
+            replies.send(Response::Rejected {
+                request: MessageType::ScheduleMessageSend,
+                reason: e,
+            });
+        }
+    }
+}
+
+pub async fn handle_schedule_list(replies: &Replies, shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
+    let shared_state = shared_state.read().await;
+    match shared_state.get_user_by_session(&session_id).await {
+        Some(user) => send_pending(replies, &shared_state, &user),
+        None => replies.send(Response::Nack),
+    }
+}
+
+pub async fn handle_schedule_cancel(
+    request: ScheduleCancel,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    match shared_state.schedule_mut().cancel(&user, request.id) {
+        Ok(_) => send_pending(replies, &shared_state, &user),
+        Err(e) => replies.send(Response::Rejected {
+            request: MessageType::ScheduleCancel,
+            reason: e,
+        }),
+    }
+}
+
+// run by the scheduler, each message is judged like one its sender typed at this moment
+pub async fn fire_scheduled_messages(shared_state: &mut SharedState) -> usize {
+    let now = shared_state.clock().now();
+    let due = shared_state.schedule_mut().take_due(now);
+    let fired = due.len();
+
+    for scheduled in due {
+        if shared_state.get_user(&scheduled.sender).is_none() {
+            tracing::debug!("Dropped scheduled message {}, its sender is gone", scheduled.id);
+            continue;
+        }
+        let error = match shared_state.user_may(&scheduled.sender, MessageType::DirectMessageSend) {
+            // no session asked for it, errors are in the server's language
+            true => relay_direct_message(
+                shared_state,
+                &scheduled.sender,
+                Outgoing {
+                    recipient: &scheduled.recipient,
+                    body: &scheduled.body,
+                    signature: None,
+                },
+                now,
+                None,
+                Uuid::nil(),
+            )
+            .await
+            .err()
+            .map(|(_, error)| error),
+            false => Some("You are not allowed to send direct messages".to_string()),
+        };
+        match &error {
+            Some(error) => tracing::debug!("Scheduled message {} was not sent: {}", scheduled.id, error),
+            None => tracing::debug!("Sent scheduled message {} to {}", scheduled.id, scheduled.recipient),
+        }
+
+        // a sender who is offline sees it in the list, the message is no longer there
+        let outcome = Message::schedule_fired(scheduled.id, &scheduled.recipient, error.as_deref());
+        for id in shared_state.sessions_of_user(&scheduled.sender).await {
+            shared_state.send_to_session(id, outcome.clone()).await;
+        }
+    }
+    fired
+}
+
+fn send_pending(replies: &Replies, shared_state: &SharedState, user: &str) {
+    let entries: Vec<_> = shared_state
+        .schedule()
+        .pending_for(user)
+        .into_iter()
+        .map(|scheduled| scheduled.entry())
+        .collect();
+    replies.send(Response::ScheduledMessages(entries));
+}
@@ -0,0 +1,52 @@
+use chat_core::dto::{Response, SearchRequest};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+const MIN_QUERY_LENGTH: usize = 3;
+const MAX_SEARCH_RESULTS: u64 = 50;
+
+// only ever looks at conversations the requesting user is part of
+pub async fn handle_search_request(
+    request: SearchRequest,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let (query, limit) = (request.query.as_str(), request.limit.min(MAX_SEARCH_RESULTS));
+    if query.chars().count() < MIN_QUERY_LENGTH {
+        replies.send(Response::SearchRefused {
+            reason: format!("Search queries need at least {} characters", MIN_QUERY_LENGTH),
+        });
+        return;
+    }
+
+    let mut shared_state = shared_state.write().await;
+    let Some(user) = shared_state.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+    let now = shared_state.clock().instant().into_std();
+    if !shared_state.search_limiter_mut().check(&user, now) {
+        tracing::debug!("Search rate limit reached for {}", user);
+        replies.send(Response::SearchRefused {
+            reason: "Too many searches, try again later".to_string(),
+        });
+        return;
+    }
+
+    let results = shared_state.message_store().search(
+        &user,
+        query,
+        request.peer.as_deref(),
+        limit as usize,
+        shared_state.clock().now(),
+    );
+    for (message, range) in &results {
+        replies.send(Response::Frame(message.to_search_result(&user, range.clone())));
+    }
+    replies.send(Response::SearchEnd {
+        count: results.len() as u64,
+    });
+}
@@ -0,0 +1,52 @@
+use chat_core::dto::{Response, SigningKeyRequest, SigningKeySet};
+use uuid::Uuid;
+
+use super::Replies;
+use crate::application::{ArcRwLock, SharedState};
+
+// answered with the key as it is now on record, the same frame anyone asking for it gets
+pub async fn handle_signing_key_set(
+    request: SigningKeySet,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let Some(user) = shared_state.read().await.get_user_by_session(&session_id).await else {
+        replies.send(Response::Nack);
+        return;
+    };
+
+    let mut shared_state = shared_state.write().await;
+    if shared_state.signing_key(&user) != request.key.as_ref() {
+        shared_state.set_signing_key(&user, request.key);
+        let detail = match request.key {
+            Some(_) => "set",
+            None => "removed",
+        };
+        shared_state.audit(&user, "signing_key", detail.to_string());
+    }
+    replies.send(Response::SigningKey {
+        username: user,
+        key: request.key,
+    });
+}
+
+// unknown users have no key like users that never registered one, the answer tells no one apart
+pub async fn handle_signing_key_request(
+    request: SigningKeyRequest,
+    replies: &Replies,
+    shared_state: ArcRwLock<SharedState>,
+    session_id: Uuid,
+) {
+    let shared_state = shared_state.read().await;
+    if shared_state.get_user_by_session(&session_id).await.is_none() {
+        replies.send(Response::Nack);
+        return;
+    }
+    let username = shared_state
+        .resolve_username(&request.username)
+        .unwrap_or(&request.username)
+        .to_string();
+    let key = shared_state.signing_key(&username).copied();
+    replies.send(Response::SigningKey { username, key });
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{ErrorCode, Message, MessageType, MAX_FIELD_SIZE},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use super::{
+    flood::{FloodMeter, FloodVerdict},
+    permissions::Access,
+    server::{Server, ACCEPT_BACKOFF},
+    ArcRwLock, SharedState,
+};
+
+// the whole request has to be in within this, one request per connection
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+// the quota a native connection has, kept per token across requests
+type Meters = Arc<Mutex<HashMap<u64, FloodMeter>>>;
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+// what goes back, `code` mirrors the ErrorCode names where there is one
+struct Response {
+    status: u16,
+    body: JsonValue,
+}
+
+impl Response {
+    fn ok(body: JsonValue) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, code: &str, message: &str) -> Self {
+        Self {
+            status,
+            body: JsonValue::object().with("error", JsonValue::object().with("code", code).with("message", message)),
+        }
+    }
+
+    fn to_http(&self) -> String {
+        let body = self.body.to_string();
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason_phrase(self.status),
+            body.len(),
+            body
+        )
+    }
+}
+
+pub(super) async fn open(addr: SocketAddr) -> Result<TcpListener, String> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Could not bind the HTTP bridge to {}: {}", addr, e))
+}
+
+pub(super) async fn serve(listener: TcpListener, shared_state: ArcRwLock<SharedState>) {
+    let meters = Meters::default();
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(handle_connection(
+                    stream,
+                    peer,
+                    Arc::clone(&meters),
+                    Arc::clone(&shared_state),
+                ));
+            }
+            Err(e) => {
+                tracing::warn!("Could not accept an HTTP connection: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    meters: Meters,
+    shared_state: ArcRwLock<SharedState>,
+) {
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => route(request, &meters, &shared_state).await,
+        Ok(Err(e)) => Response::error(400, "invalid_request", &e),
+        Err(_) => Response::error(408, "timeout", "The request did not arrive in time"),
+    };
+    tracing::debug!("HTTP bridge answered {} with {}", peer, response.status);
+    if let Err(e) = stream.write_all(response.to_http().as_bytes()).await {
+        tracing::debug!("Could not answer {}: {}", peer, e);
+    }
+    stream.shutdown().await.ok();
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, String> {
+    let mut buffer = Vec::new();
+    let head_end = loop {
+        let mut chunk = [0; 1024];
+        match stream.read(&mut chunk).await {
+            Ok(0) => return Err("The connection closed before the request was complete".to_string()),
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            Err(e) => return Err(e.to_string()),
+        }
+        // judged on what arrived so far, the last chunk may have carried the head past the limit
+        let end = buffer.windows(4).position(|window| window == b"\r\n\r\n");
+        if end.unwrap_or(buffer.len()) > MAX_REQUEST_HEAD {
+            return Err("The request head is too long".to_string());
+        }
+        if let Some(end) = end {
+            break end;
+        }
+    };
+
+    let head = std::str::from_utf8(&buffer[..head_end]).map_err(|_| "The request head is not text")?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Err("Invalid request line".to_string());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .map_err(|_| "Invalid Content-Length")?
+        .unwrap_or(0);
+    if content_length > MAX_FIELD_SIZE as usize {
+        return Err(format!("The body is larger than {} bytes", MAX_FIELD_SIZE));
+    }
+
+    let mut body = buffer.split_off(head_end + 4);
+    while body.len() < content_length {
+        let mut chunk = vec![0; content_length - body.len()];
+        match stream.read(&mut chunk).await {
+            Ok(0) => return Err("The connection closed before the body was complete".to_string()),
+            Ok(read) => body.extend_from_slice(&chunk[..read]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    body.truncate(content_length);
+
+    Ok(Request { method, path, body })
+}
+
+async fn route(request: Request, meters: &Meters, shared_state: &ArcRwLock<SharedState>) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        // still ready while the journal fails, only what it lost and that it is failing are reported
+        ("GET", "/api/v1/health") => {
+            let shared_state = shared_state.read().await;
+            let journal = shared_state.journal();
+            let failing = journal.is_some_and(|journal| journal.is_failing());
+            Response::ok(
+                JsonValue::object()
+                    .with("status", if failing { "degraded" } else { "ok" })
+                    .with("mode", shared_state.mode().as_str())
+                    .with("server_id", shared_state.server_id().to_string())
+                    .with("journal_failing", failing)
+                    .with("journal_dropped", journal.map_or(0, |journal| journal.dropped())),
+            )
+        }
+        ("POST", "/api/v1/messages") => match send_message(&request.body, meters, shared_state).await {
+            Ok(response) | Err(response) => response,
+        },
+        (_, "/api/v1/health" | "/api/v1/messages") => Response::error(405, "method_not_allowed", "Method not allowed"),
+        _ => Response::error(404, "not_found", "No such endpoint"),
+    }
+}
+
+// the message takes the way one from a client logged in as the token's owner would
+async fn send_message(
+    body: &[u8],
+    meters: &Meters,
+    shared_state: &ArcRwLock<SharedState>,
+) -> Result<Response, Response> {
+    let invalid = |message: &str| Response::error(400, "invalid_request", message);
+    let request = std::str::from_utf8(body)
+        .map_err(|_| invalid("The body is not text"))
+        .and_then(|body| JsonValue::parse(body).map_err(|e| invalid(&e)))?;
+    let field = |key: &str| request.get(key).and_then(JsonValue::as_str);
+    let token = field("token").ok_or_else(|| invalid("Missing 'token'"))?;
+    let text = field("body").ok_or_else(|| invalid("Missing 'body'"))?;
+    let recipient = match (field("recipient"), field("room")) {
+        (Some(recipient), None) => recipient,
+        (None, Some(_)) => return Err(invalid("This server has no rooms")),
+        _ => return Err(invalid("Expected one of 'recipient' or 'room'")),
+    };
+
+    let Some((user, token_id)) = shared_state.read().await.api_tokens().authenticate(token) else {
+        return Err(Response::error(401, "invalid_token", "Unknown or revoked token"));
+    };
+    let message = Message::direct_message_send(recipient, text);
+    let verdict = {
+        let shared_state = shared_state.read().await;
+        let now = shared_state.clock().instant().into_std();
+        let limits = shared_state.flood_limits();
+        let mut meters = meters.lock().unwrap();
+        // a revoked token sends nothing anymore, its meter goes with it
+        meters.retain(|id, _| shared_state.api_tokens().contains(*id));
+        meters
+            .entry(token_id)
+            .or_insert_with(|| FloodMeter::new(limits))
+            .record(message.wire_size() as u64, now)
+    };
+    if verdict != FloodVerdict::Allow {
+        tracing::warn!("API token #{} of {} is sending too fast", token_id, user);
+        return Err(Response::error(429, "rate_limited", "Too many messages, slow down"));
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let Some(session_id) = shared_state
+        .write()
+        .await
+        .open_control_session(Some(&user), tx.clone())
+        .await
+    else {
+        return Err(Response::error(
+            401,
+            "invalid_token",
+            "The token's owner no longer exists",
+        ));
+    };
+    let access = shared_state
+        .read()
+        .await
+        .access_of(session_id, MessageType::DirectMessageSend)
+        .await;
+    if access == Access::Temporary {
+        shared_state
+            .write()
+            .await
+            .record_temporary_use(session_id, MessageType::DirectMessageSend)
+            .await;
+    }
+    if access != Access::Denied {
+        let plugins = shared_state.read().await.plugins();
+        Server::dispatch(&message, tx, &plugins, Arc::clone(shared_state), session_id).await;
+    }
+    shared_state.write().await.close_control_session(session_id);
+    if access == Access::Denied {
+        return Err(Response::error(
+            403,
+            "forbidden",
+            "You are not allowed to send direct messages",
+        ));
+    }
+    tracing::info!("{} sent a message to {} with API token #{}", user, recipient, token_id);
+
+    // the first answer is the one to the request, anything else was for the user's other sessions
+    while let Ok(reply) = rx.try_recv() {
+        match reply.message_type() {
+            MessageType::Ack => {
+                let id = reply
+                    .payload()
+                    .u64_field(0)
+                    .map_err(|e| Response::error(500, "other", &e))?;
+                return Ok(Response::ok(
+                    JsonValue::object().with("id", id).with("recipient", recipient),
+                ));
+            }
+            MessageType::MessageError => {
+                let code = reply.error_code();
+                let error = reply.payload().str_field(0).unwrap_or_default();
+                return Err(Response::error(error_status(code), code.as_str(), error));
+            }
+            MessageType::Nack => {
+                let reason = reply.payload().str_field(1).unwrap_or("The message was refused");
+                return Err(Response::error(422, "other", reason));
+            }
+            _ => {}
+        }
+    }
+    Err(Response::error(500, "other", "The server did not answer"))
+}
+
+fn error_status(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::UserNotFound => 404,
+        ErrorCode::RecipientOffline => 409,
+        ErrorCode::OfflineQueueFull | ErrorCode::ServerBusy => 503,
+        ErrorCode::InvalidCredentials | ErrorCode::PasswordLoginDisabled => 401,
+        ErrorCode::UserExists => 409,
+        ErrorCode::Other => 422,
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+pub const VIOLATION_THRESHOLD: u32 = 10;
+pub const VIOLATION_DECAY: Duration = Duration::from_secs(60);
+pub const IP_BAN_DURATION: Duration = Duration::from_secs(15 * 60);
+// bans in one list, the longest ones still fit into one payload field
+pub const MAX_LISTED_BANS: usize = 1000;
+
+// when an address that keeps sending garbage is turned away at accept time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViolationLimits {
+    // violations that add up to a ban, 0 never bans
+    pub threshold: u32,
+    // how long it takes for one violation to be forgiven
+    pub decay: Duration,
+    pub ban: Duration,
+}
+
+// violation scores and bans per address, scores shrink by one every decay interval
+#[derive(Debug, Default)]
+pub struct IpGuard {
+    limits: ViolationLimits,
+    // the score and when the last violation in it was forgiven, or came in if none was yet
+    scores: HashMap<IpAddr, (u32, Instant)>,
+    bans: HashMap<IpAddr, Instant>,
+    issued: u64,
+    turned_away: u64,
+}
+
+impl Default for ViolationLimits {
+    fn default() -> Self {
+        Self {
+            threshold: VIOLATION_THRESHOLD,
+            decay: VIOLATION_DECAY,
+            ban: IP_BAN_DURATION,
+        }
+    }
+}
+
+impl IpGuard {
+    pub fn set_limits(&mut self, limits: ViolationLimits) {
+        self.limits = limits;
+    }
+
+    // true when this violation got the address banned
+    pub fn record_violation(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.limits.threshold == 0 || self.ban_remaining(ip, now).is_some() {
+            return false;
+        }
+        self.forget_expired(now);
+
+        let (score, since) = self.score(ip, now);
+        if score + 1 < self.limits.threshold {
+            self.scores.insert(ip, (score + 1, since));
+            return false;
+        }
+        self.scores.remove(&ip);
+        self.bans.insert(ip, now + self.limits.ban);
+        self.issued += 1;
+        true
+    }
+
+    pub fn ban_remaining(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        self.bans
+            .get(&ip)
+            .filter(|until| **until > now)
+            .map(|until| *until - now)
+    }
+
+    pub fn record_turned_away(&mut self) {
+        self.turned_away += 1;
+    }
+
+    // lifts the ban and forgets the score, false when the address was not banned
+    pub fn clear(&mut self, ip: IpAddr, now: Instant) -> bool {
+        self.scores.remove(&ip);
+        self.bans.remove(&ip).is_some_and(|until| until > now)
+    }
+
+    // the bans in effect, the longest remaining first and at most MAX_LISTED_BANS of them
+    pub fn bans(&self, now: Instant) -> Vec<(IpAddr, Duration)> {
+        let mut bans: Vec<(IpAddr, Duration)> = self
+            .bans
+            .keys()
+            .filter_map(|ip| self.ban_remaining(*ip, now).map(|remaining| (*ip, remaining)))
+            .collect();
+        bans.sort_by(|(a_ip, a), (b_ip, b)| b.cmp(a).then(a_ip.cmp(b_ip)));
+        bans.truncate(MAX_LISTED_BANS);
+        bans
+    }
+
+    pub fn issued(&self) -> u64 {
+        self.issued
+    }
+
+    pub fn turned_away(&self) -> u64 {
+        self.turned_away
+    }
+
+    // the score with what was forgiven since taken off, and when the next forgiving interval started
+    fn score(&self, ip: IpAddr, now: Instant) -> (u32, Instant) {
+        let Some((score, since)) = self.scores.get(&ip).copied() else {
+            return (0, now);
+        };
+        let decay = self.limits.decay;
+        if decay.is_zero() {
+            return (0, now);
+        }
+        let forgiven = now.saturating_duration_since(since).as_nanos() / decay.as_nanos();
+        match u32::try_from(forgiven) {
+            Ok(forgiven) if forgiven < score => (score - forgiven, since + decay * forgiven),
+            _ => (0, now),
+        }
+    }
+
+    // whenever a new violation comes in, so neither map grows with addresses that behaved since
+    fn forget_expired(&mut self, now: Instant) {
+        self.bans.retain(|_, until| *until > now);
+        let decay = self.limits.decay;
+        self.scores
+            .retain(|_, (score, since)| now.saturating_duration_since(*since) < decay.saturating_mul(*score));
+    }
+}
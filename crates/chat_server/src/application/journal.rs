@@ -0,0 +1,269 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chat_core::{json::JsonValue, protocol::ServerStats};
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+pub const AUDIT_FILE: &str = "audit.log";
+pub const METRICS_FILE: &str = "metrics.log";
+pub const JOURNAL_CAPACITY: usize = 4096;
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+// lines written at once, all of one kind
+const JOURNAL_BATCH: usize = 256;
+// the wait before the next try doubles after every failed write, up to the maximum
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// which file an entry goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalKind {
+    Audit,
+    Metrics,
+}
+
+// what goes first when the queue is full, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    // the next snapshot tells the same story
+    Metrics,
+    // audit entries that come with normal use, like every frame a temporary grant let through
+    Routine,
+    // everything an admin or a user changed on purpose
+    Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub kind: JournalKind,
+    pub severity: Severity,
+    // one json line without the newline
+    pub line: String,
+}
+
+// how big the queue may grow and how often the counters are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalLimits {
+    pub capacity: usize,
+    pub metrics_interval: Duration,
+}
+
+// where the writer task puts entries, runs on a blocking thread so a hanging disk holds up nobody else
+pub trait JournalWriter: fmt::Debug + Send + Sync {
+    fn write(&self, kind: JournalKind, lines: &[String]) -> io::Result<()>;
+}
+
+// appends to audit.log and metrics.log, the files are opened for every batch so one that went away comes back
+#[derive(Debug, Clone)]
+pub struct FileJournal {
+    dir: PathBuf,
+}
+
+// handlers push and never wait, the writer task takes entries off the front
+#[derive(Debug, Clone)]
+pub struct Journal {
+    queue: Arc<JournalQueue>,
+}
+
+#[derive(Debug)]
+struct JournalQueue {
+    entries: Mutex<VecDeque<JournalEntry>>,
+    capacity: usize,
+    dropped: AtomicU64,
+    failing: AtomicBool,
+    closed: AtomicBool,
+    wake: Notify,
+}
+
+impl Default for JournalLimits {
+    fn default() -> Self {
+        Self {
+            capacity: JOURNAL_CAPACITY,
+            metrics_interval: METRICS_INTERVAL,
+        }
+    }
+}
+
+impl JournalEntry {
+    pub fn audit(timestamp: DateTime<Utc>, actor: &str, action: &str, detail: &str) -> Self {
+        let severity = match action {
+            "temporary_grant_used" | "session_takeover" => Severity::Routine,
+            _ => Severity::Change,
+        };
+        Self {
+            kind: JournalKind::Audit,
+            severity,
+            line: JsonValue::object()
+                .with("at", timestamp.to_rfc3339())
+                .with("actor", actor)
+                .with("action", action)
+                .with("detail", detail)
+                .to_string(),
+        }
+    }
+
+    pub fn metrics(timestamp: DateTime<Utc>, stats: &ServerStats) -> Self {
+        Self {
+            kind: JournalKind::Metrics,
+            severity: Severity::Metrics,
+            line: JsonValue::object()
+                .with("at", timestamp.to_rfc3339())
+                .with("sessions", stats.sessions)
+                .with("users", stats.users)
+                .with("missed_heartbeats", stats.missed_heartbeats)
+                .with("stalled_writes", stats.stalled_writes)
+                .with("flood_warnings", stats.flood_warnings)
+                .with("flood_disconnects", stats.flood_disconnects)
+                .with("queued_messages", stats.queued_messages)
+                .with("expired_messages", stats.expired_messages)
+                .with("closed_by_peer", stats.closed_by_peer)
+                .with("broken_connections", stats.broken_connections)
+                .with("presence_coalesced", stats.presence_coalesced)
+                .with("presence_dropped", stats.presence_dropped)
+                .with("accept_errors", stats.accept_errors)
+                .with("integrity_failures", stats.integrity_failures)
+                .with("webhooks_delivered", stats.webhooks_delivered)
+                .with("webhooks_failed", stats.webhooks_failed)
+                .with("auth_queue_depth", stats.auth_queue_depth)
+                .with("auth_rejected", stats.auth_rejected)
+                .with("journal_dropped", stats.journal_dropped)
+                .to_string(),
+        }
+    }
+}
+
+impl FileJournal {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+}
+
+impl JournalWriter for FileJournal {
+    fn write(&self, kind: JournalKind, lines: &[String]) -> io::Result<()> {
+        let file = match kind {
+            JournalKind::Audit => AUDIT_FILE,
+            JournalKind::Metrics => METRICS_FILE,
+        };
+        let mut text = lines.join("\n");
+        text.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(self.dir.join(file))?;
+        file.write_all(text.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: Arc::new(JournalQueue {
+                entries: Mutex::new(VecDeque::new()),
+                capacity: capacity.max(1),
+                dropped: AtomicU64::new(0),
+                failing: AtomicBool::new(false),
+                closed: AtomicBool::new(false),
+                wake: Notify::new(),
+            }),
+        }
+    }
+
+    // a full queue gives up the oldest of its least severe entries, or the new one when that is less severe still
+    pub fn push(&self, entry: JournalEntry) {
+        let mut entries = self.queue.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= self.queue.capacity {
+            self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+            let lowest = entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, queued)| (queued.severity, *index))
+                .map(|(index, queued)| (index, queued.severity));
+            match lowest {
+                Some((index, severity)) if severity <= entry.severity => {
+                    entries.remove(index);
+                }
+                _ => return,
+            }
+        }
+        entries.push_back(entry);
+        drop(entries);
+        self.queue.wake.notify_one();
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    // set from the first failed write until one goes through again
+    pub fn is_failing(&self) -> bool {
+        self.queue.failing.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    // the writer task finishes what is queued and ends
+    pub fn close(&self) {
+        self.queue.closed.store(true, Ordering::Relaxed);
+        self.queue.wake.notify_one();
+    }
+
+    fn take_batch(&self) -> (Option<JournalKind>, Vec<String>) {
+        let mut entries = self.queue.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(kind) = entries.front().map(|entry| entry.kind) else {
+            return (None, Vec::new());
+        };
+        let mut lines = Vec::new();
+        while lines.len() < JOURNAL_BATCH && entries.front().is_some_and(|entry| entry.kind == kind) {
+            lines.extend(entries.pop_front().map(|entry| entry.line));
+        }
+        (Some(kind), lines)
+    }
+}
+
+// a batch that failed is tried again until it goes through, new entries wait in the queue meanwhile
+pub async fn write_journal(journal: Journal, writer: Arc<dyn JournalWriter>) {
+    let queue = &journal.queue;
+    loop {
+        let (Some(kind), lines) = journal.take_batch() else {
+            if queue.closed.load(Ordering::Relaxed) {
+                return;
+            }
+            queue.wake.notified().await;
+            continue;
+        };
+
+        let lines = Arc::new(lines);
+        let mut delay = RETRY_DELAY;
+        loop {
+            let (writer, batch) = (Arc::clone(&writer), Arc::clone(&lines));
+            let written = tokio::task::spawn_blocking(move || writer.write(kind, &batch))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::other(e)));
+            match written {
+                Ok(()) => {
+                    if queue.failing.swap(false, Ordering::Relaxed) {
+                        tracing::info!("Journal writes go through again, {} entries waiting", journal.queued());
+                    }
+                    break;
+                }
+                Err(e) => {
+                    if !queue.failing.swap(true, Ordering::Relaxed) {
+                        tracing::warn!("Could not write the journal, retrying in {:?}: {}", delay, e);
+                    }
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
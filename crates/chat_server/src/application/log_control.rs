@@ -0,0 +1,37 @@
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+#[derive(Debug)]
+pub struct LogControl {
+    handle: FilterHandle,
+    generation: u64,
+}
+
+impl LogControl {
+    pub fn new(handle: FilterHandle) -> Self {
+        Self { handle, generation: 0 }
+    }
+
+    pub fn filter(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // returns the previous filter so a caller can revert to it
+    pub fn set_filter(&mut self, directives: &str) -> Result<String, String> {
+        let filter =
+            EnvFilter::try_new(directives).map_err(|e| format!("Invalid log filter '{}': {}", directives, e))?;
+        let previous = self.filter();
+
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        self.generation += 1;
+
+        Ok(previous)
+    }
+}
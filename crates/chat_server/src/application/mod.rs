@@ -1,37 +1,236 @@
-use std::{collections::HashMap, error::Error, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    error::Error,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+use chat_core::{
+    integrity::Integrity,
+    json::JsonValue,
+    protocol::{
+        ConsistencyReport, ConsistencyViolation, DisconnectReason, ErrorCode, HeartbeatEcho, IpBanList, Message,
+        MessageType, OfflineSummary, PresenceEntry, PresenceScope, PresenceSnapshot, PresenceStatus, ServerStats,
+        SessionFilter, SessionInfo, NOTICE_KICKED, NOTICE_MESSAGE_EXPIRED, NOTICE_SESSION_TAKEOVER, SIGNING_KEY_LENGTH,
+    },
+    time_sync::{Clock, SystemClock},
+    version::VersionRange,
+};
+use chrono::{DateTime, Utc};
 use tokio::sync::{mpsc, RwLock};
 
+mod api_tokens;
+mod audit;
+mod auth_limit;
+mod catalog;
+mod config;
+mod control;
+mod data_dir;
+mod dedup;
+mod export;
+mod fanout;
+mod flood;
 mod handles;
+mod ip_guard;
+mod journal;
+mod log_control;
+mod mode;
+mod notices;
+mod offline;
+mod permissions;
+mod plugin;
+mod preferences;
+mod presence;
+mod rate_limit;
+mod recovery;
+mod relay_stats;
+#[cfg(feature = "test-util")]
+mod replay;
+mod schedule;
 mod server;
 mod session;
+mod shutdown;
+mod store;
+mod tenant;
+mod transfers;
+mod unread;
 mod user;
 
-use server::Server;
-use session::{AccessLevel, Session};
-use user::User;
+#[cfg(feature = "http-bridge")]
+mod http_bridge;
+#[cfg(feature = "test-util")]
+pub mod testing;
+#[cfg(feature = "webhook")]
+mod webhook;
+mod webhook_url;
+
+use api_tokens::{ApiTokens, API_TOKENS_FILE};
+use audit::AuditLog;
+use auth_limit::{AuthLimiter, AuthLimits};
+use catalog::Catalog;
+pub use catalog::{BUILT_IN_LANGUAGE, LANGUAGE_DIR};
+pub use config::{ConfigError, ServerConfig};
+pub use control::{send_command, CONTROL_SOCKET, CONTROL_TOKEN};
+pub use data_dir::DataDir;
+use dedup::DedupCache;
+pub use export::ImportMode;
+use fanout::{fan_out, FANOUT_TIMEOUT};
+use flood::{FloodCooldowns, FloodLimits};
+use ip_guard::{IpGuard, ViolationLimits};
+use journal::{Journal, JournalEntry};
+use log_control::LogControl;
+use mode::ServerMode;
+use notices::MissedNotices;
+use offline::{OfflineNotice, OfflineWebhooks, QueuedMessage, OFFLINE_TTL};
+use permissions::{Access, AccessPresets, Permissions, TemporaryGrant};
+use plugin::Plugins;
+pub use plugin::{Dispatch, PluginContext, PluginFuture, ServerPlugin, PLUGIN_TIMEOUT};
+use preferences::{NO_WEBHOOK, WEBHOOK};
+use presence::{PresenceBatch, PresenceSubscriptions, MAX_PENDING_PRESENCE};
+use rate_limit::RateLimiter;
+pub use recovery::Cleanup;
+use relay_stats::{RelayCounters, RelayStats};
+use schedule::{Schedule, ScheduleLimits, SCHEDULE_FILE};
+use server::{Server, MAX_INTEGRITY_FAILURES, OFFERED_INTEGRITY};
+use session::{AccessLevel, Session, TakeoverPolicy};
+pub use shutdown::{ShutdownReason, ShutdownSummary, EXIT_CLEAN, EXIT_FATAL, LAST_SHUTDOWN_FILE};
+use store::{DeletedHistory, MessageStore, EDIT_WINDOW, MAX_MESSAGE_TTL, MESSAGE_IDS_FILE};
+use tenant::Tenant;
+use transfers::FileTransfers;
+use unread::UnreadCounters;
+use user::{fold_username, validate_username, User};
 use uuid::Uuid;
+#[cfg(feature = "webhook")]
+pub use webhook::WebhookPlugin;
+pub use webhook_url::WebhookUrl;
 
 const TRACING_LEVEL: tracing::Level = tracing::Level::DEBUG;
+// a week away can add up, clients that sync pull this in batches instead of in one burst
+const MAX_OFFLINE_MESSAGES: usize = 1000;
+const DATA_DIR: &str = "data";
+const SERVER_NAME: &str = "chat_rs";
+// what the audit names for changes nobody's account made
+const SERVER_ACTOR: &str = "server";
+const SEARCH_RATE_LIMIT: usize = 10;
+const SEARCH_RATE_WINDOW: Duration = Duration::from_secs(60);
 type ArcRwLock<T> = Arc<RwLock<T>>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delivery {
+    Delivered,
+    Queued,
+}
+
 #[derive(Debug)]
 struct SharedState {
-    users: HashMap<String, User>,
+    users: HashMap<Uuid, User>,
+    // usernames can change, this is only for looking up who a name belongs to, keyed by the folded name
+    user_ids: HashMap<String, Uuid>,
     sessions: HashMap<Uuid, ArcRwLock<Session>>,
-    shutdown_tx: Option<mpsc::Sender<bool>>,
+    offline_messages: HashMap<Uuid, VecDeque<QueuedMessage>>,
+    // the sequence of the last queued message, over every queue
+    offline_sequence: u64,
+    // how long a message waits for its recipient unless it brings its own ttl
+    offline_ttl: Duration,
+    // queued messages that ran out before their recipient came back
+    expired_messages: u64,
+    relay_stats: RelayStats,
+    missed_notices: HashMap<Uuid, MissedNotices>,
+    unread: UnreadCounters,
+    message_store: MessageStore,
+    edit_window: Duration,
+    deleted_history: DeletedHistory,
+    max_message_ttl: Duration,
+    search_limiter: RateLimiter,
+    dedup: DedupCache,
+    // messages handed over to be sent later, fired by the scheduler task
+    schedule: Schedule,
+    // made by users for the http bridge, only hashes are kept
+    api_tokens: ApiTokens,
+    // where the http bridge listens once it is bound
+    #[cfg_attr(not(feature = "http-bridge"), allow(dead_code))]
+    http_bridge_addr: Option<SocketAddr>,
+    // writes that did not finish within the write timeout, kept after their sessions are gone
+    stalled_writes: u64,
+    // argon2 runs of logins and registrations, every path runs exactly one whether the name exists or not
+    password_checks: u64,
+    // shared with the handlers, which wait for their turn without holding the state
+    auth_limiter: Arc<AuthLimiter>,
+    flood_warnings: u64,
+    flood_disconnects: u64,
+    // how connections ended without a Disconnect, cleanly between frames or broken inside one
+    closed_by_peer: u64,
+    broken_connections: u64,
+    // where logins and logouts go to be announced, None while presence is off
+    presence_tx: Option<mpsc::UnboundedSender<(String, bool)>>,
+    presence_coalesced: u64,
+    presence_dropped: u64,
+    // followed by clients that asked for a snapshot and deltas, independent of the broadcasts above
+    presence_subscriptions: PresenceSubscriptions,
+    // transient ones only, anything else stops the server
+    accept_errors: u64,
+    // the algorithms clients may select besides crc32, which every connection starts with
+    integrity: Vec<Integrity>,
+    // frames that failed the check of their session, a session is dropped once it reaches the maximum
+    integrity_failures: u64,
+    // 0 never disconnects
+    max_integrity_failures: u32,
+    offline_webhooks: OfflineWebhooks,
+    // where queued messages go to be posted to the webhook of their recipient, None while nobody posts them
+    offline_notice_tx: Option<mpsc::UnboundedSender<OfflineNotice>>,
+    webhooks_delivered: u64,
+    webhooks_failed: u64,
+    flood_limits: FloodLimits,
+    protocol_versions: VersionRange,
+    flood_cooldowns: FloodCooldowns,
+    ip_guard: IpGuard,
+    data_dir: PathBuf,
+    // how the server introduces itself in the hello and in the stats
+    server_name: String,
+    server_id: Uuid,
+    motd: String,
+    // where a changed message of the day is kept, nothing is persisted without a data directory
+    motd_file: Option<PathBuf>,
+    // what clients are told, in the language their session asked for
+    catalog: Catalog,
+    // old names stay taken for a while after a rename, so nobody can pose as the previous owner
+    rename_grace: Duration,
+    // the folded old name, who gave it up and until when
+    reserved_names: HashMap<String, (String, DateTime<Utc>)>,
+    file_transfers: FileTransfers,
+    shutdown_tx: Option<mpsc::Sender<ShutdownReason>>,
+    // the most sessions open at once since the start
+    peak_sessions: usize,
+    takeover_policy: TakeoverPolicy,
+    // false answers messages to offline users with an error instead of keeping them for the next login
+    offline_queue: bool,
+    // unknown and offline recipients get the same answer, so nobody can probe which names exist
+    conceal_users: bool,
+    // guests are sent presence too, and the guest preset is cut down to the session
+    listen_only: bool,
+    // whether Auth with the plain password is still accepted, clients from before challenges need it
+    plain_auth: bool,
+    mode: ServerMode,
+    access_presets: AccessPresets,
+    audit: AuditLog,
+    // where audit entries and metric snapshots are written from, None without a data directory
+    journal: Option<Journal>,
+    log_control: Option<LogControl>,
+    plugins: Plugins,
+    // every timer and expiry reads the time from here, tests swap in one they control
+    clock: Arc<dyn Clock>,
 }
 
 #[derive(Debug)]
 pub struct Application {
-    server: server::Server,
-    shared_state: ArcRwLock<SharedState>,
+    // a single one unless TENANTS lists several
+    tenants: Vec<Tenant>,
 }
 
 impl SharedState {
     pub fn new() -> Self {
-        let mut users = HashMap::new();
-
         let mut luffy_admin = User::new(
             "luffy",
             "$argon2id$v=19$m=19456,t=2,p=1$cmFuZG9tc2FsdA$jDQwPD4k6mPV4oT/0Y4M2nhVSGDxpbbJaxIbNYc84rU".to_string(),
@@ -39,150 +238,2120 @@ impl SharedState {
 
         luffy_admin.set_access_level(AccessLevel::Admin);
 
-        // add Admin user
-        users.insert("luffy".to_string(), luffy_admin);
-
-        Self {
-            users,
+        let mut state = Self {
+            users: HashMap::new(),
+            user_ids: HashMap::new(),
             sessions: HashMap::new(),
+            offline_messages: HashMap::new(),
+            offline_sequence: 0,
+            offline_ttl: OFFLINE_TTL,
+            expired_messages: 0,
+            relay_stats: RelayStats::default(),
+            missed_notices: HashMap::new(),
+            unread: UnreadCounters::default(),
+            message_store: MessageStore::default(),
+            edit_window: EDIT_WINDOW,
+            deleted_history: DeletedHistory::default(),
+            max_message_ttl: MAX_MESSAGE_TTL,
+            search_limiter: RateLimiter::new(SEARCH_RATE_LIMIT, SEARCH_RATE_WINDOW),
+            dedup: DedupCache::default(),
+            schedule: Schedule::default(),
+            api_tokens: ApiTokens::default(),
+            http_bridge_addr: None,
+            stalled_writes: 0,
+            password_checks: 0,
+            auth_limiter: Arc::default(),
+            flood_warnings: 0,
+            flood_disconnects: 0,
+            closed_by_peer: 0,
+            broken_connections: 0,
+            presence_tx: None,
+            presence_coalesced: 0,
+            presence_dropped: 0,
+            presence_subscriptions: PresenceSubscriptions::default(),
+            accept_errors: 0,
+            integrity: OFFERED_INTEGRITY.to_vec(),
+            integrity_failures: 0,
+            offline_webhooks: OfflineWebhooks::default(),
+            offline_notice_tx: None,
+            webhooks_delivered: 0,
+            webhooks_failed: 0,
+            max_integrity_failures: MAX_INTEGRITY_FAILURES,
+            flood_limits: FloodLimits::default(),
+            protocol_versions: VersionRange::default(),
+            flood_cooldowns: FloodCooldowns::default(),
+            ip_guard: IpGuard::default(),
+            data_dir: PathBuf::from(DATA_DIR),
+            server_name: SERVER_NAME.to_string(),
+            server_id: Uuid::new_v4(),
+            motd: String::new(),
+            motd_file: None,
+            catalog: Catalog::default(),
+            rename_grace: Duration::ZERO,
+            reserved_names: HashMap::new(),
+            file_transfers: FileTransfers::default(),
             shutdown_tx: None,
-        }
+            peak_sessions: 0,
+            takeover_policy: TakeoverPolicy::default(),
+            offline_queue: true,
+            conceal_users: false,
+            listen_only: false,
+            plain_auth: true,
+            mode: ServerMode::default(),
+            access_presets: AccessPresets::default(),
+            audit: AuditLog::default(),
+            journal: None,
+            log_control: None,
+            plugins: Plugins::default(),
+            clock: Arc::new(SystemClock),
+        };
+
+        // add Admin user
+        state.add_user(luffy_admin);
+        state
+    }
+
+    pub fn add_user(&mut self, user: User) {
+        self.user_ids.insert(fold_username(user.name()), user.id());
+        self.users.insert(user.id(), user);
+    }
+
+    // logins and everything else addressed by name match it exactly
+    pub fn user_id(&self, name: &str) -> Option<Uuid> {
+        self.user_ids
+            .get(&fold_username(name))
+            .copied()
+            .filter(|id| self.users.get(id).is_some_and(|user| user.name() == name))
+    }
+
+    // the name as it was registered, for recipients typed in any case
+    pub fn resolve_username(&self, name: &str) -> Option<&str> {
+        let id = self.user_ids.get(&fold_username(name))?;
+        self.users.get(id).map(User::name)
     }
 
-    pub fn add_user(&mut self, name: String, user: User) {
-        self.users.insert(name, user);
+    fn user_mut(&mut self, name: &str) -> Option<&mut User> {
+        let id = self.user_id(name)?;
+        self.users.get_mut(&id)
     }
 
     pub fn add_session(&mut self, id: Uuid, session: ArcRwLock<Session>) {
         self.sessions.insert(id, session);
+        self.peak_sessions = self.peak_sessions.max(self.sessions.len());
     }
 
-    pub fn set_shutdown_tx(&mut self, tx: mpsc::Sender<bool>) {
-        self.shutdown_tx = Some(tx);
+    pub fn peak_sessions(&self) -> usize {
+        self.peak_sessions
     }
 
-    pub async fn shutdown(&self) {
-        if let Some(tx) = &self.shutdown_tx {
-            tx.send(true).await.unwrap();
+    // delivered straight away or later out of the offline queue, over every user
+    pub fn messages_relayed(&self) -> u64 {
+        self.relay_stats.delivered()
+    }
+
+    // a session can be gone a moment before it is removed, then another device or the offline queue takes the message
+    pub async fn deliver_to_user(&mut self, user: &str, message: Message) -> Result<Delivery, (ErrorCode, String)> {
+        if self.send_to_user(user, message.clone()).await {
+            return Ok(Delivery::Delivered);
         }
+        self.queue_offline_message(user, message).map(|()| Delivery::Queued)
     }
 
-    pub fn get_user(&self, name: &str) -> Option<&User> {
-        self.users.get(name)
+    // the session the user logged in with last, then any other one, true once one of them took it
+    pub async fn send_to_user(&self, user: &str, message: Message) -> bool {
+        if let Some(session) = self.get_session_by_user(user).await {
+            if session.read().await.send(message.clone()).is_ok() {
+                return true;
+            }
+        }
+        for id in self.sessions_of_user(user).await {
+            if self.send_to_session(id, message.clone()).await {
+                return true;
+            }
+        }
+        false
     }
 
-    pub fn sessions(&self) -> &HashMap<Uuid, ArcRwLock<Session>> {
-        &self.sessions
+    // every session of the user gets it, without one that still listens it waits for the next login
+    pub async fn notify_user(&mut self, user: &str, message: Message, kind: &str, detail: &str) {
+        let mut delivered = false;
+        for id in self.sessions_of_user(user).await {
+            delivered |= self.send_to_session(id, message.clone()).await;
+        }
+        if !delivered {
+            self.queue_missed_notice(user, kind, detail);
+        }
     }
 
-    pub async fn remove_session(&mut self, id: Uuid) {
-        if !self.sessions.contains_key(&id) {
+    pub fn queue_offline_message(&mut self, user: &str, message: Message) -> Result<(), (ErrorCode, String)> {
+        let Some(id) = self.user_id(user) else {
+            return Err(recipient_error(ErrorCode::UserNotFound, user));
+        };
+        if !self.offline_queue {
+            return Err(recipient_error(ErrorCode::RecipientOffline, user));
+        }
+
+        let notice = self.offline_notice(id, user, &message);
+        let queue = self.offline_messages.entry(id).or_default();
+        if queue.len() >= MAX_OFFLINE_MESSAGES {
+            return Err(recipient_error(ErrorCode::OfflineQueueFull, user));
+        }
+        self.offline_sequence += 1;
+        queue.push_back(QueuedMessage::new(
+            message,
+            self.offline_sequence,
+            self.offline_ttl,
+            self.clock.now(),
+        ));
+        if let (Some(tx), Some(notice)) = (&self.offline_notice_tx, notice) {
+            tx.send(notice).ok();
+        }
+        Ok(())
+    }
+
+    // the allowlist is checked again, the operator may have taken a domain off it since the user set the webhook
+    fn offline_notice(&self, id: Uuid, user: &str, message: &Message) -> Option<OfflineNotice> {
+        self.offline_notice_tx.as_ref()?;
+        let webhook = self.users.get(&id)?.preferences().webhook()?;
+        let url = self.offline_webhooks.check(webhook).ok()?;
+        self.offline_webhooks.notice(url, user, message)
+    }
+
+    // messages that expired while the user was away are never delivered
+    pub async fn take_offline_messages(&mut self, user: &str) -> Vec<Message> {
+        let now = self.clock.now();
+        let queue = self
+            .user_id(user)
+            .and_then(|id| self.offline_messages.remove(&id))
+            .unwrap_or_default();
+        let (expired, live): (Vec<_>, Vec<_>) = queue.into_iter().partition(|queued| queued.is_expired(now));
+        self.expire_queued(user, expired).await;
+        for queued in &live {
+            if let Some(counters) = queued.sender().and_then(|sender| self.relay_counters_mut(sender)) {
+                counters.delivered += 1;
+            }
+        }
+        live.into_iter().map(|queued| queued.message).collect()
+    }
+
+    // what a syncing client is told at login, the cursor sits right below the oldest message still queued
+    pub async fn offline_summary(&mut self, user: &str) -> OfflineSummary {
+        self.expire_offline_messages(user).await;
+        let Some(queue) = self.user_id(user).and_then(|id| self.offline_messages.get(&id)) else {
+            return OfflineSummary::default();
+        };
+
+        let mut peers: Vec<(String, u64)> = Vec::new();
+        for sender in queue.iter().filter_map(QueuedMessage::sender) {
+            match peers.iter_mut().find(|(peer, _)| peer == sender) {
+                Some((_, count)) => *count += 1,
+                None => peers.push((sender.to_string(), 1)),
+            }
+        }
+        OfflineSummary {
+            cursor: queue.front().map_or(0, |queued| queued.sequence() - 1),
+            total: queue.len() as u64,
+            peers,
+        }
+    }
+
+    // drops what the cursor acknowledges and copies out up to `limit` of what follows, which stays queued
+    // until a later cursor acknowledges it too, so a batch lost with the connection is sent again
+    pub async fn offline_batch(&mut self, user: &str, cursor: u64, limit: usize) -> (Vec<(u64, Message)>, u64) {
+        self.expire_offline_messages(user).await;
+        let Some(id) = self.user_id(user) else {
+            return (Vec::new(), 0);
+        };
+        let Some(queue) = self.offline_messages.get_mut(&id) else {
+            return (Vec::new(), 0);
+        };
+
+        let mut delivered = Vec::new();
+        while let Some(queued) = queue.pop_front() {
+            if queued.sequence() > cursor {
+                queue.push_front(queued);
+                break;
+            }
+            delivered.extend(queued.sender().map(str::to_string));
+        }
+        let batch: Vec<_> = queue
+            .iter()
+            .take(limit)
+            .map(|queued| (queued.sequence(), queued.message.clone()))
+            .collect();
+        let remaining = (queue.len() - batch.len()) as u64;
+        if queue.is_empty() {
+            self.offline_messages.remove(&id);
+        }
+
+        for sender in delivered {
+            if let Some(counters) = self.relay_counters_mut(&sender) {
+                counters.delivered += 1;
+            }
+        }
+        (batch, remaining)
+    }
+
+    async fn expire_offline_messages(&mut self, user: &str) {
+        let now = self.clock.now();
+        let Some(id) = self.user_id(user) else {
             return;
+        };
+        let Some(queue) = self.offline_messages.remove(&id) else {
+            return;
+        };
+        let (expired, live): (Vec<_>, Vec<_>) = queue.into_iter().partition(|queued| queued.is_expired(now));
+        if !live.is_empty() {
+            self.offline_messages.insert(id, VecDeque::from(live));
         }
-        let session = self.sessions.get(&id).unwrap().read().await;
-        let user = session.user();
-        if let Some(user) = user {
-            self.users.get_mut(user).unwrap().remove_session_id();
+        self.expire_queued(user, expired).await;
+    }
+
+    // the author hears about it right away, or after their next login
+    async fn expire_queued(&mut self, recipient: &str, expired: Vec<QueuedMessage>) {
+        for queued in expired {
+            forget_unread(&mut self.unread, recipient, &queued.message);
+            self.expired_messages += 1;
+            let (Some(sender), Some(id)) = (queued.sender(), queued.id()) else {
+                continue;
+            };
+
+            let detail = format!("Message #{} to {} expired before it was delivered", id, recipient);
+            if let Some(counters) = self.relay_counters_mut(sender) {
+                counters.drop_message(&detail);
+            }
+            self.notify_user(
+                sender,
+                Message::message_expired(id, recipient),
+                NOTICE_MESSAGE_EXPIRED,
+                &detail,
+            )
+            .await;
         }
-        drop(session);
-        self.sessions.remove(&id);
     }
 
-    pub async fn is_active_session(&self, id: Uuid) -> bool {
-        self.sessions.contains_key(&id) && !self.sessions[&id].read().await.is_closed()
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
     }
 
-    pub async fn close_session(&mut self, id: Uuid) {
-        if let Some(session) = self.sessions.get(&id) {
-            session.write().await.close();
-            self.remove_session(id).await;
-            tracing::debug!("Closed session {}", id);
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_offline_ttl(&mut self, offline_ttl: Duration) {
+        self.offline_ttl = offline_ttl;
+    }
+
+    pub fn queued_messages(&self) -> u64 {
+        self.offline_messages.values().map(VecDeque::len).sum::<usize>() as u64
+    }
+
+    pub fn expired_messages(&self) -> u64 {
+        self.expired_messages
+    }
+
+    pub fn relay_counters(&self, user: &str) -> Option<RelayCounters> {
+        self.user_id(user).map(|id| self.relay_stats.get(id))
+    }
+
+    pub fn relay_counters_mut(&mut self, user: &str) -> Option<&mut RelayCounters> {
+        let id = self.user_id(user)?;
+        Some(self.relay_stats.get_mut(id))
+    }
+
+    pub fn queue_missed_notice(&mut self, user: &str, kind: &str, detail: &str) {
+        if let Some(id) = self.user_id(user) {
+            let now = self.clock.now();
+            self.missed_notices.entry(id).or_default().push(kind, detail, now);
         }
     }
 
-    pub async fn get_session_by_user(&self, user: &str) -> Option<ArcRwLock<Session>> {
-        if let Some(user) = self.users.get(user) {
-            if let Some(id) = user.session_id() {
-                return self.sessions.get(&id).cloned();
+    pub fn take_missed_notices(&mut self, user: &str) -> Vec<Message> {
+        let now = self.clock.now();
+        self.user_id(user)
+            .and_then(|id| self.missed_notices.remove(&id))
+            .map(|notices| notices.into_messages(now))
+            .unwrap_or_default()
+    }
+
+    pub fn unread(&self) -> &UnreadCounters {
+        &self.unread
+    }
+
+    pub fn unread_mut(&mut self) -> &mut UnreadCounters {
+        &mut self.unread
+    }
+
+    pub fn message_store(&self) -> &MessageStore {
+        &self.message_store
+    }
+
+    pub fn message_store_mut(&mut self) -> &mut MessageStore {
+        &mut self.message_store
+    }
+
+    pub fn edit_window(&self) -> Duration {
+        self.edit_window
+    }
+
+    pub fn set_edit_window(&mut self, edit_window: Duration) {
+        self.edit_window = edit_window;
+    }
+
+    pub fn deleted_history(&self) -> DeletedHistory {
+        self.deleted_history
+    }
+
+    pub fn set_deleted_history(&mut self, deleted_history: DeletedHistory) {
+        self.deleted_history = deleted_history;
+    }
+
+    pub fn max_message_ttl(&self) -> Duration {
+        self.max_message_ttl
+    }
+
+    pub fn set_max_message_ttl(&mut self, max_message_ttl: Duration) {
+        self.max_message_ttl = max_message_ttl;
+    }
+
+    // drops expired ephemeral messages from the store and expired messages from the offline queues
+    pub async fn purge_expired_messages(&mut self) -> usize {
+        let now = self.clock.now();
+        let mut purged = self.message_store.purge_expired(now);
+        let mut expired = Vec::new();
+        for (id, queue) in self.offline_messages.iter_mut() {
+            let (gone, kept): (VecDeque<_>, VecDeque<_>) = std::mem::take(queue)
+                .into_iter()
+                .partition(|queued| queued.is_expired(now));
+            *queue = kept;
+            if !gone.is_empty() {
+                expired.push((*id, Vec::from(gone)));
             }
         }
+        self.offline_messages.retain(|_, queue| !queue.is_empty());
 
-        None
+        for (id, gone) in expired {
+            purged += gone.len();
+            let user = self
+                .users
+                .get(&id)
+                .map(|user| user.name().to_string())
+                .unwrap_or_default();
+            self.expire_queued(&user, gone).await;
+        }
+        purged
     }
 
-    pub async fn get_user_by_session(&self, id: &Uuid) -> Option<String> {
-        if let Some(session) = self.sessions.get(id) {
-            return session.read().await.user().cloned();
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn set_data_dir(&mut self, data_dir: PathBuf) {
+        self.data_dir = data_dir;
+    }
+
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    pub fn set_server_name(&mut self, server_name: String) {
+        self.server_name = server_name;
+    }
+
+    pub fn server_id(&self) -> Uuid {
+        self.server_id
+    }
+
+    pub fn set_server_id(&mut self, server_id: Uuid) {
+        self.server_id = server_id;
+    }
+
+    pub fn motd(&self) -> &str {
+        &self.motd
+    }
+
+    // the message of the day from the file wins over one set before
+    pub fn set_motd_file(&mut self, path: PathBuf) -> Result<(), String> {
+        self.motd = data_dir::load_motd(&path)?;
+        self.motd_file = Some(path);
+        Ok(())
+    }
+
+    // the old message stays when it cannot be persisted, a restart would bring it back anyway
+    pub fn set_motd(&mut self, motd: &str) -> Result<(), String> {
+        if let Some(path) = &self.motd_file {
+            let written = if motd.is_empty() {
+                std::fs::remove_file(path).or_else(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                })
+            } else {
+                std::fs::write(path, format!("{}\n", motd))
+            };
+            written.map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
         }
-        None
+        self.motd = motd.to_string();
+        Ok(())
     }
 
-    pub async fn update_heartbeat(&self, id: Uuid, heartbeat: Option<chrono::DateTime<chrono::Utc>>) {
-        if let Some(session) = self.sessions.get(&id) {
-            session.write().await.update_heartbeat(heartbeat);
+    pub fn set_catalog(&mut self, catalog: Catalog) {
+        self.catalog = catalog;
+    }
+
+    pub async fn offline_sync(&self, id: Uuid) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => session.read().await.offline_sync(),
+            None => false,
         }
     }
 
-    pub async fn is_authenticated(&self, id: Uuid) -> bool {
+    pub async fn select_offline_sync(&self, id: Uuid) {
         if let Some(session) = self.sessions.get(&id) {
-            return session.read().await.user().is_some();
+            session.write().await.select_offline_sync();
         }
-        false
     }
 
-    pub async fn authenticate(&mut self, id: Uuid, user: String) {
+    pub async fn set_language(&self, id: Uuid, language: &str) {
         if let Some(session) = self.sessions.get(&id) {
-            if let Some(user) = self.users.get_mut(&user) {
-                user.set_session_id(id);
+            session.write().await.set_language(language);
+        }
+    }
+
+    // the text in the language the session asked for, or the server default
+    pub async fn text(&self, id: Uuid, key: &str, params: &[(&str, &str)]) -> String {
+        let language = match self.sessions.get(&id) {
+            Some(session) => session.read().await.language().map(str::to_string),
+            None => None,
+        };
+        self.catalog.text(language.as_deref(), key, params)
+    }
+
+    pub async fn error_text(&self, id: Uuid, code: ErrorCode, params: &[(&str, &str)]) -> String {
+        self.text(id, code.as_str(), params).await
+    }
+
+    pub fn set_rename_grace(&mut self, rename_grace: Duration) {
+        self.rename_grace = rename_grace;
+    }
+
+    pub fn set_dedup_limits(&mut self, capacity: usize, ttl: Duration) {
+        self.dedup.set_limits(capacity, ttl);
+    }
+
+    pub fn set_schedule_limits(&mut self, limits: ScheduleLimits) {
+        self.schedule.set_limits(limits);
+    }
+
+    // messages scheduled before a restart are picked up again, late ones go out on the first tick
+    pub fn load_schedule(&mut self, data_dir: &Path) -> Result<(), String> {
+        let pending = self.schedule.load(data_dir.join(SCHEDULE_FILE))?;
+        if pending > 0 {
+            tracing::info!("{} scheduled messages are waiting", pending);
+        }
+        Ok(())
+    }
+
+    pub fn load_message_ids(&mut self, data_dir: &Path) -> Result<(), String> {
+        let next = self.message_store.load_ids(data_dir.join(MESSAGE_IDS_FILE))?;
+        tracing::debug!("Message ids go on from {}", next);
+        Ok(())
+    }
+
+    pub fn load_api_tokens(&mut self, data_dir: &Path) -> Result<(), String> {
+        let tokens = self.api_tokens.load(data_dir.join(API_TOKENS_FILE))?;
+        tracing::debug!("{} API tokens loaded", tokens);
+        Ok(())
+    }
+
+    pub fn is_name_available(&self, name: &str) -> bool {
+        self.is_name_available_to(name, None)
+    }
+
+    // a reserved name is still free for the user who gave it up
+    fn is_name_available_to(&self, name: &str, claimant: Option<&str>) -> bool {
+        let folded = fold_username(name);
+        // changing only the case of your own name is fine
+        let owner = self.user_ids.get(&folded).copied();
+        (owner.is_none() || owner == claimant.and_then(|claimant| self.user_id(claimant)))
+            && self.reserved_names.get(&folded).map_or(true, |(holder, until)| {
+                Some(holder.as_str()) == claimant || *until <= self.clock.now()
+            })
+    }
+
+    // sessions, queues and notices follow the id, only what carries the name itself is rewritten
+    pub fn rename_user(&mut self, old: &str, new: &str) -> Result<(), String> {
+        validate_username(new)?;
+        let Some(id) = self.user_id(old) else {
+            return Err(format!("User {} does not exist", old));
+        };
+        if !self.is_name_available_to(new, Some(old)) {
+            return Err(format!("Username {} is taken", new));
+        }
+
+        self.user_ids.remove(&fold_username(old));
+        self.user_ids.insert(fold_username(new), id);
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_name(new);
+            // the key is salted with the name, the next password login derives a new one
+            user.set_auth_key(None);
+            if user.session_id().is_some() {
+                self.announce_presence(old, false);
+                self.announce_presence(new, true);
+            }
+        }
+        // queued messages from the renamed user should be answered under the new name
+        for queue in self.offline_messages.values_mut() {
+            for QueuedMessage { message, .. } in queue.iter_mut() {
+                if message.is(MessageType::DirectMessageReceive) && message.payload().str_field(0) == Ok(old) {
+                    *message = message.with_field_replaced(0, new.as_bytes().to_vec());
+                }
+            }
+        }
+        self.unread.rename(old, new);
+        self.message_store.rename(old, new);
+        self.schedule.rename(old, new);
+        self.api_tokens.rename(old, new);
+        self.publish_presence(id);
+
+        self.reserved_names.remove(&fold_username(new));
+        for (holder, _) in self.reserved_names.values_mut() {
+            if holder == old {
+                *holder = new.to_string();
+            }
+        }
+        if !self.rename_grace.is_zero() && fold_username(old) != fold_username(new) {
+            if let Ok(grace) = chrono::Duration::from_std(self.rename_grace) {
+                self.reserved_names
+                    .insert(fold_username(old), (new.to_string(), self.clock.now() + grace));
             }
-            self.sync_access_level(id, &user).await;
-            session.write().await.set_user(user);
         }
+        Ok(())
     }
 
-    pub async fn get_access_level(&self, id: Uuid) -> AccessLevel {
-        if let Some(session) = self.sessions.get(&id) {
-            return session.read().await.access_level().clone();
+    // the user's own sessions and everyone online they have talked to, returns who was told
+    pub async fn announce_rename(&self, old: &str, new: &str) -> BTreeSet<String> {
+        let mut recipients = self.message_store.peers_of(new);
+        recipients.insert(new.to_string());
+        let mut targets = Vec::new();
+        for recipient in &recipients {
+            for id in self.sessions_of_user(recipient).await {
+                if let Some(session) = self.sessions.get(&id) {
+                    targets.push((id, Arc::clone(session)));
+                }
+            }
         }
-        AccessLevel::Guest
+        let report = fan_out(targets, &Message::user_renamed(old, new)).await;
+        if !report.failed.is_empty() {
+            tracing::debug!(
+                "Rename of {} reached {} of {} sessions",
+                old,
+                report.delivered,
+                report.attempted()
+            );
+        }
+        recipients
     }
 
-    pub async fn sync_access_level(&self, id: Uuid, user: &str) {
-        if let Some(session) = self.sessions.get(&id) {
-            if let Some(user) = self.users.get(user) {
-                session.write().await.set_access_level(user.access_level().clone());
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn export_users(&self) -> JsonValue {
+        export::export_users(self.users.values())
+    }
+
+    pub fn import_users(&mut self, document: &str, mode: ImportMode) -> Result<(usize, Cleanup), String> {
+        let snapshot = export::parse_snapshot(document)?;
+        let count = export::import_users(&mut self.users, snapshot.users, mode)?;
+        self.user_ids = self
+            .users
+            .values()
+            .map(|user| (fold_username(user.name()), user.id()))
+            .collect();
+        let cleanup = Cleanup {
+            transient_fields: snapshot.transient_fields,
+            ..self.sanitize()
+        };
+        Ok((count, cleanup))
+    }
+
+    // the file holds a whole export, it replaces the built-in users
+    pub fn load_users(&mut self, path: &Path) -> Result<(usize, Cleanup), Box<dyn Error>> {
+        let document = std::fs::read_to_string(path)?;
+        Ok(self.import_users(&document, ImportMode::Replace)?)
+    }
+
+    // whatever still refers to a user or session that is gone, so nothing trips over it later
+    fn sanitize(&mut self) -> Cleanup {
+        let mut cleanup = Cleanup::default();
+        for user in self.users.values_mut() {
+            if user.session_id().is_some_and(|id| !self.sessions.contains_key(&id)) {
+                user.remove_session_id();
+                cleanup.session_references += 1;
             }
         }
+
+        let users = &self.users;
+        self.offline_messages.retain(|id, queue| {
+            let known = users.contains_key(id);
+            if !known {
+                cleanup.queued_messages += queue.len();
+            }
+            known
+        });
+        self.missed_notices.retain(|id, notices| {
+            let known = users.contains_key(id);
+            if !known {
+                cleanup.missed_notices += notices.len();
+            }
+            known
+        });
+        let user_ids = &self.user_ids;
+        cleanup.unread_counters = self
+            .unread
+            .retain_users(|name| user_ids.contains_key(&fold_username(name)));
+
+        let now = self.clock.now();
+        let reserved = self.reserved_names.len();
+        self.reserved_names.retain(|_, (_, until)| *until > now);
+        cleanup.reserved_names = reserved - self.reserved_names.len();
+        cleanup
     }
-}
 
-impl Application {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        use tracing_subscriber::fmt::format::FmtSpan;
-        tracing_subscriber::fmt()
-            .with_max_level(TRACING_LEVEL)
-            .compact()
-            .with_span_events(FmtSpan::FULL)
-            .init();
+    // walks every cross reference between sessions, users and what is kept for users, with `repair` the
+    // dangling ones are dropped and each drop is audited, anything that would take a guess is only reported
+    pub async fn check_consistency(&mut self, actor: &str, repair: bool) -> ConsistencyReport {
+        let mut violations = Vec::new();
+        let mut found = |invariant: &str, detail: String, repaired: bool| {
+            tracing::warn!("Consistency check: {}: {}", invariant, detail);
+            violations.push(ConsistencyViolation {
+                invariant: invariant.to_string(),
+                detail,
+                repaired,
+            });
+        };
+
+        // the open sessions of every user, newest login first
+        let mut logged_in: HashMap<Uuid, Vec<(DateTime<Utc>, Uuid)>> = HashMap::new();
+        let mut unknown_users = Vec::new();
+        for (id, session) in &self.sessions {
+            let session = session.read().await;
+            let Some(user) = session.user().filter(|_| !session.is_closed()) else {
+                continue;
+            };
+            match self.users.contains_key(&user) {
+                true => logged_in
+                    .entry(user)
+                    .or_default()
+                    .push((session.authenticated_at().unwrap_or(DateTime::<Utc>::MIN_UTC), *id)),
+                false => unknown_users.push((*id, user)),
+            }
+        }
+        for sessions in logged_in.values_mut() {
+            sessions.sort_by(|a, b| b.cmp(a));
+        }
+        for (id, user) in unknown_users {
+            if repair {
+                self.expire_session(id, "Your account no longer exists").await;
+            }
+            found(
+                "session_user",
+                format!("session {} is logged in as unknown user {}", id, user),
+                repair,
+            );
+        }
+
+        // a user points at one of their sessions, the newest one unless it was closed in the meantime
+        for user in self.users.values_mut() {
+            let newest = logged_in
+                .get(&user.id())
+                .and_then(|sessions| sessions.first())
+                .map(|(_, id)| *id);
+            let attached = logged_in
+                .get(&user.id())
+                .is_some_and(|sessions| sessions.iter().any(|(_, id)| Some(*id) == user.session_id()));
+            match (user.session_id(), newest) {
+                (Some(_), _) if attached => {}
+                (Some(id), _) => found(
+                    "user_session",
+                    format!("{} points at session {}, which is not open for them", user.name(), id),
+                    repair,
+                ),
+                (None, Some(id)) => found(
+                    "session_back_reference",
+                    format!("{} has open session {} but points at none", user.name(), id),
+                    repair,
+                ),
+                (None, None) => continue,
+            }
+            if repair {
+                match newest {
+                    Some(id) => user.set_session_id(id),
+                    None => user.remove_session_id(),
+                }
+            }
+        }
+
+        // the name index maps every folded name to the user that has it now, and nothing else
+        let mut stale_names = Vec::new();
+        for (name, id) in &self.user_ids {
+            match self.users.get(id) {
+                None => found(
+                    "username_index",
+                    format!("'{}' maps to unknown user {}", name, id),
+                    repair,
+                ),
+                Some(user) if fold_username(user.name()) != *name => found(
+                    "username_index",
+                    format!("'{}' maps to {}, who is called '{}' now", name, id, user.name()),
+                    repair,
+                ),
+                Some(_) => continue,
+            }
+            stale_names.push(name.clone());
+        }
+        if repair {
+            for name in &stale_names {
+                self.user_ids.remove(name);
+            }
+        }
+        for user in self.users.values() {
+            let name = fold_username(user.name());
+            match self.user_ids.get(&name) {
+                Some(id) if *id == user.id() => {}
+                // another user holds the name, which one keeps it is not ours to decide
+                Some(id) if !stale_names.contains(&name) => found(
+                    "username_index",
+                    format!("{} is missing from the index, '{}' maps to {}", user.id(), name, id),
+                    false,
+                ),
+                _ => {
+                    let repaired = repair && !self.user_ids.contains_key(&name);
+                    if repaired {
+                        self.user_ids.insert(name.clone(), user.id());
+                    }
+                    found(
+                        "username_index",
+                        format!("{} is missing from the index as '{}'", user.id(), name),
+                        repaired,
+                    );
+                }
+            }
+        }
+
+        // what is kept for users that are gone
+        let users = &self.users;
+        for (id, queue) in &self.offline_messages {
+            if !users.contains_key(id) {
+                found(
+                    "offline_queue",
+                    format!("{} messages are queued for unknown user {}", queue.len(), id),
+                    repair,
+                );
+            }
+        }
+        for (id, notices) in &self.missed_notices {
+            if !users.contains_key(id) {
+                found(
+                    "missed_notices",
+                    format!("{} notices wait for unknown user {}", notices.len(), id),
+                    repair,
+                );
+            }
+        }
+        let user_ids = &self.user_ids;
+        let known = |name: &str| {
+            user_ids
+                .get(&fold_username(name))
+                .is_some_and(|id| users.contains_key(id))
+        };
+        for (user, peer) in self.unread.pairs() {
+            if !known(user) || !known(peer) {
+                found(
+                    "unread_counter",
+                    format!("{} counts unread messages from {}, one of them is unknown", user, peer),
+                    repair,
+                );
+            }
+        }
+        let mut orphaned_transfers = Vec::new();
+        for (id, sender, recipient) in self.file_transfers.ends() {
+            if let Some(gone) = [sender, recipient]
+                .into_iter()
+                .find(|end| !self.sessions.contains_key(end))
+            {
+                found(
+                    "file_transfer",
+                    format!("transfer {} runs to session {}, which is gone", id, gone),
+                    repair,
+                );
+                orphaned_transfers.push(id);
+            }
+        }
 
-        Ok(Self::default())
+        violations.sort_by(|a, b| a.invariant.cmp(&b.invariant).then_with(|| a.detail.cmp(&b.detail)));
+        if repair {
+            self.offline_messages.retain(|id, _| users.contains_key(id));
+            self.missed_notices.retain(|id, _| users.contains_key(id));
+            self.unread.retain_users(known);
+            for id in orphaned_transfers {
+                self.file_transfers.remove(id);
+            }
+            for violation in violations.iter().filter(|violation| violation.repaired) {
+                self.audit(
+                    actor,
+                    "consistency_repair",
+                    format!("{}: {}", violation.invariant, violation.detail),
+                );
+            }
+        }
+        ConsistencyReport {
+            users: self.users.len() as u64,
+            sessions: self.sessions.len() as u64,
+            violations,
+        }
     }
 
-    pub async fn run(&self) -> Result<(), Box<dyn Error>> {
-        tracing::info!("Running application");
+    pub fn search_limiter_mut(&mut self) -> &mut RateLimiter {
+        &mut self.search_limiter
+    }
 
-        self.server.serve(Arc::clone(&self.shared_state)).await?;
+    pub fn dedup_mut(&mut self) -> &mut DedupCache {
+        &mut self.dedup
+    }
 
-        tracing::info!("Application finished");
-        Ok(())
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
     }
-}
 
-impl Default for Application {
-    fn default() -> Self {
-        Self {
-            server: Server::new(),
-            shared_state: Arc::new(RwLock::new(SharedState::new())),
+    pub fn schedule_mut(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+
+    pub fn api_tokens(&self) -> &ApiTokens {
+        &self.api_tokens
+    }
+
+    pub fn api_tokens_mut(&mut self) -> &mut ApiTokens {
+        &mut self.api_tokens
+    }
+
+    #[cfg_attr(not(feature = "http-bridge"), allow(dead_code))]
+    pub fn set_http_bridge_addr(&mut self, addr: SocketAddr) {
+        self.http_bridge_addr = Some(addr);
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn http_bridge_addr(&self) -> Option<SocketAddr> {
+        self.http_bridge_addr
+    }
+
+    pub fn file_transfers(&self) -> &FileTransfers {
+        &self.file_transfers
+    }
+
+    pub fn file_transfers_mut(&mut self) -> &mut FileTransfers {
+        &mut self.file_transfers
+    }
+
+    pub async fn send_to_session(&self, id: Uuid, message: Message) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => session.read().await.send(message).is_ok(),
+            None => false,
         }
     }
+
+    pub fn set_offline_queue(&mut self, offline_queue: bool) {
+        self.offline_queue = offline_queue;
+    }
+
+    pub fn conceal_users(&self) -> bool {
+        self.conceal_users
+    }
+
+    pub fn set_conceal_users(&mut self, conceal_users: bool) {
+        self.conceal_users = conceal_users;
+    }
+
+    pub fn set_listen_only(&mut self, listen_only: bool) {
+        self.listen_only = listen_only;
+    }
+
+    pub fn plain_auth(&self) -> bool {
+        self.plain_auth
+    }
+
+    pub fn set_plain_auth(&mut self, plain_auth: bool) {
+        self.plain_auth = plain_auth;
+    }
+
+    pub fn takeover_policy(&self) -> TakeoverPolicy {
+        self.takeover_policy
+    }
+
+    pub fn set_takeover_policy(&mut self, takeover_policy: TakeoverPolicy) {
+        self.takeover_policy = takeover_policy;
+    }
+
+    pub async fn nonce_of(&self, id: Uuid) -> Option<String> {
+        match self.sessions.get(&id) {
+            Some(session) => Some(session.read().await.nonce().to_string()),
+            None => None,
+        }
+    }
+
+    pub async fn peer_of(&self, id: Uuid) -> Option<std::net::SocketAddr> {
+        match self.sessions.get(&id) {
+            Some(session) => session.read().await.peer(),
+            None => None,
+        }
+    }
+
+    // detaches the old session from its user and asks it to disconnect, the old
+    // connection may already be dead so nothing here waits for it
+    pub async fn take_over_session(&mut self, user: &str, old_id: Uuid, new_id: Uuid) {
+        let new_peer = self.peer_of(new_id).await;
+
+        if let Some(user) = self.user_mut(user) {
+            if user.session_id() == Some(old_id) {
+                user.remove_session_id();
+            }
+        }
+
+        if let Some(session) = self.sessions.get(&old_id) {
+            let mut session = session.write().await;
+            session.demote();
+
+            let language = session.language();
+            let detail = match new_peer {
+                Some(peer) => self.catalog.text(
+                    language,
+                    "session_takeover_from",
+                    &[("address", &peer.ip().to_string())],
+                ),
+                None => self.catalog.text(language, NOTICE_SESSION_TAKEOVER, &[]),
+            };
+            session
+                .send(Message::security_notice(NOTICE_SESSION_TAKEOVER, &detail))
+                .ok();
+            session
+                .send(Message::disconnect(DisconnectReason::Kicked, &detail))
+                .ok();
+        }
+
+        tracing::warn!("Session {} of {} was taken over by session {}", old_id, user, new_id);
+        self.audit(user, "session_takeover", format!("{} -> {}", old_id, new_id));
+    }
+
+    pub fn set_shutdown_tx(&mut self, tx: mpsc::Sender<ShutdownReason>) {
+        self.shutdown_tx = Some(tx);
+    }
+
+    pub async fn shutdown(&self, reason: ShutdownReason) {
+        if let Some(tx) = &self.shutdown_tx {
+            // a second shutdown request finds the server already stopped
+            if tx.send(reason).await.is_err() {
+                tracing::debug!("Server is already shut down");
+            }
+        }
+    }
+
+    pub fn mode(&self) -> ServerMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ServerMode) {
+        self.mode = mode;
+    }
+
+    pub async fn logged_in_user_count(&self) -> usize {
+        let mut count = 0;
+        for session in self.sessions.values() {
+            if session.read().await.user().is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn audit(&mut self, actor: &str, action: &str, detail: String) {
+        let entry = self.audit.record(actor, action, detail);
+        if let Some(journal) = &self.journal {
+            journal.push(JournalEntry::audit(
+                entry.timestamp(),
+                entry.actor(),
+                entry.action(),
+                entry.detail(),
+            ));
+        }
+    }
+
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    // what ServerStats answers with and metric snapshots write, the counters count since startup
+    pub async fn server_stats(&self) -> ServerStats {
+        ServerStats {
+            mode: self.mode().as_str().to_string(),
+            sessions: self.sessions().len() as u64,
+            users: self.logged_in_user_count().await as u64,
+            missed_heartbeats: self.missed_heartbeats().await,
+            stalled_writes: self.stalled_writes(),
+            flood_warnings: self.flood_warnings(),
+            flood_disconnects: self.flood_disconnects(),
+            queued_messages: self.queued_messages(),
+            expired_messages: self.expired_messages(),
+            closed_by_peer: self.closed_by_peer(),
+            broken_connections: self.broken_connections(),
+            presence_coalesced: self.presence_coalesced(),
+            presence_dropped: self.presence_dropped(),
+            accept_errors: self.accept_errors(),
+            integrity_failures: self.integrity_failures(),
+            webhooks_delivered: self.webhooks_delivered(),
+            webhooks_failed: self.webhooks_failed(),
+            auth_queue_depth: self.auth_limiter().queue_depth(),
+            auth_rejected: self.auth_limiter().rejected(),
+            journal_dropped: self.journal.as_ref().map_or(0, Journal::dropped),
+            journal_failing: self.journal.as_ref().is_some_and(Journal::is_failing),
+            server_name: self.server_name().to_string(),
+            server_id: self.server_id().to_string(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub fn set_log_control(&mut self, log_control: LogControl) {
+        self.log_control = Some(log_control);
+    }
+
+    pub fn plugins(&self) -> Plugins {
+        self.plugins.clone()
+    }
+
+    pub fn set_plugins(&mut self, plugins: Plugins) {
+        self.plugins = plugins;
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn log_control(&self) -> Option<&LogControl> {
+        self.log_control.as_ref()
+    }
+
+    pub fn log_control_mut(&mut self) -> Option<&mut LogControl> {
+        self.log_control.as_mut()
+    }
+
+    pub fn get_user(&self, name: &str) -> Option<&User> {
+        self.users.get(&self.user_id(name)?)
+    }
+
+    pub fn sessions(&self) -> &HashMap<Uuid, ArcRwLock<Session>> {
+        &self.sessions
+    }
+
+    // cheap to take under the state lock, the sends happen after it is released
+    pub fn session_handles(&self) -> Vec<(Uuid, ArcRwLock<Session>)> {
+        self.sessions
+            .iter()
+            .map(|(id, session)| (*id, Arc::clone(session)))
+            .collect()
+    }
+
+    pub async fn remove_session(&mut self, id: Uuid) {
+        if !self.sessions.contains_key(&id) {
+            return;
+        }
+        let session = self.sessions.get(&id).unwrap().read().await;
+        let user = session.user();
+        drop(session);
+        self.sessions.remove(&id);
+        self.presence_subscriptions.remove_session(id);
+        // the user may already be attached to a newer session after a takeover
+        if let Some(user_id) = user.filter(|user| self.users.get(user).and_then(User::session_id) == Some(id)) {
+            // with concurrent logins another session of the user takes over
+            let mut remaining = None;
+            for (other, session) in &self.sessions {
+                if session.read().await.user() == Some(user_id) {
+                    remaining = Some(*other);
+                    break;
+                }
+            }
+            if let Some(user) = self.users.get_mut(&user_id) {
+                match remaining {
+                    Some(other) => user.set_session_id(other),
+                    None => user.remove_session_id(),
+                }
+            }
+        }
+
+        for (transfer_id, peer) in self.file_transfers.remove_session(id) {
+            self.send_to_session(peer, Message::file_reject(transfer_id, "Peer disconnected"))
+                .await;
+        }
+        if let Some(user_id) = user {
+            self.presence_changed(user_id).await;
+        }
+    }
+
+    pub async fn is_active_session(&self, id: Uuid) -> bool {
+        self.sessions.contains_key(&id) && !self.sessions[&id].read().await.is_closed()
+    }
+
+    pub async fn close_session(&mut self, id: Uuid) {
+        if let Some(session) = self.sessions.get(&id) {
+            session.write().await.close();
+            self.remove_session(id).await;
+            tracing::debug!("Closed session {}", id);
+        }
+    }
+
+    pub async fn get_session_by_user(&self, user: &str) -> Option<ArcRwLock<Session>> {
+        if let Some(user) = self.get_user(user) {
+            if let Some(id) = user.session_id() {
+                return self.sessions.get(&id).cloned();
+            }
+        }
+
+        None
+    }
+
+    pub async fn get_user_by_session(&self, id: &Uuid) -> Option<String> {
+        let user = self.sessions.get(id)?.read().await.user()?;
+        self.users.get(&user).map(|user| user.name().to_string())
+    }
+
+    // the rank of the session's user, or the one a control session without a user was opened with
+    pub async fn access_level_of(&self, id: Uuid) -> Option<AccessLevel> {
+        let session = self.sessions.get(&id)?.read().await;
+        match session.user() {
+            Some(user) => self.users.get(&user).map(|user| user.access_level().clone()),
+            None => Some(session.access_level().clone()),
+        }
+    }
+
+    // who the audit names for what a session did, a control session without a user acts as the server
+    pub async fn actor_of(&self, id: Uuid) -> String {
+        self.get_user_by_session(&id)
+            .await
+            .unwrap_or_else(|| SERVER_ACTOR.to_string())
+    }
+
+    // without a timestamp from the client the server's own time is recorded, returns the reply to send
+    pub async fn receive_heartbeat(
+        &self,
+        id: Uuid,
+        heartbeat: Option<chrono::DateTime<chrono::Utc>>,
+        echo: Option<HeartbeatEcho>,
+    ) -> Option<HeartbeatEcho> {
+        let session = self.sessions.get(&id)?;
+        let now = self.clock.now();
+        session
+            .write()
+            .await
+            .receive_heartbeat(heartbeat.unwrap_or(now), echo, now)
+    }
+
+    // the probe to send, None once the session is gone
+    pub async fn expect_heartbeat(&self, id: Uuid) -> Option<HeartbeatEcho> {
+        let session = self.sessions.get(&id)?;
+        Some(session.write().await.next_heartbeat(false))
+    }
+
+    pub async fn begin_disconnect(&self, id: Uuid) {
+        if let Some(session) = self.sessions.get(&id) {
+            session.write().await.begin_disconnect();
+        }
+    }
+
+    pub async fn is_disconnecting(&self, id: Uuid) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => session.read().await.is_disconnecting(),
+            None => false,
+        }
+    }
+
+    pub async fn check_heartbeat(&self, id: Uuid) -> u32 {
+        match self.sessions.get(&id) {
+            Some(session) => session.write().await.check_heartbeat(),
+            None => 0,
+        }
+    }
+
+    pub async fn record_received(&self, id: Uuid, message_type: MessageType) {
+        if let Some(session) = self.sessions.get(&id) {
+            session
+                .write()
+                .await
+                .record_received(message_type == MessageType::Heartbeat, self.clock.now());
+        }
+    }
+
+    pub async fn record_sent(&self, id: Uuid, message_type: MessageType) {
+        if let Some(session) = self.sessions.get(&id) {
+            session
+                .write()
+                .await
+                .record_sent(message_type == MessageType::PresenceUpdate);
+        }
+    }
+
+    // the sessions the filter selects with their level, oldest connection first
+    pub async fn list_sessions(&self, filter: &SessionFilter) -> Vec<(Uuid, AccessLevel, SessionInfo)> {
+        let now = self.clock.now();
+        let age = |since: Option<DateTime<Utc>>| since.map_or(0, |since| (now - since).num_seconds().max(0) as u64);
+
+        let mut listed = Vec::new();
+        for (id, session) in &self.sessions {
+            let session = session.read().await;
+            let username = session.user().and_then(|user| self.users.get(&user)).map(User::name);
+            let ip = session.peer().map(|peer| peer.ip().to_string()).unwrap_or_default();
+            let idle = age(session.last_activity());
+            if !filter.matches(username.is_none(), idle, &ip) {
+                continue;
+            }
+            let info = SessionInfo {
+                id: id.to_string(),
+                username: username.unwrap_or("guest").to_string(),
+                peer: session.peer().map(|peer| peer.to_string()).unwrap_or_default(),
+                access_level: session.access_level().as_str().to_string(),
+                connected: age(session.connected_at()),
+                idle,
+                heartbeat_age: age(session.last_heartbeat()),
+                received: session.frames_received(),
+                sent: session.frames_sent(),
+                received_age: age(session.last_received()),
+                two_way_age: age(session.last_two_way()),
+                integrity: session.integrity().as_str().to_string(),
+                integrity_failures: u64::from(session.integrity_failures()),
+            };
+            listed.push((*id, session.access_level().clone(), info));
+        }
+        listed.sort_by(|(_, _, a), (_, _, b)| b.connected.cmp(&a.connected).then_with(|| a.id.cmp(&b.id)));
+        listed
+    }
+
+    pub async fn missed_heartbeats(&self) -> u64 {
+        let mut missed = 0;
+        for session in self.sessions.values() {
+            missed += session.read().await.total_missed_heartbeats();
+        }
+        missed
+    }
+
+    pub fn record_stalled_write(&mut self) {
+        self.stalled_writes += 1;
+    }
+
+    pub fn stalled_writes(&self) -> u64 {
+        self.stalled_writes
+    }
+
+    pub fn flood_limits(&self) -> FloodLimits {
+        self.flood_limits
+    }
+
+    pub fn set_flood_limits(&mut self, flood_limits: FloodLimits) {
+        self.flood_limits = flood_limits;
+    }
+
+    pub fn protocol_versions(&self) -> VersionRange {
+        self.protocol_versions
+    }
+
+    pub fn set_protocol_versions(&mut self, protocol_versions: VersionRange) {
+        self.protocol_versions = protocol_versions;
+    }
+
+    pub fn record_password_check(&mut self) {
+        self.password_checks += 1;
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn password_checks(&self) -> u64 {
+        self.password_checks
+    }
+
+    // the same limits keep the limiter in place, along with whoever waits in it
+    pub fn set_auth_limits(&mut self, limits: AuthLimits) {
+        if self.auth_limiter.limits() != limits {
+            self.auth_limiter = Arc::new(AuthLimiter::new(limits));
+        }
+    }
+
+    pub fn auth_limiter(&self) -> Arc<AuthLimiter> {
+        Arc::clone(&self.auth_limiter)
+    }
+
+    pub fn record_flood_warning(&mut self) {
+        self.flood_warnings += 1;
+    }
+
+    // the address stays on the cooldown list after the session is gone
+    pub fn record_flood_disconnect(&mut self, ip: Option<IpAddr>, cooldown: Duration) {
+        self.flood_disconnects += 1;
+        if let Some(ip) = ip {
+            let now = self.clock.instant().into_std();
+            self.flood_cooldowns.insert(ip, now + cooldown, now);
+        }
+    }
+
+    pub fn flood_cooldown(&self, ip: IpAddr) -> Option<Duration> {
+        self.flood_cooldowns.remaining(ip, self.clock.instant().into_std())
+    }
+
+    pub fn set_violation_limits(&mut self, limits: ViolationLimits) {
+        self.ip_guard.set_limits(limits);
+    }
+
+    // true when the address is banned from now on
+    pub fn record_violation(&mut self, ip: IpAddr) -> bool {
+        self.ip_guard.record_violation(ip, self.clock.instant().into_std())
+    }
+
+    pub fn ip_ban(&self, ip: IpAddr) -> Option<Duration> {
+        self.ip_guard.ban_remaining(ip, self.clock.instant().into_std())
+    }
+
+    pub fn record_ban_turn_away(&mut self) {
+        self.ip_guard.record_turned_away();
+    }
+
+    pub fn clear_ip_ban(&mut self, ip: IpAddr) -> bool {
+        self.ip_guard.clear(ip, self.clock.instant().into_std())
+    }
+
+    // remaining time rounded up, a ban with half a second left is not over yet
+    pub fn ip_ban_list(&self) -> IpBanList {
+        let bans = self
+            .ip_guard
+            .bans(self.clock.instant().into_std())
+            .into_iter()
+            .map(|(ip, remaining)| {
+                let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+                (ip.to_string(), seconds)
+            })
+            .collect();
+        IpBanList {
+            bans,
+            issued: self.ip_guard.issued(),
+            turned_away: self.ip_guard.turned_away(),
+        }
+    }
+
+    pub fn flood_warnings(&self) -> u64 {
+        self.flood_warnings
+    }
+
+    pub fn flood_disconnects(&self) -> u64 {
+        self.flood_disconnects
+    }
+
+    pub fn record_connection_end(&mut self, broken: bool) {
+        if broken {
+            self.broken_connections += 1;
+        } else {
+            self.closed_by_peer += 1;
+        }
+    }
+
+    pub fn set_integrity(&mut self, integrity: Vec<Integrity>) {
+        self.integrity = integrity;
+    }
+
+    pub fn offers_integrity(&self, integrity: Integrity) -> bool {
+        integrity == Integrity::Crc32 || self.integrity.contains(&integrity)
+    }
+
+    pub fn set_max_integrity_failures(&mut self, max_integrity_failures: u32) {
+        self.max_integrity_failures = max_integrity_failures;
+    }
+
+    pub fn max_integrity_failures(&self) -> u32 {
+        self.max_integrity_failures
+    }
+
+    pub async fn select_integrity(&self, id: Uuid, integrity: Integrity) {
+        if let Some(session) = self.sessions.get(&id) {
+            session.write().await.set_integrity(integrity);
+        }
+    }
+
+    // the failures of the session so far, this one included
+    pub async fn record_integrity_failure(&mut self, id: Uuid) -> u32 {
+        self.integrity_failures += 1;
+        match self.sessions.get(&id) {
+            Some(session) => session.write().await.record_integrity_failure(),
+            None => 0,
+        }
+    }
+
+    pub fn integrity_failures(&self) -> u64 {
+        self.integrity_failures
+    }
+
+    pub fn set_offline_webhooks(&mut self, offline_webhooks: OfflineWebhooks) {
+        self.offline_webhooks = offline_webhooks;
+    }
+
+    #[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+    pub fn set_offline_notice_tx(&mut self, tx: mpsc::UnboundedSender<OfflineNotice>) {
+        self.offline_notice_tx = Some(tx);
+    }
+
+    #[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+    pub fn record_webhook(&mut self, delivered: bool) {
+        match delivered {
+            true => self.webhooks_delivered += 1,
+            false => self.webhooks_failed += 1,
+        }
+    }
+
+    pub fn webhooks_delivered(&self) -> u64 {
+        self.webhooks_delivered
+    }
+
+    pub fn webhooks_failed(&self) -> u64 {
+        self.webhooks_failed
+    }
+
+    pub fn record_accept_error(&mut self) {
+        self.accept_errors += 1;
+    }
+
+    pub fn accept_errors(&self) -> u64 {
+        self.accept_errors
+    }
+
+    pub fn set_presence_tx(&mut self, tx: mpsc::UnboundedSender<(String, bool)>) {
+        self.presence_tx = Some(tx);
+    }
+
+    fn announce_presence(&self, user: &str, online: bool) {
+        if let Some(tx) = &self.presence_tx {
+            tx.send((user.to_string(), online)).ok();
+        }
+    }
+
+    // a user stays online for as long as any of their sessions is logged in
+    async fn presence_changed(&mut self, user_id: Uuid) {
+        self.publish_presence(user_id);
+        if self.presence_tx.is_none() {
+            return;
+        }
+        let Some(name) = self.users.get(&user_id).map(User::name) else {
+            return;
+        };
+        let mut online = false;
+        for session in self.sessions.values() {
+            if session.read().await.user() == Some(user_id) {
+                online = true;
+                break;
+            }
+        }
+        self.announce_presence(name, online);
+    }
+
+    // the logged in sessions with their user, taken under the state lock and sent to after it is released
+    // listening guests go by no name, unless who is online is kept from strangers
+    pub async fn presence_targets(&self) -> Vec<(String, ArcRwLock<Session>)> {
+        let guests = self.listen_only && !self.conceal_users;
+        let mut targets = Vec::new();
+        for session in self.sessions.values() {
+            let user = session.read().await.user();
+            match user.and_then(|user| self.users.get(&user)) {
+                Some(user) => targets.push((user.name().to_string(), Arc::clone(session))),
+                None if guests => targets.push((String::new(), Arc::clone(session))),
+                None => {}
+            }
+        }
+        targets
+    }
+
+    // the snapshot to answer with, sent under the same lock as every delta so none can overtake it
+    pub async fn subscribe_presence(
+        &mut self,
+        session_id: Uuid,
+        scope: PresenceScope,
+        tx: mpsc::UnboundedSender<Message>,
+    ) -> Result<PresenceSnapshot, String> {
+        let Some(session) = self.sessions.get(&session_id) else {
+            return Err("The session is gone".to_string());
+        };
+        let Some(user_id) = session.read().await.user() else {
+            return Err("Log in to follow presence".to_string());
+        };
+        let version = self
+            .presence_subscriptions
+            .subscribe(session_id, user_id, scope.clone(), tx)?;
+        let members = self.presence_members(user_id, &scope);
+        Ok(self.presence_subscriptions.snapshot(scope, version, |name| {
+            members.as_ref().map_or(true, |members| members.contains(name))
+        }))
+    }
+
+    pub fn unsubscribe_presence(&mut self, session_id: Uuid, scope: &PresenceScope) -> bool {
+        self.presence_subscriptions.unsubscribe(session_id, scope)
+    }
+
+    // who a subscriber of the scope hears about, None for everyone
+    fn presence_members(&self, subscriber: Uuid, scope: &PresenceScope) -> Option<BTreeSet<String>> {
+        match scope {
+            PresenceScope::All => None,
+            PresenceScope::Contacts => Some(
+                self.users
+                    .get(&subscriber)
+                    .map(|user| self.message_store.peers_of(user.name()))
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    // tells subscribers how the user changed since they last heard, online means attached to a session
+    fn publish_presence(&mut self, user_id: Uuid) {
+        let user = self.users.get(&user_id);
+        let current = user
+            .filter(|user| user.session_id().is_some())
+            .map(|user| PresenceEntry {
+                user: user.name().to_string(),
+                status: match user.preferences().dnd() {
+                    true => PresenceStatus::DoNotDisturb,
+                    false => PresenceStatus::Online,
+                },
+            });
+        // contacts are looked up by the current name, the one a rename left behind is no longer in the store
+        let name = user.map(|user| user.name().to_string()).unwrap_or_default();
+        let changes = self.presence_subscriptions.update(user_id, current);
+        if changes.is_empty() {
+            return;
+        }
+        let targets: Vec<(Uuid, PresenceScope)> = self
+            .presence_subscriptions
+            .subscriptions()
+            .into_iter()
+            .filter(|(_, subscriber, scope)| {
+                self.presence_members(*subscriber, scope)
+                    .map_or(true, |members| members.contains(&name))
+            })
+            .map(|(session, _, scope)| (session, scope))
+            .collect();
+        for (change, entry) in changes {
+            for (session, scope) in &targets {
+                self.presence_subscriptions.send(*session, scope, change, entry.clone());
+            }
+        }
+    }
+
+    pub fn record_presence(&mut self, coalesced: u64, dropped: u64) {
+        self.presence_coalesced += coalesced;
+        self.presence_dropped += dropped;
+    }
+
+    pub fn presence_coalesced(&self) -> u64 {
+        self.presence_coalesced
+    }
+
+    pub fn presence_dropped(&self) -> u64 {
+        self.presence_dropped
+    }
+
+    pub fn closed_by_peer(&self) -> u64 {
+        self.closed_by_peer
+    }
+
+    pub fn broken_connections(&self) -> u64 {
+        self.broken_connections
+    }
+
+    pub async fn is_authenticated(&self, id: Uuid) -> bool {
+        if let Some(session) = self.sessions.get(&id) {
+            return session.read().await.user().is_some();
+        }
+        false
+    }
+
+    pub async fn authenticate(&mut self, id: Uuid, user: &str) {
+        let Some(user_id) = self.user_id(user) else {
+            tracing::warn!("Cannot authenticate session {} as unknown user {}", id, user);
+            return;
+        };
+        if let Some(session) = self.sessions.get(&id) {
+            if let Some(user) = self.users.get_mut(&user_id) {
+                user.set_session_id(id);
+            }
+            self.sync_access_level(id, user).await;
+            session.write().await.set_user(user_id, self.clock.now());
+            self.presence_changed(user_id).await;
+        }
+    }
+
+    // lives for one control socket command, logged in as `user` without taking over their connection,
+    // showing them online or counting towards the peak, without a user it is the server's own with an admin's rights
+    pub async fn open_control_session(
+        &mut self,
+        user: Option<&str>,
+        tx: mpsc::UnboundedSender<Message>,
+    ) -> Option<Uuid> {
+        let user_id = match user {
+            Some(user) => Some(self.user_id(user)?),
+            None => None,
+        };
+        let mut session = Session::new();
+        let id = session.id();
+        session.set_channel(tx);
+        match user_id {
+            Some(user_id) => session.set_user(user_id, self.clock.now()),
+            None => session.set_access_level(AccessLevel::Admin),
+        }
+        self.sessions.insert(id, Arc::new(RwLock::new(session)));
+        if let Some(user) = user {
+            self.sync_access_level(id, user).await;
+        }
+        Some(id)
+    }
+
+    pub fn close_control_session(&mut self, id: Uuid) {
+        self.sessions.remove(&id);
+    }
+
+    pub async fn sessions_of_user(&self, user: &str) -> Vec<Uuid> {
+        let mut ids = Vec::new();
+        let Some(user) = self.user_id(user) else {
+            return ids;
+        };
+        for (id, session) in &self.sessions {
+            if session.read().await.user() == Some(user) {
+                ids.push(*id);
+            }
+        }
+        ids
+    }
+
+    pub async fn expired_sessions(&self, max_age: Duration) -> Vec<Uuid> {
+        let now = self.clock.now();
+        let mut ids = Vec::new();
+        for (id, session) in &self.sessions {
+            let authenticated_at = session.read().await.authenticated_at();
+            if authenticated_at.is_some_and(|at| (now - at).to_std().unwrap_or_default() >= max_age) {
+                ids.push(*id);
+            }
+        }
+        ids
+    }
+
+    pub async fn expire_session(&mut self, id: Uuid, reason: &str) {
+        let Some(session) = self.sessions.get(&id) else {
+            return;
+        };
+
+        let mut session = session.write().await;
+        let user_id = session.user();
+        if let Some(user) = user_id.and_then(|user| self.users.get_mut(&user)) {
+            if user.session_id() == Some(id) {
+                user.remove_session_id();
+            }
+        }
+        session.demote();
+        session.send(Message::reauth_required(reason)).ok();
+        drop(session);
+        // followed again after the next login, like on a new connection
+        self.presence_subscriptions.remove_session(id);
+        tracing::info!("Session {} requires re-authentication: {}", id, reason);
+        if let Some(user_id) = user_id {
+            self.presence_changed(user_id).await;
+        }
+    }
+
+    // the user keeps their connection, the new level applies to the next request
+    pub async fn set_access_level(&mut self, user: &str, access_level: AccessLevel) -> Option<AccessLevel> {
+        let sessions = self.sessions_of_user(user).await;
+        let user = self.user_mut(user)?;
+        let previous = user.access_level().clone();
+        user.set_access_level(access_level.clone());
+
+        for id in sessions {
+            if let Some(session) = self.sessions.get(&id) {
+                session.write().await.set_access_level(access_level.clone());
+            }
+        }
+
+        Some(previous)
+    }
+
+    pub async fn kick_session(&mut self, id: Uuid, reason: &str) {
+        if let Some(session) = self.sessions.get(&id) {
+            let session = session.read().await;
+            session.send(Message::security_notice(NOTICE_KICKED, reason)).ok();
+            session.send(Message::disconnect(DisconnectReason::Kicked, reason)).ok();
+        }
+    }
+
+    pub fn set_password(&mut self, user: &str, pw_hash: String, auth_key: String) -> bool {
+        match self.user_mut(user) {
+            Some(user) => {
+                user.set_pw_hash(pw_hash);
+                user.set_auth_key(Some(auth_key));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_auth_key(&mut self, user: &str, auth_key: String) {
+        if let Some(user) = self.user_mut(user) {
+            user.set_auth_key(Some(auth_key));
+        }
+    }
+
+    pub fn signing_key(&self, user: &str) -> Option<&[u8; SIGNING_KEY_LENGTH]> {
+        self.get_user(user)?.signing_key()
+    }
+
+    pub fn set_signing_key(&mut self, user: &str, key: Option<[u8; SIGNING_KEY_LENGTH]>) {
+        if let Some(user) = self.user_mut(user) {
+            user.set_signing_key(key);
+        }
+    }
+
+    pub fn set_preferences(&mut self, user: &str, updates: &[(String, String)]) -> Result<(), String> {
+        for (_, url) in updates
+            .iter()
+            .filter(|(key, value)| key == WEBHOOK && value != NO_WEBHOOK)
+        {
+            self.offline_webhooks.check(url)?;
+        }
+        let user_id = self
+            .user_id(user)
+            .ok_or_else(|| format!("User {} does not exist", user))?;
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.preferences_mut().set_all(updates)?;
+        }
+        // do not disturb is shown to those following the user's presence
+        self.publish_presence(user_id);
+        Ok(())
+    }
+
+    pub fn is_muted(&self, recipient: &str, sender: &str) -> bool {
+        match (self.get_user(recipient), self.user_id(sender)) {
+            (Some(recipient), Some(sender)) => recipient.preferences().mutes(sender),
+            _ => false,
+        }
+    }
+
+    // the sender may be typed in any case, it is stored by id
+    pub fn mute(&mut self, user: &str, sender: &str) -> Result<(), String> {
+        let sender = self
+            .resolve_username(sender)
+            .and_then(|sender| self.user_id(sender))
+            .ok_or_else(|| format!("User {} does not exist", sender))?;
+        let user = self
+            .user_mut(user)
+            .ok_or_else(|| format!("User {} does not exist", user))?;
+        user.preferences_mut().mute(sender)
+    }
+
+    pub fn unmute(&mut self, user: &str, sender: &str) -> Result<(), String> {
+        let sender = self
+            .resolve_username(sender)
+            .and_then(|sender| self.user_id(sender))
+            .ok_or_else(|| format!("User {} does not exist", sender))?;
+        let user = self
+            .user_mut(user)
+            .ok_or_else(|| format!("User {} does not exist", user))?;
+        user.preferences_mut().unmute(sender);
+        Ok(())
+    }
+
+    // current names, sorted, senders whose account is gone are left out
+    pub fn muted_senders(&self, user: &str) -> Vec<String> {
+        let Some(user) = self.get_user(user) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = user
+            .preferences()
+            .muted()
+            .iter()
+            .filter_map(|id| self.users.get(id))
+            .map(|sender| sender.name().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn set_access_presets(&mut self, access_presets: AccessPresets) {
+        self.access_presets = access_presets;
+    }
+
+    // what the session holds without its temporary grants, sessions without a user fall back to the guest preset
+    pub async fn permissions_of(&self, id: Uuid) -> Permissions {
+        self.permission_sets(id).await.0
+    }
+
+    pub async fn access_of(&self, id: Uuid, message_type: MessageType) -> Access {
+        let (base, temporary) = self.permission_sets(id).await;
+        Access::check(base, temporary, message_type)
+    }
+
+    async fn permission_sets(&self, id: Uuid) -> (Permissions, Permissions) {
+        let guest = self.guest_permissions();
+        let Some(session) = self.sessions.get(&id) else {
+            return (guest, Permissions::NONE);
+        };
+
+        let session = session.read().await;
+        match session.user().and_then(|user| self.users.get(&user)) {
+            Some(user) => (
+                user.permissions(&self.access_presets),
+                user.temporary_permissions(self.clock.now()),
+            ),
+            None if *session.access_level() == AccessLevel::Guest => (guest, Permissions::NONE),
+            None => (
+                self.access_presets.permissions(session.access_level()),
+                Permissions::NONE,
+            ),
+        }
+    }
+
+    // for what a user does while none of their sessions is involved, like a scheduled message going out
+    pub fn user_may(&self, user: &str, message_type: MessageType) -> bool {
+        self.get_user(user).is_some_and(|user| {
+            let permissions = user.permissions(&self.access_presets);
+            let temporary = user.temporary_permissions(self.clock.now());
+            !matches!(Access::check(permissions, temporary, message_type), Access::Denied)
+        })
+    }
+
+    // None when there is no such user
+    pub fn grant_temporary(
+        &mut self,
+        user: &str,
+        permission: Permissions,
+        duration: Duration,
+        granted_by: &str,
+    ) -> Option<DateTime<Utc>> {
+        let expires_at = self.clock.now() + chrono::Duration::from_std(duration).ok()?;
+        self.user_mut(user)?.grant_temporary(TemporaryGrant {
+            permission,
+            granted_by: granted_by.to_string(),
+            expires_at,
+        });
+        Some(expires_at)
+    }
+
+    pub fn revoke_temporary(&mut self, user: &str, permission: Permissions) -> Option<TemporaryGrant> {
+        self.user_mut(user)?.revoke_temporary(permission)
+    }
+
+    // every frame a grant let through, next to the grant itself in the audit log
+    pub async fn record_temporary_use(&mut self, id: Uuid, message_type: MessageType) {
+        let user = self.get_user_by_session(&id).await.unwrap_or_default();
+        self.audit(&user, "temporary_grant_used", format!("{:?}", message_type));
+    }
+
+    // run by the reaper, a grant that lapsed between two runs is already ignored by the permission check
+    pub fn expire_temporary_grants(&mut self) -> usize {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        for user in self.users.values_mut() {
+            for grant in user.take_expired_grants(now) {
+                expired.push((user.name().to_string(), grant));
+            }
+        }
+        for (user, grant) in &expired {
+            self.audit(
+                SERVER_ACTOR,
+                "temporary_grant_expired",
+                format!("{}: {}, granted by {}", user, grant.permission, grant.granted_by),
+            );
+        }
+        expired.len()
+    }
+
+    // a listen-only server takes nothing from guests but logging in, whatever the preset grants
+    fn guest_permissions(&self) -> Permissions {
+        let guest = self.access_presets.permissions(&AccessLevel::Guest);
+        match self.listen_only {
+            true => guest.intersect(Permissions::SESSION | Permissions::AUTHENTICATE),
+            false => guest,
+        }
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn set_permission_overrides(&mut self, user: &str, granted: Permissions, revoked: Permissions) -> bool {
+        match self.user_mut(user) {
+            Some(user) => {
+                user.set_permission_overrides(granted, revoked);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn sync_access_level(&self, id: Uuid, user: &str) {
+        if let Some(session) = self.sessions.get(&id) {
+            if let Some(user) = self.get_user(user) {
+                session.write().await.set_access_level(user.access_level().clone());
+            }
+        }
+    }
+}
+
+impl Application {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        use tracing_subscriber::{fmt::format::FmtSpan, prelude::*, reload, EnvFilter};
+
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(TRACING_LEVEL.as_str()));
+        let (filter, filter_handle) = reload::Layer::new(filter);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .compact()
+                    .with_span_events(FmtSpan::FULL),
+            )
+            .init();
+
+        let config = ServerConfig::from_env();
+        config.validate()?;
+        tracing::info!("Effective configuration: {}", config.summary());
+        Self::open(&config, Some(LogControl::new(filter_handle)))
+    }
+
+    // like new, for a config that was checked already and without taking over the process's logging
+    pub fn from_config(config: &ServerConfig) -> Result<Self, Box<dyn Error>> {
+        Self::open(config, None)
+    }
+
+    fn open(config: &ServerConfig, log_control: Option<LogControl>) -> Result<Self, Box<dyn Error>> {
+        if config.tenants().is_empty() {
+            return Ok(Self {
+                tenants: vec![Tenant::open(None, config, log_control)?],
+            });
+        }
+
+        // the filter is the process's, an admin of one tenant must not change what the others log
+        if log_control.is_some() {
+            tracing::info!("Log levels cannot be changed by tenant admins");
+        }
+        let mut tenants = Vec::new();
+        for (name, tenant) in config.tenants() {
+            tracing::info!("Effective configuration of tenant {}: {}", name, tenant.summary());
+            tenants.push(Tenant::open(Some(name), tenant, None)?);
+        }
+        Ok(Self { tenants })
+    }
+
+    // plugins are part of the server, so they are added before it runs, every tenant gets the same ones
+    pub fn with_plugin(mut self, plugin: Arc<dyn ServerPlugin>) -> Self {
+        self.tenants = self
+            .tenants
+            .into_iter()
+            .map(|tenant| tenant.with_plugin(Arc::clone(&plugin)))
+            .collect();
+        self
+    }
+
+    // errors are failures to start, once serving every way of stopping ends in a summary
+    pub async fn run(&self) -> Result<ShutdownSummary, Box<dyn Error>> {
+        tracing::info!("Running application");
+
+        let summary = tenant::run_all(&self.tenants).await?;
+
+        tracing::info!("Application finished");
+        Ok(summary)
+    }
+}
+
+// offline tools for USERS_FILE, the user database a server started now would load
+pub fn export_users_file(users_file: Option<&Path>, path: &Path) -> Result<usize, Box<dyn Error>> {
+    let state = load_state(users_file)?;
+    export::write_document(path, &state.export_users())?;
+    Ok(state.user_count())
+}
+
+// nothing is written unless the whole import applies
+pub fn import_users_file(users_file: &Path, path: &Path, mode: ImportMode) -> Result<usize, Box<dyn Error>> {
+    let mut state = load_state(Some(users_file))?;
+    let (count, cleanup) = state.import_users(&std::fs::read_to_string(path)?, mode)?;
+    if !cleanup.is_empty() {
+        tracing::warn!("Cleaned up while importing {}: {}", path.display(), cleanup);
+    }
+    export::write_document(users_file, &state.export_users())?;
+    Ok(count)
+}
+
+pub fn data_dir_path() -> PathBuf {
+    ServerConfig::from_env().data_dir()
+}
+
+// each tenant has a data directory, and with it a control socket, of its own
+pub fn tenant_data_dir_path(name: &str) -> Result<PathBuf, String> {
+    ServerConfig::from_env()
+        .tenant(name)
+        .map(ServerConfig::data_dir)
+        .ok_or_else(|| format!("Unknown tenant {}", name))
+}
+
+pub fn users_file() -> Option<PathBuf> {
+    ServerConfig::from_env().users_file()
+}
+
+fn load_state(users_file: Option<&Path>) -> Result<SharedState, Box<dyn Error>> {
+    let mut state = SharedState::new();
+    if let Some(path) = users_file.filter(|path| path.exists()) {
+        state.load_users(path)?;
+    }
+    Ok(state)
+}
+
+impl Default for Application {
+    fn default() -> Self {
+        Self {
+            tenants: vec![Tenant::new(Server::new(), SharedState::new())],
+        }
+    }
+}
+
+// recipients that still have updates waiting miss this one, returns how many changes were dropped that way
+pub async fn send_presence(targets: Vec<(String, ArcRwLock<Session>)>, batch: &PresenceBatch) -> u64 {
+    let mut dropped = 0;
+    for (user, session) in targets {
+        let chunks = batch.without(&user).chunks();
+        if chunks.is_empty() {
+            continue;
+        }
+        let Ok(mut session) = tokio::time::timeout(FANOUT_TIMEOUT, session.write()).await else {
+            dropped += chunks.iter().map(PresenceBatch::len).sum::<usize>() as u64;
+            continue;
+        };
+        for chunk in chunks {
+            if session.pending_presence() >= MAX_PENDING_PRESENCE {
+                dropped += chunk.len() as u64;
+                continue;
+            }
+            session
+                .send_presence(Message::presence_update(&chunk.online, &chunk.offline))
+                .ok();
+        }
+    }
+    dropped
+}
+
+// an expired message the user never saw should not stay in the unread count
+fn forget_unread(unread: &mut UnreadCounters, user: &str, message: &Message) {
+    if let Ok(sender) = message.payload().str_field(0) {
+        unread.decrement(user, sender);
+    }
+}
+
+// in the server's own words, for the relay counters, the sender is answered in its language
+fn recipient_error(code: ErrorCode, user: &str) -> (ErrorCode, String) {
+    (code, catalog::built_in(code.as_str(), &[("user", user)]))
 }
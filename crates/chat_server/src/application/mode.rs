@@ -0,0 +1,32 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerMode {
+    #[default]
+    Normal,
+    // existing sessions keep working, new connections are turned away
+    Draining,
+}
+
+impl ServerMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "normal" => Ok(ServerMode::Normal),
+            "draining" => Ok(ServerMode::Draining),
+            _ => Err(format!("Unknown server mode '{}', expected normal or draining", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerMode::Normal => "normal",
+            ServerMode::Draining => "draining",
+        }
+    }
+}
+
+impl fmt::Display for ServerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
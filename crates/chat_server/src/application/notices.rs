@@ -0,0 +1,38 @@
+use std::collections::VecDeque;
+
+use chat_core::protocol::{Message, NOTICE_TRUNCATED};
+use chrono::{DateTime, Utc};
+
+const MAX_MISSED_NOTICES: usize = 50;
+
+// notices for a user without a connected session, the oldest make room for new ones
+#[derive(Debug, Default)]
+pub struct MissedNotices {
+    notices: VecDeque<Message>,
+    truncated: usize,
+}
+
+impl MissedNotices {
+    pub fn push(&mut self, kind: &str, detail: &str, now: DateTime<Utc>) {
+        if self.notices.len() >= MAX_MISSED_NOTICES {
+            self.notices.pop_front();
+            self.truncated += 1;
+        }
+        self.notices.push_back(Message::missed_notice(kind, detail, now));
+    }
+
+    pub fn len(&self) -> usize {
+        self.notices.len()
+    }
+
+    // the truncation marker goes first, it stands in for the notices that came before the rest
+    pub fn into_messages(self, now: DateTime<Utc>) -> Vec<Message> {
+        let mut messages = Vec::with_capacity(self.notices.len() + 1);
+        if self.truncated > 0 {
+            let detail = format!("{} notices truncated", self.truncated);
+            messages.push(Message::missed_notice(NOTICE_TRUNCATED, &detail, now));
+        }
+        messages.extend(self.notices);
+        messages
+    }
+}
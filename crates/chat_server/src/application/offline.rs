@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageId, MessageType},
+};
+use chrono::{DateTime, Utc};
+
+use super::webhook_url::WebhookUrl;
+
+pub const OFFLINE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// what an offline notice shows of a body that is not sent whole
+const SNIPPET_CHARS: usize = 40;
+
+// a direct message or a notice about one waiting for its recipient to log in, given up on once it expires
+#[derive(Debug)]
+pub struct QueuedMessage {
+    pub message: Message,
+    pub expires_at: DateTime<Utc>,
+    // the id the message was stored under, kept next to the frame so nothing has to dig it out again
+    id: Option<MessageId>,
+    // counts up over every queue, a syncing client acknowledges everything up to one
+    sequence: u64,
+}
+
+impl QueuedMessage {
+    // the ttl of an ephemeral message takes the place of the queue's
+    pub fn new(message: Message, sequence: u64, ttl: Duration, now: DateTime<Utc>) -> Self {
+        let expires_at = message.payload().timestamp_field(4).unwrap_or_else(|_| {
+            chrono::Duration::from_std(ttl)
+                .ok()
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        });
+        Self {
+            id: message.message_id(),
+            message,
+            expires_at,
+            sequence,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+
+    // only set for the direct message itself, the notices about one start with its id instead
+    pub fn sender(&self) -> Option<&str> {
+        if !self.message.is(MessageType::DirectMessageReceive) {
+            return None;
+        }
+        self.message.payload().str_field(0).ok()
+    }
+
+    pub fn id(&self) -> Option<MessageId> {
+        self.id
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+// what operators allow of the webhooks users set for themselves, nothing while no domain is listed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OfflineWebhooks {
+    domains: Vec<String>,
+    // the whole body goes out next to the snippet, only for endpoints trusted to keep it
+    bodies: bool,
+}
+
+// a direct message that waits for a user with a webhook, posted as json by the delivery task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineNotice {
+    pub url: WebhookUrl,
+    pub sender: String,
+    pub recipient: String,
+    pub snippet: String,
+    pub body: Option<String>,
+    pub sent_at: String,
+}
+
+impl OfflineWebhooks {
+    pub fn new(domains: Vec<String>, bodies: bool) -> Self {
+        let domains = domains
+            .iter()
+            .map(|domain| domain.trim().to_ascii_lowercase())
+            .collect();
+        Self { domains, bodies }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.domains.is_empty()
+    }
+
+    // a listed domain allows its subdomains too
+    pub fn check(&self, url: &str) -> Result<WebhookUrl, String> {
+        if !self.is_enabled() {
+            return Err("Webhooks are not enabled on this server".to_string());
+        }
+        let url = WebhookUrl::parse(url).map_err(|e| format!("Invalid webhook, {}", e))?;
+        let host = url.host().to_ascii_lowercase();
+        let allowed = self
+            .domains
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+        match allowed {
+            true => Ok(url),
+            false => Err(format!("Webhooks to {} are not allowed on this server", host)),
+        }
+    }
+
+    // None for anything but a direct message, notices about one are not announced
+    pub fn notice(&self, url: WebhookUrl, recipient: &str, message: &Message) -> Option<OfflineNotice> {
+        if !message.is(MessageType::DirectMessageReceive) {
+            return None;
+        }
+        let payload = message.payload();
+        let body = payload.str_field(1).ok()?;
+        Some(OfflineNotice {
+            url,
+            sender: payload.str_field(0).ok()?.to_string(),
+            recipient: recipient.to_string(),
+            snippet: body.chars().take(SNIPPET_CHARS).collect(),
+            body: self.bodies.then(|| body.to_string()),
+            sent_at: payload.str_field(2).unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl OfflineNotice {
+    #[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+    pub fn to_json(&self) -> String {
+        let mut json = JsonValue::object()
+            .with("sender", self.sender.as_str())
+            .with("recipient", self.recipient.as_str())
+            .with("snippet", self.snippet.as_str())
+            .with("sent_at", self.sent_at.as_str());
+        if let Some(body) = &self.body {
+            json = json.with("body", body.as_str());
+        }
+        json.to_string()
+    }
+}
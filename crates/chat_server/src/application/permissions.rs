@@ -0,0 +1,349 @@
+use std::{fmt, ops::BitOr, time::Duration};
+
+use chat_core::protocol::MessageType;
+use chrono::{DateTime, Utc};
+
+use super::session::AccessLevel;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions(u32);
+
+// which permissions each access level expands to, the defaults can be replaced from config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPresets {
+    guest: Permissions,
+    user: Permissions,
+    moderator: Permissions,
+    admin: Permissions,
+}
+
+// the longest an admin may lend a permission for, anything longer is a change of access level
+pub const MAX_TEMPORARY_GRANT: Duration = Duration::from_secs(24 * 60 * 60);
+
+// one permission lent to a user by an admin, on top of the preset and the overrides, never exported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemporaryGrant {
+    pub permission: Permissions,
+    pub granted_by: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// what a permission check came to, a temporary grant is audited every time it makes the difference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allowed,
+    Temporary,
+    Denied,
+}
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    pub const SESSION: Self = Self(1 << 0);
+    pub const AUTHENTICATE: Self = Self(1 << 1);
+    pub const SEND_DM: Self = Self(1 << 2);
+    pub const CHANGE_PASSWORD: Self = Self(1 << 3);
+    pub const CREATE_ROOM: Self = Self(1 << 4);
+    pub const KICK: Self = Self(1 << 5);
+    pub const BAN: Self = Self(1 << 6);
+    pub const VIEW_STATS: Self = Self(1 << 7);
+    pub const DEBUG_LOG: Self = Self(1 << 8);
+    pub const SET_LOG_LEVEL: Self = Self(1 << 9);
+    pub const RESET_PASSWORD: Self = Self(1 << 10);
+    pub const SET_SERVER_MODE: Self = Self(1 << 11);
+    pub const SHUTDOWN: Self = Self(1 << 12);
+    pub const SET_ACCESS_LEVEL: Self = Self(1 << 13);
+    pub const READ_HISTORY: Self = Self(1 << 14);
+    pub const SEND_FILE: Self = Self(1 << 15);
+    pub const SET_PREFERENCES: Self = Self(1 << 16);
+    pub const EXPORT_STATE: Self = Self(1 << 17);
+    pub const RENAME_ACCOUNT: Self = Self(1 << 18);
+    pub const RENAME_USER: Self = Self(1 << 19);
+    pub const SET_MOTD: Self = Self(1 << 20);
+    pub const CHECK_STATE: Self = Self(1 << 21);
+    // frames only the server sends, no preset or override can grant it
+    pub const SERVER: Self = Self(1 << 31);
+
+    // names are part of the config format, never rename one that has shipped
+    const NAMES: &[(&str, Self)] = &[
+        ("session", Self::SESSION),
+        ("authenticate", Self::AUTHENTICATE),
+        ("send_dm", Self::SEND_DM),
+        ("change_password", Self::CHANGE_PASSWORD),
+        ("create_room", Self::CREATE_ROOM),
+        ("kick", Self::KICK),
+        ("ban", Self::BAN),
+        ("view_stats", Self::VIEW_STATS),
+        ("debug_log", Self::DEBUG_LOG),
+        ("set_log_level", Self::SET_LOG_LEVEL),
+        ("reset_password", Self::RESET_PASSWORD),
+        ("set_server_mode", Self::SET_SERVER_MODE),
+        ("shutdown", Self::SHUTDOWN),
+        ("set_access_level", Self::SET_ACCESS_LEVEL),
+        ("read_history", Self::READ_HISTORY),
+        ("send_file", Self::SEND_FILE),
+        ("set_preferences", Self::SET_PREFERENCES),
+        ("export_state", Self::EXPORT_STATE),
+        ("rename_account", Self::RENAME_ACCOUNT),
+        ("rename_user", Self::RENAME_USER),
+        ("set_motd", Self::SET_MOTD),
+        ("check_state", Self::CHECK_STATE),
+    ];
+
+    pub fn all() -> Self {
+        Self::NAMES
+            .iter()
+            .fold(Self::NONE, |permissions, (_, permission)| permissions | *permission)
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(&self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub fn intersect(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    // no wildcard on purpose, a new message type does not compile until it is given a permission
+    pub fn required_for(message_type: MessageType) -> Self {
+        match message_type {
+            MessageType::Empty
+            | MessageType::Ack
+            | MessageType::Nack
+            | MessageType::Disconnect
+            | MessageType::Heartbeat
+            | MessageType::TimeSync
+            | MessageType::ClientHello
+            | MessageType::IntegritySelect
+            | MessageType::SyncSelect
+            | MessageType::SyncRequest
+            | MessageType::SubscribePresence
+            | MessageType::UnsubscribePresence => Self::SESSION,
+            MessageType::Auth | MessageType::AuthChallenge | MessageType::AuthCreate => Self::AUTHENTICATE,
+            MessageType::PasswordChange => Self::CHANGE_PASSWORD,
+            MessageType::RenameAccount => Self::RENAME_ACCOUNT,
+            MessageType::DirectMessageSend
+            | MessageType::MessageEdit
+            | MessageType::MessageDelete
+            | MessageType::MarkConversationRead
+            | MessageType::ScheduleMessageSend
+            | MessageType::ScheduleList
+            | MessageType::ScheduleCancel
+            | MessageType::ApiTokenCreate
+            | MessageType::ApiTokenRevoke
+            | MessageType::ApiTokenList
+            | MessageType::SigningKeySet
+            | MessageType::SigningKeyRequest => Self::SEND_DM,
+            MessageType::HistoryRequest | MessageType::SearchRequest => Self::READ_HISTORY,
+            MessageType::FileOffer
+            | MessageType::FileAccept
+            | MessageType::FileReject
+            | MessageType::FileChunk
+            | MessageType::FileComplete => Self::SEND_FILE,
+            MessageType::SetPreference
+            | MessageType::GetPreferences
+            | MessageType::MuteAdd
+            | MessageType::MuteRemove
+            | MessageType::MuteList => Self::SET_PREFERENCES,
+            MessageType::ServerDebugLog => Self::DEBUG_LOG,
+            MessageType::ServerShutdown => Self::SHUTDOWN,
+            MessageType::AdminSetLogLevel => Self::SET_LOG_LEVEL,
+            MessageType::AdminResetPassword => Self::RESET_PASSWORD,
+            MessageType::AdminSetServerMode => Self::SET_SERVER_MODE,
+            MessageType::AdminServerStats | MessageType::AdminUserInfo | MessageType::AdminListSessions => {
+                Self::VIEW_STATS
+            }
+            MessageType::AdminKickUser | MessageType::AdminKickWhere => Self::KICK,
+            MessageType::AdminListIpBans | MessageType::AdminClearIpBan => Self::BAN,
+            MessageType::AdminSetAccessLevel | MessageType::AdminGrantTemporary | MessageType::AdminRevokeTemporary => {
+                Self::SET_ACCESS_LEVEL
+            }
+            MessageType::AdminExportState => Self::EXPORT_STATE,
+            MessageType::AdminRenameUser => Self::RENAME_USER,
+            MessageType::AdminSetMotd => Self::SET_MOTD,
+            MessageType::AdminConsistencyCheck => Self::CHECK_STATE,
+            MessageType::TimeSyncReply
+            | MessageType::AuthSuccess
+            | MessageType::AuthFailure
+            | MessageType::PasswordChanged
+            | MessageType::ServerShutdownWarning
+            | MessageType::AdminLogLevelChanged
+            | MessageType::IntegritySelected
+            | MessageType::ServerHello
+            | MessageType::ReauthRequired
+            | MessageType::SecurityNotice
+            | MessageType::ServerBusy
+            | MessageType::ServerStats
+            | MessageType::AccessLevelChanged
+            | MessageType::UserKicked
+            | MessageType::MissedNotice
+            | MessageType::StateExported
+            | MessageType::UserRenamed
+            | MessageType::UserInfo
+            | MessageType::Motd
+            | MessageType::SessionInfo
+            | MessageType::SessionListEnd
+            | MessageType::MessageError
+            | MessageType::DirectMessageReceive
+            | MessageType::MessageEdited
+            | MessageType::MessageDeleted
+            | MessageType::HistoryEntry
+            | MessageType::HistoryEnd
+            | MessageType::UnreadSummary
+            | MessageType::SearchResult
+            | MessageType::SearchEnd
+            | MessageType::MessageExpired
+            | MessageType::FileOffered
+            | MessageType::Preferences
+            | MessageType::PreferencesChanged
+            | MessageType::PresenceUpdate
+            | MessageType::PresenceSnapshot
+            | MessageType::PresenceDelta
+            | MessageType::IpBanList
+            | MessageType::ConsistencyReport
+            | MessageType::MutedSenders
+            | MessageType::OfflineSummary
+            | MessageType::SyncBatch
+            | MessageType::ScheduledMessages
+            | MessageType::ScheduleFired
+            | MessageType::ApiTokens
+            | MessageType::ApiTokenCreated
+            | MessageType::SigningKey
+            | MessageType::Break => Self::SERVER,
+        }
+    }
+
+    pub fn can_access(&self, message_type: &MessageType) -> bool {
+        self.contains(Self::required_for(*message_type))
+    }
+
+    // a single permission by its config name, lists and `all` are not one
+    pub fn named(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, permission)| *permission)
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut permissions = Self::NONE;
+
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == "all" {
+                permissions = permissions | Self::all();
+                continue;
+            }
+            let (_, permission) = Self::NAMES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .ok_or_else(|| format!("Unknown permission '{}'", name))?;
+            permissions = permissions | *permission;
+        }
+
+        Ok(permissions)
+    }
+}
+
+impl Access {
+    // base is what the user holds anyway, temporary what their live grants add
+    pub fn check(base: Permissions, temporary: Permissions, message_type: MessageType) -> Self {
+        if base.can_access(&message_type) {
+            Self::Allowed
+        } else if (base | temporary).can_access(&message_type) {
+            Self::Temporary
+        } else {
+            Self::Denied
+        }
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(_, permission)| self.contains(*permission))
+            .map(|(name, _)| *name)
+            .collect();
+
+        if names.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+impl AccessPresets {
+    pub fn permissions(&self, access_level: &AccessLevel) -> Permissions {
+        match access_level {
+            AccessLevel::Guest => self.guest,
+            AccessLevel::User => self.user,
+            AccessLevel::Moderator => self.moderator,
+            AccessLevel::Admin => self.admin,
+        }
+    }
+
+    pub fn with(mut self, access_level: &AccessLevel, permissions: Permissions) -> Self {
+        let preset = match access_level {
+            AccessLevel::Guest => &mut self.guest,
+            AccessLevel::User => &mut self.user,
+            AccessLevel::Moderator => &mut self.moderator,
+            AccessLevel::Admin => &mut self.admin,
+        };
+        *preset = permissions;
+        self
+    }
+
+    // one `level = permission, permission` line per preset, levels that are not listed keep their defaults
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut presets = Self::default();
+
+        for (index, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (level, permissions) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected 'level = permissions'", index + 1))?;
+            let level = AccessLevel::parse(level.trim()).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+            let permissions = Permissions::parse(permissions).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+            presets = presets.with(&level, permissions);
+        }
+
+        Ok(presets)
+    }
+}
+
+impl Default for AccessPresets {
+    fn default() -> Self {
+        let guest = Permissions::SESSION | Permissions::AUTHENTICATE;
+        let user = guest
+            | Permissions::SEND_DM
+            | Permissions::CHANGE_PASSWORD
+            | Permissions::CREATE_ROOM
+            | Permissions::READ_HISTORY
+            | Permissions::SEND_FILE
+            | Permissions::SET_PREFERENCES
+            | Permissions::RENAME_ACCOUNT;
+        let moderator = user | Permissions::KICK | Permissions::VIEW_STATS;
+
+        Self {
+            guest,
+            user,
+            moderator,
+            admin: Permissions::all(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use chat_core::protocol::Message;
+use uuid::Uuid;
+
+use super::{ArcRwLock, SharedState};
+
+// a plugin that takes longer than this is abandoned, the session moves on without it
+pub const PLUGIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type PluginFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+// which side of the handler an on_message call is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    Before,
+    After,
+}
+
+// every callback does nothing unless overridden, errors are logged and never reach the session
+pub trait ServerPlugin: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn on_session_open<'a>(&'a self, _context: &'a PluginContext, _session: Uuid) -> PluginFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_authenticated<'a>(&'a self, _context: &'a PluginContext, _session: Uuid, _user: &'a str) -> PluginFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // frames refused by the flood meter or the permission check never get here
+    fn on_message<'a>(
+        &'a self,
+        _context: &'a PluginContext,
+        _session: Uuid,
+        _message: &'a Message,
+        _dispatch: Dispatch,
+    ) -> PluginFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // the session is already gone, the context no longer knows its user
+    fn on_session_close<'a>(&'a self, _context: &'a PluginContext, _session: Uuid) -> PluginFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_shutdown<'a>(&'a self, _context: &'a PluginContext) -> PluginFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// what a plugin may see of the server, nothing in here changes state except sending to a user
+#[derive(Debug, Clone)]
+pub struct PluginContext {
+    shared_state: ArcRwLock<SharedState>,
+}
+
+// called in the order they were registered, one after the other
+#[derive(Debug, Clone, Default)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn ServerPlugin>>,
+}
+
+impl PluginContext {
+    pub(super) fn new(shared_state: ArcRwLock<SharedState>) -> Self {
+        Self { shared_state }
+    }
+
+    pub async fn server_name(&self) -> String {
+        self.shared_state.read().await.server_name().to_string()
+    }
+
+    pub async fn user_of(&self, session: Uuid) -> Option<String> {
+        self.shared_state.read().await.get_user_by_session(&session).await
+    }
+
+    pub async fn user_exists(&self, user: &str) -> bool {
+        self.shared_state.read().await.get_user(user).is_some()
+    }
+
+    // every logged in user once, however many sessions they have
+    pub async fn online_users(&self) -> Vec<String> {
+        let state = self.shared_state.read().await;
+        let mut users = Vec::new();
+        for id in state.sessions().keys() {
+            if let Some(user) = state.get_user_by_session(id).await {
+                users.push(user);
+            }
+        }
+        users.sort();
+        users.dedup();
+        users
+    }
+
+    // false when the user has no session, nothing is queued for later
+    pub async fn send_to_user(&self, user: &str, message: Message) -> bool {
+        self.shared_state.read().await.send_to_user(user, message).await
+    }
+}
+
+impl Plugins {
+    pub fn push(&mut self, plugin: Arc<dyn ServerPlugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub async fn session_opened(&self, shared_state: &ArcRwLock<SharedState>, session: Uuid) {
+        self.invoke("on_session_open", shared_state, |plugin, context| {
+            Box::pin(async move { plugin.on_session_open(&context, session).await })
+        })
+        .await;
+    }
+
+    pub async fn authenticated(&self, shared_state: &ArcRwLock<SharedState>, session: Uuid, user: &str) {
+        self.invoke("on_authenticated", shared_state, |plugin, context| {
+            let user = user.to_string();
+            Box::pin(async move { plugin.on_authenticated(&context, session, &user).await })
+        })
+        .await;
+    }
+
+    pub async fn message(
+        &self,
+        shared_state: &ArcRwLock<SharedState>,
+        session: Uuid,
+        message: &Message,
+        dispatch: Dispatch,
+    ) {
+        self.invoke("on_message", shared_state, |plugin, context| {
+            let message = message.clone();
+            Box::pin(async move { plugin.on_message(&context, session, &message, dispatch).await })
+        })
+        .await;
+    }
+
+    pub async fn session_closed(&self, shared_state: &ArcRwLock<SharedState>, session: Uuid) {
+        self.invoke("on_session_close", shared_state, |plugin, context| {
+            Box::pin(async move { plugin.on_session_close(&context, session).await })
+        })
+        .await;
+    }
+
+    pub async fn shutdown(&self, shared_state: &ArcRwLock<SharedState>) {
+        self.invoke("on_shutdown", shared_state, |plugin, context| {
+            Box::pin(async move { plugin.on_shutdown(&context).await })
+        })
+        .await;
+    }
+
+    // each call runs as its own task, so a plugin that panics only takes that task down
+    async fn invoke<F>(&self, callback: &str, shared_state: &ArcRwLock<SharedState>, call: F)
+    where
+        F: Fn(Arc<dyn ServerPlugin>, PluginContext) -> PluginFuture<'static>,
+    {
+        for plugin in &self.plugins {
+            let context = PluginContext::new(Arc::clone(shared_state));
+            let mut task = tokio::spawn(call(Arc::clone(plugin), context));
+            match tokio::time::timeout(PLUGIN_TIMEOUT, &mut task).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(e))) => tracing::warn!("Plugin {} failed in {}: {}", plugin.name(), callback, e),
+                Ok(Err(e)) => tracing::error!("Plugin {} crashed in {}: {}", plugin.name(), callback, e),
+                Err(_) => {
+                    task.abort();
+                    tracing::warn!(
+                        "Plugin {} did not finish {} within {:?}",
+                        plugin.name(),
+                        callback,
+                        PLUGIN_TIMEOUT
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+
+use uuid::Uuid;
+
+const DND: &str = "dnd";
+// where messages that wait for the user are announced, the server decides which hosts are allowed
+pub const WEBHOOK: &str = "webhook";
+pub const NO_WEBHOOK: &str = "none";
+const MAX_KEY_LENGTH: usize = 128;
+const MAX_WEBHOOK_LENGTH: usize = 512;
+// what a client is told it may set
+const VALID_KEYS: &str = "dnd or webhook";
+const MAX_MUTED_SENDERS: usize = 200;
+
+// per-user settings, kept on the user so every session sees the same values
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preferences {
+    dnd: bool,
+    // by user id, so a rename keeps the sender muted
+    muted: BTreeSet<Uuid>,
+    webhook: Option<String>,
+    // bumped by every change of the settings, mutes are not settings, they have their own list
+    version: u64,
+}
+
+impl Preferences {
+    pub fn dnd(&self) -> bool {
+        self.dnd
+    }
+
+    // their messages are still delivered and kept, they just do not count as unread or ask for attention
+    pub fn mutes(&self, sender: Uuid) -> bool {
+        self.muted.contains(&sender)
+    }
+
+    pub fn muted(&self) -> &BTreeSet<Uuid> {
+        &self.muted
+    }
+
+    pub fn mute(&mut self, sender: Uuid) -> Result<(), String> {
+        if !self.muted.contains(&sender) && self.muted.len() >= MAX_MUTED_SENDERS {
+            return Err(format!("At most {} senders can be muted", MAX_MUTED_SENDERS));
+        }
+        self.muted.insert(sender);
+        Ok(())
+    }
+
+    pub fn unmute(&mut self, sender: Uuid) {
+        self.muted.remove(&sender);
+    }
+
+    pub fn webhook(&self) -> Option<&str> {
+        self.webhook.as_deref()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.set_all(&[(key.to_string(), value.to_string())])
+    }
+
+    // applied to a copy, one invalid update leaves every setting as it was
+    pub fn set_all(&mut self, updates: &[(String, String)]) -> Result<(), String> {
+        let mut updated = self.clone();
+        for (key, value) in updates {
+            updated.apply(key, value)?;
+        }
+        updated.version += 1;
+        *self = updated;
+        Ok(())
+    }
+
+    // keys are `dnd` (on/off) and `webhook` (an url or none)
+    fn apply(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(format!("Preference keys are at most {} bytes", MAX_KEY_LENGTH));
+        }
+        if key == DND {
+            self.dnd = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("Unknown value '{}' for dnd, expected on or off", value)),
+            };
+            return Ok(());
+        }
+        if key == WEBHOOK {
+            if value.len() > MAX_WEBHOOK_LENGTH {
+                return Err(format!("Webhooks are at most {} bytes", MAX_WEBHOOK_LENGTH));
+            }
+            self.webhook = (value != NO_WEBHOOK).then(|| value.to_string());
+            return Ok(());
+        }
+
+        Err(format!("Unknown preference '{}', expected {}", key, VALID_KEYS))
+    }
+
+    // every setting as a key and value pair, an unset webhook is not listed
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let dnd = if self.dnd { "on" } else { "off" };
+        let mut entries = vec![(DND.to_string(), dnd.to_string())];
+        if let Some(webhook) = &self.webhook {
+            entries.push((WEBHOOK.to_string(), webhook.clone()));
+        }
+        entries
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use chat_core::protocol::{Message, PresenceChange, PresenceDelta, PresenceEntry, PresenceScope, PresenceSnapshot};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+pub const PRESENCE_INTERVAL: Duration = Duration::from_millis(500);
+pub const PRESENCE_WINDOW: Duration = Duration::from_secs(1);
+// updates a recipient may have waiting to be written before it is skipped
+pub const MAX_PENDING_PRESENCE: usize = 2;
+// names per update, a whole restart's worth still fits into one payload field
+pub const MAX_PRESENCE_BATCH: usize = 1000;
+
+// scopes one session may follow at once
+pub const MAX_PRESENCE_SUBSCRIPTIONS: usize = 4;
+
+// updates go out every interval, a user has to keep a status for the window before it is announced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceTiming {
+    pub interval: Duration,
+    pub window: Duration,
+}
+
+// the changes that settled since the last tick, sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PresenceBatch {
+    pub online: Vec<String>,
+    pub offline: Vec<String>,
+}
+
+// raw online and offline events in, at most one change per user and tick out
+#[derive(Debug, Default)]
+pub struct PresenceCoalescer {
+    // who the last batches announced as online
+    announced: HashSet<String>,
+    // the newest status of users that changed, and when it last changed
+    pending: HashMap<String, (bool, Instant)>,
+    coalesced: u64,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    scope: PresenceScope,
+    version: u64,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    user: Uuid,
+    tx: mpsc::UnboundedSender<Message>,
+    subscriptions: Vec<Subscription>,
+}
+
+// snapshots and deltas are both taken from what subscribers were last told, so the two always agree
+#[derive(Debug, Default)]
+pub struct PresenceSubscriptions {
+    // by session, gone with the session
+    subscribers: HashMap<Uuid, Subscriber>,
+    // by user id, so a rename is a change of the entry and not a new user
+    published: HashMap<Uuid, PresenceEntry>,
+}
+
+impl Default for PresenceTiming {
+    fn default() -> Self {
+        Self {
+            interval: PRESENCE_INTERVAL,
+            window: PRESENCE_WINDOW,
+        }
+    }
+}
+
+impl PresenceBatch {
+    pub fn is_empty(&self) -> bool {
+        self.online.is_empty() && self.offline.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.online.len() + self.offline.len()
+    }
+
+    // what one recipient is told, nobody hears about themselves
+    pub fn without(&self, user: &str) -> PresenceBatch {
+        let others = |names: &[String]| names.iter().filter(|name| *name != user).cloned().collect();
+        PresenceBatch {
+            online: others(&self.online),
+            offline: others(&self.offline),
+        }
+    }
+
+    // split so no update carries more than MAX_PRESENCE_BATCH names
+    pub fn chunks(&self) -> Vec<PresenceBatch> {
+        let mut changes = self
+            .online
+            .iter()
+            .map(|name| (name, true))
+            .chain(self.offline.iter().map(|name| (name, false)))
+            .peekable();
+        let mut chunks = Vec::new();
+        while changes.peek().is_some() {
+            let mut chunk = PresenceBatch::default();
+            for (name, online) in changes.by_ref().take(MAX_PRESENCE_BATCH) {
+                match online {
+                    true => chunk.online.push(name.clone()),
+                    false => chunk.offline.push(name.clone()),
+                }
+            }
+            chunks.push(chunk);
+        }
+        chunks
+    }
+}
+
+impl PresenceCoalescer {
+    // an event for a user that is still waiting replaces the one before
+    pub fn record(&mut self, user: String, online: bool, now: Instant) {
+        if self.pending.insert(user, (online, now)).is_some() {
+            self.coalesced += 1;
+        }
+    }
+
+    // users that kept their status for the window, one that is back where it was announced needs no update
+    pub fn settle(&mut self, window: Duration, now: Instant) -> PresenceBatch {
+        let settled: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, since))| now.saturating_duration_since(*since) >= window)
+            .map(|(user, _)| user.clone())
+            .collect();
+
+        let mut changes = BTreeMap::new();
+        for user in settled {
+            let Some((online, _)) = self.pending.remove(&user) else {
+                continue;
+            };
+            if online == self.announced.contains(&user) {
+                self.coalesced += 1;
+                continue;
+            }
+            if online {
+                self.announced.insert(user.clone());
+            } else {
+                self.announced.remove(&user);
+            }
+            changes.insert(user, online);
+        }
+
+        let mut batch = PresenceBatch::default();
+        for (user, online) in changes {
+            match online {
+                true => batch.online.push(user),
+                false => batch.offline.push(user),
+            }
+        }
+        batch
+    }
+
+    // since the last call
+    pub fn take_coalesced(&mut self) -> u64 {
+        std::mem::take(&mut self.coalesced)
+    }
+}
+
+impl PresenceSubscriptions {
+    // the version of the snapshot to send, following a scope again starts over from a newer snapshot
+    pub fn subscribe(
+        &mut self,
+        session: Uuid,
+        user: Uuid,
+        scope: PresenceScope,
+        tx: mpsc::UnboundedSender<Message>,
+    ) -> Result<u64, String> {
+        let subscriber = self.subscribers.entry(session).or_insert_with(|| Subscriber {
+            user,
+            tx,
+            subscriptions: Vec::new(),
+        });
+        if let Some(subscription) = subscriber.subscriptions.iter_mut().find(|s| s.scope == scope) {
+            subscription.version += 1;
+            return Ok(subscription.version);
+        }
+        if subscriber.subscriptions.len() >= MAX_PRESENCE_SUBSCRIPTIONS {
+            return Err(format!(
+                "At most {} presence subscriptions per session",
+                MAX_PRESENCE_SUBSCRIPTIONS
+            ));
+        }
+        subscriber.subscriptions.push(Subscription { scope, version: 1 });
+        Ok(1)
+    }
+
+    pub fn unsubscribe(&mut self, session: Uuid, scope: &PresenceScope) -> bool {
+        let Some(subscriber) = self.subscribers.get_mut(&session) else {
+            return false;
+        };
+        let before = subscriber.subscriptions.len();
+        subscriber
+            .subscriptions
+            .retain(|subscription| subscription.scope != *scope);
+        let removed = subscriber.subscriptions.len() < before;
+        if subscriber.subscriptions.is_empty() {
+            self.subscribers.remove(&session);
+        }
+        removed
+    }
+
+    pub fn remove_session(&mut self, session: Uuid) {
+        self.subscribers.remove(&session);
+    }
+
+    // sessions and scopes currently followed, with the user that follows them
+    pub fn subscriptions(&self) -> Vec<(Uuid, Uuid, PresenceScope)> {
+        self.subscribers
+            .iter()
+            .flat_map(|(session, subscriber)| {
+                subscriber
+                    .subscriptions
+                    .iter()
+                    .map(|subscription| (*session, subscriber.user, subscription.scope.clone()))
+            })
+            .collect()
+    }
+
+    pub fn snapshot(&self, scope: PresenceScope, version: u64, included: impl Fn(&str) -> bool) -> PresenceSnapshot {
+        let mut entries: Vec<PresenceEntry> = self
+            .published
+            .values()
+            .filter(|entry| included(&entry.user))
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| a.user.cmp(&b.user));
+        PresenceSnapshot {
+            scope,
+            version,
+            entries,
+        }
+    }
+
+    // what changed since the user was last published, a rename leaves under the old name and joins under the new
+    pub fn update(&mut self, user: Uuid, current: Option<PresenceEntry>) -> Vec<(PresenceChange, PresenceEntry)> {
+        let previous = match &current {
+            Some(entry) => self.published.insert(user, entry.clone()),
+            None => self.published.remove(&user),
+        };
+        match (previous, current) {
+            (None, Some(current)) => vec![(PresenceChange::Joined, current)],
+            (Some(previous), None) => vec![(PresenceChange::Left, previous)],
+            (Some(previous), Some(current)) if previous.user != current.user => {
+                vec![(PresenceChange::Left, previous), (PresenceChange::Joined, current)]
+            }
+            (Some(previous), Some(current)) if previous.status != current.status => {
+                vec![(PresenceChange::StatusChanged, current)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // counts the subscription on and sends, false when the session is gone
+    pub fn send(&mut self, session: Uuid, scope: &PresenceScope, change: PresenceChange, entry: PresenceEntry) -> bool {
+        let Some(subscriber) = self.subscribers.get_mut(&session) else {
+            return false;
+        };
+        let Some(subscription) = subscriber.subscriptions.iter_mut().find(|s| s.scope == *scope) else {
+            return false;
+        };
+        subscription.version += 1;
+        let delta = PresenceDelta {
+            scope: scope.clone(),
+            version: subscription.version,
+            change,
+            entry,
+        };
+        subscriber.tx.send(Message::presence_delta(&delta)).is_ok()
+    }
+}
@@ -0,0 +1,36 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+// at most `limit` hits per user within any `window`
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: HashMap::new(),
+        }
+    }
+
+    // counts the hit if it is allowed, refused hits do not extend the wait
+    pub fn check(&mut self, user: &str, now: Instant) -> bool {
+        let hits = self.hits.entry(user.to_string()).or_default();
+        while hits.front().is_some_and(|hit| now.duration_since(*hit) >= self.window) {
+            hits.pop_front();
+        }
+
+        if hits.len() >= self.limit {
+            return false;
+        }
+        hits.push_back(now);
+        true
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+// what loading users had to clean up so the state agrees with itself again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cleanup {
+    // session ids and the like written by older builds, dropped while parsing
+    pub transient_fields: usize,
+    // users pointing at a session that no longer exists
+    pub session_references: usize,
+    // messages waiting for users that are not in the database anymore
+    pub queued_messages: usize,
+    pub missed_notices: usize,
+    pub unread_counters: usize,
+    // old names whose grace period ran out while nobody was looking
+    pub reserved_names: usize,
+}
+
+impl Cleanup {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            (self.transient_fields, "transient fields"),
+            (self.session_references, "stale session references"),
+            (self.queued_messages, "orphaned queued messages"),
+            (self.missed_notices, "orphaned missed notices"),
+            (self.unread_counters, "orphaned unread counters"),
+            (self.reserved_names, "expired name reservations"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect();
+
+        if parts.is_empty() {
+            return f.write_str("nothing");
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
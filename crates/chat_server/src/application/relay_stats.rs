@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+// what became of the messages one user sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayCounters {
+    pub sent: u64,
+    // straight away or later out of the offline queue
+    pub delivered: u64,
+    pub queued: u64,
+    pub dropped: u64,
+    pub last_error: Option<String>,
+}
+
+// keyed by user id so reconnects and renames keep the counters, nothing is persisted
+#[derive(Debug, Default)]
+pub struct RelayStats {
+    counters: HashMap<Uuid, RelayCounters>,
+}
+
+impl RelayCounters {
+    pub fn drop_message(&mut self, error: &str) {
+        self.dropped += 1;
+        self.last_error = Some(error.to_string());
+    }
+}
+
+impl RelayStats {
+    pub fn get(&self, user: Uuid) -> RelayCounters {
+        self.counters.get(&user).cloned().unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, user: Uuid) -> &mut RelayCounters {
+        self.counters.entry(user).or_default()
+    }
+
+    pub fn delivered(&self) -> u64 {
+        self.counters.values().map(|counters| counters.delivered).sum()
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, fmt, path::Path, time::Duration};
+
+use chat_core::{
+    capability::Capabilities,
+    protocol::{Message, MessageType},
+    trace::{summarize_field, Direction, TraceRecord},
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{
+    testing::{RawConnection, TestServer, TestServerBuilder},
+    ImportMode,
+};
+
+// how long a recorded frame may take to show up
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+// after the last record, frames arriving within this are ones the recording does not have
+const TRAILING_WAIT: Duration = Duration::from_millis(200);
+// the replay stamps a frame when it is handled, the recording when it was written
+const DEFAULT_TIME_TOLERANCE: Duration = Duration::from_secs(2);
+// eight byte fields in between are timestamps in microseconds, from 2000 to 2100
+const MICROS_FROM: u64 = 946_684_800_000_000;
+const MICROS_UNTIL: u64 = 4_102_444_800_000_000;
+
+// a recorded server trace played back against a fresh test server: the inbound frames of every session are sent
+// again in the recorded order with the clock set to when they arrived, the outbound ones are compared with what the
+// server answers now. nonces and ids are random on every run, they only have to map one to one onto the recorded
+// ones. logins that answered a challenge cannot be replayed, the nonce they answered is gone
+#[derive(Debug)]
+pub struct Replay {
+    records: Vec<TraceRecord>,
+    server: TestServerBuilder,
+    // a users export, loaded before the first frame
+    users: Option<String>,
+    time_tolerance: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // the record's line in the capture, one past the last for frames the recording ends before
+    pub frame: usize,
+    // numbered from 1 in the order the capture opens them
+    pub session: usize,
+    pub message_type: MessageType,
+    // None when the frame as a whole differs
+    pub field: Option<usize>,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    // outbound frames compared
+    pub frames: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+// random values of the recording and the replay, each stands for the same one on the other side every time
+#[derive(Debug, Default)]
+struct Identifiers {
+    recorded: BTreeMap<String, String>,
+    replayed: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+struct Comparison<'a> {
+    frame: usize,
+    session: usize,
+    expected: &'a Message,
+    actual: &'a Message,
+}
+
+impl Replay {
+    pub fn new(records: Vec<TraceRecord>) -> Self {
+        Self {
+            records,
+            server: TestServer::builder(),
+            users: None,
+            time_tolerance: DEFAULT_TIME_TOLERANCE,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let records = TraceRecord::read_all(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self::new(records))
+    }
+
+    // the capabilities come from the recorded server hello whatever the builder says
+    pub fn with_server(mut self, server: TestServerBuilder) -> Self {
+        self.server = server;
+        self
+    }
+
+    pub fn with_users(mut self, users: &str) -> Self {
+        self.users = Some(users.to_string());
+        self
+    }
+
+    pub fn with_time_tolerance(mut self, time_tolerance: Duration) -> Self {
+        self.time_tolerance = time_tolerance;
+        self
+    }
+
+    // errors for captures that cannot be played back at all, everything else is in the report
+    pub async fn run(self) -> Result<ReplayReport, String> {
+        let mut builder = self.server;
+        if let Some(hello) = self
+            .records
+            .iter()
+            .find(|record| record.direction() == Direction::Sent && record.message().is(MessageType::ServerHello))
+        {
+            let capabilities = hello.message().payload().str_field(0)?;
+            builder = builder.with_capabilities(Capabilities::parse(capabilities)?);
+        }
+        let server = builder.try_start()?;
+        if let Some(users) = &self.users {
+            server.reload_users(users, ImportMode::Replace).await?;
+        }
+
+        let mut report = ReplayReport::default();
+        let mut identifiers = Identifiers::default();
+        let mut sessions: BTreeMap<&str, (usize, RawConnection)> = BTreeMap::new();
+        for (index, record) in self.records.iter().enumerate() {
+            let frame = index + 1;
+            let recorded = record
+                .session()
+                .ok_or_else(|| format!("Frame {} belongs to no session, only server traces replay", frame))?;
+            let arrived = DateTime::parse_from_rfc3339(record.timestamp())
+                .map_err(|e| format!("Frame {}: {}", frame, e))?
+                .with_timezone(&Utc);
+            let count = sessions.len();
+            let (session, connection) = sessions.entry(recorded).or_insert_with(|| {
+                // the hello is stamped with the time the connection opened
+                server.clock().set_now(arrived);
+                (count + 1, server.unchecked_connection())
+            });
+
+            let expected = record.message();
+            match record.direction() {
+                Direction::Received => {
+                    server.clock().set_now(arrived);
+                    if let Err(e) = connection.try_send(expected.clone()).await {
+                        report
+                            .divergences
+                            .push(Divergence::whole(frame, *session, expected, "sent", &e));
+                    }
+                }
+                Direction::Sent => {
+                    report.frames += 1;
+                    let actual = match tokio::time::timeout(FRAME_TIMEOUT, connection.try_receive()).await {
+                        Ok(Ok(actual)) => actual,
+                        Ok(Err(e)) => {
+                            report
+                                .divergences
+                                .push(Divergence::whole(frame, *session, expected, "a frame", &e));
+                            continue;
+                        }
+                        Err(_) => {
+                            let waited = format!("nothing within {:?}", FRAME_TIMEOUT);
+                            report
+                                .divergences
+                                .push(Divergence::whole(frame, *session, expected, "a frame", &waited));
+                            continue;
+                        }
+                    };
+                    // the confirmation is the last frame with the old trailer
+                    if actual.is(MessageType::IntegritySelected) {
+                        if let Some(integrity) = actual.switches_integrity() {
+                            connection.set_integrity(integrity);
+                        }
+                    }
+                    let comparison = Comparison {
+                        frame,
+                        session: *session,
+                        expected,
+                        actual: &actual,
+                    };
+                    report
+                        .divergences
+                        .extend(comparison.divergences(&mut identifiers, self.time_tolerance));
+                }
+            }
+        }
+
+        // sessions the recording left open or closed, anything more is new
+        let frame = self.records.len() + 1;
+        for (session, connection) in sessions.values_mut() {
+            if let Ok(Ok(actual)) = tokio::time::timeout(TRAILING_WAIT, connection.try_receive()).await {
+                report.divergences.push(Divergence {
+                    frame,
+                    session: *session,
+                    message_type: actual.message_type(),
+                    field: None,
+                    expected: "nothing".to_string(),
+                    actual: format!("{:?}", actual.message_type()),
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl Divergence {
+    fn whole(frame: usize, session: usize, expected: &Message, wanted: &str, error: &str) -> Self {
+        Self {
+            frame,
+            session,
+            message_type: expected.message_type(),
+            field: None,
+            expected: wanted.to_string(),
+            actual: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {} (session {}) {:?}",
+            self.frame, self.session, self.message_type
+        )?;
+        if let Some(field) = self.field {
+            write!(f, " field {}", field)?;
+        }
+        write!(f, ": expected {:?}, got {:?}", self.expected, self.actual)
+    }
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames compared, {} divergences",
+            self.frames,
+            self.divergences.len()
+        )?;
+        for divergence in &self.divergences {
+            write!(f, "\n  {}", divergence)?;
+        }
+        Ok(())
+    }
+}
+
+impl Comparison<'_> {
+    fn divergences(&self, identifiers: &mut Identifiers, time_tolerance: Duration) -> Vec<Divergence> {
+        let divergence = |field: Option<usize>, expected: String, actual: String| Divergence {
+            frame: self.frame,
+            session: self.session,
+            message_type: self.expected.message_type(),
+            field,
+            expected,
+            actual,
+        };
+        if self.expected.message_type() != self.actual.message_type() {
+            return vec![divergence(
+                None,
+                format!("{:?}", self.expected.message_type()),
+                format!("{:?}", self.actual.message_type()),
+            )];
+        }
+
+        let (expected, actual) = (self.expected.payload().get_data(), self.actual.payload().get_data());
+        let summary = |field: Option<&Vec<u8>>| match field {
+            Some(field) => summarize_field(field),
+            None => "<missing>".to_string(),
+        };
+        (0..expected.len().max(actual.len()))
+            .filter(|&index| match (expected.get(index), actual.get(index)) {
+                (Some(expected), Some(actual)) => !identifiers.same_field(expected, actual, time_tolerance),
+                _ => true,
+            })
+            .map(|index| divergence(Some(index), summary(expected.get(index)), summary(actual.get(index))))
+            .collect()
+    }
+}
+
+impl Identifiers {
+    fn same_field(&mut self, expected: &[u8], actual: &[u8], time_tolerance: Duration) -> bool {
+        if expected == actual {
+            return true;
+        }
+        if let (Some(expected), Some(actual)) = (timestamp_of(expected), timestamp_of(actual)) {
+            return (expected - actual)
+                .abs()
+                .to_std()
+                .is_ok_and(|apart| apart <= time_tolerance);
+        }
+        match (std::str::from_utf8(expected), std::str::from_utf8(actual)) {
+            (Ok(expected), Ok(actual)) if Uuid::parse_str(expected).is_ok() && Uuid::parse_str(actual).is_ok() => {
+                self.same(expected, actual)
+            }
+            _ => false,
+        }
+    }
+
+    // the first time either is seen it is paired with the other
+    fn same(&mut self, recorded: &str, replayed: &str) -> bool {
+        match (self.recorded.get(recorded), self.replayed.get(replayed)) {
+            (None, None) => {
+                self.recorded.insert(recorded.to_string(), replayed.to_string());
+                self.replayed.insert(replayed.to_string(), recorded.to_string());
+                true
+            }
+            (Some(paired), _) => paired == replayed,
+            (None, Some(_)) => false,
+        }
+    }
+}
+
+// rfc 3339 text, or microseconds in eight bytes like history entries carry them
+fn timestamp_of(field: &[u8]) -> Option<DateTime<Utc>> {
+    if let Ok(micros) = <[u8; 8]>::try_from(field) {
+        let micros = u64::from_be_bytes(micros);
+        return (MICROS_FROM..MICROS_UNTIL)
+            .contains(&micros)
+            .then(|| DateTime::from_timestamp_micros(micros as i64))
+            .flatten();
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
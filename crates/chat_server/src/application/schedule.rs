@@ -0,0 +1,248 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf, time::Duration};
+
+use chat_core::{json::JsonValue, protocol::ScheduledEntry};
+use chrono::{DateTime, Utc};
+
+use super::{export, user::fold_username};
+
+pub const SCHEDULE_FILE: &str = "scheduled.json";
+pub const MAX_PENDING_SCHEDULED: usize = 20;
+pub const MAX_SCHEDULE_HORIZON: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleLimits {
+    // per sender, a fired or cancelled message makes room again
+    pub max_pending: usize,
+    // how far ahead a message may be due
+    pub max_horizon: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledMessage {
+    pub id: u64,
+    pub sender: String,
+    pub recipient: String,
+    pub body: String,
+    pub due: DateTime<Utc>,
+}
+
+// messages waiting for their due time, written through to the data directory on every change
+#[derive(Debug)]
+pub struct Schedule {
+    pending: BTreeMap<u64, ScheduledMessage>,
+    next_id: u64,
+    limits: ScheduleLimits,
+    // nothing is persisted without a data directory
+    file: Option<PathBuf>,
+}
+
+impl Default for ScheduleLimits {
+    fn default() -> Self {
+        Self {
+            max_pending: MAX_PENDING_SCHEDULED,
+            max_horizon: MAX_SCHEDULE_HORIZON,
+        }
+    }
+}
+
+impl ScheduledMessage {
+    pub fn entry(&self) -> ScheduledEntry {
+        ScheduledEntry {
+            id: self.id,
+            recipient: self.recipient.clone(),
+            body: self.body.clone(),
+            due: self.due,
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("id", self.id)
+            .with("sender", self.sender.as_str())
+            .with("recipient", self.recipient.as_str())
+            .with("body", self.body.as_str())
+            .with("due", self.due.to_rfc3339())
+    }
+
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let field = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| format!("Scheduled message without '{}'", key))
+        };
+        let due = field("due")?;
+        Ok(Self {
+            id: value
+                .get("id")
+                .and_then(JsonValue::as_u64)
+                .ok_or("Scheduled message without 'id'")?,
+            sender: field("sender")?.to_string(),
+            recipient: field("recipient")?.to_string(),
+            body: field("body")?.to_string(),
+            due: DateTime::parse_from_rfc3339(due)
+                .map_err(|_| format!("Invalid due time '{}'", due))?
+                .with_timezone(&Utc),
+        })
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            next_id: 1,
+            limits: ScheduleLimits::default(),
+            file: None,
+        }
+    }
+}
+
+impl Schedule {
+    pub fn set_limits(&mut self, limits: ScheduleLimits) {
+        self.limits = limits;
+    }
+
+    // what a previous run left behind, a missing file is an empty schedule
+    pub fn load(&mut self, path: PathBuf) -> Result<usize, String> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        if let Some(content) = content {
+            let problem = |e: String| format!("{}: {}", path.display(), e);
+            let document = JsonValue::parse(&content).map_err(problem)?;
+            for value in document
+                .get("scheduled")
+                .and_then(JsonValue::as_array)
+                .unwrap_or_default()
+            {
+                let scheduled = ScheduledMessage::from_json(value).map_err(problem)?;
+                self.next_id = self.next_id.max(scheduled.id + 1);
+                self.pending.insert(scheduled.id, scheduled);
+            }
+        }
+        self.file = Some(path);
+        Ok(self.pending.len())
+    }
+
+    // refused past the limits, checked against `now` when the request came in
+    pub fn add(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        body: &str,
+        due: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<u64, String> {
+        if due < now {
+            return Err("The time is in the past".to_string());
+        }
+        if (due - now).to_std().unwrap_or(Duration::MAX) > self.limits.max_horizon {
+            return Err(format!(
+                "Messages can be scheduled at most {} seconds ahead",
+                self.limits.max_horizon.as_secs()
+            ));
+        }
+        if self.pending_for(sender).len() >= self.limits.max_pending {
+            return Err(format!(
+                "At most {} messages can wait to be sent",
+                self.limits.max_pending
+            ));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(
+            id,
+            ScheduledMessage {
+                id,
+                sender: sender.to_string(),
+                recipient: recipient.to_string(),
+                body: body.to_string(),
+                due,
+            },
+        );
+        if let Err(e) = self.save() {
+            self.pending.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    // only the sender may cancel, someone else's id looks like one that does not exist
+    pub fn cancel(&mut self, sender: &str, id: u64) -> Result<ScheduledMessage, String> {
+        match self.pending.get(&id) {
+            Some(scheduled) if scheduled.sender == sender => {}
+            _ => return Err(format!("No scheduled message #{}", id)),
+        }
+        let cancelled = self.pending.remove(&id).expect("Checked above");
+        if let Err(e) = self.save() {
+            self.pending.insert(id, cancelled);
+            return Err(e);
+        }
+        Ok(cancelled)
+    }
+
+    // soonest first
+    pub fn pending_for(&self, sender: &str) -> Vec<&ScheduledMessage> {
+        let mut pending: Vec<&ScheduledMessage> = self
+            .pending
+            .values()
+            .filter(|scheduled| scheduled.sender == sender)
+            .collect();
+        pending.sort_by_key(|scheduled| (scheduled.due, scheduled.id));
+        pending
+    }
+
+    // removed before they are sent, a crash in between loses them rather than sending them twice
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledMessage> {
+        let due: Vec<u64> = self
+            .pending
+            .values()
+            .filter(|scheduled| scheduled.due <= now)
+            .map(|scheduled| scheduled.id)
+            .collect();
+        if due.is_empty() {
+            return Vec::new();
+        }
+        let mut taken: Vec<ScheduledMessage> = due.iter().filter_map(|id| self.pending.remove(id)).collect();
+        taken.sort_by_key(|scheduled| (scheduled.due, scheduled.id));
+        if let Err(e) = self.save() {
+            tracing::error!("{}", e);
+        }
+        taken
+    }
+
+    // recipients are kept as they were typed, so they are matched like a login would
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let mut changed = false;
+        for scheduled in self.pending.values_mut() {
+            if scheduled.sender == old {
+                scheduled.sender = new.to_string();
+                changed = true;
+            }
+            if fold_username(&scheduled.recipient) == fold_username(old) {
+                scheduled.recipient = new.to_string();
+                changed = true;
+            }
+        }
+        if changed {
+            if let Err(e) = self.save() {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let document = JsonValue::object().with(
+            "scheduled",
+            JsonValue::Array(self.pending.values().map(ScheduledMessage::to_json).collect()),
+        );
+        export::write_document(path, &document).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
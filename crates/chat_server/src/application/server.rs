@@ -1,107 +1,914 @@
-use std::sync::Arc;
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use chat_core::{
+    capability::{self, Capabilities},
     constants::{HOST, PORT},
-    protocol::{Message, MessageType},
+    dto::{Request, Response},
+    integrity::Integrity,
+    protocol::{DisconnectReason, Message, MessageType, MAX_FIELD_SIZE},
+    time_sync::{Clock, SystemClock},
+    trace::{Direction, FrameTracer},
+    transport::{Listener, Stream},
+    version::{FrameError, VersionRange},
 };
 use tokio::{
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpListener, TcpStream,
-    },
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
     sync::mpsc,
 };
+use tracing::Instrument;
 use uuid::Uuid;
 
-use super::{ArcRwLock, SharedState};
+use super::{
+    auth_limit::AuthLimits,
+    catalog::{Catalog, LANGUAGE_DIR},
+    control,
+    data_dir::{self, MOTD_FILE},
+    dedup::{DEDUP_CAPACITY, DEDUP_TTL},
+    flood::{FloodLimits, FloodMeter, FloodVerdict},
+    ip_guard::ViolationLimits,
+    journal::{write_journal, FileJournal, Journal, JournalEntry, JournalLimits, JournalWriter},
+    mode::ServerMode,
+    offline::{OfflineWebhooks, OFFLINE_TTL},
+    permissions::Access,
+    plugin::{Dispatch, Plugins, ServerPlugin},
+    presence::{PresenceCoalescer, PresenceTiming},
+    schedule::ScheduleLimits,
+    send_presence,
+    store::{DeletedHistory, EDIT_WINDOW, MAX_MESSAGE_TTL},
+    transfers::{MAX_CHUNK_SIZE, MAX_FILE_SIZE},
+    ArcRwLock, SharedState, ShutdownReason, ShutdownSummary, SERVER_NAME,
+};
 use crate::application::{
     handles::{
-        admin::handle_server_shutdown,
-        auth::{handle_auth, handle_auth_create},
-        handle_heartbeat,
-        message::handle_direct_message_send,
+        admin::{
+            handle_consistency_check, handle_export_state, handle_grant_temporary, handle_list_sessions,
+            handle_rename_user, handle_reset_password, handle_revoke_temporary, handle_server_shutdown,
+            handle_server_stats, handle_set_access_level, handle_set_log_level, handle_set_motd,
+            handle_set_server_mode, handle_user_info, stop_server,
+        },
+        api_token::{handle_api_token_create, handle_api_token_list, handle_api_token_revoke},
+        auth::{handle_auth, handle_auth_challenge, handle_auth_create, handle_password_change, handle_rename_account},
+        file::{handle_file_accept, handle_file_chunk, handle_file_complete, handle_file_offer, handle_file_reject},
+        handle_client_hello, handle_heartbeat, handle_sync_select, handle_time_sync,
+        message::{
+            handle_direct_message_send, handle_history_request, handle_mark_conversation_read, handle_message_delete,
+            handle_message_edit, handle_sync_request,
+        },
+        moderation::{handle_clear_ip_ban, handle_kick_user, handle_kick_where, handle_list_ip_bans},
+        preferences::{handle_get_preferences, handle_mute, handle_mute_list, handle_set_preference},
+        presence::{handle_subscribe_presence, handle_unsubscribe_presence},
+        schedule::{
+            fire_scheduled_messages, handle_schedule_cancel, handle_schedule_list, handle_schedule_message_send,
+        },
+        search::handle_search_request,
+        signing::{handle_signing_key_request, handle_signing_key_set},
+        Replies,
     },
-    session::Session,
+    session::{Session, TakeoverPolicy},
 };
 
-const HEARTBEAT_INTERVAL: u64 = 30;
+pub const HEARTBEAT_INTERVAL: u64 = 30;
+pub const HEARTBEAT_GRACE: Duration = Duration::from_secs(10);
+pub const MAX_MISSED_HEARTBEATS: u32 = 3;
+pub const MAX_SESSION_AGE: u64 = 24 * 60 * 60;
+const MAX_REAP_INTERVAL: Duration = Duration::from_secs(60);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+// only for the audit log and memory, a lapsed grant stops counting the moment it lapses
+const GRANT_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+// how late a scheduled message may go out at most
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+const DRAIN_RETRY_AFTER: u64 = 30;
+pub const HANDSHAKE_TIMEOUT: u64 = 5;
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+// none is left out, it is only safe on a transport that checks the bytes itself
+pub const OFFERED_INTEGRITY: &[Integrity] = &[Integrity::Crc32, Integrity::Xxh3];
+pub const MAX_INTEGRITY_FAILURES: u32 = 3;
+const FLOOD_WARNING: &str = "Too many frames, slow down or be disconnected";
+const FLOOD_DISCONNECT: &str = "Too many frames";
+const IP_BANNED: &str = "Too many protocol errors from this address";
+const INTEGRITY_DISCONNECT: &str = "Too many frames failed the integrity check";
+const SESSION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// what is still queued after this is lost, a disk that is gone for good does not hold up the exit
+const JOURNAL_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// every interval the server sends a heartbeat, the client has the grace period to answer it
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    interval: Duration,
+    grace: Duration,
+    // 0 never disconnects
+    max_misses: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    handshake: Duration,
+    disconnect: Duration,
+    // a peer that stops reading leaves a write pending forever once its buffers are full
+    write: Duration,
+}
 
 #[derive(Debug)]
-pub struct Server {}
+pub struct Server {
+    port: u16,
+    heartbeat: Heartbeat,
+    timeouts: Timeouts,
+    tracer: Option<FrameTracer>,
+    capabilities: Capabilities,
+    max_session_age: Option<Duration>,
+    takeover_policy: TakeoverPolicy,
+    edit_window: Duration,
+    deleted_history: DeletedHistory,
+    max_message_ttl: Duration,
+    rename_grace: Duration,
+    dedup_capacity: usize,
+    dedup_ttl: Duration,
+    schedule_limits: ScheduleLimits,
+    max_file_size: u64,
+    max_chunk_size: u64,
+    data_dir: Option<PathBuf>,
+    // commands from `server ctl` on a socket in the data directory, needs one to be set
+    control_socket: bool,
+    // messages posted over http with a user's API token, needs the http-bridge feature
+    http_bridge: Option<SocketAddr>,
+    server_name: String,
+    // for clients that do not declare one, None answers in the built-in language
+    default_language: Option<String>,
+    plain_auth: bool,
+    offline_queue: bool,
+    offline_ttl: Duration,
+    conceal_users: bool,
+    // guests listen to broadcasts and presence, nothing they send beyond the session is taken
+    listen_only: bool,
+    // the hosts users may point their webhook at, posting needs the webhook feature
+    offline_webhooks: OfflineWebhooks,
+    flood_limits: FloodLimits,
+    violation_limits: ViolationLimits,
+    auth_limits: AuthLimits,
+    protocol_versions: VersionRange,
+    // what clients may select, each one but crc32 is advertised as a capability
+    integrity: Vec<Integrity>,
+    max_integrity_failures: u32,
+    // None keeps logins and logouts to the server
+    presence: Option<PresenceTiming>,
+    // the pause after a transient accept error, out of file descriptors stays that way for a moment
+    accept_backoff: Duration,
+    // audit entries and metric snapshots, written to the data directory unless another writer is set
+    journal_writer: Option<Arc<dyn JournalWriter>>,
+    journal_limits: JournalLimits,
+    clock: Arc<dyn Clock>,
+    plugins: Plugins,
+}
 impl Server {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            port: PORT,
+            heartbeat: Heartbeat {
+                interval: Duration::from_secs(HEARTBEAT_INTERVAL),
+                grace: HEARTBEAT_GRACE,
+                max_misses: MAX_MISSED_HEARTBEATS,
+            },
+            timeouts: Timeouts {
+                handshake: Duration::from_secs(HANDSHAKE_TIMEOUT),
+                disconnect: DISCONNECT_TIMEOUT,
+                write: WRITE_TIMEOUT,
+            },
+            tracer: None,
+            capabilities: Capabilities::new()
+                .with(capability::DIRECT_MESSAGES)
+                .with(capability::ADMIN_LOG_LEVEL)
+                .with(capability::ADMIN_SERVER_MODE)
+                .with(capability::MODERATION)
+                .with(capability::MESSAGE_EDIT)
+                .with(capability::HISTORY)
+                .with(capability::FILE_TRANSFER)
+                .with(capability::UNREAD)
+                .with(capability::PREFERENCES)
+                .with(capability::PREFERENCE_BATCH)
+                .with(capability::EPHEMERAL_MESSAGES)
+                .with(capability::SEARCH)
+                .with(capability::LANGUAGES)
+                .with(capability::MUTING)
+                .with(capability::TEMPORARY_GRANTS)
+                .with(capability::OFFLINE_SYNC)
+                .with(capability::SCHEDULED_MESSAGES)
+                .with(capability::PRESENCE_SUBSCRIPTIONS)
+                .with(capability::MESSAGE_SIGNING),
+            max_session_age: Some(Duration::from_secs(MAX_SESSION_AGE)),
+            takeover_policy: TakeoverPolicy::default(),
+            edit_window: EDIT_WINDOW,
+            deleted_history: DeletedHistory::default(),
+            max_message_ttl: MAX_MESSAGE_TTL,
+            rename_grace: Duration::ZERO,
+            dedup_capacity: DEDUP_CAPACITY,
+            dedup_ttl: DEDUP_TTL,
+            schedule_limits: ScheduleLimits::default(),
+            max_file_size: MAX_FILE_SIZE,
+            max_chunk_size: MAX_CHUNK_SIZE,
+            data_dir: None,
+            control_socket: false,
+            http_bridge: None,
+            server_name: SERVER_NAME.to_string(),
+            default_language: None,
+            plain_auth: true,
+            offline_queue: true,
+            offline_ttl: OFFLINE_TTL,
+            conceal_users: false,
+            listen_only: false,
+            offline_webhooks: OfflineWebhooks::default(),
+            flood_limits: FloodLimits::default(),
+            violation_limits: ViolationLimits::default(),
+            auth_limits: AuthLimits::default(),
+            protocol_versions: VersionRange::default(),
+            integrity: OFFERED_INTEGRITY.to_vec(),
+            max_integrity_failures: MAX_INTEGRITY_FAILURES,
+            presence: None,
+            accept_backoff: ACCEPT_BACKOFF,
+            journal_writer: None,
+            journal_limits: JournalLimits::default(),
+            clock: Arc::new(SystemClock),
+            plugins: Plugins::default(),
+        }
+    }
+
+    // only used by serve, serve_on takes whatever listener it is given
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat.interval = heartbeat_interval;
+        self
+    }
+
+    // capped by the interval, an answer has to arrive before the next heartbeat goes out
+    pub fn with_heartbeat_grace(mut self, heartbeat_grace: Duration) -> Self {
+        self.heartbeat.grace = heartbeat_grace;
+        self
+    }
+
+    // consecutive unanswered heartbeats before the connection is dropped
+    pub fn with_max_missed_heartbeats(mut self, max_missed_heartbeats: u32) -> Self {
+        self.heartbeat.max_misses = max_missed_heartbeats;
+        self
+    }
+
+    // how long a new connection may stay silent before its first frame
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.timeouts.handshake = handshake_timeout;
+        self
+    }
+
+    // how long the side that sent a Disconnect waits for the Ack before closing anyway
+    pub fn with_disconnect_timeout(mut self, disconnect_timeout: Duration) -> Self {
+        self.timeouts.disconnect = disconnect_timeout;
+        self
+    }
+
+    // how long a single frame may take to leave before the session counts as dead
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.timeouts.write = write_timeout;
+        self
+    }
+
+    pub fn with_accept_backoff(mut self, accept_backoff: Duration) -> Self {
+        self.accept_backoff = accept_backoff;
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: FrameTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    // None disables expiry, authenticated sessions then live as long as their connection
+    pub fn with_max_session_age(mut self, max_session_age: Option<Duration>) -> Self {
+        self.max_session_age = max_session_age;
+        self
+    }
+
+    pub fn with_takeover_policy(mut self, takeover_policy: TakeoverPolicy) -> Self {
+        self.takeover_policy = takeover_policy;
+        self
+    }
+
+    // how long after sending a direct message its sender may still edit or delete it
+    pub fn with_edit_window(mut self, edit_window: Duration) -> Self {
+        self.edit_window = edit_window;
+        self
+    }
+
+    pub fn with_deleted_history(mut self, deleted_history: DeletedHistory) -> Self {
+        self.deleted_history = deleted_history;
+        self
+    }
+
+    // where admin exports are written, the shared state default is used otherwise
+    pub fn with_data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
+    pub fn with_control_socket(mut self, control_socket: bool) -> Self {
+        self.control_socket = control_socket;
+        self
+    }
+
+    pub fn with_http_bridge(mut self, http_bridge: Option<SocketAddr>) -> Self {
+        self.http_bridge = http_bridge;
+        self
+    }
+
+    // shown to clients in the hello and to admins in the stats
+    pub fn with_server_name(mut self, server_name: String) -> Self {
+        self.server_name = server_name;
+        self
+    }
+
+    pub fn with_default_language(mut self, default_language: Option<String>) -> Self {
+        self.default_language = default_language;
+        self
+    }
+
+    // ephemeral messages asking for a longer time to live are refused
+    pub fn with_max_message_ttl(mut self, max_message_ttl: Duration) -> Self {
+        self.max_message_ttl = max_message_ttl;
+        self
+    }
+
+    // how long an old name stays reserved after a rename
+    pub fn with_rename_grace(mut self, rename_grace: Duration) -> Self {
+        self.rename_grace = rename_grace;
+        self
+    }
+
+    // recent client message ids remembered per user, 0 relays every retry again
+    pub fn with_dedup_capacity(mut self, dedup_capacity: usize) -> Self {
+        self.dedup_capacity = dedup_capacity;
+        self
+    }
+
+    // how long a retried message is still recognised as a duplicate
+    pub fn with_dedup_ttl(mut self, dedup_ttl: Duration) -> Self {
+        self.dedup_ttl = dedup_ttl;
+        self
+    }
+
+    // pending messages per user and how far ahead they may be due
+    pub fn with_schedule_limits(mut self, schedule_limits: ScheduleLimits) -> Self {
+        self.schedule_limits = schedule_limits;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    // capped by the frame limit, a chunk always has to fit into a single payload field
+    pub fn with_max_chunk_size(mut self, max_chunk_size: u64) -> Self {
+        self.max_chunk_size = max_chunk_size.min(MAX_FIELD_SIZE as u64);
+        self
+    }
+
+    // false turns away clients that still send the password instead of answering the challenge
+    pub fn with_plain_auth(mut self, plain_auth: bool) -> Self {
+        self.plain_auth = plain_auth;
+        self
+    }
+
+    pub fn with_offline_queue(mut self, offline_queue: bool) -> Self {
+        self.offline_queue = offline_queue;
+        self
+    }
+
+    // how long a queued message waits for its recipient, ephemeral messages bring their own ttl
+    pub fn with_offline_ttl(mut self, offline_ttl: Duration) -> Self {
+        self.offline_ttl = offline_ttl;
+        self
+    }
+
+    // only hides who exists when offline messages are not queued, an ack gives the recipient away
+    pub fn with_conceal_users(mut self, conceal_users: bool) -> Self {
+        self.conceal_users = conceal_users;
+        self
+    }
+
+    pub fn with_listen_only(mut self, listen_only: bool) -> Self {
+        self.listen_only = listen_only;
+        self
+    }
+
+    pub fn with_offline_webhooks(mut self, offline_webhooks: OfflineWebhooks) -> Self {
+        self.offline_webhooks = offline_webhooks;
+        self
+    }
+
+    pub fn with_flood_limits(mut self, flood_limits: FloodLimits) -> Self {
+        self.flood_limits = flood_limits;
+        self
+    }
+
+    pub fn with_violation_limits(mut self, violation_limits: ViolationLimits) -> Self {
+        self.violation_limits = violation_limits;
+        self
+    }
+
+    // logins, registrations and password changes beyond the concurrency wait, up to the timeout, for a turn
+    pub fn with_auth_limits(mut self, auth_limits: AuthLimits) -> Self {
+        self.auth_limits = auth_limits;
+        self
     }
 
-    pub async fn serve(&self, shared_state: ArcRwLock<SharedState>) -> Result<(), Box<dyn std::error::Error>> {
-        tracing::info!("Starting server on {}:{}", HOST, PORT);
-        let listener = TcpListener::bind((HOST, PORT)).await?;
+    // frames of other versions are refused with a notice the peer can still read
+    pub fn with_protocol_versions(mut self, protocol_versions: VersionRange) -> Self {
+        self.protocol_versions = protocol_versions;
+        self
+    }
+
+    // the algorithms clients may select, the ones beyond crc32 are advertised in the hello
+    pub fn with_integrity(mut self, integrity: Vec<Integrity>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    // frames of a session that may fail their check before it is dropped, 0 never drops it
+    pub fn with_max_integrity_failures(mut self, max_integrity_failures: u32) -> Self {
+        self.max_integrity_failures = max_integrity_failures;
+        self
+    }
+
+    // logged in sessions are told who came and went, batched per interval
+    pub fn with_presence(mut self, presence: Option<PresenceTiming>) -> Self {
+        self.presence = presence;
+        self
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn with_journal_writer(mut self, journal_writer: Arc<dyn JournalWriter>) -> Self {
+        self.journal_writer = Some(journal_writer);
+        self
+    }
+
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn with_journal_limits(mut self, journal_limits: JournalLimits) -> Self {
+        self.journal_limits = journal_limits;
+        self
+    }
+
+    // heartbeats, the reaper, expiries and rate limits all go by this clock
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // called in the order they were added
+    pub fn with_plugin(mut self, plugin: Arc<dyn ServerPlugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub async fn serve(
+        &self,
+        shared_state: ArcRwLock<SharedState>,
+    ) -> Result<ShutdownSummary, Box<dyn std::error::Error>> {
+        tracing::info!("Starting server on {}:{}", HOST, self.port);
+        let listener = TcpListener::bind((HOST, self.port)).await?;
         tracing::info!("Server started");
 
-        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<bool>(1);
+        let signals_h = tokio::spawn(Self::stop_on_signal(Arc::clone(&shared_state)));
+        let summary = self.serve_on(listener, shared_state).await;
+        signals_h.abort();
+        summary
+    }
 
-        shared_state.write().await.set_shutdown_tx(shutdown_tx);
+    // errors before the first accept are returned, everything after that ends in a summary
+    pub async fn serve_on<L: Listener>(
+        &self,
+        mut listener: L,
+        shared_state: ArcRwLock<SharedState>,
+    ) -> Result<ShutdownSummary, Box<dyn std::error::Error>> {
+        let started = self.clock.instant();
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<ShutdownReason>(1);
 
-        loop {
+        let mut state = shared_state.write().await;
+        state.set_shutdown_tx(shutdown_tx);
+        state.set_clock(Arc::clone(&self.clock));
+        state.set_takeover_policy(self.takeover_policy);
+        state.set_plain_auth(self.plain_auth);
+        state.set_offline_queue(self.offline_queue);
+        state.set_offline_ttl(self.offline_ttl);
+        state.set_conceal_users(self.conceal_users);
+        state.set_listen_only(self.listen_only);
+        state.set_offline_webhooks(self.offline_webhooks.clone());
+        state.set_flood_limits(self.flood_limits);
+        state.set_violation_limits(self.violation_limits);
+        state.set_auth_limits(self.auth_limits);
+        state.set_protocol_versions(self.protocol_versions);
+        state.set_integrity(self.integrity.clone());
+        state.set_max_integrity_failures(self.max_integrity_failures);
+        state.set_edit_window(self.edit_window);
+        state.set_deleted_history(self.deleted_history);
+        state.set_max_message_ttl(self.max_message_ttl);
+        state.set_rename_grace(self.rename_grace);
+        state.set_dedup_limits(self.dedup_capacity, self.dedup_ttl);
+        state.set_schedule_limits(self.schedule_limits);
+        state.set_server_name(self.server_name.clone());
+        state.set_plugins(self.plugins.clone());
+        let mut catalog = Catalog::default();
+        if let Some(data_dir) = &self.data_dir {
+            state.set_data_dir(data_dir.clone());
+            state.set_server_id(data_dir::server_id(data_dir)?);
+            state.set_motd_file(data_dir.join(MOTD_FILE))?;
+            state.load_schedule(data_dir)?;
+            state.load_api_tokens(data_dir)?;
+            state.load_message_ids(data_dir)?;
+            catalog = Catalog::load(&data_dir.join(LANGUAGE_DIR))?;
+        }
+        catalog.set_default_language(self.default_language.clone());
+        state.set_catalog(catalog);
+        tracing::info!("Server {} has id {}", state.server_name(), state.server_id());
+        state
+            .file_transfers_mut()
+            .set_limits(self.max_file_size, self.max_chunk_size);
+        drop(state);
+
+        let control_h = match self.data_dir.as_deref().filter(|_| self.control_socket) {
+            Some(data_dir) => {
+                let (listener, token) = control::open(data_dir)?;
+                Some(tokio::spawn(control::serve(listener, token, Arc::clone(&shared_state))))
+            }
+            None => None,
+        };
+        #[cfg(feature = "http-bridge")]
+        let http_bridge_h = match self.http_bridge {
+            Some(addr) => {
+                let listener = super::http_bridge::open(addr).await?;
+                let addr = listener.local_addr()?;
+                tracing::info!("HTTP bridge listening on {}", addr);
+                shared_state.write().await.set_http_bridge_addr(addr);
+                Some(tokio::spawn(super::http_bridge::serve(
+                    listener,
+                    Arc::clone(&shared_state),
+                )))
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "http-bridge"))]
+        if self.http_bridge.is_some() {
+            return Err("The HTTP bridge needs a server built with the http-bridge feature".into());
+        }
+        let reaper_h = self.max_session_age.map(|max_age| {
+            tokio::spawn(Self::reap_sessions(
+                max_age,
+                Arc::clone(&self.clock),
+                Arc::clone(&shared_state),
+            ))
+        });
+        let purge_h = tokio::spawn(Self::purge_expired_messages(
+            self.offline_ttl,
+            Arc::clone(&self.clock),
+            Arc::clone(&shared_state),
+        ));
+        let grants_h = tokio::spawn(Self::expire_temporary_grants(
+            Arc::clone(&self.clock),
+            Arc::clone(&shared_state),
+        ));
+        let schedule_h = tokio::spawn(Self::fire_scheduled_messages(
+            Arc::clone(&self.clock),
+            Arc::clone(&shared_state),
+        ));
+        let journal_writer = self.journal_writer.clone().or_else(|| {
+            self.data_dir
+                .as_deref()
+                .map(|data_dir| Arc::new(FileJournal::new(data_dir)) as Arc<dyn JournalWriter>)
+        });
+        let journal = match journal_writer {
+            Some(writer) => {
+                let journal = Journal::new(self.journal_limits.capacity);
+                shared_state.write().await.set_journal(journal.clone());
+                let journal_h = tokio::spawn(write_journal(journal.clone(), writer));
+                let metrics_h = tokio::spawn(Self::snapshot_metrics(
+                    self.journal_limits.metrics_interval,
+                    Arc::clone(&self.clock),
+                    Arc::clone(&shared_state),
+                ));
+                Some((journal, journal_h, metrics_h))
+            }
+            None => None,
+        };
+        let presence_h = match self.presence {
+            Some(timing) => {
+                let (presence_tx, presence_rx) = mpsc::unbounded_channel();
+                shared_state.write().await.set_presence_tx(presence_tx);
+                Some(tokio::spawn(Self::broadcast_presence(
+                    timing,
+                    presence_rx,
+                    Arc::clone(&self.clock),
+                    Arc::clone(&shared_state),
+                )))
+            }
+            None => None,
+        };
+        #[cfg(feature = "webhook")]
+        let webhook_h = match self.offline_webhooks.is_enabled() {
+            true => {
+                let (notice_tx, notice_rx) = mpsc::unbounded_channel();
+                shared_state.write().await.set_offline_notice_tx(notice_tx);
+                Some(tokio::spawn(super::webhook::deliver_offline_notices(
+                    notice_rx,
+                    Arc::clone(&shared_state),
+                )))
+            }
+            false => None,
+        };
+        let mut capabilities = match self.presence {
+            Some(_) => self.capabilities.clone().with(capability::PRESENCE),
+            None => self.capabilities.clone(),
+        };
+        for capability in self.integrity.iter().filter_map(Integrity::capability) {
+            capabilities = capabilities.with(capability);
+        }
+        if self.listen_only {
+            capabilities = capabilities.with(capability::LISTEN_ONLY);
+        }
+        if self.http_bridge.is_some() {
+            capabilities = capabilities.with(capability::API_TOKENS);
+        }
+
+        let reason = loop {
             tokio::select! {
-                _ = shutdown_rx.recv() => {
-                    break;
+                Some(reason) = shutdown_rx.recv() => {
+                    break reason;
                 },
                 result = listener.accept() => {
-                    let (socket, addr) = result?;
+                    let (socket, addr) = match result {
+                        Ok(accepted) => accepted,
+                        Err(e) if is_transient_accept_error(&e) => {
+                            tracing::warn!("Could not accept a connection, retrying in {:?}: {}", self.accept_backoff, e);
+                            shared_state.write().await.record_accept_error();
+                            tokio::time::sleep(self.accept_backoff).await;
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::error!("Listener failed, no longer accepting connections: {}", e);
+                            break ShutdownReason::FatalError(e.to_string());
+                        }
+                    };
+                    let banned = shared_state.read().await.ip_ban(addr.ip());
+                    if let Some(remaining) = banned {
+                        tracing::info!("Turned away connection from banned address {}", addr);
+                        shared_state.write().await.record_ban_turn_away();
+                        let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+                        tokio::spawn(Self::turn_away(socket, retry_after, IP_BANNED));
+                        continue;
+                    }
+                    if shared_state.read().await.mode() == ServerMode::Draining {
+                        tracing::info!("Turned away connection from {} while draining", addr);
+                        tokio::spawn(Self::turn_away(socket, DRAIN_RETRY_AFTER, "Server is draining"));
+                        continue;
+                    }
+                    let cooldown = shared_state.read().await.flood_cooldown(addr.ip());
+                    if let Some(cooldown) = cooldown {
+                        tracing::info!("Turned away connection from {} after flooding", addr);
+                        // rounded up, retrying on the second before the cooldown ends would be turned away again
+                        let retry_after = cooldown.as_secs() + u64::from(cooldown.subsec_nanos() > 0);
+                        tokio::spawn(Self::turn_away(socket, retry_after, "Too many frames, slow down"));
+                        continue;
+                    }
                     tracing::info!("Accepted connection from {}", addr);
-                    tokio::spawn(Self::handle_connection(socket, Arc::clone(&shared_state)));
+                    tokio::spawn(Self::handle_connection(
+                        socket,
+                        addr,
+                        self.heartbeat,
+                        self.timeouts,
+                        self.tracer.clone(),
+                        capabilities.clone(),
+                        Arc::clone(&shared_state),
+                    ));
+                }
+            }
+        };
+
+        if let Some(reaper_h) = reaper_h {
+            reaper_h.abort();
+        }
+        if let Some(control_h) = control_h {
+            control_h.abort();
+            if let Some(data_dir) = &self.data_dir {
+                control::close(data_dir);
+            }
+        }
+        purge_h.abort();
+        grants_h.abort();
+        schedule_h.abort();
+        if let Some(presence_h) = presence_h {
+            presence_h.abort();
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(webhook_h) = webhook_h {
+            webhook_h.abort();
+        }
+        #[cfg(feature = "http-bridge")]
+        if let Some(http_bridge_h) = http_bridge_h {
+            http_bridge_h.abort();
+        }
+        self.plugins.shutdown(&shared_state).await;
+
+        // sessions that were told to disconnect get the chance to finish the handshake,
+        // after a fatal error nobody told them and the process goes without waiting
+        if reason.error().is_none() {
+            let closed = tokio::time::timeout(self.timeouts.disconnect, async {
+                while !shared_state.read().await.sessions().is_empty() {
+                    tokio::time::sleep(SESSION_POLL_INTERVAL).await;
                 }
+            })
+            .await;
+            if closed.is_err() {
+                tracing::debug!(
+                    "Sessions still open after {:?}, shutting down anyway",
+                    self.timeouts.disconnect
+                );
             }
         }
 
-        tracing::info!("Shutting down server");
-        Ok(())
+        if let Some((journal, mut journal_h, metrics_h)) = journal {
+            metrics_h.abort();
+            let stats = shared_state.read().await.server_stats().await;
+            journal.push(JournalEntry::metrics(self.clock.now(), &stats));
+            journal.close();
+            if tokio::time::timeout(JOURNAL_DRAIN_TIMEOUT, &mut journal_h)
+                .await
+                .is_err()
+            {
+                tracing::warn!(
+                    "Journal still not written after {:?}, {} entries are lost",
+                    JOURNAL_DRAIN_TIMEOUT,
+                    journal.queued()
+                );
+                journal_h.abort();
+            }
+        }
+
+        let state = shared_state.read().await;
+        let summary = ShutdownSummary {
+            reason,
+            uptime: self.clock.instant().duration_since(started),
+            peak_sessions: state.peak_sessions(),
+            messages_relayed: state.messages_relayed(),
+        };
+        drop(state);
+        if let Some(data_dir) = &self.data_dir {
+            if let Err(e) = summary.write(data_dir) {
+                tracing::warn!("Could not write the shutdown report to {}: {}", data_dir.display(), e);
+            }
+        }
+
+        tracing::info!("Shutting down server ({})", summary.reason);
+        Ok(summary)
     }
 
-    async fn handle_connection(socket: TcpStream, shared_state: ArcRwLock<SharedState>) {
-        let socket_addr = socket.peer_addr().unwrap();
-        let (reader, writer) = socket.into_split();
+    // SIGINT from a terminal or SIGTERM from a supervisor, both stop the server like an admin would
+    async fn stop_on_signal(shared_state: ArcRwLock<SharedState>) {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => Some(terminate),
+            Err(e) => {
+                tracing::warn!("Could not listen for SIGTERM: {}", e);
+                None
+            }
+        };
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                if let Err(e) = result {
+                    tracing::warn!("Could not listen for SIGINT: {}", e);
+                    return;
+                }
+            }
+            Some(_) = async { terminate.as_mut()?.recv().await } => {}
+        }
+
+        tracing::info!("Received a signal, stopping the server");
+        stop_server(shared_state, ShutdownReason::Signal).await;
+    }
+
+    // the busy frame tells clients when to come back instead of hammering a server that will not take them
+    async fn turn_away<S: Stream>(mut socket: S, retry_after: u64, reason: &str) {
+        let busy = Message::server_busy(retry_after, reason);
+        if let Err(e) = busy.send(&mut socket).await {
+            tracing::debug!("Could not send server busy: {}", e);
+        }
+        socket.shutdown().await.ok();
+    }
+
+    async fn handle_connection<S: Stream>(
+        socket: S,
+        socket_addr: SocketAddr,
+        heartbeat: Heartbeat,
+        timeouts: Timeouts,
+        tracer: Option<FrameTracer>,
+        capabilities: Capabilities,
+        shared_state: ArcRwLock<SharedState>,
+    ) {
+        let (reader, writer) = tokio::io::split(socket);
         let (tx, rx) = mpsc::unbounded_channel::<Message>();
 
         //let mut session = Session::new(Arc::clone(&socket));
         let mut session = Session::new();
         let session_id = session.id();
         session.set_channel(tx.clone());
-        session.update_heartbeat(None);
+        session.set_peer(socket_addr);
+        let connected_at = shared_state.read().await.clock().now();
+        session.set_connected_at(connected_at);
+        session.update_heartbeat(connected_at);
+
+        // queued before anything else so the hello is always the first frame
+        let state = shared_state.read().await;
+        let plugins = state.plugins();
+        tx.send(Message::server_hello(
+            &capabilities,
+            session.nonce(),
+            state.server_name(),
+            &state.server_id().to_string(),
+        ))
+        .ok();
+        if !state.motd().is_empty() {
+            tx.send(Message::motd(state.motd())).ok();
+        }
+        drop(state);
 
         shared_state
             .write()
             .await
             .add_session(session.id(), Arc::new(tokio::sync::RwLock::new(session)));
+        plugins.session_opened(&shared_state, session_id).await;
 
-        let send_h = tokio::spawn(Self::handle_send(writer, rx, Arc::clone(&shared_state), session_id));
-        let recv_h = tokio::spawn(Self::handle_receive(
-            reader,
-            tx.clone(),
-            Arc::clone(&shared_state),
-            session_id,
-        ));
+        let tracer = tracer.map(|tracer| tracer.for_session(&session_id.to_string()));
+        // every log line of the connection carries these, handlers add a message span below
+        let span = tracing::info_span!("session", session_id = %session_id, peer = %socket_addr);
 
-        let hb_h = tokio::spawn(Self::handle_heartbeat(
-            tx.clone(),
-            Arc::clone(&shared_state),
-            session_id,
-        ));
+        let send_h = tokio::spawn(
+            Self::handle_send(
+                writer,
+                rx,
+                timeouts,
+                tracer.clone(),
+                Arc::clone(&shared_state),
+                session_id,
+            )
+            .instrument(span.clone()),
+        );
+        let recv_h = tokio::spawn(
+            Self::handle_receive(
+                reader,
+                tx.clone(),
+                tracer,
+                timeouts.handshake,
+                Arc::clone(&shared_state),
+                session_id,
+            )
+            .instrument(span.clone()),
+        );
+
+        let hb_h = tokio::spawn(
+            Self::handle_heartbeat(tx.clone(), heartbeat, Arc::clone(&shared_state), session_id)
+                .instrument(span.clone()),
+        );
 
         send_h.await.unwrap();
         recv_h.await.unwrap();
         hb_h.await.unwrap();
+        plugins.session_closed(&shared_state, session_id).await;
 
-        tracing::info!("Closed connection from {}", socket_addr);
+        span.in_scope(|| tracing::info!("Closed connection from {}", socket_addr));
     }
 
-    async fn handle_send(
-        mut writer: OwnedWriteHalf,
+    async fn handle_send<W: AsyncWrite + Unpin>(
+        mut writer: W,
         mut rx: mpsc::UnboundedReceiver<Message>,
+        timeouts: Timeouts,
+        tracer: Option<FrameTracer>,
         shared_state: ArcRwLock<SharedState>,
         session_id: Uuid,
     ) {
+        let mut integrity = Integrity::default();
         loop {
             if !shared_state.read().await.is_active_session(session_id).await {
                 break;
@@ -109,27 +916,127 @@ impl Server {
 
             if let Some(message) = rx.recv().await {
                 if message.is(MessageType::Break) {
+                    writer.shutdown().await.ok();
                     Self::handle_disconnect(shared_state.clone(), session_id).await;
                     break;
                 }
+                let disconnecting = message.is(MessageType::Disconnect);
+                if disconnecting {
+                    // set before the frame leaves so the receiver cannot miss the Ack
+                    shared_state.read().await.begin_disconnect(session_id).await;
+                }
                 tracing::info!("Sending message: {:?}", message.message_type());
-                if let Err(e) = message.send(&mut writer).await {
+                if let Err(e) = Self::write_frame(&mut writer, &message, integrity, timeouts.write, &shared_state).await
+                {
                     tracing::error!("Error sending message: {}", e);
                     Self::handle_disconnect(shared_state.clone(), session_id).await;
+                    continue;
+                }
+                // the confirmation is the last frame with the old trailer
+                if let Some(selected) = message.switches_integrity() {
+                    integrity = selected;
+                }
+                shared_state
+                    .read()
+                    .await
+                    .record_sent(session_id, message.message_type())
+                    .await;
+                if let Some(tracer) = &tracer {
+                    tracer.record(Direction::Sent, &message);
                 }
-                if message.is(MessageType::Disconnect) {
+                if disconnecting {
+                    Self::await_disconnect_ack(&mut rx, timeouts.disconnect, session_id).await;
+                    writer.shutdown().await.ok();
                     Self::handle_disconnect(shared_state.clone(), session_id).await;
+                    break;
+                }
+            }
+        }
+    }
+
+    // control frames get a second window on the same write, a cancelled write could leave half a frame behind
+    async fn write_frame<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        message: &Message,
+        integrity: Integrity,
+        write_timeout: Duration,
+        shared_state: &ArcRwLock<SharedState>,
+    ) -> Result<(), String> {
+        let started = Instant::now();
+        let mut windows = if message.message_type().is_control() { 2 } else { 1 };
+        let write = message.send_using(writer, integrity);
+        tokio::pin!(write);
+        loop {
+            match tokio::time::timeout(write_timeout, &mut write).await {
+                Ok(result) => return result,
+                Err(_) => {
+                    shared_state.write().await.record_stalled_write();
+                    windows -= 1;
+                    if windows == 0 {
+                        return Err(format!(
+                            "{:?} stalled for {:?}, closing the session",
+                            message.message_type(),
+                            started.elapsed()
+                        ));
+                    }
+                    tracing::debug!("Retrying stalled {:?} write", message.message_type());
+                }
+            }
+        }
+    }
+
+    // the receiver turns the Ack (or the peer closing) into a Break, anything else queued is dropped
+    async fn await_disconnect_ack(
+        rx: &mut mpsc::UnboundedReceiver<Message>,
+        disconnect_timeout: Duration,
+        session_id: Uuid,
+    ) {
+        let acked = tokio::time::timeout(disconnect_timeout, async {
+            while let Some(message) = rx.recv().await {
+                if message.is(MessageType::Break) {
+                    return;
                 }
             }
+        })
+        .await;
+        if acked.is_err() {
+            tracing::debug!(
+                "Session {} did not acknowledge the disconnect within {:?}",
+                session_id,
+                disconnect_timeout
+            );
         }
     }
 
-    async fn handle_receive(
-        mut reader: OwnedReadHalf,
+    async fn handle_receive<R: AsyncRead + Unpin>(
+        mut reader: R,
         tx: mpsc::UnboundedSender<Message>,
+        tracer: Option<FrameTracer>,
+        handshake_timeout: Duration,
         shared_state: ArcRwLock<SharedState>,
         session_id: Uuid,
     ) {
+        // a peer that connects and never sends a valid frame is dropped without the disconnect ceremony
+        let handshake = tokio::time::sleep(handshake_timeout);
+        tokio::pin!(handshake);
+        let mut greeted = false;
+        // only the first run of bytes without a header counts against the address
+        let mut garbled = false;
+        let mut frames: u64 = 0;
+        // switched once, by the client, right after its select
+        let mut integrity = Integrity::default();
+        let mut integrity_selected = false;
+        let (mut flood, clock, versions, plugins, max_integrity_failures) = {
+            let state = shared_state.read().await;
+            (
+                FloodMeter::new(state.flood_limits()),
+                state.clock(),
+                state.protocol_versions(),
+                state.plugins(),
+                state.max_integrity_failures(),
+            )
+        };
+
         loop {
             // if !shared_state.read().await.is_active_session(session_id).await || tx.is_closed() {
             //     break;
@@ -139,52 +1046,183 @@ impl Server {
                 _ = tx.closed() => {
                     break;
                 },
-                valid = Message::has_header_start(&mut reader) => {
-                    if !valid {
-                        continue;
+                () = &mut handshake, if !greeted => {
+                    tracing::debug!("Session {} sent nothing within {:?}, closing", session_id, handshake_timeout);
+                    tx.send(Message::BREAK).ok();
+                    break;
+                },
+                valid = Message::read_frame_start(&mut reader) => {
+                    match valid {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            if !garbled {
+                                garbled = true;
+                                Self::record_violation(&shared_state, session_id).await;
+                            }
+                            continue;
+                        }
+                        Err(FrameError::PeerClosed) => {
+                            tracing::debug!("Session {} closed the connection", session_id);
+                            shared_state.write().await.record_connection_end(false);
+                            tx.send(Message::BREAK).ok();
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::error!("Session {} lost its connection: {}", session_id, e);
+                            shared_state.write().await.record_connection_end(true);
+                            tx.send(Message::BREAK).ok();
+                            break;
+                        }
                     }
-                    let message = Message::receive(&mut reader).await;
+                    let message = if greeted {
+                        Message::receive_using(&mut reader, versions, integrity).await
+                    } else {
+                        match tokio::time::timeout_at(handshake.deadline(), Message::receive_using(&mut reader, versions, integrity)).await {
+                            Ok(message) => message,
+                            Err(_) => {
+                                tracing::debug!("Session {} did not finish its first frame in time, closing", session_id);
+                                tx.send(Message::BREAK).ok();
+                                break;
+                            }
+                        }
+                    };
                     match message {
                         Ok(message) => {
-                            tracing::info!("Received message: {:?}", message.message_type());
-                            if !shared_state
-                                .read()
-                                .await
-                                .get_access_level(session_id)
-                                .await
-                                .can_access(&message.message_type())
-                            {
-                                tx.send(Message::NACK).unwrap();
-                                continue;
+                            greeted = true;
+                            frames += 1;
+                            shared_state.read().await.record_received(session_id, message.message_type()).await;
+                            // message_id is filled in by the handlers that deal with a stored message
+                            let span = tracing::info_span!(
+                                "message",
+                                message_type = ?message.message_type(),
+                                frame = frames,
+                                message_id = tracing::field::Empty,
+                                elapsed_us = tracing::field::Empty,
+                            );
+                            span.in_scope(|| tracing::info!("Received message: {:?}", message.message_type()));
+                            if let Some(tracer) = &tracer {
+                                tracer.record(Direction::Received, &message);
                             }
-                            match message.message_type() {
-                                MessageType::Disconnect => {
-                                    tx.send(Message::BREAK).unwrap();
-                                    break;
-                                }
-                                MessageType::Heartbeat => {
-                                    handle_heartbeat(&message, Arc::clone(&shared_state), session_id).await;
+                            if shared_state.read().await.is_disconnecting(session_id).await {
+                                // only the answer to our Disconnect matters now
+                                match message.message_type() {
+                                    MessageType::Ack => {}
+                                    MessageType::Disconnect => {
+                                        tx.send(Message::ACK).ok();
+                                    }
+                                    _ => continue,
                                 }
-                                MessageType::Auth => {
-                                    handle_auth(&message, tx.clone(), Arc::clone(&shared_state), session_id).await;
+                                tx.send(Message::BREAK).ok();
+                                break;
+                            }
+                            // ahead of the flood meter, a dropped select would leave the two sides on different trailers
+                            if message.is(MessageType::IntegritySelect) {
+                                match Self::select_integrity(&message, integrity_selected, &shared_state, session_id).await {
+                                    Ok(selected) => {
+                                        integrity = selected;
+                                        integrity_selected = true;
+                                        tx.send(Message::integrity_selected(selected)).ok();
+                                    }
+                                    Err(e) => {
+                                        tracing::warn!("Session {} sent an invalid integrity select: {}", session_id, e);
+                                        Self::record_violation(&shared_state, session_id).await;
+                                        tx.send(Message::disconnect(DisconnectReason::ProtocolError, &e)).ok();
+                                    }
                                 }
-                                MessageType::AuthCreate => {
-                                    handle_auth_create(&message, tx.clone(), Arc::clone(&shared_state), session_id).await;
+                                continue;
+                            }
+                            match flood.record(message.wire_size() as u64, clock.instant().into_std()) {
+                                FloodVerdict::Allow => {}
+                                FloodVerdict::Drop => continue,
+                                FloodVerdict::Warn => {
+                                    tracing::warn!("Session {} is flooding, warning it", session_id);
+                                    shared_state.write().await.record_flood_warning();
+                                    tx.send(Message::message_error(FLOOD_WARNING)).ok();
+                                    continue;
                                 }
-                                MessageType::ServerDebugLog => {
-                                    tracing::debug!("{:#?}", shared_state.read().await);
+                                FloodVerdict::Disconnect => {
+                                    tracing::warn!("Session {} kept flooding, disconnecting it", session_id);
+                                    let peer = shared_state.read().await.peer_of(session_id).await;
+                                    shared_state
+                                        .write()
+                                        .await
+                                        .record_flood_disconnect(peer.map(|peer| peer.ip()), flood.cooldown());
+                                    tx.send(Message::disconnect(DisconnectReason::ProtocolError, FLOOD_DISCONNECT))
+                                        .ok();
+                                    continue;
                                 }
-                                MessageType::ServerShutdown => {
-                                    handle_server_shutdown(&message, Arc::clone(&shared_state)).await;
+                            }
+                            let access = shared_state
+                                .read()
+                                .await
+                                .access_of(session_id, message.message_type())
+                                .await;
+                            match access {
+                                Access::Allowed => {}
+                                Access::Temporary => {
+                                    shared_state
+                                        .write()
+                                        .await
+                                        .record_temporary_use(session_id, message.message_type())
+                                        .await
                                 }
-                                MessageType::DirectMessageSend => {
-                                     handle_direct_message_send(&message, tx.clone(), Arc::clone(&shared_state), session_id).await;
+                                Access::Denied => {
+                                    tx.send(Message::not_authorized(message.message_type())).ok();
+                                    continue;
                                 }
-                                _ => {}
+                            }
+                            if message.is(MessageType::Disconnect) {
+                                span.in_scope(|| match message.disconnect_reason() {
+                                    Ok((reason, detail)) => {
+                                        tracing::info!("Session {} disconnected: {} {}", session_id, reason.as_str(), detail)
+                                    }
+                                    Err(e) => tracing::debug!("Session {} sent a malformed disconnect: {}", session_id, e),
+                                });
+                                tx.send(Message::ACK).ok();
+                                tx.send(Message::BREAK).ok();
+                                break;
+                            }
+                            let started = Instant::now();
+                            Self::dispatch(&message, tx.clone(), &plugins, Arc::clone(&shared_state), session_id)
+                                .instrument(span.clone())
+                                .await;
+                            span.record("elapsed_us", started.elapsed().as_micros() as u64);
+                        }
+                        // the rest of the frame cannot be read, so there is no waiting for an answer either
+                        Err(FrameError::UnsupportedVersion { version, supported }) => {
+                            tracing::warn!("Session {} speaks protocol version {}, dropping it", session_id, version);
+                            tx.send(supported.unsupported_notice(version)).ok();
+                            tx.send(Message::BREAK).ok();
+                            break;
+                        }
+                        // nothing more can be read, so there is nobody left to explain anything to
+                        Err(FrameError::Closed(e)) => {
+                            tracing::error!("Session {} broke off partway through a frame: {}", session_id, e);
+                            shared_state.write().await.record_connection_end(true);
+                            tx.send(Message::BREAK).ok();
+                            break;
+                        }
+                        // the frame was read whole, so the stream is still in step and a few are let go
+                        Err(e) if greeted && e.is_integrity_failure() => {
+                            let failures = shared_state.write().await.record_integrity_failure(session_id).await;
+                            if failures == max_integrity_failures {
+                                tracing::warn!("Session {} failed the {} check {} times, disconnecting it", session_id, integrity, failures);
+                                Self::record_violation(&shared_state, session_id).await;
+                                tx.send(Message::disconnect(DisconnectReason::ProtocolError, INTEGRITY_DISCONNECT)).ok();
+                            } else {
+                                tracing::warn!("Session {} sent a frame that failed the {} check, skipping it", session_id, integrity);
                             }
                         }
+                        // before the first frame there is nobody to explain the error to
+                        Err(e) if greeted => {
+                            tracing::error!("Error receiving message: {}", e);
+                            Self::record_violation(&shared_state, session_id).await;
+                            tx.send(Message::disconnect(DisconnectReason::ProtocolError, &e.to_string())).ok();
+                        }
                         Err(e) => {
                             tracing::error!("Error receiving message: {}", e);
+                            Self::record_violation(&shared_state, session_id).await;
+                            tx.send(Message::BREAK).ok();
                             break;
                         }
                     }
@@ -206,12 +1244,12 @@ impl Server {
             //             .await
             //             .can_access(&message.message_type())
             //         {
-            //             tx.send(Message::NACK).unwrap();
+            //             tx.send(Message::NACK).ok();
             //             continue;
             //         }
             //         match message.message_type() {
             //             MessageType::Disconnect => {
-            //                 tx.send(Message::BREAK).unwrap();
+            //                 tx.send(Message::BREAK).ok();
             //                 break;
             //             }
             //             MessageType::Heartbeat => {
@@ -237,27 +1275,346 @@ impl Server {
         }
     }
 
+    async fn reap_sessions(max_age: Duration, clock: Arc<dyn Clock>, shared_state: ArcRwLock<SharedState>) {
+        let mut interval = clock.interval((max_age / 4).clamp(Duration::from_millis(10), MAX_REAP_INTERVAL));
+
+        loop {
+            interval.tick().await;
+
+            let expired = shared_state.read().await.expired_sessions(max_age).await;
+            if expired.is_empty() {
+                continue;
+            }
+
+            let mut state = shared_state.write().await;
+            for id in expired {
+                state.expire_session(id, "Session expired").await;
+            }
+        }
+    }
+
+    async fn snapshot_metrics(interval: Duration, clock: Arc<dyn Clock>, shared_state: ArcRwLock<SharedState>) {
+        let mut interval = clock.interval(interval);
+        // the first tick is right away, there is nothing to count yet
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let state = shared_state.read().await;
+            let stats = state.server_stats().await;
+            if let Some(journal) = state.journal() {
+                journal.push(JournalEntry::metrics(clock.now(), &stats));
+            }
+        }
+    }
+
+    // a short offline ttl is purged more often, so senders hear about it close to when it happened
+    async fn purge_expired_messages(
+        offline_ttl: Duration,
+        clock: Arc<dyn Clock>,
+        shared_state: ArcRwLock<SharedState>,
+    ) {
+        let mut interval = clock.interval((offline_ttl / 4).clamp(Duration::from_millis(10), PURGE_INTERVAL));
+
+        loop {
+            interval.tick().await;
+
+            let purged = shared_state.write().await.purge_expired_messages().await;
+            if purged > 0 {
+                tracing::debug!("Purged {} expired messages", purged);
+            }
+        }
+    }
+
+    async fn expire_temporary_grants(clock: Arc<dyn Clock>, shared_state: ArcRwLock<SharedState>) {
+        let mut interval = clock.interval(GRANT_EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let expired = shared_state.write().await.expire_temporary_grants();
+            if expired > 0 {
+                tracing::debug!("{} temporary grants expired", expired);
+            }
+        }
+    }
+
+    async fn fire_scheduled_messages(clock: Arc<dyn Clock>, shared_state: ArcRwLock<SharedState>) {
+        let mut interval = clock.interval(SCHEDULE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let fired = fire_scheduled_messages(&mut *shared_state.write().await).await;
+            if fired > 0 {
+                tracing::debug!("{} scheduled messages were due", fired);
+            }
+        }
+    }
+
+    // raw logins and logouts come in as they happen, the settled changes go out once per interval
+    async fn broadcast_presence(
+        timing: PresenceTiming,
+        mut events: mpsc::UnboundedReceiver<(String, bool)>,
+        clock: Arc<dyn Clock>,
+        shared_state: ArcRwLock<SharedState>,
+    ) {
+        let mut coalescer = PresenceCoalescer::default();
+        let mut interval = clock.interval(timing.interval.max(Duration::from_millis(10)));
+
+        loop {
+            tokio::select! {
+                Some((user, online)) = events.recv() => {
+                    coalescer.record(user, online, clock.instant().into_std());
+                }
+                _ = interval.tick() => {
+                    let batch = coalescer.settle(timing.window, clock.instant().into_std());
+                    let mut dropped = 0;
+                    if !batch.is_empty() {
+                        let targets = shared_state.read().await.presence_targets().await;
+                        dropped = send_presence(targets, &batch).await;
+                        tracing::debug!("Announced {} presence changes", batch.len());
+                    }
+                    let coalesced = coalescer.take_coalesced();
+                    if coalesced > 0 || dropped > 0 {
+                        shared_state.write().await.record_presence(coalesced, dropped);
+                    }
+                }
+            }
+        }
+    }
+
+    // once per connection, and only an algorithm the server offers
+    async fn select_integrity(
+        message: &Message,
+        already_selected: bool,
+        shared_state: &ArcRwLock<SharedState>,
+        session_id: Uuid,
+    ) -> Result<Integrity, String> {
+        if already_selected {
+            return Err("The frame integrity was already selected".to_string());
+        }
+        let integrity = Integrity::parse(message.payload().str_field(0)?)?;
+        let state = shared_state.read().await;
+        if !state.offers_integrity(integrity) {
+            return Err(format!("Frame integrity {} is not offered", integrity));
+        }
+        state.select_integrity(session_id, integrity).await;
+        Ok(integrity)
+    }
+
+    // an address that keeps sending garbage is banned at accept time for a while
+    async fn record_violation(shared_state: &ArcRwLock<SharedState>, session_id: Uuid) {
+        let Some(peer) = shared_state.read().await.peer_of(session_id).await else {
+            return;
+        };
+        if shared_state.write().await.record_violation(peer.ip()) {
+            tracing::warn!("Banned {} after repeated protocol violations", peer.ip());
+        }
+    }
+
+    // without plugins this is just the handler, nobody pays for the lookups
+    pub(super) async fn dispatch(
+        message: &Message,
+        tx: mpsc::UnboundedSender<Message>,
+        plugins: &Plugins,
+        shared_state: ArcRwLock<SharedState>,
+        session_id: Uuid,
+    ) {
+        if plugins.is_empty() {
+            return Self::handle_message(message, tx, shared_state, session_id).await;
+        }
+
+        plugins
+            .message(&shared_state, session_id, message, Dispatch::Before)
+            .await;
+        let logging_in = matches!(
+            message.message_type(),
+            MessageType::Auth | MessageType::AuthChallenge | MessageType::AuthCreate
+        );
+        let before = if logging_in {
+            shared_state.read().await.get_user_by_session(&session_id).await
+        } else {
+            None
+        };
+        Self::handle_message(message, tx, Arc::clone(&shared_state), session_id).await;
+        if logging_in {
+            let after = shared_state.read().await.get_user_by_session(&session_id).await;
+            if let Some(user) = after.filter(|user| before.as_ref() != Some(user)) {
+                plugins.authenticated(&shared_state, session_id, &user).await;
+            }
+        }
+        plugins
+            .message(&shared_state, session_id, message, Dispatch::After)
+            .await;
+    }
+
+    // the one place frames turn into requests, handlers only see decoded requests and answer through replies
+    async fn handle_message(
+        message: &Message,
+        tx: mpsc::UnboundedSender<Message>,
+        shared_state: ArcRwLock<SharedState>,
+        session_id: Uuid,
+    ) {
+        let replies = Replies::new(tx);
+        let request = match Request::decode(message) {
+            Some(Ok(request)) => request,
+            Some(Err(e)) => {
+                Self::refuse_request(message.message_type(), e, &replies, session_id);
+                return;
+            }
+            None => return,
+        };
+        let state = Arc::clone(&shared_state);
+
+        match request {
+            Request::Heartbeat(request) => handle_heartbeat(request, &replies, state, session_id).await,
+            Request::TimeSync(request) => handle_time_sync(request, &replies, state).await,
+            Request::ClientHello(request) => handle_client_hello(request, &replies, state, session_id).await,
+            Request::Auth(request) => handle_auth(request, &replies, state, session_id).await,
+            Request::AuthChallenge(request) => handle_auth_challenge(request, &replies, state, session_id).await,
+            Request::AuthCreate(request) => handle_auth_create(request, &replies, state, session_id).await,
+            Request::PasswordChange(request) => handle_password_change(request, &replies, state, session_id).await,
+            Request::RenameAccount(request) => handle_rename_account(request, &replies, state, session_id).await,
+            Request::ServerDebugLog => tracing::debug!("{:#?}", state.read().await),
+            Request::ServerShutdown(request) => handle_server_shutdown(request, state).await,
+            Request::AdminSetLogLevel(request) => handle_set_log_level(request, &replies, state, session_id).await,
+            Request::AdminResetPassword(request) => handle_reset_password(request, &replies, state, session_id).await,
+            Request::AdminSetServerMode(request) => handle_set_server_mode(request, &replies, state, session_id).await,
+            Request::AdminServerStats => handle_server_stats(&replies, state).await,
+            Request::AdminKickUser(request) => handle_kick_user(request, &replies, state, session_id).await,
+            Request::AdminSetAccessLevel(request) => {
+                handle_set_access_level(request, &replies, state, session_id).await
+            }
+            Request::AdminExportState => handle_export_state(&replies, state, session_id).await,
+            Request::AdminRenameUser(request) => handle_rename_user(request, &replies, state, session_id).await,
+            Request::AdminUserInfo(request) => handle_user_info(request, &replies, state).await,
+            Request::AdminSetMotd(request) => handle_set_motd(request, &replies, state, session_id).await,
+            Request::AdminListSessions(filter) => handle_list_sessions(filter, &replies, state).await,
+            Request::AdminKickWhere(request) => handle_kick_where(request, &replies, state, session_id).await,
+            Request::AdminListIpBans => handle_list_ip_bans(&replies, state).await,
+            Request::AdminClearIpBan(request) => handle_clear_ip_ban(request, &replies, state, session_id).await,
+            Request::AdminConsistencyCheck(request) => {
+                handle_consistency_check(request, &replies, state, session_id).await
+            }
+            Request::AdminGrantTemporary(request) => handle_grant_temporary(request, &replies, state, session_id).await,
+            Request::AdminRevokeTemporary(request) => {
+                handle_revoke_temporary(request, &replies, state, session_id).await
+            }
+            Request::DirectMessageSend(request) => {
+                handle_direct_message_send(request, &replies, state, session_id).await
+            }
+            Request::MessageEdit(request) => handle_message_edit(request, &replies, state, session_id).await,
+            Request::MessageDelete(request) => handle_message_delete(request, &replies, state, session_id).await,
+            Request::HistoryRequest(request) => handle_history_request(request, &replies, state, session_id).await,
+            Request::MarkConversationRead(request) => {
+                handle_mark_conversation_read(request, &replies, state, session_id).await
+            }
+            Request::SearchRequest(request) => handle_search_request(request, &replies, state, session_id).await,
+            Request::FileOffer(request) => handle_file_offer(request, &replies, state, session_id).await,
+            Request::FileAccept(request) => handle_file_accept(request, &replies, state, session_id).await,
+            Request::FileReject(request) => handle_file_reject(request, &replies, state, session_id).await,
+            Request::FileChunk(request) => handle_file_chunk(request, state, session_id).await,
+            Request::FileComplete(request) => handle_file_complete(request, state, session_id).await,
+            Request::SetPreference(request) => handle_set_preference(request, &replies, state, session_id).await,
+            Request::GetPreferences => handle_get_preferences(&replies, state, session_id).await,
+            Request::MuteAdd(request) => handle_mute(request, true, &replies, state, session_id).await,
+            Request::MuteRemove(request) => handle_mute(request, false, &replies, state, session_id).await,
+            Request::MuteList => handle_mute_list(&replies, state, session_id).await,
+            Request::SubscribePresence(scope) => handle_subscribe_presence(scope, &replies, state, session_id).await,
+            Request::UnsubscribePresence(scope) => {
+                handle_unsubscribe_presence(scope, &replies, state, session_id).await
+            }
+            Request::SyncSelect => handle_sync_select(state, session_id).await,
+            Request::SyncRequest(request) => handle_sync_request(request, &replies, state, session_id).await,
+            Request::ScheduleMessageSend(request) => {
+                handle_schedule_message_send(request, &replies, state, session_id).await
+            }
+            Request::ScheduleList => handle_schedule_list(&replies, state, session_id).await,
+            Request::ScheduleCancel(request) => handle_schedule_cancel(request, &replies, state, session_id).await,
+            Request::ApiTokenCreate(request) => handle_api_token_create(request, &replies, state, session_id).await,
+            Request::ApiTokenRevoke(request) => handle_api_token_revoke(request, &replies, state, session_id).await,
+            Request::ApiTokenList => handle_api_token_list(&replies, state, session_id).await,
+            Request::SigningKeySet(request) => handle_signing_key_set(request, &replies, state, session_id).await,
+            Request::SigningKeyRequest(request) => {
+                handle_signing_key_request(request, &replies, state, session_id).await
+            }
+        }
+    }
+
+    // answers a request that did not decode the way its kind of request fails
+    fn refuse_request(message_type: MessageType, error: String, replies: &Replies, session_id: Uuid) {
+        match message_type {
+            MessageType::Heartbeat | MessageType::FileChunk | MessageType::FileComplete => {
+                tracing::warn!("Invalid {:?} from session {}: {}", message_type, session_id, error);
+            }
+            MessageType::Auth
+            | MessageType::AuthChallenge
+            | MessageType::AuthCreate
+            | MessageType::RenameAccount
+            | MessageType::PasswordChange => replies.send(Response::AuthFailure { code: None, error }),
+            MessageType::DirectMessageSend => replies.send(Response::MessageError { code: None, error }),
+            MessageType::ScheduleMessageSend | MessageType::SigningKeySet => {
+                tracing::warn!("Invalid {:?} from session {}: {}", message_type, session_id, error);
+                replies.send(Response::Rejected {
+                    request: message_type,
+                    reason: error,
+                });
+            }
+            _ => {
+                tracing::warn!("Invalid {:?} from session {}: {}", message_type, session_id, error);
+                replies.send(Response::Nack);
+            }
+        }
+    }
+
     async fn handle_disconnect(shared_state: ArcRwLock<SharedState>, session_id: Uuid) {
         shared_state.write().await.close_session(session_id).await;
     }
 
     async fn handle_heartbeat(
         tx: mpsc::UnboundedSender<Message>,
+        heartbeat: Heartbeat,
         shared_state: ArcRwLock<SharedState>,
         session_id: Uuid,
     ) {
-        use tokio::time::{self, Duration, Instant};
-
-        let sleep = time::sleep(Duration::from_secs(HEARTBEAT_INTERVAL));
+        let clock = shared_state.read().await.clock();
+        let grace = heartbeat.grace.min(heartbeat.interval);
+        let sleep = clock.sleep(heartbeat.interval);
         tokio::pin!(sleep);
+        let deadline = clock.sleep(grace);
+        tokio::pin!(deadline);
+        let mut waiting = false;
 
         loop {
             tokio::select! {
+                // an answer is judged before the next heartbeat resets it
+                biased;
+                () = &mut deadline, if waiting => {
+                    waiting = false;
+                    let misses = shared_state.read().await.check_heartbeat(session_id).await;
+                    if misses > 0 {
+                        tracing::debug!("Session {} missed {} heartbeats in a row", session_id, misses);
+                    }
+                    if heartbeat.max_misses > 0 && misses >= heartbeat.max_misses {
+                        tracing::info!("Session {} stopped answering heartbeats, closing", session_id);
+                        let detail = format!("No answer to {} heartbeats", misses);
+                        tx.send(Message::disconnect(DisconnectReason::IdleTimeout, &detail)).ok();
+                        break;
+                    }
+                },
                 () = &mut sleep => {
-                    if let Err(e) = tx.send(Message::heartbeat()) {
+                    let Some(probe) = shared_state.read().await.expect_heartbeat(session_id).await else {
+                        break;
+                    };
+                    if let Err(e) = tx.send(Message::heartbeat_with(probe)) {
                         tracing::warn!("Error sending heartbeat: {}", e);
                     }
-                    sleep.as_mut().reset(Instant::now() + Duration::from_secs(HEARTBEAT_INTERVAL));
+                    let now = clock.instant();
+                    sleep.as_mut().reset(now + heartbeat.interval);
+                    deadline.as_mut().reset(now + grace);
+                    waiting = true;
                 },
                 _ = tx.closed() => {
                     shared_state.write().await.close_session(session_id).await;
@@ -267,3 +1624,19 @@ impl Server {
         }
     }
 }
+
+// errors that say nothing about the listener itself, the next accept may well succeed
+fn is_transient_accept_error(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::OutOfMemory => true,
+        _ => matches!(
+            error.raw_os_error(),
+            Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM | libc::EPROTO)
+        ),
+    }
+}
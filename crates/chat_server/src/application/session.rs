@@ -1,48 +1,125 @@
-use chat_core::protocol::{Message, MessageType};
+use std::net::SocketAddr;
+
+use chat_core::{
+    integrity::Integrity,
+    protocol::{HeartbeatEcho, Message},
+};
 use chrono::{DateTime, Utc};
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+// declared from least to most privileged, moderation compares levels by this order
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AccessLevel {
     Guest,
     User,
+    Moderator,
     Admin,
 }
 
+// what happens when a user authenticates while an older session is still attached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TakeoverPolicy {
+    #[default]
+    Reject,
+    Replace {
+        same_peer: bool,
+    },
+    // both sessions stay logged in, messages from others go to the newest one
+    Allow,
+}
+
 #[derive(Debug)]
 pub struct Session {
     id: Uuid,
-    user: Option<String>,
+    peer: Option<SocketAddr>,
+    user: Option<Uuid>,
     access_level: AccessLevel,
     tx: Option<mpsc::UnboundedSender<Message>>,
     last_heartbeat: Option<DateTime<Utc>>,
+    // whether anything answered the heartbeat we sent last
+    heartbeat_answered: bool,
+    // in a row, reset by every answer
+    missed_heartbeats: u32,
+    total_missed_heartbeats: u64,
+    // the last sequence we sent, and the last one we sent as a probe rather than a reply
+    heartbeat_sequence: u64,
+    probe_sequence: u64,
+    // the last sequence the peer sent, echoed back with our next heartbeat
+    peer_sequence: u64,
+    // the highest of our sequences the peer echoed
+    acknowledged: u64,
+    // frames of any kind, a peer we can no longer reach may well keep talking
+    last_received: Option<DateTime<Utc>>,
+    // the last time the peer proved it got something from us
+    last_two_way: Option<DateTime<Utc>>,
+    authenticated_at: Option<DateTime<Utc>>,
+    connected_at: Option<DateTime<Utc>>,
+    // the last frame that was not a heartbeat, None until the first one
+    last_activity: Option<DateTime<Utc>>,
+    frames_received: u64,
+    frames_sent: u64,
+    // presence updates handed to the channel that were not written yet
+    pending_presence: usize,
+    // the server sent a Disconnect and only waits for its Ack
+    disconnecting: bool,
 
     closed: bool,
+    // answered by AuthChallenge, a captured answer is worthless on any other connection
+    nonce: String,
+    // from the client hello, None uses the server's default language
+    language: Option<String>,
+    // what the frames from the client are checked with, crc32 until it selects another
+    integrity: Integrity,
+    integrity_failures: u32,
+    // the client pulls its offline queue in batches instead of having it flushed at login
+    offline_sync: bool,
+}
+
+impl TakeoverPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "reject" => Ok(TakeoverPolicy::Reject),
+            "replace" => Ok(TakeoverPolicy::Replace { same_peer: false }),
+            "replace-same-peer" => Ok(TakeoverPolicy::Replace { same_peer: true }),
+            "allow" => Ok(TakeoverPolicy::Allow),
+            _ => Err(format!(
+                "Unknown takeover policy '{}', expected reject, replace, replace-same-peer or allow",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TakeoverPolicy::Reject => "reject",
+            TakeoverPolicy::Replace { same_peer: false } => "replace",
+            TakeoverPolicy::Replace { same_peer: true } => "replace-same-peer",
+            TakeoverPolicy::Allow => "allow",
+        }
+    }
 }
 
 impl AccessLevel {
-    const ADMIN_ACCESS_GROUP: &[MessageType] = &[MessageType::ServerDebugLog, MessageType::ServerShutdown];
-    const GUEST_ACCESS_GROUP: &[MessageType] = &[
-        MessageType::Empty,
-        MessageType::Ack,
-        MessageType::Nack,
-        MessageType::AuthCreate,
-        MessageType::Auth,
-        MessageType::Heartbeat,
-        MessageType::Disconnect,
-    ];
-    const USER_ACCESS_GROUP: &[MessageType] = &[MessageType::DirectMessageSend];
-
-    pub fn can_access(&self, message_type: &MessageType) -> bool {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "guest" => Ok(AccessLevel::Guest),
+            "user" => Ok(AccessLevel::User),
+            "moderator" => Ok(AccessLevel::Moderator),
+            "admin" => Ok(AccessLevel::Admin),
+            _ => Err(format!(
+                "Unknown access level '{}', expected guest, user, moderator or admin",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
         match self {
-            AccessLevel::Admin => {
-                Self::ADMIN_ACCESS_GROUP.contains(message_type)
-                    || Self::User.can_access(message_type)
-                    || Self::Guest.can_access(message_type)
-            }
-            AccessLevel::User => Self::USER_ACCESS_GROUP.contains(message_type) || Self::Guest.can_access(message_type),
-            AccessLevel::Guest => Self::GUEST_ACCESS_GROUP.contains(message_type),
+            AccessLevel::Guest => "guest",
+            AccessLevel::User => "user",
+            AccessLevel::Moderator => "moderator",
+            AccessLevel::Admin => "admin",
         }
     }
 }
@@ -53,11 +130,33 @@ impl Session {
 
         Self {
             id,
+            peer: None,
             user: None,
             access_level: AccessLevel::Guest,
             tx: None,
             closed: false,
             last_heartbeat: None,
+            heartbeat_answered: true,
+            missed_heartbeats: 0,
+            total_missed_heartbeats: 0,
+            heartbeat_sequence: 0,
+            probe_sequence: 0,
+            peer_sequence: 0,
+            acknowledged: 0,
+            last_received: None,
+            last_two_way: None,
+            authenticated_at: None,
+            connected_at: None,
+            last_activity: None,
+            frames_received: 0,
+            frames_sent: 0,
+            pending_presence: 0,
+            disconnecting: false,
+            nonce: Uuid::new_v4().simple().to_string(),
+            language: None,
+            integrity: Integrity::default(),
+            integrity_failures: 0,
+            offline_sync: false,
         }
     }
 
@@ -65,12 +164,123 @@ impl Session {
         self.id
     }
 
-    pub fn user(&self) -> Option<&String> {
-        self.user.as_ref()
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub fn nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn set_language(&mut self, language: &str) {
+        self.language = Some(language.to_string());
+    }
+
+    pub fn integrity(&self) -> Integrity {
+        self.integrity
+    }
+
+    pub fn set_integrity(&mut self, integrity: Integrity) {
+        self.integrity = integrity;
+    }
+
+    pub fn integrity_failures(&self) -> u32 {
+        self.integrity_failures
+    }
+
+    // the failures so far, this one included
+    pub fn record_integrity_failure(&mut self) -> u32 {
+        self.integrity_failures += 1;
+        self.integrity_failures
+    }
+
+    pub fn offline_sync(&self) -> bool {
+        self.offline_sync
+    }
+
+    pub fn select_offline_sync(&mut self) {
+        self.offline_sync = true;
+    }
+
+    pub fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = Some(peer);
+    }
+
+    pub fn set_connected_at(&mut self, now: DateTime<Utc>) {
+        self.connected_at = Some(now);
+    }
+
+    pub fn connected_at(&self) -> Option<DateTime<Utc>> {
+        self.connected_at
+    }
+
+    // a session that never sent anything is idle since it connected
+    pub fn last_activity(&self) -> Option<DateTime<Utc>> {
+        self.last_activity.or(self.connected_at)
+    }
+
+    pub fn last_heartbeat(&self) -> Option<DateTime<Utc>> {
+        self.last_heartbeat
     }
 
-    pub fn set_user(&mut self, user: String) {
+    // both fall back to the connection time until something came in
+    pub fn last_received(&self) -> Option<DateTime<Utc>> {
+        self.last_received.or(self.connected_at)
+    }
+
+    pub fn last_two_way(&self) -> Option<DateTime<Utc>> {
+        self.last_two_way.or(self.connected_at)
+    }
+
+    pub fn record_received(&mut self, heartbeat: bool, now: DateTime<Utc>) {
+        self.frames_received += 1;
+        self.last_received = Some(now);
+        if !heartbeat {
+            self.last_activity = Some(now);
+        }
+    }
+
+    pub fn record_sent(&mut self, presence: bool) {
+        self.frames_sent += 1;
+        if presence {
+            self.pending_presence = self.pending_presence.saturating_sub(1);
+        }
+    }
+
+    pub fn pending_presence(&self) -> usize {
+        self.pending_presence
+    }
+
+    pub fn frames_received(&self) -> u64 {
+        self.frames_received
+    }
+
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent
+    }
+
+    pub fn user(&self) -> Option<Uuid> {
+        self.user
+    }
+
+    pub fn set_user(&mut self, user: Uuid, now: DateTime<Utc>) {
         self.user = Some(user);
+        self.authenticated_at = Some(now);
+    }
+
+    pub fn authenticated_at(&self) -> Option<DateTime<Utc>> {
+        self.authenticated_at
+    }
+
+    // keeps the connection open but requires a new Auth before anything else
+    pub fn demote(&mut self) {
+        self.user = None;
+        self.access_level = AccessLevel::Guest;
+        self.authenticated_at = None;
     }
 
     pub fn access_level(&self) -> &AccessLevel {
@@ -93,12 +303,78 @@ impl Session {
         self.closed
     }
 
-    pub fn update_heartbeat(&mut self, heartbeat: Option<DateTime<Utc>>) {
-        if let Some(heartbeat) = heartbeat {
-            self.last_heartbeat = Some(heartbeat);
-        } else {
-            self.last_heartbeat = Some(Utc::now());
+    pub fn begin_disconnect(&mut self) {
+        self.disconnecting = true;
+    }
+
+    pub fn is_disconnecting(&self) -> bool {
+        self.disconnecting
+    }
+
+    pub fn update_heartbeat(&mut self, heartbeat: DateTime<Utc>) {
+        self.last_heartbeat = Some(heartbeat);
+        self.heartbeat_answered = true;
+        self.missed_heartbeats = 0;
+    }
+
+    // returns the reply to send, heartbeats without sequences are taken as answers like they always were
+    pub fn receive_heartbeat(
+        &mut self,
+        heartbeat: DateTime<Utc>,
+        echo: Option<HeartbeatEcho>,
+        now: DateTime<Utc>,
+    ) -> Option<HeartbeatEcho> {
+        let Some(echo) = echo else {
+            self.update_heartbeat(heartbeat);
+            self.last_two_way = Some(now);
+            return None;
+        };
+        self.last_heartbeat = Some(heartbeat);
+        self.peer_sequence = self.peer_sequence.max(echo.sequence);
+        if echo.echo > self.acknowledged && echo.echo <= self.heartbeat_sequence {
+            self.acknowledged = echo.echo;
+            self.last_two_way = Some(now);
+        }
+        // a peer that only hears from itself keeps sending an old echo, that is no answer
+        if self.probe_sequence > 0 && echo.echo >= self.probe_sequence {
+            self.heartbeat_answered = true;
+            self.missed_heartbeats = 0;
+        }
+        (!echo.reply).then(|| self.next_heartbeat(true))
+    }
+
+    // the sequences for the next heartbeat we send
+    pub fn next_heartbeat(&mut self, reply: bool) -> HeartbeatEcho {
+        self.heartbeat_sequence += 1;
+        if !reply {
+            self.probe_sequence = self.heartbeat_sequence;
+            self.heartbeat_answered = false;
+        }
+        HeartbeatEcho {
+            sequence: self.heartbeat_sequence,
+            echo: self.peer_sequence,
+            reply,
+        }
+    }
+
+    // called once the grace period is over, returns the misses in a row
+    pub fn check_heartbeat(&mut self) -> u32 {
+        if !self.heartbeat_answered {
+            self.missed_heartbeats += 1;
+            self.total_missed_heartbeats += 1;
         }
+        self.missed_heartbeats
+    }
+
+    pub fn total_missed_heartbeats(&self) -> u64 {
+        self.total_missed_heartbeats
+    }
+
+    // counted until the writer got it out, a peer that stops reading stops getting them
+    pub fn send_presence(&mut self, message: Message) -> Result<(), mpsc::error::SendError<Message>> {
+        self.send(message)?;
+        self.pending_presence += 1;
+        Ok(())
     }
 
     pub fn send(&self, message: Message) -> Result<(), mpsc::error::SendError<Message>> {
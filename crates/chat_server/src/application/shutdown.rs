@@ -0,0 +1,89 @@
+use std::{fmt, io, path::Path, time::Duration};
+
+use chat_core::json::JsonValue;
+
+use super::export;
+
+pub const LAST_SHUTDOWN_FILE: &str = "last_shutdown.json";
+
+// what the process exits with, startup errors before anything was served exit with 1
+pub const EXIT_CLEAN: i32 = 0;
+pub const EXIT_FATAL: i32 = 2;
+
+// why the server stopped, supervisors tell crashes from intentional stops by the exit code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    // an admin sent a shutdown request
+    AdminRequested,
+    // SIGINT or SIGTERM
+    Signal,
+    // a drain with a deadline ran out of sessions or time
+    Drained,
+    // the listener broke and no more connections could be accepted
+    FatalError(String),
+}
+
+// written as the last log event and to the data directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    pub reason: ShutdownReason,
+    pub uptime: Duration,
+    pub peak_sessions: usize,
+    pub messages_relayed: u64,
+}
+
+impl ShutdownReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownReason::AdminRequested => "admin_requested",
+            ShutdownReason::Signal => "signal",
+            ShutdownReason::Drained => "drained",
+            ShutdownReason::FatalError(_) => "fatal_error",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // a supervisor that sent the signal expects a clean exit from a stop it asked for
+            ShutdownReason::AdminRequested | ShutdownReason::Drained | ShutdownReason::Signal => EXIT_CLEAN,
+            ShutdownReason::FatalError(_) => EXIT_FATAL,
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            ShutdownReason::FatalError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::FatalError(error) => write!(f, "{}: {}", self.as_str(), error),
+            _ => f.write_str(self.as_str()),
+        }
+    }
+}
+
+impl ShutdownSummary {
+    pub fn exit_code(&self) -> i32 {
+        self.reason.exit_code()
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("reason", self.reason.as_str())
+            .with("error", self.reason.error())
+            .with("exit_code", i64::from(self.exit_code()))
+            .with("uptime_secs", self.uptime.as_secs())
+            .with("peak_sessions", self.peak_sessions)
+            .with("messages_relayed", self.messages_relayed)
+    }
+
+    // replaces the report of the previous run
+    pub fn write(&self, data_dir: &Path) -> io::Result<()> {
+        export::write_document(&data_dir.join(LAST_SHUTDOWN_FILE), &self.to_json())
+    }
+}
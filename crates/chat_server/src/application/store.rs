@@ -0,0 +1,355 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs, io,
+    ops::Range,
+    path::PathBuf,
+    time::Duration,
+};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageId, HISTORY_DELETED, HISTORY_EDITED, HISTORY_SELF_NOTE},
+};
+use chrono::{DateTime, Utc};
+
+use super::export;
+
+pub const EDIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const MAX_MESSAGE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MAX_STORED_MESSAGES: usize = 10_000;
+pub const MESSAGE_IDS_FILE: &str = "message_ids.json";
+// ids written ahead to the data directory at a time, a restart skips what was left of the block
+const ID_BLOCK: u64 = 1000;
+// characters kept on either side of a search match
+const SNIPPET_CONTEXT: usize = 32;
+
+// how deleted messages show up when a conversation is replayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletedHistory {
+    #[default]
+    Tombstone,
+    Omit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage {
+    id: MessageId,
+    sender: String,
+    recipient: String,
+    body: String,
+    sent_at: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    deleted: bool,
+    // the sender's, kept as it came so history can be verified like the live message, it only covers the first body
+    signature: Option<Vec<u8>>,
+}
+
+// every relayed direct message, the oldest make room once the store is full
+#[derive(Debug)]
+pub struct MessageStore {
+    messages: VecDeque<StoredMessage>,
+    // the only place ids are made
+    next_id: MessageId,
+    // every id below this one may have been handed out, a restart goes on from here
+    reserved: MessageId,
+    // nothing is persisted without a data directory, ids start over with every start then
+    file: Option<PathBuf>,
+}
+
+impl DeletedHistory {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "tombstone" => Ok(DeletedHistory::Tombstone),
+            "omit" => Ok(DeletedHistory::Omit),
+            _ => Err(format!(
+                "Unknown history deletion mode '{}', expected tombstone or omit",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletedHistory::Tombstone => "tombstone",
+            DeletedHistory::Omit => "omit",
+        }
+    }
+}
+
+impl StoredMessage {
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    pub fn sent_at(&self) -> DateTime<Utc> {
+        self.sent_at
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn edit(&mut self, body: &str, edited_at: DateTime<Utc>) {
+        self.body = body.to_string();
+        self.edited_at = Some(edited_at);
+        self.signature = None;
+    }
+
+    // the body is dropped for good, only the tombstone remains
+    pub fn delete(&mut self) {
+        self.body.clear();
+        self.deleted = true;
+        self.signature = None;
+    }
+
+    pub fn to_history_entry(&self) -> Message {
+        let mut flags = 0;
+        if self.edited_at.is_some() {
+            flags |= HISTORY_EDITED;
+        }
+        if self.deleted {
+            flags |= HISTORY_DELETED;
+        }
+        if self.sender == self.recipient {
+            flags |= HISTORY_SELF_NOTE;
+        }
+
+        let entry = Message::history_entry(self.id, &self.sender, &self.recipient, &self.body, self.sent_at, flags);
+        match &self.signature {
+            Some(signature) => entry.with_signature(signature),
+            None => entry,
+        }
+    }
+
+    // `range` is the match within the body, the result carries it relative to the snippet
+    pub fn to_search_result(&self, user: &str, range: Range<usize>) -> Message {
+        let peer = if self.sender == user {
+            &self.recipient
+        } else {
+            &self.sender
+        };
+        let start = self.body[..range.start]
+            .char_indices()
+            .rev()
+            .nth(SNIPPET_CONTEXT - 1)
+            .map_or(0, |(index, _)| index);
+        let end = self.body[range.end..]
+            .char_indices()
+            .nth(SNIPPET_CONTEXT)
+            .map_or(self.body.len(), |(index, _)| range.end + index);
+
+        Message::search_result(
+            self.id,
+            peer,
+            self.sent_at,
+            &self.body[start..end],
+            (range.start - start) as u64,
+            (range.end - start) as u64,
+        )
+    }
+
+    fn is_between(&self, user: &str, peer: &str) -> bool {
+        (self.sender == user && self.recipient == peer) || (self.sender == peer && self.recipient == user)
+    }
+}
+
+impl MessageStore {
+    // the messages stay in memory, only where the ids go on from outlives a restart
+    pub fn load_ids(&mut self, path: PathBuf) -> Result<MessageId, String> {
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let next = JsonValue::parse(&content)
+                    .ok()
+                    .and_then(|document| document.get("next_id").and_then(JsonValue::as_u64))
+                    .ok_or_else(|| format!("{}: expected a numeric 'next_id'", path.display()))?;
+                self.next_id = self.next_id.max(MessageId::new(next));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+        self.reserved = self.next_id;
+        self.file = Some(path);
+        Ok(self.next_id)
+    }
+
+    pub fn record(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        body: &str,
+        sent_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+        signature: Option<&[u8]>,
+    ) -> MessageId {
+        if self.messages.len() >= MAX_STORED_MESSAGES {
+            self.messages.pop_front();
+        }
+
+        let id = self.next_id;
+        self.next_id = id.next();
+        if id >= self.reserved {
+            self.reserve(MessageId::new(id.get() + ID_BLOCK));
+        }
+        self.messages.push_back(StoredMessage {
+            id,
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            body: body.to_string(),
+            sent_at,
+            edited_at: None,
+            expires_at,
+            deleted: false,
+            signature: signature.map(<[u8]>::to_vec),
+        });
+        id
+    }
+
+    fn reserve(&mut self, reserved: MessageId) {
+        self.reserved = reserved;
+        let Some(path) = &self.file else {
+            return;
+        };
+        let document = JsonValue::object().with("next_id", reserved.get());
+        if let Err(e) = export::write_document(path, &document) {
+            tracing::warn!(
+                "Could not write {}: {}, message ids may repeat after a restart",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    // expired messages are already hidden everywhere, this only frees their memory
+    pub fn purge_expired(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|message| !message.is_expired(now));
+        before - self.messages.len()
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for message in self.messages.iter_mut() {
+            if message.sender != old && message.recipient != old {
+                continue;
+            }
+            if message.sender == old {
+                message.sender = new.to_string();
+            }
+            if message.recipient == old {
+                message.recipient = new.to_string();
+            }
+            // the signature covers the names it was made over, with one of them gone it could only ever fail
+            message.signature = None;
+        }
+    }
+
+    // everyone the user has a stored conversation with
+    pub fn peers_of(&self, user: &str) -> BTreeSet<String> {
+        self.messages
+            .iter()
+            .filter_map(|message| match (message.sender == user, message.recipient == user) {
+                (true, false) => Some(message.recipient.clone()),
+                (false, true) => Some(message.sender.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // for messages that could be neither delivered nor queued
+    pub fn forget(&mut self, id: MessageId) {
+        self.messages.retain(|message| message.id != id);
+    }
+
+    pub fn get_mut(&mut self, id: MessageId) -> Option<&mut StoredMessage> {
+        // ids only grow, so the store stays sorted by id
+        let index = self.messages.binary_search_by_key(&id, |message| message.id).ok()?;
+        self.messages.get_mut(index)
+    }
+
+    // the newest `limit` messages between the two users older than `before`, oldest first
+    pub fn conversation(
+        &self,
+        user: &str,
+        peer: &str,
+        before: Option<MessageId>,
+        limit: usize,
+        deleted: DeletedHistory,
+        now: DateTime<Utc>,
+    ) -> Vec<&StoredMessage> {
+        let mut messages: Vec<&StoredMessage> = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|message| !message.is_expired(now))
+            .filter(|message| before.map_or(true, |before| message.id < before))
+            .filter(|message| message.is_between(user, peer))
+            .filter(|message| !(message.deleted && deleted == DeletedHistory::Omit))
+            .take(limit)
+            .collect();
+        messages.reverse();
+        messages
+    }
+
+    // newest first, deleted and expired messages never match
+    pub fn search(
+        &self,
+        user: &str,
+        query: &str,
+        peer: Option<&str>,
+        limit: usize,
+        now: DateTime<Utc>,
+    ) -> Vec<(&StoredMessage, Range<usize>)> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|message| !message.deleted && !message.is_expired(now))
+            .filter(|message| match peer {
+                Some(peer) => message.is_between(user, peer),
+                None => message.sender == user || message.recipient == user,
+            })
+            .filter_map(|message| find_ignore_case(&message.body, query).map(|range| (message, range)))
+            .take(limit)
+            .collect()
+    }
+}
+
+impl Default for MessageStore {
+    fn default() -> Self {
+        // ids start at 1 so 0 is never mistaken for a real message
+        Self {
+            messages: VecDeque::new(),
+            next_id: MessageId::new(1),
+            reserved: MessageId::new(1),
+            file: None,
+        }
+    }
+}
+
+// byte range of the first case-insensitive occurrence of `needle`
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<Range<usize>> {
+    let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut remaining = needle.as_slice();
+        for (offset, c) in haystack[start..].char_indices() {
+            for lower in c.to_lowercase() {
+                match remaining.split_first() {
+                    Some((expected, rest)) if *expected == lower => remaining = rest,
+                    Some(_) => return None,
+                    None => break,
+                }
+            }
+            if remaining.is_empty() {
+                return Some(start..start + offset + c.len_utf8());
+            }
+        }
+        None
+    })
+}
@@ -0,0 +1,132 @@
+use std::{error::Error, future::Future, pin::Pin, sync::Arc, task::Poll};
+
+use tokio::sync::RwLock;
+use tracing::Instrument;
+
+use super::{
+    config::ServerConfig, log_control::LogControl, permissions::AccessPresets, plugin::ServerPlugin, server::Server,
+    shutdown::ShutdownSummary, ArcRwLock, DataDir, SharedState,
+};
+use chat_core::trace::FrameTracer;
+
+// one chat instance of the process, nothing of its users, sessions or messages is visible to another
+#[derive(Debug)]
+pub(super) struct Tenant {
+    // None for a process that serves a single instance
+    name: Option<String>,
+    server: Server,
+    shared_state: ArcRwLock<SharedState>,
+    // held until the application is dropped
+    _data_dir: Option<DataDir>,
+}
+
+impl Tenant {
+    pub(super) fn open(
+        name: Option<&str>,
+        config: &ServerConfig,
+        log_control: Option<LogControl>,
+    ) -> Result<Self, Box<dyn Error>> {
+        // before anything is read or written, a second instance stops here
+        let data_dir = DataDir::open(&config.data_dir())?;
+        tracing::info!("Using data directory {}", data_dir.path().display());
+
+        let mut shared_state = SharedState::new();
+        if let Some(log_control) = log_control {
+            shared_state.set_log_control(log_control);
+        }
+
+        if let Some(path) = config.users_file().filter(|path| path.exists()) {
+            let (count, cleanup) = shared_state.load_users(&path)?;
+            tracing::info!("Loaded {} users from {}", count, path.display());
+            if !cleanup.is_empty() {
+                tracing::warn!("Cleaned up after loading {}: {}", path.display(), cleanup);
+            }
+        }
+
+        if let Some(path) = config.access_presets_file() {
+            let presets = std::fs::read_to_string(path)?;
+            shared_state.set_access_presets(AccessPresets::parse(&presets)?);
+            tracing::info!("Loaded access presets from {}", path.display());
+        }
+
+        let mut server = config.apply(Server::new());
+        if let Some(path) = config.trace_file() {
+            server = server.with_tracer(FrameTracer::create(path)?);
+            tracing::info!("Tracing frames to {}", path.display());
+        }
+
+        Ok(Self {
+            name: name.map(str::to_string),
+            server,
+            shared_state: Arc::new(RwLock::new(shared_state)),
+            _data_dir: Some(data_dir),
+        })
+    }
+
+    pub(super) fn new(server: Server, shared_state: SharedState) -> Self {
+        Self {
+            name: None,
+            server,
+            shared_state: Arc::new(RwLock::new(shared_state)),
+            _data_dir: None,
+        }
+    }
+
+    pub(super) fn with_plugin(mut self, plugin: Arc<dyn ServerPlugin>) -> Self {
+        self.server = self.server.with_plugin(plugin);
+        self
+    }
+
+    async fn run(&self) -> Result<ShutdownSummary, Box<dyn Error>> {
+        let serve = self.server.serve(Arc::clone(&self.shared_state));
+        match &self.name {
+            Some(name) => {
+                serve
+                    .instrument(tracing::info_span!("tenant", name = name.as_str()))
+                    .await
+            }
+            None => serve.await,
+        }
+    }
+}
+
+// every tenant serves until it stops on its own, the first one that cannot start ends the run
+pub(super) async fn run_all(tenants: &[Tenant]) -> Result<ShutdownSummary, Box<dyn Error>> {
+    let mut runs: Vec<Option<Pin<Box<_>>>> = tenants.iter().map(|tenant| Some(Box::pin(tenant.run()))).collect();
+    let mut summaries = Vec::new();
+    std::future::poll_fn(|cx| {
+        for run in runs.iter_mut() {
+            let Some(future) = run else {
+                continue;
+            };
+            if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                *run = None;
+                match result {
+                    Ok(summary) => summaries.push(summary),
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+        }
+        match runs.iter().all(Option::is_none) {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        }
+    })
+    .await?;
+    Ok(combine(summaries))
+}
+
+// the process exits with the worst of the reasons, counters add up over the tenants
+fn combine(summaries: Vec<ShutdownSummary>) -> ShutdownSummary {
+    let mut summaries = summaries.into_iter();
+    let first = summaries.next().expect("There is always at least one tenant");
+    summaries.fold(first, |combined, summary| ShutdownSummary {
+        reason: match summary.exit_code() > combined.exit_code() {
+            true => summary.reason,
+            false => combined.reason,
+        },
+        uptime: combined.uptime.max(summary.uptime),
+        peak_sessions: combined.peak_sessions + summary.peak_sessions,
+        messages_relayed: combined.messages_relayed + summary.messages_relayed,
+    })
+}
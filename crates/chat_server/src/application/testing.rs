@@ -0,0 +1,1154 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+use chat_core::{
+    capability::Capabilities,
+    integrity::Integrity,
+    protocol::{Message, MessageId, MessageType},
+    time_sync::ManualClock,
+    trace::FrameTracer,
+    transport::{
+        chaos::{Chaos, ChaosConnector, ChaosControl},
+        memory::{self, MemoryConnector, MemoryListener},
+        Connector, Listener, TcpConnector,
+    },
+    version::VersionRange,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    sync::{mpsc, watch, Notify, OwnedRwLockWriteGuard, RwLock},
+    task::JoinHandle,
+};
+
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
+
+pub use super::{
+    audit::AuditEntry,
+    auth_limit::AuthLimits,
+    flood::FloodLimits,
+    ip_guard::ViolationLimits,
+    journal::{write_journal, Journal, JournalEntry, JournalKind, JournalLimits, JournalWriter},
+    permissions::{AccessPresets, Permissions},
+    presence::PresenceTiming,
+    replay::{Divergence, Replay, ReplayReport},
+    schedule::ScheduleLimits,
+    session::{AccessLevel, TakeoverPolicy},
+    store::DeletedHistory,
+};
+use super::{
+    handles::{admin::stop_server, auth::hash_credentials},
+    log_control::LogControl,
+    offline::OfflineWebhooks,
+    plugin::ServerPlugin,
+    server::Server,
+    session::Session,
+    user::User,
+    Application, ArcRwLock, Cleanup, DataDir, ImportMode, ServerConfig, SharedState, ShutdownReason, ShutdownSummary,
+};
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_LOG_FILTER: &str = "info";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const LINK_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+pub struct TestServerBuilder {
+    heartbeat_interval: Option<Duration>,
+    heartbeat_grace: Option<Duration>,
+    max_missed_heartbeats: Option<u32>,
+    handshake_timeout: Option<Duration>,
+    disconnect_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    accept_backoff: Option<Duration>,
+    tracer: Option<FrameTracer>,
+    capabilities: Option<Capabilities>,
+    max_session_age: Option<Option<Duration>>,
+    takeover_policy: Option<TakeoverPolicy>,
+    access_presets: Option<AccessPresets>,
+    edit_window: Option<Duration>,
+    deleted_history: Option<DeletedHistory>,
+    max_file_size: Option<u64>,
+    max_chunk_size: Option<u64>,
+    max_message_ttl: Option<Duration>,
+    rename_grace: Option<Duration>,
+    dedup_capacity: Option<usize>,
+    dedup_ttl: Option<Duration>,
+    data_dir: Option<PathBuf>,
+    control_socket: bool,
+    http_bridge: bool,
+    users_file: Option<PathBuf>,
+    plain_auth: Option<bool>,
+    offline_queue: Option<bool>,
+    offline_ttl: Option<Duration>,
+    conceal_users: Option<bool>,
+    listen_only: Option<bool>,
+    offline_webhooks: Option<OfflineWebhooks>,
+    flood_limits: Option<FloodLimits>,
+    schedule_limits: Option<ScheduleLimits>,
+    violation_limits: Option<ViolationLimits>,
+    auth_limits: Option<AuthLimits>,
+    protocol_versions: Option<VersionRange>,
+    integrity: Option<Vec<Integrity>>,
+    max_integrity_failures: Option<u32>,
+    server_name: Option<String>,
+    default_language: Option<String>,
+    presence: Option<PresenceTiming>,
+    journal_writer: Option<Arc<dyn JournalWriter>>,
+    journal_limits: Option<JournalLimits>,
+    plugins: Vec<Arc<dyn ServerPlugin>>,
+}
+
+#[derive(Debug)]
+pub struct TestServer {
+    connector: MemoryConnector,
+    accept_faults: AcceptFaults,
+    shared_state: ArcRwLock<SharedState>,
+    handle: JoinHandle<()>,
+    // None until the server stopped
+    summary: watch::Receiver<Option<ShutdownSummary>>,
+    // every test server runs on one, it follows tokio's clock until a test moves it
+    clock: Arc<ManualClock>,
+    // what loading the users file had to clean up
+    load_cleanup: Cleanup,
+    // keeps the reloadable filter alive so log level changes can be applied
+    _dispatch: tracing::Dispatch,
+    // the lock on the data directory, released when the server is dropped
+    _data_dir: Option<DataDir>,
+}
+
+// errors the listener returns before it goes back to accepting, in order
+#[derive(Debug, Clone, Default)]
+struct AcceptFaults {
+    errors: Arc<Mutex<VecDeque<io::Error>>>,
+    // wakes an accept that is already waiting for a connection
+    added: Arc<Notify>,
+}
+
+#[derive(Debug)]
+struct FaultyListener {
+    inner: MemoryListener,
+    faults: AcceptFaults,
+}
+
+// keeps what it was given in memory, or fails every write while a test says so
+#[derive(Debug, Default)]
+pub struct TestJournal {
+    failing: AtomicBool,
+    audit: Mutex<Vec<String>>,
+    metrics: Mutex<Vec<String>>,
+    failed_writes: AtomicU64,
+}
+
+// what the connections of one client lose on the way, each direction is switched on its own
+#[derive(Debug, Clone, Default)]
+pub struct LinkFaults {
+    to_server: Arc<AtomicBool>,
+    to_client: Arc<AtomicBool>,
+}
+
+// relays every connection through a pump per direction, reconnects go through the same faults
+#[derive(Debug, Clone)]
+struct FaultyConnector {
+    inner: MemoryConnector,
+    faults: LinkFaults,
+}
+
+#[derive(Debug)]
+pub struct TestClient {
+    client: ChatClient,
+    events: mpsc::UnboundedReceiver<ClientEvent>,
+}
+
+// a session whose lock someone holds, nothing reaches it until the wedge is dropped
+#[derive(Debug)]
+pub struct SessionWedge {
+    _guard: OwnedRwLockWriteGuard<Session>,
+}
+
+#[derive(Debug)]
+pub struct RawConnection {
+    reader: ReadHalf<DuplexStream>,
+    writer: WriteHalf<DuplexStream>,
+    // both directions, a test switches it once the selection is confirmed
+    integrity: Integrity,
+}
+
+// like the application the binary starts, with a log filter of its own instead of the process's,
+// the dispatch holds that filter and is kept for as long as the application runs
+pub fn application_with_log_control(
+    config: &ServerConfig,
+) -> Result<(Application, tracing::Dispatch), Box<dyn std::error::Error>> {
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(DEFAULT_LOG_FILTER));
+    let dispatch = tracing::Dispatch::new(Registry::default().with(filter));
+    Ok((
+        Application::open(config, Some(LogControl::new(filter_handle)))?,
+        dispatch,
+    ))
+}
+
+pub async fn within<F: Future>(future: F) -> F::Output {
+    match tokio::time::timeout(TIMEOUT, future).await {
+        Ok(output) => output,
+        Err(_) => panic!("Timed out after {:?}", TIMEOUT),
+    }
+}
+
+pub async fn eventually<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    within(async {
+        while !condition().await {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    })
+    .await
+}
+
+impl TestServerBuilder {
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = Some(heartbeat_interval);
+        self
+    }
+
+    pub fn with_heartbeat_grace(mut self, heartbeat_grace: Duration) -> Self {
+        self.heartbeat_grace = Some(heartbeat_grace);
+        self
+    }
+
+    pub fn with_max_missed_heartbeats(mut self, max_missed_heartbeats: u32) -> Self {
+        self.max_missed_heartbeats = Some(max_missed_heartbeats);
+        self
+    }
+
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = Some(handshake_timeout);
+        self
+    }
+
+    pub fn with_disconnect_timeout(mut self, disconnect_timeout: Duration) -> Self {
+        self.disconnect_timeout = Some(disconnect_timeout);
+        self
+    }
+
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    pub fn with_accept_backoff(mut self, accept_backoff: Duration) -> Self {
+        self.accept_backoff = Some(accept_backoff);
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: FrameTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn with_max_session_age(mut self, max_session_age: Option<Duration>) -> Self {
+        self.max_session_age = Some(max_session_age);
+        self
+    }
+
+    pub fn with_takeover_policy(mut self, takeover_policy: TakeoverPolicy) -> Self {
+        self.takeover_policy = Some(takeover_policy);
+        self
+    }
+
+    pub fn with_access_presets(mut self, access_presets: AccessPresets) -> Self {
+        self.access_presets = Some(access_presets);
+        self
+    }
+
+    pub fn with_edit_window(mut self, edit_window: Duration) -> Self {
+        self.edit_window = Some(edit_window);
+        self
+    }
+
+    pub fn with_deleted_history(mut self, deleted_history: DeletedHistory) -> Self {
+        self.deleted_history = Some(deleted_history);
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    pub fn with_max_chunk_size(mut self, max_chunk_size: u64) -> Self {
+        self.max_chunk_size = Some(max_chunk_size);
+        self
+    }
+
+    pub fn with_max_message_ttl(mut self, max_message_ttl: Duration) -> Self {
+        self.max_message_ttl = Some(max_message_ttl);
+        self
+    }
+
+    pub fn with_rename_grace(mut self, rename_grace: Duration) -> Self {
+        self.rename_grace = Some(rename_grace);
+        self
+    }
+
+    pub fn with_dedup_capacity(mut self, dedup_capacity: usize) -> Self {
+        self.dedup_capacity = Some(dedup_capacity);
+        self
+    }
+
+    pub fn with_dedup_ttl(mut self, dedup_ttl: Duration) -> Self {
+        self.dedup_ttl = Some(dedup_ttl);
+        self
+    }
+
+    pub fn with_data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
+    // `send_command` reaches the server through the data directory, which has to be set as well
+    pub fn with_control_socket(mut self) -> Self {
+        self.control_socket = true;
+        self
+    }
+
+    // on a free port of localhost, `http_address` says which
+    pub fn with_http_bridge(mut self) -> Self {
+        self.http_bridge = true;
+        self
+    }
+
+    // starts with the users of an export instead of the built-in ones
+    pub fn with_users_file(mut self, users_file: PathBuf) -> Self {
+        self.users_file = Some(users_file);
+        self
+    }
+
+    pub fn with_plain_auth(mut self, plain_auth: bool) -> Self {
+        self.plain_auth = Some(plain_auth);
+        self
+    }
+
+    pub fn with_offline_queue(mut self, offline_queue: bool) -> Self {
+        self.offline_queue = Some(offline_queue);
+        self
+    }
+
+    pub fn with_offline_ttl(mut self, offline_ttl: Duration) -> Self {
+        self.offline_ttl = Some(offline_ttl);
+        self
+    }
+
+    pub fn with_conceal_users(mut self, conceal_users: bool) -> Self {
+        self.conceal_users = Some(conceal_users);
+        self
+    }
+
+    pub fn with_listen_only(mut self, listen_only: bool) -> Self {
+        self.listen_only = Some(listen_only);
+        self
+    }
+
+    // bodies go out whole, not only their start
+    pub fn with_offline_webhooks(mut self, domains: &[&str], bodies: bool) -> Self {
+        let domains = domains.iter().map(|domain| domain.to_string()).collect();
+        self.offline_webhooks = Some(OfflineWebhooks::new(domains, bodies));
+        self
+    }
+
+    pub fn with_flood_limits(mut self, flood_limits: FloodLimits) -> Self {
+        self.flood_limits = Some(flood_limits);
+        self
+    }
+
+    pub fn with_schedule_limits(mut self, schedule_limits: ScheduleLimits) -> Self {
+        self.schedule_limits = Some(schedule_limits);
+        self
+    }
+
+    pub fn with_violation_limits(mut self, violation_limits: ViolationLimits) -> Self {
+        self.violation_limits = Some(violation_limits);
+        self
+    }
+
+    pub fn with_auth_limits(mut self, auth_limits: AuthLimits) -> Self {
+        self.auth_limits = Some(auth_limits);
+        self
+    }
+
+    pub fn with_protocol_versions(mut self, protocol_versions: VersionRange) -> Self {
+        self.protocol_versions = Some(protocol_versions);
+        self
+    }
+
+    pub fn with_integrity(mut self, integrity: Vec<Integrity>) -> Self {
+        self.integrity = Some(integrity);
+        self
+    }
+
+    pub fn with_max_integrity_failures(mut self, max_integrity_failures: u32) -> Self {
+        self.max_integrity_failures = Some(max_integrity_failures);
+        self
+    }
+
+    pub fn with_server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.to_string());
+        self
+    }
+
+    // catalogs are loaded from the data directory, set one up first
+    pub fn with_default_language(mut self, language: &str) -> Self {
+        self.default_language = Some(language.to_string());
+        self
+    }
+
+    pub fn with_presence(mut self, presence: PresenceTiming) -> Self {
+        self.presence = Some(presence);
+        self
+    }
+
+    pub fn with_plugin(mut self, plugin: Arc<dyn ServerPlugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    // takes the place of the files in the data directory, a journal is kept without one too
+    pub fn with_journal_writer(mut self, journal_writer: Arc<dyn JournalWriter>) -> Self {
+        self.journal_writer = Some(journal_writer);
+        self
+    }
+
+    pub fn with_journal_limits(mut self, journal_limits: JournalLimits) -> Self {
+        self.journal_limits = Some(journal_limits);
+        self
+    }
+
+    pub fn start(self) -> TestServer {
+        self.try_start().expect("Could not start the test server")
+    }
+
+    // fails like a real instance would, before anything is served
+    pub fn try_start(self) -> Result<TestServer, String> {
+        let data_dir = self.data_dir.as_deref().map(DataDir::open).transpose()?;
+
+        let mut server = Server::new();
+        if let Some(heartbeat_interval) = self.heartbeat_interval {
+            server = server.with_heartbeat_interval(heartbeat_interval);
+        }
+        if let Some(heartbeat_grace) = self.heartbeat_grace {
+            server = server.with_heartbeat_grace(heartbeat_grace);
+        }
+        if let Some(max_missed_heartbeats) = self.max_missed_heartbeats {
+            server = server.with_max_missed_heartbeats(max_missed_heartbeats);
+        }
+        if let Some(handshake_timeout) = self.handshake_timeout {
+            server = server.with_handshake_timeout(handshake_timeout);
+        }
+        if let Some(disconnect_timeout) = self.disconnect_timeout {
+            server = server.with_disconnect_timeout(disconnect_timeout);
+        }
+        if let Some(write_timeout) = self.write_timeout {
+            server = server.with_write_timeout(write_timeout);
+        }
+        if let Some(accept_backoff) = self.accept_backoff {
+            server = server.with_accept_backoff(accept_backoff);
+        }
+        if let Some(tracer) = self.tracer {
+            server = server.with_tracer(tracer);
+        }
+        if let Some(capabilities) = self.capabilities {
+            server = server.with_capabilities(capabilities);
+        }
+        if let Some(max_session_age) = self.max_session_age {
+            server = server.with_max_session_age(max_session_age);
+        }
+        if let Some(takeover_policy) = self.takeover_policy {
+            server = server.with_takeover_policy(takeover_policy);
+        }
+        if let Some(edit_window) = self.edit_window {
+            server = server.with_edit_window(edit_window);
+        }
+        if let Some(deleted_history) = self.deleted_history {
+            server = server.with_deleted_history(deleted_history);
+        }
+        if let Some(max_file_size) = self.max_file_size {
+            server = server.with_max_file_size(max_file_size);
+        }
+        if let Some(max_chunk_size) = self.max_chunk_size {
+            server = server.with_max_chunk_size(max_chunk_size);
+        }
+        if let Some(max_message_ttl) = self.max_message_ttl {
+            server = server.with_max_message_ttl(max_message_ttl);
+        }
+        if let Some(rename_grace) = self.rename_grace {
+            server = server.with_rename_grace(rename_grace);
+        }
+        if let Some(dedup_capacity) = self.dedup_capacity {
+            server = server.with_dedup_capacity(dedup_capacity);
+        }
+        if let Some(dedup_ttl) = self.dedup_ttl {
+            server = server.with_dedup_ttl(dedup_ttl);
+        }
+        if let Some(plain_auth) = self.plain_auth {
+            server = server.with_plain_auth(plain_auth);
+        }
+        if let Some(offline_queue) = self.offline_queue {
+            server = server.with_offline_queue(offline_queue);
+        }
+        if let Some(offline_ttl) = self.offline_ttl {
+            server = server.with_offline_ttl(offline_ttl);
+        }
+        if let Some(conceal_users) = self.conceal_users {
+            server = server.with_conceal_users(conceal_users);
+        }
+        if let Some(listen_only) = self.listen_only {
+            server = server.with_listen_only(listen_only);
+        }
+        if let Some(offline_webhooks) = self.offline_webhooks {
+            server = server.with_offline_webhooks(offline_webhooks);
+        }
+        if let Some(flood_limits) = self.flood_limits {
+            server = server.with_flood_limits(flood_limits);
+        }
+        if let Some(schedule_limits) = self.schedule_limits {
+            server = server.with_schedule_limits(schedule_limits);
+        }
+        if let Some(violation_limits) = self.violation_limits {
+            server = server.with_violation_limits(violation_limits);
+        }
+        if let Some(auth_limits) = self.auth_limits {
+            server = server.with_auth_limits(auth_limits);
+        }
+        if let Some(protocol_versions) = self.protocol_versions {
+            server = server.with_protocol_versions(protocol_versions);
+        }
+        if let Some(integrity) = self.integrity {
+            server = server.with_integrity(integrity);
+        }
+        if let Some(max_integrity_failures) = self.max_integrity_failures {
+            server = server.with_max_integrity_failures(max_integrity_failures);
+        }
+        if let Some(server_name) = self.server_name {
+            server = server.with_server_name(server_name);
+        }
+        server = server.with_default_language(self.default_language);
+        if let Some(presence) = self.presence {
+            server = server.with_presence(Some(presence));
+        }
+        for plugin in self.plugins {
+            server = server.with_plugin(plugin);
+        }
+        if let Some(journal_writer) = self.journal_writer {
+            server = server.with_journal_writer(journal_writer);
+        }
+        if let Some(journal_limits) = self.journal_limits {
+            server = server.with_journal_limits(journal_limits);
+        }
+        if let Some(data_dir) = &data_dir {
+            server = server
+                .with_data_dir(data_dir.path().to_path_buf())
+                .with_control_socket(self.control_socket);
+        }
+        if self.http_bridge {
+            server = server.with_http_bridge(Some(SocketAddr::from(([127, 0, 0, 1], 0))));
+        }
+        let clock = Arc::new(ManualClock::default());
+        server = server.with_clock(clock.clone());
+
+        let (listener, connector) = memory::network();
+        let accept_faults = AcceptFaults::default();
+        let listener = FaultyListener {
+            inner: listener,
+            faults: accept_faults.clone(),
+        };
+        let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(DEFAULT_LOG_FILTER));
+        let dispatch = tracing::Dispatch::new(Registry::default().with(filter));
+
+        let mut shared_state = SharedState::new();
+        shared_state.set_log_control(LogControl::new(filter_handle));
+        if let Some(access_presets) = self.access_presets {
+            shared_state.set_access_presets(access_presets);
+        }
+        // in place before the server starts, so slots taken right away belong to the limiter it uses
+        if let Some(auth_limits) = self.auth_limits {
+            shared_state.set_auth_limits(auth_limits);
+        }
+        let mut load_cleanup = Cleanup::default();
+        if let Some(users_file) = &self.users_file {
+            (_, load_cleanup) = shared_state
+                .load_users(users_file)
+                .expect("Could not load the users file");
+        }
+        let shared_state = Arc::new(RwLock::new(shared_state));
+
+        let state = Arc::clone(&shared_state);
+        let (summary_tx, summary) = watch::channel(None);
+        let handle = tokio::spawn(async move {
+            match server.serve_on(listener, state).await {
+                Ok(summary) => {
+                    summary_tx.send(Some(summary)).ok();
+                }
+                Err(e) => tracing::error!("Test server error: {}", e),
+            }
+        });
+
+        Ok(TestServer {
+            connector,
+            accept_faults,
+            shared_state,
+            handle,
+            summary,
+            clock,
+            load_cleanup,
+            _dispatch: dispatch,
+            _data_dir: data_dir,
+        })
+    }
+}
+
+impl Listener for FaultyListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, SocketAddr)> {
+        loop {
+            if let Some(error) = self.faults.errors.lock().unwrap().pop_front() {
+                return Err(error);
+            }
+            // faults added while waiting go before a connection that arrived at the same time
+            tokio::select! {
+                biased;
+                _ = self.faults.added.notified() => continue,
+                accepted = self.inner.accept() => return accepted,
+            }
+        }
+    }
+}
+
+impl Connector for FaultyConnector {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> io::Result<Self::Stream> {
+        let (server_reader, server_writer) = tokio::io::split(self.inner.connect().await?);
+        let (client, relay) = tokio::io::duplex(LINK_BUFFER_SIZE);
+        let (client_reader, client_writer) = tokio::io::split(relay);
+        tokio::spawn(pump(client_reader, server_writer, Arc::clone(&self.faults.to_server)));
+        tokio::spawn(pump(server_reader, client_writer, Arc::clone(&self.faults.to_client)));
+        Ok(client)
+    }
+}
+
+// what is read while dropping is gone, the other side never learns it was sent
+async fn pump(mut from: ReadHalf<DuplexStream>, mut to: WriteHalf<DuplexStream>, dropping: Arc<AtomicBool>) {
+    let mut buffer = vec![0; LINK_BUFFER_SIZE];
+    loop {
+        match from.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) if dropping.load(Ordering::SeqCst) => {}
+            Ok(read) => {
+                if to.write_all(&buffer[..read]).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    to.shutdown().await.ok();
+}
+
+impl TestJournal {
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    pub fn lines(&self, kind: JournalKind) -> Vec<String> {
+        let lines = match kind {
+            JournalKind::Audit => &self.audit,
+            JournalKind::Metrics => &self.metrics,
+        };
+        lines.lock().unwrap().clone()
+    }
+
+    pub fn failed_writes(&self) -> u64 {
+        self.failed_writes.load(Ordering::SeqCst)
+    }
+}
+
+impl JournalWriter for TestJournal {
+    fn write(&self, kind: JournalKind, lines: &[String]) -> io::Result<()> {
+        if self.failing.load(Ordering::SeqCst) {
+            self.failed_writes.fetch_add(1, Ordering::SeqCst);
+            return Err(io::Error::other("No space left on device"));
+        }
+        let written = match kind {
+            JournalKind::Audit => &self.audit,
+            JournalKind::Metrics => &self.metrics,
+        };
+        written.lock().unwrap().extend_from_slice(lines);
+        Ok(())
+    }
+}
+
+impl LinkFaults {
+    // switch these while the connection is quiet, a frame cut in half is garbage to the other side
+    pub fn drop_to_server(&self, dropping: bool) {
+        self.to_server.store(dropping, Ordering::SeqCst);
+    }
+
+    pub fn drop_to_client(&self, dropping: bool) {
+        self.to_client.store(dropping, Ordering::SeqCst);
+    }
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    pub fn start() -> Self {
+        Self::builder().start()
+    }
+
+    // an empty directory under the temp dir, the process id keeps test binaries out of each other's way
+    pub fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat_rs_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).expect("Could not create the scratch dir");
+        dir
+    }
+
+    pub fn connector(&self) -> MemoryConnector {
+        self.connector.clone()
+    }
+
+    pub async fn client(&self) -> TestClient {
+        self.client_with(ClientOptions::new()).await
+    }
+
+    pub async fn client_with(&self, options: ClientOptions) -> TestClient {
+        Self::client_through(self.connector(), options).await
+    }
+
+    // a client whose connections lose what the returned faults say, both directions work until switched
+    pub async fn faulty_client(&self, options: ClientOptions) -> (TestClient, LinkFaults) {
+        let faults = LinkFaults::default();
+        let connector = FaultyConnector {
+            inner: self.connector(),
+            faults: faults.clone(),
+        };
+        (Self::client_through(connector, options).await, faults)
+    }
+
+    // a client whose connections go through the chaos, the control calms it down or cuts them
+    pub async fn chaos_client(&self, chaos: Chaos, options: ClientOptions) -> (TestClient, ChaosControl) {
+        let connector = ChaosConnector::new(self.connector(), chaos);
+        let control = connector.control();
+        (Self::client_through(connector, options).await, control)
+    }
+
+    async fn client_through<C: Connector>(connector: C, options: ClientOptions) -> TestClient {
+        let options = options.with_reconnect_interval(1);
+        let (client, events) = ChatClient::connect_with(connector, options)
+            .await
+            .expect("Could not connect to the test server");
+
+        let mut client = TestClient { client, events };
+        client.expect(|event| matches!(event, ClientEvent::Connected)).await;
+        client
+            .expect(|event| matches!(event, ClientEvent::ServerCapabilities(_)))
+            .await;
+        client
+    }
+
+    // consumes the server hello so tests start at the first response
+    pub async fn raw_connection(&self) -> RawConnection {
+        let mut connection = self.unchecked_connection();
+        let hello = connection.receive().await;
+        assert!(
+            hello.is(MessageType::ServerHello),
+            "Expected a server hello, got {:?}",
+            hello
+        );
+        connection
+    }
+
+    // registers the user with the password "secret", the auth success is consumed
+    pub async fn logged_in(&self, username: &str) -> RawConnection {
+        self.authenticated(Message::auth_create(username, "secret")).await
+    }
+
+    // like logged_in, for a user that already exists
+    pub async fn login(&self, username: &str) -> RawConnection {
+        self.authenticated(Message::auth(username, "secret")).await
+    }
+
+    async fn authenticated(&self, auth: Message) -> RawConnection {
+        let mut connection = self.raw_connection().await;
+        connection.send(auth).await;
+        let reply = connection.receive().await;
+        assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+        connection
+    }
+
+    // leaves the first frame to the test, a draining server answers with a busy notice instead of a hello
+    pub fn unchecked_connection(&self) -> RawConnection {
+        Self::raw_connection_with(&self.connector)
+    }
+
+    // like unchecked_connection, but the server sees the peer at another address
+    pub fn unchecked_connection_from(&self, address: IpAddr) -> RawConnection {
+        Self::raw_connection_with(&self.connector().with_address(address))
+    }
+
+    fn raw_connection_with(connector: &MemoryConnector) -> RawConnection {
+        let stream = connector.connect_now().expect("Could not connect to the test server");
+        let (reader, writer) = tokio::io::split(stream);
+
+        RawConnection {
+            reader,
+            writer,
+            integrity: Integrity::default(),
+        }
+    }
+
+    // pause the runtime with `start_paused` to move the timers along with it
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    // the server sees these from its next accepts, like a real listener out of file descriptors
+    pub fn fail_accepts(&self, errors: impl IntoIterator<Item = io::Error>) {
+        self.accept_faults.errors.lock().unwrap().extend(errors);
+        self.accept_faults.added.notify_one();
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    // what a SIGTERM does to a real server
+    pub async fn signal(&self) {
+        stop_server(Arc::clone(&self.shared_state), ShutdownReason::Signal).await;
+    }
+
+    // waits for the server to stop
+    pub async fn summary(&self) -> ShutdownSummary {
+        let mut summary = self.summary.clone();
+        let stopped = within(summary.wait_for(Option::is_some))
+            .await
+            .expect("The test server failed instead of stopping")
+            .clone();
+        stopped.unwrap()
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.shared_state.read().await.sessions().len()
+    }
+
+    pub async fn is_logged_in(&self, username: &str) -> bool {
+        self.shared_state
+            .read()
+            .await
+            .get_session_by_user(username)
+            .await
+            .is_some()
+    }
+
+    // the last connection of the user is gone but the server has not cleaned up after it yet
+    pub async fn close_current_channel(&self, username: &str) {
+        let state = self.shared_state.read().await;
+        let session = state
+            .get_session_by_user(username)
+            .await
+            .expect("The user is not logged in");
+        let (tx, _) = mpsc::unbounded_channel();
+        session.write().await.set_channel(tx);
+    }
+
+    pub async fn wedge_session(&self, username: &str) -> SessionWedge {
+        let state = self.shared_state.read().await;
+        let session = state
+            .get_session_by_user(username)
+            .await
+            .expect("The user is not logged in");
+        SessionWedge {
+            _guard: session.write_owned().await,
+        }
+    }
+
+    // the connection ends after whatever was already handed to it, as if the network went away
+    pub async fn drop_connection(&self, username: &str) {
+        let state = self.shared_state.read().await;
+        let session = state
+            .get_session_by_user(username)
+            .await
+            .expect("The user is not logged in");
+        session.read().await.send(Message::BREAK).ok();
+    }
+
+    // the user still points at the session, but the session table has lost it, as if a logout died halfway
+    pub async fn lose_session(&self, username: &str) {
+        let mut state = self.shared_state.write().await;
+        let id = state
+            .get_user(username)
+            .and_then(|user| user.session_id())
+            .expect("The user is not logged in");
+        state.sessions.remove(&id);
+    }
+
+    // the user record is gone, but the name index, sessions and queues still refer to it
+    pub async fn forget_user(&self, username: &str) {
+        let mut state = self.shared_state.write().await;
+        let id = state.user_id(username).expect("Unknown user");
+        state.users.remove(&id);
+    }
+
+    // the session is still logged in as the user, the user does not know about it
+    pub async fn unlink_session(&self, username: &str) {
+        let mut state = self.shared_state.write().await;
+        let user = state.user_mut(username).expect("Unknown user");
+        assert!(user.session_id().is_some(), "The user is not logged in");
+        user.remove_session_id();
+    }
+
+    pub fn load_cleanup(&self) -> Cleanup {
+        self.load_cleanup
+    }
+
+    pub async fn export_users(&self) -> String {
+        self.shared_state.read().await.export_users().to_string()
+    }
+
+    // loads a document over the running state, the way a restart would load it over what was left behind
+    pub async fn reload_users(&self, document: &str, mode: ImportMode) -> Result<Cleanup, String> {
+        let (_, cleanup) = self.shared_state.write().await.import_users(document, mode)?;
+        Ok(cleanup)
+    }
+
+    pub async fn queued_messages(&self) -> u64 {
+        self.shared_state.read().await.queued_messages()
+    }
+
+    pub async fn create_admin(&self, username: &str, password: &str) {
+        self.create_user(username, password, AccessLevel::Admin).await;
+    }
+
+    // a client logged in as a new admin named admin
+    pub async fn admin(&self) -> TestClient {
+        self.create_admin("admin", "secret").await;
+        let mut admin = self.client().await;
+        admin.login("admin", "secret").await;
+        admin
+    }
+
+    pub async fn create_user(&self, username: &str, password: &str, access_level: AccessLevel) {
+        let (hash, auth_key) = hash_credentials(username, password).expect("Could not hash password");
+        let mut user = User::new(username, hash);
+        user.set_auth_key(Some(auth_key));
+        user.set_access_level(access_level);
+
+        self.shared_state.write().await.add_user(user);
+    }
+
+    pub async fn set_permission_overrides(&self, username: &str, granted: Permissions, revoked: Permissions) {
+        let updated = self
+            .shared_state
+            .write()
+            .await
+            .set_permission_overrides(username, granted, revoked);
+        assert!(updated, "Unknown user {}", username);
+    }
+
+    pub async fn log_filter(&self) -> String {
+        self.shared_state
+            .read()
+            .await
+            .log_control()
+            .map(LogControl::filter)
+            .unwrap_or_default()
+    }
+
+    // what ServerDebugLog writes to the log
+    pub async fn debug_state(&self) -> String {
+        format!("{:#?}", self.shared_state.read().await)
+    }
+
+    pub async fn password_checks(&self) -> u64 {
+        self.shared_state.read().await.password_checks()
+    }
+
+    // every argon2 slot is taken until the permit is dropped, logins queue behind it
+    pub async fn occupy_auth_slots(&self) -> tokio::sync::OwnedSemaphorePermit {
+        let limiter = self.shared_state.read().await.auth_limiter();
+        limiter.occupy().await
+    }
+
+    pub async fn audit_entries(&self) -> Vec<AuditEntry> {
+        self.shared_state.read().await.audit_log().entries().cloned().collect()
+    }
+
+    // bound once the server started, waits for that
+    pub async fn http_address(&self) -> SocketAddr {
+        eventually(|| async { self.shared_state.read().await.http_bridge_addr().is_some() }).await;
+        self.shared_state.read().await.http_bridge_addr().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl TestClient {
+    // for servers that listen on a real port, like the ones an Application starts
+    pub async fn connect_tcp(port: u16) -> Self {
+        TestServer::client_through(TcpConnector::new("127.0.0.1", port), ClientOptions::new()).await
+    }
+
+    pub fn client(&self) -> &ChatClient {
+        &self.client
+    }
+
+    pub async fn next_event(&mut self) -> ClientEvent {
+        within(self.events.recv()).await.expect("Client event stream closed")
+    }
+
+    pub async fn expect<F: Fn(&ClientEvent) -> bool>(&mut self, predicate: F) -> ClientEvent {
+        loop {
+            let event = self.next_event().await;
+            if predicate(&event) {
+                return event;
+            }
+        }
+    }
+
+    pub async fn register(&mut self, username: &str, password: &str) {
+        self.client.create_account(username, password).await;
+        self.expect_authenticated().await;
+    }
+
+    pub async fn login(&mut self, username: &str, password: &str) {
+        self.client.login(username, password).await;
+        self.expect_authenticated().await;
+    }
+
+    async fn expect_authenticated(&mut self) {
+        let event = self
+            .expect(|event| matches!(event, ClientEvent::Authenticated | ClientEvent::AuthFailed(_)))
+            .await;
+        if let ClientEvent::AuthFailed(error) = event {
+            panic!("Authentication failed: {}", error);
+        }
+    }
+
+    pub async fn disconnect(mut self) {
+        self.client.disconnect().await;
+        self.expect(|event| matches!(event, ClientEvent::Closed)).await;
+    }
+}
+
+impl RawConnection {
+    pub async fn send(&mut self, message: Message) {
+        self.try_send(message)
+            .await
+            .expect("Could not send message to the test server");
+    }
+
+    // the frame that answers the message, whatever it is
+    pub async fn request(&mut self, message: Message) -> Message {
+        self.send(message).await;
+        self.receive().await
+    }
+
+    // the ack of the message, any other answer fails the test
+    pub async fn send_acked(&mut self, message: Message) -> Message {
+        let ack = self.request(message).await;
+        assert!(ack.is(MessageType::Ack), "Expected an ACK, got {:?}", ack);
+        ack
+    }
+
+    // a direct message the server has to accept, returns the id it assigned
+    pub async fn send_direct(&mut self, recipient: &str, body: &str) -> MessageId {
+        let ack = self.send_acked(Message::direct_message_send(recipient, body)).await;
+        ack.message_id().expect("The ACK carried no message id")
+    }
+
+    // like send_direct with a client id, the ack has to name it even after a lost one
+    pub async fn send_direct_with_id(&mut self, recipient: &str, body: &str, client_id: &str) -> Message {
+        let ack = self
+            .send_acked(Message::direct_message_send_with_id(recipient, body, 0, client_id))
+            .await;
+        assert_eq!(ack.payload().str_field(1), Ok(client_id));
+        ack
+    }
+
+    pub async fn send_bytes(&mut self, bytes: &[u8]) {
+        self.writer
+            .write_all(bytes)
+            .await
+            .expect("Could not send bytes to the test server");
+    }
+
+    pub async fn try_send(&mut self, message: Message) -> Result<(), String> {
+        message.send_using(&mut self.writer, self.integrity).await
+    }
+
+    pub fn set_integrity(&mut self, integrity: Integrity) {
+        self.integrity = integrity;
+    }
+
+    pub async fn receive(&mut self) -> Message {
+        within(async {
+            loop {
+                match Message::read_header_start(&mut self.reader).await {
+                    Ok(true) => break,
+                    Ok(false) => continue,
+                    Err(e) => panic!("Connection closed: {}", e),
+                }
+            }
+            Message::receive_using(&mut self.reader, VersionRange::default(), self.integrity)
+                .await
+                .expect("Could not receive message from the test server")
+        })
+        .await
+    }
+
+    // like receive, but waits as long as the caller lets it and leaves a closed connection to the caller
+    pub async fn try_receive(&mut self) -> Result<Message, String> {
+        loop {
+            match Message::read_header_start(&mut self.reader).await {
+                Ok(true) => break,
+                Ok(false) => continue,
+                Err(e) => return Err(format!("Connection closed: {}", e)),
+            }
+        }
+        Message::receive_using(&mut self.reader, VersionRange::default(), self.integrity)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn expect_closed(&mut self) {
+        let result = within(Message::read_header_start(&mut self.reader)).await;
+        assert!(
+            result.is_err(),
+            "Expected the connection to be closed, got {:?}",
+            result
+        );
+    }
+}
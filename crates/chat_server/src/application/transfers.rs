@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+pub const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+pub const MAX_CHUNK_SIZE: u64 = 32 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferState {
+    Offered,
+    Accepted,
+}
+
+// the server only keeps bookkeeping, chunks are relayed as they arrive and never stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    sender: Uuid,
+    recipient: Uuid,
+    size: u64,
+    received: u64,
+    next_chunk: u64,
+    state: TransferState,
+}
+
+#[derive(Debug)]
+pub struct FileTransfers {
+    transfers: HashMap<u64, Transfer>,
+    next_id: u64,
+    max_file_size: u64,
+    max_chunk_size: u64,
+}
+
+impl Transfer {
+    pub fn sender(&self) -> Uuid {
+        self.sender
+    }
+
+    pub fn recipient(&self) -> Uuid {
+        self.recipient
+    }
+
+    // the session on the other end of the transfer
+    pub fn peer_of(&self, session: Uuid) -> Option<Uuid> {
+        match session {
+            _ if session == self.sender => Some(self.recipient),
+            _ if session == self.recipient => Some(self.sender),
+            _ => None,
+        }
+    }
+}
+
+impl FileTransfers {
+    pub fn set_limits(&mut self, max_file_size: u64, max_chunk_size: u64) {
+        self.max_file_size = max_file_size;
+        self.max_chunk_size = max_chunk_size;
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn offer(&mut self, id: u64, sender: Uuid, recipient: Uuid, size: u64) -> Result<(), String> {
+        if size > self.max_file_size {
+            return Err(format!("File is too large ({} > {} bytes)", size, self.max_file_size));
+        }
+
+        self.transfers.insert(
+            id,
+            Transfer {
+                sender,
+                recipient,
+                size,
+                received: 0,
+                next_chunk: 0,
+                state: TransferState::Offered,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Transfer> {
+        self.transfers.get(&id)
+    }
+
+    pub fn accept(&mut self, id: u64, session: Uuid) -> Result<&Transfer, String> {
+        let transfer = self
+            .transfers
+            .get_mut(&id)
+            .filter(|transfer| transfer.recipient == session && transfer.state == TransferState::Offered)
+            .ok_or_else(|| format!("No pending offer {}", id))?;
+        transfer.state = TransferState::Accepted;
+        Ok(transfer)
+    }
+
+    // chunks have to arrive in order and stay within both limits
+    pub fn chunk(&mut self, id: u64, session: Uuid, index: u64, length: u64) -> Result<&Transfer, String> {
+        let max_chunk_size = self.max_chunk_size;
+        let transfer = self
+            .transfers
+            .get_mut(&id)
+            .filter(|transfer| transfer.sender == session && transfer.state == TransferState::Accepted)
+            .ok_or_else(|| format!("No running transfer {}", id))?;
+
+        if index != transfer.next_chunk {
+            return Err(format!("Expected chunk {}, got {}", transfer.next_chunk, index));
+        }
+        if length > max_chunk_size {
+            return Err(format!("Chunk is too large ({} > {} bytes)", length, max_chunk_size));
+        }
+        if transfer.received + length > transfer.size {
+            return Err("Chunks exceed the offered size".into());
+        }
+
+        transfer.received += length;
+        transfer.next_chunk += 1;
+        Ok(transfer)
+    }
+
+    pub fn complete(&mut self, id: u64, session: Uuid) -> Result<Transfer, String> {
+        match self.transfers.get(&id) {
+            Some(transfer) if transfer.sender == session && transfer.state == TransferState::Accepted => {
+                if transfer.received != transfer.size {
+                    return Err(format!(
+                        "Transfer ended after {} of {} bytes",
+                        transfer.received, transfer.size
+                    ));
+                }
+                Ok(self.transfers.remove(&id).unwrap())
+            }
+            _ => Err(format!("No running transfer {}", id)),
+        }
+    }
+
+    // every transfer with the sessions at both ends
+    pub fn ends(&self) -> Vec<(u64, Uuid, Uuid)> {
+        self.transfers
+            .iter()
+            .map(|(id, transfer)| (*id, transfer.sender, transfer.recipient))
+            .collect()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Transfer> {
+        self.transfers.remove(&id)
+    }
+
+    // every transfer the session takes part in, with the id of the other end
+    pub fn remove_session(&mut self, session: Uuid) -> Vec<(u64, Uuid)> {
+        let ids: Vec<(u64, Uuid)> = self
+            .transfers
+            .iter()
+            .filter_map(|(id, transfer)| transfer.peer_of(session).map(|peer| (*id, peer)))
+            .collect();
+        for (id, _) in &ids {
+            self.transfers.remove(id);
+        }
+        ids
+    }
+}
+
+impl Default for FileTransfers {
+    fn default() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            next_id: 1,
+            max_file_size: MAX_FILE_SIZE,
+            max_chunk_size: MAX_CHUNK_SIZE,
+        }
+    }
+}
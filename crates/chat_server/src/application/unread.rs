@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+
+// messages a user has not read yet, per conversation partner
+#[derive(Debug, Default)]
+pub struct UnreadCounters {
+    counts: HashMap<String, BTreeMap<String, u64>>,
+}
+
+impl UnreadCounters {
+    pub fn increment(&mut self, user: &str, peer: &str) {
+        *self
+            .counts
+            .entry(user.to_string())
+            .or_default()
+            .entry(peer.to_string())
+            .or_default() += 1;
+    }
+
+    // for queued messages that expired before they were delivered
+    pub fn decrement(&mut self, user: &str, peer: &str) {
+        let Some(peers) = self.counts.get_mut(user) else {
+            return;
+        };
+        if let Some(count) = peers.get_mut(peer) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                peers.remove(peer);
+            }
+        }
+        if peers.is_empty() {
+            self.counts.remove(user);
+        }
+    }
+
+    // both as the reader and as the conversation partner, counts for the same peer are added up
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(peers) = self.counts.remove(old) {
+            self.counts.insert(new.to_string(), peers);
+        }
+        for peers in self.counts.values_mut() {
+            if let Some(count) = peers.remove(old) {
+                *peers.entry(new.to_string()).or_default() += count;
+            }
+        }
+    }
+
+    // true if there was anything to clear
+    pub fn clear(&mut self, user: &str, peer: &str) -> bool {
+        let Some(peers) = self.counts.get_mut(user) else {
+            return false;
+        };
+        let cleared = peers.remove(peer).is_some();
+        if peers.is_empty() {
+            self.counts.remove(user);
+        }
+        cleared
+    }
+
+    // drops counts where either side is not a known user, returns how many went
+    pub fn retain_users(&mut self, known: impl Fn(&str) -> bool) -> usize {
+        let mut removed = 0;
+        self.counts.retain(|user, peers| {
+            if !known(user) {
+                removed += peers.len();
+                return false;
+            }
+            let before = peers.len();
+            peers.retain(|peer, _| known(peer));
+            removed += before - peers.len();
+            !peers.is_empty()
+        });
+        removed
+    }
+
+    // every user with a peer they have unread messages from
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.counts
+            .iter()
+            .flat_map(|(user, peers)| peers.keys().map(move |peer| (user.as_str(), peer.as_str())))
+    }
+
+    // sorted by peer, conversations without unread messages are left out
+    pub fn summary(&self, user: &str) -> Vec<(String, u64)> {
+        self.counts
+            .get(user)
+            .map(|peers| peers.iter().map(|(peer, count)| (peer.clone(), *count)).collect())
+            .unwrap_or_default()
+    }
+}
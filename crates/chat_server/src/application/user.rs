@@ -1,33 +1,117 @@
+use std::fmt;
+
+use chat_core::protocol::SIGNING_KEY_LENGTH;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use super::session::AccessLevel;
+use super::{
+    permissions::{AccessPresets, Permissions, TemporaryGrant},
+    preferences::Preferences,
+    session::AccessLevel,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct User {
+    // stays the same across renames, everything else refers to the user by it
+    id: Uuid,
     name: String,
     pw_hash: String,
+    // answers login challenges, None for accounts that have not logged in with a password since it was introduced
+    auth_key: Option<String>,
     access_level: AccessLevel,
+    // applied on top of the access level preset, revocations win
+    granted: Permissions,
+    revoked: Permissions,
+    preferences: Preferences,
+    // other users verify this user's messages with it, the server never checks a signature itself
+    signing_key: Option<[u8; SIGNING_KEY_LENGTH]>,
+    // everything above is exported, this only means something while the server runs
     session_id: Option<Uuid>,
+    temporary_grants: Vec<TemporaryGrant>,
+}
+
+// names differing only in case belong to the same account, "Alice" cannot register next to "alice"
+pub fn fold_username(name: &str) -> String {
+    name.to_lowercase()
+}
+
+// the same rules for new accounts and renames
+pub fn validate_username(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Username must not be empty".into());
+    }
+    Ok(())
+}
+
+// the state is logged whole on request, the hash and the key stay out of it since the key answers challenges
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("pw_hash", &"..")
+            .field("auth_key", &self.auth_key.as_ref().map(|_| ".."))
+            .field("access_level", &self.access_level)
+            .field("granted", &self.granted)
+            .field("revoked", &self.revoked)
+            .field("preferences", &self.preferences)
+            .field("signing_key", &self.signing_key)
+            .field("session_id", &self.session_id)
+            .field("temporary_grants", &self.temporary_grants)
+            .finish()
+    }
 }
 
 impl User {
     pub fn new(name: &str, pw_hash: String) -> Self {
         Self {
+            id: Uuid::new_v4(),
             name: name.to_string(),
             pw_hash,
+            auth_key: None,
             access_level: AccessLevel::User,
+            granted: Permissions::NONE,
+            revoked: Permissions::NONE,
+            preferences: Preferences::default(),
+            signing_key: None,
             session_id: None,
+            temporary_grants: Vec::new(),
         }
     }
 
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
     pub fn pw_hash(&self) -> &str {
         &self.pw_hash
     }
 
+    pub fn set_pw_hash(&mut self, pw_hash: String) {
+        self.pw_hash = pw_hash;
+    }
+
+    pub fn auth_key(&self) -> Option<&str> {
+        self.auth_key.as_deref()
+    }
+
+    pub fn set_auth_key(&mut self, auth_key: Option<String>) {
+        self.auth_key = auth_key;
+    }
+
     pub fn access_level(&self) -> &AccessLevel {
         &self.access_level
     }
@@ -36,6 +120,76 @@ impl User {
         self.access_level = access_level;
     }
 
+    pub fn set_permission_overrides(&mut self, granted: Permissions, revoked: Permissions) {
+        self.granted = granted;
+        self.revoked = revoked;
+    }
+
+    pub fn granted(&self) -> Permissions {
+        self.granted
+    }
+
+    pub fn revoked(&self) -> Permissions {
+        self.revoked
+    }
+
+    pub fn permissions(&self, presets: &AccessPresets) -> Permissions {
+        (presets.permissions(&self.access_level) | self.granted).without(self.revoked)
+    }
+
+    // what the grants that have not lapsed add, revocations still win
+    pub fn temporary_permissions(&self, now: DateTime<Utc>) -> Permissions {
+        self.temporary_grants
+            .iter()
+            .filter(|grant| grant.expires_at > now)
+            .fold(Permissions::NONE, |permissions, grant| permissions | grant.permission)
+            .without(self.revoked)
+    }
+
+    pub fn temporary_grants(&self) -> &[TemporaryGrant] {
+        &self.temporary_grants
+    }
+
+    // a second grant of the same permission replaces the first, with its own expiry
+    pub fn grant_temporary(&mut self, grant: TemporaryGrant) {
+        self.temporary_grants
+            .retain(|existing| existing.permission != grant.permission);
+        self.temporary_grants.push(grant);
+    }
+
+    pub fn revoke_temporary(&mut self, permission: Permissions) -> Option<TemporaryGrant> {
+        let index = self
+            .temporary_grants
+            .iter()
+            .position(|grant| grant.permission == permission)?;
+        Some(self.temporary_grants.remove(index))
+    }
+
+    pub fn take_expired_grants(&mut self, now: DateTime<Utc>) -> Vec<TemporaryGrant> {
+        let (expired, live) = self
+            .temporary_grants
+            .drain(..)
+            .partition(|grant| grant.expires_at <= now);
+        self.temporary_grants = live;
+        expired
+    }
+
+    pub fn preferences(&self) -> &Preferences {
+        &self.preferences
+    }
+
+    pub fn preferences_mut(&mut self) -> &mut Preferences {
+        &mut self.preferences
+    }
+
+    pub fn signing_key(&self) -> Option<&[u8; SIGNING_KEY_LENGTH]> {
+        self.signing_key.as_ref()
+    }
+
+    pub fn set_signing_key(&mut self, signing_key: Option<[u8; SIGNING_KEY_LENGTH]>) {
+        self.signing_key = signing_key;
+    }
+
     pub fn session_id(&self) -> Option<Uuid> {
         self.session_id
     }
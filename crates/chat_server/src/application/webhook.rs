@@ -0,0 +1,153 @@
+use std::{sync::Arc, time::Duration};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+use super::{
+    offline::OfflineNotice,
+    plugin::{Dispatch, PluginContext, PluginFuture, ServerPlugin},
+    webhook_url::WebhookUrl,
+    ArcRwLock, SharedState,
+};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+// tries per offline notice, the wait before the next one doubles every time
+const WEBHOOK_ATTEMPTS: u32 = 3;
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_millis(250);
+// the status line is all that is read of the answer
+const MAX_RESPONSE_HEAD: usize = 1024;
+
+// posts every direct message a user sends as json, {"sender", "recipient", "body"}
+#[derive(Debug, Clone)]
+pub struct WebhookPlugin {
+    url: WebhookUrl,
+}
+
+impl WebhookPlugin {
+    pub fn new(url: WebhookUrl) -> Self {
+        Self { url }
+    }
+
+    async fn post(url: &WebhookUrl, body: String) -> Result<(), String> {
+        let mut stream = TcpStream::connect((url.host(), url.port()))
+            .await
+            .map_err(|e| e.to_string())?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            url.path(),
+            url.host(),
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+
+        let mut head = vec![0; MAX_RESPONSE_HEAD];
+        let read = stream.read(&mut head).await.map_err(|e| e.to_string())?;
+        let head = String::from_utf8_lossy(&head[..read]);
+        match head.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            Some(status) => Err(format!("answered {}", status)),
+            None => Err("no http answer".to_string()),
+        }
+    }
+}
+
+impl ServerPlugin for WebhookPlugin {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    // after the handler, a message that was refused for its format never gets posted
+    fn on_message<'a>(
+        &'a self,
+        context: &'a PluginContext,
+        session: Uuid,
+        message: &'a Message,
+        dispatch: Dispatch,
+    ) -> PluginFuture<'a> {
+        Box::pin(async move {
+            if dispatch != Dispatch::After || !message.is(MessageType::DirectMessageSend) {
+                return Ok(());
+            }
+            let payload = message.payload();
+            let (recipient, body) = (payload.str_field(0)?, payload.str_field(1)?);
+            let Some(sender) = context.user_of(session).await else {
+                return Ok(());
+            };
+            let json = JsonValue::object()
+                .with("sender", sender)
+                .with("recipient", recipient)
+                .with("body", body)
+                .to_string();
+
+            // the sender's session does not wait on the endpoint, failures only show up in the log
+            let url = self.url.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(WEBHOOK_TIMEOUT, Self::post(&url, json)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::warn!("Webhook {} failed: {}", url, e),
+                    Err(_) => tracing::warn!("Webhook {} did not answer within {:?}", url, WEBHOOK_TIMEOUT),
+                }
+            });
+            Ok(())
+        })
+    }
+}
+
+// every notice is posted on its own, an endpoint that is down holds up no other
+pub async fn deliver_offline_notices(
+    mut notices: mpsc::UnboundedReceiver<OfflineNotice>,
+    shared_state: ArcRwLock<SharedState>,
+) {
+    while let Some(notice) = notices.recv().await {
+        let shared_state = Arc::clone(&shared_state);
+        tokio::spawn(async move {
+            let delivered = post_notice(&notice).await;
+            shared_state.write().await.record_webhook(delivered);
+        });
+    }
+}
+
+// the url may carry a token, only its host is logged
+async fn post_notice(notice: &OfflineNotice) -> bool {
+    let json = notice.to_json();
+    let mut delay = WEBHOOK_RETRY_DELAY;
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        let result = match tokio::time::timeout(WEBHOOK_TIMEOUT, WebhookPlugin::post(&notice.url, json.clone())).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("no answer within {:?}", WEBHOOK_TIMEOUT)),
+        };
+        match result {
+            Ok(()) => {
+                tracing::debug!("Told {} about a message for {}", notice.url.host(), notice.recipient);
+                return true;
+            }
+            Err(e) if attempt < WEBHOOK_ATTEMPTS => {
+                tracing::debug!(
+                    "Webhook at {} failed, retrying in {:?}: {}",
+                    notice.url.host(),
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => tracing::warn!(
+                "Gave up on the webhook at {} for {} after {} attempts: {}",
+                notice.url.host(),
+                notice.recipient,
+                WEBHOOK_ATTEMPTS,
+                e
+            ),
+        }
+    }
+    false
+}
@@ -1,15 +1,91 @@
-use std::error::Error;
+use std::{error::Error, path::Path};
 
-mod application;
+use chat_core::json::JsonValue;
+use chat_server::application::{self, ImportMode};
+
+const USAGE: &str = "Usage: server [export-users <path> | import-users <path> [--merge | --replace] | ctl [--tenant <name>] [--as <user>] <command> [args...]]";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "ctl") {
+        return run_ctl(&args[1..]).await;
+    }
+    if !args.is_empty() {
+        return run_command(&args);
+    }
+
     let app = application::Application::new()?;
 
-    if let Err(e) = app.run().await {
-        tracing::error!("Application error: {}", e);
-        return Err(e);
+    let summary = match app.run().await {
+        Ok(summary) => summary,
+        Err(e) => {
+            tracing::error!("Application error: {}", e);
+            return Err(e);
+        }
+    };
+
+    // the last event of every run, a supervisor reads the reason from here and from the exit code
+    tracing::info!(
+        reason = summary.reason.as_str(),
+        error = summary.reason.error(),
+        exit_code = summary.exit_code(),
+        uptime_secs = summary.uptime.as_secs(),
+        peak_sessions = summary.peak_sessions,
+        messages_relayed = summary.messages_relayed,
+        "Server stopped"
+    );
+    drop(app);
+    std::process::exit(summary.exit_code());
+}
+
+// the user database tools never start the listener
+fn run_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let users_file = application::users_file();
+
+    match args {
+        [command, path] if command == "export-users" => {
+            let count = application::export_users_file(users_file.as_deref(), Path::new(path))?;
+            println!("Exported {} users to {}", count, path);
+        }
+        [command, path, options @ ..] if command == "import-users" => {
+            let mode = match options {
+                [] => ImportMode::Merge,
+                [option] if option == "--merge" => ImportMode::Merge,
+                [option] if option == "--replace" => ImportMode::Replace,
+                _ => return Err(USAGE.into()),
+            };
+            let users_file = users_file.ok_or("import-users needs USERS_FILE to know which database to update")?;
+            // a running server would overwrite the import with its own copy
+            let _data_dir = application::DataDir::open(&application::data_dir_path())?;
+            let count = application::import_users_file(&users_file, Path::new(path), mode)?;
+            println!("Imported {} users into {}", count, users_file.display());
+        }
+        _ => return Err(USAGE.into()),
     }
 
     Ok(())
 }
+
+// one command to the running server, the answer goes to stdout and a refusal ends in a failing exit code
+async fn run_ctl(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (data_dir, args) = match args {
+        [option, name, args @ ..] if option == "--tenant" => (application::tenant_data_dir_path(name)?, args),
+        args => (application::data_dir_path(), args),
+    };
+    // without --as the command runs as the server itself, with an admin's permissions and no account
+    let (user, command) = match args {
+        [option, user, command @ ..] if option == "--as" => (Some(user.as_str()), command),
+        command => (None, command),
+    };
+    if command.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let response = application::send_command(&data_dir, user, command).await?;
+    println!("{}", response);
+    if response.get("ok") != Some(&JsonValue::Bool(true)) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
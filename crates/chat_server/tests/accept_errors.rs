@@ -0,0 +1,51 @@
+use std::{io, time::Duration};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(20);
+
+fn out_of_file_descriptors() -> io::Error {
+    io::Error::from_raw_os_error(libc::EMFILE)
+}
+
+async fn accept_errors(admin: &mut TestClient) -> u64 {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats { accept_errors, .. } = event else {
+        unreachable!();
+    };
+    accept_errors
+}
+
+#[tokio::test]
+async fn transient_accept_errors_are_counted_and_survived() {
+    let server = TestServer::builder().with_accept_backoff(ACCEPT_BACKOFF).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    server.fail_accepts([
+        out_of_file_descriptors(),
+        io::Error::from(io::ErrorKind::ConnectionAborted),
+        io::Error::from(io::ErrorKind::Interrupted),
+    ]);
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    assert!(server.is_running());
+    assert_eq!(accept_errors(&mut admin).await, 3);
+}
+
+#[tokio::test]
+async fn a_broken_listener_stops_the_server() {
+    let server = TestServer::start();
+
+    server.fail_accepts([io::Error::from_raw_os_error(libc::EBADF)]);
+
+    eventually(|| async { !server.is_running() }).await;
+}
@@ -0,0 +1,113 @@
+use chat_client::client::{AccessLevel, ClientEvent, CommandError, ConnectionState};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::TestServer;
+
+#[tokio::test]
+async fn commands_need_a_login_first() {
+    let server = TestServer::start();
+    let alice = server.client().await;
+
+    assert_eq!(alice.client().access_level().await, Some(AccessLevel::Guest));
+    assert_eq!(
+        alice.client().check_command("msg").await,
+        Err(CommandError::InvalidState {
+            command: "msg".to_string(),
+            state: ConnectionState::Connected,
+        })
+    );
+    assert_eq!(
+        alice
+            .client()
+            .check_command("shutdown")
+            .await
+            .map_err(|e| e.to_string()),
+        Err("Cannot use 'shutdown': you must log in first".to_string())
+    );
+    assert_eq!(alice.client().check_command("auth").await, Ok(()));
+    assert_eq!(alice.client().check_command("new").await, Ok(()));
+}
+
+#[tokio::test]
+async fn auth_success_carries_the_access_level() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    let success = connection.receive().await;
+    assert!(success.is(MessageType::AuthSuccess));
+    assert_eq!(success.payload().str_field(1), Ok("user"));
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    assert_eq!(admin.client().access_level().await, Some(AccessLevel::Admin));
+}
+
+#[tokio::test]
+async fn privileged_commands_are_refused_by_level() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    assert_eq!(alice.client().access_level().await, Some(AccessLevel::User));
+    assert_eq!(alice.client().check_command("msg").await, Ok(()));
+    assert_eq!(
+        alice.client().check_command("shutdown").await,
+        Err(CommandError::Forbidden {
+            command: "shutdown".to_string(),
+            problem: "admin only, you are logged in as user".to_string(),
+        })
+    );
+    assert_eq!(
+        alice.client().check_command("kick").await.map_err(|e| e.to_string()),
+        Err("Cannot use 'kick': moderator only, you are logged in as user".to_string())
+    );
+}
+
+#[tokio::test]
+async fn server_nacks_name_the_rejected_command() {
+    let server = TestServer::start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::server_shutdown(5)).await;
+    let nack = connection.receive().await;
+    assert!(nack.is(MessageType::Nack));
+    assert_eq!(nack.rejected_type(), Some(MessageType::ServerShutdown));
+
+    // skipping the local check, the server still has the last word
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send(Message::server_shutdown(5)).await;
+    assert_eq!(
+        alice
+            .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+            .await,
+        ClientEvent::Rejected {
+            command: Some("shutdown".to_string()),
+            reason: "admin only, you are logged in as user".to_string(),
+        }
+    );
+    assert!(server.is_running());
+}
+
+#[tokio::test]
+async fn level_changes_reach_the_local_checks() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    admin
+        .client()
+        .send(Message::admin_set_access_level("alice", "moderator"))
+        .await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+        .await;
+
+    assert_eq!(alice.client().access_level().await, Some(AccessLevel::Moderator));
+    assert_eq!(alice.client().check_command("kick").await, Ok(()));
+    assert!(alice.client().check_command("shutdown").await.is_err());
+}
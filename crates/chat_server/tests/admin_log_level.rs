@@ -0,0 +1,104 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageBuilder, MessageType};
+use chat_server::application::testing::{eventually, TestServer, DEFAULT_LOG_FILTER};
+
+const HANDLES_FILTER: &str = "info,chat_server::application::handles=trace";
+
+#[tokio::test]
+async fn admin_changes_log_filter_and_it_reverts() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    assert!(
+        admin
+            .client()
+            .send(Message::admin_set_log_level(HANDLES_FILTER, Some(1)))
+            .await
+    );
+
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::LogLevelChanged { .. }))
+        .await;
+    let ClientEvent::LogLevelChanged { previous, current } = event else {
+        unreachable!();
+    };
+    assert_eq!(previous, DEFAULT_LOG_FILTER);
+    assert!(current.contains("chat_server::application::handles=trace"));
+    assert_eq!(server.log_filter().await, current);
+
+    eventually(|| async { server.log_filter().await == DEFAULT_LOG_FILTER }).await;
+
+    let entries = server.audit_entries().await;
+    let actions: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.actor(), entry.action())).collect();
+    assert_eq!(actions, [("admin", "set_log_level"), ("server", "revert_log_level")]);
+    assert!(entries[0].detail().contains("reverts in 1 seconds"));
+}
+
+#[tokio::test]
+async fn later_change_cancels_pending_revert() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    admin
+        .client()
+        .send(Message::admin_set_log_level("debug", Some(1)))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::LogLevelChanged { .. }))
+        .await;
+
+    admin.client().send(Message::admin_set_log_level("warn", None)).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::LogLevelChanged { .. }))
+        .await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert_eq!(server.log_filter().await, "warn");
+}
+
+#[tokio::test]
+async fn non_admin_cannot_change_log_filter() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().send(Message::admin_set_log_level("trace", None)).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    assert_eq!(server.log_filter().await, DEFAULT_LOG_FILTER);
+    assert!(server.audit_entries().await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_log_filter_is_rejected() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    admin.client().send(Message::admin_set_log_level("info,[=", None)).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    let malformed = MessageBuilder::new(MessageType::AdminSetLogLevel)
+        .with_field(b"debug".to_vec())
+        .with_field(vec![1, 2])
+        .build();
+    admin.client().send(malformed).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    assert_eq!(server.log_filter().await, DEFAULT_LOG_FILTER);
+}
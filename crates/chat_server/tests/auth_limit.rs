@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    auth::{challenge_response, derive_key},
+    protocol::{ErrorCode, Message, MessageType},
+};
+use chat_server::application::testing::{within, AuthLimits, TakeoverPolicy, TestClient, TestServer};
+
+const LIMITS: AuthLimits = AuthLimits {
+    concurrency: 1,
+    queue_timeout: Duration::from_secs(1),
+};
+
+async fn auth_queue(admin: &mut TestClient) -> (u64, u64) {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats {
+        auth_queue_depth,
+        auth_rejected,
+        ..
+    } = event
+    else {
+        unreachable!();
+    };
+    (auth_queue_depth, auth_rejected)
+}
+
+#[tokio::test]
+async fn a_login_that_waits_too_long_is_told_when_to_retry() {
+    let server = TestServer::builder().with_auth_limits(LIMITS).start();
+    let mut admin = server.admin().await;
+    let slots = server.occupy_auth_slots().await;
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    let busy = alice.receive().await;
+    assert!(busy.is(MessageType::AuthFailure), "Unexpected {:?}", busy);
+    assert_eq!(busy.payload().field(1), Ok([ErrorCode::ServerBusy as u8].as_slice()));
+    assert_eq!(busy.auth_retry_after(), Some(1));
+    assert_eq!(auth_queue(&mut admin).await, (0, 1));
+
+    // nothing was created, the same registration goes through once there is room
+    drop(slots);
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+}
+
+#[tokio::test]
+async fn waiting_logins_show_up_in_the_stats() {
+    let server = TestServer::builder()
+        .with_auth_limits(AuthLimits {
+            queue_timeout: Duration::from_secs(30),
+            ..LIMITS
+        })
+        .start();
+    let mut admin = server.admin().await;
+    let slots = server.occupy_auth_slots().await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth_create("bob", "secret")).await;
+    // the heartbeat and the stats are answered while the login waits
+    let depth = within(async {
+        loop {
+            let depth = auth_queue(&mut admin).await;
+            if depth.0 > 0 {
+                return depth;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert_eq!(depth, (1, 0));
+
+    drop(slots);
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert_eq!(auth_queue(&mut admin).await, (0, 0));
+}
+
+#[tokio::test]
+async fn the_client_sends_its_login_again_after_the_hinted_delay() {
+    let server = TestServer::builder().with_auth_limits(LIMITS).start();
+    let slots = server.occupy_auth_slots().await;
+
+    let mut carol = server.client().await;
+    carol.client().create_account("carol", "secret").await;
+    let event = carol
+        .expect(|event| matches!(event, ClientEvent::ServerBusy { .. }))
+        .await;
+    assert!(
+        matches!(event, ClientEvent::ServerBusy { retry_after: 1, .. }),
+        "{:?}",
+        event
+    );
+
+    drop(slots);
+    carol.expect(|event| matches!(event, ClientEvent::Authenticated)).await;
+}
+
+#[tokio::test]
+async fn renames_wait_in_the_queue_without_holding_up_the_server() {
+    let server = TestServer::builder()
+        .with_auth_limits(AuthLimits {
+            queue_timeout: Duration::from_secs(30),
+            ..LIMITS
+        })
+        .with_takeover_policy(TakeoverPolicy::Replace { same_peer: false })
+        .start();
+    let mut admin = server.admin().await;
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    let slots = server.occupy_auth_slots().await;
+
+    alice.send(Message::rename_account("alicia", "secret")).await;
+    within(async {
+        while auth_queue(&mut admin).await.0 == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    // the password check and the key for the new name both took their turn
+    drop(slots);
+    let renamed = alice.receive().await;
+    assert!(renamed.is(MessageType::UserRenamed), "Unexpected {:?}", renamed);
+    assert_eq!(auth_queue(&mut admin).await, (0, 0));
+
+    let mut connection = server.unchecked_connection();
+    let nonce = connection.receive().await.payload().str_field(1).unwrap().to_string();
+    let key = derive_key("alicia", "secret").unwrap();
+    connection
+        .send(Message::auth_challenge(
+            "alicia",
+            &challenge_response(&key, &nonce).unwrap(),
+        ))
+        .await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+}
@@ -0,0 +1,79 @@
+use chat_core::{
+    auth::{challenge_response, derive_key, PLAIN_LOGIN_REQUIRED},
+    protocol::{Message, MessageType},
+};
+use chat_server::application::testing::{AccessLevel, RawConnection, TestServer};
+
+async fn failure(connection: &mut RawConnection, message: Message) -> String {
+    connection.send(message).await;
+    let response = connection.receive().await;
+    assert!(response.is(MessageType::AuthFailure), "Unexpected {:?}", response);
+    response.payload().str_field(0).unwrap().to_string()
+}
+
+// how many argon2 runs a request caused
+async fn checks_for(server: &TestServer, connection: &mut RawConnection, message: Message) -> (u64, String) {
+    let before = server.password_checks().await;
+    let error = failure(connection, message).await;
+    (server.password_checks().await - before, error)
+}
+
+#[tokio::test]
+async fn unknown_users_cost_the_same_as_wrong_passwords() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut connection = server.raw_connection().await;
+
+    let known = checks_for(&server, &mut connection, Message::auth("alice", "wrong")).await;
+    let unknown = checks_for(&server, &mut connection, Message::auth("nobody", "wrong")).await;
+
+    assert_eq!(known, (1, "Invalid username or password".to_string()));
+    assert_eq!(unknown, known);
+}
+
+// the reply to a challenge, on a fresh connection with its own nonce
+async fn challenge_reply(server: &TestServer, username: &str, password: &str) -> Vec<u8> {
+    let mut connection = server.unchecked_connection();
+    let nonce = connection.receive().await.payload().str_field(1).unwrap().to_string();
+    let key = derive_key(username, password).unwrap();
+    connection
+        .send(Message::auth_challenge(
+            username,
+            &challenge_response(&key, &nonce).unwrap(),
+        ))
+        .await;
+    let response = connection.receive().await;
+    assert!(response.is(MessageType::AuthFailure), "Unexpected {:?}", response);
+    response.to_bytes()
+}
+
+#[tokio::test]
+async fn challenges_for_unknown_users_fail_like_wrong_responses() {
+    for plain_auth in [true, false] {
+        let server = TestServer::builder().with_plain_auth(plain_auth).start();
+        server.create_user("alice", "secret", AccessLevel::User).await;
+
+        let unknown = challenge_reply(&server, "nobody", "secret").await;
+        let wrong = challenge_reply(&server, "alice", "wrong").await;
+
+        assert_eq!(unknown, wrong);
+        assert_ne!(
+            Message::from_bytes(&unknown).unwrap().payload().str_field(0),
+            Ok(PLAIN_LOGIN_REQUIRED)
+        );
+    }
+}
+
+#[tokio::test]
+async fn taken_names_are_hashed_before_they_are_refused() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut connection = server.raw_connection().await;
+
+    let taken = checks_for(&server, &mut connection, Message::auth_create("alice", "secret")).await;
+    assert_eq!(taken, (1, "User already exists".to_string()));
+
+    // an invalid name is refused before any work
+    let invalid = checks_for(&server, &mut connection, Message::auth_create("", "secret")).await;
+    assert_eq!(invalid.0, 0);
+}
@@ -0,0 +1,96 @@
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions, CommandError, ConnectionState};
+use chat_core::capability::{self, Capabilities};
+use chat_server::application::testing::TestServer;
+
+#[tokio::test]
+async fn hello_advertises_server_capabilities() {
+    let server = TestServer::start();
+    let alice = server.client().await;
+
+    let capabilities = alice.client().capabilities().await.expect("hello was received");
+    assert!(capabilities.supports(capability::DIRECT_MESSAGES));
+    assert!(capabilities.supports(capability::ADMIN_LOG_LEVEL));
+    assert!(capabilities.supports(capability::ADMIN_SERVER_MODE));
+    assert!(!capabilities.supports(capability::ROOMS));
+
+    assert!(alice.client().server_supports(capability::DIRECT_MESSAGES).await);
+    assert!(!alice.client().server_supports(capability::ROOMS).await);
+}
+
+#[tokio::test]
+async fn unsupported_commands_are_rejected_locally() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let error = alice.client().check_command("join").await.unwrap_err();
+    assert_eq!(
+        error,
+        CommandError::Unsupported {
+            command: "join".to_string(),
+            capability: capability::ROOMS,
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "The server does not support 'rooms', 'join' is not available"
+    );
+
+    assert_eq!(alice.client().check_command("msg").await, Ok(()));
+    assert_eq!(alice.client().check_command("auth").await, Ok(()));
+}
+
+#[tokio::test]
+async fn disabled_capabilities_gate_their_commands() {
+    let capabilities = Capabilities::new().with(capability::ADMIN_LOG_LEVEL);
+    let server = TestServer::builder().with_capabilities(capabilities).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    assert!(admin.client().check_command("msg").await.is_err());
+    assert!(admin.client().check_command("send").await.is_err());
+    assert_eq!(admin.client().check_command("loglevel").await, Ok(()));
+}
+
+#[tokio::test]
+async fn capabilities_are_reported_as_an_event() {
+    let server = TestServer::start();
+    let (_client, mut events) = ChatClient::connect_with(server.connector(), ClientOptions::new())
+        .await
+        .unwrap();
+
+    assert_eq!(events.recv().await, Some(ClientEvent::Connected));
+    assert_eq!(
+        events.recv().await,
+        Some(ClientEvent::StateChanged {
+            from: ConnectionState::Connecting,
+            to: ConnectionState::Connected,
+        })
+    );
+    assert_eq!(
+        events.recv().await,
+        Some(ClientEvent::ServerCapabilities(vec![
+            capability::ADMIN_LOG_LEVEL.to_string(),
+            capability::ADMIN_SERVER_MODE.to_string(),
+            capability::DIRECT_MESSAGES.to_string(),
+            capability::EPHEMERAL_MESSAGES.to_string(),
+            capability::FILE_TRANSFER.to_string(),
+            capability::HISTORY.to_string(),
+            capability::INTEGRITY_XXH3.to_string(),
+            capability::LANGUAGES.to_string(),
+            capability::MESSAGE_EDIT.to_string(),
+            capability::MESSAGE_SIGNING.to_string(),
+            capability::MODERATION.to_string(),
+            capability::MUTING.to_string(),
+            capability::OFFLINE_SYNC.to_string(),
+            capability::PREFERENCE_BATCH.to_string(),
+            capability::PREFERENCES.to_string(),
+            capability::PRESENCE_SUBSCRIPTIONS.to_string(),
+            capability::SCHEDULED_MESSAGES.to_string(),
+            capability::SEARCH.to_string(),
+            capability::TEMPORARY_GRANTS.to_string(),
+            capability::UNREAD.to_string(),
+        ]))
+    );
+}
@@ -0,0 +1,137 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chat_client::client::{ClientEvent, ClientOptions, SendStatus};
+use chat_core::{
+    protocol::Message,
+    transport::{
+        chaos::{Chaos, ChaosConnector, ChaosStats, Faults},
+        memory, Connector, Listener,
+    },
+};
+use chat_server::application::testing::{AccessLevel, FloodLimits, TestServer, ViolationLimits};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const MESSAGES: usize = 40;
+// a soak that has not settled by then is stuck, not slow
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(60);
+// how long the chaos runs once everything was sent
+const STORM: Duration = Duration::from_secs(2);
+
+// what reaches the other end when these frames are written in one go
+async fn through(chaos: Chaos, frames: &[Vec<u8>]) -> Vec<u8> {
+    let (mut listener, connector) = memory::network();
+    let mut client = ChaosConnector::new(connector, chaos).connect().await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    client.write_all(&frames.concat()).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).await.unwrap();
+    received
+}
+
+#[tokio::test]
+async fn the_seed_decides_which_frames_are_hit() {
+    let frames: Vec<Vec<u8>> = (0..50)
+        .map(|n| Message::direct_message_send("bob", &format!("message {}", n)).to_bytes())
+        .collect();
+    let chaos = |seed| Chaos::new(seed).with_to_server(Faults::new().with_corruption(0.2).with_reordering(0.2));
+
+    let first = through(chaos(7), &frames).await;
+    assert_eq!(first.len(), frames.concat().len());
+    assert_ne!(first, frames.concat());
+    assert_eq!(through(chaos(7), &frames).await, first);
+    assert_ne!(through(chaos(8), &frames).await, first);
+
+    // without faults the relay is invisible
+    assert_eq!(through(Chaos::new(7), &frames).await, frames.concat());
+}
+
+// alice sends through the chaos to bob on a clean connection, then the network calms down and
+// reconnects once, after which every message has to have arrived exactly once and left alice's outbox
+async fn soak(chaos: Chaos) -> ChaosStats {
+    let server = TestServer::builder()
+        .with_flood_limits(FloodLimits {
+            frames_per_second: 10_000,
+            ..FloodLimits::default()
+        })
+        // everyone shares the memory network's address, a banned alice would take bob with her
+        .with_violation_limits(ViolationLimits {
+            threshold: 0,
+            ..ViolationLimits::default()
+        })
+        .start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    // every message has to fit, a full outbox would refuse the last few
+    let options = ClientOptions::new().with_outbox_capacity(MESSAGES);
+    let (mut alice, control) = server.chaos_client(chaos, options).await;
+    // the answer may never come through, the client logs in again after each reconnect
+    alice.client().login("alice", "secret").await;
+    let sent: Vec<String> = (0..MESSAGES).map(|n| format!("message {}", n)).collect();
+    for body in &sent {
+        let status = alice.client().send_direct_message("bob", body).await;
+        assert_ne!(status, SendStatus::OutboxFull);
+    }
+
+    let mut received: BTreeMap<String, usize> = BTreeMap::new();
+    let settled = async {
+        let storm = tokio::time::sleep(STORM);
+        tokio::pin!(storm);
+        loop {
+            tokio::select! {
+                () = &mut storm, if !control.is_calm() => {
+                    control.calm();
+                    control.cut();
+                }
+                event = bob.next_event() => {
+                    if let ClientEvent::DirectMessage { body, .. } = event {
+                        *received.entry(body).or_default() += 1;
+                    }
+                }
+                // alice's events are only drained, what counts is her outbox
+                _ = alice.next_event() => {}
+                // quiet stretches still get the outbox checked
+                () = tokio::time::sleep(Duration::from_millis(50)) => {}
+            }
+            if control.is_calm() && received.len() == MESSAGES && alice.client().outbox().await.is_empty() {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(SETTLE_TIMEOUT, settled)
+        .await
+        .unwrap_or_else(|_| panic!("Not settled, bob has {} of {}", received.len(), MESSAGES));
+
+    // a late duplicate would show up right after
+    while let Ok(event) = tokio::time::timeout(Duration::from_millis(300), bob.next_event()).await {
+        if let ClientEvent::DirectMessage { body, .. } = event {
+            *received.entry(body).or_default() += 1;
+        }
+    }
+    let expected: BTreeMap<String, usize> = sent.into_iter().map(|body| (body, 1)).collect();
+    assert_eq!(received, expected);
+    assert!(server.is_running());
+    control.stats()
+}
+
+#[tokio::test]
+async fn flaky_wifi_delivers_everything_once() {
+    let stats = soak(Chaos::flaky_wifi(1)).await;
+    assert!(stats.connections > 1, "{:?}", stats);
+    assert!(stats.partial_writes > 0 && stats.stalls > 0, "{:?}", stats);
+}
+
+#[tokio::test]
+async fn a_slow_server_delivers_everything_once() {
+    let stats = soak(Chaos::slow_server(2)).await;
+    assert!(stats.partial_writes > 0 && stats.stalls > 0, "{:?}", stats);
+}
+
+#[tokio::test]
+async fn a_corrupting_middlebox_delivers_everything_once() {
+    let stats = soak(Chaos::corrupting_middlebox(3)).await;
+    assert!(stats.corrupted > 0 && stats.reordered > 0, "{:?}", stats);
+}
@@ -0,0 +1,350 @@
+use std::fs;
+
+use chat_server::application::{testing::TestServer, ServerConfig};
+
+fn config(dir: &std::path::Path, vars: &[(&str, &str)]) -> ServerConfig {
+    let data_dir = dir.join("data").display().to_string();
+    ServerConfig::from_vars(
+        [("DATA_DIR", data_dir.as_str())]
+            .into_iter()
+            .chain(vars.iter().copied()),
+    )
+}
+
+fn problems(config: &ServerConfig) -> Vec<String> {
+    config.validate().unwrap_err().problems().to_vec()
+}
+
+#[test]
+fn defaults_are_valid() {
+    let dir = TestServer::scratch_dir("defaults");
+    assert_eq!(config(&dir, &[]).validate(), Ok(()));
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let dir = TestServer::scratch_dir("every_problem");
+    let config = config(
+        &dir,
+        &[
+            ("PORT", "70000"),
+            ("HEARTBEAT_INTERVAL", "10"),
+            ("HEARTBEAT_GRACE", "10"),
+            ("WRITE_TIMEOUT", "0"),
+            ("SESSION_TAKEOVER", "maybe"),
+            ("MAX_CHUNK_SIZE", "1000000"),
+            ("MAX_FILE_SIZE", "lots"),
+            ("UNRELATED", "ignored"),
+        ],
+    );
+
+    assert_eq!(
+        problems(&config),
+        [
+            "PORT: expected a port between 1 and 65535, got '70000'",
+            "SESSION_TAKEOVER: Unknown takeover policy 'maybe', expected reject, replace, replace-same-peer or allow",
+            "MAX_FILE_SIZE: expected bytes, got 'lots'",
+            "HEARTBEAT_GRACE: must be shorter than HEARTBEAT_INTERVAL (10s), got 10s",
+            "WRITE_TIMEOUT: must be at least 1 second",
+            "MAX_CHUNK_SIZE: must be at most 65536 bytes, got 1000000",
+        ]
+    );
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.starts_with("Invalid configuration:\n  PORT: "), "{}", error);
+}
+
+#[test]
+fn zero_values_are_refused() {
+    let dir = TestServer::scratch_dir("zero");
+    let config = config(
+        &dir,
+        &[
+            ("PORT", "0"),
+            ("HEARTBEAT_INTERVAL", "0"),
+            ("HANDSHAKE_TIMEOUT", "0"),
+            ("MAX_FILE_SIZE", "0"),
+            ("MAX_CHUNK_SIZE", "0"),
+            ("FLOOD_MAX_FRAMES", "0"),
+            ("FLOOD_MAX_BYTES", "0"),
+            ("FLOOD_COOLDOWN", "0"),
+            ("AUTH_CONCURRENCY", "0"),
+            ("AUTH_QUEUE_TIMEOUT", "0"),
+            ("OFFLINE_TTL", "0"),
+            ("OLDEST_PROTOCOL_VERSION", "0"),
+            ("SERVER_NAME", "  "),
+        ],
+    );
+
+    assert_eq!(
+        problems(&config),
+        [
+            "PORT: expected a port between 1 and 65535, got '0'",
+            "HEARTBEAT_INTERVAL: must be at least 1 second",
+            "HANDSHAKE_TIMEOUT: must be at least 1 second",
+            "OFFLINE_TTL: must be at least 1 second",
+            "FLOOD_COOLDOWN: must be at least 1 second",
+            "AUTH_QUEUE_TIMEOUT: must be at least 1 second",
+            "AUTH_CONCURRENCY: must be at least 1",
+            "FLOOD_MAX_FRAMES: must be at least 1 frame per second",
+            "FLOOD_MAX_BYTES: must be at least 65536 bytes per second, got 0",
+            "OLDEST_PROTOCOL_VERSION: must be between 1 and 1, got 0",
+            "SERVER_NAME: must not be empty",
+            "MAX_FILE_SIZE: must be at least 1 byte",
+            "MAX_CHUNK_SIZE: must be at least 1 byte",
+        ]
+    );
+}
+
+#[test]
+fn referenced_files_must_be_readable_and_valid() {
+    let dir = TestServer::scratch_dir("files");
+    fs::create_dir_all(dir.join("data")).unwrap();
+    fs::write(dir.join("data").join("users.json"), "{\"users\": []}").unwrap();
+    let presets = dir.join("presets.conf");
+    fs::write(&presets, "owner = all").unwrap();
+    let missing = dir.join("missing.conf");
+
+    let invalid = config(
+        &dir,
+        &[
+            ("USERS_FILE", "users.json"),
+            ("ACCESS_PRESETS_FILE", presets.to_str().unwrap()),
+        ],
+    );
+    assert_eq!(
+        problems(&invalid),
+        [
+            format!(
+                "ACCESS_PRESETS_FILE: {}: Line 1: Unknown access level 'owner', expected guest, user, moderator or admin",
+                presets.display()
+            ),
+            format!(
+                "USERS_FILE: {}: Missing export version",
+                dir.join("data").join("users.json").display()
+            ),
+        ]
+    );
+
+    let unreadable = config(&dir, &[("ACCESS_PRESETS_FILE", missing.to_str().unwrap())]);
+    let problems = problems(&unreadable);
+    assert_eq!(problems.len(), 1);
+    assert!(
+        problems[0].starts_with(&format!("ACCESS_PRESETS_FILE: could not read {}", missing.display())),
+        "{:?}",
+        problems
+    );
+}
+
+#[test]
+fn the_webhook_url_must_be_plain_http() {
+    let dir = TestServer::scratch_dir("webhook");
+    let invalid = config(&dir, &[("WEBHOOK_URL", "https://relay.example.com/hook")]);
+    assert_eq!(
+        problems(&invalid),
+        ["WEBHOOK_URL: expected an http:// url, got 'https://relay.example.com/hook'"]
+    );
+
+    let valid = config(&dir, &[("WEBHOOK_URL", "http://relay:8080/hook?token=abc")]);
+    assert_eq!(valid.validate(), Ok(()));
+    assert!(
+        valid.summary().ends_with(" WEBHOOK_URL=http://relay:8080/hook"),
+        "{}",
+        valid.summary()
+    );
+}
+
+#[test]
+fn webhook_bodies_need_webhook_domains() {
+    let dir = TestServer::scratch_dir("webhook_domains");
+    let invalid = config(&dir, &[("WEBHOOK_BODIES", "on"), ("WEBHOOK_DOMAINS", ",")]);
+    assert_eq!(
+        problems(&invalid),
+        [
+            "WEBHOOK_DOMAINS: expected a list of domains, got ','",
+            "WEBHOOK_BODIES: requires WEBHOOK_DOMAINS",
+        ]
+    );
+
+    let valid = config(
+        &dir,
+        &[
+            ("WEBHOOK_DOMAINS", "hooks.example.com, relay.local"),
+            ("WEBHOOK_BODIES", "on"),
+        ],
+    );
+    assert_eq!(valid.validate(), Ok(()));
+    assert!(
+        valid
+            .summary()
+            .contains(" WEBHOOK_DOMAINS=hooks.example.com,relay.local WEBHOOK_BODIES=on "),
+        "{}",
+        valid.summary()
+    );
+}
+
+#[test]
+fn the_data_dir_must_be_writable() {
+    let dir = TestServer::scratch_dir("data_dir");
+    // a file where the directory should be
+    let blocked = dir.join("blocked");
+    fs::write(&blocked, "").unwrap();
+
+    let config = ServerConfig::from_vars([("DATA_DIR", blocked.to_str().unwrap())]);
+    let problems = problems(&config);
+    assert_eq!(problems.len(), 1);
+    assert!(
+        problems[0].starts_with(&format!("DATA_DIR: {} is not writable", blocked.display())),
+        "{:?}",
+        problems
+    );
+}
+
+#[test]
+fn the_summary_lists_effective_values() {
+    let dir = TestServer::scratch_dir("summary");
+    let config = config(&dir, &[("HEARTBEAT_GRACE", "5"), ("SESSION_MAX_AGE", "0")]);
+
+    let summary = config.summary();
+    assert!(summary.starts_with("PORT=42423 DATA_DIR="), "{}", summary);
+    assert!(summary.contains(" SERVER_NAME=chat_rs "), "{}", summary);
+    assert!(summary.contains(" USERS_FILE=none "), "{}", summary);
+    assert!(summary.contains(" SESSION_MAX_AGE=never "), "{}", summary);
+    assert!(
+        summary.contains(" HEARTBEAT_INTERVAL=30s HEARTBEAT_GRACE=5s "),
+        "{}",
+        summary
+    );
+    assert!(summary.contains(" SESSION_TAKEOVER=reject "), "{}", summary);
+}
+
+#[test]
+fn concealing_users_needs_the_offline_queue_off() {
+    let dir = TestServer::scratch_dir("conceal");
+
+    assert_eq!(
+        problems(&config(&dir, &[("CONCEAL_USERS", "on")])),
+        ["CONCEAL_USERS: requires OFFLINE_QUEUE=off"]
+    );
+    assert_eq!(
+        config(&dir, &[("CONCEAL_USERS", "on"), ("OFFLINE_QUEUE", "off")]).validate(),
+        Ok(())
+    );
+}
+
+#[test]
+fn the_default_language_needs_a_catalog() {
+    let dir = TestServer::scratch_dir("language");
+    let languages = dir.join("data").join("lang");
+    fs::create_dir_all(&languages).unwrap();
+
+    assert_eq!(config(&dir, &[("DEFAULT_LANGUAGE", "en")]).validate(), Ok(()));
+    assert_eq!(
+        problems(&config(&dir, &[("DEFAULT_LANGUAGE", "de")])),
+        [format!(
+            "DEFAULT_LANGUAGE: no catalog for 'de' in {}",
+            languages.display()
+        )]
+    );
+
+    fs::write(languages.join("de.toml"), "invalid_credentials = Falsch\n").unwrap();
+    assert_eq!(
+        problems(&config(&dir, &[("DEFAULT_LANGUAGE", "de")])),
+        [format!(
+            "DATA_DIR: {}: Line 1: expected a quoted text",
+            languages.join("de.toml").display()
+        )]
+    );
+
+    fs::write(languages.join("de.toml"), "invalid_credentials = \"Falsch\"\n").unwrap();
+    assert_eq!(config(&dir, &[("DEFAULT_LANGUAGE", "de")]).validate(), Ok(()));
+}
+
+#[test]
+fn the_frame_integrity_is_a_list_of_known_algorithms() {
+    let dir = TestServer::scratch_dir("integrity");
+
+    let summary = config(&dir, &[]).summary();
+    assert!(
+        summary.contains(" FRAME_INTEGRITY=crc32,xxh3 MAX_INTEGRITY_FAILURES=3 "),
+        "{}",
+        summary
+    );
+    let summary = config(
+        &dir,
+        &[("FRAME_INTEGRITY", "xxh3,none"), ("MAX_INTEGRITY_FAILURES", "0")],
+    )
+    .summary();
+    assert!(
+        summary.contains(" FRAME_INTEGRITY=xxh3,none MAX_INTEGRITY_FAILURES=0 "),
+        "{}",
+        summary
+    );
+
+    assert_eq!(
+        problems(&config(&dir, &[("FRAME_INTEGRITY", "crc32,md5")])),
+        ["FRAME_INTEGRITY: expected a list of crc32, xxh3 and none, got 'crc32,md5'"]
+    );
+}
+
+#[test]
+fn tenants_inherit_the_process_settings_and_override_their_own() {
+    let dir = TestServer::scratch_dir("tenants");
+    let config = config(
+        &dir,
+        &[
+            ("TENANTS", "acme, blue-sky"),
+            ("SERVER_NAME", "shared"),
+            ("TENANT_ACME_PORT", "5001"),
+            ("TENANT_BLUE_SKY_PORT", "5002"),
+            ("TENANT_BLUE_SKY_SERVER_NAME", "blue"),
+        ],
+    );
+    assert_eq!(config.validate(), Ok(()));
+
+    let names: Vec<&str> = config.tenants().iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["acme", "blue-sky"]);
+    let acme = config.tenant("acme").unwrap();
+    let blue = config.tenant("blue-sky").unwrap();
+    assert_eq!(acme.data_dir(), dir.join("data").join("acme"));
+    assert_eq!(blue.data_dir(), dir.join("data").join("blue-sky"));
+    assert!(acme.summary().contains("PORT=5001 "), "{}", acme.summary());
+    assert!(acme.summary().contains(" SERVER_NAME=shared "), "{}", acme.summary());
+    assert!(blue.summary().contains(" SERVER_NAME=blue "), "{}", blue.summary());
+    assert!(
+        config.summary().contains(" TENANTS=acme,blue-sky"),
+        "{}",
+        config.summary()
+    );
+    assert!(config.tenant("other").is_none());
+}
+
+#[test]
+fn tenants_may_not_share_a_port_or_use_odd_names() {
+    let dir = TestServer::scratch_dir("tenant_clash");
+    let config = config(&dir, &[("TENANTS", "a,b,Bad_Name,a"), ("TENANT_B_WRITE_TIMEOUT", "0")]);
+    let problems = problems(&config);
+    assert!(
+        problems
+            .iter()
+            .any(|problem| problem.starts_with("TENANTS: 'Bad_Name' is not a tenant name")),
+        "{:?}",
+        problems
+    );
+    assert!(
+        problems.contains(&"TENANTS: 'a' is listed twice".to_string()),
+        "{:?}",
+        problems
+    );
+    assert!(
+        problems.contains(&"TENANTS: a and b both listen on port 42423".to_string()),
+        "{:?}",
+        problems
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|problem| problem.starts_with("tenant b: WRITE_TIMEOUT")),
+        "{:?}",
+        problems
+    );
+}
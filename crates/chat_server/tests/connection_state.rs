@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use chat_client::client::{ClientEvent, CommandError, ConnectionState};
+use chat_server::application::testing::TestServer;
+
+fn changed_to(state: ConnectionState) -> impl Fn(&ClientEvent) -> bool {
+    move |event| matches!(event, ClientEvent::StateChanged { to, .. } if *to == state)
+}
+
+#[tokio::test]
+async fn login_walks_through_the_states() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    assert_eq!(alice.client().connection_state().await, ConnectionState::Connected);
+
+    alice.client().create_account("alice", "secret").await;
+    assert_eq!(
+        alice.expect(changed_to(ConnectionState::Authenticating)).await,
+        ClientEvent::StateChanged {
+            from: ConnectionState::Connected,
+            to: ConnectionState::Authenticating,
+        }
+    );
+    alice.expect(changed_to(ConnectionState::Ready)).await;
+    assert!(alice.client().is_authenticated().await);
+
+    alice.client().disconnect().await;
+    alice.expect(changed_to(ConnectionState::Draining)).await;
+    assert_eq!(
+        alice.client().check_command("history").await,
+        Err(CommandError::InvalidState {
+            command: "history".to_string(),
+            state: ConnectionState::Draining,
+        })
+    );
+    alice.expect(changed_to(ConnectionState::Disconnected)).await;
+    alice.expect(|event| matches!(event, ClientEvent::Closed)).await;
+}
+
+#[tokio::test]
+async fn an_expired_session_reauthenticates() {
+    let server = TestServer::builder()
+        .with_max_session_age(Some(Duration::from_millis(300)))
+        .start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.expect(changed_to(ConnectionState::Reauthenticating)).await;
+    assert_eq!(
+        alice.expect(changed_to(ConnectionState::Ready)).await,
+        ClientEvent::StateChanged {
+            from: ConnectionState::Reauthenticating,
+            to: ConnectionState::Ready,
+        }
+    );
+}
+
+#[tokio::test]
+async fn a_failed_login_goes_back_to_connected() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut alice = server.client().await;
+
+    alice.client().login("admin", "wrong").await;
+    alice.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+    assert_eq!(alice.client().connection_state().await, ConnectionState::Connected);
+}
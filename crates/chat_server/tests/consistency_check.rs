@@ -0,0 +1,132 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{ConsistencyReport, Message, MessageType};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+async fn check(admin: &mut TestClient, repair: bool) -> ConsistencyReport {
+    admin.client().send(Message::admin_consistency_check(repair)).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ConsistencyReport(_)))
+        .await;
+    let ClientEvent::ConsistencyReport(report) = event else {
+        unreachable!();
+    };
+    report
+}
+
+fn invariants(report: &ConsistencyReport) -> Vec<(&str, bool)> {
+    report
+        .violations
+        .iter()
+        .map(|violation| (violation.invariant.as_str(), violation.repaired))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_healthy_server_has_nothing_to_report() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let report = check(&mut admin, true).await;
+    assert_eq!(report.sessions, 2);
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert!(server
+        .audit_entries()
+        .await
+        .iter()
+        .all(|entry| entry.action() != "consistency_repair"));
+}
+
+#[tokio::test]
+async fn a_lost_session_is_reported_until_repaired() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.lose_session("alice").await;
+
+    // a plain check only looks
+    let report = check(&mut admin, false).await;
+    assert_eq!(invariants(&report), [("user_session", false)]);
+    assert_eq!(check(&mut admin, false).await, report);
+
+    let report = check(&mut admin, true).await;
+    assert_eq!(invariants(&report), [("user_session", true)]);
+    let repairs: Vec<_> = server
+        .audit_entries()
+        .await
+        .into_iter()
+        .filter(|entry| entry.action() == "consistency_repair")
+        .collect();
+    assert_eq!(repairs.len(), 1);
+    assert_eq!(repairs[0].actor(), "admin");
+    assert!(repairs[0].detail().starts_with("user_session: alice"));
+
+    assert!(check(&mut admin, false).await.violations.is_empty());
+}
+
+#[tokio::test]
+async fn a_session_the_user_forgot_is_linked_again() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.unlink_session("alice").await;
+    assert!(!server.is_logged_in("alice").await);
+
+    let report = check(&mut admin, true).await;
+    assert_eq!(invariants(&report), [("session_back_reference", true)]);
+    assert!(server.is_logged_in("alice").await);
+}
+
+#[tokio::test]
+async fn whatever_refers_to_a_vanished_user_is_dropped() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    bob.disconnect().await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+    // queued for bob and counted as unread from alice
+    alice.client().send_direct_message("bob", "hi").await;
+    eventually(|| async { server.queued_messages().await == 1 }).await;
+
+    server.forget_user("bob").await;
+    server.forget_user("alice").await;
+
+    let report = check(&mut admin, false).await;
+    assert_eq!(
+        invariants(&report),
+        [
+            ("offline_queue", false),
+            ("session_user", false),
+            ("unread_counter", false),
+            ("username_index", false),
+            ("username_index", false),
+        ]
+    );
+
+    let report = check(&mut admin, true).await;
+    assert!(report.violations.iter().all(|violation| violation.repaired));
+    assert_eq!(server.queued_messages().await, 0);
+    // alice's session is logged out rather than left pointing at nobody
+    alice
+        .expect(|event| matches!(event, ClientEvent::ReauthRequired(_)))
+        .await;
+
+    assert!(check(&mut admin, false).await.violations.is_empty());
+}
+
+#[tokio::test]
+async fn only_admins_check_the_state() {
+    let server = TestServer::start();
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    alice.send(Message::admin_consistency_check(true)).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+}
@@ -0,0 +1,140 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use chat_client::client::ClientEvent;
+use chat_core::{json::JsonValue, protocol::NOTICE_KICKED};
+use chat_server::application::{
+    send_command,
+    testing::{eventually, AccessLevel, TestServer},
+    CONTROL_SOCKET, CONTROL_TOKEN,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+
+fn control_server(dir: &Path) -> TestServer {
+    TestServer::builder()
+        .with_data_dir(dir.to_path_buf())
+        .with_control_socket()
+        .start()
+}
+
+async fn ctl(dir: &Path, user: &str, command: &[&str]) -> JsonValue {
+    ctl_as(dir, Some(user), command).await
+}
+
+// what `server ctl` sends, None when --as was left out
+async fn ctl_as(dir: &Path, user: Option<&str>, command: &[&str]) -> JsonValue {
+    let command: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+    send_command(dir, user, &command).await.unwrap()
+}
+
+#[tokio::test]
+async fn a_kick_over_the_control_socket_ends_a_live_session() {
+    let dir = TestServer::scratch_dir("kick");
+    let server = control_server(&dir);
+    server.create_user("admin", "secret", AccessLevel::Admin).await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let response = ctl(&dir, "admin", &["kick", "alice", "spam"]).await;
+    assert_eq!(
+        response.to_string(),
+        r#"{"ok":true,"replies":[{"type":"UserKicked","fields":["alice","spam"]}]}"#
+    );
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::SecurityNotice { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::SecurityNotice {
+            kind: NOTICE_KICKED.to_string(),
+            detail: "spam".to_string(),
+        }
+    );
+    alice.expect(|event| matches!(event, ClientEvent::Closed)).await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+
+    // audited like a kick from a client, and the admin was never shown as logged in
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.actor() == "admin" && entry.action() == "kick" && entry.detail() == "alice (spam)"));
+    assert!(!server.is_logged_in("admin").await);
+}
+
+#[tokio::test]
+async fn commands_without_a_user_run_as_the_server_on_a_fresh_state() {
+    let dir = TestServer::scratch_dir("default_user");
+    let server = control_server(&dir);
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let response = ctl_as(&dir, None, &["stats"]).await;
+    assert_eq!(response.get("ok"), Some(&JsonValue::Bool(true)), "{}", response);
+
+    let response = ctl_as(&dir, None, &["kick", "alice", "spam"]).await;
+    assert_eq!(response.get("ok"), Some(&JsonValue::Bool(true)), "{}", response);
+    alice.expect(|event| matches!(event, ClientEvent::Closed)).await;
+
+    // no account stands behind it, the audit names the server
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.actor() == "server" && entry.action() == "kick" && entry.detail() == "alice (spam)"));
+}
+
+#[tokio::test]
+async fn control_commands_are_checked_like_in_band_ones() {
+    let dir = TestServer::scratch_dir("denied");
+    let server = control_server(&dir);
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let response = ctl(&dir, "bob", &["kick", "alice", "spam"]).await;
+    assert_eq!(
+        response.to_string(),
+        r#"{"ok":false,"error":"bob may not send AdminKickUser"}"#
+    );
+    let response = ctl(&dir, "nobody", &["stats"]).await;
+    assert_eq!(response.to_string(), r#"{"ok":false,"error":"Unknown user nobody"}"#);
+    assert!(server.is_logged_in("alice").await);
+    assert!(server.audit_entries().await.is_empty());
+}
+
+#[tokio::test]
+async fn commands_without_the_token_are_refused() {
+    let dir = TestServer::scratch_dir("token");
+    let server = control_server(&dir);
+    server.create_user("admin", "secret", AccessLevel::Admin).await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let mode = fs::metadata(dir.join(CONTROL_TOKEN)).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // a prefix of the token or one with its last character changed is no closer than a guess
+    let token = fs::read_to_string(dir.join(CONTROL_TOKEN)).unwrap().trim().to_string();
+    let mut almost = token[..token.len() - 1].to_string();
+    almost.push(if token.ends_with('0') { '1' } else { '0' });
+    let stream = UnixStream::connect(dir.join(CONTROL_SOCKET)).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    for guess in ["guess", &token[..token.len() / 2], &almost, ""] {
+        writer
+            .write_all(
+                format!(
+                    "{{\"token\":\"{}\",\"user\":\"admin\",\"command\":[\"kick\",\"alice\"]}}\n",
+                    guess
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let response = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(response, r#"{"ok":false,"error":"Invalid control token"}"#);
+    }
+    assert!(server.is_logged_in("alice").await);
+}
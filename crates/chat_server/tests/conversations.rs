@@ -0,0 +1,156 @@
+use chat_client::client::{ClientEvent, SendStatus, NOTES};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{RawConnection, TestClient, TestServer};
+
+async fn received(connection: &mut RawConnection) -> (String, String) {
+    let message = connection.receive().await;
+    assert!(
+        message.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        message
+    );
+    let payload = message.payload();
+    (
+        payload.str_field(0).unwrap().to_string(),
+        payload.str_field(1).unwrap().to_string(),
+    )
+}
+
+async fn message_from(client: &mut TestClient, from: &str) {
+    client
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { sender, .. } if sender == from))
+        .await;
+}
+
+#[tokio::test]
+async fn typed_lines_go_to_the_active_conversation() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut carol = server.logged_in("carol").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    assert!(alice.client().send_to_active("anyone?").await.is_err());
+
+    assert_eq!(alice.client().switch_conversation("bob").await, Ok("bob".to_string()));
+    assert_eq!(alice.client().send_to_active("hi bob").await, Ok(SendStatus::Sent));
+    assert_eq!(received(&mut bob).await, ("alice".to_string(), "hi bob".to_string()));
+
+    alice.client().switch_conversation("carol").await.unwrap();
+    assert_eq!(alice.client().active_conversation().await.as_deref(), Some("carol"));
+    alice.client().send_to_active("hi carol").await.unwrap();
+    assert_eq!(
+        received(&mut carol).await,
+        ("alice".to_string(), "hi carol".to_string())
+    );
+
+    // closing the active one leaves nowhere to send to
+    assert!(alice.client().close_conversation("carol").await);
+    assert!(!alice.client().close_conversation("carol").await);
+    assert_eq!(alice.client().active_conversation().await, None);
+    assert!(alice.client().send_to_active("hello?").await.is_err());
+    let open: Vec<String> = alice
+        .client()
+        .conversations()
+        .await
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(open, vec!["bob".to_string()]);
+}
+
+#[tokio::test]
+async fn typed_lines_in_the_notes_are_notes() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    // your own name is the notes as well
+    assert_eq!(alice.client().switch_conversation("alice").await, Ok(NOTES.to_string()));
+    assert_eq!(alice.client().send_to_active("buy milk").await, Ok(SendStatus::Sent));
+
+    let scrollback = alice.client().scrollback(NOTES).await.unwrap();
+    assert_eq!(scrollback.len(), 1);
+    assert_eq!(
+        (scrollback[0].sender.as_str(), scrollback[0].body.as_str()),
+        ("alice", "buy milk")
+    );
+}
+
+#[tokio::test]
+async fn conversations_count_unread_and_keep_their_lines() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut carol = server.logged_in("carol").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().switch_conversation("carol").await.unwrap();
+    alice.client().send_to_active("hi carol").await.unwrap();
+    received(&mut carol).await;
+    for body in ["one", "two"] {
+        bob.send(Message::direct_message_send("alice", body)).await;
+        assert!(bob.receive().await.is(MessageType::Ack));
+        message_from(&mut alice, "bob").await;
+    }
+    carol.send(Message::direct_message_send("alice", "hey")).await;
+    message_from(&mut alice, "carol").await;
+
+    assert_eq!(
+        alice.client().conversations().await,
+        vec![("bob".to_string(), 2), ("carol".to_string(), 0)]
+    );
+
+    alice.client().switch_conversation("bob").await.unwrap();
+    assert_eq!(alice.client().conversations().await[0], ("bob".to_string(), 0));
+    let lines: Vec<(String, String)> = alice
+        .client()
+        .scrollback("carol")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|line| (line.sender, line.body))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            ("alice".to_string(), "hi carol".to_string()),
+            ("carol".to_string(), "hey".to_string())
+        ]
+    );
+
+    // going through them by name, wrapping around
+    assert_eq!(alice.client().cycle_conversation(true).await.as_deref(), Some("carol"));
+    assert_eq!(alice.client().cycle_conversation(true).await.as_deref(), Some("bob"));
+    assert_eq!(alice.client().cycle_conversation(false).await.as_deref(), Some("carol"));
+}
+
+#[tokio::test]
+async fn a_renamed_peer_keeps_the_conversation() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().switch_conversation("bob").await.unwrap();
+    alice.client().send_to_active("hi").await.unwrap();
+    received(&mut bob).await;
+
+    bob.send(Message::rename_account("robert", "secret")).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::UserRenamed { new, .. } if new == "robert"))
+        .await;
+    assert_eq!(alice.client().active_conversation().await.as_deref(), Some("robert"));
+    alice.client().send_to_active("hi robert").await.unwrap();
+    let (sender, body) = loop {
+        let message = bob.receive().await;
+        if message.is(MessageType::DirectMessageReceive) {
+            let payload = message.payload();
+            break (
+                payload.str_field(0).unwrap().to_string(),
+                payload.str_field(1).unwrap().to_string(),
+            );
+        }
+    };
+    assert_eq!((sender.as_str(), body.as_str()), ("alice", "hi robert"));
+}
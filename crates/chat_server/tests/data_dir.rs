@@ -0,0 +1,54 @@
+use std::{fs, path::PathBuf};
+
+use chat_server::application::{testing::TestServer, DataDir};
+
+#[tokio::test]
+async fn startup_creates_the_directory_and_records_the_owner() {
+    let dir = TestServer::scratch_dir("create").join("nested").join("data");
+    let _server = TestServer::builder().with_data_dir(dir.clone()).start();
+
+    assert!(dir.is_dir());
+    let owner = fs::read_to_string(dir.join("server.lock")).unwrap();
+    assert_eq!(owner.trim(), std::process::id().to_string());
+}
+
+#[tokio::test]
+async fn a_second_instance_refuses_to_start() {
+    let dir = TestServer::scratch_dir("second");
+    let first = TestServer::builder().with_data_dir(dir.clone()).start();
+
+    let error = TestServer::builder()
+        .with_data_dir(dir.clone())
+        .try_start()
+        .unwrap_err();
+    assert_eq!(
+        error,
+        format!(
+            "Data directory {} is already in use by process {}",
+            dir.display(),
+            std::process::id()
+        )
+    );
+    // the refused instance left the owner alone
+    let owner = fs::read_to_string(dir.join("server.lock")).unwrap();
+    assert_eq!(owner.trim(), std::process::id().to_string());
+
+    // the first instance keeps working
+    let mut alice = first.client().await;
+    alice.register("alice", "secret").await;
+}
+
+#[tokio::test]
+async fn the_lock_is_released_with_the_owner() {
+    let dir = TestServer::scratch_dir("release");
+    let first = TestServer::builder().with_data_dir(dir.clone()).start();
+    assert!(DataDir::open(&dir).is_err());
+
+    drop(first);
+    let data_dir = DataDir::open(&dir).unwrap();
+    assert_eq!(data_dir.resolve("users.json".as_ref()), dir.join("users.json"));
+    assert_eq!(
+        data_dir.resolve("/etc/users.json".as_ref()),
+        PathBuf::from("/etc/users.json")
+    );
+}
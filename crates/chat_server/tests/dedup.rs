@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn expect_body(connection: &mut RawConnection, body: &str) {
+    let received = connection.receive().await;
+    assert!(
+        received.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        received
+    );
+    assert_eq!(received.payload().str_field(1), Ok(body));
+}
+
+#[tokio::test]
+async fn retried_message_is_delivered_once_across_a_reconnect() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let first = alice.send_direct_with_id("bob", "hello", "retry-1").await;
+    expect_body(&mut bob, "hello").await;
+
+    // the ack was lost, the client retries from a new connection
+    leave(&server, alice, "alice").await;
+    let mut alice = server.login("alice").await;
+    assert_eq!(alice.send_direct_with_id("bob", "hello", "retry-1").await, first);
+
+    // the next frame bob sees is the next message, not the retry
+    alice.send_direct_with_id("bob", "second", "retry-2").await;
+    expect_body(&mut bob, "second").await;
+}
+
+#[tokio::test]
+async fn ids_are_remembered_per_user() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+    let mut carol = server.logged_in("carol").await;
+
+    let from_alice = alice.send_direct_with_id("bob", "from alice", "shared").await;
+    expect_body(&mut bob, "from alice").await;
+    let from_carol = carol.send_direct_with_id("bob", "from carol", "shared").await;
+    expect_body(&mut bob, "from carol").await;
+    assert_ne!(from_alice, from_carol);
+}
+
+#[tokio::test]
+async fn forgotten_ids_are_relayed_again() {
+    let server = TestServer::builder()
+        .with_dedup_capacity(1)
+        .with_dedup_ttl(Duration::from_millis(200))
+        .start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    // pushed out by a newer id
+    let first = alice.send_direct_with_id("bob", "one", "one").await;
+    expect_body(&mut bob, "one").await;
+    alice.send_direct_with_id("bob", "two", "two").await;
+    expect_body(&mut bob, "two").await;
+    assert_ne!(alice.send_direct_with_id("bob", "one", "one").await, first);
+    expect_body(&mut bob, "one").await;
+
+    // aged out
+    let again = alice.send_direct_with_id("bob", "one", "one").await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_ne!(alice.send_direct_with_id("bob", "one", "one").await, again);
+    expect_body(&mut bob, "one").await;
+}
@@ -0,0 +1,63 @@
+use chat_core::protocol::{ErrorCode, Message, MessageType};
+use chat_server::application::testing::{eventually, AccessLevel, TakeoverPolicy, TestServer};
+
+#[tokio::test]
+async fn a_recipient_gone_mid_send_gets_it_queued() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    server.close_current_channel("bob").await;
+    alice.send(Message::direct_message_send("bob", "hello bob")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert_eq!(server.queued_messages().await, 1);
+
+    bob.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert!(bob.receive().await.is(MessageType::UnreadSummary));
+    let delivered = bob.receive().await;
+    assert!(delivered.is(MessageType::DirectMessageReceive));
+    assert_eq!(delivered.payload().str_field(1), Ok("hello bob"));
+}
+
+#[tokio::test]
+async fn another_device_takes_it_first() {
+    let server = TestServer::builder()
+        .with_takeover_policy(TakeoverPolicy::Allow)
+        .start();
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut laptop = server.raw_connection().await;
+    laptop.send(Message::auth("bob", "secret")).await;
+    assert!(laptop.receive().await.is(MessageType::AuthSuccess));
+    let mut phone = server.raw_connection().await;
+    phone.send(Message::auth("bob", "secret")).await;
+    assert!(phone.receive().await.is(MessageType::AuthSuccess));
+    let mut alice = server.logged_in("alice").await;
+
+    // the phone logged in last, its channel closes first
+    server.close_current_channel("bob").await;
+    alice.send(Message::direct_message_send("bob", "hello bob")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+
+    let delivered = laptop.receive().await;
+    assert!(delivered.is(MessageType::DirectMessageReceive));
+    assert_eq!(server.queued_messages().await, 0);
+}
+
+#[tokio::test]
+async fn without_the_queue_the_sender_hears_about_it() {
+    let server = TestServer::builder().with_offline_queue(false).start();
+    let _bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    server.close_current_channel("bob").await;
+    alice.send(Message::direct_message_send("bob", "hello bob")).await;
+    let response = alice.receive().await;
+    assert!(response.is(MessageType::MessageError));
+    assert_eq!(response.error_code(), ErrorCode::RecipientOffline);
+    assert!(server.is_running());
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+use chat_core::protocol::{DisconnectReason, Message, MessageType};
+use chat_server::application::testing::{eventually, within, RawConnection, TestServer};
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+const DISCONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+async fn expect_disconnect(connection: &mut RawConnection) -> (DisconnectReason, String) {
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::Disconnect) {
+            return message.disconnect_reason().unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn server_acknowledges_a_client_quit() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice.send(Message::disconnect(DisconnectReason::ClientQuit, "")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    alice.expect_closed().await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+}
+
+#[tokio::test]
+async fn kicked_peer_closes_as_soon_as_it_acknowledges() {
+    // long enough that only the Ack can explain the connection closing in time
+    let server = TestServer::builder()
+        .with_disconnect_timeout(Duration::from_secs(60))
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.logged_in("alice").await;
+
+    admin.client().send(Message::admin_kick_user("alice", "spamming")).await;
+    assert_eq!(
+        expect_disconnect(&mut alice).await,
+        (DisconnectReason::Kicked, "spamming".to_string())
+    );
+    alice.send(Message::ACK).await;
+    alice.expect_closed().await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+}
+
+#[tokio::test]
+async fn client_is_told_why_it_was_kicked() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    admin.client().send(Message::admin_kick_user("alice", "spamming")).await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::ServerDisconnected { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::ServerDisconnected {
+            reason: DisconnectReason::Kicked,
+            detail: "spamming".to_string()
+        }
+    );
+    // a kicked client does not come back on its own
+    alice.expect(|event| matches!(event, ClientEvent::Closed)).await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+}
+
+#[tokio::test]
+async fn peer_that_never_acknowledges_is_closed_after_the_timeout() {
+    let server = TestServer::builder()
+        .with_disconnect_timeout(DISCONNECT_TIMEOUT)
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.logged_in("alice").await;
+
+    admin.client().send(Message::admin_kick_user("alice", "spamming")).await;
+    expect_disconnect(&mut alice).await;
+    // anything but the Ack is ignored while the server waits
+    alice.send(Message::direct_message_send("admin", "wait")).await;
+    alice.expect_closed().await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+}
+
+#[tokio::test]
+async fn client_closes_when_the_server_never_acknowledges() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let options = ClientOptions::new()
+        .with_host("127.0.0.1")
+        .with_port(port)
+        .with_disconnect_timeout(DISCONNECT_TIMEOUT);
+    let (client, mut events) = ChatClient::connect(options).await.unwrap();
+
+    // reads whatever arrives and never answers, until the client closes its end
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let silent = tokio::spawn(async move {
+        let mut buffer = [0; 1024];
+        while socket.read(&mut buffer).await.unwrap_or(0) > 0 {}
+    });
+
+    client.disconnect().await;
+    loop {
+        if within(events.recv()).await == Some(ClientEvent::Closed) {
+            break;
+        }
+    }
+    within(silent).await.unwrap();
+}
+
+async fn connection_ends(server: &TestServer) -> (u64, u64) {
+    let mut admin = server.raw_connection().await;
+    admin.send(Message::auth("admin", "secret")).await;
+    assert!(admin.receive().await.is(MessageType::AuthSuccess));
+    admin.send(Message::admin_server_stats()).await;
+    loop {
+        let message = admin.receive().await;
+        if message.is(MessageType::ServerStats) {
+            let payload = message.payload();
+            let ends = (payload.u64_field(11).unwrap(), payload.u64_field(12).unwrap());
+            admin.send(Message::disconnect(DisconnectReason::ClientQuit, "")).await;
+            return ends;
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_peer_that_hangs_up_between_frames_is_a_normal_close() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let alice = server.logged_in("alice").await;
+
+    drop(alice);
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+    assert_eq!(connection_ends(&server).await, (1, 0));
+}
+
+#[tokio::test]
+async fn a_peer_that_hangs_up_inside_a_frame_is_a_broken_connection() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let frame = Message::direct_message_send("bob", "never finished").to_bytes();
+    alice.send_bytes(&frame[..frame.len() / 2]).await;
+    drop(alice);
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+    assert_eq!(connection_ends(&server).await, (0, 1));
+}
@@ -0,0 +1,158 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+async fn expect_stats(admin: &mut TestClient) -> (String, u64, u64) {
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats {
+        mode, sessions, users, ..
+    } = event
+    else {
+        unreachable!();
+    };
+    (mode, sessions, users)
+}
+
+#[tokio::test]
+async fn draining_refuses_new_connections_but_keeps_existing_ones() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("draining", None))
+        .await;
+    assert_eq!(expect_stats(&mut admin).await, ("draining".to_string(), 3, 3));
+
+    let mut refused = server.unchecked_connection();
+    let busy = refused.receive().await;
+    assert!(busy.is(MessageType::ServerBusy));
+    assert_eq!(busy.payload().u64_field(0), Ok(30));
+    refused.expect_closed().await;
+    assert_eq!(server.session_count().await, 3);
+
+    alice.client().send_direct_message("bob", "still here").await;
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(
+        matches!(event, ClientEvent::DirectMessage { sender, body, .. } if sender == "alice" && body == "still here")
+    );
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("normal", None))
+        .await;
+    assert_eq!(expect_stats(&mut admin).await.0, "normal");
+    server.raw_connection().await;
+
+    let entries = server.audit_entries().await;
+    let details: Vec<&str> = entries
+        .iter()
+        .filter(|entry| entry.action() == "set_server_mode")
+        .map(|entry| entry.detail())
+        .collect();
+    assert_eq!(details, ["normal -> draining", "draining -> normal"]);
+}
+
+#[tokio::test]
+async fn stats_report_mode_and_session_counts() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let _guest = server.raw_connection().await;
+
+    admin.client().send(Message::admin_server_stats()).await;
+    assert_eq!(expect_stats(&mut admin).await, ("normal".to_string(), 2, 1));
+}
+
+#[tokio::test]
+async fn drain_stops_the_server_once_sessions_are_gone() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let alice = server.raw_connection().await;
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("draining", Some(60)))
+        .await;
+    expect_stats(&mut admin).await;
+
+    // the admin's own session does not hold the drain open, alice's does
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert!(server.is_running());
+
+    drop(alice);
+    eventually(|| async { !server.is_running() }).await;
+    admin.expect(|event| matches!(event, ClientEvent::Disconnected)).await;
+}
+
+#[tokio::test]
+async fn drain_timeout_stops_the_server_with_sessions_left() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.raw_connection().await;
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("draining", Some(1)))
+        .await;
+    expect_stats(&mut admin).await;
+
+    assert!(alice.receive().await.is(MessageType::Disconnect));
+    eventually(|| async { !server.is_running() }).await;
+}
+
+#[tokio::test]
+async fn invalid_mode_requests_are_rejected() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("closed", None))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("normal", Some(5)))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice
+        .client()
+        .send(Message::admin_set_server_mode("draining", None))
+        .await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    admin.client().send(Message::admin_server_stats()).await;
+    assert_eq!(expect_stats(&mut admin).await.0, "normal");
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use chat_client::client::{ClientEvent, SendStatus};
+use chat_core::protocol::{Message, MessageBuilder, MessageType};
+use chat_server::application::testing::{eventually, TestServer};
+
+#[tokio::test]
+async fn register_login_and_direct_message() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let mut bob = server.client().await;
+    bob.register("bob", "hunter2").await;
+    bob.client().disconnect().await;
+
+    let mut bob = server.client().await;
+    bob.login("bob", "hunter2").await;
+
+    let status = alice.client().send_direct_message("bob", "hello bob").await;
+    assert_eq!(status, SendStatus::Sent);
+
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    let ClientEvent::DirectMessage { sender, body, .. } = event else {
+        unreachable!();
+    };
+    assert_eq!(sender, "alice");
+    assert_eq!(body, "hello bob");
+
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { recipient, .. } if recipient == "bob"))
+        .await;
+}
+
+#[tokio::test]
+async fn direct_message_to_unknown_user_fails() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send_direct_message("nobody", "hello?").await;
+
+    alice
+        .expect(|event| matches!(event, ClientEvent::DeliveryFailed { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn unauthorized_command_is_rejected() {
+    let server = TestServer::start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::direct_message_send("bob", "hi")).await;
+
+    assert!(connection.receive().await.is(MessageType::Nack));
+}
+
+#[tokio::test]
+async fn server_sends_heartbeats() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(Duration::from_millis(50))
+        .start();
+
+    let mut connection = server.raw_connection().await;
+    assert!(connection.receive().await.is(MessageType::Heartbeat));
+    assert!(connection.receive().await.is(MessageType::Heartbeat));
+}
+
+#[tokio::test]
+async fn disconnect_cleans_up_session() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    eventually(|| server.is_logged_in("alice")).await;
+
+    alice.disconnect().await;
+
+    eventually(|| async { server.session_count().await == 0 }).await;
+    assert!(!server.is_logged_in("alice").await);
+}
+
+#[tokio::test]
+async fn dropped_connection_cleans_up_session() {
+    let server = TestServer::start();
+
+    let connection = server.raw_connection().await;
+    eventually(|| async { server.session_count().await == 1 }).await;
+
+    drop(connection);
+
+    eventually(|| async { server.session_count().await == 0 }).await;
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_answered_not_fatal() {
+    let server = TestServer::start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(MessageBuilder::new(MessageType::Auth).build()).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+
+    connection
+        .send(
+            MessageBuilder::new(MessageType::Heartbeat)
+                .with_field(vec![0xff])
+                .build(),
+        )
+        .await;
+    connection.send(Message::auth("nobody", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+    assert_eq!(server.session_count().await, 1);
+}
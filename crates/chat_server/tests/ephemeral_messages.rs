@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use chat_client::client::{ClientEvent, SendStatus};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+#[tokio::test]
+async fn expired_messages_are_never_delivered() {
+    let server = TestServer::start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    alice
+        .send(Message::direct_message_send_with_ttl("bob", "gone soon", 1))
+        .await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    alice.send(Message::direct_message_send("bob", "stays")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+
+    // the expired message is not counted either
+    let summary = bob.receive().await;
+    assert!(summary.is(MessageType::UnreadSummary));
+    assert_eq!(summary.unread_counts(), Ok(vec![("alice".to_string(), 1)]));
+    let message = bob.receive().await;
+    assert_eq!(message.payload().str_field(1), Ok("stays"));
+
+    // the sender hears that it never arrived
+    let expired = alice.receive().await;
+    assert!(expired.is(MessageType::MessageExpired), "Unexpected {:?}", expired);
+    assert_eq!(expired.payload().str_field(1), Ok("bob"));
+
+    // and gone from the history of both sides
+    assert_eq!(history(&mut bob, "alice").await, vec!["stays"]);
+    assert_eq!(history(&mut alice, "bob").await, vec!["stays"]);
+}
+
+#[tokio::test]
+async fn live_messages_carry_their_expiry() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::direct_message_send_with_ttl("bob", "secret", 60))
+        .await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    let message = bob.receive().await;
+    let sent_at = chrono::Utc::now();
+    let expires_at = message.payload().timestamp_field(4).unwrap();
+    assert!(expires_at > sent_at + chrono::Duration::seconds(55));
+    assert!(expires_at <= sent_at + chrono::Duration::seconds(60));
+
+    // a zero ttl is an ordinary message
+    alice
+        .send(Message::direct_message_send_with_ttl("bob", "plain", 0))
+        .await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    let message = bob.receive().await;
+    assert_eq!(message.payload().field_count(), 4);
+}
+
+#[tokio::test]
+async fn ttl_is_capped_by_the_server() {
+    let server = TestServer::builder()
+        .with_max_message_ttl(Duration::from_secs(30))
+        .start();
+    server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::direct_message_send_with_ttl("bob", "too long", 31))
+        .await;
+    let error = alice.receive().await;
+    assert!(error.is(MessageType::MessageError), "Unexpected {:?}", error);
+    assert_eq!(
+        error.payload().str_field(0),
+        Ok("Time to live of 31 seconds exceeds the maximum of 30 seconds")
+    );
+    assert_eq!(history(&mut alice, "bob").await, Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn client_reports_when_a_message_expires() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let status = alice
+        .client()
+        .send_ephemeral_message("bob", "blink", Duration::from_secs(1))
+        .await;
+    assert_eq!(status, SendStatus::Sent);
+
+    let ClientEvent::DirectMessage { id, expires_at, .. } = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert!(expires_at.is_some());
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::MessageExpired { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::MessageExpired {
+            id: id.unwrap(),
+            sender: "alice".to_string()
+        }
+    );
+}
+
+async fn history(connection: &mut RawConnection, peer: &str) -> Vec<String> {
+    connection.send(Message::history_request(peer, 10)).await;
+    let mut bodies = Vec::new();
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::HistoryEnd) {
+            return bodies;
+        }
+        assert!(message.is(MessageType::HistoryEntry), "Unexpected {:?}", message);
+        bodies.push(message.payload().str_field(3).unwrap().to_string());
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{RawConnection, TestServer};
+
+const SESSIONS: usize = 1_000;
+// well below the time a wedged session may hold up its own send
+const PROMPT: Duration = Duration::from_millis(500);
+
+async fn expect_promptly(connection: &mut RawConnection, message_type: MessageType) {
+    let message = tokio::time::timeout(PROMPT, connection.receive())
+        .await
+        .expect("The fan-out waited for the wedged session");
+    assert!(message.is(message_type), "{:?}", message);
+}
+
+#[tokio::test]
+async fn a_shutdown_warning_reaches_every_session() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    let mut connections = Vec::with_capacity(SESSIONS);
+    for _ in 0..SESSIONS {
+        connections.push(server.raw_connection().await);
+    }
+
+    let started = Instant::now();
+    admin.client().send(Message::server_shutdown(0)).await;
+    for connection in &mut connections {
+        assert!(connection.receive().await.is(MessageType::ServerShutdownWarning));
+    }
+    println!("Warned {} sessions in {:?}", SESSIONS, started.elapsed());
+
+    for connection in &mut connections {
+        let disconnect = connection.receive().await;
+        assert!(disconnect.is(MessageType::Disconnect), "{:?}", disconnect);
+    }
+}
+
+#[tokio::test]
+async fn a_wedged_session_does_not_hold_up_the_others() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let mut connections = Vec::new();
+    for _ in 0..10 {
+        connections.push(server.raw_connection().await);
+    }
+
+    let wedge = server.wedge_session("alice").await;
+    admin
+        .client()
+        .send(Message::admin_set_motd("Maintenance at noon", true))
+        .await;
+    for connection in &mut connections {
+        expect_promptly(connection, MessageType::Motd).await;
+    }
+
+    // the stuck session still gets it once its lock is free again
+    drop(wedge);
+    let event = alice.expect(|event| matches!(event, ClientEvent::Motd(_))).await;
+    assert!(matches!(event, ClientEvent::Motd(text) if text == "Maintenance at noon"));
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{RawConnection, TestClient, TestServer};
+
+async fn receiver(server: &TestServer, username: &str, download_dir: PathBuf) -> TestClient {
+    let mut client = server
+        .client_with(ClientOptions::new().with_download_dir(download_dir))
+        .await;
+    client.register(username, "secret").await;
+    client
+}
+
+// returns the transfer id the server assigned
+async fn offer(connection: &mut RawConnection, recipient: &str, size: u64) -> u64 {
+    connection
+        .send(Message::file_offer(recipient, "notes.txt", size, "checksum"))
+        .await;
+    let offered = connection.receive().await;
+    assert!(offered.is(MessageType::FileOffered), "Unexpected {:?}", offered);
+    offered.payload().u64_field(0).unwrap()
+}
+
+#[tokio::test]
+async fn file_is_sent_in_chunks_and_saved_without_overwriting() {
+    let server = TestServer::start();
+    let downloads = TestServer::scratch_dir("downloads");
+    let source = TestServer::scratch_dir("uploads").join("report.bin");
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &data).unwrap();
+
+    let mut bob = receiver(&server, "bob", downloads.clone()).await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let mut saved = Vec::new();
+    for _ in 0..2 {
+        alice.client().send_file("bob", &source).await.unwrap();
+        let ClientEvent::FileOffer {
+            id,
+            sender,
+            filename,
+            size,
+        } = bob.expect(|event| matches!(event, ClientEvent::FileOffer { .. })).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            (sender.as_str(), filename.as_str(), size),
+            ("alice", "report.bin", 100_000)
+        );
+
+        assert!(bob.client().accept_file(id).await);
+        alice.expect(|event| *event == ClientEvent::FileSent(id)).await;
+        let event = bob
+            .expect(|event| {
+                matches!(
+                    event,
+                    ClientEvent::FileReceived { .. } | ClientEvent::FileRejected { .. }
+                )
+            })
+            .await;
+        let ClientEvent::FileReceived { path, .. } = event else {
+            panic!("Transfer failed: {:?}", event);
+        };
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        saved.push(path);
+    }
+
+    assert_eq!(saved[0], downloads.join("report.bin"));
+    assert_eq!(saved[1], downloads.join("report (1).bin"));
+}
+
+#[tokio::test]
+async fn recipient_can_reject_an_offer() {
+    let server = TestServer::start();
+    let mut bob = receiver(&server, "bob", TestServer::scratch_dir("rejected")).await;
+    let mut alice = server.logged_in("alice").await;
+
+    let id = offer(&mut alice, "bob", 10).await;
+    bob.expect(|event| matches!(event, ClientEvent::FileOffer { .. })).await;
+    assert!(bob.client().reject_file(id, "No thanks").await);
+
+    let reject = alice.receive().await;
+    assert!(reject.is(MessageType::FileReject));
+    assert_eq!(reject.payload().u64_field(0), Ok(id));
+    assert_eq!(reject.payload().str_field(1), Ok("No thanks"));
+
+    // the transfer is gone, accepting it afterwards is refused
+    assert!(!bob.client().accept_file(id).await);
+}
+
+#[tokio::test]
+async fn oversized_offers_are_rejected_by_the_server() {
+    let server = TestServer::builder().with_max_file_size(1024).start();
+    server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let id = offer(&mut alice, "bob", 4096).await;
+    let reject = alice.receive().await;
+    assert!(reject.is(MessageType::FileReject));
+    assert_eq!(reject.payload().u64_field(0), Ok(id));
+
+    offer(&mut alice, "nobody", 10).await;
+    let reject = alice.receive().await;
+    assert!(reject.is(MessageType::FileReject));
+    assert_eq!(reject.payload().str_field(1), Ok("User nobody is not online"));
+}
+
+#[tokio::test]
+async fn sender_disconnect_cancels_the_transfer() {
+    let server = TestServer::start();
+    let downloads = TestServer::scratch_dir("interrupted");
+    let mut bob = receiver(&server, "bob", downloads.clone()).await;
+    let mut alice = server.logged_in("alice").await;
+
+    let id = offer(&mut alice, "bob", 1000).await;
+    bob.expect(|event| matches!(event, ClientEvent::FileOffer { .. })).await;
+    assert!(bob.client().accept_file(id).await);
+    assert!(alice.receive().await.is(MessageType::FileAccept));
+
+    alice.send(Message::file_chunk(id, 0, &[7; 500])).await;
+    alice.send(Message::DISCONNECT).await;
+
+    let event = bob
+        .expect(|event| {
+            matches!(
+                event,
+                ClientEvent::FileRejected { .. } | ClientEvent::FileReceived { .. }
+            )
+        })
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::FileRejected {
+            id,
+            reason: "Peer disconnected".to_string()
+        }
+    );
+    assert_eq!(std::fs::read_dir(&downloads).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn protocol_violations_cancel_both_ends() {
+    let server = TestServer::builder().with_max_chunk_size(100).start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let id = offer(&mut alice, "bob", 1000).await;
+    assert!(bob.receive().await.is(MessageType::FileOffer));
+    bob.send(Message::file_accept(id)).await;
+    assert!(alice.receive().await.is(MessageType::FileAccept));
+
+    // only the recipient may accept, and only once
+    bob.send(Message::file_accept(id)).await;
+    assert!(bob.receive().await.is(MessageType::Nack));
+
+    alice.send(Message::file_chunk(id, 0, &[1; 100])).await;
+    assert!(bob.receive().await.is(MessageType::FileChunk));
+    alice.send(Message::file_chunk(id, 1, &[1; 101])).await;
+
+    for connection in [&mut alice, &mut bob] {
+        let reject = connection.receive().await;
+        assert!(reject.is(MessageType::FileReject), "Unexpected {:?}", reject);
+        assert_eq!(
+            reject.payload().str_field(1),
+            Ok("Chunk is too large (101 > 100 bytes)")
+        );
+    }
+
+    // later chunks of the cancelled transfer are dropped silently
+    alice.send(Message::file_chunk(id, 2, &[1; 10])).await;
+    alice.send(Message::history_request("bob", 1)).await;
+    assert!(alice.receive().await.is(MessageType::HistoryEnd));
+}
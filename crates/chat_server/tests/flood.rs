@@ -0,0 +1,103 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{DisconnectReason, Message, MessageType};
+use chat_server::application::testing::{FloodLimits, RawConnection, TestClient, TestServer};
+
+const FLOODER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+const LIMITS: FloodLimits = FloodLimits {
+    frames_per_second: 10,
+    bytes_per_second: 1024 * 1024,
+    cooldown: Duration::from_secs(60),
+};
+// long enough for the meter to start a new window
+const NEXT_WINDOW: Duration = Duration::from_millis(1100);
+
+async fn blast(connection: &mut RawConnection, frames: u64) {
+    for _ in 0..frames {
+        connection.send(Message::heartbeat()).await;
+    }
+}
+
+async fn flood_counters(admin: &mut TestClient) -> (u64, u64) {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats {
+        flood_warnings,
+        flood_disconnects,
+        ..
+    } = event
+    else {
+        unreachable!();
+    };
+    (flood_warnings, flood_disconnects)
+}
+
+#[tokio::test]
+async fn flooding_escalates_from_a_warning_to_a_disconnect() {
+    let server = TestServer::builder().with_flood_limits(LIMITS).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    let mut flooder = server.unchecked_connection_from(FLOODER);
+    assert!(flooder.receive().await.is(MessageType::ServerHello));
+    blast(&mut flooder, LIMITS.frames_per_second + 5).await;
+    let warning = flooder.receive().await;
+    assert!(
+        warning.is(MessageType::MessageError),
+        "Expected a warning, got {:?}",
+        warning
+    );
+    assert_eq!(flood_counters(&mut admin).await, (1, 0));
+
+    tokio::time::sleep(NEXT_WINDOW).await;
+    blast(&mut flooder, LIMITS.frames_per_second + 1).await;
+    let disconnect = flooder.receive().await;
+    assert_eq!(
+        disconnect.disconnect_reason(),
+        Ok((DisconnectReason::ProtocolError, "Too many frames".to_string()))
+    );
+    flooder.send(Message::ACK).await;
+    flooder.expect_closed().await;
+    assert_eq!(flood_counters(&mut admin).await, (1, 1));
+
+    // the address is turned away at accept time, everyone else is not
+    let mut refused = server.unchecked_connection_from(FLOODER);
+    let busy = refused.receive().await;
+    assert!(busy.is(MessageType::ServerBusy));
+    assert_eq!(busy.payload().u64_field(0), Ok(LIMITS.cooldown.as_secs()));
+    refused.expect_closed().await;
+
+    server.raw_connection().await;
+}
+
+#[tokio::test]
+async fn a_calm_window_forgives_the_warning() {
+    let server = TestServer::builder().with_flood_limits(LIMITS).start();
+
+    let mut connection = server.raw_connection().await;
+    blast(&mut connection, LIMITS.frames_per_second + 1).await;
+    assert!(connection.receive().await.is(MessageType::MessageError));
+
+    tokio::time::sleep(NEXT_WINDOW).await;
+    blast(&mut connection, 1).await;
+    tokio::time::sleep(NEXT_WINDOW).await;
+    blast(&mut connection, LIMITS.frames_per_second + 1).await;
+    assert!(connection.receive().await.is(MessageType::MessageError));
+}
+
+#[tokio::test]
+async fn ordinary_traffic_is_not_metered_as_a_flood() {
+    let server = TestServer::builder().with_flood_limits(LIMITS).start();
+
+    let mut connection = server.raw_connection().await;
+    blast(&mut connection, LIMITS.frames_per_second - 1).await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+}
@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::{
+    protocol::{Message, MessageType},
+    trace::{Direction, FrameTracer, TraceRecord},
+};
+use chat_server::application::testing::{eventually, TestServer};
+
+fn trace_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chat_rs_{}_{}.jsonl", name, std::process::id()))
+}
+
+fn find(records: &[TraceRecord], direction: Direction, message_type: MessageType) -> Vec<&TraceRecord> {
+    records
+        .iter()
+        .filter(|record| record.direction() == direction && record.message().is(message_type))
+        .collect()
+}
+
+#[tokio::test]
+async fn server_trace_replays_scripted_session() {
+    let path = trace_path("server_trace");
+    let server = TestServer::builder()
+        .with_tracer(FrameTracer::create(&path).unwrap())
+        .start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "alice-password").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "bob-password").await;
+
+    alice.client().send_direct_message("bob", "hello bob").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("alice-password"));
+    assert!(!content.contains("bob-password"));
+
+    let records = TraceRecord::read_all(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    for record in &records {
+        let replayed = Message::from_bytes(&record.message().to_bytes()).unwrap();
+        assert_eq!(&replayed, record.message());
+    }
+
+    let auth = find(&records, Direction::Received, MessageType::AuthCreate);
+    assert_eq!(auth.len(), 2);
+    assert!(auth.iter().all(|record| record.is_redacted()));
+    assert_eq!(auth[0].message().payload().str_field(1), Ok("<redacted>"));
+
+    let sent = find(&records, Direction::Received, MessageType::DirectMessageSend);
+    let relayed = find(&records, Direction::Sent, MessageType::DirectMessageReceive);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(relayed.len(), 1);
+    assert_ne!(sent[0].session(), relayed[0].session());
+    assert_eq!(relayed[0].message().payload().str_field(0), Ok("alice"));
+    assert_eq!(relayed[0].message().payload().str_field(1), Ok("hello bob"));
+
+    let acks = find(&records, Direction::Sent, MessageType::Ack);
+    assert!(acks.iter().any(|ack| ack.session() == sent[0].session()));
+}
+
+#[tokio::test]
+async fn client_trace_records_both_directions() {
+    let path = trace_path("client_trace");
+    let server = TestServer::start();
+
+    let options = ClientOptions::new().with_tracer(FrameTracer::create(&path).unwrap());
+    let mut alice = server.client_with(options).await;
+    alice.register("alice", "alice-password").await;
+    alice.disconnect().await;
+
+    let records = TraceRecord::read_all(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    let auth = find(&records, Direction::Sent, MessageType::AuthCreate);
+    assert_eq!(auth.len(), 1);
+    assert!(auth[0].is_redacted());
+    assert_eq!(auth[0].message().payload().str_field(0), Ok("alice"));
+    assert_eq!(find(&records, Direction::Received, MessageType::AuthSuccess).len(), 1);
+    assert_eq!(find(&records, Direction::Sent, MessageType::Disconnect).len(), 1);
+}
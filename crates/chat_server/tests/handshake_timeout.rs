@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, TestServer};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(300);
+
+fn alive_tasks() -> usize {
+    tokio::runtime::Handle::current().metrics().num_alive_tasks()
+}
+
+fn server() -> TestServer {
+    TestServer::builder().with_handshake_timeout(HANDSHAKE_TIMEOUT).start()
+}
+
+#[tokio::test]
+async fn silent_connection_is_dropped() {
+    let server = server();
+
+    // a finished round trip guarantees the server's own background tasks are running
+    let mut warmup = server.raw_connection().await;
+    warmup.send(Message::DISCONNECT).await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+    let baseline = alive_tasks();
+
+    let mut idle = server.raw_connection().await;
+    assert_eq!(server.session_count().await, 1);
+
+    idle.expect_closed().await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+    eventually(|| async { alive_tasks() <= baseline }).await;
+}
+
+#[tokio::test]
+async fn first_frame_in_time_keeps_the_connection() {
+    let server = server();
+
+    let mut connection = server.raw_connection().await;
+    tokio::time::sleep(HANDSHAKE_TIMEOUT / 3).await;
+    connection.send(Message::heartbeat()).await;
+
+    tokio::time::sleep(HANDSHAKE_TIMEOUT * 2).await;
+    assert_eq!(server.session_count().await, 1);
+
+    connection.send(Message::auth_create("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+}
+
+#[tokio::test]
+async fn garbage_does_not_count_as_a_first_frame() {
+    let server = server();
+
+    let mut connection = server.raw_connection().await;
+    for _ in 0..5 {
+        connection.send_bytes(b"GET / HTTP/1.1\r\n").await;
+        tokio::time::sleep(HANDSHAKE_TIMEOUT / 5).await;
+    }
+
+    connection.expect_closed().await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+}
+
+#[tokio::test]
+async fn stalled_first_frame_is_dropped() {
+    let server = server();
+
+    let mut connection = server.raw_connection().await;
+    let frame = Message::auth_create("alice", "secret").to_bytes();
+    connection.send_bytes(&frame[..frame.len() / 2]).await;
+
+    connection.expect_closed().await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+    assert!(server.is_running());
+}
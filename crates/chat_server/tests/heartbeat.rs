@@ -0,0 +1,115 @@
+use std::{path::PathBuf, time::Duration};
+
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::{
+    protocol::{DisconnectReason, Message, MessageType},
+    trace::{Direction, FrameTracer, TraceRecord},
+};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+// the runtime is paused, these take no real time but have to stay below the test timeout
+const INTERVAL: Duration = Duration::from_secs(1);
+const GRACE: Duration = Duration::from_millis(500);
+
+async fn missed_heartbeats(admin: &mut TestClient) -> u64 {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats { missed_heartbeats, .. } = event else {
+        unreachable!();
+    };
+    missed_heartbeats
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_client_is_dropped_after_exactly_the_allowed_misses() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_heartbeat_grace(GRACE)
+        .with_max_missed_heartbeats(3)
+        .start();
+    let mut alice = server.logged_in("alice").await;
+
+    for _ in 0..3 {
+        assert!(alice.receive().await.is(MessageType::Heartbeat));
+    }
+    let disconnect = alice.receive().await;
+    assert_eq!(
+        disconnect.disconnect_reason().map(|(reason, _)| reason),
+        Ok(DisconnectReason::IdleTimeout)
+    );
+    alice.expect_closed().await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn answering_clients_stay_connected() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_heartbeat_grace(GRACE)
+        .with_max_missed_heartbeats(1)
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    // the client core answers on its own, nobody is reading its events here
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    tokio::time::sleep(INTERVAL * 6).await;
+
+    assert!(server.is_logged_in("alice").await);
+    assert_eq!(missed_heartbeats(&mut admin).await, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn misses_show_up_in_the_stats() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_heartbeat_grace(GRACE)
+        .with_max_missed_heartbeats(0)
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    let mut alice = server.logged_in("alice").await;
+    for _ in 0..3 {
+        assert!(alice.receive().await.is(MessageType::Heartbeat));
+    }
+    // 0 never disconnects, the misses are only counted
+    tokio::time::sleep(GRACE * 2).await;
+    assert!(missed_heartbeats(&mut admin).await >= 3);
+    assert!(server.is_logged_in("alice").await);
+}
+
+fn trace_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chat_rs_{}_{}.jsonl", name, std::process::id()))
+}
+
+#[tokio::test(start_paused = true)]
+async fn client_speaks_up_when_the_server_is_quiet() {
+    let path = trace_path("keepalive");
+    let server = TestServer::builder()
+        .with_tracer(FrameTracer::create(&path).unwrap())
+        .start();
+    let mut alice = server
+        .client_with(ClientOptions::new().with_keepalive_interval(INTERVAL))
+        .await;
+    alice.register("alice", "secret").await;
+
+    eventually(|| async {
+        TraceRecord::read_all(&path).is_ok_and(|records| {
+            records
+                .iter()
+                .filter(|record| {
+                    record.direction() == Direction::Received && record.message().is(MessageType::Heartbeat)
+                })
+                .count()
+                >= 2
+        })
+    })
+    .await;
+    std::fs::remove_file(&path).ok();
+}
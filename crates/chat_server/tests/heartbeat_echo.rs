@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use chat_client::client::{ClientEvent, ClientOptions, MAX_QUALITY};
+use chat_core::protocol::{DisconnectReason, HeartbeatEcho, Message, MessageType, SessionFilter, SessionInfo};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+use tokio::time::Instant;
+
+// the runtime is paused, these take no real time but have to stay below the test timeout
+const INTERVAL: Duration = Duration::from_secs(1);
+const GRACE: Duration = Duration::from_millis(500);
+const KEEPALIVE: Duration = Duration::from_secs(2);
+
+async fn sessions(admin: &mut TestClient) -> Vec<SessionInfo> {
+    admin
+        .client()
+        .send(Message::admin_list_sessions(&SessionFilter::default()))
+        .await;
+    let mut sessions = Vec::new();
+    loop {
+        match admin.next_event().await {
+            ClientEvent::SessionInfo(info) => sessions.push(info),
+            ClientEvent::SessionListEnd { .. } => return sessions,
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_server_echoes_the_clients_sequence() {
+    let server = TestServer::builder().with_max_missed_heartbeats(0).start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::heartbeat_with(HeartbeatEcho {
+            sequence: 7,
+            echo: 0,
+            reply: false,
+        }))
+        .await;
+
+    let reply = alice.receive().await;
+    let echo = reply.heartbeat_echo().expect("Expected sequences in the reply");
+    assert_eq!((echo.echo, echo.reply), (7, true));
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_client_that_never_hears_the_server_is_dropped() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_heartbeat_grace(GRACE)
+        .with_max_missed_heartbeats(3)
+        .start();
+    let mut alice = server.logged_in("alice").await;
+
+    // it keeps sending its own heartbeats, but never echoes one of the server's
+    let mut sequence = 0;
+    let disconnect = loop {
+        let message = alice.receive().await;
+        if !message.is(MessageType::Heartbeat) {
+            break message;
+        }
+        // the server answers ours, those replies need no answer
+        if message.heartbeat_echo().is_some_and(|echo| !echo.reply) {
+            sequence += 1;
+            alice
+                .send(Message::heartbeat_with(HeartbeatEcho {
+                    sequence,
+                    echo: 0,
+                    reply: false,
+                }))
+                .await;
+        }
+    };
+    assert_eq!(
+        disconnect.disconnect_reason().map(|(reason, _)| reason),
+        Ok(DisconnectReason::IdleTimeout)
+    );
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn heartbeats_without_sequences_still_count() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_heartbeat_grace(GRACE)
+        .with_max_missed_heartbeats(1)
+        .start();
+    let mut alice = server.logged_in("alice").await;
+
+    for _ in 0..5 {
+        assert!(alice.receive().await.is(MessageType::Heartbeat));
+        alice.send(Message::heartbeat()).await;
+    }
+    assert!(server.is_logged_in("alice").await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_client_the_server_stopped_hearing_reconnects() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_max_missed_heartbeats(0)
+        .start();
+    let (mut alice, link) = server
+        .faulty_client(ClientOptions::new().with_keepalive_interval(KEEPALIVE))
+        .await;
+    alice.register("alice", "secret").await;
+    eventually(|| async { alice.client().contact().await.two_way.is_some() }).await;
+    assert!(!alice.client().contact().await.is_one_way());
+
+    // the server's heartbeats still come in, the answers never reach it
+    link.drop_to_server(true);
+    eventually(|| async { alice.client().contact().await.is_one_way() }).await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::ConnectionStale { .. }))
+        .await;
+    let ClientEvent::ConnectionStale {
+        received_ms,
+        two_way_ms,
+    } = event
+    else {
+        unreachable!();
+    };
+    assert!(received_ms <= INTERVAL.as_millis() as u64);
+    assert!(two_way_ms >= 2 * KEEPALIVE.as_millis() as u64);
+
+    link.drop_to_server(false);
+    alice.expect(|event| matches!(event, ClientEvent::Authenticated)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_client_that_stopped_hearing_the_server_reconnects() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_max_missed_heartbeats(0)
+        .start();
+    let (mut alice, link) = server
+        .faulty_client(ClientOptions::new().with_keepalive_interval(KEEPALIVE))
+        .await;
+    alice.register("alice", "secret").await;
+    eventually(|| async { alice.client().contact().await.two_way.is_some() }).await;
+
+    // the keepalives reach the server, its echoes do not come back
+    link.drop_to_client(true);
+    alice
+        .expect(|event| matches!(event, ClientEvent::ConnectionStale { .. }))
+        .await;
+
+    link.drop_to_client(false);
+    alice.expect(|event| matches!(event, ClientEvent::Authenticated)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn connection_quality_drops_within_a_keepalive_interval_of_silence() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_max_missed_heartbeats(0)
+        .start();
+    let (mut alice, link) = server
+        .faulty_client(ClientOptions::new().with_keepalive_interval(KEEPALIVE))
+        .await;
+    alice.register("alice", "secret").await;
+    assert_eq!(alice.client().connection_quality().await, MAX_QUALITY);
+
+    link.drop_to_client(true);
+    let silent = Instant::now();
+    alice
+        .expect(|event| matches!(event, ClientEvent::ConnectionQuality(level) if *level <= 2))
+        .await;
+    assert!(silent.elapsed() <= KEEPALIVE + KEEPALIVE / 4);
+
+    // back before the connection went stale, the level climbs back a step at a time as the server keeps talking
+    link.drop_to_client(false);
+    for step in 1..=MAX_QUALITY {
+        alice
+            .expect(|event| matches!(event, ClientEvent::ConnectionQuality(level) if *level == step))
+            .await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_session_list_tells_receiving_from_two_way_contact() {
+    let server = TestServer::builder()
+        .with_heartbeat_interval(INTERVAL)
+        .with_max_missed_heartbeats(0)
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.logged_in("alice").await;
+
+    // talking all along, but never echoing
+    for sequence in 1..=30 {
+        tokio::time::sleep(INTERVAL).await;
+        alice
+            .send(Message::heartbeat_with(HeartbeatEcho {
+                sequence,
+                echo: 0,
+                reply: true,
+            }))
+            .await;
+    }
+
+    let sessions = sessions(&mut admin).await;
+    let alice = sessions.iter().find(|info| info.username == "alice").unwrap();
+    assert!(alice.received_age <= 1);
+    assert!(alice.two_way_age >= 30);
+    let admin = sessions.iter().find(|info| info.username == "admin").unwrap();
+    assert!(admin.two_way_age <= 2);
+}
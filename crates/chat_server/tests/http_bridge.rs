@@ -0,0 +1,185 @@
+use std::net::SocketAddr;
+
+use chat_core::{
+    capability,
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::testing::{AccessLevel, Permissions, RawConnection, TestServer};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn create_token(connection: &mut RawConnection, label: &str) -> (u64, String) {
+    connection.send(Message::api_token_create(label)).await;
+    let reply = connection.receive().await;
+    assert!(reply.is(MessageType::ApiTokenCreated), "Unexpected {:?}", reply);
+    let (info, token) = reply.created_api_token().unwrap();
+    assert_eq!(info.label, label);
+    (info.id, token)
+}
+
+// one request per connection, the status and the parsed body
+async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, JsonValue) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").expect("A complete response");
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, JsonValue::parse(body).unwrap())
+}
+
+async fn post_message(addr: SocketAddr, token: &str, recipient: &str, body: &str) -> (u16, JsonValue) {
+    let request_body = JsonValue::object()
+        .with("token", token)
+        .with("recipient", recipient)
+        .with("body", body)
+        .to_string();
+    request(addr, "POST", "/api/v1/messages", &request_body).await
+}
+
+fn error_code(body: &JsonValue) -> &str {
+    body.get("error")
+        .and_then(|error| error.get("code"))
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn the_health_endpoint_answers_without_a_token() {
+    let server = TestServer::builder().with_http_bridge().start();
+    let addr = server.http_address().await;
+
+    let (status, body) = request(addr, "GET", "/api/v1/health", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(body.get("status").and_then(JsonValue::as_str), Some("ok"));
+    assert_eq!(body.get("mode").and_then(JsonValue::as_str), Some("normal"));
+
+    assert_eq!(request(addr, "POST", "/api/v1/health", "").await.0, 405);
+    let (status, body) = request(addr, "GET", "/api/v2/health", "").await;
+    assert_eq!((status, error_code(&body)), (404, "not_found"));
+}
+
+// a health request whose head is made `size` bytes long by a filler header
+async fn padded_health_check(addr: SocketAddr, size: usize) -> u16 {
+    let start = format!("GET /api/v1/health HTTP/1.1\r\nHost: {}\r\nX-Filler: ", addr);
+    let request = format!("{}{}\r\n\r\n", start, "x".repeat(size - start.len() - 4));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn request_heads_past_the_limit_are_refused() {
+    let server = TestServer::builder().with_http_bridge().start();
+    let addr = server.http_address().await;
+
+    assert_eq!(padded_health_check(addr, 8 * 1024).await, 200);
+    // the last chunk read carries the head over the limit, it is not let through
+    assert_eq!(padded_health_check(addr, 8 * 1024 + 300).await, 400);
+}
+
+#[tokio::test]
+async fn a_posted_message_reaches_a_connected_client() {
+    let server = TestServer::builder().with_http_bridge().start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let (id, token) = create_token(&mut alice, "deploy bot").await;
+
+    let (status, body) = post_message(server.http_address().await, &token, "bob", "the build is green").await;
+    assert_eq!(status, 200, "Unexpected {}", body);
+    assert_eq!(body.get("recipient").and_then(JsonValue::as_str), Some("bob"));
+    assert!(body.get("id").and_then(JsonValue::as_u64).is_some());
+
+    let message = bob.receive().await;
+    assert!(
+        message.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        message
+    );
+    assert_eq!(message.payload().str_field(0), Ok("alice"));
+    assert_eq!(message.payload().str_field(1), Ok("the build is green"));
+
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.action() == "create_api_token" && entry.detail() == format!("#{} 'deploy bot'", id)));
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_refused() {
+    let server = TestServer::builder().with_http_bridge().start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let (kept, _) = create_token(&mut alice, "kept").await;
+    let (revoked, token) = create_token(&mut alice, "revoked").await;
+    let addr = server.http_address().await;
+
+    alice.send(Message::api_token_revoke(revoked)).await;
+    let reply = alice.receive().await;
+    let left = reply.api_token_entries().unwrap();
+    assert_eq!(left.iter().map(|token| token.id).collect::<Vec<_>>(), [kept]);
+
+    let (status, body) = post_message(addr, &token, "bob", "hello").await;
+    assert_eq!((status, error_code(&body)), (401, "invalid_token"));
+    let (status, _) = post_message(addr, "made up", "bob", "hello").await;
+    assert_eq!(status, 401);
+
+    // tokens belong to their owner
+    let mut bob = server.login("bob").await;
+    bob.send(Message::api_token_revoke(kept)).await;
+    assert_eq!(bob.receive().await.rejected_type(), Some(MessageType::ApiTokenRevoke));
+}
+
+#[tokio::test]
+async fn sends_are_judged_like_those_of_a_logged_in_client() {
+    let server = TestServer::builder().with_http_bridge().start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let (_, token) = create_token(&mut alice, "script").await;
+    let addr = server.http_address().await;
+
+    let (status, body) = post_message(addr, &token, "nobody", "hello").await;
+    assert_eq!((status, error_code(&body)), (404, "user_not_found"));
+
+    let room = JsonValue::object()
+        .with("token", token.as_str())
+        .with("room", "general")
+        .with("body", "hello")
+        .to_string();
+    assert_eq!(request(addr, "POST", "/api/v1/messages", &room).await.0, 400);
+
+    server
+        .set_permission_overrides("alice", Permissions::NONE, Permissions::SEND_DM)
+        .await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let (status, body) = post_message(addr, &token, "bob", "hello").await;
+    assert_eq!((status, error_code(&body)), (403, "forbidden"));
+}
+
+#[tokio::test]
+async fn tokens_are_only_advertised_with_the_bridge() {
+    let server = TestServer::start();
+    let client = server.client().await;
+    assert!(!client.client().server_supports(capability::API_TOKENS).await);
+
+    let server = TestServer::builder().with_http_bridge().start();
+    let client = server.client().await;
+    assert!(client.client().server_supports(capability::API_TOKENS).await);
+}
@@ -0,0 +1,48 @@
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::TestServer;
+
+#[tokio::test]
+async fn a_recipient_typed_with_a_line_ending_is_found() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+
+    bob.send(Message::direct_message_send("alice \r\n", "hello\r\n")).await;
+    assert!(bob.receive().await.is(MessageType::Ack));
+    let received = alice.receive().await;
+    assert!(
+        received.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        received
+    );
+    assert_eq!(received.payload().str_field(0), Ok("bob"));
+    assert_eq!(received.payload().str_field(1), Ok("hello"));
+}
+
+#[tokio::test]
+async fn a_name_registered_decomposed_logs_in_composed() {
+    let server = TestServer::start();
+    server.logged_in("e\u{301}mile\r").await;
+    assert!(server.is_logged_in("\u{e9}mile").await);
+
+    let mut again = server.raw_connection().await;
+    again.send(Message::auth("\u{e9}mile", "secret")).await;
+    assert!(again.receive().await.is(MessageType::AuthSuccess));
+}
+
+#[tokio::test]
+async fn input_that_normalizes_to_nothing_is_refused() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice.send(Message::direct_message_send("bob", " \r\n")).await;
+    let response = alice.receive().await;
+    assert!(response.is(MessageType::MessageError), "Unexpected {:?}", response);
+    assert_eq!(response.payload().str_field(0), Ok("Message must not be empty"));
+
+    let mut nobody = server.raw_connection().await;
+    nobody.send(Message::auth_create("\r\n", "secret")).await;
+    let response = nobody.receive().await;
+    assert!(response.is(MessageType::AuthFailure), "Unexpected {:?}", response);
+    assert_eq!(response.payload().str_field(0), Ok("Username must not be empty"));
+}
@@ -0,0 +1,179 @@
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::{
+    integrity::Integrity,
+    protocol::{DisconnectReason, Message, MessageType, SessionFilter, SessionInfo},
+};
+use chat_server::application::testing::{RawConnection, TestClient, TestServer};
+
+async fn sessions(admin: &mut TestClient) -> Vec<SessionInfo> {
+    admin
+        .client()
+        .send(Message::admin_list_sessions(&SessionFilter::default()))
+        .await;
+    let mut sessions = Vec::new();
+    loop {
+        match admin.next_event().await {
+            ClientEvent::SessionInfo(info) => sessions.push(info),
+            ClientEvent::SessionListEnd { .. } => return sessions,
+            _ => {}
+        }
+    }
+}
+
+async fn integrity_failures(admin: &mut TestClient) -> u64 {
+    admin.client().send(Message::admin_server_stats()).await;
+    match admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await
+    {
+        ClientEvent::ServerStats { integrity_failures, .. } => integrity_failures,
+        _ => unreachable!(),
+    }
+}
+
+async fn expect_protocol_error(connection: &mut RawConnection) -> String {
+    let message = connection.receive().await;
+    assert!(message.is(MessageType::Disconnect), "Unexpected {:?}", message);
+    let (reason, detail) = message.disconnect_reason().unwrap();
+    assert_eq!(reason, DisconnectReason::ProtocolError);
+    detail
+}
+
+#[tokio::test]
+async fn every_algorithm_can_be_negotiated() {
+    let server = TestServer::builder().with_integrity(Integrity::ALL.to_vec()).start();
+    let mut admin = server.admin().await;
+
+    let mut clients = Vec::new();
+    for &integrity in Integrity::ALL {
+        let options = ClientOptions::new().with_integrity(vec![integrity]);
+        let mut client = server.client_with(options).await;
+        client.register(integrity.as_str(), "secret").await;
+        assert_eq!(client.client().integrity().await, integrity);
+        clients.push(client);
+    }
+
+    // both directions of every connection carry the agreed trailer
+    for index in 0..clients.len() {
+        let recipient = Integrity::ALL[(index + 1) % clients.len()].as_str();
+        clients[index].client().send_direct_message(recipient, "ping").await;
+    }
+    for client in &mut clients {
+        client
+            .expect(|event| matches!(event, ClientEvent::DirectMessage { body, .. } if body == "ping"))
+            .await;
+    }
+
+    let mut listed: Vec<(String, String)> = sessions(&mut admin)
+        .await
+        .into_iter()
+        .map(|info| (info.username, info.integrity))
+        .collect();
+    listed.sort();
+    assert_eq!(
+        listed,
+        vec![
+            ("admin".to_string(), "crc32".to_string()),
+            ("crc32".to_string(), "crc32".to_string()),
+            ("none".to_string(), "none".to_string()),
+            ("xxh3".to_string(), "xxh3".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_client_takes_the_first_algorithm_the_server_offers() {
+    let server = TestServer::start();
+
+    let preferred = ClientOptions::new().with_integrity(vec![Integrity::None, Integrity::Xxh3]);
+    let mut alice = server.client_with(preferred).await;
+    alice.register("alice", "secret").await;
+    assert_eq!(alice.client().integrity().await, Integrity::Xxh3);
+
+    // nothing it asks for is offered, so it stays with what every connection starts with
+    let unavailable = ClientOptions::new().with_integrity(vec![Integrity::None]);
+    let mut bob = server.client_with(unavailable).await;
+    bob.register("bob", "secret").await;
+    assert_eq!(bob.client().integrity().await, Integrity::Crc32);
+}
+
+#[tokio::test]
+async fn frames_with_another_trailer_are_skipped_until_the_limit() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.logged_in("alice").await;
+
+    let foreign = Message::direct_message_send("admin", "hi").to_bytes_using(Integrity::Xxh3);
+    alice.send_bytes(&foreign).await;
+    alice.send_bytes(&foreign).await;
+    // the stream is still in step, the next good frame is handled
+    alice.send(Message::direct_message_send("admin", "hi")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    let listed = sessions(&mut admin).await;
+    let info = listed.iter().find(|info| info.username == "alice").unwrap();
+    assert_eq!((info.integrity.as_str(), info.integrity_failures), ("crc32", 2));
+
+    alice.send_bytes(&foreign).await;
+    assert_eq!(
+        expect_protocol_error(&mut alice).await,
+        "Too many frames failed the integrity check"
+    );
+    assert_eq!(integrity_failures(&mut admin).await, 3);
+}
+
+#[tokio::test]
+async fn after_switching_to_xxh3_crc32_frames_are_rejected() {
+    let server = TestServer::builder().with_max_integrity_failures(2).start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    alice.send(Message::integrity_select(Integrity::Xxh3)).await;
+    let selected = alice.receive().await;
+    assert_eq!(selected.switches_integrity(), Some(Integrity::Xxh3));
+    alice.set_integrity(Integrity::Xxh3);
+
+    alice.send(Message::direct_message_send("bob", "hello bob")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    // a crc32 trailer is four bytes short of an xxh3 one, padded the frame is read whole and compared
+    let mut padded = Message::heartbeat().to_bytes();
+    padded.extend_from_slice(&[0; 4]);
+    alice.send_bytes(&padded).await;
+    alice.send_bytes(&padded).await;
+    expect_protocol_error(&mut alice).await;
+}
+
+#[tokio::test]
+async fn a_selection_the_server_does_not_offer_is_refused() {
+    let server = TestServer::start();
+
+    let mut alice = server.logged_in("alice").await;
+    alice.send(Message::integrity_select(Integrity::None)).await;
+    assert_eq!(
+        expect_protocol_error(&mut alice).await,
+        "Frame integrity none is not offered"
+    );
+
+    // once per connection, switching back and forth would race the frames in flight
+    let mut bob = server.logged_in("bob").await;
+    bob.send(Message::integrity_select(Integrity::Crc32)).await;
+    assert!(bob.receive().await.is(MessageType::IntegritySelected));
+    bob.send(Message::integrity_select(Integrity::Xxh3)).await;
+    expect_protocol_error(&mut bob).await;
+}
+
+#[tokio::test]
+async fn a_limit_of_zero_never_disconnects() {
+    let server = TestServer::builder().with_max_integrity_failures(0).start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let foreign = Message::heartbeat().to_bytes_using(Integrity::Xxh3);
+    for _ in 0..5 {
+        alice.send_bytes(&foreign).await;
+    }
+    alice.send(Message::direct_message_send("bob", "still here")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+}
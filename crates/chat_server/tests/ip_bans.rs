@@ -0,0 +1,159 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{IpBanList, Message, MessageType};
+use chat_server::application::testing::{TestClient, TestServer, ViolationLimits};
+
+const SCANNER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9));
+const LIMITS: ViolationLimits = ViolationLimits {
+    threshold: 3,
+    decay: Duration::from_secs(60),
+    ban: Duration::from_secs(300),
+};
+// an even number of bytes, so the frame sent after it starts where the server looks for one
+const GARBAGE: &[u8] = b"GET / HTTP/1.1\r\n";
+
+// the server answers the login only after it skipped the garbage in front of it
+async fn garble_from(server: &TestServer, address: IpAddr, username: &str) {
+    let mut connection = server.unchecked_connection_from(address);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+    connection.send_bytes(GARBAGE).await;
+    connection.send(Message::auth_create(username, "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+}
+
+async fn ban_scanner(server: &TestServer) {
+    for index in 0..LIMITS.threshold {
+        garble_from(server, SCANNER, &format!("scanner{}", index)).await;
+    }
+}
+
+async fn bans(admin: &mut TestClient) -> IpBanList {
+    admin.client().send(Message::admin_list_ip_bans()).await;
+    let event = admin.expect(|event| matches!(event, ClientEvent::IpBans(_))).await;
+    let ClientEvent::IpBans(list) = event else {
+        unreachable!();
+    };
+    list
+}
+
+#[tokio::test]
+async fn repeated_garbage_bans_the_address() {
+    let server = TestServer::builder().with_violation_limits(LIMITS).start();
+    let mut admin = server.admin().await;
+
+    ban_scanner(&server).await;
+
+    let mut refused = server.unchecked_connection_from(SCANNER);
+    let busy = refused.receive().await;
+    assert!(
+        busy.is(MessageType::ServerBusy),
+        "Expected a busy notice, got {:?}",
+        busy
+    );
+    // rounded up, the scanner is told to wait out the whole ban
+    let retry_after = busy.payload().u64_field(0).unwrap();
+    assert!(retry_after > LIMITS.ban.as_secs() - 10 && retry_after <= LIMITS.ban.as_secs());
+    refused.expect_closed().await;
+
+    // everyone else is still served
+    server.raw_connection().await;
+
+    let list = bans(&mut admin).await;
+    assert_eq!(list.bans.len(), 1);
+    assert_eq!(list.bans[0].0, SCANNER.to_string());
+    assert!(list.bans[0].1 <= retry_after);
+    assert_eq!((list.issued, list.turned_away), (1, 1));
+}
+
+#[tokio::test]
+async fn garbage_within_one_connection_counts_once() {
+    let server = TestServer::builder().with_violation_limits(LIMITS).start();
+    let mut admin = server.admin().await;
+
+    let mut connection = server.unchecked_connection_from(SCANNER);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+    for _ in 0..LIMITS.threshold * 2 {
+        connection.send_bytes(GARBAGE).await;
+    }
+    connection.send(Message::auth_create("scanner", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+
+    assert!(bans(&mut admin).await.bans.is_empty());
+    let mut next = server.unchecked_connection_from(SCANNER);
+    assert!(next.receive().await.is(MessageType::ServerHello));
+}
+
+#[tokio::test]
+async fn clearing_a_ban_lets_the_address_back_in() {
+    let server = TestServer::builder().with_violation_limits(LIMITS).start();
+    let mut admin = server.admin().await;
+    ban_scanner(&server).await;
+
+    admin
+        .client()
+        .send(Message::admin_clear_ip_ban(&SCANNER.to_string()))
+        .await;
+    let event = admin.expect(|event| matches!(event, ClientEvent::IpBans(_))).await;
+    assert_eq!(
+        event,
+        ClientEvent::IpBans(IpBanList {
+            issued: 1,
+            ..Default::default()
+        })
+    );
+
+    let mut connection = server.unchecked_connection_from(SCANNER);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+
+    // there is nothing left to lift
+    admin
+        .client()
+        .send(Message::admin_clear_ip_ban(&SCANNER.to_string()))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn only_admins_see_the_bans() {
+    let server = TestServer::start();
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    alice.send(Message::admin_list_ip_bans()).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+}
+
+#[tokio::test(start_paused = true)]
+async fn bans_run_out() {
+    let server = TestServer::builder().with_violation_limits(LIMITS).start();
+    ban_scanner(&server).await;
+
+    server.clock().advance(LIMITS.ban - Duration::from_secs(1)).await;
+    let busy = server.unchecked_connection_from(SCANNER).receive().await;
+    assert_eq!(busy.payload().u64_field(0), Ok(1));
+
+    server.clock().advance(Duration::from_secs(1)).await;
+    let mut connection = server.unchecked_connection_from(SCANNER);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+}
+
+#[tokio::test(start_paused = true)]
+async fn violations_are_forgiven_over_time() {
+    let server = TestServer::builder().with_violation_limits(LIMITS).start();
+
+    // one short of a ban at any time, the oldest is forgiven before the next comes in
+    for index in 0..LIMITS.threshold * 2 {
+        garble_from(&server, SCANNER, &format!("scanner{}", index)).await;
+        server.clock().advance(LIMITS.decay).await;
+    }
+
+    let mut connection = server.unchecked_connection_from(SCANNER);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+}
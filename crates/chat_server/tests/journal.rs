@@ -0,0 +1,116 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageType, ServerStats},
+};
+use chat_server::application::testing::{
+    eventually, within, write_journal, Journal, JournalEntry, JournalKind, JournalLimits, RawConnection, TestJournal,
+    TestServer,
+};
+use chrono::Utc;
+
+// (dropped, failing) as the stats message reports them
+async fn journal_stats(admin: &mut RawConnection) -> (u64, bool) {
+    admin.send(Message::admin_server_stats()).await;
+    let stats = admin.receive().await;
+    assert!(stats.is(MessageType::ServerStats), "Unexpected {:?}", stats);
+    let payload = stats.payload();
+    (payload.u64_field(21).unwrap(), payload.u64_field(22).unwrap() != 0)
+}
+
+fn actions(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| JsonValue::parse(line).unwrap())
+        .map(|entry| entry.get("detail").and_then(JsonValue::as_str).unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn a_failing_disk_holds_up_no_handler_and_the_backlog_follows_once_it_is_back() {
+    let journal = Arc::new(TestJournal::default());
+    journal.set_failing(true);
+    let server = TestServer::builder()
+        .with_journal_writer(journal.clone())
+        .with_journal_limits(JournalLimits {
+            capacity: 4,
+            metrics_interval: Duration::from_secs(3600),
+        })
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.login("admin").await;
+
+    // every one is answered right away, although none of them can be written
+    for index in 0..10 {
+        admin
+            .send(Message::admin_set_motd(&format!("motd {}", index), false))
+            .await;
+        assert!(admin.receive().await.is(MessageType::Motd));
+    }
+    eventually(|| async { journal.failed_writes() > 0 }).await;
+    let (dropped, failing) = journal_stats(&mut admin).await;
+    assert!(failing);
+    assert!(dropped > 0);
+
+    journal.set_failing(false);
+    eventually(|| async { journal.lines(JournalKind::Audit).len() as u64 + dropped == 10 }).await;
+    // the newest entries made it, the queue gave up the oldest ones
+    let written = actions(&journal.lines(JournalKind::Audit));
+    assert_eq!(written.last().map(String::as_str), Some("'motd 9'"));
+    assert_eq!(journal_stats(&mut admin).await, (dropped, false));
+}
+
+#[tokio::test]
+async fn metric_snapshots_and_routine_entries_are_dropped_before_changes() {
+    let journal = Journal::new(3);
+    let now = Utc::now();
+    let metrics = JournalEntry::metrics(now, &ServerStats::default());
+    let change = |detail: &str| JournalEntry::audit(now, "admin", "kick", detail);
+
+    journal.push(metrics.clone());
+    journal.push(change("first"));
+    journal.push(metrics.clone());
+    // a change takes the place of the oldest snapshot, a new snapshot that of an older one
+    journal.push(change("second"));
+    journal.push(metrics.clone());
+    journal.push(JournalEntry::audit(now, "alice", "session_takeover", "routine"));
+    // nothing is less important than a snapshot, so the snapshot itself goes
+    journal.push(metrics);
+    assert_eq!(journal.dropped(), 4);
+
+    // a closed journal is written out before the writer ends
+    let written = Arc::new(TestJournal::default());
+    journal.close();
+    within(write_journal(journal, written.clone())).await;
+    assert_eq!(
+        actions(&written.lines(JournalKind::Audit)),
+        ["first", "second", "routine"]
+    );
+    assert!(written.lines(JournalKind::Metrics).is_empty());
+}
+
+#[tokio::test]
+async fn a_clean_shutdown_writes_what_is_queued_and_a_last_snapshot() {
+    let dir = TestServer::scratch_dir("shutdown");
+    // the raw connection never answers the disconnect, the server does not wait long for it
+    let server = TestServer::builder()
+        .with_data_dir(dir.clone())
+        .with_disconnect_timeout(Duration::from_millis(100))
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.login("admin").await;
+    admin.send(Message::admin_set_motd("hello", false)).await;
+    assert!(admin.receive().await.is(MessageType::Motd));
+
+    server.signal().await;
+    server.summary().await;
+
+    let audit = fs::read_to_string(dir.join("audit.log")).unwrap();
+    let audit = audit.lines().map(str::to_string).collect::<Vec<_>>();
+    assert_eq!(actions(&audit), ["'hello'"]);
+    let metrics = fs::read_to_string(dir.join("metrics.log")).unwrap();
+    let last = JsonValue::parse(metrics.lines().last().unwrap()).unwrap();
+    assert_eq!(last.get("journal_dropped").and_then(JsonValue::as_u64), Some(0));
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,126 @@
+use std::io;
+
+use chat_client::client::{json::JsonValue, run_json, ChatClient, ClientEvent, ClientOptions};
+use chat_server::application::testing::{within, TestServer};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines},
+    task::JoinHandle,
+};
+
+// a client in --output json mode, stdin and stdout are pipes the test holds the other ends of
+struct Machine {
+    input: DuplexStream,
+    output: Lines<BufReader<DuplexStream>>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl Machine {
+    async fn start(server: &TestServer) -> Self {
+        let (client, events) = ChatClient::connect_with(server.connector(), ClientOptions::new())
+            .await
+            .unwrap();
+        let (input, stdin) = tokio::io::duplex(4096);
+        let (stdout, output) = tokio::io::duplex(64 * 1024);
+        let handle = tokio::spawn(run_json(client, events, BufReader::new(stdin), stdout));
+        Self {
+            input,
+            output: BufReader::new(output).lines(),
+            handle,
+        }
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.input.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+    }
+
+    // every line is an object, the ones of other types are skipped
+    async fn expect(&mut self, kind: &str) -> JsonValue {
+        within(async {
+            loop {
+                let line = self.output.next_line().await.unwrap().expect("The output ended");
+                let value = JsonValue::parse(&line).unwrap_or_else(|e| panic!("{}: {}", e, line));
+                assert!(value.get("timestamp").is_some(), "{}", line);
+                if value.get("type").and_then(JsonValue::as_str) == Some(kind) {
+                    return value;
+                }
+            }
+        })
+        .await
+    }
+}
+
+fn text<'a>(value: &'a JsonValue, key: &str) -> &'a str {
+    value.get(key).and_then(JsonValue::as_str).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn malformed_lines_are_answered_with_error_objects() {
+    let server = TestServer::start();
+    let mut machine = Machine::start(&server).await;
+
+    machine.send("this is not json").await;
+    assert!(!text(&machine.expect("error").await, "error").is_empty());
+    machine.send(r#"{"body": "no command"}"#).await;
+    assert_eq!(
+        text(&machine.expect("error").await, "error"),
+        "Missing string field 'cmd'"
+    );
+    machine.send(r#"{"cmd": "fly"}"#).await;
+    assert_eq!(text(&machine.expect("error").await, "error"), "Unknown command 'fly'");
+    machine.send(r#"{"cmd": "send", "to": "bob"}"#).await;
+    assert_eq!(
+        text(&machine.expect("error").await, "error"),
+        "Missing string field 'body'"
+    );
+    machine.send(r#"{"cmd": "cancel", "index": "first"}"#).await;
+    assert_eq!(
+        text(&machine.expect("error").await, "error"),
+        "Missing numeric field 'index'"
+    );
+
+    // the session goes on after them, a command that needs a login says so
+    machine.send(r#"{"cmd": "state"}"#).await;
+    assert_eq!(text(&machine.expect("state").await, "state"), "connected");
+    machine.send(r#"{"cmd": "send", "to": "bob", "body": "hi"}"#).await;
+    assert!(!text(&machine.expect("error").await, "error").is_empty());
+}
+
+#[tokio::test]
+async fn commands_and_events_round_trip_as_json_lines() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let mut machine = Machine::start(&server).await;
+
+    machine
+        .send(r#"{"cmd": "register", "username": "alice", "password": "secret"}"#)
+        .await;
+    machine.expect("authenticated").await;
+
+    machine.send(r#"{"cmd": "send", "to": "bob", "body": "hi bob"}"#).await;
+    let status = machine.expect("send_status").await;
+    assert_eq!((text(&status, "to"), text(&status, "status")), ("bob", "sent"));
+    assert_eq!(text(&machine.expect("delivered").await, "recipient"), "bob");
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::DirectMessage { body, .. } if body == "hi bob"));
+
+    bob.client().send_direct_message("alice", "hi alice").await;
+    let received = machine.expect("direct_message").await;
+    assert_eq!(
+        (text(&received, "sender"), text(&received, "body")),
+        ("bob", "hi alice")
+    );
+    assert!(received.get("id").and_then(JsonValue::as_u64).is_some());
+
+    machine.send(r#"{"cmd": "outbox"}"#).await;
+    let outbox = machine.expect("outbox").await;
+    assert_eq!(outbox.get("entries").map(JsonValue::to_string).as_deref(), Some("[]"));
+
+    // disconnect ends the stream after the last event
+    machine.send(r#"{"cmd": "disconnect"}"#).await;
+    machine.expect("closed").await;
+    within(async { assert!(machine.output.next_line().await.unwrap().is_none()) }).await;
+    machine.handle.await.unwrap().unwrap();
+}
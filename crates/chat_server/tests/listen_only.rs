@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use chat_core::{
+    capability,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::testing::{
+    AccessLevel, AccessPresets, Permissions, PresenceTiming, RawConnection, TestServer,
+};
+
+const FAST: PresenceTiming = PresenceTiming {
+    interval: Duration::from_millis(20),
+    window: Duration::ZERO,
+};
+
+// skips heartbeats and anything else that is not of the expected type
+async fn next_of(connection: &mut RawConnection, message_type: MessageType) -> Message {
+    loop {
+        let message = connection.receive().await;
+        if message.is(message_type) {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn listen_only_is_advertised_when_enabled() {
+    let server = TestServer::start();
+    let alice = server.client().await;
+    assert!(!alice.client().server_supports(capability::LISTEN_ONLY).await);
+
+    let enabled = TestServer::builder().with_listen_only(true).start();
+    let alice = enabled.client().await;
+    assert!(alice.client().server_supports(capability::LISTEN_ONLY).await);
+}
+
+#[tokio::test]
+async fn guests_receive_broadcasts_without_logging_in() {
+    let server = TestServer::builder().with_listen_only(true).start();
+    let admin = server.admin().await;
+    let mut guest = server.raw_connection().await;
+
+    admin
+        .client()
+        .send(Message::admin_set_motd("Maintenance at noon", true))
+        .await;
+    let motd = next_of(&mut guest, MessageType::Motd).await;
+    assert_eq!(motd.payload().str_field(0), Ok("Maintenance at noon"));
+}
+
+#[tokio::test]
+async fn guests_hear_about_logins() {
+    let server = TestServer::builder().with_listen_only(true).with_presence(FAST).start();
+    let mut guest = server.raw_connection().await;
+
+    let _alice = server.logged_in("alice").await;
+    let update = next_of(&mut guest, MessageType::PresenceUpdate).await;
+    assert_eq!(update.presence_changes(), Ok((vec!["alice".to_string()], vec![])));
+}
+
+#[tokio::test]
+async fn guests_cannot_send_whatever_their_preset_grants() {
+    let presets = AccessPresets::default().with(
+        &AccessLevel::Guest,
+        Permissions::SESSION | Permissions::AUTHENTICATE | Permissions::SEND_DM | Permissions::VIEW_STATS,
+    );
+    let server = TestServer::builder()
+        .with_listen_only(true)
+        .with_access_presets(presets)
+        .start();
+    let _alice = server.logged_in("alice").await;
+    let mut guest = server.raw_connection().await;
+
+    guest.send(Message::direct_message_send("alice", "hello")).await;
+    let nack = next_of(&mut guest, MessageType::Nack).await;
+    assert_eq!(nack.rejected_type(), Some(MessageType::DirectMessageSend));
+    guest.send(Message::admin_server_stats()).await;
+    let nack = next_of(&mut guest, MessageType::Nack).await;
+    assert_eq!(nack.rejected_type(), Some(MessageType::AdminServerStats));
+
+    // logging in is still open to them, and afterwards they are users like any other
+    guest.send(Message::auth_create("bob", "secret")).await;
+    next_of(&mut guest, MessageType::AuthSuccess).await;
+    guest.send(Message::direct_message_send("alice", "hello")).await;
+    next_of(&mut guest, MessageType::Ack).await;
+}
@@ -0,0 +1,139 @@
+use std::{fs, path::PathBuf};
+
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::protocol::{ErrorCode, Message, MessageType};
+use chat_server::application::{
+    testing::{AccessLevel, RawConnection, TestServer},
+    LANGUAGE_DIR,
+};
+
+const GERMAN: &str = r#"
+# only what the tests need, everything else stays in the built-in language
+invalid_credentials = "Benutzername oder Passwort falsch"
+user_not_found = "Den Benutzer {user} gibt es nicht" # with a parameter
+"#;
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat_rs_localization_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(dir.join(LANGUAGE_DIR)).unwrap();
+    fs::write(dir.join(LANGUAGE_DIR).join("de.toml"), GERMAN).unwrap();
+    dir
+}
+
+async fn failed_login(connection: &mut RawConnection) -> Message {
+    connection.send(Message::auth("alice", "wrong")).await;
+    let failure = connection.receive().await;
+    assert!(failure.is(MessageType::AuthFailure), "Unexpected {:?}", failure);
+    failure
+}
+
+#[tokio::test]
+async fn a_client_that_asks_for_a_language_gets_its_auth_failure_translated() {
+    let dir = data_dir("translated");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.client_with(ClientOptions::new().with_language("de")).await;
+
+    alice.client().login("alice", "wrong").await;
+    let event = alice.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+    assert_eq!(
+        event,
+        ClientEvent::AuthFailed("Benutzername oder Passwort falsch".to_string())
+    );
+
+    // everyone else is still answered in the built-in language
+    let mut bob = server.client().await;
+    bob.client().login("alice", "wrong").await;
+    let event = bob.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+    assert_eq!(
+        event,
+        ClientEvent::AuthFailed("Invalid username or password".to_string())
+    );
+
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn the_error_code_stays_the_same_in_every_language() {
+    let dir = data_dir("codes");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut connection = server.raw_connection().await;
+
+    let english = failed_login(&mut connection).await;
+    connection.send(Message::client_hello("de")).await;
+    let german = failed_login(&mut connection).await;
+
+    assert_eq!(english.error_code(), ErrorCode::InvalidCredentials);
+    assert_eq!(german.error_code(), ErrorCode::InvalidCredentials);
+    assert_eq!(german.payload().str_field(0), Ok("Benutzername oder Passwort falsch"));
+
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn clients_that_ask_for_nothing_get_the_server_default() {
+    let dir = data_dir("default");
+    let server = TestServer::builder()
+        .with_data_dir(dir.clone())
+        .with_default_language("de")
+        .start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+
+    let mut connection = server.raw_connection().await;
+    assert_eq!(
+        failed_login(&mut connection).await.payload().str_field(0),
+        Ok("Benutzername oder Passwort falsch")
+    );
+
+    // a language without a catalog falls back to the default, a region to its language
+    connection.send(Message::client_hello("fr")).await;
+    assert_eq!(
+        failed_login(&mut connection).await.payload().str_field(0),
+        Ok("Benutzername oder Passwort falsch")
+    );
+    connection.send(Message::client_hello("en")).await;
+    assert_eq!(
+        failed_login(&mut connection).await.payload().str_field(0),
+        Ok("Invalid username or password")
+    );
+    connection.send(Message::client_hello("de-AT")).await;
+    assert_eq!(
+        failed_login(&mut connection).await.payload().str_field(0),
+        Ok("Benutzername oder Passwort falsch")
+    );
+
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn parameters_are_filled_into_the_translation() {
+    let dir = data_dir("parameters");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::client_hello("de")).await;
+    alice.send(Message::auth_create("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    alice.send(Message::direct_message_send("nobody", "hi")).await;
+    let error = alice.receive().await;
+    assert!(error.is(MessageType::MessageError), "Unexpected {:?}", error);
+    assert_eq!(error.error_code(), ErrorCode::UserNotFound);
+    assert_eq!(error.payload().str_field(0), Ok("Den Benutzer nobody gibt es nicht"));
+
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn an_invalid_language_is_refused() {
+    let server = TestServer::start();
+    let mut connection = server.raw_connection().await;
+
+    connection.send(Message::client_hello("../etc/passwd")).await;
+    assert!(connection.receive().await.is(MessageType::Nack));
+}
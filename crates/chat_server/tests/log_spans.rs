@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chat_core::protocol::{Message, MessageId, MessageType};
+use chat_server::application::testing::{eventually, TestServer};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+type Fields = BTreeMap<String, String>;
+
+// every event with the fields of the spans it happened in, and every closed span
+#[derive(Debug, Clone, Default)]
+struct Captured {
+    events: Arc<Mutex<Vec<Fields>>>,
+    closed: Arc<Mutex<Vec<(String, Fields)>>>,
+}
+
+struct SpanFields(Fields);
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Captured {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.clone());
+                }
+            }
+        }
+        event.record(&mut FieldVisitor(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                self.closed
+                    .lock()
+                    .unwrap()
+                    .push((span.name().to_string(), fields.clone()));
+            }
+        }
+    }
+}
+
+impl Captured {
+    fn event(&self, prefix: &str) -> Option<Fields> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .find(|fields| fields.get("message").is_some_and(|value| value.starts_with(prefix)))
+            .cloned()
+    }
+}
+
+// the server spawns its own tasks, only a global subscriber sees all of them
+fn captured() -> &'static Captured {
+    static CAPTURED: OnceLock<Captured> = OnceLock::new();
+    CAPTURED.get_or_init(|| {
+        let captured = Captured::default();
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(captured.clone()))
+            .expect("Could not install the capturing subscriber");
+        captured
+    })
+}
+
+#[tokio::test]
+async fn handler_logs_carry_the_session_and_message() {
+    let captured = captured();
+    let server = TestServer::start();
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth_create("spans_alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    alice.send(Message::message_edit(MessageId::new(4242), "changed")).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+
+    let message = "spans_alice cannot edit message 4242:";
+    eventually(|| async { captured.event(message).is_some() }).await;
+    let fields = captured.event(message).unwrap();
+    assert_eq!(fields.get("message_type").map(String::as_str), Some("MessageEdit"));
+    assert_eq!(fields.get("message_id").map(String::as_str), Some("4242"));
+    // the server hello is not counted, auth was the first frame
+    assert_eq!(fields.get("frame").map(String::as_str), Some("2"));
+    let session_id = fields.get("session_id").expect("No session id on the event").clone();
+    assert!(fields.contains_key("peer"));
+
+    // the handling time lands on the message span once it is done
+    eventually(|| async {
+        captured.closed.lock().unwrap().iter().any(|(name, fields)| {
+            name == "message"
+                && fields.get("message_id").is_some_and(|id| id == "4242")
+                && fields.contains_key("elapsed_us")
+        })
+    })
+    .await;
+
+    // log lines outside the handlers belong to the same session
+    let events = captured.events.lock().unwrap();
+    assert!(events.iter().any(|fields| {
+        fields.get("session_id") == Some(&session_id)
+            && fields
+                .get("message")
+                .is_some_and(|message| message == "Received message: MessageEdit")
+    }));
+}
+
+#[tokio::test]
+async fn client_logs_carry_the_connection_attempt() {
+    let captured = captured();
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("spans_bob", "secret").await;
+
+    eventually(|| async {
+        captured.events.lock().unwrap().iter().any(|fields| {
+            fields.get("attempt").is_some_and(|attempt| attempt == "0")
+                && fields
+                    .get("message")
+                    .is_some_and(|message| message == "Sending message: AuthCreate")
+        })
+    })
+    .await;
+}
@@ -0,0 +1,175 @@
+use std::fs;
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    auth::{challenge_response, derive_key, PLAIN_LOGIN_REQUIRED},
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::testing::{AccessLevel, RawConnection, TakeoverPolicy, TestServer};
+
+// the nonce comes with the hello, the first frame of every connection
+async fn greeted(server: &TestServer) -> (RawConnection, String) {
+    let mut connection = server.unchecked_connection();
+    let hello = connection.receive().await;
+    assert!(hello.is(MessageType::ServerHello), "Unexpected {:?}", hello);
+    let nonce = hello.payload().str_field(1).unwrap().to_string();
+    (connection, nonce)
+}
+
+fn answer(username: &str, password: &str, nonce: &str) -> Message {
+    let key = derive_key(username, password).unwrap();
+    Message::auth_challenge(username, &challenge_response(&key, nonce).unwrap())
+}
+
+#[tokio::test]
+async fn every_connection_gets_its_own_nonce() {
+    let server = TestServer::start();
+    let (_, first) = greeted(&server).await;
+    let (_, second) = greeted(&server).await;
+
+    assert!(!first.is_empty());
+    assert_ne!(first, second);
+}
+
+#[tokio::test]
+async fn a_captured_challenge_answer_cannot_be_replayed() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+
+    let (mut first, nonce) = greeted(&server).await;
+    let captured = answer("alice", "secret", &nonce);
+    first.send(captured.clone()).await;
+    assert!(first.receive().await.is(MessageType::AuthSuccess));
+
+    let (mut second, _) = greeted(&server).await;
+    second.send(captured).await;
+    let failure = second.receive().await;
+    assert!(failure.is(MessageType::AuthFailure), "Unexpected {:?}", failure);
+    assert_eq!(failure.payload().str_field(0), Ok("Invalid username or password"));
+
+    let (mut third, nonce) = greeted(&server).await;
+    third.send(answer("alice", "wrong", &nonce)).await;
+    assert!(third.receive().await.is(MessageType::AuthFailure));
+}
+
+#[tokio::test]
+async fn plain_passwords_can_be_refused() {
+    let server = TestServer::builder().with_plain_auth(false).start();
+
+    // the client answers the challenge and never needs the plain path
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut bob = server.client().await;
+    bob.login("bob", "secret").await;
+
+    let mut old_client = server.raw_connection().await;
+    old_client.send(Message::auth("bob", "secret")).await;
+    let failure = old_client.receive().await;
+    assert!(failure.is(MessageType::AuthFailure), "Unexpected {:?}", failure);
+    assert_eq!(
+        failure.payload().str_field(0),
+        Ok("Password login is disabled, use a client that answers the login challenge")
+    );
+}
+
+#[tokio::test]
+async fn accounts_without_a_key_get_one_from_a_password_login() {
+    let dir = std::env::temp_dir().join(format!("chat_rs_login_challenge_{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let source = TestServer::builder().with_data_dir(dir.join("data")).start();
+    source.create_admin("admin", "secret").await;
+    let mut admin = source.raw_connection().await;
+    admin.send(Message::auth("admin", "secret")).await;
+    assert!(admin.receive().await.is(MessageType::AuthSuccess));
+    admin.send(Message::admin_export_state()).await;
+    let exported = admin.receive().await;
+    let exported = exported.payload().str_field(0).unwrap().to_string();
+
+    // what an export from before login keys looks like
+    let document = JsonValue::parse(&fs::read_to_string(&exported).unwrap()).unwrap();
+    let users: Vec<JsonValue> = document
+        .get("users")
+        .and_then(JsonValue::as_array)
+        .unwrap()
+        .iter()
+        .map(|user| match user {
+            JsonValue::Object(fields) => {
+                JsonValue::Object(fields.iter().filter(|(key, _)| key != "auth_key").cloned().collect())
+            }
+            _ => panic!("Unexpected {:?}", user),
+        })
+        .collect();
+    let users_file = dir.join("users.json");
+    fs::write(
+        &users_file,
+        JsonValue::object()
+            .with("version", 2u64)
+            .with("users", users)
+            .to_string(),
+    )
+    .unwrap();
+
+    let locked = TestServer::builder()
+        .with_users_file(users_file.clone())
+        .with_plain_auth(false)
+        .start();
+    let (mut connection, nonce) = greeted(&locked).await;
+    connection.send(answer("admin", "secret", &nonce)).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+
+    // the client below stays logged in while the raw connection logs in again
+    let server = TestServer::builder()
+        .with_users_file(users_file)
+        .with_takeover_policy(TakeoverPolicy::Replace { same_peer: false })
+        .start();
+    let (mut connection, nonce) = greeted(&server).await;
+    connection.send(answer("admin", "secret", &nonce)).await;
+    let failure = connection.receive().await;
+    assert_eq!(failure.payload().str_field(0), Ok(PLAIN_LOGIN_REQUIRED));
+
+    // the client falls back to the password once, from then on the challenge works
+    let mut client = server.client().await;
+    client.login("admin", "secret").await;
+
+    let (mut connection, nonce) = greeted(&server).await;
+    connection.send(answer("admin", "secret", &nonce)).await;
+    let reply = connection.receive().await;
+    assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+}
+
+#[tokio::test]
+async fn password_changes_replace_the_key() {
+    let server = TestServer::builder()
+        .with_plain_auth(false)
+        .with_takeover_policy(TakeoverPolicy::Replace { same_peer: false })
+        .start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().send(Message::password_change("secret", "better")).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::PasswordChanged(_)))
+        .await;
+
+    let (mut connection, nonce) = greeted(&server).await;
+    connection.send(answer("alice", "secret", &nonce)).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+
+    let (mut connection, nonce) = greeted(&server).await;
+    connection.send(answer("alice", "better", &nonce)).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+}
+
+#[tokio::test]
+async fn the_debug_log_leaves_out_hashes_and_keys() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+
+    // the key answers challenges as well as the password does
+    let state = server.debug_state().await;
+    assert!(state.contains("\"alice\""), "{}", state);
+    assert!(!state.contains(&derive_key("alice", "secret").unwrap()), "{}", state);
+    assert!(!state.contains("$argon2"), "{}", state);
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageId, MessageType, HISTORY_DELETED, HISTORY_EDITED};
+use chat_server::application::testing::{eventually, DeletedHistory, RawConnection, TestServer};
+
+async fn history(connection: &mut RawConnection, peer: &str) -> Vec<Message> {
+    connection.send(Message::history_request(peer, 10)).await;
+    let mut entries = Vec::new();
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::HistoryEnd) {
+            assert_eq!(message.payload().u64_field(0), Ok(entries.len() as u64));
+            return entries;
+        }
+        assert!(message.is(MessageType::HistoryEntry), "Unexpected {:?}", message);
+        entries.push(message);
+    }
+}
+
+#[tokio::test]
+async fn edits_and_deletes_reach_the_recipient() {
+    let server = TestServer::start();
+
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().send_direct_message("bob", "helo").await;
+    let ClientEvent::Delivered { id: Some(id), .. } = alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await
+    else {
+        panic!("Delivery carried no message id");
+    };
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::DirectMessage { id: received, .. } if received == Some(id)));
+    assert_eq!(alice.client().recent_sent_ids().await, vec![id]);
+
+    alice.client().edit_message(id, "hello").await.unwrap();
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::MessageEdited { .. }))
+        .await;
+    assert!(
+        matches!(event, ClientEvent::MessageEdited { id: edited, sender, body, .. } if edited == id && sender == "alice" && body == "hello")
+    );
+    alice
+        .expect(|event| matches!(event, ClientEvent::MessageEdited { .. }))
+        .await;
+
+    alice.client().delete_message(id).await.unwrap();
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::MessageDeleted { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::MessageDeleted {
+            id,
+            sender: "alice".to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn only_the_sender_may_edit_or_delete() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let id = alice.send_direct("bob", "mine").await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    bob.send(Message::message_edit(id, "yours now")).await;
+    assert!(bob.receive().await.is(MessageType::Nack));
+    bob.send(Message::message_delete(id)).await;
+    assert!(bob.receive().await.is(MessageType::Nack));
+    alice
+        .send(Message::message_edit(MessageId::new(id.get() + 100), "unknown"))
+        .await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+
+    let entries = history(&mut alice, "bob").await;
+    assert_eq!(entries[0].payload().str_field(3), Ok("mine"));
+    assert_eq!(entries[0].payload().u64_field(5), Ok(0));
+}
+
+#[tokio::test]
+async fn client_only_addresses_its_recent_messages() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let error = alice.client().edit_message(42, "hello").await.unwrap_err();
+    assert_eq!(error, "Message 42 is not one of your recent messages");
+    assert!(alice.client().delete_message(42).await.is_err());
+}
+
+#[tokio::test]
+async fn edit_window_closes() {
+    let server = TestServer::builder()
+        .with_edit_window(Duration::from_millis(200))
+        .start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let early = alice.send_direct("bob", "early").await;
+    alice.send(Message::message_edit(early, "still early")).await;
+    assert!(alice.receive().await.is(MessageType::MessageEdited));
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    alice.send(Message::message_edit(early, "too late")).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+    alice.send(Message::message_delete(early)).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    assert!(bob.receive().await.is(MessageType::MessageEdited));
+    let entries = history(&mut bob, "alice").await;
+    assert_eq!(entries[0].payload().str_field(3), Ok("still early"));
+}
+
+#[tokio::test]
+async fn history_reflects_edits_and_tombstones() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    let first = alice.send_direct("bob", "first").await;
+    let second = alice.send_direct("bob", "second").await;
+    let third = alice.send_direct("bob", "third").await;
+    alice.send(Message::message_edit(first, "first, edited")).await;
+    assert!(alice.receive().await.is(MessageType::MessageEdited));
+    alice.send(Message::message_delete(second)).await;
+    assert!(alice.receive().await.is(MessageType::MessageDeleted));
+
+    for _ in 0..5 {
+        bob.receive().await;
+    }
+    let entries = history(&mut bob, "alice").await;
+    let summary: Vec<(MessageId, &str, u64)> = entries
+        .iter()
+        .map(|entry| {
+            let payload = entry.payload();
+            (
+                payload.message_id_field(0).unwrap(),
+                payload.str_field(3).unwrap(),
+                payload.u64_field(5).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (first, "first, edited", HISTORY_EDITED),
+            (second, "", HISTORY_DELETED),
+            (third, "third", 0),
+        ]
+    );
+
+    // a deleted message cannot be brought back by editing it
+    alice.send(Message::message_edit(second, "back")).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+}
+
+#[tokio::test]
+async fn history_can_omit_deletions() {
+    let server = TestServer::builder().with_deleted_history(DeletedHistory::Omit).start();
+    let mut alice = server.logged_in("alice").await;
+    server.logged_in("bob").await;
+
+    alice.send_direct("bob", "kept").await;
+    let removed = alice.send_direct("bob", "removed").await;
+    alice.send(Message::message_delete(removed)).await;
+    assert!(alice.receive().await.is(MessageType::MessageDeleted));
+
+    let entries = history(&mut alice, "bob").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].payload().str_field(3), Ok("kept"));
+}
+
+#[tokio::test]
+async fn offline_recipients_get_the_edit_after_the_message() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    bob.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+
+    let mut alice = server.logged_in("alice").await;
+    let id = alice.send_direct("bob", "typo").await;
+    alice.send(Message::message_edit(id, "fixed")).await;
+    assert!(alice.receive().await.is(MessageType::MessageEdited));
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert!(bob.receive().await.is(MessageType::UnreadSummary));
+
+    let original = bob.receive().await;
+    assert!(original.is(MessageType::DirectMessageReceive));
+    assert_eq!(original.message_id(), Some(id));
+    let edit = bob.receive().await;
+    assert!(edit.is(MessageType::MessageEdited));
+    assert_eq!(edit.payload().str_field(2), Ok("fixed"));
+}
+
+#[tokio::test]
+async fn history_pages_walk_back_without_overlap() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    server.logged_in("bob").await;
+
+    let mut ids = Vec::new();
+    for body in ["one", "two", "three", "four", "five"] {
+        ids.push(alice.send_direct("bob", body).await);
+    }
+
+    let mut pages = Vec::new();
+    let mut request = Message::history_request("bob", 2);
+    loop {
+        alice.send(request).await;
+        let mut page = Vec::new();
+        loop {
+            let message = alice.receive().await;
+            if message.is(MessageType::HistoryEnd) {
+                break;
+            }
+            page.push(message.message_id().unwrap());
+        }
+        let Some(oldest) = page.first().copied() else {
+            break;
+        };
+        pages.push(page);
+        request = Message::history_request_before("bob", 2, oldest);
+    }
+
+    assert_eq!(pages, vec![vec![ids[3], ids[4]], vec![ids[1], ids[2]], vec![ids[0]]]);
+}
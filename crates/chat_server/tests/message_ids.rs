@@ -0,0 +1,111 @@
+use std::fs;
+
+use chat_core::protocol::{Message, MessageId, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+// every frame up to the one that ends the listing, which is not included
+async fn collect_until(connection: &mut RawConnection, end: MessageType) -> Vec<Message> {
+    let mut messages = Vec::new();
+    loop {
+        let message = connection.receive().await;
+        if message.is(end) {
+            return messages;
+        }
+        messages.push(message);
+    }
+}
+
+#[tokio::test]
+async fn one_id_follows_a_message_everywhere() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    bob.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+
+    let mut alice = server.logged_in("alice").await;
+    alice.send(Message::direct_message_send("bob", "queued thing")).await;
+    let ack = alice.receive().await;
+    assert!(ack.is(MessageType::Ack));
+    let id = ack.message_id().expect("The ACK carried no message id");
+
+    alice.send(Message::message_edit(id, "queued thing, edited")).await;
+    let edited = alice.receive().await;
+    assert!(edited.is(MessageType::MessageEdited));
+    assert_eq!(edited.message_id(), Some(id));
+
+    // the offline queue hands out the message and its edit under the id the ACK named
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert!(bob.receive().await.is(MessageType::UnreadSummary));
+    let queued = bob.receive().await;
+    assert!(queued.is(MessageType::DirectMessageReceive));
+    assert_eq!(queued.message_id(), Some(id));
+    let edit = bob.receive().await;
+    assert!(edit.is(MessageType::MessageEdited));
+    assert_eq!(edit.message_id(), Some(id));
+
+    // a live relay gets the next id, with the same value on both ends
+    alice.send(Message::direct_message_send("bob", "live thing")).await;
+    let ack = alice.receive().await;
+    let live = ack.message_id().expect("The ACK carried no message id");
+    assert!(live > id);
+    let relayed = bob.receive().await;
+    assert!(relayed.is(MessageType::DirectMessageReceive));
+    assert_eq!(relayed.message_id(), Some(live));
+
+    bob.send(Message::history_request("alice", 10)).await;
+    let history: Vec<Option<MessageId>> = collect_until(&mut bob, MessageType::HistoryEnd)
+        .await
+        .iter()
+        .map(Message::message_id)
+        .collect();
+    assert_eq!(history, vec![Some(id), Some(live)]);
+
+    bob.send(Message::search_request("thing", Some("alice"), 10)).await;
+    let mut found: Vec<Option<MessageId>> = collect_until(&mut bob, MessageType::SearchEnd)
+        .await
+        .iter()
+        .map(Message::message_id)
+        .collect();
+    found.sort();
+    assert_eq!(found, vec![Some(id), Some(live)]);
+
+    alice.send(Message::message_delete(live)).await;
+    let deleted = alice.receive().await;
+    assert!(deleted.is(MessageType::MessageDeleted));
+    assert_eq!(deleted.message_id(), Some(live));
+    let deleted = bob.receive().await;
+    assert!(deleted.is(MessageType::MessageDeleted));
+    assert_eq!(deleted.message_id(), Some(live));
+}
+
+#[tokio::test]
+async fn ids_go_on_after_a_restart() {
+    let dir = TestServer::scratch_dir("restart");
+    let first = TestServer::builder().with_data_dir(dir.clone()).start();
+    let _bob = first.logged_in("bob").await;
+    let mut alice = first.logged_in("alice").await;
+    let mut before = Vec::new();
+    for body in ["one", "two", "three"] {
+        before.push(alice.send_direct("bob", body).await);
+    }
+    drop(alice);
+    drop(first);
+
+    // a client that kept its transcript must not see an old id on a new message
+    let second = TestServer::builder().with_data_dir(dir.clone()).start();
+    let _bob = second.logged_in("bob").await;
+    let mut alice = second.logged_in("alice").await;
+    let after = alice.send_direct("bob", "four").await;
+    assert!(before.iter().all(|id| *id < after), "{:?} after {:?}", after, before);
+    assert!(alice.send_direct("bob", "five").await > after);
+    drop(alice);
+    drop(second);
+
+    let third = TestServer::builder().with_data_dir(dir.clone()).start();
+    let _bob = third.logged_in("bob").await;
+    let mut alice = third.logged_in("alice").await;
+    assert!(alice.send_direct("bob", "six").await > after);
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,240 @@
+use chat_client::client::{ClientEvent, ClientOptions, SigningIdentity, Verification};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{RawConnection, TestClient, TestServer};
+
+// a sender that signs by hand, with whatever key it likes over whatever body it likes
+async fn signer(server: &TestServer, username: &str, identity: &SigningIdentity) -> RawConnection {
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create(username, "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    register(&mut connection, identity).await;
+    connection
+}
+
+async fn register(connection: &mut RawConnection, identity: &SigningIdentity) {
+    connection
+        .send(Message::signing_key_set(Some(&identity.public_key())))
+        .await;
+    let reply = connection.receive().await;
+    assert!(reply.is(MessageType::SigningKey), "Unexpected {:?}", reply);
+}
+
+async fn send_signed(connection: &mut RawConnection, identity: &SigningIdentity, body: &str, signed_body: &str) {
+    let signature = identity.sign("alice", "bob", signed_body).unwrap();
+    connection
+        .send(Message::direct_message_send("bob", body).with_signature(&signature))
+        .await;
+    assert!(connection.receive().await.is(MessageType::Ack));
+}
+
+// the verdict shown with the message
+async fn received(bob: &mut TestClient, expected: &str) -> Verification {
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    let ClientEvent::DirectMessage { body, verification, .. } = event else {
+        unreachable!();
+    };
+    assert_eq!(body, expected);
+    verification
+}
+
+// the one it settles on once the sender's key is known, nothing else may come first
+async fn settled(bob: &mut TestClient) -> Verification {
+    match bob.next_event().await {
+        ClientEvent::MessageVerified { verification, .. } => verification,
+        event => panic!("Expected a verdict, got {:?}", event),
+    }
+}
+
+#[tokio::test]
+async fn signed_messages_verify_against_the_key_trusted_on_first_use() {
+    let server = TestServer::start();
+    let alice_key = SigningIdentity::from_seed([1; 32]);
+    let mut alice = server
+        .client_with(ClientOptions::new().with_signing_identity(alice_key.clone()))
+        .await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    alice.client().send_direct_message("bob", "first").await;
+    // nothing to check it with yet, the key the server has is trusted from here on
+    assert_eq!(received(&mut bob, "first").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Verified);
+    assert_eq!(
+        bob.client().signing_fingerprint(Some("Alice")).await,
+        Some(alice_key.fingerprint())
+    );
+
+    alice.client().send_direct_message("bob", "second").await;
+    assert_eq!(received(&mut bob, "second").await, Verification::Verified);
+
+    // unsigned senders stay unremarkable
+    let mut carol = server.client().await;
+    carol.register("carol", "secret").await;
+    carol.client().send_direct_message("bob", "hi").await;
+    assert_eq!(received(&mut bob, "hi").await, Verification::Unsigned);
+}
+
+#[tokio::test]
+async fn a_body_changed_after_signing_is_flagged() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let key = SigningIdentity::from_seed([1; 32]);
+    let mut alice = signer(&server, "alice", &key).await;
+
+    send_signed(&mut alice, &key, "pay 10", "pay 10").await;
+    assert_eq!(received(&mut bob, "pay 10").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Verified);
+
+    // shown as unverified until the server confirms the key did not change
+    send_signed(&mut alice, &key, "pay 1000", "pay 10").await;
+    assert_eq!(received(&mut bob, "pay 1000").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Invalid);
+}
+
+#[tokio::test]
+async fn a_changed_key_is_announced_and_trusted_only_on_request() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let old_key = SigningIdentity::from_seed([1; 32]);
+    let new_key = SigningIdentity::from_seed([2; 32]);
+    let mut alice = signer(&server, "alice", &old_key).await;
+
+    send_signed(&mut alice, &old_key, "hello", "hello").await;
+    assert_eq!(received(&mut bob, "hello").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Verified);
+    assert!(bob.client().trust_signing_key("alice").await.is_err());
+
+    register(&mut alice, &new_key).await;
+    send_signed(&mut alice, &new_key, "new laptop", "new laptop").await;
+    assert_eq!(received(&mut bob, "new laptop").await, Verification::Unverified);
+    let ClientEvent::SigningKeyChanged {
+        username,
+        trusted,
+        announced,
+    } = bob.next_event().await
+    else {
+        panic!("Expected the new key to be announced");
+    };
+    assert_eq!(username, "alice");
+    assert_eq!(trusted, old_key.fingerprint());
+    assert_eq!(announced, new_key.fingerprint());
+    // signed by the account, just not with the key bob trusts, the message stays unverified without a second verdict
+    send_signed(&mut alice, &new_key, "still me", "still me").await;
+    assert_eq!(received(&mut bob, "still me").await, Verification::Unverified);
+
+    assert_eq!(bob.client().trust_signing_key("alice").await, Ok(new_key.fingerprint()));
+    send_signed(&mut alice, &new_key, "thanks", "thanks").await;
+    assert_eq!(received(&mut bob, "thanks").await, Verification::Verified);
+}
+
+#[tokio::test]
+async fn the_key_survives_an_export() {
+    let server = TestServer::start();
+    let key = SigningIdentity::from_seed([1; 32]);
+    let _alice = signer(&server, "alice", &key).await;
+
+    let exported = server.export_users().await;
+    assert!(
+        exported.contains(&chat_core::trace::to_hex(&key.public_key())),
+        "{}",
+        exported
+    );
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth_create("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    bob.send(Message::signing_key_request("ALICE")).await;
+    assert_eq!(
+        bob.receive().await.signing_key_of(),
+        Ok(("alice".to_string(), Some(key.public_key())))
+    );
+    bob.send(Message::signing_key_request("nobody")).await;
+    assert_eq!(bob.receive().await.signing_key_of(), Ok(("nobody".to_string(), None)));
+}
+
+#[tokio::test]
+async fn signatures_are_kept_with_the_history() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let key = SigningIdentity::from_seed([1; 32]);
+    let mut alice = signer(&server, "alice", &key).await;
+
+    send_signed(&mut alice, &key, "pay 10", "pay 10").await;
+    assert_eq!(received(&mut bob, "pay 10").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Verified);
+    send_signed(&mut alice, &key, "pay 1000", "pay 10").await;
+    assert_eq!(received(&mut bob, "pay 1000").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Invalid);
+    alice.send(Message::direct_message_send("bob", "plain")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert_eq!(received(&mut bob, "plain").await, Verification::Unsigned);
+
+    // relayed as it came, the server never checks it
+    alice.send(Message::history_request("bob", 10)).await;
+    let mut signatures = Vec::new();
+    loop {
+        let entry = alice.receive().await;
+        if entry.is(MessageType::HistoryEnd) {
+            break;
+        }
+        signatures.push(entry.signature().map(<[u8]>::to_vec));
+    }
+    let signature = key.sign("alice", "bob", "pay 10").unwrap();
+    assert_eq!(signatures, [Some(signature.clone()), Some(signature), None]);
+
+    // told apart from each other the same way as when they arrived
+    assert!(bob.client().request_history("alice", 10, None).await);
+    let mut verdicts = Vec::new();
+    while verdicts.len() < 3 {
+        if let ClientEvent::HistoryEntry { body, verification, .. } = bob.next_event().await {
+            verdicts.push((body, verification));
+        }
+    }
+    assert_eq!(
+        verdicts,
+        [
+            ("pay 10".to_string(), Verification::Verified),
+            ("pay 1000".to_string(), Verification::Unverified),
+            ("plain".to_string(), Verification::Unsigned),
+        ]
+    );
+    bob.expect(|event| matches!(event, ClientEvent::HistoryEnd(_))).await;
+    assert_eq!(settled(&mut bob).await, Verification::Invalid);
+}
+
+#[tokio::test]
+async fn renamed_history_is_unsigned_instead_of_invalid() {
+    let server = TestServer::start();
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let key = SigningIdentity::from_seed([1; 32]);
+    let mut alice = signer(&server, "alice", &key).await;
+
+    send_signed(&mut alice, &key, "pay 10", "pay 10").await;
+    assert_eq!(received(&mut bob, "pay 10").await, Verification::Unverified);
+    assert_eq!(settled(&mut bob).await, Verification::Verified);
+
+    alice.send(Message::rename_account("alicia", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::UserRenamed));
+    bob.expect(|event| matches!(event, ClientEvent::UserRenamed { .. }))
+        .await;
+
+    // signed as alice, the history now says alicia
+    assert!(bob.client().request_history("alicia", 10, None).await);
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::HistoryEntry { .. }))
+        .await;
+    assert!(
+        matches!(&event, ClientEvent::HistoryEntry { sender, verification, .. }
+            if sender == "alicia" && *verification == Verification::Unsigned),
+        "{:?}",
+        event
+    );
+    bob.expect(|event| matches!(event, ClientEvent::HistoryEnd(_))).await;
+}
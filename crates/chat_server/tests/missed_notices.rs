@@ -0,0 +1,160 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType, NOTICE_ACCESS_LEVEL_CHANGED, NOTICE_PASSWORD_RESET, NOTICE_TRUNCATED};
+use chat_server::application::testing::{eventually, RawConnection, TestClient, TestServer};
+use chrono::Utc;
+
+async fn register_and_leave(server: &TestServer, username: &str) {
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create(username, "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn set_level(admin: &mut TestClient, username: &str, level: &str) {
+    admin
+        .client()
+        .send(Message::admin_set_access_level(username, level))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+        .await;
+}
+
+fn notice(message: &Message) -> (&str, &str) {
+    assert!(
+        message.is(MessageType::MissedNotice),
+        "Expected a missed notice, got {:?}",
+        message
+    );
+    let payload = message.payload();
+    (payload.str_field(0).unwrap(), payload.str_field(1).unwrap())
+}
+
+// a time sync reply proves nothing else was queued before it
+async fn expect_nothing_queued(connection: &mut RawConnection) {
+    connection.send(Message::time_sync(Utc::now())).await;
+    assert!(connection.receive().await.is(MessageType::TimeSyncReply));
+}
+
+#[tokio::test]
+async fn missed_notices_arrive_after_login_before_offline_messages() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    register_and_leave(&server, "alice").await;
+
+    set_level(&mut admin, "alice", "moderator").await;
+    admin.client().reset_password("alice", "reset-secret").await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::PasswordChanged(_)))
+        .await;
+    admin.client().send_direct_message("alice", "welcome back").await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "reset-secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    let first = alice.receive().await;
+    assert_eq!(
+        notice(&first),
+        (
+            NOTICE_ACCESS_LEVEL_CHANGED,
+            "Your access level was changed to moderator"
+        )
+    );
+    let second = alice.receive().await;
+    assert_eq!(
+        notice(&second),
+        (NOTICE_PASSWORD_RESET, "Your password was reset by an administrator")
+    );
+    assert!(second.payload().timestamp_field(2).unwrap() >= first.payload().timestamp_field(2).unwrap());
+
+    assert!(alice.receive().await.is(MessageType::UnreadSummary));
+    let message = alice.receive().await;
+    assert!(message.is(MessageType::DirectMessageReceive));
+    assert_eq!(message.payload().str_field(1), Ok("welcome back"));
+
+    // the backlog is delivered once
+    alice.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "reset-secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    // the message was never marked as read, so it is still counted
+    assert!(alice.receive().await.is(MessageType::UnreadSummary));
+    expect_nothing_queued(&mut alice).await;
+}
+
+#[tokio::test]
+async fn full_backlog_drops_the_oldest_with_a_marker() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    register_and_leave(&server, "alice").await;
+
+    let levels: Vec<&str> = (0..53).map(|i| if i % 2 == 0 { "moderator" } else { "user" }).collect();
+    for level in &levels {
+        set_level(&mut admin, "alice", level).await;
+    }
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    let marker = alice.receive().await;
+    assert_eq!(notice(&marker), (NOTICE_TRUNCATED, "3 notices truncated"));
+
+    for level in &levels[3..] {
+        let message = alice.receive().await;
+        let expected = format!("Your access level was changed to {}", level);
+        assert_eq!(notice(&message), (NOTICE_ACCESS_LEVEL_CHANGED, expected.as_str()));
+    }
+    expect_nothing_queued(&mut alice).await;
+}
+
+#[tokio::test]
+async fn connected_users_get_live_updates_instead() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    set_level(&mut admin, "alice", "moderator").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+        .await;
+    alice.disconnect().await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    expect_nothing_queued(&mut alice).await;
+}
+
+#[tokio::test]
+async fn client_reports_missed_notices() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    register_and_leave(&server, "alice").await;
+    set_level(&mut admin, "alice", "moderator").await;
+
+    let mut alice = server.client().await;
+    alice.login("alice", "secret").await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::MissedNotice { .. }))
+        .await;
+    let ClientEvent::MissedNotice {
+        kind,
+        detail,
+        occurred_at,
+    } = event
+    else {
+        unreachable!();
+    };
+    assert_eq!(kind, NOTICE_ACCESS_LEVEL_CHANGED);
+    assert_eq!(detail, "Your access level was changed to moderator");
+    assert!(occurred_at <= Utc::now());
+}
@@ -0,0 +1,191 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, NOTICE_KICKED};
+use chat_server::application::testing::{eventually, AccessLevel, TestClient, TestServer};
+
+async fn moderator_server() -> (TestServer, TestClient) {
+    let server = TestServer::start();
+    server.create_user("mod", "secret", AccessLevel::Moderator).await;
+
+    let mut moderator = server.client().await;
+    moderator.login("mod", "secret").await;
+    (server, moderator)
+}
+
+async fn expect_rejected(client: &mut TestClient, message: Message) {
+    client.client().send(message).await;
+    client
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn moderator_kicks_a_user() {
+    let (server, mut moderator) = moderator_server().await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    moderator.client().send(Message::admin_kick_user("alice", "spam")).await;
+    let event = moderator
+        .expect(|event| matches!(event, ClientEvent::UserKicked { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::UserKicked {
+            username: "alice".to_string(),
+            reason: "spam".to_string(),
+        }
+    );
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::SecurityNotice { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::SecurityNotice {
+            kind: NOTICE_KICKED.to_string(),
+            detail: "spam".to_string(),
+        }
+    );
+    // a kicked client stays gone instead of reconnecting
+    alice.expect(|event| matches!(event, ClientEvent::Closed)).await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.actor() == "mod" && entry.action() == "kick" && entry.detail() == "alice (spam)"));
+}
+
+#[tokio::test]
+async fn moderator_views_stats() {
+    let (_server, mut moderator) = moderator_server().await;
+
+    moderator.client().send(Message::admin_server_stats()).await;
+    moderator
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn moderator_cannot_kick_equal_or_higher_ranks() {
+    let (server, mut moderator) = moderator_server().await;
+    server.create_admin("admin", "secret").await;
+    server.create_user("other-mod", "secret", AccessLevel::Moderator).await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut other = server.client().await;
+    other.login("other-mod", "secret").await;
+
+    expect_rejected(&mut moderator, Message::admin_kick_user("admin", "bye")).await;
+    expect_rejected(&mut moderator, Message::admin_kick_user("other-mod", "bye")).await;
+    expect_rejected(&mut moderator, Message::admin_kick_user("nobody", "bye")).await;
+    assert!(server.is_logged_in("admin").await);
+    assert!(server.is_logged_in("other-mod").await);
+}
+
+#[tokio::test]
+async fn admin_only_actions_are_rejected_for_moderators() {
+    let (server, mut moderator) = moderator_server().await;
+    server.create_user("alice", "secret", AccessLevel::User).await;
+
+    expect_rejected(&mut moderator, Message::server_shutdown(0)).await;
+    expect_rejected(&mut moderator, Message::admin_set_access_level("alice", "admin")).await;
+    expect_rejected(&mut moderator, Message::admin_set_server_mode("draining", None)).await;
+    expect_rejected(&mut moderator, Message::admin_reset_password("alice", "x")).await;
+    expect_rejected(&mut moderator, Message::admin_set_log_level("trace", None)).await;
+    assert!(server.is_running());
+}
+
+#[tokio::test]
+async fn users_cannot_kick() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    expect_rejected(&mut alice, Message::admin_kick_user("bob", "bye")).await;
+    assert!(server.is_logged_in("bob").await);
+}
+
+#[tokio::test]
+async fn admin_promotes_and_demotes() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    expect_rejected(&mut alice, Message::admin_kick_user("bob", "bye")).await;
+
+    admin
+        .client()
+        .send(Message::admin_set_access_level("alice", "moderator"))
+        .await;
+    let promoted = ClientEvent::AccessLevelChanged {
+        username: "alice".to_string(),
+        level: "moderator".to_string(),
+    };
+    assert_eq!(
+        admin
+            .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+            .await,
+        promoted
+    );
+    assert_eq!(
+        alice
+            .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+            .await,
+        promoted
+    );
+
+    // the new level applies to the live session
+    alice.client().send(Message::admin_kick_user("bob", "bye")).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::UserKicked { .. }))
+        .await;
+
+    admin
+        .client()
+        .send(Message::admin_set_access_level("alice", "user"))
+        .await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::AccessLevelChanged { .. }))
+        .await;
+    expect_rejected(&mut alice, Message::admin_server_stats()).await;
+
+    let details: Vec<String> = server
+        .audit_entries()
+        .await
+        .iter()
+        .filter(|entry| entry.action() == "set_access_level")
+        .map(|entry| entry.detail().to_string())
+        .collect();
+    assert_eq!(details, ["alice: user -> moderator", "alice: moderator -> user"]);
+}
+
+#[tokio::test]
+async fn invalid_access_level_changes_are_rejected() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    expect_rejected(&mut admin, Message::admin_set_access_level("admin", "user")).await;
+    expect_rejected(&mut admin, Message::admin_set_access_level("nobody", "user")).await;
+    expect_rejected(&mut admin, Message::admin_set_access_level("admin", "owner")).await;
+
+    // the admin kept their own level
+    admin.client().send(Message::admin_server_stats()).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+}
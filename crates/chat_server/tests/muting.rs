@@ -0,0 +1,106 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn muted(connection: &mut RawConnection) -> Vec<String> {
+    let message = connection.receive().await;
+    assert!(message.is(MessageType::MutedSenders), "Unexpected {:?}", message);
+    message.muted_sender_names().unwrap()
+}
+
+#[tokio::test]
+async fn a_muted_sender_is_delivered_without_a_notify_hint() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+    let mut carol = server.logged_in("carol").await;
+
+    bob.send(Message::mute_add("alice")).await;
+    assert_eq!(muted(&mut bob).await, vec!["alice".to_string()]);
+
+    alice.send_direct("bob", "beep").await;
+    let message = bob.receive().await;
+    assert!(message.is(MessageType::DirectMessageReceive));
+    assert_eq!(message.payload().str_field(1), Ok("beep"));
+    assert!(message.is_muted());
+
+    carol.send_direct("bob", "hi").await;
+    assert!(!bob.receive().await.is_muted());
+
+    bob.send(Message::mute_remove("alice")).await;
+    assert!(muted(&mut bob).await.is_empty());
+    alice.send_direct("bob", "beep").await;
+    assert!(!bob.receive().await.is_muted());
+}
+
+#[tokio::test]
+async fn a_muted_sender_adds_nothing_to_the_unread_count() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    bob.send(Message::mute_add("Alice")).await;
+    assert!(bob.receive().await.is(MessageType::Nack));
+    let mut alice = server.logged_in("alice").await;
+    let mut carol = server.logged_in("carol").await;
+    // typed in any case, listed as registered
+    bob.send(Message::mute_add("ALICE")).await;
+    assert_eq!(muted(&mut bob).await, vec!["alice".to_string()]);
+    leave(&server, bob, "bob").await;
+
+    alice.send_direct("bob", "one").await;
+    alice.send_direct("bob", "two").await;
+    carol.send_direct("bob", "hi").await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    let summary = bob.receive().await;
+    assert!(summary.is(MessageType::UnreadSummary), "Unexpected {:?}", summary);
+    assert_eq!(summary.unread_counts(), Ok(vec![("carol".to_string(), 1)]));
+
+    // queued all the same, history is not affected either
+    let delivered: Vec<(String, bool)> = [bob.receive().await, bob.receive().await, bob.receive().await]
+        .iter()
+        .map(|message| (message.payload().str_field(1).unwrap().to_string(), message.is_muted()))
+        .collect();
+    assert_eq!(
+        delivered,
+        vec![
+            ("one".to_string(), true),
+            ("two".to_string(), true),
+            ("hi".to_string(), false)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_client_knows_who_it_muted() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    bob.expect(|event| matches!(event, ClientEvent::MutedSenders(_))).await;
+
+    assert!(bob.client().mute("alice").await);
+    let event = bob.expect(|event| matches!(event, ClientEvent::MutedSenders(_))).await;
+    assert_eq!(event, ClientEvent::MutedSenders(vec!["alice".to_string()]));
+    bob.disconnect().await;
+
+    // the list is kept on the server and comes back with the next login
+    let mut bob = server.client().await;
+    bob.login("bob", "secret").await;
+    bob.expect(|event| matches!(event, ClientEvent::MutedSenders(senders) if !senders.is_empty()))
+        .await;
+    assert!(bob.client().is_muted("alice").await);
+
+    alice.client().send_direct_message("bob", "beep").await;
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::DirectMessage { muted: true, .. }));
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType, NOTICE_MESSAGE_EXPIRED};
+use chat_server::application::testing::{eventually, RawConnection, TestClient, TestServer};
+
+const OFFLINE_TTL: Duration = Duration::from_millis(300);
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn queue_counters(admin: &mut TestClient) -> (u64, u64) {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats {
+        queued_messages,
+        expired_messages,
+        ..
+    } = event
+    else {
+        unreachable!();
+    };
+    (queued_messages, expired_messages)
+}
+
+#[tokio::test]
+async fn the_purge_tells_the_sender_what_expired() {
+    let server = TestServer::builder().with_offline_ttl(OFFLINE_TTL).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    let id = alice.send_direct("bob", "are you there?").await;
+
+    // nobody logs in, the background purge has to find it
+    let expired = alice.receive().await;
+    assert!(expired.is(MessageType::MessageExpired), "Unexpected {:?}", expired);
+    assert_eq!(expired.payload().u64_field(0), Ok(id.get()));
+    assert_eq!(expired.payload().str_field(1), Ok("bob"));
+    assert_eq!(queue_counters(&mut admin).await, (0, 1));
+
+    // neither the message nor its unread count is waiting for bob
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    bob.send_direct("alice", "sorry").await;
+}
+
+#[tokio::test]
+async fn offline_senders_find_out_after_their_next_login() {
+    let server = TestServer::builder().with_offline_ttl(OFFLINE_TTL).start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    let id = alice.send_direct("bob", "later").await;
+    leave(&server, alice, "alice").await;
+    tokio::time::sleep(OFFLINE_TTL * 2).await;
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    let notice = alice.receive().await;
+    assert!(notice.is(MessageType::MissedNotice), "Unexpected {:?}", notice);
+    assert_eq!(notice.payload().str_field(0), Ok(NOTICE_MESSAGE_EXPIRED));
+    assert!(notice
+        .payload()
+        .str_field(1)
+        .unwrap()
+        .contains(&format!("#{} to bob", id.get())));
+}
+
+#[tokio::test]
+async fn a_message_ttl_overrides_the_queue_default() {
+    let server = TestServer::builder().with_offline_ttl(OFFLINE_TTL).start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    alice
+        .send_acked(Message::direct_message_send_with_ttl("bob", "still here", 3600))
+        .await;
+    tokio::time::sleep(OFFLINE_TTL * 2).await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert!(bob.receive().await.is(MessageType::UnreadSummary));
+    let message = bob.receive().await;
+    assert_eq!(message.payload().str_field(1), Ok("still here"));
+}
+
+#[tokio::test]
+async fn stats_show_the_queue_depth() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    alice.send_direct("bob", "one").await;
+    alice.send_direct("bob", "two").await;
+    assert_eq!(queue_counters(&mut admin).await, (2, 0));
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert_eq!(queue_counters(&mut admin).await, (0, 0));
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+use chat_core::{
+    protocol::{Message, MessageBuilder, MessageType},
+    transport::{memory, Listener},
+};
+use chat_server::application::testing::{eventually, within, TestServer};
+use chrono::{DateTime, Utc};
+
+fn sent_at(message: &Message) -> DateTime<Utc> {
+    let timestamp = message.payload().str_field(2).expect("relayed messages are stamped");
+    DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)
+}
+
+#[tokio::test]
+async fn relayed_messages_carry_the_server_receive_time() {
+    let server = TestServer::start();
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth_create("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let before = Utc::now();
+    alice.client().send_direct_message("bob", "hello bob").await;
+
+    let relayed = bob.receive().await;
+    assert!(relayed.is(MessageType::DirectMessageReceive));
+    assert!(sent_at(&relayed) >= before - chrono::Duration::seconds(1));
+    assert!(sent_at(&relayed) <= Utc::now());
+}
+
+#[tokio::test]
+async fn offline_messages_keep_their_original_stamp() {
+    let server = TestServer::start();
+
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    bob.disconnect().await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send_direct_message("bob", "while you were away").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { recipient, .. } if recipient == "bob"))
+        .await;
+    let queued_at = Utc::now();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth("bob", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    assert!(connection.receive().await.is(MessageType::UnreadSummary));
+
+    let delivered = connection.receive().await;
+    assert!(delivered.is(MessageType::DirectMessageReceive));
+    assert_eq!(delivered.payload().str_field(0), Ok("alice"));
+    assert_eq!(delivered.payload().str_field(1), Ok("while you were away"));
+    assert!(sent_at(&delivered) <= queued_at);
+}
+
+#[tokio::test]
+async fn client_reports_the_server_stamp() {
+    let server = TestServer::start();
+
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    bob.disconnect().await;
+    eventually(|| async { !server.is_logged_in("bob").await }).await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send_direct_message("bob", "first").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+    let queued_at = Utc::now();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut bob = server.client().await;
+    bob.login("bob", "secret").await;
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    let ClientEvent::DirectMessage { sender, sent_at, .. } = event else {
+        unreachable!();
+    };
+    assert_eq!(sender, "alice");
+    assert!(sent_at <= queued_at);
+}
+
+#[tokio::test]
+async fn unstamped_messages_fall_back_to_local_time() {
+    let (mut listener, connector) = memory::network();
+    let legacy_server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        // older servers relay sender and body only
+        let unstamped = MessageBuilder::new(MessageType::DirectMessageReceive)
+            .with_field(b"alice".to_vec())
+            .with_field(b"hi".to_vec())
+            .build();
+        unstamped.send(&mut stream).await.unwrap();
+        stream
+    });
+
+    let before = Utc::now();
+    let (_client, mut events) = ChatClient::connect_with(connector, ClientOptions::new()).await.unwrap();
+    let _stream = legacy_server.await.unwrap();
+
+    let event = within(async {
+        loop {
+            if let Some(event @ ClientEvent::DirectMessage { .. }) = events.recv().await {
+                return event;
+            }
+        }
+    })
+    .await;
+    let ClientEvent::DirectMessage { sent_at, .. } = event else {
+        unreachable!();
+    };
+    assert!(sent_at >= before);
+}
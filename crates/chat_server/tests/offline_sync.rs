@@ -0,0 +1,152 @@
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::protocol::{Message, MessageType, OfflineSummary};
+use chat_server::application::testing::{eventually, AccessLevel, FloodLimits, RawConnection, TestClient, TestServer};
+
+fn sync_server() -> TestServer {
+    TestServer::builder()
+        .with_flood_limits(FloodLimits {
+            frames_per_second: 10_000,
+            ..FloodLimits::default()
+        })
+        .start()
+}
+
+// bob sends them one at a time while alice is away
+async fn queue_messages(server: &TestServer, count: usize) -> Vec<String> {
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth_create("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+
+    let bodies: Vec<String> = (0..count).map(|n| format!("message {}", n)).collect();
+    for body in &bodies {
+        bob.send(Message::direct_message_send("alice", body)).await;
+        assert!(bob.receive().await.is(MessageType::Ack));
+    }
+    bodies
+}
+
+// logs in asking to pull the queue, and reads up to the summary
+async fn syncing_login(server: &TestServer) -> (RawConnection, OfflineSummary) {
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::sync_select()).await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    loop {
+        let message = alice.receive().await;
+        assert!(
+            !message.is(MessageType::DirectMessageReceive),
+            "Queued messages were flushed"
+        );
+        if message.is(MessageType::OfflineSummary) {
+            return (alice, message.offline_counts().unwrap());
+        }
+    }
+}
+
+fn body(message: &Message) -> String {
+    assert!(
+        message.is(MessageType::DirectMessageReceive),
+        "Expected a message, got {:?}",
+        message
+    );
+    message.payload().str_field(1).unwrap().to_string()
+}
+
+async fn received_bodies(client: &mut TestClient, count: usize) -> Vec<String> {
+    let mut bodies = Vec::new();
+    while bodies.len() < count {
+        if let ClientEvent::DirectMessage { body, .. } = client
+            .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+            .await
+        {
+            bodies.push(body);
+        }
+    }
+    bodies
+}
+
+#[tokio::test]
+async fn a_batch_cut_short_resumes_after_the_last_message_that_arrived() {
+    let server = sync_server();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let bodies = queue_messages(&server, 30).await;
+
+    let (mut alice, summary) = syncing_login(&server).await;
+    assert_eq!(summary.total, 30);
+    assert_eq!(summary.peers, vec![("bob".to_string(), 30)]);
+
+    alice.send(Message::sync_request(summary.cursor, 10)).await;
+    let batch = alice.receive().await.sync_sequences().unwrap();
+    assert_eq!((batch.sequences.len(), batch.remaining), (10, 20));
+    let mut received = Vec::new();
+    for _ in 0..4 {
+        received.push(body(&alice.receive().await));
+    }
+    drop(alice);
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+
+    // nothing was acknowledged yet, the cursor of the fourth message skips what did arrive
+    let (mut alice, summary) = syncing_login(&server).await;
+    assert_eq!(summary.total, 30);
+    alice.send(Message::sync_request(batch.sequences[3], 100)).await;
+    let batch = alice.receive().await.sync_sequences().unwrap();
+    assert_eq!((batch.sequences.len(), batch.remaining), (26, 0));
+    for _ in 0..26 {
+        received.push(body(&alice.receive().await));
+    }
+    assert_eq!(received, bodies);
+
+    alice
+        .send(Message::sync_request(*batch.sequences.last().unwrap(), 0))
+        .await;
+    let batch = alice.receive().await.sync_sequences().unwrap();
+    assert_eq!((batch.sequences.len(), batch.remaining), (0, 0));
+    assert_eq!(server.queued_messages().await, 0);
+}
+
+#[tokio::test]
+async fn the_client_pulls_hundreds_of_messages_exactly_once_across_a_reconnect() {
+    let server = sync_server();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let bodies = queue_messages(&server, 300).await;
+
+    let mut alice = server
+        .client_with(ClientOptions::new().with_offline_sync(25).with_reconnect_interval(1))
+        .await;
+    alice.login("alice", "secret").await;
+
+    let mut received = Vec::new();
+    let mut started = Vec::new();
+    loop {
+        match alice.next_event().await {
+            ClientEvent::DirectMessage { body, .. } => received.push(body),
+            ClientEvent::SyncStarted { total, peers } => started.push((total, peers)),
+            // the first batch is in, the connection goes away under the ones after it
+            ClientEvent::SyncProgress { delivered, remaining } if started.len() == 1 && delivered == 25 => {
+                assert_eq!(remaining, 275);
+                server.drop_connection("alice").await;
+            }
+            ClientEvent::SyncFinished { delivered } => {
+                assert_eq!(delivered, 300);
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(started.len(), 2);
+    assert_eq!(started[0], (300, vec![("bob".to_string(), 300)]));
+    assert_eq!(received, bodies);
+    assert_eq!(server.queued_messages().await, 0);
+}
+
+#[tokio::test]
+async fn clients_that_do_not_sync_still_get_the_queue_at_login() {
+    let server = sync_server();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let bodies = queue_messages(&server, 5).await;
+
+    let mut alice = server.client_with(ClientOptions::new().with_offline_sync(0)).await;
+    alice.login("alice", "secret").await;
+    assert_eq!(received_bodies(&mut alice, 5).await, bodies);
+    assert_eq!(server.queued_messages().await, 0);
+}
@@ -0,0 +1,165 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    time::Duration,
+};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+async fn webhooks(admin: &mut TestClient) -> (u64, u64) {
+    admin.client().send(Message::admin_server_stats()).await;
+    match admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await
+    {
+        ClientEvent::ServerStats {
+            webhooks_delivered,
+            webhooks_failed,
+            ..
+        } => (webhooks_delivered, webhooks_failed),
+        _ => unreachable!(),
+    }
+}
+
+// registers with a webhook at the endpoint and goes offline
+async fn away_with_webhook(server: &TestServer, username: &str, url: &str) {
+    let mut connection = server.logged_in(username).await;
+    connection.send(Message::set_preference("webhook", url)).await;
+    let preferences = connection.receive().await;
+    assert!(preferences.is(MessageType::Preferences), "Unexpected {:?}", preferences);
+    drop(connection);
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+// answers each request with the next status line, returns what was posted
+async fn serve(endpoint: TcpListener, statuses: &'static [&'static str]) -> Vec<String> {
+    tokio::task::spawn_blocking(move || {
+        statuses
+            .iter()
+            .map(|status| {
+                let (mut connection, _) = endpoint.accept().unwrap();
+                let mut request = String::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with('}') {
+                    let read = connection.read(&mut buffer).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buffer[..read]));
+                }
+                connection
+                    .write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes())
+                    .unwrap();
+                request
+            })
+            .collect()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_message_for_an_offline_user_is_posted_to_their_webhook() {
+    let endpoint = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/alice?token=abc", endpoint.local_addr().unwrap());
+    let server = TestServer::builder()
+        .with_offline_webhooks(&["127.0.0.1"], false)
+        .start();
+    away_with_webhook(&server, "alice", &url).await;
+
+    let mut bob = server.logged_in("bob").await;
+    let long = format!("the build is green again {}", "and then some ".repeat(10));
+    bob.send(Message::direct_message_send("alice", &long)).await;
+    assert!(bob.receive().await.is(MessageType::Ack));
+
+    let requests = serve(endpoint, &["204 No Content"]).await;
+    let request = &requests[0];
+    assert!(
+        request.starts_with("POST /hooks/alice?token=abc HTTP/1.1\r\n"),
+        "{}",
+        request
+    );
+    // only a snippet unless the server is configured otherwise
+    assert!(
+        request.contains(r#"{"sender":"bob","recipient":"alice","snippet":"the build is green again and then some a"#),
+        "{}",
+        request
+    );
+    assert!(request.contains(r#""sent_at":""#), "{}", request);
+    assert!(!request.contains(r#""body""#), "{}", request);
+}
+
+#[tokio::test]
+async fn a_failed_post_is_retried() {
+    let endpoint = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", endpoint.local_addr().unwrap());
+    let server = TestServer::builder()
+        .with_offline_webhooks(&["127.0.0.1"], true)
+        .start();
+    let mut admin = server.admin().await;
+    away_with_webhook(&server, "alice", &url).await;
+
+    let mut bob = server.logged_in("bob").await;
+    bob.send(Message::direct_message_send("alice", "ping")).await;
+    assert!(bob.receive().await.is(MessageType::Ack));
+
+    let requests = serve(endpoint, &["500 Internal Server Error", "204 No Content"]).await;
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].contains(r#""body":"ping""#), "{}", requests[1]);
+
+    for _ in 0..50 {
+        if webhooks(&mut admin).await == (1, 0) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The delivery was not counted: {:?}", webhooks(&mut admin).await);
+}
+
+#[tokio::test]
+async fn only_allowed_domains_are_accepted() {
+    let server = TestServer::builder()
+        .with_offline_webhooks(&["hooks.example.com"], false)
+        .start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::set_preference(
+            "webhook",
+            "http://relay.hooks.example.com/alice",
+        ))
+        .await;
+    let entries = alice.receive().await.preference_entries().unwrap();
+    assert!(entries.contains(&(
+        "webhook".to_string(),
+        "http://relay.hooks.example.com/alice".to_string()
+    )));
+
+    alice
+        .send(Message::set_preference("webhook", "http://attacker.example/alice"))
+        .await;
+    let nack = alice.receive().await;
+    assert_eq!(nack.rejected_type(), Some(MessageType::SetPreference));
+    assert_eq!(
+        nack.rejection_reason(),
+        Some("Webhooks to attacker.example are not allowed on this server")
+    );
+
+    // none turns it off again
+    alice.send(Message::set_preference("webhook", "none")).await;
+    let entries = alice.receive().await.preference_entries().unwrap();
+    assert!(entries.iter().all(|(key, _)| key != "webhook"), "{:?}", entries);
+}
+
+#[tokio::test]
+async fn webhooks_are_refused_when_not_enabled() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::set_preference("webhook", "http://127.0.0.1/hook"))
+        .await;
+    assert_eq!(
+        alice.receive().await.rejection_reason(),
+        Some("Webhooks are not enabled on this server")
+    );
+}
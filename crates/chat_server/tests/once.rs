@@ -0,0 +1,127 @@
+use chat_client::client::{run_once, ChatClient, ClientEvent, ClientOptions, OnceError, ONCE_TIMEOUT};
+use chat_server::application::testing::{AccessLevel, TestServer};
+
+// a fresh connection per invocation, the way the binary runs it
+async fn once(server: &TestServer, credentials: (&str, &str), line: &str) -> Result<Vec<ClientEvent>, OnceError> {
+    let options = ClientOptions::new().with_reconnect_interval(1);
+    let (client, mut events) = ChatClient::connect_with(server.connector(), options)
+        .await
+        .expect("Could not connect to the test server");
+    run_once(&client, &mut events, credentials, line, ONCE_TIMEOUT).await
+}
+
+#[tokio::test]
+async fn a_message_is_delivered_and_answered() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.create_user("ci-bot", "secret", AccessLevel::User).await;
+
+    let answered = once(&server, ("ci-bot", "secret"), "msg alice nightly build green")
+        .await
+        .unwrap();
+    assert!(
+        matches!(answered.as_slice(), [ClientEvent::Delivered { recipient, .. }] if recipient == "alice"),
+        "{:?}",
+        answered
+    );
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::DirectMessage { sender, body, .. }
+        if sender == "ci-bot" && body == "nightly build green"));
+}
+
+#[tokio::test]
+async fn an_earlier_message_to_the_same_recipient_does_not_answer() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.create_user("ci-bot", "secret", AccessLevel::User).await;
+
+    let options = ClientOptions::new().with_reconnect_interval(1);
+    let (client, mut events) = ChatClient::connect_with(server.connector(), options)
+        .await
+        .expect("Could not connect to the test server");
+    // queued before the login, it goes out and is delivered first
+    client.send_direct_message("alice", "earlier").await;
+    let answered = run_once(
+        &client,
+        &mut events,
+        ("ci-bot", "secret"),
+        "msg alice later",
+        ONCE_TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert!(
+        matches!(answered.as_slice(), [ClientEvent::Delivered { .. }]),
+        "{:?}",
+        answered
+    );
+    assert!(client.outbox().await.is_empty());
+
+    for expected in ["earlier", "later"] {
+        let event = alice
+            .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+            .await;
+        assert!(matches!(event, ClientEvent::DirectMessage { body, .. } if body == expected));
+    }
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_an_auth_failure() {
+    let server = TestServer::start();
+    server.create_user("ci-bot", "secret", AccessLevel::User).await;
+
+    let error = once(&server, ("ci-bot", "wrong"), "msg alice hello").await.unwrap_err();
+    assert!(matches!(error, OnceError::Auth(_)), "{:?}", error);
+    assert_eq!(error.exit_code(), 3);
+}
+
+#[tokio::test]
+async fn an_unknown_recipient_fails_the_command() {
+    let server = TestServer::start();
+    server.create_user("ci-bot", "secret", AccessLevel::User).await;
+
+    let error = once(&server, ("ci-bot", "secret"), "msg nobody hello")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, OnceError::Failed(_)), "{:?}", error);
+    assert_eq!(error.exit_code(), 1);
+}
+
+#[tokio::test]
+async fn a_typo_fails_before_logging_in() {
+    let server = TestServer::start();
+
+    // nobody is registered, so getting as far as the login would be an auth failure
+    let error = once(&server, ("ci-bot", "secret"), "msg alice").await.unwrap_err();
+    assert_eq!(error, OnceError::Usage("Usage: msg <user> <text>".to_string()));
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[tokio::test]
+async fn a_session_listing_ends_with_its_count() {
+    let server = TestServer::start();
+    server.create_admin("ops", "secret").await;
+
+    let answered = once(&server, ("ops", "secret"), "sessions --ip 127.").await.unwrap();
+    assert!(
+        matches!(
+            answered.last(),
+            Some(ClientEvent::SessionListEnd {
+                count: 1,
+                kicked: false
+            })
+        ),
+        "{:?}",
+        answered
+    );
+    assert!(matches!(answered.first(), Some(ClientEvent::SessionInfo(info)) if info.username == "ops"));
+
+    let error = once(&server, ("ops", "secret"), "kickwhere spam").await.unwrap_err();
+    // a bulk kick without a condition is refused before it reaches the server
+    assert!(matches!(error, OnceError::Usage(_)), "{:?}", error);
+}
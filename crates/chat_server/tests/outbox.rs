@@ -0,0 +1,53 @@
+use chat_client::client::{ClientEvent, ClientOptions, OutboxState, SendStatus};
+use chat_core::protocol::MessageType;
+use chat_server::application::testing::{RawConnection, TestServer};
+
+async fn expect_body(connection: &mut RawConnection, body: &str) {
+    let received = connection.receive().await;
+    assert!(
+        received.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        received
+    );
+    assert_eq!(received.payload().str_field(1), Ok(body));
+}
+
+#[tokio::test]
+async fn a_message_whose_ack_was_lost_is_delivered_once_after_reconnecting() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let (mut alice, link) = server.faulty_client(ClientOptions::new()).await;
+    alice.register("alice", "secret").await;
+
+    // the message reaches the server, its ack never comes back
+    link.drop_to_client(true);
+    assert_eq!(
+        alice.client().send_direct_message("bob", "hello").await,
+        SendStatus::Sent
+    );
+    expect_body(&mut bob, "hello").await;
+    let outbox = alice.client().outbox().await;
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].state(), OutboxState::InFlight);
+
+    // the connection dies with the ack still unanswered, the next one works again
+    server.drop_connection("alice").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Reconnecting(_)))
+        .await;
+    link.drop_to_client(false);
+
+    let delivered = alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+    let ClientEvent::Delivered { recipient, id, .. } = delivered else {
+        unreachable!();
+    };
+    assert_eq!(recipient, "bob");
+    assert!(id.is_some());
+    assert!(alice.client().outbox().await.is_empty());
+
+    // the retry was answered, not relayed again, the next thing bob gets is the next message
+    alice.client().send_direct_message("bob", "second").await;
+    expect_body(&mut bob, "second").await;
+}
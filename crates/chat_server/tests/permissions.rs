@@ -0,0 +1,141 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{AccessLevel, AccessPresets, Permissions, TestServer};
+
+const LEVELS: [AccessLevel; 4] = [
+    AccessLevel::Guest,
+    AccessLevel::User,
+    AccessLevel::Moderator,
+    AccessLevel::Admin,
+];
+
+#[test]
+fn every_message_type_requires_a_permission() {
+    let presets = AccessPresets::default();
+
+    for &message_type in MessageType::ALL {
+        let required = Permissions::required_for(message_type);
+        assert_ne!(required, Permissions::NONE, "{:?} is unrestricted", message_type);
+
+        // anything a client may send must be reachable by at least one preset
+        let reachable = LEVELS
+            .iter()
+            .any(|level| presets.permissions(level).can_access(&message_type));
+        assert!(
+            reachable || required == Permissions::SERVER,
+            "{:?} is unreachable",
+            message_type
+        );
+    }
+}
+
+#[test]
+fn server_frames_cannot_be_granted() {
+    let presets = AccessPresets::default();
+
+    assert!(!Permissions::all().contains(Permissions::SERVER));
+    assert!(!presets
+        .permissions(&AccessLevel::Admin)
+        .can_access(&MessageType::AuthSuccess));
+    assert!(Permissions::parse("server").is_err());
+}
+
+#[test]
+fn presets_are_layered_by_default() {
+    let presets = AccessPresets::default();
+    let guest = presets.permissions(&AccessLevel::Guest);
+    let user = presets.permissions(&AccessLevel::User);
+    let moderator = presets.permissions(&AccessLevel::Moderator);
+    let admin = presets.permissions(&AccessLevel::Admin);
+
+    assert!(user.contains(guest) && moderator.contains(user) && admin.contains(moderator));
+    assert!(!guest.can_access(&MessageType::DirectMessageSend));
+    assert!(user.can_access(&MessageType::DirectMessageSend));
+    assert!(moderator.contains(Permissions::KICK | Permissions::VIEW_STATS));
+    assert!(!moderator.can_access(&MessageType::ServerShutdown));
+    assert!(!moderator.contains(Permissions::BAN));
+}
+
+#[test]
+fn presets_parse_from_config() {
+    let config = "
+        # moderators only look, they do not kick
+        moderator = session, authenticate, send_dm, view_stats
+        user = all
+    ";
+    let presets = AccessPresets::parse(config).unwrap();
+
+    assert_eq!(
+        presets.permissions(&AccessLevel::Moderator).to_string(),
+        "session, authenticate, send_dm, view_stats"
+    );
+    assert_eq!(presets.permissions(&AccessLevel::User), Permissions::all());
+    assert_eq!(
+        presets.permissions(&AccessLevel::Guest),
+        AccessPresets::default().permissions(&AccessLevel::Guest)
+    );
+
+    assert_eq!(
+        AccessPresets::parse("owner = all").unwrap_err(),
+        "Line 1: Unknown access level 'owner', expected guest, user, moderator or admin"
+    );
+    assert_eq!(
+        AccessPresets::parse("user = send_dm, fly").unwrap_err(),
+        "Line 1: Unknown permission 'fly'"
+    );
+    assert!(AccessPresets::parse("user").is_err());
+}
+
+#[tokio::test]
+async fn configured_presets_gate_requests() {
+    let presets = AccessPresets::default().with(
+        &AccessLevel::User,
+        Permissions::SESSION | Permissions::AUTHENTICATE | Permissions::VIEW_STATS,
+    );
+    let server = TestServer::builder().with_access_presets(presets).start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice
+        .client()
+        .send(Message::direct_message_send("alice", "hello"))
+        .await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    alice.client().send(Message::admin_server_stats()).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn user_overrides_apply_on_top_of_the_preset() {
+    let server = TestServer::start();
+    server.create_user("mod", "secret", AccessLevel::Moderator).await;
+
+    let mut moderator = server.client().await;
+    moderator.login("mod", "secret").await;
+    moderator.client().send(Message::admin_server_stats()).await;
+    moderator
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+
+    server
+        .set_permission_overrides("mod", Permissions::SET_LOG_LEVEL, Permissions::VIEW_STATS)
+        .await;
+    moderator.client().send(Message::admin_server_stats()).await;
+    moderator
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+
+    moderator
+        .client()
+        .send(Message::admin_set_log_level("debug", None))
+        .await;
+    moderator
+        .expect(|event| matches!(event, ClientEvent::LogLevelChanged { .. }))
+        .await;
+}
@@ -0,0 +1,248 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::{
+    testing::{eventually, TestServer},
+    Dispatch, PluginContext, PluginFuture, ServerPlugin, WebhookPlugin, WebhookUrl,
+};
+use uuid::Uuid;
+
+// writes down every callback, frames only of the types a test cares about
+#[derive(Debug, Default)]
+struct Recorder {
+    calls: Mutex<Vec<String>>,
+}
+
+impl Recorder {
+    fn record(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl ServerPlugin for Recorder {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn on_session_open<'a>(&'a self, _context: &'a PluginContext, _session: Uuid) -> PluginFuture<'a> {
+        self.record("open".to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_authenticated<'a>(&'a self, _context: &'a PluginContext, _session: Uuid, user: &'a str) -> PluginFuture<'a> {
+        self.record(format!("authenticated {}", user));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_message<'a>(
+        &'a self,
+        _context: &'a PluginContext,
+        _session: Uuid,
+        message: &'a Message,
+        dispatch: Dispatch,
+    ) -> PluginFuture<'a> {
+        if message.is(MessageType::AuthCreate) || message.is(MessageType::DirectMessageSend) {
+            self.record(format!("{:?} {:?}", dispatch, message.message_type()));
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_session_close<'a>(&'a self, _context: &'a PluginContext, _session: Uuid) -> PluginFuture<'a> {
+        self.record("close".to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn on_shutdown<'a>(&'a self, _context: &'a PluginContext) -> PluginFuture<'a> {
+        self.record("shutdown".to_string());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// fails every frame one way or the other
+#[derive(Debug)]
+struct Broken;
+
+impl ServerPlugin for Broken {
+    fn name(&self) -> &str {
+        "broken"
+    }
+
+    fn on_message<'a>(
+        &'a self,
+        _context: &'a PluginContext,
+        _session: Uuid,
+        _message: &'a Message,
+        dispatch: Dispatch,
+    ) -> PluginFuture<'a> {
+        Box::pin(async move {
+            match dispatch {
+                Dispatch::Before => Err("not today".to_string()),
+                Dispatch::After => panic!("plugin bug"),
+            }
+        })
+    }
+}
+
+// greets every user with who else is around
+#[derive(Debug)]
+struct Greeter;
+
+impl ServerPlugin for Greeter {
+    fn name(&self) -> &str {
+        "greeter"
+    }
+
+    fn on_authenticated<'a>(&'a self, context: &'a PluginContext, _session: Uuid, user: &'a str) -> PluginFuture<'a> {
+        Box::pin(async move {
+            let greeting = format!(
+                "Welcome to {}, {}. Online: {}",
+                context.server_name().await,
+                user,
+                context.online_users().await.join(", ")
+            );
+            context.send_to_user(user, Message::motd(&greeting)).await;
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test]
+async fn callbacks_follow_the_session() {
+    let recorder = Arc::new(Recorder::default());
+    let server = TestServer::builder().with_plugin(recorder.clone()).start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    alice.client().send_direct_message("alice", "note to self").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+    alice.disconnect().await;
+
+    eventually(|| async { recorder.calls().last().map(String::as_str) == Some("close") }).await;
+    assert_eq!(
+        recorder.calls(),
+        [
+            "open",
+            "Before AuthCreate",
+            "authenticated alice",
+            "After AuthCreate",
+            "Before DirectMessageSend",
+            "After DirectMessageSend",
+            "close",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_shutdown_is_announced() {
+    let recorder = Arc::new(Recorder::default());
+    let server = TestServer::builder().with_plugin(recorder.clone()).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    admin.client().send(Message::server_shutdown(0)).await;
+    eventually(|| async { recorder.calls().contains(&"shutdown".to_string()) }).await;
+}
+
+#[tokio::test]
+async fn a_broken_plugin_does_not_break_the_session() {
+    let recorder = Arc::new(Recorder::default());
+    let server = TestServer::builder()
+        .with_plugin(Arc::new(Broken))
+        .with_plugin(recorder.clone())
+        .start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    alice.client().send_direct_message("bob", "still here?").await;
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::DirectMessage { body, .. } if body == "still here?"));
+
+    // the plugins after it still hear about everything
+    let calls = recorder.calls();
+    assert!(calls.contains(&"Before DirectMessageSend".to_string()), "{:?}", calls);
+    assert!(calls.contains(&"After DirectMessageSend".to_string()), "{:?}", calls);
+}
+
+#[tokio::test]
+async fn plugins_can_look_around_and_send() {
+    let server = TestServer::builder()
+        .with_server_name("north")
+        .with_plugin(Arc::new(Greeter))
+        .start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let event = alice.expect(|event| matches!(event, ClientEvent::Motd(_))).await;
+    assert!(matches!(event, ClientEvent::Motd(text) if text == "Welcome to north, alice. Online: alice"));
+
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let event = bob.expect(|event| matches!(event, ClientEvent::Motd(_))).await;
+    assert!(matches!(event, ClientEvent::Motd(text) if text == "Welcome to north, bob. Online: alice, bob"));
+}
+
+#[tokio::test]
+async fn the_webhook_posts_direct_messages() {
+    let endpoint = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/chat", endpoint.local_addr().unwrap());
+    let webhook = WebhookPlugin::new(WebhookUrl::parse(&url).unwrap());
+    let server = TestServer::builder().with_plugin(Arc::new(webhook)).start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    alice.client().send_direct_message("bob", "build is green").await;
+
+    let request = tokio::task::spawn_blocking(move || {
+        let (mut connection, _) = endpoint.accept().unwrap();
+        let mut request = String::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with('}') {
+            let read = connection.read(&mut buffer).unwrap();
+            request.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        connection.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        request
+    })
+    .await
+    .unwrap();
+
+    assert!(request.starts_with("POST /hooks/chat HTTP/1.1\r\n"), "{}", request);
+    assert!(
+        request.ends_with(r#"{"sender":"alice","recipient":"bob","body":"build is green"}"#),
+        "{}",
+        request
+    );
+}
+
+#[test]
+fn webhook_urls_are_checked() {
+    assert!(WebhookUrl::parse("http://localhost:8080/hook").is_ok());
+    assert_eq!(
+        WebhookUrl::parse("http://relay").unwrap().to_string(),
+        "http://relay:80/"
+    );
+    assert_eq!(
+        WebhookUrl::parse("https://relay/hook"),
+        Err("expected an http:// url, got 'https://relay/hook'".to_string())
+    );
+    assert!(WebhookUrl::parse("http://relay:port/hook").is_err());
+}
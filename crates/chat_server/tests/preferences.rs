@@ -0,0 +1,180 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TakeoverPolicy, TestServer};
+
+async fn second_login(server: &TestServer, username: &str) -> RawConnection {
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth(username, "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    connection
+}
+
+async fn preferences(connection: &mut RawConnection) -> Vec<(String, String)> {
+    let message = connection.receive().await;
+    assert!(message.is(MessageType::Preferences), "Unexpected {:?}", message);
+    message.preference_entries().unwrap()
+}
+
+async fn versioned(connection: &mut RawConnection, message_type: MessageType) -> (Vec<(String, String)>, u64) {
+    let message = connection.receive().await;
+    assert!(message.is(message_type), "Unexpected {:?}", message);
+    (
+        message.preference_entries().unwrap(),
+        message.preferences_version().unwrap(),
+    )
+}
+
+fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn preferences_follow_the_user_across_sessions() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let event = alice.expect(|event| matches!(event, ClientEvent::Preferences(_))).await;
+    assert_eq!(event, ClientEvent::Preferences(entries(&[("dnd", "off")])));
+
+    assert!(alice.client().set_preference("dnd", "on").await);
+    alice
+        .expect(|event| matches!(event, ClientEvent::Preferences(entries) if entries[0].1 == "on"))
+        .await;
+    alice.disconnect().await;
+
+    let mut alice = server.client().await;
+    alice.login("alice", "secret").await;
+    let event = alice.expect(|event| matches!(event, ClientEvent::Preferences(_))).await;
+    let expected = entries(&[("dnd", "on")]);
+    assert_eq!(event, ClientEvent::Preferences(expected.clone()));
+    assert_eq!(alice.client().preferences().await, expected.into_iter().collect());
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_refused() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    for (key, value) in [("dnd", "maybe"), ("colour", "blue"), ("room.general.notify", "mute")] {
+        alice.send(Message::set_preference(key, value)).await;
+        assert!(
+            alice.receive().await.is(MessageType::Nack),
+            "{} = {} was accepted",
+            key,
+            value
+        );
+    }
+
+    // no webhook is not stored
+    alice.send(Message::set_preference("webhook", "none")).await;
+    assert_eq!(preferences(&mut alice).await, entries(&[("dnd", "off")]));
+}
+
+#[tokio::test]
+async fn messages_during_dnd_count_as_unread() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    bob.send(Message::set_preference("dnd", "on")).await;
+    preferences(&mut bob).await;
+
+    alice.send(Message::direct_message_send("bob", "ping")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    bob.send(Message::mark_conversation_read("nobody")).await;
+    let summary = bob.receive().await;
+    assert!(summary.is(MessageType::UnreadSummary));
+    assert_eq!(summary.unread_counts(), Ok(vec![("alice".to_string(), 1)]));
+}
+
+#[tokio::test]
+async fn a_batch_is_applied_whole_or_not_at_all() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice
+        .send(Message::set_preferences(&entries(&[
+            ("dnd", "on"),
+            ("webhook", "none"),
+        ])))
+        .await;
+    let expected = (entries(&[("dnd", "on")]), 1);
+    assert_eq!(versioned(&mut alice, MessageType::Preferences).await, expected);
+
+    // the valid first pair is not kept either, and the client is told what it may set
+    alice
+        .send(Message::set_preferences(&entries(&[
+            ("dnd", "off"),
+            ("colour", "blue"),
+        ])))
+        .await;
+    let nack = alice.receive().await;
+    assert_eq!(nack.rejected_type(), Some(MessageType::SetPreference));
+    assert_eq!(
+        nack.rejection_reason(),
+        Some("Unknown preference 'colour', expected dnd or webhook")
+    );
+    alice.send(Message::set_preference(&"a".repeat(200), "on")).await;
+    assert_eq!(
+        alice.receive().await.rejection_reason(),
+        Some("Preference keys are at most 128 bytes")
+    );
+
+    alice.send(Message::get_preferences()).await;
+    assert_eq!(versioned(&mut alice, MessageType::Preferences).await, expected);
+
+    // a request without pairs is malformed, not refused
+    alice.send(Message::set_preferences(&[])).await;
+    assert_eq!(alice.receive().await.rejected_type(), None);
+}
+
+#[tokio::test]
+async fn other_sessions_are_told_about_a_change() {
+    let server = TestServer::builder()
+        .with_takeover_policy(TakeoverPolicy::Allow)
+        .start();
+    let mut laptop = server.logged_in("alice").await;
+    let mut phone = second_login(&server, "alice").await;
+
+    laptop.send(Message::set_preference("dnd", "on")).await;
+    let expected = (entries(&[("dnd", "on")]), 1);
+    assert_eq!(versioned(&mut laptop, MessageType::Preferences).await, expected);
+    assert_eq!(versioned(&mut phone, MessageType::PreferencesChanged).await, expected);
+
+    // the laptop is not told twice, the next frame answers the next request
+    laptop.send(Message::get_preferences()).await;
+    assert_eq!(versioned(&mut laptop, MessageType::Preferences).await, expected);
+}
+
+#[tokio::test]
+async fn concurrent_changes_from_two_sessions_converge() {
+    let server = TestServer::builder()
+        .with_takeover_policy(TakeoverPolicy::Allow)
+        .start();
+    let mut laptop = server.client().await;
+    laptop.register("alice", "secret").await;
+    laptop
+        .expect(|event| matches!(event, ClientEvent::Preferences(_)))
+        .await;
+    let mut phone = server.client().await;
+    phone.login("alice", "secret").await;
+    phone.expect(|event| matches!(event, ClientEvent::Preferences(_))).await;
+
+    let (laptop, phone) = (laptop.client().clone(), phone.client().clone());
+    let laptop_updates = entries(&[("dnd", "on")]);
+    let phone_updates = entries(&[("dnd", "off")]);
+    let (sent_by_laptop, sent_by_phone) = tokio::join!(
+        laptop.set_preferences(&laptop_updates),
+        phone.set_preferences(&phone_updates)
+    );
+    assert!(sent_by_laptop && sent_by_phone);
+
+    // whichever was applied second decides dnd, both sessions end with the set it left
+    eventually(|| async { laptop.preferences_version().await == 2 && phone.preferences_version().await == 2 }).await;
+    assert_eq!(phone.preferences().await, laptop.preferences().await);
+}
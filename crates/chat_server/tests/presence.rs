@@ -0,0 +1,142 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    capability,
+    protocol::{Message, MessageType, MAX_FIELD_SIZE},
+};
+use chat_server::application::testing::{eventually, within, PresenceTiming, RawConnection, TestClient, TestServer};
+
+const FAST: PresenceTiming = PresenceTiming {
+    interval: Duration::from_millis(20),
+    window: Duration::ZERO,
+};
+
+// skips heartbeats and anything else that is not about presence
+async fn next_presence(connection: &mut RawConnection) -> (Vec<String>, Vec<String>) {
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::PresenceUpdate) {
+            return message.presence_changes().unwrap();
+        }
+    }
+}
+
+async fn presence_stats(admin: &mut TestClient) -> (u64, u64) {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats {
+        presence_coalesced,
+        presence_dropped,
+        ..
+    } = event
+    else {
+        unreachable!();
+    };
+    (presence_coalesced, presence_dropped)
+}
+
+#[tokio::test]
+async fn presence_is_off_by_default() {
+    let server = TestServer::start();
+    let alice = server.client().await;
+    assert!(!alice.client().server_supports(capability::PRESENCE).await);
+
+    let enabled = TestServer::builder().with_presence(FAST).start();
+    let alice = enabled.client().await;
+    assert!(alice.client().server_supports(capability::PRESENCE).await);
+}
+
+#[tokio::test]
+async fn others_hear_about_logins_and_logouts() {
+    let server = TestServer::builder().with_presence(FAST).start();
+    let mut alice = server.logged_in("alice").await;
+
+    let mut bob = server.logged_in("bob").await;
+    assert_eq!(next_presence(&mut alice).await, (vec!["bob".to_string()], vec![]));
+
+    bob.send(Message::DISCONNECT).await;
+    assert_eq!(next_presence(&mut alice).await, (vec![], vec!["bob".to_string()]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_burst_of_logins_is_coalesced() {
+    let window = Duration::from_secs(1);
+    let server = TestServer::builder()
+        .with_presence(PresenceTiming {
+            interval: Duration::from_millis(100),
+            window,
+        })
+        .start();
+    server.create_admin("admin", "secret").await;
+    let mut watcher = server.logged_in("watcher").await;
+
+    // half of them are gone again before the window closes
+    let mut stayed = BTreeSet::new();
+    let mut connections = Vec::new();
+    for index in 0..20 {
+        let username = format!("user{:02}", index);
+        let mut connection = server.logged_in(&username).await;
+        if index % 2 == 0 {
+            connection.send(Message::DISCONNECT).await;
+        } else {
+            stayed.insert(username);
+            connections.push(connection);
+        }
+    }
+    eventually(|| async { server.session_count().await == 11 }).await;
+    server.clock().advance(window * 2).await;
+
+    // a tick may fall into the burst, the flapping users must not show up either way
+    let mut online = BTreeSet::new();
+    let mut updates = 0;
+    while online != stayed {
+        let (joined, left) = next_presence(&mut watcher).await;
+        updates += 1;
+        assert!(left.is_empty(), "Flapping users were announced: {:?}", left);
+        online.extend(joined);
+    }
+    assert!(updates <= 2, "Expected the burst in at most 2 updates, got {}", updates);
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let (coalesced, dropped) = presence_stats(&mut admin).await;
+    assert_eq!(coalesced, 20);
+    assert_eq!(dropped, 0);
+}
+
+#[tokio::test]
+async fn recipients_that_stop_reading_miss_updates() {
+    let server = TestServer::builder().with_presence(FAST).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    // never reads again, its pipe fills up with the messages below
+    let _alice = server.logged_in("alice").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    let body = "x".repeat(MAX_FIELD_SIZE as usize - 1024);
+    for _ in 0..4 {
+        bob.client().send_direct_message("alice", &body).await;
+        bob.expect(|event| matches!(event, ClientEvent::Delivered { .. })).await;
+    }
+
+    // every login is a tick of its own, the first updates wait in alice's queue and the rest are dropped
+    // the connections stay open, a logout within the same tick would cancel the login out
+    let mut others = Vec::new();
+    for username in ["carol", "dave", "erin", "frank"] {
+        others.push(server.logged_in(username).await);
+        admin
+            .expect(|event| matches!(event, ClientEvent::PresenceUpdate { online, .. } if online == &[username]))
+            .await;
+    }
+    within(async {
+        while presence_stats(&mut admin).await.1 == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+}
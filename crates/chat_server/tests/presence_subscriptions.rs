@@ -0,0 +1,204 @@
+use chat_client::client::{ClientEvent, Roster};
+use chat_core::{
+    capability,
+    protocol::{Message, MessageType, PresenceChange, PresenceEntry, PresenceScope, PresenceSnapshot, PresenceStatus},
+};
+use chat_server::application::testing::{eventually, AccessLevel, RawConnection, TestServer};
+
+// skips heartbeats and anything else that is not about presence subscriptions
+async fn next_presence(connection: &mut RawConnection) -> Message {
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::PresenceSnapshot) || message.is(MessageType::PresenceDelta) {
+            return message;
+        }
+        assert!(!message.is(MessageType::MessageError), "Unexpected {:?}", message);
+    }
+}
+
+async fn subscribe(connection: &mut RawConnection, scope: PresenceScope) -> PresenceSnapshot {
+    connection.send(Message::subscribe_presence(&scope)).await;
+    let message = next_presence(connection).await;
+    assert!(message.is(MessageType::PresenceSnapshot), "Unexpected {:?}", message);
+    message.roster_snapshot().unwrap()
+}
+
+// applies the delta that comes next, it has to follow the roster's version
+async fn follow(connection: &mut RawConnection, roster: &mut Roster) -> (PresenceChange, String) {
+    let message = next_presence(connection).await;
+    let delta = message.roster_delta().unwrap();
+    assert_eq!(delta.version, roster.version() + 1, "A delta went missing");
+    assert!(roster.apply(&delta));
+    (delta.change, delta.entry.user)
+}
+
+fn online(user: &str) -> PresenceEntry {
+    PresenceEntry {
+        user: user.to_string(),
+        status: PresenceStatus::Online,
+    }
+}
+
+#[tokio::test]
+async fn snapshot_and_deltas_agree_with_the_server() {
+    let server = TestServer::start();
+    for user in ["alice", "bob", "carol", "dave"] {
+        server.create_user(user, "secret", AccessLevel::User).await;
+    }
+    server.create_admin("admin", "secret").await;
+    let mut alice = server.login("alice").await;
+
+    let snapshot = subscribe(&mut alice, PresenceScope::All).await;
+    assert_eq!(snapshot.entries, [online("alice")]);
+    let mut roster = Roster::from_snapshot(&snapshot);
+
+    let mut bob = server.login("bob").await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Joined, "bob".to_string())
+    );
+    let mut carol = server.login("carol").await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Joined, "carol".to_string())
+    );
+
+    bob.send(Message::DISCONNECT).await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Left, "bob".to_string())
+    );
+
+    carol.send(Message::set_preference("dnd", "on")).await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::StatusChanged, "carol".to_string())
+    );
+
+    let _dave = server.login("dave").await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Joined, "dave".to_string())
+    );
+    let mut admin = server.login("admin").await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Joined, "admin".to_string())
+    );
+
+    // a rename is the old name leaving and the new one joining
+    admin.send(Message::admin_rename_user("dave", "david")).await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Left, "dave".to_string())
+    );
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Joined, "david".to_string())
+    );
+
+    let mut expected = Vec::new();
+    for user in ["admin", "alice", "bob", "carol", "dave", "david"] {
+        if server.is_logged_in(user).await {
+            expected.push(user);
+        }
+    }
+    let entries = roster.entries();
+    assert_eq!(
+        entries.iter().map(|entry| entry.user.as_str()).collect::<Vec<_>>(),
+        expected
+    );
+    let carol = entries.iter().find(|entry| entry.user == "carol").unwrap();
+    assert_eq!(carol.status, PresenceStatus::DoNotDisturb);
+
+    // a new snapshot is what the deltas built, and counts on from them
+    let again = subscribe(&mut alice, PresenceScope::All).await;
+    assert_eq!(again.entries, entries);
+    assert!(again.version > roster.version());
+}
+
+#[tokio::test]
+async fn contacts_are_the_users_one_has_a_conversation_with() {
+    let server = TestServer::start();
+    for user in ["alice", "bob", "carol"] {
+        server.create_user(user, "secret", AccessLevel::User).await;
+    }
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.send(Message::direct_message_send("bob", "hi")).await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    let snapshot = subscribe(&mut alice, PresenceScope::Contacts).await;
+    assert_eq!(snapshot.entries, [online("bob")]);
+    let mut roster = Roster::from_snapshot(&snapshot);
+
+    // carol is no contact, her login is not heard about
+    let _carol = server.login("carol").await;
+    bob.send(Message::DISCONNECT).await;
+    assert_eq!(
+        follow(&mut alice, &mut roster).await,
+        (PresenceChange::Left, "bob".to_string())
+    );
+    assert!(roster.entries().is_empty());
+}
+
+#[tokio::test]
+async fn subscriptions_can_be_ended() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+
+    subscribe(&mut alice, PresenceScope::All).await;
+    alice.send(Message::unsubscribe_presence(&PresenceScope::All)).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    alice.send(Message::unsubscribe_presence(&PresenceScope::All)).await;
+    assert_eq!(
+        alice.receive().await.rejected_type(),
+        Some(MessageType::UnsubscribePresence)
+    );
+
+    // bob's login would have been the next delta, the time sync answer comes first instead
+    let _bob = server.login("bob").await;
+    alice.send(Message::time_sync(chrono::Utc::now())).await;
+    assert!(alice.receive().await.is(MessageType::TimeSyncReply));
+}
+
+#[tokio::test]
+async fn the_client_keeps_a_roster_across_logins() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.client().await;
+    assert!(alice.client().server_supports(capability::PRESENCE_SUBSCRIPTIONS).await);
+
+    // followed before the login, the subscription goes out with it
+    alice.client().follow_presence(PresenceScope::All).await;
+    alice.login("alice", "secret").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::PresenceSnapshot(_)))
+        .await;
+
+    let _bob = server.login("bob").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::PresenceDelta(delta) if delta.entry.user == "bob"))
+        .await;
+    assert_eq!(
+        alice.client().roster(&PresenceScope::All).await,
+        Some(vec![online("alice"), online("bob")])
+    );
+
+    // the server forgets subscriptions with the session, the client subscribes again after the next login
+    server.drop_connection("alice").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::PresenceSnapshot(_)))
+        .await;
+    eventually(|| async {
+        alice
+            .client()
+            .roster(&PresenceScope::All)
+            .await
+            .is_some_and(|roster| roster.len() == 2)
+    })
+    .await;
+}
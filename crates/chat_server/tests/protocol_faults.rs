@@ -0,0 +1,124 @@
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+use chat_core::{
+    capability::Capabilities,
+    protocol::Message,
+    transport::{
+        memory::{self, MemoryListener},
+        Listener,
+    },
+};
+use chat_server::application::testing::within;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::mpsc,
+};
+
+// a server that writes whatever bytes a test hands it
+async fn connect() -> (MemoryListener, ChatClient, mpsc::UnboundedReceiver<ClientEvent>) {
+    let (listener, connector) = memory::network();
+    let options = ClientOptions::new().with_reconnect_interval(1);
+    let (client, events) = ChatClient::connect_with(connector, options)
+        .await
+        .expect("Could not connect to the fake server");
+    (listener, client, events)
+}
+
+async fn accept(listener: &mut MemoryListener) -> DuplexStream {
+    let (mut stream, _) = within(listener.accept()).await.unwrap();
+    let hello = Message::server_hello(
+        &Capabilities::new(),
+        "nonce",
+        "fake",
+        "00000000-0000-0000-0000-000000000000",
+    );
+    stream.write_all(&hello.to_bytes()).await.unwrap();
+    stream
+}
+
+// a frame that arrives whole but whose checksum does not match
+fn corrupted(text: &str) -> Vec<u8> {
+    let mut bytes = Message::motd(text).to_bytes();
+    *bytes.last_mut().unwrap() ^= 0xff;
+    bytes
+}
+
+// the events that matter here, in the order they came
+async fn next(events: &mut mpsc::UnboundedReceiver<ClientEvent>) -> ClientEvent {
+    loop {
+        let event = within(events.recv()).await.expect("Client event stream closed");
+        match event {
+            ClientEvent::ProtocolFault { .. }
+            | ClientEvent::Motd(_)
+            | ClientEvent::Disconnected
+            | ClientEvent::Reconnecting(_)
+            | ClientEvent::Connected => return event,
+            _ => {}
+        }
+    }
+}
+
+fn fault(recovered: bool) -> impl Fn(&ClientEvent) -> bool {
+    move |event| matches!(event, ClientEvent::ProtocolFault { recovered: got, .. } if *got == recovered)
+}
+
+#[tokio::test]
+async fn a_bad_frame_is_skipped() {
+    let (mut listener, _client, mut events) = connect().await;
+    assert!(matches!(next(&mut events).await, ClientEvent::Connected));
+    let mut server = accept(&mut listener).await;
+
+    server.write_all(&corrupted("first")).await.unwrap();
+    server.write_all(&Message::motd("second").to_bytes()).await.unwrap();
+    // odd garbage between frames, the header start is found byte by byte
+    server.write_all(b"abc").await.unwrap();
+    server.write_all(&Message::motd("third").to_bytes()).await.unwrap();
+
+    let event = next(&mut events).await;
+    assert!(
+        matches!(&event, ClientEvent::ProtocolFault { error, recovered: true } if error == "Invalid checksum"),
+        "{:?}",
+        event
+    );
+    assert!(matches!(next(&mut events).await, ClientEvent::Motd(text) if text == "second"));
+    assert!(fault(true)(&next(&mut events).await));
+    assert!(matches!(next(&mut events).await, ClientEvent::Motd(text) if text == "third"));
+}
+
+#[tokio::test]
+async fn a_stream_of_bad_frames_is_given_up_and_reconnected() {
+    let (mut listener, _client, mut events) = connect().await;
+    assert!(matches!(next(&mut events).await, ClientEvent::Connected));
+    let mut server = accept(&mut listener).await;
+
+    for text in ["one", "two", "three"] {
+        server.write_all(&corrupted(text)).await.unwrap();
+    }
+
+    assert!(fault(true)(&next(&mut events).await));
+    assert!(fault(true)(&next(&mut events).await));
+    assert!(fault(false)(&next(&mut events).await));
+    assert!(matches!(next(&mut events).await, ClientEvent::Disconnected));
+    assert!(matches!(next(&mut events).await, ClientEvent::Reconnecting(1)));
+
+    // both tasks are gone, so the old stream is closed from the client's side
+    let mut rest = Vec::new();
+    within(server.read_to_end(&mut rest)).await.unwrap();
+
+    assert!(matches!(next(&mut events).await, ClientEvent::Connected));
+    let mut server = accept(&mut listener).await;
+    server.write_all(&Message::motd("back").to_bytes()).await.unwrap();
+    assert!(matches!(next(&mut events).await, ClientEvent::Motd(text) if text == "back"));
+}
+
+#[tokio::test]
+async fn a_frame_cut_short_is_a_closed_connection() {
+    let (mut listener, _client, mut events) = connect().await;
+    assert!(matches!(next(&mut events).await, ClientEvent::Connected));
+    let mut server = accept(&mut listener).await;
+
+    let frame = Message::motd("cut short").to_bytes();
+    server.write_all(&frame[..frame.len() / 2]).await.unwrap();
+    drop(server);
+
+    assert!(matches!(next(&mut events).await, ClientEvent::Disconnected));
+}
@@ -0,0 +1,53 @@
+use chat_core::{
+    protocol::{DisconnectReason, Message},
+    version::{VersionRange, VERSION},
+};
+use chat_server::application::testing::TestServer;
+
+fn with_version(message: &Message, version: u8) -> Vec<u8> {
+    let mut bytes = message.to_bytes();
+    bytes[2] = version;
+    bytes
+}
+
+#[tokio::test]
+async fn unsupported_version_is_told_and_dropped() {
+    let server = TestServer::start();
+    let mut connection = server.raw_connection().await;
+
+    connection
+        .send_bytes(&with_version(&Message::auth_create("alice", "secret"), VERSION + 1))
+        .await;
+    let notice = connection.receive().await;
+    assert_eq!(
+        notice.disconnect_reason(),
+        Ok((
+            DisconnectReason::ProtocolError,
+            format!(
+                "Unsupported protocol version {}, this side speaks version {}",
+                VERSION + 1,
+                VERSION
+            )
+        ))
+    );
+    connection.expect_closed().await;
+    assert!(!server.is_logged_in("alice").await);
+}
+
+#[tokio::test]
+async fn the_oldest_version_can_be_raised() {
+    let server = TestServer::builder()
+        .with_protocol_versions(VersionRange::new(VERSION + 1, VERSION + 1))
+        .start();
+    let mut connection = server.unchecked_connection();
+
+    // the hello still goes out, the refusal comes with the first frame
+    connection.receive().await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    let notice = connection.receive().await;
+    assert_eq!(
+        notice.disconnect_reason().map(|(reason, _)| reason),
+        Ok(DisconnectReason::ProtocolError)
+    );
+    connection.expect_closed().await;
+}
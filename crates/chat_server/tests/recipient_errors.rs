@@ -0,0 +1,107 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{ErrorCode, Message, MessageType};
+use chat_server::application::testing::{eventually, TestServer};
+
+// registers the user and leaves again, so it exists but is offline
+async fn offline_user(server: &TestServer, username: &str) {
+    let mut connection = server.logged_in(username).await;
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+fn error(response: &Message) -> (ErrorCode, &str) {
+    assert!(response.is(MessageType::MessageError), "Unexpected {:?}", response);
+    (response.error_code(), response.payload().str_field(0).unwrap())
+}
+
+#[tokio::test]
+async fn unknown_recipients_are_told_apart_from_offline_ones() {
+    let server = TestServer::start();
+    offline_user(&server, "bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    assert_eq!(
+        error(&alice.request(Message::direct_message_send("nobody", "hello")).await),
+        (ErrorCode::UserNotFound, "User nobody does not exist")
+    );
+    // queued for the next login
+    alice.send_direct("bob", "hello").await;
+}
+
+#[tokio::test]
+async fn offline_recipients_are_reported_without_the_queue() {
+    let server = TestServer::builder().with_offline_queue(false).start();
+    offline_user(&server, "bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    assert_eq!(
+        error(&alice.request(Message::direct_message_send("nobody", "hello")).await),
+        (ErrorCode::UserNotFound, "User nobody does not exist")
+    );
+    assert_eq!(
+        error(&alice.request(Message::direct_message_send("bob", "hello")).await),
+        (ErrorCode::RecipientOffline, "bob is not online")
+    );
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    alice.send_direct("bob", "hello").await;
+    assert_eq!(bob.receive().await.payload().str_field(1), Ok("hello"));
+}
+
+#[tokio::test]
+async fn concealed_users_look_like_unknown_ones() {
+    let server = TestServer::builder()
+        .with_offline_queue(false)
+        .with_conceal_users(true)
+        .start();
+    offline_user(&server, "bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    assert_eq!(
+        error(&alice.request(Message::direct_message_send("nobody", "hello")).await),
+        (ErrorCode::UserNotFound, "User nobody does not exist")
+    );
+    assert_eq!(
+        error(&alice.request(Message::direct_message_send("bob", "hello")).await),
+        (ErrorCode::UserNotFound, "User bob does not exist")
+    );
+}
+
+#[tokio::test]
+async fn recipients_match_regardless_of_case() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    alice.send_direct("BoB", "hello").await;
+    assert_eq!(bob.receive().await.payload().str_field(1), Ok("hello"));
+    alice.send(Message::history_request("bob", 10)).await;
+    let entry = alice.receive().await;
+    assert_eq!(entry.payload().str_field(2), Ok("bob"));
+
+    // registration folds case the same way
+    let mut imposter = server.raw_connection().await;
+    imposter.send(Message::auth_create("Bob", "secret")).await;
+    assert!(imposter.receive().await.is(MessageType::AuthFailure));
+}
+
+#[tokio::test]
+async fn the_client_reports_the_error_code() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().send_direct_message("nobody", "hello?").await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::DeliveryFailed { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        ClientEvent::DeliveryFailed {
+            code: ErrorCode::UserNotFound,
+            ..
+        }
+    ));
+}
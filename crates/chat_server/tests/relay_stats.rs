@@ -0,0 +1,98 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, AccessLevel, TestClient, TestServer};
+
+async fn user_info(admin: &mut TestClient, username: &str) -> ClientEvent {
+    admin.client().send(Message::admin_user_info(username)).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::UserInfo { .. }))
+        .await
+}
+
+#[tokio::test]
+async fn counters_follow_each_outcome() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    for name in ["alice", "bob", "carol"] {
+        server.create_user(name, "secret", AccessLevel::User).await;
+    }
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut bob = server.login("bob").await;
+
+    let mut alice = server.login("alice").await;
+    alice.send(Message::direct_message_send("bob", "hi")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    alice.send(Message::direct_message_send("carol", "call me")).await;
+    assert!(alice.receive().await.is(MessageType::Ack));
+    alice.send(Message::direct_message_send("nobody", "hello?")).await;
+    assert!(alice.receive().await.is(MessageType::MessageError));
+
+    assert_eq!(
+        user_info(&mut admin, "alice").await,
+        ClientEvent::UserInfo {
+            username: "alice".to_string(),
+            access_level: "user".to_string(),
+            sessions: 1,
+            sent: 3,
+            delivered: 1,
+            queued: 1,
+            dropped: 1,
+            last_error: Some("User nobody does not exist".to_string()),
+            temporary_grants: Vec::new(),
+        }
+    );
+
+    // carol picks up the queued message, it counts as delivered from then on
+    let _carol = server.login("carol").await;
+    let ClientEvent::UserInfo { delivered, queued, .. } = user_info(&mut admin, "alice").await else {
+        unreachable!();
+    };
+    assert_eq!((delivered, queued), (2, 1));
+}
+
+#[tokio::test]
+async fn counters_survive_a_reconnect() {
+    let server = TestServer::builder().with_offline_queue(false).start();
+    server.create_admin("admin", "secret").await;
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+
+    let mut alice = server.login("alice").await;
+    alice.send(Message::direct_message_send("bob", "are you there?")).await;
+    assert!(alice.receive().await.is(MessageType::MessageError));
+    alice.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+    let _alice = server.login("alice").await;
+
+    let ClientEvent::UserInfo {
+        sessions,
+        sent,
+        dropped,
+        last_error,
+        ..
+    } = user_info(&mut admin, "alice").await
+    else {
+        unreachable!();
+    };
+    assert_eq!((sessions, sent, dropped), (1, 1, 1));
+    assert_eq!(last_error, Some("bob is not online".to_string()));
+}
+
+#[tokio::test]
+async fn only_moderators_see_user_info() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+
+    alice.send(Message::admin_user_info("alice")).await;
+    assert!(alice.receive().await.is(MessageType::Nack));
+
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.login("admin").await;
+    admin.send(Message::admin_user_info("nobody")).await;
+    assert!(admin.receive().await.is(MessageType::Nack));
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+// the connection and the user id the server confirmed
+async fn authenticated(server: &TestServer, auth: Message) -> (RawConnection, String) {
+    let mut connection = server.raw_connection().await;
+    connection.send(auth).await;
+    let reply = connection.receive().await;
+    assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+    let id = reply.payload().str_field(0).unwrap().to_string();
+    (connection, id)
+}
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn expect_renamed(connection: &mut RawConnection, old: &str, new: &str) {
+    let notice = connection.receive().await;
+    assert!(notice.is(MessageType::UserRenamed), "Unexpected {:?}", notice);
+    assert_eq!(notice.payload().str_field(0), Ok(old));
+    assert_eq!(notice.payload().str_field(1), Ok(new));
+}
+
+async fn history(connection: &mut RawConnection, peer: &str) -> Vec<String> {
+    connection.send(Message::history_request(peer, 10)).await;
+    let mut bodies = Vec::new();
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::HistoryEnd) {
+            return bodies;
+        }
+        assert!(message.is(MessageType::HistoryEntry), "Unexpected {:?}", message);
+        bodies.push(message.payload().str_field(3).unwrap().to_string());
+    }
+}
+
+#[tokio::test]
+async fn every_reference_follows_the_new_name() {
+    let server = TestServer::start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+    let mut carol = server.logged_in("carol").await;
+    let (mut alice, id) = authenticated(&server, Message::auth_create("alice", "secret")).await;
+
+    alice.send_direct("carol", "hello carol").await;
+    assert!(carol.receive().await.is(MessageType::DirectMessageReceive));
+    alice.send_direct("bob", "queued for bob").await;
+
+    alice.send(Message::rename_account("alicia", "secret")).await;
+    expect_renamed(&mut alice, "alice", "alicia").await;
+    expect_renamed(&mut carol, "alice", "alicia").await;
+
+    // the live session now speaks as the new name
+    alice.send_direct("carol", "still me").await;
+    let received = carol.receive().await;
+    assert_eq!(received.payload().str_field(0), Ok("alicia"));
+    assert_eq!(history(&mut carol, "alicia").await, vec!["hello carol", "still me"]);
+    assert!(history(&mut carol, "alice").await.is_empty());
+
+    // the queued message and its unread count are under the new name as well
+    let mut bob = server.login("bob").await;
+    let summary = bob.receive().await;
+    assert_eq!(summary.unread_counts(), Ok(vec![("alicia".to_string(), 1)]));
+    let queued = bob.receive().await;
+    assert!(queued.is(MessageType::DirectMessageReceive));
+    assert_eq!(queued.payload().str_field(0), Ok("alicia"));
+
+    leave(&server, alice, "alicia").await;
+    // same account, same id
+    let (_, renamed_id) = authenticated(&server, Message::auth("alicia", "secret")).await;
+    assert_eq!(renamed_id, id);
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+
+    // without a grace period the old name is free straight away
+    server.logged_in("alice").await;
+    let audit = server.audit_entries().await;
+    assert!(audit.iter().any(|entry| entry.action() == "rename_account"));
+}
+
+#[tokio::test]
+async fn invalid_renames_are_refused() {
+    let server = TestServer::start();
+    server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    for (new_name, password) in [("alicia", "wrong"), ("bob", "secret"), ("", "secret")] {
+        alice.send(Message::rename_account(new_name, password)).await;
+        let reply = alice.receive().await;
+        assert!(reply.is(MessageType::AuthFailure), "{} was accepted", new_name);
+    }
+    alice.send(Message::rename_account("alicia", "wrong")).await;
+    assert_eq!(alice.receive().await.payload().str_field(0), Ok("Invalid password"));
+    assert!(server.is_logged_in("alice").await);
+}
+
+#[tokio::test]
+async fn old_name_is_reserved_during_the_grace_period() {
+    let server = TestServer::builder().with_rename_grace(Duration::from_secs(60)).start();
+    let mut alice = server.logged_in("alice").await;
+    alice.send(Message::rename_account("alicia", "secret")).await;
+    expect_renamed(&mut alice, "alice", "alicia").await;
+
+    let mut mallory = server.raw_connection().await;
+    mallory.send(Message::auth_create("alice", "secret")).await;
+    assert!(mallory.receive().await.is(MessageType::AuthFailure));
+
+    // the previous owner may take it back
+    alice.send(Message::rename_account("alice", "secret")).await;
+    expect_renamed(&mut alice, "alicia", "alice").await;
+}
+
+#[tokio::test]
+async fn admins_can_rename_other_users() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+    let id = bob.client().user_id().await;
+    assert!(id.is_some());
+    let mut admin = server.login("admin").await;
+
+    admin.send(Message::admin_rename_user("bob", "robert")).await;
+    expect_renamed(&mut admin, "bob", "robert").await;
+    let event = bob
+        .expect(|event| matches!(event, ClientEvent::UserRenamed { .. }))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::UserRenamed {
+            old: "bob".to_string(),
+            new: "robert".to_string()
+        }
+    );
+    assert_eq!(bob.client().username().await.as_deref(), Some("robert"));
+    assert_eq!(bob.client().user_id().await, id);
+
+    admin.send(Message::admin_rename_user("nobody", "somebody")).await;
+    assert!(admin.receive().await.is(MessageType::Nack));
+    let audit = server.audit_entries().await;
+    assert!(audit
+        .iter()
+        .any(|entry| entry.action() == "rename_user" && entry.detail() == "bob -> robert"));
+}
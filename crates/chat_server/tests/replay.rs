@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageId, MessageType},
+    trace::{to_hex, Direction, TraceRecord, REDACTED},
+};
+use chat_server::application::testing::{AccessLevel, Replay, TestServer};
+use chrono::{DateTime, Utc};
+
+fn capture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/captures")
+        .join(format!("{}.jsonl", name))
+}
+
+// the record with another frame in its place
+fn replaced(record: &TraceRecord, message: &Message) -> TraceRecord {
+    let value = JsonValue::object()
+        .with("timestamp", record.timestamp())
+        .with("direction", record.direction().as_str())
+        .with("session", record.session().map(str::to_string))
+        .with("bytes", to_hex(&message.to_bytes()));
+    TraceRecord::from_json(&value).unwrap()
+}
+
+#[tokio::test]
+async fn the_recorded_auth_flow_replays_unchanged() {
+    let report = Replay::load(&capture("auth_flow")).unwrap().run().await.unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.frames, 7);
+}
+
+#[tokio::test]
+async fn the_recorded_direct_message_exchange_replays_unchanged() {
+    let records = TraceRecord::read_all(&capture("dm_exchange")).unwrap();
+    // nothing that was said is in the checked in capture
+    assert!(records
+        .iter()
+        .filter(|record| record.message().is(MessageType::DirectMessageSend))
+        .all(TraceRecord::is_redacted));
+
+    let report = Replay::new(records).run().await.unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.frames, 15);
+}
+
+#[tokio::test]
+async fn a_different_answer_is_reported_by_field() {
+    let mut records = TraceRecord::read_all(&capture("dm_exchange")).unwrap();
+    let index = records
+        .iter()
+        .position(|record| record.message().is(MessageType::DirectMessageReceive))
+        .unwrap();
+    let recorded = records[index].message().clone();
+    let payload = recorded.payload();
+    let sent_at = DateTime::parse_from_rfc3339(payload.str_field(2).unwrap()).unwrap();
+    let expected = Message::direct_message_receive(
+        "carol",
+        payload.str_field(1).unwrap(),
+        sent_at.with_timezone(&Utc),
+        MessageId::new(payload.u64_field(3).unwrap()),
+        None,
+    );
+    records[index] = replaced(&records[index], &expected);
+
+    let report = Replay::new(records).run().await.unwrap();
+    assert_eq!(report.divergences.len(), 1, "{}", report);
+    let divergence = &report.divergences[0];
+    assert_eq!(divergence.frame, index + 1);
+    // bob's, the second connection the capture opened
+    assert_eq!(divergence.session, 2);
+    assert_eq!(divergence.message_type, MessageType::DirectMessageReceive);
+    assert_eq!(divergence.field, Some(0));
+    assert_eq!(
+        (divergence.expected.as_str(), divergence.actual.as_str()),
+        ("carol", "alice")
+    );
+    assert!(report.to_string().contains("field 0"), "{}", report);
+}
+
+#[tokio::test]
+async fn a_session_replays_against_a_snapshot_of_users() {
+    // the second connection of the auth flow, alice registered before it
+    let records = TraceRecord::read_all(&capture("auth_flow")).unwrap();
+    let first = records[0].session().map(str::to_string);
+    let second: Vec<TraceRecord> = records
+        .into_iter()
+        .filter(|record| record.session().map(str::to_string) != first)
+        .collect();
+    assert!(second[0].direction() == Direction::Sent);
+
+    let report = Replay::new(second.clone()).run().await.unwrap();
+    assert!(!report.is_clean(), "alice is not known without the snapshot");
+
+    // recorded passwords read as the placeholder, the snapshot has it as hers
+    let snapshot = TestServer::start();
+    snapshot.create_user("alice", REDACTED, AccessLevel::User).await;
+    let users = snapshot.export_users().await;
+    let report = Replay::new(second).with_users(&users).run().await.unwrap();
+    assert!(report.is_clean(), "{}", report);
+}
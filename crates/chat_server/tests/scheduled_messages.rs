@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use chat_core::{
+    protocol::{Message, MessageType, ScheduleTime, ScheduledEntry},
+    time_sync::Clock,
+};
+use chat_server::application::testing::{AccessLevel, Permissions, RawConnection, ScheduleLimits, TestServer};
+
+async fn schedule(connection: &mut RawConnection, recipient: &str, body: &str, due: ScheduleTime) -> Message {
+    connection
+        .send(Message::schedule_message_send(recipient, body, due))
+        .await;
+    next(connection).await
+}
+
+fn pending(message: &Message) -> Vec<ScheduledEntry> {
+    assert!(message.is(MessageType::ScheduledMessages), "Unexpected {:?}", message);
+    message.scheduled_entries().unwrap()
+}
+
+fn minutes(minutes: u64) -> ScheduleTime {
+    ScheduleTime::In(Duration::from_secs(minutes * 60))
+}
+
+// skips the heartbeats that came in while the clock moved on
+async fn next(connection: &mut RawConnection) -> Message {
+    loop {
+        let message = connection.receive().await;
+        if !message.is(MessageType::Heartbeat) {
+            return message;
+        }
+    }
+}
+
+// the sender hears back once the message came due, with the reason if it did not go out
+async fn fired(connection: &mut RawConnection) -> (u64, String, Option<String>) {
+    let message = next(connection).await;
+    assert!(message.is(MessageType::ScheduleFired), "Unexpected {:?}", message);
+    message.schedule_outcome().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_scheduled_message_goes_out_when_it_is_due() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let entries = pending(&schedule(&mut alice, "bob", "standup", minutes(10)).await);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].recipient, "bob");
+    assert_eq!(entries[0].body, "standup");
+    let ahead = entries[0].due - server.clock().now();
+    assert!(ahead > chrono::Duration::minutes(9) && ahead <= chrono::Duration::minutes(10));
+
+    server.clock().advance(Duration::from_secs(9 * 60)).await;
+    alice.send(Message::schedule_list()).await;
+    assert_eq!(pending(&next(&mut alice).await), entries);
+
+    server.clock().advance(Duration::from_secs(60)).await;
+    let message = next(&mut bob).await;
+    assert!(
+        message.is(MessageType::DirectMessageReceive),
+        "Unexpected {:?}",
+        message
+    );
+    assert_eq!(message.payload().str_field(0), Ok("alice"));
+    assert_eq!(message.payload().str_field(1), Ok("standup"));
+    assert_eq!(fired(&mut alice).await, (entries[0].id, "bob".to_string(), None));
+
+    alice.send(Message::schedule_list()).await;
+    assert!(pending(&next(&mut alice).await).is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_cancelled_message_never_goes_out() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let first = pending(&schedule(&mut alice, "bob", "first", minutes(5)).await);
+    let both = pending(&schedule(&mut alice, "bob", "second", minutes(1)).await);
+    // soonest first
+    assert_eq!(
+        both.iter().map(|entry| entry.body.as_str()).collect::<Vec<_>>(),
+        ["second", "first"]
+    );
+
+    // only the sender can cancel it
+    bob.send(Message::schedule_cancel(first[0].id)).await;
+    let reply = next(&mut bob).await;
+    assert_eq!(reply.rejected_type(), Some(MessageType::ScheduleCancel));
+
+    alice.send(Message::schedule_cancel(first[0].id)).await;
+    let left = pending(&next(&mut alice).await);
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].body, "second");
+
+    server.clock().advance(Duration::from_secs(10 * 60)).await;
+    assert_eq!(next(&mut bob).await.payload().str_field(1), Ok("second"));
+    assert_eq!(fired(&mut alice).await.0, left[0].id);
+
+    // nothing else arrives
+    bob.send(Message::schedule_list()).await;
+    assert!(pending(&next(&mut bob).await).is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_past_the_limits_are_refused() {
+    let server = TestServer::builder()
+        .with_schedule_limits(ScheduleLimits {
+            max_pending: 2,
+            max_horizon: Duration::from_secs(60 * 60),
+        })
+        .start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+
+    let reply = schedule(&mut alice, "bob", "too late", minutes(61)).await;
+    assert_eq!(reply.rejected_type(), Some(MessageType::ScheduleMessageSend));
+    assert_eq!(
+        reply.payload().str_field(1),
+        Ok("Messages can be scheduled at most 3600 seconds ahead")
+    );
+
+    let past = server.clock().now() - chrono::Duration::minutes(1);
+    let reply = schedule(&mut alice, "bob", "yesterday", ScheduleTime::At(past)).await;
+    assert_eq!(reply.payload().str_field(1), Ok("The time is in the past"));
+
+    pending(&schedule(&mut alice, "bob", "one", minutes(1)).await);
+    pending(&schedule(&mut alice, "bob", "two", minutes(2)).await);
+    let reply = schedule(&mut alice, "bob", "three", minutes(3)).await;
+    assert_eq!(reply.rejected_type(), Some(MessageType::ScheduleMessageSend));
+    assert_eq!(
+        reply.payload().str_field(1),
+        Ok("At most 2 messages can wait to be sent")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_checks_of_a_normal_send_apply_when_the_message_is_due() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+
+    // the recipient does not have to exist yet, only when the message goes out
+    let later = pending(&schedule(&mut alice, "carol", "welcome", minutes(10)).await);
+    let early = pending(&schedule(&mut alice, "dave", "hello", minutes(1)).await);
+    let early = early.iter().find(|entry| entry.recipient == "dave").unwrap();
+
+    server.clock().advance(Duration::from_secs(60)).await;
+    let (id, recipient, error) = fired(&mut alice).await;
+    assert_eq!((id, recipient.as_str()), (early.id, "dave"));
+    assert_eq!(error.as_deref(), Some("User dave does not exist"));
+
+    server.create_user("carol", "secret", AccessLevel::User).await;
+    let mut carol = server.login("carol").await;
+    server.clock().advance(Duration::from_secs(9 * 60)).await;
+    assert_eq!(next(&mut carol).await.payload().str_field(1), Ok("welcome"));
+    assert_eq!(fired(&mut alice).await, (later[0].id, "carol".to_string(), None));
+
+    // permission is judged as it is then, not as it was when the message was scheduled
+    let revoked = pending(&schedule(&mut alice, "carol", "still there?", minutes(1)).await);
+    server
+        .set_permission_overrides("alice", Permissions::NONE, Permissions::SEND_DM)
+        .await;
+    server.clock().advance(Duration::from_secs(60)).await;
+    let (id, _, error) = fired(&mut alice).await;
+    assert_eq!(id, revoked[0].id);
+    assert_eq!(error.as_deref(), Some("You are not allowed to send direct messages"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn pending_messages_survive_a_restart() {
+    let dir = TestServer::scratch_dir("restart");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    let entries = pending(&schedule(&mut alice, "bob", "after the restart", minutes(30)).await);
+    drop(alice);
+    drop(server);
+
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    server.create_user("bob", "secret", AccessLevel::User).await;
+    let mut alice = server.login("alice").await;
+    alice.send(Message::schedule_list()).await;
+    assert_eq!(pending(&next(&mut alice).await), entries);
+
+    // the ids keep counting where they were
+    let more = pending(&schedule(&mut alice, "bob", "another", minutes(40)).await);
+    assert!(more[1].id > entries[0].id);
+}
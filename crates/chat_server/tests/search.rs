@@ -0,0 +1,185 @@
+use chat_client::client::ClientEvent;
+use chat_core::{
+    protocol::{Message, MessageType},
+    time_sync::Clock,
+};
+use chat_server::application::testing::{RawConnection, TestServer};
+
+// (peer, highlighted text, snippet) of every result
+async fn search(connection: &mut RawConnection, query: &str, peer: Option<&str>) -> Vec<(String, String, String)> {
+    connection.send(Message::search_request(query, peer, 20)).await;
+    let mut results = Vec::new();
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::SearchEnd) {
+            assert_eq!(message.payload().u64_field(0), Ok(results.len() as u64));
+            assert!(message.payload().str_field(1).is_err(), "Refused {:?}", message);
+            return results;
+        }
+        assert!(message.is(MessageType::SearchResult), "Unexpected {:?}", message);
+        let payload = message.payload();
+        let snippet = payload.str_field(3).unwrap().to_string();
+        let range = payload.u64_field(4).unwrap() as usize..payload.u64_field(5).unwrap() as usize;
+        results.push((
+            payload.str_field(1).unwrap().to_string(),
+            snippet[range].to_string(),
+            snippet,
+        ));
+    }
+}
+
+async fn refusal(connection: &mut RawConnection, query: &str) -> String {
+    connection.send(Message::search_request(query, None, 20)).await;
+    let message = connection.receive().await;
+    assert!(message.is(MessageType::SearchEnd), "Unexpected {:?}", message);
+    message.payload().str_field(1).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn only_own_conversations_are_searched() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+    let mut carol = server.logged_in("carol").await;
+
+    alice.send_direct("bob", "the secret plan").await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    carol.send_direct("bob", "my own secret").await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    assert_eq!(search(&mut carol, "secret", None).await.len(), 1);
+    // naming someone else's conversation partner does not widen the search
+    assert!(search(&mut carol, "plan", Some("alice")).await.is_empty());
+    assert!(search(&mut carol, "plan", None).await.is_empty());
+
+    let results = search(&mut bob, "SECRET", None).await;
+    let peers: Vec<&str> = results.iter().map(|(peer, _, _)| peer.as_str()).collect();
+    assert_eq!(peers, vec!["carol", "alice"]);
+    assert_eq!(results[1].1, "secret");
+}
+
+#[tokio::test]
+async fn results_are_newest_first_and_can_be_filtered() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+    let mut carol = server.logged_in("carol").await;
+
+    alice.send_direct("bob", "lunch at noon?").await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    bob.send_direct("alice", "Lunch sounds good").await;
+    assert!(alice.receive().await.is(MessageType::DirectMessageReceive));
+    alice.send_direct("carol", "lunch with bob later").await;
+    assert!(carol.receive().await.is(MessageType::DirectMessageReceive));
+
+    let results = search(&mut alice, "lunch", None).await;
+    let snippets: Vec<&str> = results.iter().map(|(_, _, snippet)| snippet.as_str()).collect();
+    assert_eq!(
+        snippets,
+        vec!["lunch with bob later", "Lunch sounds good", "lunch at noon?"]
+    );
+
+    let results = search(&mut alice, "lunch", Some("bob")).await;
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|(peer, _, _)| peer == "bob"));
+    assert_eq!(results[0].1, "Lunch");
+}
+
+#[tokio::test]
+async fn long_messages_are_cut_to_a_snippet_around_the_match() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+
+    let body = format!("{}needle{}", "ä".repeat(100), "z".repeat(100));
+    alice.send_direct("bob", &body).await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    let results = search(&mut bob, "NEEDLE", None).await;
+    let (_, highlighted, snippet) = &results[0];
+    assert_eq!(highlighted, "needle");
+    assert_eq!(snippet, &format!("{}needle{}", "ä".repeat(32), "z".repeat(32)));
+}
+
+#[tokio::test]
+async fn short_and_excessive_searches_are_refused() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    assert_eq!(
+        refusal(&mut alice, " ab ").await,
+        "Search queries need at least 3 characters"
+    );
+    for _ in 0..10 {
+        search(&mut alice, "anything", None).await;
+    }
+    assert_eq!(
+        refusal(&mut alice, "anything").await,
+        "Too many searches, try again later"
+    );
+}
+
+#[tokio::test]
+async fn client_reports_results_and_refusals() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().send_direct_message("bob", "meet at the station").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    assert!(alice.client().search("station", Some("bob"), 5).await);
+    let ClientEvent::SearchResult {
+        peer,
+        snippet,
+        highlight,
+        ..
+    } = alice
+        .expect(|event| matches!(event, ClientEvent::SearchResult { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(peer, "bob");
+    assert_eq!(&snippet[highlight.0..highlight.1], "station");
+    alice
+        .expect(|event| *event == ClientEvent::SearchEnd { count: 1, error: None })
+        .await;
+
+    assert!(alice.client().search("at", None, 5).await);
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::SearchEnd { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        ClientEvent::SearchEnd {
+            count: 0,
+            error: Some(_)
+        }
+    ));
+}
+
+#[tokio::test]
+async fn expiry_follows_the_server_clock() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+
+    alice
+        .send_acked(Message::direct_message_send_with_ttl("bob", "the secret plan", 60))
+        .await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    assert_eq!(search(&mut bob, "plan", None).await.len(), 1);
+
+    // only the wall clock jumps, the purge timer has not fired, the message is still stored
+    let later = server.clock().now() + chrono::Duration::seconds(61);
+    server.clock().set_now(later);
+    assert!(search(&mut bob, "plan", None).await.is_empty());
+    bob.send(Message::history_request("alice", 10)).await;
+    let end = bob.receive().await;
+    assert!(end.is(MessageType::HistoryEnd), "Unexpected {:?}", end);
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use chat_core::protocol::{Message, MessageType, HISTORY_SELF_NOTE};
+use chat_server::application::testing::{eventually, FloodLimits, RawConnection, TakeoverPolicy, TestServer};
+
+async fn second_login(server: &TestServer, username: &str) -> RawConnection {
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth(username, "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    connection
+}
+
+#[tokio::test]
+async fn a_single_session_keeps_notes_in_history() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+
+    alice.send(Message::direct_message_send("alice", "buy milk")).await;
+    let ack = alice.receive().await;
+    assert!(ack.is(MessageType::Ack));
+    let id = ack.payload().u64_field(0).unwrap();
+
+    // the author's own session gets the ack and nothing else
+    alice.send(Message::history_request("alice", 10)).await;
+    let entry = alice.receive().await;
+    assert!(entry.is(MessageType::HistoryEntry), "Unexpected {:?}", entry);
+    assert_eq!(entry.payload().u64_field(0), Ok(id));
+    assert_eq!(entry.payload().str_field(3), Ok("buy milk"));
+    assert_eq!(entry.payload().u64_field(5), Ok(HISTORY_SELF_NOTE));
+    assert!(alice.receive().await.is(MessageType::HistoryEnd));
+}
+
+#[tokio::test]
+async fn notes_reach_the_other_sessions_of_the_author() {
+    let server = TestServer::builder()
+        .with_takeover_policy(TakeoverPolicy::Allow)
+        .start();
+    let mut laptop = server.logged_in("alice").await;
+    let mut phone = second_login(&server, "alice").await;
+
+    laptop.send(Message::direct_message_send("alice", "call bob")).await;
+    assert!(laptop.receive().await.is(MessageType::Ack));
+    let note = phone.receive().await;
+    assert!(note.is_self_note(), "Expected a note, got {:?}", note);
+    assert_eq!(note.payload().str_field(0), Ok("alice"));
+    assert_eq!(note.payload().str_field(1), Ok("call bob"));
+
+    // the laptop never sees its own note, the next frame answers the next request
+    laptop.send(Message::direct_message_send("alice", "again")).await;
+    assert!(laptop.receive().await.is(MessageType::Ack));
+    assert!(phone.receive().await.is_self_note());
+}
+
+#[tokio::test]
+async fn concurrent_logins_hand_over_when_one_leaves() {
+    let server = TestServer::builder()
+        .with_takeover_policy(TakeoverPolicy::Allow)
+        .start();
+    let mut laptop = server.logged_in("alice").await;
+    let mut phone = second_login(&server, "alice").await;
+    let mut bob = server.logged_in("bob").await;
+
+    phone.send(Message::DISCONNECT).await;
+    eventually(|| async { server.session_count().await == 2 }).await;
+    assert!(server.is_logged_in("alice").await);
+
+    bob.send(Message::direct_message_send("alice", "hi")).await;
+    assert!(bob.receive().await.is(MessageType::Ack));
+    let message = laptop.receive().await;
+    assert!(message.is(MessageType::DirectMessageReceive));
+    assert!(!message.is_self_note());
+}
+
+#[tokio::test]
+async fn notes_are_bound_by_the_same_limits() {
+    let server = TestServer::builder()
+        .with_max_message_ttl(Duration::from_secs(60))
+        .with_flood_limits(FloodLimits {
+            frames_per_second: 5,
+            ..FloodLimits::default()
+        })
+        .start();
+    let mut alice = server.logged_in("alice").await;
+    // logging in used up a frame of the window
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    alice
+        .send(Message::direct_message_send_with_ttl("alice", "forever", 3600))
+        .await;
+    assert!(alice.receive().await.is(MessageType::MessageError));
+
+    for _ in 0..5 {
+        alice.send(Message::direct_message_send("alice", "note")).await;
+    }
+    for _ in 0..4 {
+        assert!(alice.receive().await.is(MessageType::Ack));
+    }
+    assert!(alice.receive().await.is(MessageType::MessageError));
+}
@@ -0,0 +1,142 @@
+use std::fs;
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{RawConnection, TestServer};
+
+// name and id from the hello of a fresh connection
+async fn hello(server: &TestServer) -> (String, String) {
+    let mut connection = server.unchecked_connection();
+    let hello = connection.receive().await;
+    assert!(hello.is(MessageType::ServerHello), "{:?}", hello);
+    let payload = hello.payload();
+    (
+        payload.str_field(2).unwrap().to_string(),
+        payload.str_field(3).unwrap().to_string(),
+    )
+}
+
+async fn expect_motd(connection: &mut RawConnection, text: &str) {
+    let motd = connection.receive().await;
+    assert!(motd.is(MessageType::Motd), "{:?}", motd);
+    assert_eq!(motd.payload().str_field(0), Ok(text));
+}
+
+#[tokio::test]
+async fn the_id_survives_a_restart() {
+    let dir = TestServer::scratch_dir("restart");
+    let first = TestServer::builder()
+        .with_data_dir(dir.clone())
+        .with_server_name("north")
+        .start();
+    let (name, id) = hello(&first).await;
+    assert_eq!(name, "north");
+    assert_eq!(fs::read_to_string(dir.join("server_id")).unwrap().trim(), id);
+    drop(first);
+
+    let second = TestServer::builder().with_data_dir(dir.clone()).start();
+    assert_eq!(hello(&second).await, ("chat_rs".to_string(), id.clone()));
+
+    // without a data directory every start is a new server
+    let other = TestServer::start();
+    assert_ne!(hello(&other).await.1, id);
+}
+
+#[tokio::test]
+async fn clients_and_stats_name_the_server() {
+    let server = TestServer::builder().with_server_name("north").start();
+    server.create_admin("admin", "secret").await;
+    let (_, id) = hello(&server).await;
+
+    let mut admin = server.client().await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerIdentity { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::ServerIdentity { name, id: got } if name == "north" && got == id));
+    assert_eq!(admin.client().server_name().await.as_deref(), Some("north"));
+
+    admin.login("admin", "secret").await;
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    assert!(
+        matches!(event, ClientEvent::ServerStats { server_name, server_id, .. } if server_name == "north" && server_id == id)
+    );
+}
+
+#[tokio::test]
+async fn a_new_motd_is_broadcast_and_kept() {
+    let dir = TestServer::scratch_dir("motd");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    // a connection does not have to be logged in to hear it
+    let mut guest = server.raw_connection().await;
+
+    admin
+        .client()
+        .send(Message::admin_set_motd("Maintenance at noon", true))
+        .await;
+    for client in [&mut admin, &mut alice] {
+        let event = client.expect(|event| matches!(event, ClientEvent::Motd(_))).await;
+        assert!(matches!(event, ClientEvent::Motd(text) if text == "Maintenance at noon"));
+    }
+    expect_motd(&mut guest, "Maintenance at noon").await;
+
+    // later connections get it right after the hello
+    let mut late = server.raw_connection().await;
+    expect_motd(&mut late, "Maintenance at noon").await;
+
+    let details: Vec<String> = server
+        .audit_entries()
+        .await
+        .iter()
+        .filter(|entry| entry.action() == "set_motd")
+        .map(|entry| entry.detail().to_string())
+        .collect();
+    assert_eq!(details, ["'Maintenance at noon' (broadcast)"]);
+
+    drop((admin, alice, guest, late));
+    drop(server);
+    let restarted = TestServer::builder().with_data_dir(dir).start();
+    let mut connection = restarted.raw_connection().await;
+    expect_motd(&mut connection, "Maintenance at noon").await;
+}
+
+#[tokio::test]
+async fn a_quiet_update_only_answers_the_admin() {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    admin
+        .client()
+        .send(Message::admin_set_motd("Read the rules", false))
+        .await;
+    let event = admin.expect(|event| matches!(event, ClientEvent::Motd(_))).await;
+    assert!(matches!(event, ClientEvent::Motd(text) if text == "Read the rules"));
+
+    // the answer to a request of her own comes first, no motd was queued before it
+    alice.client().send(Message::admin_server_stats()).await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::Motd(_) | ClientEvent::Rejected { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::Rejected { .. }), "{:?}", event);
+
+    // users without the permission cannot change it
+    alice.client().send(Message::admin_set_motd("Mine now", true)).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+    let mut connection = server.raw_connection().await;
+    expect_motd(&mut connection, "Read the rules").await;
+}
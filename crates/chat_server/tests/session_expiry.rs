@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    protocol::{Message, MessageType},
+    time_sync::Clock,
+};
+use chat_server::application::testing::{eventually, TestServer};
+
+// the runtime is paused for the expiry tests, the time passes without waiting for it
+const MAX_SESSION_AGE: Duration = Duration::from_secs(10);
+
+#[tokio::test(start_paused = true)]
+async fn expired_session_reauthenticates_automatically() {
+    let server = TestServer::builder()
+        .with_max_session_age(Some(MAX_SESSION_AGE))
+        .start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    server.clock().advance(MAX_SESSION_AGE).await;
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::ReauthRequired(_)))
+        .await;
+    assert_eq!(event, ClientEvent::ReauthRequired("Session expired".to_string()));
+
+    alice.expect(|event| matches!(event, ClientEvent::Authenticated)).await;
+    assert!(alice.client().is_authenticated().await);
+    assert!(server.is_logged_in("alice").await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_session_is_demoted_but_stays_connected() {
+    let server = TestServer::builder()
+        .with_max_session_age(Some(MAX_SESSION_AGE))
+        .start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    server.clock().advance(MAX_SESSION_AGE).await;
+
+    let notice = connection.receive().await;
+    assert!(notice.is(MessageType::ReauthRequired));
+    assert!(!server.is_logged_in("alice").await);
+
+    connection.send(Message::direct_message_send("alice", "hello")).await;
+    assert!(connection.receive().await.is(MessageType::Nack));
+    assert_eq!(server.session_count().await, 1);
+
+    connection.send(Message::auth("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+    assert!(server.is_logged_in("alice").await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn sessions_do_not_expire_when_disabled() {
+    let server = TestServer::builder().with_max_session_age(None).start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+
+    server.clock().advance(MAX_SESSION_AGE * 2).await;
+    connection.send(Message::direct_message_send("nobody", "hello")).await;
+    assert!(connection.receive().await.is(MessageType::MessageError));
+}
+
+#[tokio::test(start_paused = true)]
+async fn wall_clock_jump_expires_sessions() {
+    let server = TestServer::builder()
+        .with_max_session_age(Some(MAX_SESSION_AGE))
+        .start();
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth_create("alice", "secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthSuccess));
+
+    // the reaper only needs to tick once more to see the session is too old
+    server
+        .clock()
+        .set_now(server.clock().now() + chrono::Duration::hours(2));
+    assert!(connection.receive().await.is(MessageType::ReauthRequired));
+    assert!(!server.is_logged_in("alice").await);
+}
+
+#[tokio::test]
+async fn password_change_updates_credentials() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "old-secret").await;
+
+    alice.client().change_password("wrong", "new-secret").await;
+    alice.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+
+    alice.client().change_password("old-secret", "new-secret").await;
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::PasswordChanged(_)))
+        .await;
+    assert_eq!(event, ClientEvent::PasswordChanged("alice".to_string()));
+    alice.disconnect().await;
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth("alice", "old-secret")).await;
+    assert!(connection.receive().await.is(MessageType::AuthFailure));
+
+    let mut alice = server.client().await;
+    alice.login("alice", "new-secret").await;
+}
+
+#[tokio::test]
+async fn admin_reset_expires_the_users_session() {
+    let server = TestServer::start();
+    server.create_admin("admin", "admin-secret").await;
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "admin-secret").await;
+    admin.client().reset_password("alice", "reset-secret").await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::PasswordChanged(_)))
+        .await;
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::ReauthRequired(_)))
+        .await;
+    assert_eq!(
+        event,
+        ClientEvent::ReauthRequired("Password was reset by an administrator".to_string())
+    );
+
+    // the stored credentials are stale, so the automatic re-authentication fails
+    alice.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+    assert!(!server.is_logged_in("alice").await);
+
+    alice.client().login("alice", "reset-secret").await;
+    alice.expect(|event| matches!(event, ClientEvent::Authenticated)).await;
+
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.actor() == "admin" && entry.action() == "reset_password" && entry.detail() == "alice"));
+}
+
+#[tokio::test]
+async fn users_cannot_reset_passwords() {
+    let server = TestServer::start();
+
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice.client().reset_password("alice", "other").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+}
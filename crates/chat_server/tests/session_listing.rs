@@ -0,0 +1,225 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{DisconnectReason, Message, MessageType, SessionFilter, SessionInfo, NOTICE_KICKED};
+use chat_server::application::testing::{eventually, AccessLevel, RawConnection, TestClient, TestServer};
+
+const OFFICE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 1, 0, 5));
+const ELSEWHERE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 2, 0, 7));
+
+async fn admin_server() -> (TestServer, TestClient) {
+    let server = TestServer::builder().with_max_missed_heartbeats(0).start();
+    server.create_admin("admin", "secret").await;
+
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    (server, admin)
+}
+
+// a connection that never logs in, one frame keeps it past the handshake timeout
+async fn guest(server: &TestServer, address: IpAddr) -> RawConnection {
+    let mut connection = server.unchecked_connection_from(address);
+    assert!(connection.receive().await.is(MessageType::ServerHello));
+    connection.send(Message::heartbeat()).await;
+    connection
+}
+
+// the entries of one list and whether it kicked them
+async fn request(client: &mut TestClient, message: Message) -> (Vec<SessionInfo>, bool) {
+    client.client().send(message).await;
+    let mut sessions = Vec::new();
+    loop {
+        match client.next_event().await {
+            ClientEvent::SessionInfo(info) => sessions.push(info),
+            ClientEvent::SessionListEnd { count, kicked } => {
+                assert_eq!(count, sessions.len() as u64);
+                return (sessions, kicked);
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn list(client: &mut TestClient, filter: SessionFilter) -> Vec<SessionInfo> {
+    let (sessions, kicked) = request(client, Message::admin_list_sessions(&filter)).await;
+    assert!(!kicked);
+    sessions
+}
+
+fn usernames(sessions: &[SessionInfo]) -> Vec<&str> {
+    let mut names: Vec<&str> = sessions.iter().map(|info| info.username.as_str()).collect();
+    names.sort();
+    names
+}
+
+fn guests_only() -> SessionFilter {
+    SessionFilter {
+        guests_only: true,
+        ..SessionFilter::default()
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_listed_and_filtered() {
+    let (server, mut admin) = admin_server().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let _office = guest(&server, OFFICE).await;
+    let _elsewhere = guest(&server, ELSEWHERE).await;
+
+    let everyone = list(&mut admin, SessionFilter::default()).await;
+    assert_eq!(usernames(&everyone), vec!["admin", "alice", "guest", "guest"]);
+    let own = everyone.iter().find(|info| info.username == "admin").unwrap();
+    assert_eq!(own.access_level, "admin");
+    assert!(own.received > 0 && own.sent > 0, "{:?}", own);
+
+    let guests = list(&mut admin, guests_only()).await;
+    assert_eq!(usernames(&guests), vec!["guest", "guest"]);
+    assert!(guests.iter().all(|info| info.access_level == "guest"));
+
+    let office = list(
+        &mut admin,
+        SessionFilter {
+            ip_prefix: "10.1.".to_string(),
+            ..SessionFilter::default()
+        },
+    )
+    .await;
+    assert_eq!(office.len(), 1);
+    assert!(office[0].peer.starts_with("10.1.0.5:"), "{:?}", office[0]);
+
+    let nobody = list(
+        &mut admin,
+        SessionFilter {
+            guests_only: true,
+            ip_prefix: "192.168.".to_string(),
+            ..SessionFilter::default()
+        },
+    )
+    .await;
+    assert!(nobody.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_sessions_are_told_apart_from_active_ones() {
+    let (server, mut admin) = admin_server().await;
+    let _idle = guest(&server, OFFICE).await;
+
+    server.clock().advance(Duration::from_secs(10 * 60)).await;
+
+    // the list request itself makes the admin active again
+    let idle = list(
+        &mut admin,
+        SessionFilter {
+            idle_minutes: 5,
+            ..SessionFilter::default()
+        },
+    )
+    .await;
+    assert_eq!(usernames(&idle), vec!["guest"]);
+    assert!(idle[0].idle >= 10 * 60 && idle[0].connected >= 10 * 60, "{:?}", idle[0]);
+
+    let everyone = list(&mut admin, SessionFilter::default()).await;
+    let own = everyone.iter().find(|info| info.username == "admin").unwrap();
+    assert_eq!(own.idle, 0);
+}
+
+#[tokio::test]
+async fn a_dry_run_kicks_nobody() {
+    let (server, mut admin) = admin_server().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let _office = guest(&server, OFFICE).await;
+    let _elsewhere = guest(&server, ELSEWHERE).await;
+
+    let (affected, kicked) = request(&mut admin, Message::admin_kick_where(&guests_only(), "cleanup", true)).await;
+    assert!(!kicked);
+    assert_eq!(usernames(&affected), vec!["guest", "guest"]);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(server.session_count().await, 4);
+    assert!(server
+        .audit_entries()
+        .await
+        .iter()
+        .all(|entry| entry.action() != "kick"));
+}
+
+#[tokio::test]
+async fn matching_sessions_are_kicked_and_cleaned_up() {
+    let (server, mut admin) = admin_server().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut office = guest(&server, OFFICE).await;
+    let mut elsewhere = guest(&server, ELSEWHERE).await;
+
+    let (kicked_sessions, kicked) =
+        request(&mut admin, Message::admin_kick_where(&guests_only(), "cleanup", false)).await;
+    assert!(kicked);
+    assert_eq!(kicked_sessions.len(), 2);
+
+    for connection in [&mut office, &mut elsewhere] {
+        let notice = connection.receive().await;
+        assert!(notice.is(MessageType::SecurityNotice), "Unexpected {:?}", notice);
+        assert_eq!(notice.payload().str_field(0), Ok(NOTICE_KICKED));
+        let disconnect = loop {
+            let message = connection.receive().await;
+            if !message.is(MessageType::Heartbeat) {
+                break message;
+            }
+        };
+        assert_eq!(
+            disconnect.disconnect_reason(),
+            Ok((DisconnectReason::Kicked, "cleanup".to_string()))
+        );
+        connection.send(Message::ACK).await;
+    }
+
+    eventually(|| async { server.session_count().await == 2 }).await;
+    assert!(server.is_logged_in("alice").await);
+    assert!(server.is_logged_in("admin").await);
+    assert!(list(&mut admin, guests_only()).await.is_empty());
+    assert!(server
+        .audit_entries()
+        .await
+        .iter()
+        .any(|entry| entry.actor() == "admin" && entry.action() == "kick" && entry.detail() == "2 sessions (cleanup)"));
+}
+
+#[tokio::test]
+async fn a_moderator_only_kicks_lower_ranks_and_never_itself() {
+    let (server, _admin) = admin_server().await;
+    server.create_user("mod", "secret", AccessLevel::Moderator).await;
+    server.create_user("other-mod", "secret", AccessLevel::Moderator).await;
+    let mut moderator = server.client().await;
+    moderator.login("mod", "secret").await;
+    let mut other = server.client().await;
+    other.login("other-mod", "secret").await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    let (affected, _) = request(
+        &mut moderator,
+        Message::admin_kick_where(&SessionFilter::default(), "everyone out", true),
+    )
+    .await;
+    assert_eq!(usernames(&affected), vec!["alice"]);
+}
+
+#[tokio::test]
+async fn users_cannot_list_sessions() {
+    let (server, _admin) = admin_server().await;
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+
+    alice
+        .client()
+        .send(Message::admin_list_sessions(&SessionFilter::default()))
+        .await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Rejected { .. }))
+        .await;
+}
@@ -0,0 +1,144 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use chat_client::client::{ChatClient, ClientEvent, ClientOptions};
+use chat_core::protocol::{Message, MessageType, NOTICE_SESSION_TAKEOVER};
+use chat_server::application::testing::{eventually, within, TakeoverPolicy, TestServer};
+
+const REPLACE: TakeoverPolicy = TakeoverPolicy::Replace { same_peer: false };
+const REPLACE_SAME_PEER: TakeoverPolicy = TakeoverPolicy::Replace { same_peer: true };
+
+#[tokio::test]
+async fn second_login_is_rejected_by_default() {
+    let server = TestServer::start();
+
+    let mut first = server.raw_connection().await;
+    first.send(Message::auth_create("alice", "secret")).await;
+    assert!(first.receive().await.is(MessageType::AuthSuccess));
+
+    let mut second = server.raw_connection().await;
+    second.send(Message::auth("alice", "secret")).await;
+    let reply = second.receive().await;
+    assert!(reply.is(MessageType::AuthFailure));
+    assert_eq!(reply.payload().str_field(0), Ok("User already logged in"));
+
+    // the original session keeps working
+    first.send(Message::direct_message_send("nobody", "hello")).await;
+    assert!(first.receive().await.is(MessageType::MessageError));
+}
+
+#[tokio::test]
+async fn replace_policy_closes_the_old_session() {
+    let server = TestServer::builder().with_takeover_policy(REPLACE).start();
+
+    let mut old = server.client().await;
+    old.register("alice", "secret").await;
+
+    let mut new = server.client().await;
+    new.login("alice", "secret").await;
+
+    let event = old
+        .expect(|event| matches!(event, ClientEvent::SecurityNotice { .. }))
+        .await;
+    let ClientEvent::SecurityNotice { kind, detail } = event else {
+        unreachable!();
+    };
+    assert_eq!(kind, NOTICE_SESSION_TAKEOVER);
+    assert!(detail.contains("127.0.0.1"));
+
+    // the old client must not reconnect and take the session back
+    old.expect(|event| matches!(event, ClientEvent::Closed)).await;
+    eventually(|| async { server.session_count().await == 1 }).await;
+    assert!(server.is_logged_in("alice").await);
+    assert!(new.client().is_authenticated().await);
+
+    let entries = server.audit_entries().await;
+    assert!(entries
+        .iter()
+        .any(|entry| entry.actor() == "alice" && entry.action() == "session_takeover"));
+}
+
+#[tokio::test]
+async fn replace_policy_still_checks_the_password() {
+    let server = TestServer::builder().with_takeover_policy(REPLACE).start();
+
+    let mut old = server.raw_connection().await;
+    old.send(Message::auth_create("alice", "secret")).await;
+    assert!(old.receive().await.is(MessageType::AuthSuccess));
+
+    let mut new = server.raw_connection().await;
+    new.send(Message::auth("alice", "wrong")).await;
+    assert!(new.receive().await.is(MessageType::AuthFailure));
+
+    old.send(Message::direct_message_send("nobody", "hello")).await;
+    assert!(old.receive().await.is(MessageType::MessageError));
+    assert!(server.audit_entries().await.is_empty());
+}
+
+#[tokio::test]
+async fn same_peer_policy_rejects_other_addresses() {
+    let server = TestServer::builder().with_takeover_policy(REPLACE_SAME_PEER).start();
+
+    let mut old = server.raw_connection().await;
+    old.send(Message::auth_create("alice", "secret")).await;
+    assert!(old.receive().await.is(MessageType::AuthSuccess));
+
+    let connector = server.connector().with_address(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)));
+    let (client, mut events) = ChatClient::connect_with(connector, ClientOptions::new()).await.unwrap();
+    client.login("alice", "secret").await;
+    let event = within(async {
+        loop {
+            match events.recv().await {
+                Some(event @ (ClientEvent::Authenticated | ClientEvent::AuthFailed(_))) => break event,
+                Some(_) => continue,
+                None => panic!("Client event stream closed"),
+            }
+        }
+    })
+    .await;
+    assert_eq!(
+        event,
+        ClientEvent::AuthFailed("User already logged in from another address".to_string())
+    );
+
+    // a login from the same address is allowed to take over
+    let mut new = server.raw_connection().await;
+    new.send(Message::auth("alice", "secret")).await;
+    assert!(new.receive().await.is(MessageType::AuthSuccess));
+
+    let notice = old.receive().await;
+    assert!(notice.is(MessageType::SecurityNotice));
+    assert_eq!(notice.payload().str_field(0), Ok(NOTICE_SESSION_TAKEOVER));
+    assert!(old.receive().await.is(MessageType::Disconnect));
+}
+
+#[tokio::test]
+async fn takeover_survives_the_old_session_disconnecting_concurrently() {
+    let server = TestServer::builder().with_takeover_policy(REPLACE).start();
+
+    let mut setup = server.raw_connection().await;
+    setup.send(Message::auth_create("alice", "secret")).await;
+    assert!(setup.receive().await.is(MessageType::AuthSuccess));
+    setup.send(Message::DISCONNECT).await;
+    eventually(|| async { server.session_count().await == 0 }).await;
+
+    for _ in 0..10 {
+        let mut old = server.raw_connection().await;
+        old.send(Message::auth("alice", "secret")).await;
+        assert!(old.receive().await.is(MessageType::AuthSuccess));
+
+        let mut new = server.raw_connection().await;
+        let (_, disconnected) = tokio::join!(
+            new.send(Message::auth("alice", "secret")),
+            old.try_send(Message::DISCONNECT)
+        );
+        assert_eq!(disconnected, Ok(()));
+        assert!(new.receive().await.is(MessageType::AuthSuccess));
+
+        // the old session's cleanup must not log out the new one
+        eventually(|| async { server.session_count().await == 1 }).await;
+        assert!(server.is_logged_in("alice").await);
+
+        new.send(Message::DISCONNECT).await;
+        eventually(|| async { server.session_count().await == 0 }).await;
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs, io};
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::{testing::TestServer, ShutdownReason, EXIT_CLEAN, EXIT_FATAL, LAST_SHUTDOWN_FILE};
+
+#[tokio::test]
+async fn an_admin_shutdown_is_a_clean_exit() {
+    let server = TestServer::start();
+    let admin = server.admin().await;
+
+    admin.client().send(Message::server_shutdown(0)).await;
+
+    let summary = server.summary().await;
+    assert_eq!(summary.reason, ShutdownReason::AdminRequested);
+    assert_eq!(summary.exit_code(), EXIT_CLEAN);
+}
+
+#[tokio::test]
+async fn a_signal_disconnects_everyone_and_is_a_clean_exit() {
+    let server = TestServer::start();
+    let mut alice = server.raw_connection().await;
+
+    server.signal().await;
+
+    assert!(alice.receive().await.is(MessageType::Disconnect));
+    let summary = server.summary().await;
+    assert_eq!(summary.reason, ShutdownReason::Signal);
+    assert_eq!(summary.exit_code(), EXIT_CLEAN);
+}
+
+#[tokio::test]
+async fn a_finished_drain_is_a_clean_exit() {
+    let server = TestServer::start();
+    let admin = server.admin().await;
+
+    admin
+        .client()
+        .send(Message::admin_set_server_mode("draining", Some(60)))
+        .await;
+
+    let summary = server.summary().await;
+    assert_eq!(summary.reason, ShutdownReason::Drained);
+    assert_eq!(summary.exit_code(), EXIT_CLEAN);
+}
+
+#[tokio::test]
+async fn a_broken_listener_is_a_fatal_exit() {
+    let server = TestServer::start();
+
+    server.fail_accepts([io::Error::from_raw_os_error(libc::EBADF)]);
+
+    let summary = server.summary().await;
+    assert!(
+        matches!(&summary.reason, ShutdownReason::FatalError(error) if error.contains("Bad file descriptor")),
+        "Expected a fatal error, got {:?}",
+        summary.reason
+    );
+    assert_eq!(summary.exit_code(), EXIT_FATAL);
+}
+
+#[tokio::test]
+async fn the_summary_counts_sessions_and_relayed_messages() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    alice.client().send_direct_message("bob", "hi").await;
+    bob.expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+        .await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+    bob.disconnect().await;
+
+    server.signal().await;
+
+    let summary = server.summary().await;
+    assert_eq!(summary.peak_sessions, 2);
+    assert_eq!(summary.messages_relayed, 1);
+}
+
+#[tokio::test]
+async fn the_summary_is_kept_in_the_data_directory() {
+    let dir = TestServer::scratch_dir("report");
+    let server = TestServer::builder().with_data_dir(dir.clone()).start();
+    let _alice = server.raw_connection().await;
+
+    server.signal().await;
+    server.summary().await;
+
+    let report = JsonValue::parse(&fs::read_to_string(dir.join(LAST_SHUTDOWN_FILE)).unwrap()).unwrap();
+    assert_eq!(report.get("reason").and_then(JsonValue::as_str), Some("signal"));
+    assert_eq!(report.get("exit_code").and_then(JsonValue::as_u64), Some(0));
+    assert_eq!(report.get("peak_sessions").and_then(JsonValue::as_u64), Some(1));
+    assert_eq!(report.get("messages_relayed").and_then(JsonValue::as_u64), Some(0));
+    assert!(report.get("uptime_secs").and_then(JsonValue::as_u64).is_some());
+    assert_eq!(report.get("error"), Some(&JsonValue::Null));
+
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,127 @@
+use std::{fs, time::Duration};
+
+use chat_client::client::ClientEvent;
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::{
+    testing::{AccessLevel, TestServer},
+    ImportMode,
+};
+
+// rewrites every user of an export, None leaves the user out
+fn edit_users(document: &str, edit: impl Fn(Vec<(String, JsonValue)>) -> Option<Vec<(String, JsonValue)>>) -> String {
+    let document = JsonValue::parse(document).unwrap();
+    let users: Vec<JsonValue> = document
+        .get("users")
+        .and_then(JsonValue::as_array)
+        .unwrap()
+        .iter()
+        .filter_map(|user| match user {
+            JsonValue::Object(fields) => edit(fields.clone()).map(JsonValue::Object),
+            _ => panic!("Unexpected {:?}", user),
+        })
+        .collect();
+    JsonValue::object()
+        .with("version", document.get("version").cloned().unwrap())
+        .with("users", users)
+        .to_string()
+}
+
+fn name_of(fields: &[(String, JsonValue)]) -> &str {
+    fields
+        .iter()
+        .find(|(key, _)| key == "name")
+        .and_then(|(_, value)| value.as_str())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn session_state_in_a_users_file_is_dropped_on_load() {
+    let source = TestServer::start();
+    source.create_user("alice", "secret", AccessLevel::User).await;
+    // what a build that wrote out live session state left behind
+    let document = edit_users(&source.export_users().await, |mut fields| {
+        fields.push(("session_id".into(), JsonValue::from(uuid::Uuid::new_v4().to_string())));
+        fields.push(("online".into(), JsonValue::Bool(true)));
+        Some(fields)
+    });
+    let users_file = TestServer::scratch_dir("transient").join("users.json");
+    fs::write(&users_file, document).unwrap();
+
+    let server = TestServer::builder().with_users_file(users_file).start();
+    assert_eq!(server.load_cleanup().transient_fields, 4);
+    assert!(!server.export_users().await.contains("session_id"));
+
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    let reply = alice.receive().await;
+    assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+}
+
+#[tokio::test]
+async fn a_clean_users_file_needs_no_cleanup() {
+    let source = TestServer::start();
+    source.create_user("alice", "secret", AccessLevel::User).await;
+    let users_file = TestServer::scratch_dir("clean").join("users.json");
+    fs::write(&users_file, source.export_users().await).unwrap();
+
+    let server = TestServer::builder().with_users_file(users_file).start();
+    assert!(server.load_cleanup().is_empty(), "{}", server.load_cleanup());
+}
+
+#[tokio::test]
+async fn a_reference_to_a_lost_session_no_longer_blocks_the_login() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+
+    server.lose_session("alice").await;
+    let mut again = server.raw_connection().await;
+    again.send(Message::auth("alice", "secret")).await;
+    assert!(again.receive().await.is(MessageType::AuthFailure));
+
+    // merging nothing keeps every user as it is, only the dangling reference goes
+    let cleanup = server
+        .reload_users(r#"{"version":2,"users":[]}"#, ImportMode::Merge)
+        .await
+        .unwrap();
+    assert_eq!(cleanup.session_references, 1, "{}", cleanup);
+
+    let mut again = server.raw_connection().await;
+    again.send(Message::auth("alice", "secret")).await;
+    let reply = again.receive().await;
+    assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+}
+
+#[tokio::test]
+async fn queued_state_of_users_missing_from_the_file_is_dropped() {
+    let server = TestServer::start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut bob = server.client().await;
+    bob.register("bob", "secret").await;
+
+    bob.client().send_direct_message("alice", "are you there").await;
+    bob.expect(|event| matches!(event, ClientEvent::Delivered { recipient, .. } if recipient == "alice"))
+        .await;
+    assert_eq!(server.queued_messages().await, 1);
+
+    let without_alice = edit_users(&server.export_users().await, |fields| {
+        (name_of(&fields) != "alice").then_some(fields)
+    });
+    let cleanup = server.reload_users(&without_alice, ImportMode::Replace).await.unwrap();
+    assert_eq!(cleanup.queued_messages, 1, "{}", cleanup);
+    assert_eq!(cleanup.unread_counters, 1, "{}", cleanup);
+    assert_eq!(server.queued_messages().await, 0);
+
+    // a new alice starts with an empty queue
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let mut alice = server.raw_connection().await;
+    alice.send(Message::auth("alice", "secret")).await;
+    assert!(alice.receive().await.is(MessageType::AuthSuccess));
+    let nothing = tokio::time::timeout(Duration::from_millis(200), alice.receive()).await;
+    assert!(nothing.is_err(), "Unexpected {:?}", nothing);
+}
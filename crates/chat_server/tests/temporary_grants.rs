@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestClient, TestServer};
+
+// the answer to a grant or revocation, the user's info or the reason it was refused
+async fn answer(admin: &mut TestClient, request: Message) -> ClientEvent {
+    admin.client().send(request).await;
+    admin
+        .expect(|event| matches!(event, ClientEvent::UserInfo { .. } | ClientEvent::Rejected { .. }))
+        .await
+}
+
+async fn sets_motd(connection: &mut RawConnection) -> bool {
+    connection.send(Message::admin_set_motd("standup at ten", false)).await;
+    let reply = connection.receive().await;
+    if reply.is(MessageType::Nack) {
+        assert_eq!(reply.rejected_type(), Some(MessageType::AdminSetMotd));
+        return false;
+    }
+    assert!(reply.is(MessageType::Motd), "Unexpected {:?}", reply);
+    true
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_grant_lapses_after_its_duration() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.logged_in("alice").await;
+    assert!(!sets_motd(&mut alice).await);
+
+    let ClientEvent::UserInfo { temporary_grants, .. } =
+        answer(&mut admin, Message::admin_grant_temporary("alice", "set_motd", 60)).await
+    else {
+        panic!("The grant was refused");
+    };
+    assert_eq!(temporary_grants.len(), 1);
+    assert_eq!(temporary_grants[0].0, "set_motd");
+    assert!(sets_motd(&mut alice).await);
+
+    server.clock().advance(Duration::from_secs(60)).await;
+    assert!(!sets_motd(&mut alice).await);
+
+    // the reaper only writes the expiry down, the grant stopped counting the moment it lapsed
+    eventually(|| async {
+        server
+            .audit_entries()
+            .await
+            .iter()
+            .any(|entry| entry.action() == "temporary_grant_expired")
+    })
+    .await;
+    let actions: Vec<(String, String, String)> = server
+        .audit_entries()
+        .await
+        .iter()
+        .filter(|entry| entry.action().contains("temporary"))
+        .map(|entry| {
+            (
+                entry.actor().to_string(),
+                entry.action().to_string(),
+                entry.detail().to_string(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (
+                "admin".to_string(),
+                "grant_temporary".to_string(),
+                "alice: set_motd for 60 seconds".to_string()
+            ),
+            (
+                "alice".to_string(),
+                "temporary_grant_used".to_string(),
+                "AdminSetMotd".to_string()
+            ),
+            (
+                "server".to_string(),
+                "temporary_grant_expired".to_string(),
+                "alice: set_motd, granted by admin".to_string()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_revoked_grant_stops_working_at_once() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    let mut alice = server.logged_in("alice").await;
+
+    answer(&mut admin, Message::admin_grant_temporary("alice", "set_motd", 3600)).await;
+    assert!(sets_motd(&mut alice).await);
+
+    let ClientEvent::UserInfo { temporary_grants, .. } =
+        answer(&mut admin, Message::admin_revoke_temporary("alice", "set_motd")).await
+    else {
+        panic!("The revocation was refused");
+    };
+    assert!(temporary_grants.is_empty());
+    assert!(!sets_motd(&mut alice).await);
+
+    let ClientEvent::Rejected { reason, .. } =
+        answer(&mut admin, Message::admin_revoke_temporary("alice", "set_motd")).await
+    else {
+        panic!("Revoked twice");
+    };
+    assert_eq!(reason, "alice has no temporary grant of set_motd");
+}
+
+#[tokio::test]
+async fn invalid_grants_are_refused() {
+    let server = TestServer::start();
+    let mut admin = server.admin().await;
+    server.logged_in("alice").await;
+
+    for (request, expected) in [
+        (
+            Message::admin_grant_temporary("alice", "everything", 60),
+            "Unknown permission 'everything'",
+        ),
+        (
+            Message::admin_grant_temporary("alice", "set_motd", 0),
+            "A grant lasts between 1 second and 24 hours",
+        ),
+        (
+            Message::admin_grant_temporary("alice", "set_motd", 25 * 3600),
+            "A grant lasts between 1 second and 24 hours",
+        ),
+        (
+            Message::admin_grant_temporary("nobody", "set_motd", 60),
+            "Unknown user 'nobody'",
+        ),
+    ] {
+        let ClientEvent::Rejected { reason, .. } = answer(&mut admin, request).await else {
+            panic!("Expected '{}'", expected);
+        };
+        assert_eq!(reason, expected);
+    }
+}
+
+#[tokio::test]
+async fn only_admins_may_grant() {
+    let server = TestServer::start();
+    let mut alice = server.logged_in("alice").await;
+    server.logged_in("bob").await;
+
+    alice.send(Message::admin_grant_temporary("bob", "set_motd", 60)).await;
+    let nack = alice.receive().await;
+    assert_eq!(nack.rejected_type(), Some(MessageType::AdminGrantTemporary));
+}
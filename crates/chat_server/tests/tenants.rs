@@ -0,0 +1,143 @@
+use std::{fs, net::TcpListener, path::PathBuf};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::Message;
+use chat_server::application::{
+    testing::{application_with_log_control, eventually, TestClient, TestServer},
+    Application, ServerConfig,
+};
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+async fn listening(port: u16) -> bool {
+    tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok()
+}
+
+#[tokio::test]
+async fn tenants_share_the_process_and_nothing_else() {
+    let dir = TestServer::scratch_dir("isolated");
+    let (port_a, port_b) = (free_port(), free_port());
+    let config = ServerConfig::from_vars([
+        ("DATA_DIR", dir.display().to_string()),
+        ("TENANTS", "a,b".to_string()),
+        ("TENANT_A_PORT", port_a.to_string()),
+        ("TENANT_B_PORT", port_b.to_string()),
+    ]);
+    config.validate().unwrap();
+    let app = Application::from_config(&config).unwrap();
+    assert!(dir.join("a").join("server.lock").exists());
+    assert!(dir.join("b").join("server.lock").exists());
+
+    let test = async {
+        eventually(|| async { listening(port_a).await && listening(port_b).await }).await;
+
+        let mut alice_a = TestClient::connect_tcp(port_a).await;
+        alice_a.register("alice", "secret-a").await;
+        let mut alice_b = TestClient::connect_tcp(port_b).await;
+        alice_b.register("alice", "secret-b").await;
+
+        // the same name is another account in each tenant
+        let mut intruder = TestClient::connect_tcp(port_b).await;
+        intruder.client().login("alice", "secret-a").await;
+        intruder
+            .expect(|event| matches!(event, ClientEvent::AuthFailed(_)))
+            .await;
+
+        let mut bob = TestClient::connect_tcp(port_a).await;
+        bob.register("bob", "secret").await;
+        bob.client().send_direct_message("alice", "only in a").await;
+        let event = alice_a
+            .expect(|event| matches!(event, ClientEvent::DirectMessage { .. }))
+            .await;
+        assert!(matches!(event, ClientEvent::DirectMessage { body, .. } if body == "only in a"));
+
+        // bob has no account next door, a message to him would wait for nobody
+        let mut bob_b = TestClient::connect_tcp(port_b).await;
+        bob_b.client().login("bob", "secret").await;
+        bob_b.expect(|event| matches!(event, ClientEvent::AuthFailed(_))).await;
+        alice_b.client().send_direct_message("bob", "anyone?").await;
+        let event = alice_b
+            .expect(|event| matches!(event, ClientEvent::DeliveryFailed { .. }))
+            .await;
+        assert!(matches!(event, ClientEvent::DeliveryFailed { recipient, .. } if recipient.as_deref() == Some("bob")));
+    };
+
+    tokio::select! {
+        result = app.run() => panic!("The application stopped: {:?}", result.map(|summary| summary.reason)),
+        _ = test => {}
+    }
+}
+
+// a users file with an admin in it, for servers that are started from a config
+async fn users_with_admin(path: &PathBuf) {
+    let server = TestServer::start();
+    server.create_admin("admin", "secret").await;
+    fs::write(path, server.export_users().await).unwrap();
+}
+
+// what the admin of the server on this port is told after asking for another log filter
+async fn set_log_level(port: u16) -> ClientEvent {
+    let mut admin = TestClient::connect_tcp(port).await;
+    admin.login("admin", "secret").await;
+    admin.client().send(Message::admin_set_log_level("debug", None)).await;
+    admin
+        .expect(|event| {
+            matches!(
+                event,
+                ClientEvent::LogLevelChanged { .. } | ClientEvent::Rejected { .. }
+            )
+        })
+        .await
+}
+
+#[tokio::test]
+async fn only_a_single_instance_lets_its_admins_change_the_log_filter() {
+    let dir = TestServer::scratch_dir("log_control");
+    fs::create_dir_all(&dir).unwrap();
+    let port = free_port();
+    users_with_admin(&dir.join("users.json")).await;
+    let config = ServerConfig::from_vars([
+        ("DATA_DIR", dir.join("single").display().to_string()),
+        ("PORT", port.to_string()),
+        ("USERS_FILE", dir.join("users.json").display().to_string()),
+    ]);
+    config.validate().unwrap();
+    let (app, _filter) = application_with_log_control(&config).unwrap();
+    let test = async {
+        eventually(|| listening(port)).await;
+        set_log_level(port).await
+    };
+    let event = tokio::select! {
+        result = app.run() => panic!("The application stopped: {:?}", result.map(|summary| summary.reason)),
+        event = test => event,
+    };
+    assert!(matches!(event, ClientEvent::LogLevelChanged { .. }), "{:?}", event);
+    drop(app);
+
+    // the filter is the process's, none of the tenants may change it for the others
+    let (port_a, port_b) = (free_port(), free_port());
+    users_with_admin(&dir.join("users_a.json")).await;
+    users_with_admin(&dir.join("users_b.json")).await;
+    let config = ServerConfig::from_vars([
+        ("DATA_DIR", dir.join("tenants").display().to_string()),
+        ("TENANTS", "a,b".to_string()),
+        ("TENANT_A_PORT", port_a.to_string()),
+        ("TENANT_B_PORT", port_b.to_string()),
+        ("TENANT_A_USERS_FILE", dir.join("users_a.json").display().to_string()),
+        ("TENANT_B_USERS_FILE", dir.join("users_b.json").display().to_string()),
+    ]);
+    config.validate().unwrap();
+    let (app, _filter) = application_with_log_control(&config).unwrap();
+    let test = async {
+        eventually(|| async { listening(port_a).await && listening(port_b).await }).await;
+        (set_log_level(port_a).await, set_log_level(port_b).await)
+    };
+    let events = tokio::select! {
+        result = app.run() => panic!("The application stopped: {:?}", result.map(|summary| summary.reason)),
+        events = test => events,
+    };
+    assert!(matches!(events.0, ClientEvent::Rejected { .. }), "{:?}", events.0);
+    assert!(matches!(events.1, ClientEvent::Rejected { .. }), "{:?}", events.1);
+}
@@ -0,0 +1,78 @@
+use std::{sync::Arc, time::Duration};
+
+use chat_client::client::{ClientEvent, ClientOptions};
+use chat_core::time_sync::Clock;
+use chat_server::application::testing::TestServer;
+use chrono::{DateTime, Utc};
+
+const SKEW: chrono::Duration = chrono::Duration::minutes(10);
+
+// a client whose clock lags ten minutes behind the server
+#[derive(Debug)]
+struct LaggingClock;
+
+impl Clock for LaggingClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() - SKEW
+    }
+}
+
+fn assert_close(actual: chrono::Duration, expected: chrono::Duration) {
+    let difference = (actual - expected).abs();
+    assert!(
+        difference < chrono::Duration::seconds(1),
+        "{:?} is not close to {:?}",
+        actual,
+        expected
+    );
+}
+
+#[tokio::test]
+async fn client_syncs_time_after_connect() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::TimeSynced { .. }))
+        .await;
+    let ClientEvent::TimeSynced {
+        offset_ms,
+        round_trip_ms,
+    } = event
+    else {
+        unreachable!();
+    };
+    assert!(offset_ms.abs() < 1000);
+    assert!(round_trip_ms >= 0);
+}
+
+#[tokio::test]
+async fn skewed_client_clock_is_corrected() {
+    let server = TestServer::start();
+    let options = ClientOptions::new().with_clock(Arc::new(LaggingClock));
+    let mut alice = server.client_with(options).await;
+
+    alice
+        .expect(|event| matches!(event, ClientEvent::TimeSynced { .. }))
+        .await;
+
+    let sample = alice.client().time_sample().await.expect("time was synced");
+    assert_close(sample.offset(), SKEW);
+    assert_close(
+        alice.client().server_time_now().await - Utc::now(),
+        chrono::Duration::zero(),
+    );
+}
+
+#[tokio::test]
+async fn time_is_resynced_periodically() {
+    let server = TestServer::start();
+    let options = ClientOptions::new().with_time_sync_interval(Duration::from_millis(50));
+    let mut alice = server.client_with(options).await;
+
+    for _ in 0..3 {
+        alice
+            .expect(|event| matches!(event, ClientEvent::TimeSynced { .. }))
+            .await;
+    }
+}
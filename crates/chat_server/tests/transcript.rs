@@ -0,0 +1,85 @@
+use std::{fs, path::PathBuf};
+
+use chat_client::client::{json::JsonValue, ClientEvent, TranscriptExport, TRANSCRIPT_PAGE};
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{FloodLimits, TestServer};
+
+fn scratch_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chat_rs_transcript_{}_{}", std::process::id(), name));
+    fs::remove_file(&path).ok();
+    path
+}
+
+#[tokio::test]
+async fn a_transcript_pages_through_the_server_history_and_takes_in_the_scrollback() {
+    let server = TestServer::builder()
+        .with_flood_limits(FloodLimits {
+            frames_per_second: 10_000,
+            ..FloodLimits::default()
+        })
+        .start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.logged_in("bob").await;
+
+    // more than one page, so the oldest ones only come with the second request
+    let count = TRANSCRIPT_PAGE + 10;
+    let mut ids = Vec::new();
+    for index in 0..count {
+        ids.push(bob.send_direct("alice", &format!("message {}", index)).await);
+    }
+    let last = ids.last().unwrap().get();
+    alice
+        .expect(|event| matches!(event, ClientEvent::DirectMessage { id: Some(id), .. } if *id == last))
+        .await;
+    bob.send(Message::message_edit(ids[1], "edited")).await;
+    assert!(bob.receive().await.is(MessageType::MessageEdited));
+    bob.send(Message::message_delete(ids[2])).await;
+    assert!(bob.receive().await.is(MessageType::MessageDeleted));
+    // the scrollback has ours without an id, the server has it with one
+    alice.client().send_direct_message("bob", "bye").await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::Delivered { .. }))
+        .await;
+
+    let path = scratch_file("bob.json");
+    let export = TranscriptExport::parse(&format!("bob {}", path.display())).unwrap();
+    alice.client().export_transcript(export.clone()).await.unwrap();
+    // one at a time
+    assert!(alice.client().export_transcript(export).await.is_err());
+    let event = alice
+        .expect(|event| matches!(event, ClientEvent::TranscriptExported { .. }))
+        .await;
+    assert!(matches!(event, ClientEvent::TranscriptExported { messages, .. } if messages == count + 1));
+
+    let transcript = JsonValue::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+    let messages = transcript.get("messages").and_then(JsonValue::as_array).unwrap();
+    let field = |index: usize, key: &str| messages[index].get(key).cloned().unwrap();
+    let written: Vec<u64> = messages
+        .iter()
+        .filter_map(|message| message.get("id")?.as_u64())
+        .collect();
+    let mut expected: Vec<u64> = ids.iter().map(|id| id.get()).collect();
+    expected.push(written[written.len() - 1]);
+    assert_eq!(written, expected);
+    assert_eq!(field(1, "body"), JsonValue::from("edited"));
+    assert_eq!(field(1, "edited"), JsonValue::from(true));
+    assert_eq!(field(2, "deleted"), JsonValue::from(true));
+    assert_eq!(field(count as usize, "body"), JsonValue::from("bye"));
+    fs::remove_file(&path).ok();
+}
+
+#[tokio::test]
+async fn history_requests_of_our_own_still_show_while_no_transcript_is_collected() {
+    let server = TestServer::start();
+    let mut alice = server.client().await;
+    alice.register("alice", "secret").await;
+    let mut bob = server.logged_in("bob").await;
+    bob.send_direct("alice", "hi").await;
+
+    alice.client().request_history("bob", 10, None).await;
+    alice
+        .expect(|event| matches!(event, ClientEvent::HistoryEntry { body, .. } if body == "hi"))
+        .await;
+    alice.expect(|event| matches!(event, ClientEvent::HistoryEnd(1))).await;
+}
@@ -0,0 +1,97 @@
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+
+async fn leave(server: &TestServer, mut connection: RawConnection, username: &str) {
+    connection.send(Message::DISCONNECT).await;
+    eventually(|| async { !server.is_logged_in(username).await }).await;
+}
+
+async fn summary(connection: &mut RawConnection) -> Vec<(String, u64)> {
+    let message = connection.receive().await;
+    assert!(message.is(MessageType::UnreadSummary), "Unexpected {:?}", message);
+    message.unread_counts().unwrap()
+}
+
+fn counts(pairs: &[(&str, u64)]) -> Vec<(String, u64)> {
+    pairs.iter().map(|(peer, count)| (peer.to_string(), *count)).collect()
+}
+
+#[tokio::test]
+async fn summary_arrives_before_the_queued_messages() {
+    let server = TestServer::start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    let mut carol = server.logged_in("carol").await;
+    for body in ["one", "two", "three"] {
+        alice.send_direct("bob", body).await;
+    }
+    carol.send_direct("bob", "hi").await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+
+    assert_eq!(summary(&mut bob).await, counts(&[("alice", 3), ("carol", 1)]));
+    for _ in 0..4 {
+        assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    }
+}
+
+#[tokio::test]
+async fn marking_a_conversation_read_clears_only_that_peer() {
+    let server = TestServer::start();
+    let bob = server.logged_in("bob").await;
+    leave(&server, bob, "bob").await;
+
+    let mut alice = server.logged_in("alice").await;
+    let mut carol = server.logged_in("carol").await;
+    alice.send_direct("bob", "hello").await;
+    carol.send_direct("bob", "hey").await;
+
+    let mut bob = server.client().await;
+    bob.login("bob", "secret").await;
+    let event = bob.expect(|event| matches!(event, ClientEvent::UnreadSummary(_))).await;
+    assert_eq!(event, ClientEvent::UnreadSummary(counts(&[("alice", 1), ("carol", 1)])));
+
+    assert!(bob.client().mark_read("alice").await);
+    let event = bob.expect(|event| matches!(event, ClientEvent::UnreadSummary(_))).await;
+    assert_eq!(event, ClientEvent::UnreadSummary(counts(&[("carol", 1)])));
+    assert_eq!(bob.client().unread_counts().await.get("carol"), Some(&1));
+    assert!(!bob.client().unread_counts().await.contains_key("alice"));
+}
+
+#[tokio::test]
+async fn only_queued_messages_are_counted() {
+    let server = TestServer::start();
+    let mut bob = server.logged_in("bob").await;
+    let mut alice = server.logged_in("alice").await;
+
+    // delivered straight away, nothing to catch up on
+    alice.send_direct("bob", "online").await;
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+    bob.send(Message::mark_conversation_read("nobody")).await;
+    assert_eq!(summary(&mut bob).await, Vec::new());
+
+    leave(&server, bob, "bob").await;
+    alice.send_direct("bob", "offline").await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    assert_eq!(summary(&mut bob).await, counts(&[("alice", 1)]));
+    assert!(bob.receive().await.is(MessageType::DirectMessageReceive));
+
+    // the count stays until it is cleared, even though the message was delivered
+    bob.send(Message::mark_conversation_read("alice")).await;
+    assert_eq!(summary(&mut bob).await, Vec::new());
+    leave(&server, bob, "bob").await;
+
+    let mut bob = server.raw_connection().await;
+    bob.send(Message::auth("bob", "secret")).await;
+    assert!(bob.receive().await.is(MessageType::AuthSuccess));
+    bob.send(Message::history_request("alice", 0)).await;
+    assert!(bob.receive().await.is(MessageType::HistoryEnd));
+}
@@ -0,0 +1,205 @@
+use std::{fs, path::PathBuf};
+
+use chat_core::{
+    json::JsonValue,
+    protocol::{Message, MessageType},
+};
+use chat_server::application::{
+    export_users_file, import_users_file,
+    testing::{AccessLevel, RawConnection, TestServer},
+    ImportMode,
+};
+
+async fn login_with_id(server: &TestServer, username: &str) -> (RawConnection, String) {
+    let mut connection = server.raw_connection().await;
+    connection.send(Message::auth(username, "secret")).await;
+    let reply = connection.receive().await;
+    assert!(reply.is(MessageType::AuthSuccess), "Unexpected {:?}", reply);
+    let id = reply.payload().str_field(0).unwrap().to_string();
+    (connection, id)
+}
+
+// what an export looked like before users had ids
+fn as_version_one(document: &str) -> String {
+    let document = JsonValue::parse(document).unwrap();
+    let users: Vec<JsonValue> = document
+        .get("users")
+        .and_then(JsonValue::as_array)
+        .unwrap()
+        .iter()
+        .map(|user| match user {
+            JsonValue::Object(fields) => {
+                JsonValue::Object(fields.iter().filter(|(key, _)| key != "id").cloned().collect())
+            }
+            _ => panic!("Unexpected {:?}", user),
+        })
+        .collect();
+    JsonValue::object()
+        .with("version", 1u64)
+        .with("users", users)
+        .to_string()
+}
+
+// the path of the export the server wrote for an admin
+async fn export(server: &TestServer) -> PathBuf {
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.login("admin").await;
+    admin.send(Message::admin_export_state()).await;
+    let exported = admin.receive().await;
+    assert!(exported.is(MessageType::StateExported), "Unexpected {:?}", exported);
+    PathBuf::from(exported.payload().str_field(0).unwrap())
+}
+
+fn empty_users_file(dir: &std::path::Path) -> PathBuf {
+    let users_file = dir.join("users.json");
+    fs::write(&users_file, "{\"version\": 1, \"users\": []}").unwrap();
+    users_file
+}
+
+#[tokio::test]
+async fn export_round_trips_into_a_fresh_server() {
+    let dir = TestServer::scratch_dir("round_trip");
+    let server = TestServer::builder().with_data_dir(dir.join("data")).start();
+    server.create_user("alice", "secret", AccessLevel::Moderator).await;
+    let (mut alice, id) = login_with_id(&server, "alice").await;
+    alice.send(Message::set_preference("dnd", "on")).await;
+    assert!(alice.receive().await.is(MessageType::Preferences));
+
+    let exported = export(&server).await;
+    assert!(exported.starts_with(dir.join("data")));
+    let audit = server.audit_entries().await;
+    assert!(audit.iter().any(|entry| entry.action() == "export_state"));
+
+    let users_file = empty_users_file(&dir);
+    // the built-in admin, the test admin and alice
+    assert_eq!(import_users_file(&users_file, &exported, ImportMode::Merge).unwrap(), 3);
+
+    let restored = TestServer::builder().with_users_file(users_file).start();
+    let (mut alice, restored_id) = login_with_id(&restored, "alice").await;
+    assert_eq!(restored_id, id);
+    alice.send(Message::get_preferences()).await;
+    let preferences = alice.receive().await;
+    assert_eq!(
+        preferences.preference_entries(),
+        Ok(vec![("dnd".to_string(), "on".to_string())])
+    );
+    // the access level came along, moderators may look at the stats
+    alice.send(Message::admin_server_stats()).await;
+    assert!(alice.receive().await.is(MessageType::ServerStats));
+
+    let mut wrong = restored.raw_connection().await;
+    wrong.send(Message::auth("alice", "wrong")).await;
+    assert!(wrong.receive().await.is(MessageType::AuthFailure));
+}
+
+#[tokio::test]
+async fn conflicting_imports_change_nothing() {
+    let dir = TestServer::scratch_dir("conflicts");
+    let source = dir.join("source.json");
+    export_users_file(None, &source).unwrap();
+
+    let users_file = empty_users_file(&dir);
+    assert_eq!(import_users_file(&users_file, &source, ImportMode::Merge).unwrap(), 1);
+    let before = fs::read_to_string(&users_file).unwrap();
+
+    let error = import_users_file(&users_file, &source, ImportMode::Merge).unwrap_err();
+    assert_eq!(error.to_string(), "Conflicting usernames: luffy");
+    assert_eq!(fs::read_to_string(&users_file).unwrap(), before);
+
+    // replacing is always possible, the import becomes the whole database
+    assert_eq!(import_users_file(&users_file, &source, ImportMode::Replace).unwrap(), 1);
+    assert_eq!(fs::read_to_string(&users_file).unwrap(), before);
+}
+
+#[tokio::test]
+async fn invalid_documents_are_refused() {
+    let dir = TestServer::scratch_dir("invalid");
+    let users_file = empty_users_file(&dir);
+    let source = dir.join("source.json");
+
+    for (document, expected) in [
+        ("{\"users\": []}", "Missing export version"),
+        ("{\"version\": 3, \"users\": []}", "Unsupported export version 3, expected 2 or older"),
+        (
+            "{\"version\": 1, \"users\": [{\"name\": \"bob\"}]}",
+            "User 1: Missing string field 'pw_hash'",
+        ),
+        (
+            "{\"version\": 1, \"users\": [\
+                {\"name\": \"bob\", \"pw_hash\": \"x\", \"access_level\": \"user\", \"granted\": \"\", \"revoked\": \"\"},\
+                {\"name\": \"bob\", \"pw_hash\": \"y\", \"access_level\": \"user\", \"granted\": \"\", \"revoked\": \"\"}]}",
+            "Conflicting usernames: bob",
+        ),
+    ] {
+        fs::write(&source, document).unwrap();
+        let error = import_users_file(&users_file, &source, ImportMode::Replace).unwrap_err();
+        assert_eq!(error.to_string(), expected);
+    }
+}
+
+#[tokio::test]
+async fn room_preferences_are_dropped_on_import() {
+    let dir = TestServer::scratch_dir("room_preferences");
+    let users_file = empty_users_file(&dir);
+    let source = dir.join("source.json");
+    fs::write(
+        &source,
+        "{\"version\": 1, \"users\": [{\"name\": \"bob\", \"pw_hash\": \"x\", \"access_level\": \"user\", \
+            \"granted\": \"\", \"revoked\": \"\", \"preferences\": {\"dnd\": \"on\", \"room.general.notify\": \"mute\"}}]}",
+    )
+    .unwrap();
+
+    assert_eq!(import_users_file(&users_file, &source, ImportMode::Replace).unwrap(), 1);
+    let imported = JsonValue::parse(&fs::read_to_string(&users_file).unwrap()).unwrap();
+    let bob = &imported.get("users").and_then(JsonValue::as_array).unwrap()[0];
+    assert_eq!(
+        bob.get("preferences").map(JsonValue::to_string),
+        Some("{\"dnd\":\"on\"}".to_string())
+    );
+}
+
+#[tokio::test]
+async fn only_admins_may_export() {
+    let dir = TestServer::scratch_dir("permissions");
+    let server = TestServer::builder().with_data_dir(dir.join("data")).start();
+    server.create_user("mod", "secret", AccessLevel::Moderator).await;
+    let mut moderator = server.login("mod").await;
+
+    moderator.send(Message::admin_export_state()).await;
+    assert!(moderator.receive().await.is(MessageType::Nack));
+    // the directory only holds the lock file and the server id, no export was written
+    let mut entries: Vec<_> = fs::read_dir(dir.join("data"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["server.lock", "server_id"]);
+}
+
+#[tokio::test]
+async fn version_one_exports_are_migrated() {
+    let dir = TestServer::scratch_dir("version_one");
+    let server = TestServer::builder().with_data_dir(dir.join("data")).start();
+    server.create_user("alice", "secret", AccessLevel::User).await;
+    let source = dir.join("version_one.json");
+    fs::write(
+        &source,
+        as_version_one(&fs::read_to_string(export(&server).await).unwrap()),
+    )
+    .unwrap();
+
+    let users_file = empty_users_file(&dir);
+    assert_eq!(import_users_file(&users_file, &source, ImportMode::Replace).unwrap(), 3);
+    // the users file is rewritten in the current format, with the ids it handed out
+    let migrated = JsonValue::parse(&fs::read_to_string(&users_file).unwrap()).unwrap();
+    assert_eq!(migrated.get("version").and_then(JsonValue::as_u64), Some(2));
+    let users = migrated.get("users").and_then(JsonValue::as_array).unwrap();
+    assert!(users
+        .iter()
+        .all(|user| user.get("id").and_then(JsonValue::as_str).is_some()));
+
+    let restored = TestServer::builder().with_users_file(users_file.clone()).start();
+    let (_, id) = login_with_id(&restored, "alice").await;
+    let again = TestServer::builder().with_users_file(users_file).start();
+    assert_eq!(login_with_id(&again, "alice").await.1, id);
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+use chat_client::client::ClientEvent;
+use chat_core::protocol::{Message, MessageType, MAX_FIELD_SIZE};
+use chat_server::application::testing::{eventually, TestClient, TestServer};
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+async fn stalled_writes(admin: &mut TestClient) -> u64 {
+    admin.client().send(Message::admin_server_stats()).await;
+    let event = admin
+        .expect(|event| matches!(event, ClientEvent::ServerStats { .. }))
+        .await;
+    let ClientEvent::ServerStats { stalled_writes, .. } = event else {
+        unreachable!();
+    };
+    stalled_writes
+}
+
+#[tokio::test]
+async fn peer_that_stops_reading_is_dropped_after_the_write_timeout() {
+    let server = TestServer::builder().with_write_timeout(WRITE_TIMEOUT).start();
+    server.create_admin("admin", "secret").await;
+    let mut admin = server.client().await;
+    admin.login("admin", "secret").await;
+    // never reads again, the server's writes pend once the pipe is full
+    let _alice = server.logged_in("alice").await;
+    let mut bob = server.logged_in("bob").await;
+    assert_eq!(stalled_writes(&mut admin).await, 0);
+
+    let body = "x".repeat(MAX_FIELD_SIZE as usize - 1024);
+    let started = Instant::now();
+    for _ in 0..4 {
+        bob.send(Message::direct_message_send("alice", &body)).await;
+        assert!(bob.receive().await.is(MessageType::Ack));
+    }
+
+    eventually(|| async { !server.is_logged_in("alice").await }).await;
+    assert!(
+        started.elapsed() < WRITE_TIMEOUT * 3,
+        "Took {:?} to drop the stalled session",
+        started.elapsed()
+    );
+    assert_eq!(stalled_writes(&mut admin).await, 1);
+
+    // the rest of the server is unaffected
+    bob.send(Message::direct_message_send("admin", "still here")).await;
+    assert!(bob.receive().await.is(MessageType::Ack));
+}
@@ -0,0 +1,40 @@
+#![no_main]
+
+// cargo +nightly fuzz run decode corpus/decode -- -malloc_limit_mb=128
+
+use std::sync::OnceLock;
+
+use chat_core::protocol::Message;
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("Could not build runtime")
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let decoded = Message::from_bytes(data);
+
+    // the streaming decoder has to accept everything the buffered one does
+    let streamed = runtime().block_on(async {
+        let mut reader = data;
+        match Message::read_header_start(&mut reader).await {
+            Ok(true) => Message::receive(&mut reader).await,
+            Ok(false) => Err("Invalid header start".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    if let Ok(message) = decoded {
+        let encoded = message.to_bytes();
+        assert_eq!(encoded, data, "decoded message does not re-encode to its input");
+
+        let streamed = streamed.expect("streaming decoder rejected a valid frame");
+        assert_eq!(streamed.to_bytes(), encoded, "streaming and buffered decoders disagree");
+    }
+});
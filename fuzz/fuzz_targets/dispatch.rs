@@ -0,0 +1,98 @@
+#![no_main]
+
+// cargo +nightly fuzz run dispatch -- -detect_leaks=0 -rss_limit_mb=1024
+// the server and runtime live for the whole run, which leak detection reports as leaks
+
+use std::sync::OnceLock;
+
+use arbitrary::Arbitrary;
+use chat_core::protocol::{Message, MessageBuilder, MessageType};
+use chat_server::application::testing::{eventually, RawConnection, TestServer};
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+const USERNAME: &str = "fuzz";
+const PASSWORD: &str = "fuzz";
+const MAX_MESSAGES: usize = 32;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    login: bool,
+    messages: Vec<FuzzMessage>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct FuzzMessage {
+    message_type: u8,
+    fields: Vec<Vec<u8>>,
+}
+
+struct Harness {
+    runtime: Runtime,
+    server: TestServer,
+}
+
+impl FuzzMessage {
+    fn build(self) -> Message {
+        let message_type = MessageType::ALL[self.message_type as usize % MessageType::ALL.len()];
+        MessageBuilder::new(message_type).with_fields(self.fields).build()
+    }
+}
+
+fn harness() -> &'static Harness {
+    static HARNESS: OnceLock<Harness> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Could not build runtime");
+
+        let server = runtime.block_on(async {
+            let server = TestServer::start();
+            let mut connection = server.raw_connection().await;
+            connection.send(Message::auth_create(USERNAME, PASSWORD)).await;
+            assert!(connection.receive().await.is(MessageType::AuthSuccess));
+            drop(connection);
+            eventually(|| async { server.session_count().await == 0 }).await;
+            server
+        });
+
+        Harness { runtime, server }
+    })
+}
+
+async fn login(connection: &mut RawConnection) {
+    connection.send(Message::auth(USERNAME, PASSWORD)).await;
+    loop {
+        let message = connection.receive().await;
+        if message.is(MessageType::AuthSuccess) {
+            return;
+        }
+        assert!(!message.is(MessageType::AuthFailure), "fuzz user could not log in");
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let harness = harness();
+
+    harness.runtime.block_on(async {
+        let server = &harness.server;
+        let mut connection = server.raw_connection().await;
+
+        if input.login {
+            login(&mut connection).await;
+        }
+
+        for message in input.messages.into_iter().take(MAX_MESSAGES) {
+            // the server may hang up on us, e.g. after a disconnect or an oversized field
+            if connection.try_send(message.build()).await.is_err() {
+                break;
+            }
+        }
+
+        // every session has to be torn down again, whatever was sent
+        drop(connection);
+        eventually(|| async { server.session_count().await == 0 }).await;
+        assert!(!server.is_logged_in(USERNAME).await);
+    });
+});